@@ -0,0 +1,139 @@
+//! The per-message work of a fetch. Everything runs against an in-memory database, so `cargo
+//! bench` needs no network and no mailbox.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use gmail_stats::analyze::SenderCounts;
+use gmail_stats::db::{self, DEFAULT_ACCOUNT};
+use gmail_stats::parse::{cleanup_sender, get_sender};
+use gmail_stats::testsupport::{self, info, message};
+use tokio::runtime::Runtime;
+
+// As many seen ids as a large mailbox has
+const SEEN: usize = 500_000;
+
+// From headers in the shapes the fetch sees
+const FROM: [&str; 8] = [
+    "jane@example.com",
+    "Jane Doe <jane@example.com>",
+    "\"Doe, Jane\" <jane.doe+news@mail.example.co.uk>",
+    "\"Jane <jane@old.example>\" <jane@example.com>",
+    "=?UTF-8?Q?J=C3=A4ne?= <jane@example.museum>",
+    "<noreply@notifications.example.technology>",
+    "o'brien@example.ie",
+    "not an address",
+];
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+fn sender_extraction(c: &mut Criterion) {
+    let messages = FROM
+        .iter()
+        .enumerate()
+        .map(|(n, from)| message(&n.to_string(), &[("From", from)], &["INBOX"]))
+        .collect::<Vec<_>>();
+    c.bench_function("get_sender and cleanup_sender", |b| {
+        b.iter(|| {
+            for message in &messages {
+                black_box(cleanup_sender(get_sender(message)));
+            }
+        })
+    });
+}
+
+fn seen_check(c: &mut Criterion) {
+    let rt = runtime();
+    let pool = rt.block_on(async {
+        let pool = testsupport::pool().await;
+        let ids = (0..SEEN).map(|n| format!("id{}", n)).collect::<Vec<_>>();
+        let mut conn = pool.acquire().await.unwrap();
+        db::mark_seen(&ids, DEFAULT_ACCOUNT, &mut conn)
+            .await
+            .unwrap();
+        // The same rows without the (account, mail_id) index, for comparison
+        sqlx::query("CREATE TABLE seen_unindexed AS SELECT mail_id, account FROM seen_mails")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+        pool
+    });
+
+    let mut group = c.benchmark_group("seen check");
+    group.bench_function("indexed", |b| {
+        b.to_async(&rt).iter(|| async {
+            db::seen_mail("id250000", DEFAULT_ACCOUNT, &pool)
+                .await
+                .unwrap()
+        })
+    });
+    group.bench_function("unindexed", |b| {
+        b.to_async(&rt).iter(|| async {
+            let (count,): (i64,) = sqlx::query_as(
+                "SELECT count(1) FROM seen_unindexed WHERE account = ? AND mail_id = ?",
+            )
+            .bind(DEFAULT_ACCOUNT)
+            .bind("id250000")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            count
+        })
+    });
+    group.finish();
+}
+
+fn sender_upsert(c: &mut Criterion) {
+    let rt = runtime();
+    let pool = rt.block_on(testsupport::pool());
+    let mut counts = SenderCounts::default();
+    counts.add(&info("m1", "jane@example.com", 0, &["UNREAD"]));
+    c.bench_function("add_sender_counts", |b| {
+        b.to_async(&rt).iter(|| async {
+            let mut conn = pool.acquire().await.unwrap();
+            db::add_sender_counts("jane@example.com", &counts, &mut conn)
+                .await
+                .unwrap()
+        })
+    });
+}
+
+fn mark_seen_batch(c: &mut Criterion) {
+    let rt = runtime();
+    let pool = rt.block_on(testsupport::pool());
+    let mut batch = 0;
+    c.bench_function("mark_seen 500 ids", |b| {
+        b.to_async(&rt).iter_batched(
+            || {
+                // New ids each time, so every batch is inserted rather than ignored
+                batch += 1;
+                (0..500)
+                    .map(|n| format!("b{}-{}", batch, n))
+                    .collect::<Vec<_>>()
+            },
+            |ids| {
+                let pool = &pool;
+                async move {
+                    let mut conn = pool.acquire().await.unwrap();
+                    db::mark_seen(&ids, DEFAULT_ACCOUNT, &mut conn)
+                        .await
+                        .unwrap()
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(
+    benches,
+    sender_extraction,
+    seen_check,
+    sender_upsert,
+    mark_seen_batch
+);
+criterion_main!(benches);
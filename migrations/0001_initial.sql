@@ -0,0 +1,3 @@
+-- Tables that were previously created by hand, see README history.
+CREATE TABLE IF NOT EXISTS seen_mails (mail_id string);
+CREATE TABLE IF NOT EXISTS senders (sender string, mails_sent int);
@@ -0,0 +1,10 @@
+-- One row per processed message. `sender` is the address the message is
+-- attributed to in `senders`; `envelope_sender` is what the From header said
+-- and `original_sender` is set when a forwarded message was unwrapped.
+CREATE TABLE IF NOT EXISTS messages (
+    mail_id string PRIMARY KEY,
+    sender string NOT NULL,
+    envelope_sender string NOT NULL,
+    original_sender string
+);
+CREATE INDEX IF NOT EXISTS messages_sender ON messages (sender);
@@ -0,0 +1,4 @@
+-- `bulk_signal` records which header marked the message as bulk (see parse::BulkSignal).
+ALTER TABLE messages ADD COLUMN is_bulk boolean NOT NULL DEFAULT 0;
+ALTER TABLE messages ADD COLUMN bulk_signal string;
+ALTER TABLE senders ADD COLUMN bulk_count int NOT NULL DEFAULT 0;
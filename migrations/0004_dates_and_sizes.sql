@@ -0,0 +1,7 @@
+-- Dates are the message internalDate in epoch milliseconds, sizes are Gmail's sizeEstimate.
+ALTER TABLE messages ADD COLUMN internal_date int;
+ALTER TABLE messages ADD COLUMN size_estimate int NOT NULL DEFAULT 0;
+ALTER TABLE senders ADD COLUMN bytes int NOT NULL DEFAULT 0;
+ALTER TABLE senders ADD COLUMN first_seen int;
+ALTER TABLE senders ADD COLUMN last_seen int;
+CREATE INDEX IF NOT EXISTS messages_internal_date ON messages (internal_date);
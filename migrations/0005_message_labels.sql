@@ -0,0 +1,7 @@
+-- Gmail labelIds per message, both system labels (INBOX, UNREAD, CATEGORY_*) and user label IDs.
+CREATE TABLE IF NOT EXISTS message_labels (
+    mail_id string NOT NULL,
+    label string NOT NULL,
+    PRIMARY KEY (mail_id, label)
+);
+CREATE INDEX IF NOT EXISTS message_labels_label ON message_labels (label);
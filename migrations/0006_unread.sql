@@ -0,0 +1,4 @@
+-- Unread status as of when the message was fetched, it isn't refreshed afterwards.
+ALTER TABLE messages ADD COLUMN is_unread boolean NOT NULL DEFAULT 0;
+ALTER TABLE messages ADD COLUMN fetched_at int;
+ALTER TABLE senders ADD COLUMN unread_count int NOT NULL DEFAULT 0;
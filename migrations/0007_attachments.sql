@@ -0,0 +1,9 @@
+ALTER TABLE messages ADD COLUMN attachment_count int NOT NULL DEFAULT 0;
+ALTER TABLE messages ADD COLUMN attachment_bytes int NOT NULL DEFAULT 0;
+CREATE TABLE IF NOT EXISTS attachments (
+    mail_id string NOT NULL,
+    mime_type string NOT NULL,
+    size int NOT NULL,
+    inline boolean NOT NULL
+);
+CREATE INDEX IF NOT EXISTS attachments_mail_id ON attachments (mail_id);
@@ -0,0 +1,2 @@
+ALTER TABLE messages ADD COLUMN thread_id string;
+CREATE INDEX IF NOT EXISTS messages_thread_id ON messages (thread_id);
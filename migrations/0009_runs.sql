@@ -0,0 +1,10 @@
+-- One row per fetch. `messages` is the number of new messages the run recorded.
+CREATE TABLE IF NOT EXISTS runs (
+    id integer PRIMARY KEY AUTOINCREMENT,
+    started_at int NOT NULL,
+    finished_at int,
+    status string NOT NULL,
+    messages int NOT NULL DEFAULT 0
+);
+ALTER TABLE messages ADD COLUMN run_id int REFERENCES runs (id);
+CREATE INDEX IF NOT EXISTS messages_run_id ON messages (run_id);
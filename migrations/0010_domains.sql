@@ -0,0 +1,5 @@
+-- Derived from senders, rebuilt whenever the classification lists may have changed.
+CREATE TABLE IF NOT EXISTS domains (
+    domain string PRIMARY KEY,
+    class string NOT NULL
+);
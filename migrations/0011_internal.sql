@@ -0,0 +1,2 @@
+-- Recomputed from the own_domains config by db::classify_senders.
+ALTER TABLE senders ADD COLUMN is_internal boolean NOT NULL DEFAULT 0;
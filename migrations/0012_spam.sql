@@ -0,0 +1,7 @@
+-- Spam is aggregated here instead of in senders, so it stays out of the main ranking.
+ALTER TABLE messages ADD COLUMN is_spam boolean NOT NULL DEFAULT 0;
+CREATE TABLE IF NOT EXISTS spam_senders (
+    sender string PRIMARY KEY,
+    mails_sent int NOT NULL,
+    last_seen int
+);
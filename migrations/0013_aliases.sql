@@ -0,0 +1,5 @@
+-- Rebuilt from the aliases config by db::apply_aliases, the senders table itself is untouched.
+CREATE TABLE IF NOT EXISTS sender_aliases (
+    sender string PRIMARY KEY,
+    canonical string NOT NULL
+);
@@ -0,0 +1,4 @@
+-- Recomputed from the ignore config by db::flag_ignored_senders.
+ALTER TABLE senders ADD COLUMN is_ignored boolean NOT NULL DEFAULT 0;
+-- Messages from ignored senders, which are marked seen but not recorded.
+ALTER TABLE runs ADD COLUMN ignored int NOT NULL DEFAULT 0;
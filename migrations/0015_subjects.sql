@@ -0,0 +1,2 @@
+-- Only stored for messages fetched from now on, older rows stay NULL.
+ALTER TABLE messages ADD COLUMN subject string;
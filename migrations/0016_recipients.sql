@@ -0,0 +1,12 @@
+-- To and Cc addresses, lowercased. Only stored for messages fetched from now on.
+CREATE TABLE IF NOT EXISTS message_recipients (
+    mail_id string NOT NULL,
+    field string NOT NULL,
+    address string NOT NULL
+);
+CREATE INDEX IF NOT EXISTS message_recipients_mail_id ON message_recipients (mail_id);
+-- 'direct', 'cc' or 'other', NULL when the recipients weren't stored. Recomputed from the
+-- my_addresses config by db::classify_addressing, as are the per-sender counts.
+ALTER TABLE messages ADD COLUMN addressed string;
+ALTER TABLE senders ADD COLUMN direct_count int NOT NULL DEFAULT 0;
+ALTER TABLE senders ADD COLUMN cc_count int NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- One of parse::AutoKind, NULL for mail that isn't machine-generated.
+ALTER TABLE messages ADD COLUMN auto_kind string;
+ALTER TABLE senders ADD COLUMN auto_count int NOT NULL DEFAULT 0;
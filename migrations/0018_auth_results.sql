@@ -0,0 +1,5 @@
+-- Results from Gmail's Authentication-Results header, e.g. 'pass', 'fail', 'softfail', 'none'.
+-- NULL when the header didn't cover the mechanism or the message was fetched before this.
+ALTER TABLE messages ADD COLUMN spf string;
+ALTER TABLE messages ADD COLUMN dkim string;
+ALTER TABLE messages ADD COLUMN dmarc string;
@@ -0,0 +1,4 @@
+-- Meeting invites. The method and organizer are NULL when they couldn't be found.
+ALTER TABLE messages ADD COLUMN is_calendar boolean NOT NULL DEFAULT 0;
+ALTER TABLE messages ADD COLUMN calendar_method string;
+ALTER TABLE messages ADD COLUMN calendar_organizer string;
@@ -0,0 +1,2 @@
+-- Display name from the header the sender was taken from, NULL for bare addresses.
+ALTER TABLE messages ADD COLUMN sender_name string;
@@ -0,0 +1,4 @@
+-- 'bounce' or 'out-of-office' (see parse::NoticeKind). `notice_address` is who the notice is
+-- about: the failed recipient of a bounce or the sender of an out-of-office reply.
+ALTER TABLE messages ADD COLUMN notice string;
+ALTER TABLE messages ADD COLUMN notice_address string;
@@ -0,0 +1,9 @@
+-- Messages a fetch passed over because Gmail's response for them was malformed, with why.
+-- `mail_id` is NULL when the listing entry itself had no id.
+CREATE TABLE IF NOT EXISTS skipped_messages (
+    mail_id string,
+    run_id int REFERENCES runs (id),
+    reason string NOT NULL,
+    skipped_at int NOT NULL
+);
+ALTER TABLE runs ADD COLUMN skipped int NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- Where a run stopped when it was interrupted (status 'interrupted'): the label being listed,
+-- NULL for all mail, and the token of the page being worked through, NULL for the first.
+ALTER TABLE runs ADD COLUMN cursor_label string;
+ALTER TABLE runs ADD COLUMN cursor_page_token string;
@@ -0,0 +1,14 @@
+-- Messages a fetch couldn't process, replacing skipped_messages. `class` is one of
+-- metrics::ERROR_CLASSES. A message keeps only its latest failure and loses it once it's fetched;
+-- `mail_id` is NULL when a listing entry had no id.
+CREATE TABLE IF NOT EXISTS errors (
+    mail_id string,
+    run_id int REFERENCES runs (id),
+    class string NOT NULL,
+    message string NOT NULL,
+    recorded_at int NOT NULL
+);
+CREATE INDEX IF NOT EXISTS errors_mail_id ON errors (mail_id);
+INSERT INTO errors (mail_id, run_id, class, message, recorded_at)
+    SELECT mail_id, run_id, 'parse', reason, skipped_at FROM skipped_messages;
+DROP TABLE skipped_messages;
@@ -0,0 +1,2 @@
+-- Messages from my own addresses a run left uncounted (fetch --include-self counts them).
+ALTER TABLE runs ADD COLUMN self_excluded int NOT NULL DEFAULT 0;
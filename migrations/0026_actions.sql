@@ -0,0 +1,11 @@
+-- Changes `cleanup` made to the mailbox, a row per message: `action` is 'archive' or 'trash',
+-- and `error` says why it wasn't done, NULL when it was.
+CREATE TABLE IF NOT EXISTS actions (
+    id integer PRIMARY KEY AUTOINCREMENT,
+    performed_at int NOT NULL,
+    action string NOT NULL,
+    mail_id string NOT NULL,
+    sender string,
+    error string
+);
+CREATE INDEX IF NOT EXISTS actions_mail_id ON actions (mail_id);
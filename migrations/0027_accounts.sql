@@ -0,0 +1,9 @@
+-- The Gmail account (fetch --profile) each run, message and seen id belongs to, 'default' for
+-- the account without a profile. Gmail ids are only unique within a mailbox, so seen ids are
+-- looked up per account; errors belong to an account through their run. `messages` stays keyed
+-- by id alone, as rebuilding it for a collision between two mailboxes isn't worth it.
+ALTER TABLE runs ADD COLUMN account string NOT NULL DEFAULT 'default';
+ALTER TABLE messages ADD COLUMN account string NOT NULL DEFAULT 'default';
+ALTER TABLE seen_mails ADD COLUMN account string NOT NULL DEFAULT 'default';
+CREATE INDEX IF NOT EXISTS messages_account ON messages (account);
+CREATE INDEX IF NOT EXISTS seen_mails_account_mail_id ON seen_mails (account, mail_id);
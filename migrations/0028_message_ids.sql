@@ -0,0 +1,4 @@
+-- The Message-ID header, so a message fetched from Gmail and imported from an mbox file under
+-- another id is only counted once. NULL for mail fetched before this was added.
+ALTER TABLE messages ADD COLUMN message_id string;
+CREATE INDEX IF NOT EXISTS messages_message_id ON messages (message_id);
@@ -0,0 +1,7 @@
+-- Senders erased with `forget`, an address or `@domain` each. Their mail is marked seen by later
+-- fetches without being recorded, and counted in `runs.erased` instead.
+CREATE TABLE IF NOT EXISTS erased_senders (
+    pattern string PRIMARY KEY,
+    erased_at int NOT NULL
+);
+ALTER TABLE runs ADD COLUMN erased int NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+-- The address in Delivered-To, plus-tag and all, and which of my addresses each message arrived
+-- at as written, worked out after each fetch from the recipients and Delivered-To. NULL for mail
+-- fetched before this was added, and for mail not sent to any address of mine.
+ALTER TABLE messages ADD COLUMN delivered_to string;
+ALTER TABLE messages ADD COLUMN arrived_at string;
+CREATE INDEX IF NOT EXISTS messages_arrived_at ON messages (arrived_at);
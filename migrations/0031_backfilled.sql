@@ -0,0 +1,9 @@
+-- Which of a message's columns `backfill` has looked for, so a message without a subject isn't
+-- fetched again on every run and an interrupted backfill carries on where it stopped. `field` is
+-- a `cli::BackfillField` name.
+CREATE TABLE IF NOT EXISTS backfilled (
+    account string NOT NULL,
+    mail_id string NOT NULL,
+    field string NOT NULL,
+    PRIMARY KEY (account, mail_id, field)
+);
@@ -0,0 +1,88 @@
+-- Gmail ids are only unique within a mailbox, so a message and its labels, recipients and
+-- attachments are keyed by account and id, and an id is marked seen once per account. The
+-- tables are rebuilt since SQLite can't change a primary key; messages keep their rowids, which
+-- the subject index goes by.
+CREATE TABLE messages_by_account (
+    mail_id string NOT NULL,
+    sender string NOT NULL,
+    envelope_sender string NOT NULL,
+    original_sender string,
+    is_bulk boolean NOT NULL DEFAULT 0,
+    bulk_signal string,
+    internal_date int,
+    size_estimate int NOT NULL DEFAULT 0,
+    is_unread boolean NOT NULL DEFAULT 0,
+    fetched_at int,
+    attachment_count int NOT NULL DEFAULT 0,
+    attachment_bytes int NOT NULL DEFAULT 0,
+    thread_id string,
+    run_id int REFERENCES runs (id),
+    is_spam boolean NOT NULL DEFAULT 0,
+    subject string,
+    addressed string,
+    auto_kind string,
+    spf string,
+    dkim string,
+    dmarc string,
+    is_calendar boolean NOT NULL DEFAULT 0,
+    calendar_method string,
+    calendar_organizer string,
+    sender_name string,
+    notice string,
+    notice_address string,
+    account string NOT NULL DEFAULT 'default',
+    message_id string,
+    delivered_to string,
+    arrived_at string,
+    PRIMARY KEY (account, mail_id)
+);
+INSERT INTO messages_by_account (rowid, mail_id, sender, envelope_sender, original_sender,
+    is_bulk, bulk_signal, internal_date, size_estimate, is_unread, fetched_at, attachment_count,
+    attachment_bytes, thread_id, run_id, is_spam, subject, addressed, auto_kind, spf, dkim, dmarc,
+    is_calendar, calendar_method, calendar_organizer, sender_name, notice, notice_address,
+    account, message_id, delivered_to, arrived_at)
+SELECT rowid, mail_id, sender, envelope_sender, original_sender, is_bulk, bulk_signal,
+    internal_date, size_estimate, is_unread, fetched_at, attachment_count, attachment_bytes,
+    thread_id, run_id, is_spam, subject, addressed, auto_kind, spf, dkim, dmarc, is_calendar,
+    calendar_method, calendar_organizer, sender_name, notice, notice_address, account,
+    message_id, delivered_to, arrived_at
+FROM messages WHERE mail_id IS NOT NULL;
+DROP TABLE messages;
+ALTER TABLE messages_by_account RENAME TO messages;
+CREATE INDEX messages_sender ON messages (sender);
+CREATE INDEX messages_internal_date ON messages (internal_date);
+CREATE INDEX messages_thread_id ON messages (thread_id);
+CREATE INDEX messages_run_id ON messages (run_id);
+CREATE INDEX messages_message_id ON messages (message_id);
+CREATE INDEX messages_arrived_at ON messages (arrived_at);
+
+CREATE TABLE message_labels_by_account (
+    account string NOT NULL DEFAULT 'default',
+    mail_id string NOT NULL,
+    label string NOT NULL,
+    PRIMARY KEY (account, mail_id, label)
+);
+INSERT INTO message_labels_by_account (account, mail_id, label)
+SELECT coalesce((SELECT m.account FROM messages m WHERE m.mail_id = l.mail_id), 'default'),
+    l.mail_id, l.label
+FROM message_labels l;
+DROP TABLE message_labels;
+ALTER TABLE message_labels_by_account RENAME TO message_labels;
+CREATE INDEX message_labels_label ON message_labels (label);
+
+ALTER TABLE message_recipients ADD COLUMN account string NOT NULL DEFAULT 'default';
+UPDATE message_recipients SET account = coalesce(
+    (SELECT m.account FROM messages m WHERE m.mail_id = message_recipients.mail_id), 'default');
+DROP INDEX message_recipients_mail_id;
+CREATE INDEX message_recipients_mail_id ON message_recipients (account, mail_id);
+
+ALTER TABLE attachments ADD COLUMN account string NOT NULL DEFAULT 'default';
+UPDATE attachments SET account = coalesce(
+    (SELECT m.account FROM messages m WHERE m.mail_id = attachments.mail_id), 'default');
+DROP INDEX attachments_mail_id;
+CREATE INDEX attachments_mail_id ON attachments (account, mail_id);
+
+DELETE FROM seen_mails WHERE rowid NOT IN
+    (SELECT min(rowid) FROM seen_mails GROUP BY account, mail_id);
+DROP INDEX seen_mails_account_mail_id;
+CREATE UNIQUE INDEX seen_mails_account_mail_id ON seen_mails (account, mail_id);
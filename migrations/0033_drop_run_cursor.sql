@@ -0,0 +1,4 @@
+-- Where an interrupted run stopped was recorded but never read back: a fetch always starts the
+-- listing over, and carries on past what was fetched before by skipping seen mail.
+ALTER TABLE runs DROP COLUMN cursor_label;
+ALTER TABLE runs DROP COLUMN cursor_page_token;
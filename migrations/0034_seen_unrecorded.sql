@@ -0,0 +1,3 @@
+-- Why a seen message has no row by design: 'ignored', 'own', 'erased', 'duplicate' or 'expired'.
+-- NULL for a message which was recorded, or which was seen before the reason was kept.
+ALTER TABLE seen_mails ADD COLUMN unrecorded string;
@@ -0,0 +1,3 @@
+-- Tombstones keep a digest of the address or `@domain` erased rather than naming it. SQLite can't
+-- hash, so the patterns already stored are replaced by their digests in `db::migrate`.
+ALTER TABLE erased_senders RENAME COLUMN pattern TO digest;
@@ -0,0 +1,6 @@
+-- Where an interrupted fetch stopped: the listing it went through, the label it was listing and
+-- the token of the page in hand. The next fetch of the account through the same listing carries
+-- on from that page rather than listing the mailbox from the top.
+ALTER TABLE runs ADD COLUMN cursor_listing string;
+ALTER TABLE runs ADD COLUMN cursor_label string;
+ALTER TABLE runs ADD COLUMN cursor_page_token string;
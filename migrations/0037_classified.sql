@@ -0,0 +1,10 @@
+-- Which messages and senders have been classified, so a fetch or import only works out what it
+-- wrote, and the settings they were classified with. When those change, everything is again.
+ALTER TABLE messages ADD COLUMN classified boolean NOT NULL DEFAULT 0;
+ALTER TABLE senders ADD COLUMN classified boolean NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS messages_unclassified ON messages (account, mail_id) WHERE NOT classified;
+CREATE INDEX IF NOT EXISTS senders_unclassified ON senders (sender) WHERE NOT classified;
+CREATE TABLE IF NOT EXISTS classified_with (
+    id int PRIMARY KEY CHECK (id = 1),
+    fingerprint string NOT NULL
+);
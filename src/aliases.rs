@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+
+/// Resolve the `[aliases]` config section into (alias, canonical) pairs, lowercased. Chained
+/// aliases are followed to the end, so `a -> b` and `b -> c` fold both `a` and `b` onto `c`.
+/// A cycle has no canonical address to fold onto and is an error.
+pub fn resolve(aliases: &BTreeMap<String, String>) -> anyhow::Result<Vec<(String, String)>> {
+    let aliases = aliases
+        .iter()
+        .map(|(alias, canonical)| (normalize(alias), normalize(canonical)))
+        .filter(|(alias, canonical)| alias != canonical)
+        .collect::<BTreeMap<_, _>>();
+
+    let mut resolved = Vec::with_capacity(aliases.len());
+    for alias in aliases.keys() {
+        let mut chain = vec![alias.as_str()];
+        let mut canonical = &aliases[alias];
+        while let Some(next) = aliases.get(canonical) {
+            chain.push(canonical);
+            if chain.contains(&next.as_str()) {
+                chain.push(next);
+                anyhow::bail!("alias cycle: {}", chain.join(" -> "));
+            }
+            canonical = next;
+        }
+        resolved.push((alias.clone(), canonical.clone()));
+    }
+    Ok(resolved)
+}
+
+fn normalize(address: &str) -> String {
+    address.trim().to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyze::SenderCounts;
+    use crate::cli::SortBy;
+    use crate::db;
+    use crate::report::{self, SenderFilter};
+    use crate::testsupport::{self, info};
+    use crate::Storage;
+
+    fn aliases(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(alias, canonical)| (alias.to_string(), canonical.to_string()))
+            .collect()
+    }
+
+    fn pair(alias: &str, canonical: &str) -> (String, String) {
+        (alias.to_string(), canonical.to_string())
+    }
+
+    // (sender, messages) of the top senders report, most first
+    async fn top(pool: &Storage) -> Vec<(String, i64)> {
+        report::top_senders(pool, 10, &SenderFilter::default(), SortBy::Count)
+            .await
+            .unwrap()
+            .rows
+            .into_iter()
+            .map(|row| (row.sender, row.mails_sent))
+            .collect()
+    }
+
+    #[test]
+    fn chained_aliases_fold_onto_the_end_of_the_chain() {
+        let resolved = resolve(&aliases(&[
+            ("old@example.com", "work@example.com"),
+            ("work@example.com", "jane@example.com"),
+            ("jd@example.org", "jane@example.com"),
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            resolved,
+            [
+                pair("jd@example.org", "jane@example.com"),
+                pair("old@example.com", "jane@example.com"),
+                pair("work@example.com", "jane@example.com")
+            ]
+        );
+    }
+
+    #[test]
+    fn aliases_are_lowercased_and_aliasing_an_address_to_itself_is_dropped() {
+        let resolved = resolve(&aliases(&[
+            (" Work@Example.com ", "JANE@example.com"),
+            ("jane@example.com", "Jane@Example.com"),
+        ]))
+        .unwrap();
+
+        assert_eq!(resolved, [pair("work@example.com", "jane@example.com")]);
+    }
+
+    #[test]
+    fn a_cycle_is_an_error_naming_it() {
+        let err = resolve(&aliases(&[
+            ("a@example.com", "b@example.com"),
+            ("b@example.com", "c@example.com"),
+            ("c@example.com", "a@example.com"),
+        ]))
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "alias cycle: a@example.com -> b@example.com -> c@example.com -> a@example.com"
+        );
+        assert!(resolve(&aliases(&[
+            ("a@example.com", "b@example.com"),
+            ("b@example.com", "a@example.com"),
+        ]))
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn adding_an_alias_folds_reports_onto_the_canonical_address() {
+        let pool = testsupport::pool().await;
+        let mut conn = pool.acquire().await.unwrap();
+        for (sender, mails) in [
+            ("Jane@Example.com", 5),
+            ("work@example.com", 3),
+            ("old@example.com", 1),
+            ("news@example.com", 6),
+        ] {
+            let mut counts = SenderCounts::default();
+            for n in 0..mails {
+                counts.add(&info("x", sender, n, &[]));
+            }
+            db::add_sender_counts(sender, &counts, &mut conn)
+                .await
+                .unwrap();
+        }
+        drop(conn);
+
+        assert_eq!(
+            top(&pool).await,
+            [
+                ("news@example.com".to_string(), 6),
+                ("Jane@Example.com".to_string(), 5),
+                ("work@example.com".to_string(), 3),
+                ("old@example.com".to_string(), 1)
+            ]
+        );
+
+        let resolved = resolve(&aliases(&[
+            ("old@example.com", "work@example.com"),
+            ("work@example.com", "jane@example.com"),
+        ]))
+        .unwrap();
+        db::apply_aliases(&pool, &resolved).await.unwrap();
+
+        // The canonical address keeps the case it was stored in
+        assert_eq!(
+            top(&pool).await,
+            [
+                ("Jane@Example.com".to_string(), 9),
+                ("news@example.com".to_string(), 6)
+            ]
+        );
+
+        // Taking the aliases out again undoes it, as nothing stored was changed
+        db::apply_aliases(&pool, &[]).await.unwrap();
+        assert_eq!(top(&pool).await.len(), 4);
+    }
+}
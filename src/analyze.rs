@@ -0,0 +1,192 @@
+//! Counting senders from headers alone, with no Gmail account or database: the same parsing and
+//! per-sender counting a fetch does, kept in memory. For embedding the counting in something
+//! else, such as a dashboard with its own source of mail.
+
+use std::collections::BTreeMap;
+
+use google_gmail1::api::{Message, MessagePart, MessagePartHeader};
+
+use crate::own::Identity;
+use crate::parse::{MessageInfo, ParseOptions};
+
+/// What some mail adds to one sender's row, or with [`analyze_headers`] all of it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SenderCounts {
+    pub mails: i64,
+    pub bulk: i64,
+    pub unread: i64,
+    pub automated: i64,
+    pub bytes: i64,
+    /// Earliest and latest internalDate among the mail, if any had one.
+    pub first_seen: Option<i64>,
+    pub last_seen: Option<i64>,
+}
+
+impl SenderCounts {
+    pub fn add(&mut self, info: &MessageInfo) {
+        self.mails += 1;
+        self.bulk += i64::from(info.is_bulk());
+        self.unread += i64::from(info.is_unread());
+        self.automated += i64::from(info.is_automated());
+        self.bytes += info.size_estimate;
+        if let Some(date) = info.date {
+            self.first_seen = Some(self.first_seen.map_or(date, |first| first.min(date)));
+            self.last_seen = Some(self.last_seen.map_or(date, |last| last.max(date)));
+        }
+    }
+}
+
+/// One message's headers, with what Gmail keeps beside them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RawHeaders {
+    /// Header names and values in order, such as `("From", "Jane <jane@example.com>")`.
+    pub headers: Vec<(String, String)>,
+    /// Gmail label ids, such as `UNREAD` or `SPAM`.
+    pub labels: Vec<String>,
+    /// When it arrived in epoch milliseconds, taken from the Date header if not given.
+    pub date: Option<i64>,
+    /// Its size in bytes.
+    pub size: i64,
+}
+
+impl RawHeaders {
+    /// Headers with no labels, date or size.
+    pub fn new<N: Into<String>, V: Into<String>>(
+        headers: impl IntoIterator<Item = (N, V)>,
+    ) -> RawHeaders {
+        RawHeaders {
+            headers: headers
+                .into_iter()
+                .map(|(name, value)| (name.into(), value.into()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// The message the API would have returned under `id`, headers only.
+    pub fn to_message(&self, id: String) -> Message {
+        let date = self.date.or_else(|| {
+            self.headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("Date"))
+                .and_then(|(_, date)| mailparse::dateparse(date).ok())
+                .map(|seconds| seconds * 1000)
+        });
+        Message {
+            id: Some(id),
+            label_ids: Some(self.labels.clone()),
+            internal_date: date.map(|date| date.to_string()),
+            size_estimate: Some(self.size.try_into().unwrap_or(i32::MAX)),
+            payload: Some(MessagePart {
+                headers: Some(
+                    self.headers
+                        .iter()
+                        .map(|(name, value)| MessagePartHeader {
+                            name: Some(name.clone()),
+                            value: Some(value.clone()),
+                        })
+                        .collect(),
+                ),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+/// Senders counted from some mail, as a fetch would count them into the database.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SenderStats {
+    /// Messages looked at.
+    pub messages: u64,
+    /// Each sender's counts, as their `senders` row would have them.
+    pub senders: BTreeMap<String, SenderCounts>,
+    /// Spam by sender, kept out of `senders` as `spam_senders` is.
+    pub spam: BTreeMap<String, i64>,
+    /// Mail from my own addresses, which isn't counted.
+    pub self_excluded: u64,
+}
+
+impl SenderStats {
+    /// Senders with the most mail first, then by address.
+    pub fn by_count(&self) -> Vec<(&str, &SenderCounts)> {
+        let mut senders = self
+            .senders
+            .iter()
+            .map(|(sender, counts)| (sender.as_str(), counts))
+            .collect::<Vec<_>>();
+        senders.sort_by(|a, b| b.1.mails.cmp(&a.1.mails).then(a.0.cmp(b.0)));
+        senders
+    }
+}
+
+/// Count the senders of `messages` the way a fetch does: each message is attributed to the
+/// cleaned-up address of its From header, and spam is counted apart. Nothing is mine, so
+/// nothing is left out as my own mail; [`analyze_headers_with`] takes my addresses and the
+/// parsing options.
+///
+/// ```
+/// use gmail_stats::analyze::{analyze_headers, RawHeaders};
+///
+/// let stats = analyze_headers([
+///     RawHeaders::new([("From", "\"Jane Doe\" <jane@example.com>")]),
+///     RawHeaders::new([("From", "jane@example.com"), ("List-Id", "<news.example.com>")]),
+///     RawHeaders::new([("From", "Bob <bob@example.org>")]),
+/// ]);
+/// let jane = &stats.senders["jane@example.com"];
+/// assert_eq!((jane.mails, jane.bulk), (2, 1));
+/// assert_eq!(stats.by_count()[0].0, "jane@example.com");
+/// ```
+pub fn analyze_headers(messages: impl IntoIterator<Item = RawHeaders>) -> SenderStats {
+    analyze_headers_with(messages, &Identity::default(), &ParseOptions::default())
+}
+
+/// As [`analyze_headers`], leaving out mail from `identity`'s addresses and parsing with `opts`,
+/// such as to unwrap forwarded mail.
+///
+/// ```
+/// use gmail_stats::analyze::{analyze_headers_with, RawHeaders};
+/// use gmail_stats::own::Identity;
+/// use gmail_stats::parse::ParseOptions;
+/// use gmail_stats::Config;
+///
+/// let config = Config {
+///     my_addresses: vec!["me@example.com".to_string()],
+///     ..Default::default()
+/// };
+/// let stats = analyze_headers_with(
+///     [
+///         RawHeaders::new([("From", "me+notes@example.com")]),
+///         RawHeaders {
+///             labels: vec!["SPAM".to_string()],
+///             ..RawHeaders::new([("From", "win@prizes.example")])
+///         },
+///     ],
+///     &Identity::new(&config),
+///     &ParseOptions::default(),
+/// );
+/// assert_eq!((stats.self_excluded, stats.spam["win@prizes.example"]), (1, 1));
+/// assert!(stats.senders.is_empty());
+/// ```
+pub fn analyze_headers_with(
+    messages: impl IntoIterator<Item = RawHeaders>,
+    identity: &Identity,
+    opts: &ParseOptions,
+) -> SenderStats {
+    let mut stats = SenderStats::default();
+    for (n, headers) in messages.into_iter().enumerate() {
+        let Ok(info) = MessageInfo::from_message(&headers.to_message(n.to_string()), opts) else {
+            continue;
+        };
+        stats.messages += 1;
+        let sender = &info.sender.sender;
+        if identity.is_mine(sender) {
+            stats.self_excluded += 1;
+        } else if info.is_spam() {
+            *stats.spam.entry(sender.clone()).or_default() += 1;
+        } else {
+            stats.senders.entry(sender.clone()).or_default().add(&info);
+        }
+    }
+    stats
+}
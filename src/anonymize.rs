@@ -0,0 +1,149 @@
+use std::fmt::Write;
+
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Hex characters kept from each HMAC, 64 bits: plenty to keep one mailbox's addresses apart.
+const TOKEN_LEN: usize = 16;
+
+/// Stands in for hashed domains so they can't be mistaken for real ones.
+const HASHED_TLD: &str = "invalid";
+
+/// Replaces addresses with keyed hashes. The same address always gets the same token under one
+/// key, so counts and joins still line up, but without the key tokens can't be reversed by
+/// hashing guesses.
+pub struct Anonymizer {
+    key: hmac::Key,
+    hash_domains: bool,
+}
+
+impl Anonymizer {
+    pub fn new(key: &[u8], hash_domains: bool) -> Anonymizer {
+        Anonymizer {
+            key: hmac::Key::new(hmac::HMAC_SHA256, key),
+            hash_domains,
+        }
+    }
+
+    /// An anonymizer with a fresh random key, which is never shown to anyone.
+    pub fn random(hash_domains: bool) -> anyhow::Result<Anonymizer> {
+        let mut key = [0; 32];
+        SystemRandom::new()
+            .fill(&mut key)
+            .map_err(|_| anyhow::anyhow!("couldn't generate an anonymization key"))?;
+        Ok(Anonymizer::new(&key, hash_domains))
+    }
+
+    // Local parts and domains are hashed in their own namespaces, so `example` the mailbox
+    // and `example` the domain get different tokens
+    fn token(&self, namespace: &str, value: &str) -> String {
+        let mut context = hmac::Context::with_key(&self.key);
+        context.update(namespace.as_bytes());
+        context.update(b"\0");
+        context.update(value.as_bytes());
+        let mut token = String::with_capacity(TOKEN_LEN);
+        for byte in context.sign().as_ref() {
+            write!(token, "{:02x}", byte).unwrap();
+        }
+        token.truncate(TOKEN_LEN);
+        token
+    }
+
+    /// `jane@example.com` -> `3f9a…@example.com`, or `3f9a…@c41e….invalid` when hashing domains.
+    /// Case is ignored. Anything without an `@` is hashed whole like a local part.
+    pub fn address(&self, address: &str) -> String {
+        let address = address.trim().to_lowercase();
+        let Some((local, domain)) = address.rsplit_once('@') else {
+            return self.token("local", &address);
+        };
+        format!("{}@{}", self.token("local", local), self.domain(domain))
+    }
+
+    /// `example.com` as is, or `c41e….invalid` when hashing domains. Case is ignored.
+    pub fn domain(&self, domain: &str) -> String {
+        let domain = domain.trim().to_lowercase();
+        if self.hash_domains {
+            format!("{}.{}", self.token("domain", &domain), HASHED_TLD)
+        } else {
+            domain
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESSES: [&str; 4] = [
+        "jane@example.com",
+        "jane.doe+news@mail.example.co.uk",
+        "bob@example.org",
+        "postmaster",
+    ];
+
+    #[test]
+    fn an_address_always_gets_the_same_token() {
+        let anonymizer = Anonymizer::new(b"key", false);
+        let token = anonymizer.address("jane@example.com");
+        assert_eq!(
+            Anonymizer::new(b"key", false).address("jane@example.com"),
+            token
+        );
+        assert_eq!(anonymizer.address(" Jane@Example.COM "), token);
+        assert_eq!(token.len(), TOKEN_LEN + "@example.com".len());
+        assert!(token.ends_with("@example.com"));
+    }
+
+    #[test]
+    fn different_addresses_and_keys_get_different_tokens() {
+        let anonymizer = Anonymizer::new(b"key", true);
+        let mut tokens = ADDRESSES
+            .iter()
+            .map(|address| anonymizer.address(address))
+            .collect::<Vec<_>>();
+        tokens.sort();
+        tokens.dedup();
+        assert_eq!(tokens.len(), ADDRESSES.len());
+
+        assert_ne!(
+            Anonymizer::new(b"other key", true).address("jane@example.com"),
+            anonymizer.address("jane@example.com")
+        );
+        // A mailbox and a domain of the same name don't share a token
+        let mailbox = anonymizer.address("example@x.com");
+        let domain = anonymizer.domain("example");
+        assert_ne!(mailbox.split('@').next(), domain.split('.').next());
+    }
+
+    #[test]
+    fn hashed_domains_are_consistent_across_addresses() {
+        let anonymizer = Anonymizer::new(b"key", true);
+        let domain = anonymizer.domain("Example.com");
+        assert_eq!(domain.len(), TOKEN_LEN + ".invalid".len());
+        assert!(domain.ends_with(".invalid"));
+        for address in ["jane@example.com", "bob@EXAMPLE.com"] {
+            assert_eq!(
+                anonymizer.address(address).split_once('@').unwrap().1,
+                domain
+            );
+        }
+        assert_eq!(
+            Anonymizer::new(b"key", false).domain("Example.com"),
+            "example.com"
+        );
+    }
+
+    #[test]
+    fn no_part_of_an_address_is_left_in_its_token() {
+        let anonymizer = Anonymizer::new(b"key", true);
+        for address in ADDRESSES {
+            let token = anonymizer.address(address);
+            for part in address.split(['@', '.', '+']) {
+                assert!(!token.contains(part), "{:?} in {:?}", part, token);
+            }
+            assert!(token
+                .chars()
+                .all(|c| c.is_ascii_hexdigit() || "@.".contains(c) || "invalid".contains(c)));
+        }
+    }
+}
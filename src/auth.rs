@@ -0,0 +1,61 @@
+use google_gmail1::hyper::client::HttpConnector;
+use google_gmail1::hyper_rustls::HttpsConnector;
+use google_gmail1::oauth2::authenticator::Authenticator;
+use google_gmail1::{hyper, hyper_rustls, oauth2, Gmail};
+
+use crate::db;
+use crate::error::GmailStatsError;
+
+pub type Connector = HttpsConnector<HttpConnector>;
+
+// An HTTPS client for Google's APIs
+pub fn client() -> hyper::Client<Connector> {
+    hyper::Client::builder().build(
+        // hyper_rustls::HttpsConnector::with_native_roots()
+        hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .enable_http2()
+            .build(),
+    )
+}
+
+// Where the tokens of `profile` are kept. The default account keeps the original file name.
+fn token_cache(profile: Option<&str>) -> String {
+    match profile {
+        Some(name) if name != db::DEFAULT_ACCOUNT => format!("tokencache-{}.json", name),
+        _ => "tokencache.json".to_string(),
+    }
+}
+
+// Build an authenticator for `profile`'s account, which asks for consent to whichever scopes are
+// first requested of it
+pub async fn authenticator(profile: Option<&str>) -> anyhow::Result<Authenticator<Connector>> {
+    // Read application OAuth secret from a file.
+    let secret = oauth2::read_application_secret("credentials.json")
+        .await
+        .map_err(|err| GmailStatsError::Auth(format!("couldn't read credentials.json: {}", err)))?;
+
+    // Create an authenticator that uses an InstalledFlow to authenticate. The
+    // authentication tokens are persisted to the profile's token cache file. The
+    // authenticator takes care of caching tokens to disk and refreshing tokens once
+    // they've expired.
+    let auth = oauth2::InstalledFlowAuthenticator::builder(
+        secret,
+        oauth2::InstalledFlowReturnMethod::HTTPRedirect,
+    )
+    .persist_tokens_to_disk(token_cache(profile))
+    .build()
+    .await
+    .map_err(|err| GmailStatsError::Auth(err.to_string()))?;
+
+    Ok(auth)
+}
+
+// Build a Gmail hub authenticated as `profile`'s account
+pub async fn hub(profile: Option<&str>) -> anyhow::Result<Gmail> {
+    let hub = Gmail::new(client(), authenticator(profile).await?);
+
+    Ok(hub)
+}
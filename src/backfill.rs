@@ -0,0 +1,423 @@
+use sqlx::{Pool, Sqlite, SqliteConnection};
+
+use crate::auth;
+use crate::cli::{BackfillArgs, BackfillField};
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::db;
+use crate::error::GmailStatsError;
+use crate::fetch::{self, MAX_RETRIES};
+use crate::forget::{self, Tombstones};
+use crate::format;
+use crate::ignore::IgnoreList;
+use crate::interrupt::{self, CancelToken};
+use crate::own::Identity;
+use crate::parse::{MessageInfo, ParseOptions, SENT};
+use crate::retention;
+use crate::source::{GmailSource, MailSource};
+
+/// Messages looked at per transaction, and between progress lines.
+const BATCH: i64 = 100;
+
+/// Settings for a backfill.
+#[derive(Clone, Debug)]
+pub struct BackfillOptions {
+    /// The columns to fill in, every one of them if empty.
+    pub fields: Vec<BackfillField>,
+    pub parse: ParseOptions,
+    pub ignore: IgnoreList,
+    pub erased: Tombstones,
+    pub own: Identity,
+    /// The Gmail account backfilled, `--profile`.
+    pub account: Option<String>,
+    /// Record seen messages which have no row. Off with a `[retention]` period for messages,
+    /// whose deleted rows can't be told from ones never recorded.
+    pub record_missing: bool,
+    pub cancel: CancelToken,
+    pub max_retries: u32,
+    pub clock: Clock,
+}
+
+impl BackfillOptions {
+    /// The erased senders are left empty, for `run()` to read from the database.
+    pub fn new(args: &BackfillArgs, config: &Config) -> BackfillOptions {
+        BackfillOptions {
+            fields: if args.fields.is_empty() {
+                BackfillField::ALL.to_vec()
+            } else {
+                args.fields.clone()
+            },
+            parse: ParseOptions {
+                forwarders: if args.unwrap_forwarded {
+                    config.forwarders.clone()
+                } else {
+                    Vec::new()
+                },
+                exclude_inline: false,
+            },
+            ignore: IgnoreList::new(&config.ignore),
+            erased: Tombstones::default(),
+            own: config.identity.clone(),
+            account: args.profile.clone(),
+            record_missing: config.retention.messages.is_none(),
+            cancel: CancelToken::default(),
+            max_retries: args.max_retries.unwrap_or(MAX_RETRIES),
+            clock: args.clock,
+        }
+    }
+
+    pub fn account(&self) -> &str {
+        self.account.as_deref().unwrap_or(db::DEFAULT_ACCOUNT)
+    }
+}
+
+/// What a backfill did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Backfilled {
+    /// Messages which needed backfilling when it started.
+    pub total: i64,
+    /// Stored messages with missing columns filled in.
+    pub filled: u64,
+    /// Seen messages with no row until now, recorded whole.
+    pub recorded: u64,
+    /// Messages Gmail no longer has, or couldn't give us.
+    pub gone: u64,
+    /// Messages with no row which a fetch wouldn't record either: from an ignored or erased
+    /// sender, from one of my addresses, or already recorded under another id.
+    pub passed_over: u64,
+}
+
+impl Backfilled {
+    pub fn done(&self) -> u64 {
+        self.filled + self.recorded + self.gone + self.passed_over
+    }
+}
+
+// Which seen messages of `account` are missing any of `fields` and haven't been backfilled for
+// it, as a condition on `seen_mails s` left joined to `messages m`. Messages with no row at all
+// are only included when `record_missing`.
+fn missing(fields: &[BackfillField], record_missing: bool) -> String {
+    let no_row = if record_missing {
+        "m.mail_id IS NULL OR "
+    } else {
+        ""
+    };
+    let missing = fields
+        .iter()
+        .map(|field| {
+            format!(
+                "(({}m.{} IS NULL) AND NOT EXISTS (SELECT 1 FROM backfilled b
+                    WHERE b.account = s.account AND b.mail_id = s.mail_id AND b.field = '{}'))",
+                no_row,
+                field.column(),
+                field.name()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" OR ");
+    let has_row = if record_missing {
+        ""
+    } else {
+        "m.mail_id IS NOT NULL AND "
+    };
+    format!("s.account = ? AND {}({})", has_row, missing)
+}
+
+/// How many seen messages of `account` still need backfilling for `fields`, counting those with
+/// no row only when `record_missing`.
+pub async fn pending(
+    pool: &Pool<Sqlite>,
+    fields: &[BackfillField],
+    record_missing: bool,
+    account: &str,
+) -> Result<i64, GmailStatsError> {
+    let (count,): (i64,) = sqlx::query_as(&format!(
+        "SELECT count(*) FROM seen_mails s LEFT JOIN messages m
+            ON m.account = s.account AND m.mail_id = s.mail_id
+        WHERE {}",
+        missing(fields, record_missing)
+    ))
+    .bind(account)
+    .fetch_one(pool)
+    .await?;
+    Ok(count)
+}
+
+/// The next seen messages needing backfilling, most recently seen first, so reports fill in
+/// back from the present.
+pub async fn next_batch(
+    pool: &Pool<Sqlite>,
+    fields: &[BackfillField],
+    record_missing: bool,
+    account: &str,
+) -> Result<Vec<String>, GmailStatsError> {
+    let rows: Vec<(String,)> = sqlx::query_as(&format!(
+        "SELECT s.mail_id FROM seen_mails s LEFT JOIN messages m
+            ON m.account = s.account AND m.mail_id = s.mail_id
+        WHERE {}
+        ORDER BY s.rowid DESC
+        LIMIT ?",
+        missing(fields, record_missing)
+    ))
+    .bind(account)
+    .bind(BATCH)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|(id,)| id).collect())
+}
+
+/// Fill in `fields` of a stored message of `account` where they're missing, leaving the rest of
+/// its row as it is. Returns whether there was a row to fill in.
+pub async fn fill(
+    info: &MessageInfo,
+    fields: &[BackfillField],
+    account: &str,
+    conn: &mut SqliteConnection,
+) -> Result<bool, GmailStatsError> {
+    let sql = format!(
+        "UPDATE messages SET {} WHERE account = ? AND mail_id = ?",
+        fields
+            .iter()
+            .map(|field| format!("{0} = coalesce({0}, ?)", field.column()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let mut query = sqlx::query(&sql);
+    for field in fields {
+        query = match field {
+            BackfillField::Date => query.bind(info.date),
+            BackfillField::Thread => query.bind(info.thread_id.clone()),
+            BackfillField::Subject => query.bind(info.subject.clone()),
+        };
+    }
+    let updated = query
+        .bind(account)
+        .bind(&info.id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(updated.rows_affected() > 0)
+}
+
+// Note that `id` has been looked at for `fields`, whatever came of it
+async fn mark_backfilled(
+    id: &str,
+    fields: &[BackfillField],
+    account: &str,
+    conn: &mut SqliteConnection,
+) -> Result<(), GmailStatsError> {
+    for field in fields {
+        sqlx::query("INSERT OR IGNORE INTO backfilled (account, mail_id, field) VALUES (?, ?, ?)")
+            .bind(account)
+            .bind(id)
+            .bind(field.name())
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+// Fetch one message and fill in its row, or record it if it has none. Senders' counts aren't
+// touched: the message was counted when it was first fetched.
+async fn backfill_message(
+    id: &str,
+    source: &impl MailSource,
+    opts: &BackfillOptions,
+    done: &mut Backfilled,
+    conn: &mut SqliteConnection,
+) -> Result<(), GmailStatsError> {
+    let parsed = match source.get_message(id).await {
+        Ok(mut message) => {
+            message.id.get_or_insert_with(|| id.to_string());
+            MessageInfo::from_message(&message, &opts.parse)
+        }
+        Err(err) => Err(err),
+    };
+    let info = match parsed {
+        Ok(info) => info,
+        // Gone since it was fetched, or something Gmail won't give us: there's nothing to fill
+        // in, and asking again won't change that
+        Err(
+            err @ (GmailStatsError::Api {
+                status: Some(400 | 404 | 410),
+                ..
+            }
+            | GmailStatsError::Parse(_)),
+        ) => {
+            eprintln!("passing over {}: {}", id, err);
+            mark_backfilled(id, &opts.fields, opts.account(), conn).await?;
+            done.gone += 1;
+            return Ok(());
+        }
+        Err(err) => return Err(err),
+    };
+
+    if fill(&info, &opts.fields, opts.account(), conn).await? {
+        done.filled += 1;
+    } else {
+        // Recorded under the same rules as a fetch, so mail a fetch leaves out stays out
+        let sender = &info.sender.sender;
+        let sent = info.has_label(SENT);
+        let left_out = if opts.erased.is_erased(sender) {
+            Some("erased")
+        } else if opts.ignore.is_ignored(sender) && !sent {
+            Some("ignored")
+        } else if opts.own.is_mine(sender) && !sent {
+            Some("own")
+        } else if db::recorded_elsewhere(&info, opts.account(), &mut *conn).await? {
+            Some("duplicate")
+        } else {
+            None
+        };
+        if let Some(reason) = left_out {
+            db::mark_unrecorded(id, reason, opts.account(), &mut *conn).await?;
+            done.passed_over += 1;
+        } else {
+            db::record_message(&info, opts.account(), None, opts.clock.now(), conn).await?;
+            done.recorded += 1;
+        }
+    }
+    mark_backfilled(id, &opts.fields, opts.account(), conn).await
+}
+
+/// Work through the messages needing backfilling a batch at a time, each batch one transaction,
+/// until there are none left or it's cancelled.
+pub async fn work(
+    pool: &Pool<Sqlite>,
+    source: &impl MailSource,
+    opts: &BackfillOptions,
+    done: &mut Backfilled,
+) -> Result<(), GmailStatsError> {
+    loop {
+        if opts.cancel.is_cancelled() {
+            return Ok(());
+        }
+        let ids = next_batch(pool, &opts.fields, opts.record_missing, opts.account()).await?;
+        if ids.is_empty() {
+            return Ok(());
+        }
+        // Counted once committed, as a failed batch is rolled back and fetched afresh
+        let mut batch = Backfilled::default();
+        let mut tx = pool.begin().await?;
+        for id in &ids {
+            if opts.cancel.is_cancelled() {
+                break;
+            }
+            backfill_message(id, source, opts, &mut batch, &mut tx).await?;
+        }
+        tx.commit().await?;
+        done.filled += batch.filled;
+        done.recorded += batch.recorded;
+        done.gone += batch.gone;
+        done.passed_over += batch.passed_over;
+        eprintln!(
+            "backfilled {} of {} messages",
+            format::thousands(done.done() as i64),
+            format::thousands(done.total)
+        );
+    }
+}
+
+/// Backfill `opts.fields` of every seen message missing them, retrying transient errors the way
+/// a fetch does. Ctrl-C stops it after the message in hand, failing with
+/// `GmailStatsError::Interrupted`; what was done is kept, and the next backfill carries on.
+pub async fn run(
+    pool: &Pool<Sqlite>,
+    source: &impl MailSource,
+    opts: &BackfillOptions,
+) -> Result<Backfilled, GmailStatsError> {
+    let opts = &BackfillOptions {
+        erased: forget::tombstones(pool).await?,
+        ..opts.clone()
+    };
+    let mut done = Backfilled {
+        total: pending(pool, &opts.fields, opts.record_missing, opts.account()).await?,
+        ..Default::default()
+    };
+    // Expired message rows look just like ones never recorded, and recording them again would
+    // only have the next fetch delete them
+    if !opts.record_missing {
+        eprintln!(
+            "with a [retention] period for messages, only messages which still have a row are \
+            filled in"
+        );
+    }
+    if done.total == 0 {
+        return Ok(done);
+    }
+    eprintln!("{} messages to backfill", format::thousands(done.total));
+
+    let mut retries = 0;
+    loop {
+        let before = done.done();
+        let err = match work(pool, source, opts, &mut done).await {
+            Ok(()) => break,
+            Err(err) => err,
+        };
+        if done.done() > before {
+            retries = 0;
+        }
+        if !err.is_transient() {
+            if let Some(hint) = err.hint() {
+                eprintln!("{}", hint);
+            }
+            return Err(err);
+        }
+        if retries >= opts.max_retries {
+            eprintln!("giving up after {} retries in a row", retries);
+            return Err(err);
+        }
+        let backoff = fetch::backoff(&err, retries);
+        retries += 1;
+        eprintln!(
+            "{}, retrying in {}s ({}/{})",
+            err,
+            backoff.as_secs(),
+            retries,
+            opts.max_retries
+        );
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            _ = opts.cancel.cancelled() => {}
+        }
+    }
+    if opts.cancel.is_cancelled() {
+        eprintln!(
+            "{} messages left to backfill, `backfill` again carries on",
+            format::thousands(done.total - done.done() as i64)
+        );
+        return Err(GmailStatsError::Interrupted);
+    }
+    Ok(done)
+}
+
+/// `backfill`: fill in what's missing from messages fetched before it was recorded, then apply
+/// the retention policy and classify, since there may be new message rows.
+pub async fn run_backfill(
+    pool: &Pool<Sqlite>,
+    args: &BackfillArgs,
+    config: &Config,
+) -> anyhow::Result<()> {
+    let opts = BackfillOptions::new(args, config);
+    interrupt::cancel_on_signal(opts.cancel.clone());
+    let source = GmailSource::new(
+        auth::hub(args.profile.as_deref()).await?,
+        !args.no_compression,
+    );
+    let backfilled = run(pool, &source, &opts).await;
+    if matches!(
+        backfilled,
+        Ok(_) | Err(GmailStatsError::Interrupted | GmailStatsError::QuotaExhausted)
+    ) {
+        retention::enforce(pool, &config.retention, args.clock.now(), false).await?;
+        crate::classify(pool, config).await?;
+    }
+    let done = backfilled?;
+    println!(
+        "{} messages filled in, {} recorded, {} gone from Gmail, {} passed over",
+        format::thousands(done.filled as i64),
+        format::thousands(done.recorded as i64),
+        format::thousands(done.gone as i64),
+        format::thousands(done.passed_over as i64)
+    );
+    Ok(())
+}
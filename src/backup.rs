@@ -0,0 +1,308 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{Connection, SqliteConnection};
+
+use crate::cli::RestoreArgs;
+use crate::format;
+use crate::search;
+use crate::Storage;
+
+/// The database in the working directory.
+pub const DATABASE: &str = "stats.db";
+
+/// Backups are the database's name, this, and when they were taken.
+pub const SUFFIX: &str = ".bak.";
+
+/// Backups kept unless `backups` in the config says otherwise.
+pub const DEFAULT_KEEP: usize = 3;
+
+/// Where a backup of `database` taken at `now` goes. The timestamps sort in the order the
+/// backups were taken, and as `VACUUM INTO` won't write over a file, one taken in the same
+/// millisecond as another (as under a fixed clock) gets a counter after it.
+pub fn path_for(database: &Path, now: DateTime<Utc>) -> PathBuf {
+    let mut stamp = database.as_os_str().to_os_string();
+    stamp.push(format!("{}{}", SUFFIX, now.format("%Y%m%dT%H%M%S%.3fZ")));
+    let mut path = PathBuf::from(&stamp);
+    let mut taken = 0;
+    while path.exists() {
+        taken += 1;
+        let mut name = stamp.clone();
+        name.push(format!("-{}", taken));
+        path = PathBuf::from(name);
+    }
+    path
+}
+
+/// The backups of `database`, oldest first.
+pub fn list(database: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let dir = match database.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let prefix = format!(
+        "{}{}",
+        database.file_name().unwrap_or_default().to_string_lossy(),
+        SUFFIX
+    );
+    let mut backups = fs::read_dir(dir)?
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    backups.sort();
+    Ok(backups)
+}
+
+/// Delete all but the newest `keep` backups of `database`, returning those deleted.
+pub fn rotate(database: &Path, keep: usize) -> std::io::Result<Vec<PathBuf>> {
+    let backups = list(database)?;
+    let expired = backups[..backups.len().saturating_sub(keep)].to_vec();
+    for backup in &expired {
+        fs::remove_file(backup)?;
+    }
+    Ok(expired)
+}
+
+/// Copy the open database to `to` with SQLite's `VACUUM INTO`, which takes a consistent copy
+/// while it's in use, folding in anything still in the write-ahead log.
+pub async fn backup(storage: &Storage, to: &Path) -> anyhow::Result<()> {
+    sqlx::query("VACUUM INTO ?")
+        .bind(to.to_string_lossy().into_owned())
+        .execute(storage)
+        .await?;
+    Ok(())
+}
+
+/// Back the database up before a command changes it, keeping the newest `keep` backups and
+/// saying so unless `quiet`.
+pub async fn before_run(
+    storage: &Storage,
+    keep: usize,
+    now: DateTime<Utc>,
+    quiet: bool,
+) -> anyhow::Result<()> {
+    let database = Path::new(DATABASE);
+    let to = path_for(database, now);
+    backup(storage, &to).await?;
+    let expired = rotate(database, keep)?;
+    if quiet {
+        return Ok(());
+    }
+    let size = fs::metadata(&to).map(|meta| meta.len()).unwrap_or_default();
+    eprintln!(
+        "backed up {} to {} ({})",
+        DATABASE,
+        to.display(),
+        format::bytes(size as i64)
+    );
+    for expired in expired {
+        eprintln!("removed the old backup {}", expired.display());
+    }
+    Ok(())
+}
+
+/// Replace `database` with `backup`, once it's been checked to be a database. The database's
+/// write-ahead log goes too, so none of it is replayed over the backup.
+pub async fn restore(backup: &Path, database: &Path) -> anyhow::Result<()> {
+    let options = SqliteConnectOptions::new().filename(backup).read_only(true);
+    let mut conn = SqliteConnection::connect_with(&options)
+        .await
+        .map_err(|err| {
+            anyhow::anyhow!("{} isn't a readable database: {}", backup.display(), err)
+        })?;
+    let (integrity,): (String,) = sqlx::query_as("PRAGMA quick_check")
+        .fetch_one(&mut conn)
+        .await?;
+    conn.close().await?;
+    if integrity != "ok" {
+        anyhow::bail!("{} is damaged: {}", backup.display(), integrity);
+    }
+
+    // Copied alongside and renamed over, so a failed copy leaves the database as it was
+    let mut partial = database.as_os_str().to_os_string();
+    partial.push(".restoring");
+    let partial = PathBuf::from(partial);
+    fs::copy(backup, &partial)?;
+    for suffix in ["-wal", "-shm"] {
+        let mut sidecar = database.as_os_str().to_os_string();
+        sidecar.push(suffix);
+        match fs::remove_file(PathBuf::from(sidecar)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+    fs::rename(&partial, database)?;
+    Ok(())
+}
+
+/// `db restore`: roll the database back to a backup, backing up what it replaces first unless
+/// `--no-backup` was given. Old backups aren't rotated out, as the one being restored may be
+/// among them.
+pub async fn run_restore(
+    args: &RestoreArgs,
+    backup_first: bool,
+    now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let database = Path::new(DATABASE);
+    if backup_first && database.exists() {
+        let storage = crate::open_storage().await?;
+        let to = path_for(database, now);
+        backup(&storage, &to).await?;
+        storage.close().await;
+        eprintln!("backed up {} to {}", DATABASE, to.display());
+    }
+    restore(&args.backup, database).await?;
+    println!("restored {} from {}", DATABASE, args.backup.display());
+    // A backup's rowids can differ from the database it was taken of, and the subject index
+    // goes by them
+    let storage = crate::open_storage().await?;
+    if search::is_available(&storage).await? {
+        search::reindex(&storage).await?;
+    }
+    storage.close().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+
+    // A database of its own for each test, in a directory cleared first
+    fn database(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "gmail-stats-backup-{}-{}",
+            test,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(DATABASE)
+    }
+
+    async fn open(database: &Path) -> Storage {
+        let options = SqliteConnectOptions::new()
+            .filename(database)
+            .create_if_missing(true);
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await
+            .unwrap()
+    }
+
+    async fn notes(storage: &Storage) -> Vec<String> {
+        sqlx::query_scalar("SELECT note FROM notes ORDER BY note")
+            .fetch_all(storage)
+            .await
+            .unwrap()
+    }
+
+    fn at(second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, second).unwrap()
+    }
+
+    #[tokio::test]
+    async fn backups_in_the_same_instant_get_names_of_their_own() {
+        let database = database("same-instant");
+        let storage = open(&database).await;
+
+        let first = path_for(&database, at(0));
+        backup(&storage, &first).await.unwrap();
+        let second = path_for(&database, at(0));
+        backup(&storage, &second).await.unwrap();
+        storage.close().await;
+
+        assert!(first
+            .to_string_lossy()
+            .ends_with(".bak.20240101T120000.000Z"));
+        assert!(second
+            .to_string_lossy()
+            .ends_with(".bak.20240101T120000.000Z-1"));
+        assert_eq!(list(&database).unwrap(), [first, second]);
+    }
+
+    #[tokio::test]
+    async fn rotating_keeps_the_newest_backups() {
+        let database = database("rotate");
+        let storage = open(&database).await;
+        let mut taken = Vec::new();
+        // Taken out of order, to show it goes by the name rather than the file's age
+        for second in [3, 1, 2, 0] {
+            let to = path_for(&database, at(second));
+            backup(&storage, &to).await.unwrap();
+            taken.push(to);
+        }
+        storage.close().await;
+
+        let expired = rotate(&database, 2).unwrap();
+
+        assert_eq!(expired, [taken[3].clone(), taken[1].clone()]);
+        assert_eq!(
+            list(&database).unwrap(),
+            [taken[2].clone(), taken[0].clone()]
+        );
+        assert!(database.exists());
+        // Keeping more than there are removes none
+        assert!(rotate(&database, 5).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn restoring_puts_the_backup_in_place_of_the_database() {
+        let database = database("restore");
+        let storage = open(&database).await;
+        sqlx::query("CREATE TABLE notes (note TEXT)")
+            .execute(&storage)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO notes VALUES ('before')")
+            .execute(&storage)
+            .await
+            .unwrap();
+        let backup_path = path_for(&database, at(0));
+        backup(&storage, &backup_path).await.unwrap();
+        sqlx::query("INSERT INTO notes VALUES ('after')")
+            .execute(&storage)
+            .await
+            .unwrap();
+        storage.close().await;
+        let mut wal = database.as_os_str().to_os_string();
+        wal.push("-wal");
+        fs::write(&wal, "left over").unwrap();
+
+        restore(&backup_path, &database).await.unwrap();
+
+        assert!(!Path::new(&wal).exists());
+        let storage = open(&database).await;
+        assert_eq!(notes(&storage).await, ["before"]);
+        storage.close().await;
+    }
+
+    #[tokio::test]
+    async fn a_file_which_isnt_a_database_is_not_restored() {
+        let database = database("not-a-database");
+        let storage = open(&database).await;
+        sqlx::query("CREATE TABLE notes (note TEXT)")
+            .execute(&storage)
+            .await
+            .unwrap();
+        storage.close().await;
+        let bogus = database.with_file_name("bogus.bak");
+        fs::write(
+            &bogus,
+            "not a database, but long enough to have a header of sorts",
+        )
+        .unwrap();
+
+        assert!(restore(&bogus, &database).await.is_err());
+
+        let storage = open(&database).await;
+        assert!(notes(&storage).await.is_empty());
+        storage.close().await;
+    }
+}
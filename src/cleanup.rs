@@ -0,0 +1,426 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Sqlite};
+
+use crate::auth;
+use crate::cli::{CleanupAction, CleanupArgs};
+use crate::config::Config;
+use crate::dates;
+use crate::db;
+use crate::error::GmailStatsError;
+use crate::format;
+use crate::labels::{LabelTarget, BATCH_SIZE};
+use crate::report;
+
+impl CleanupAction {
+    /// How it's recorded in the `actions` table.
+    pub fn name(self) -> &'static str {
+        match self {
+            CleanupAction::Archive => "archive",
+            CleanupAction::Trash => "trash",
+        }
+    }
+
+    // The labels added and taken off to do it
+    fn labels(self) -> (&'static [&'static str], &'static [&'static str]) {
+        match self {
+            CleanupAction::Archive => (&[], &["INBOX"]),
+            CleanupAction::Trash => (&["TRASH"], &[]),
+        }
+    }
+}
+
+/// A stored message `cleanup` matched.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub id: String,
+    pub sender: String,
+    /// internalDate in epoch milliseconds.
+    pub date: Option<i64>,
+}
+
+/// Stored messages in `account` from any of `senders`, ignoring case and counting their aliases,
+/// received before `before` (epoch milliseconds) if it's set, oldest first.
+pub async fn select(
+    pool: &Pool<Sqlite>,
+    senders: &[String],
+    before: Option<i64>,
+    account: &str,
+) -> anyhow::Result<Vec<Match>> {
+    let senders = serde_json::to_string(
+        &senders
+            .iter()
+            .map(|sender| sender.trim().to_lowercase())
+            .collect::<Vec<_>>(),
+    )?;
+    let rows: Vec<(String, String, Option<i64>)> = sqlx::query_as(
+        "WITH wanted AS (SELECT value FROM json_each(?))
+        SELECT m.mail_id, m.sender, m.internal_date
+        FROM messages m LEFT JOIN sender_aliases a ON a.sender = lower(m.sender)
+        WHERE (lower(m.sender) IN wanted OR lower(a.canonical) IN wanted)
+            AND (? IS NULL OR m.internal_date < ?) AND m.account = ?
+        ORDER BY m.internal_date, m.mail_id",
+    )
+    .bind(&senders)
+    .bind(before)
+    .bind(before)
+    .bind(account)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(id, sender, date)| Match { id, sender, date })
+        .collect())
+}
+
+/// What happened to one message: `None` if the change was made, otherwise why not.
+pub type Outcome = (String, Option<String>);
+
+// Gmail rejects a whole batch over one message deleted since it was fetched, so a rejected
+// batch is retried a message at a time and only the messages it still rejects are given up on
+async fn modify_batch(
+    target: &impl LabelTarget,
+    ids: &[String],
+    action: CleanupAction,
+) -> Result<Vec<Outcome>, GmailStatsError> {
+    let (add, remove) = action.labels();
+    match target.modify(ids, add, remove).await {
+        Ok(()) => return Ok(ids.iter().map(|id| (id.clone(), None)).collect()),
+        Err(GmailStatsError::Api {
+            status: Some(400 | 404),
+            ..
+        }) => {}
+        Err(err) => return Err(err),
+    }
+
+    let mut outcomes = Vec::with_capacity(ids.len());
+    for id in ids {
+        let outcome = match target.modify(std::slice::from_ref(id), add, remove).await {
+            Ok(()) => None,
+            Err(
+                err @ GmailStatsError::Api {
+                    status: Some(400 | 404),
+                    ..
+                },
+            ) => Some(err.to_string()),
+            Err(err) => return Err(err),
+        };
+        outcomes.push((id.clone(), outcome));
+    }
+    Ok(outcomes)
+}
+
+/// Archive or trash `matches` a batch at a time, recording each message in the `actions` table
+/// as its batch finishes. Returns how many were changed and how many Gmail refused.
+pub async fn perform(
+    pool: &Pool<Sqlite>,
+    target: &impl LabelTarget,
+    action: CleanupAction,
+    matches: &[Match],
+    now: DateTime<Utc>,
+) -> anyhow::Result<(usize, usize)> {
+    let (mut done, mut failed) = (0, 0);
+    for batch in matches.chunks(BATCH_SIZE) {
+        let ids = batch.iter().map(|m| m.id.clone()).collect::<Vec<_>>();
+        let outcomes = modify_batch(target, &ids, action).await?;
+
+        let mut tx = pool.begin().await?;
+        for (m, (_, error)) in batch.iter().zip(&outcomes) {
+            db::record_action(
+                action.name(),
+                &m.id,
+                &m.sender,
+                error.as_deref(),
+                now,
+                &mut tx,
+            )
+            .await?;
+            match error {
+                None => done += 1,
+                Some(error) => {
+                    eprintln!("couldn't {} {}: {}", action.name(), m.id, error);
+                    failed += 1;
+                }
+            }
+        }
+        tx.commit().await?;
+        eprintln!("{}/{} messages", done + failed, matches.len());
+    }
+    Ok((done, failed))
+}
+
+/// `cleanup`: match the mail, then describe it for `--dry-run` or, with `--yes`, change it.
+pub async fn run(pool: &Pool<Sqlite>, args: &CleanupArgs, config: &Config) -> anyhow::Result<()> {
+    if !args.dry_run && !args.yes {
+        anyhow::bail!(
+            "cleanup changes your mailbox: run it with --dry-run to see what it would {}, then \
+            with --yes to do it",
+            args.action.name()
+        );
+    }
+    crate::classify(pool, config).await?;
+    let before = match &args.before {
+        Some(before) => Some(dates::start_of_day(
+            dates::parse_date(before)?,
+            report::timezone(None, config)?,
+        )),
+        None => None,
+    };
+    let matches = select(
+        pool,
+        &args.sender,
+        before,
+        args.profile.as_deref().unwrap_or(db::DEFAULT_ACCOUNT),
+    )
+    .await?;
+    let first = matches.iter().find_map(|m| m.date);
+    let last = matches.iter().rev().find_map(|m| m.date);
+    println!(
+        "{} {} {} messages, received {} to {}",
+        if args.dry_run { "would" } else { "will" },
+        args.action.name(),
+        format::thousands(matches.len() as i64),
+        format::date(first),
+        format::date(last)
+    );
+    if args.dry_run || matches.is_empty() {
+        return Ok(());
+    }
+
+    let hub = auth::hub(args.profile.as_deref()).await?;
+    let (done, failed) = perform(pool, &hub, args.action, &matches, args.clock.now()).await?;
+    println!(
+        "{} {} messages{}",
+        match args.action {
+            CleanupAction::Archive => "archived",
+            CleanupAction::Trash => "trashed",
+        },
+        format::thousands(done as i64),
+        if failed > 0 {
+            format!(
+                ", {} no longer there or refused",
+                format::thousands(failed as i64)
+            )
+        } else {
+            String::new()
+        }
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+    use crate::testsupport::{self, info, MockMailSource, Modification};
+
+    async fn store(pool: &Pool<Sqlite>, id: &str, from: &str, date: i64, account: &str) {
+        let mut conn = pool.acquire().await.unwrap();
+        db::record_message(
+            &info(id, from, date, &["INBOX"]),
+            account,
+            None,
+            DateTime::UNIX_EPOCH,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+    }
+
+    fn matches(count: usize) -> Vec<Match> {
+        (0..count)
+            .map(|n| Match {
+                id: format!("m{:04}", n),
+                sender: "news@example.com".to_string(),
+                date: Some(n as i64),
+            })
+            .collect()
+    }
+
+    // (action, mail_id, error) of every recorded action, in order
+    async fn actions(pool: &Pool<Sqlite>) -> Vec<(String, String, Option<String>)> {
+        sqlx::query_as("SELECT action, mail_id, error FROM actions ORDER BY rowid")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    fn args(dry_run: bool, yes: bool) -> CleanupArgs {
+        CleanupArgs {
+            clock: Clock::default(),
+            sender: vec!["news@example.com".to_string()],
+            action: CleanupAction::Archive,
+            before: None,
+            dry_run,
+            yes,
+            profile: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn mail_is_matched_by_sender_alias_date_and_account() {
+        let pool = testsupport::pool().await;
+        store(
+            &pool,
+            "late",
+            "News@Example.com",
+            3_000,
+            db::DEFAULT_ACCOUNT,
+        )
+        .await;
+        store(
+            &pool,
+            "early",
+            "news@example.com",
+            1_000,
+            db::DEFAULT_ACCOUNT,
+        )
+        .await;
+        store(
+            &pool,
+            "alias",
+            "digest@example.com",
+            2_000,
+            db::DEFAULT_ACCOUNT,
+        )
+        .await;
+        store(
+            &pool,
+            "other",
+            "jane@example.com",
+            1_500,
+            db::DEFAULT_ACCOUNT,
+        )
+        .await;
+        store(&pool, "work", "news@example.com", 1_000, "work").await;
+        db::apply_aliases(
+            &pool,
+            &[(
+                "digest@example.com".to_string(),
+                "news@example.com".to_string(),
+            )],
+        )
+        .await
+        .unwrap();
+        let ids = |matches: Vec<Match>| matches.into_iter().map(|m| m.id).collect::<Vec<_>>();
+        let senders = [" NEWS@example.com ".to_string()];
+
+        let all = select(&pool, &senders, None, db::DEFAULT_ACCOUNT)
+            .await
+            .unwrap();
+        assert_eq!(ids(all), ["early", "alias", "late"]);
+        let before = select(&pool, &senders, Some(3_000), db::DEFAULT_ACCOUNT)
+            .await
+            .unwrap();
+        assert_eq!(ids(before), ["early", "alias"]);
+        let work = select(&pool, &senders, None, "work").await.unwrap();
+        assert_eq!(ids(work), ["work"]);
+    }
+
+    #[tokio::test]
+    async fn a_rejected_batch_is_retried_a_message_at_a_time() {
+        let pool = testsupport::pool().await;
+        let target = MockMailSource::new().gone("m1100");
+        let matches = matches(1200);
+
+        let (done, failed) = perform(
+            &pool,
+            &target,
+            CleanupAction::Archive,
+            &matches,
+            DateTime::UNIX_EPOCH,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!((done, failed), (1199, 1));
+        // The first batch whole, then the second's 200 messages one by one but for the gone one
+        let modified = target.modified();
+        assert_eq!(modified.len(), 1 + 199);
+        assert_eq!(modified[0].ids.len(), BATCH_SIZE);
+        assert!(modified[1..].iter().all(|m| m.ids.len() == 1));
+        assert!(modified
+            .iter()
+            .all(|m| m.add.is_empty() && m.remove == ["INBOX"]));
+        let recorded = actions(&pool).await;
+        assert_eq!(recorded.len(), 1200);
+        let refused = recorded
+            .iter()
+            .filter(|(_, _, error)| error.is_some())
+            .map(|(action, id, _)| (action.as_str(), id.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(refused, [("archive", "m1100")]);
+    }
+
+    #[tokio::test]
+    async fn trashing_adds_the_trash_label() {
+        let pool = testsupport::pool().await;
+        let target = MockMailSource::new();
+
+        perform(
+            &pool,
+            &target,
+            CleanupAction::Trash,
+            &matches(2),
+            DateTime::UNIX_EPOCH,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            target.modified(),
+            [Modification {
+                ids: vec!["m0000".to_string(), "m0001".to_string()],
+                add: vec!["TRASH".to_string()],
+                remove: Vec::new(),
+            }]
+        );
+        assert_eq!(actions(&pool).await[0].0, "trash");
+    }
+
+    #[tokio::test]
+    async fn a_transient_failure_stops_before_recording_its_batch() {
+        let pool = testsupport::pool().await;
+        let target = MockMailSource::new().fail_modify(1, || GmailStatsError::Api {
+            status: Some(503),
+            message: "Backend Error".to_string(),
+        });
+
+        perform(
+            &pool,
+            &target,
+            CleanupAction::Archive,
+            &matches(3),
+            DateTime::UNIX_EPOCH,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(target.modified().is_empty());
+        assert!(actions(&pool).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_dry_run_changes_nothing() {
+        let pool = testsupport::pool().await;
+        store(&pool, "m1", "news@example.com", 1_000, db::DEFAULT_ACCOUNT).await;
+
+        run(&pool, &args(true, false), &Config::default())
+            .await
+            .unwrap();
+
+        assert!(actions(&pool).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn cleanup_wants_a_dry_run_or_yes() {
+        let pool = testsupport::pool().await;
+        store(&pool, "m1", "news@example.com", 1_000, db::DEFAULT_ACCOUNT).await;
+
+        let err = run(&pool, &args(false, false), &Config::default())
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("--dry-run"), "{}", err);
+        assert!(actions(&pool).await.is_empty());
+    }
+}
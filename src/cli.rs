@@ -0,0 +1,1167 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::clock::Clock;
+use crate::dates::{self, Granularity, Period};
+use crate::parse::Category;
+
+#[derive(Debug, Parser)]
+#[command(about = "Generate stats on your GMail inbox and store in a SQLite database")]
+pub struct Cli {
+    /// Path to the TOML config file
+    #[arg(long, global = true, default_value = "gmail_stats.toml")]
+    pub config: PathBuf,
+
+    /// Don't back stats.db up before a command which changes it
+    #[arg(long, global = true)]
+    pub no_backup: bool,
+
+    /// Take over the lock on stats.db left by a run which died without removing it
+    #[arg(long, global = true)]
+    pub force: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Fetch new mail and update the stats (the default)
+    Fetch(FetchArgs),
+    /// Print stats from the local database
+    Report(Box<ReportArgs>),
+    /// Write the stored messages to a file for analysis elsewhere
+    Export(ExportArgs),
+    /// Serve the report as a local web page, with the data as JSON under /api
+    Serve(ServeArgs),
+    /// Browse senders and their recent mail in the terminal
+    Tui(TuiArgs),
+    /// Compare message counts by sender between two stats databases
+    Diff(DiffArgs),
+    /// Label the stored mail of the top senders in Gmail. The only command which changes
+    /// anything in the mailbox; Google asks for permission to modify it the first time
+    ApplyLabels(ApplyLabelsArgs),
+    /// Archive or trash the stored mail of some senders in Gmail, after a --dry-run
+    Cleanup(CleanupArgs),
+    /// Count the mail in an mbox file, such as one from Google Takeout, as if it had been
+    /// fetched. Mail both imported and fetched is only counted once
+    ImportMbox(ImportMboxArgs),
+    /// Count the mail in a Maildir, such as one offlineimap syncs, as if it had been fetched.
+    /// Folders are counted as labels
+    ImportMaildir(ImportMaildirArgs),
+    /// Count the mail in a mailbox on any IMAP server, set up in the [imap] section of the
+    /// config file, as if it had been fetched
+    ImportImap(ImportImapArgs),
+    /// Erase everything stored about a sender, or every sender at a domain, and leave their
+    /// mail out of later fetches
+    Forget(ForgetArgs),
+    /// Check the database is consistent, such as after a crash, failing if it isn't
+    Verify(VerifyArgs),
+    /// Find messages by words in their subject
+    Search(SearchArgs),
+    /// Fetch again the messages seen before their date, thread or subject were recorded, and
+    /// fill those in. Senders' counts are left alone
+    Backfill(BackfillArgs),
+    /// Look after the database itself
+    Db(DbArgs),
+}
+
+impl Command {
+    /// Whether the command fetches mail or changes what's stored about it, and so holds the
+    /// lock on the database while it runs.
+    pub fn writes(&self) -> bool {
+        matches!(
+            self,
+            Command::Fetch(_)
+                | Command::Backfill(_)
+                | Command::ApplyLabels(_)
+                | Command::Cleanup(_)
+                | Command::ImportMbox(_)
+                | Command::ImportMaildir(_)
+                | Command::ImportImap(_)
+                | Command::Forget(_)
+                | Command::Verify(VerifyArgs { repair: true })
+                | Command::Db(_)
+        )
+    }
+
+    /// Whether the database is backed up before the command. `forget` isn't, or the backup
+    /// would keep what it erases.
+    pub fn backs_up(&self) -> bool {
+        self.writes() && !matches!(self, Command::Forget(_))
+    }
+
+    /// What "now" is for the command, which also stamps its lock and backup. Commands without
+    /// a clock of their own go by the system's.
+    pub fn clock(&self) -> Clock {
+        match self {
+            Command::Fetch(args) => args.clock,
+            Command::Serve(args) => args.clock,
+            Command::Cleanup(args) => args.clock,
+            Command::Forget(args) => args.clock,
+            Command::Backfill(args) => args.clock,
+            Command::ImportMbox(args) => args.import.clock,
+            Command::ImportMaildir(args) => args.import.clock,
+            Command::ImportImap(args) => args.import.clock,
+            Command::Report(args) => args.clock,
+            _ => Clock::System,
+        }
+    }
+
+    /// Whether the command only prints anything when the run was notable.
+    pub fn quiet(&self) -> bool {
+        matches!(
+            self,
+            Command::Fetch(FetchArgs {
+                quiet_unless_changed: true,
+                ..
+            })
+        )
+    }
+}
+
+#[derive(Debug, Default, Args)]
+pub struct FetchArgs {
+    /// What "now" is for the run's timestamps, the system clock from the command line
+    #[arg(skip)]
+    pub clock: Clock,
+
+    /// Fetch another Gmail account into the same database, signed in separately from the
+    /// default one. Reports merge every account unless asked for `--by-account`
+    #[arg(long, value_name = "NAME", value_parser = parse_profile)]
+    pub profile: Option<String>,
+
+    /// Attribute mail from a configured forwarder to the original sender, taken from the
+    /// X-Original-From, X-Forwarded-For or Resent-From headers (in that order)
+    #[arg(long)]
+    pub unwrap_forwarded: bool,
+
+    /// Don't count inline attachments, such as images embedded in HTML mail
+    #[arg(long)]
+    pub exclude_inline: bool,
+
+    /// Also fetch mail in spam and trash. Spam is kept out of the main sender counts, see
+    /// `report spam`
+    #[arg(long)]
+    pub include_spam_trash: bool,
+
+    /// Only fetch mail in this Gmail category
+    #[arg(long, value_enum)]
+    pub category: Option<Category>,
+
+    /// Write one JSON object per fetched message to this file as it's processed, `-` for stdout
+    #[arg(long, value_name = "PATH")]
+    pub emit_jsonl: Option<PathBuf>,
+
+    /// Write Prometheus metrics for the run to this file when it finishes, e.g. for
+    /// node_exporter's textfile collector
+    #[arg(long, value_name = "PATH")]
+    pub metrics_out: Option<PathBuf>,
+
+    /// Count mail from my own addresses (`my_addresses` in the config) as received, rather
+    /// than leaving it out of the sender counts
+    #[arg(long)]
+    pub include_self: bool,
+
+    /// Ask Gmail for uncompressed responses, e.g. to read them in a debugging proxy
+    #[arg(long)]
+    pub no_compression: bool,
+
+    /// Print how long each listing page and the whole run spent listing, fetching, parsing and
+    /// writing to the database
+    #[arg(long)]
+    pub timing: bool,
+
+    /// Write every listing call, message fetch, parse and database write as a Chrome trace to
+    /// this file, to open in Perfetto or chrome://tracing
+    #[arg(long, value_name = "PATH")]
+    pub trace_out: Option<PathBuf>,
+
+    /// Also print the end-of-run summary as a JSON object, the last line on stdout
+    #[arg(long)]
+    pub summary_json: bool,
+
+    /// Print nothing unless the run was notable: it recorded `--notify-threshold` new messages,
+    /// found a new sender, or hit errors. Then it prints the summary as usual, so cron mails
+    /// only what's worth reading
+    #[arg(long)]
+    pub quiet_unless_changed: bool,
+
+    /// New messages which make a run notable for `--quiet-unless-changed` [default: 1]
+    #[arg(long, value_name = "N", requires = "quiet_unless_changed")]
+    pub notify_threshold: Option<u64>,
+
+    /// Don't compare the messages seen with Gmail's count of the mailbox after a complete run
+    #[arg(long)]
+    pub no_total_check: bool,
+
+    /// Instead of listing the mailbox, try again just the messages `report errors` lists. Those
+    /// which work are cleared from it
+    #[arg(long)]
+    pub retry_errors: bool,
+
+    /// Stop cleanly once the fetch has run this long, such as `30m` or `1h30m`, as Ctrl-C
+    /// would: the message in hand is recorded and the next fetch carries on. Exits with
+    /// status 5
+    #[arg(long, value_name = "DURATION", value_parser = dates::parse_duration)]
+    pub max_duration: Option<Duration>,
+
+    /// Give up after this many transient errors in a row (rate limits, dropped connections, a
+    /// locked database) [default: 5]
+    #[arg(long, value_name = "N")]
+    pub max_retries: Option<u32>,
+
+    /// After fetching, append the date, message and sender totals, new messages and top sender
+    /// to this Google Sheet. The first time, Google asks for access to your spreadsheets
+    #[arg(long, value_name = "SPREADSHEET_ID")]
+    pub sheet_id: Option<String>,
+}
+
+#[derive(Debug, Args)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct ExportArgs {
+    #[command(subcommand)]
+    pub command: Option<ExportCommand>,
+
+    #[arg(long, value_enum, default_value_t = ExportFormat::Parquet)]
+    pub format: ExportFormat,
+
+    /// File to write the messages table to, or the workbook for xlsx
+    #[arg(long, required = true)]
+    pub out: Option<PathBuf>,
+
+    /// Also write the senders table to this file
+    #[arg(long)]
+    pub senders_out: Option<PathBuf>,
+
+    /// Comma-separated message columns to export, all of them by default
+    #[arg(long, value_delimiter = ',')]
+    pub columns: Vec<String>,
+
+    /// Replace the local part of every address with a keyed hash and leave out display names
+    /// and subjects, for sharing
+    #[arg(long)]
+    pub anonymize: bool,
+
+    /// Key for --anonymize, so tokens match between exports. A random key is used otherwise
+    #[arg(long, requires = "anonymize")]
+    pub anonymize_key: Option<String>,
+
+    /// Hash domains as well as local parts
+    #[arg(long, requires = "anonymize")]
+    pub anonymize_domains: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ExportCommand {
+    /// Print the Gmail ids of a sender's stored messages, one per line, for other Gmail tools
+    Ids(ExportIdsArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ExportIdsArgs {
+    /// The sender, matched ignoring case along with every address aliased to the same one
+    #[arg(long)]
+    pub sender: String,
+
+    /// Only mail received before this day (YYYY-MM-DD), in the config's timezone
+    #[arg(long, value_name = "DATE")]
+    pub before: Option<String>,
+
+    /// Only mail of this account, as for `fetch --profile`. Every account's by default
+    #[arg(long, value_name = "NAME", value_parser = parse_profile)]
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    /// Port to listen on
+    #[arg(long, default_value_t = 8080)]
+    pub port: u16,
+
+    /// Address to listen on. Anything but a loopback address lets other machines read your stats
+    #[arg(long, default_value = "127.0.0.1")]
+    pub bind: IpAddr,
+
+    /// Timezone to bucket dates in, e.g. America/New_York (default from config, else UTC)
+    #[arg(long)]
+    pub timezone: Option<String>,
+
+    /// What "now" is for each page, the system clock from the command line
+    #[arg(skip)]
+    pub clock: Clock,
+}
+
+#[derive(Debug, Args)]
+pub struct TuiArgs {
+    /// Hide senders whose mail is at least 90% bulk
+    #[arg(long)]
+    pub no_bulk: bool,
+
+    /// Hide senders from my own domains (`own_domains` in the config)
+    #[arg(long)]
+    pub external_only: bool,
+
+    /// Hide senders who have never mailed one of my addresses directly
+    #[arg(long)]
+    pub direct_only: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ApplyLabelsArgs {
+    /// Number of top senders, by message count, whose mail is labelled
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+
+    /// The Gmail label to add, created if it doesn't exist
+    #[arg(long, default_value = "High Volume")]
+    pub label: String,
+
+    /// List the senders and how many of their messages would be labelled, without signing in
+    /// or changing anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Label the mail of the account fetched with this `fetch --profile`
+    #[arg(long, value_name = "NAME", value_parser = parse_profile)]
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct CleanupArgs {
+    /// What "now" is for the actions table, the system clock from the command line
+    #[arg(skip)]
+    pub clock: Clock,
+
+    /// A sender whose mail to clean up, or one of their aliases; repeat for more
+    #[arg(long, required = true)]
+    pub sender: Vec<String>,
+
+    #[arg(long, value_enum)]
+    pub action: CleanupAction,
+
+    /// Only mail received before this day (YYYY-MM-DD), in the config's timezone
+    #[arg(long, value_name = "DATE")]
+    pub before: Option<String>,
+
+    /// Say how many messages would be changed, without signing in or changing anything
+    #[arg(long, conflicts_with = "yes")]
+    pub dry_run: bool,
+
+    /// Go ahead and change them
+    #[arg(long)]
+    pub yes: bool,
+
+    /// Clean up the account fetched with this `fetch --profile`
+    #[arg(long, value_name = "NAME", value_parser = parse_profile)]
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ForgetArgs {
+    /// What "now" is for the tombstone, the system clock from the command line
+    #[arg(skip)]
+    pub clock: Clock,
+
+    /// An address, or a domain (`example.com` or `@example.com`) to erase every address at it
+    /// and its subdomains
+    pub target: String,
+
+    /// Don't leave a tombstone: forget that their mail was seen too, so the next fetch records
+    /// it afresh
+    #[arg(long)]
+    pub allow_refetch: bool,
+
+    /// Keep the backups of the database, which still hold everything erased, instead of
+    /// deleting them, with a warning saying which they are
+    #[arg(long)]
+    pub keep_backups: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyArgs {
+    /// Fix what can be fixed without guessing, such as counts lower than the messages stored
+    /// and labels of messages which are gone, then check again
+    #[arg(long)]
+    pub repair: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct SearchArgs {
+    /// Words the subject must all contain, in any order. Put a phrase in double quotes inside
+    /// the argument, and end a word with `*` to match words starting with it
+    pub terms: String,
+
+    /// Number of messages to show, newest first
+    #[arg(long, default_value_t = 50)]
+    pub top: usize,
+}
+
+#[derive(Debug, Default, Args)]
+pub struct BackfillArgs {
+    /// What "now" is for `fetched_at` of newly recorded messages, the system clock from the
+    /// command line
+    #[arg(skip)]
+    pub clock: Clock,
+
+    /// Comma-separated columns to fill in, all of them by default
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub fields: Vec<BackfillField>,
+
+    /// As for `fetch --profile`
+    #[arg(long, value_name = "NAME", value_parser = parse_profile)]
+    pub profile: Option<String>,
+
+    /// As for `fetch --unwrap-forwarded`, for messages which had no row at all
+    #[arg(long)]
+    pub unwrap_forwarded: bool,
+
+    /// As for `fetch --no-compression`
+    #[arg(long)]
+    pub no_compression: bool,
+
+    /// As for `fetch --max-retries`
+    #[arg(long, value_name = "N")]
+    pub max_retries: Option<u32>,
+}
+
+/// A column of `messages` which `backfill` fills in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum BackfillField {
+    /// When the message arrived, `internal_date`
+    Date,
+    /// Its Gmail thread, `thread_id`
+    Thread,
+    Subject,
+}
+
+impl BackfillField {
+    pub const ALL: [BackfillField; 3] = [
+        BackfillField::Date,
+        BackfillField::Thread,
+        BackfillField::Subject,
+    ];
+
+    pub fn column(&self) -> &'static str {
+        match self {
+            BackfillField::Date => "internal_date",
+            BackfillField::Thread => "thread_id",
+            BackfillField::Subject => "subject",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackfillField::Date => "date",
+            BackfillField::Thread => "thread",
+            BackfillField::Subject => "subject",
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct DbArgs {
+    #[command(subcommand)]
+    pub command: DbCommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DbCommand {
+    /// Replace stats.db with one of its backups, backing it up first
+    Restore(RestoreArgs),
+    /// Unmark messages marked seen but never recorded, left by a crash, so the next fetch
+    /// fetches them again
+    RepairOrphans(RepairOrphansArgs),
+    /// Rebuild the subject index `search` uses, adding mail fetched before it existed
+    Reindex,
+}
+
+#[derive(Debug, Args)]
+pub struct RepairOrphansArgs {
+    /// Only count them
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RestoreArgs {
+    /// The backup, such as stats.db.bak.20240101T120000.000Z
+    pub backup: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportMboxArgs {
+    /// The mbox file, read a page at a time however large it is
+    pub path: PathBuf,
+
+    #[command(flatten)]
+    pub import: ImportArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportMaildirArgs {
+    /// The Maildir, with every folder under it read
+    pub dir: PathBuf,
+
+    #[command(flatten)]
+    pub import: ImportArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportImapArgs {
+    /// The mailbox to read, with its name counted as a label
+    #[arg(long, default_value = "INBOX")]
+    pub mailbox: String,
+
+    #[command(flatten)]
+    pub import: ImportArgs,
+}
+
+/// How imported mail is counted, the same for every kind of file.
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    /// What "now" is for the run's timestamps, the system clock from the command line
+    #[arg(skip)]
+    pub clock: Clock,
+
+    /// Count the mail under this account, as for `fetch --profile`
+    #[arg(long, value_name = "NAME", value_parser = parse_profile)]
+    pub profile: Option<String>,
+
+    /// As for `fetch --unwrap-forwarded`
+    #[arg(long)]
+    pub unwrap_forwarded: bool,
+
+    /// As for `fetch --exclude-inline`
+    #[arg(long)]
+    pub exclude_inline: bool,
+
+    /// Also import mail labelled, or in a folder, as spam or trash
+    #[arg(long)]
+    pub include_spam_trash: bool,
+
+    /// As for `fetch --include-self`
+    #[arg(long)]
+    pub include_self: bool,
+
+    /// As for `fetch --summary-json`
+    #[arg(long)]
+    pub summary_json: bool,
+
+    /// As for `fetch --max-duration`
+    #[arg(long, value_name = "DURATION", value_parser = dates::parse_duration)]
+    pub max_duration: Option<Duration>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CleanupAction {
+    /// Take them out of the inbox
+    Archive,
+    /// Move them to the trash, where Gmail deletes them after 30 days
+    Trash,
+}
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    /// The database to compare from, opened read-only
+    pub a: PathBuf,
+
+    /// The database to compare to, opened read-only
+    pub b: PathBuf,
+
+    #[arg(long, value_enum, default_value_t = DiffFormat::Plain)]
+    pub format: DiffFormat,
+
+    /// Number of changed senders to show in plain output. CSV has every sender
+    #[arg(long, default_value_t = 50)]
+    pub top: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DiffFormat {
+    /// Aligned columns of the biggest changes, then totals
+    Plain,
+    /// sender,a,b,delta for every sender in either database
+    Csv,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// Apache Parquet, readable by DuckDB, pandas and polars
+    Parquet,
+    /// An Excel workbook with sheets of senders, domains, messages per month and fetch runs
+    Xlsx,
+}
+
+#[derive(Debug, Args)]
+pub struct ReportArgs {
+    #[command(subcommand)]
+    pub view: Option<ReportView>,
+
+    /// What "now" is for the report, the system clock from the command line
+    #[arg(skip)]
+    pub clock: Clock,
+
+    /// Timezone to bucket dates in, e.g. America/New_York (default from config, else UTC)
+    #[arg(long, global = true)]
+    pub timezone: Option<String>,
+
+    /// Plain ASCII tables without color, as when output isn't a terminal. Also set by the
+    /// NO_COLOR environment variable
+    #[arg(long, global = true)]
+    pub no_color: bool,
+
+    /// Number of senders to show
+    #[arg(long, default_value_t = 50)]
+    pub top: usize,
+
+    /// Only include senders matching this pattern, in any view: a glob such as
+    /// `*@*.substack.com`, or a regex between slashes such as `/^news(letter)?@/`. Repeat it to
+    /// include senders matching any of several
+    #[arg(long = "match", global = true, value_name = "PATTERN")]
+    pub matches: Vec<String>,
+
+    /// Leave out senders matching this pattern, written as for --match. Repeatable
+    #[arg(long, global = true, value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+
+    /// Leave out senders, or domains in `auth`, with fewer messages than this. `ignored` and
+    /// `stale` default to 10, `growth` counts recent messages and defaults to 5, and `auth`
+    /// defaults to 1
+    #[arg(long, global = true)]
+    pub min_count: Option<i64>,
+
+    /// Hide senders whose mail is at least 90% bulk
+    #[arg(long)]
+    pub no_bulk: bool,
+
+    /// Hide senders from my own domains (`own_domains` in the config)
+    #[arg(long)]
+    pub external_only: bool,
+
+    /// Hide senders who have never mailed one of my addresses (`my_addresses` in the config)
+    /// directly, rather than by Cc, Bcc or a mailing list
+    #[arg(long)]
+    pub direct_only: bool,
+
+    /// How to rank senders
+    #[arg(long, value_enum, default_value_t = SortBy::Count)]
+    pub by: SortBy,
+
+    /// Output format. HTML and Markdown add the domain breakdown, monthly trend and hour of day
+    /// histogram to the senders table
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Comma-separated sections to include in HTML or Markdown output, all of them by default
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub sections: Vec<Section>,
+
+    /// Render the senders, domains, monthly trend and latest run with a Tera template: `text`
+    /// or `html` for the built-in ones, else the path of your own
+    #[arg(long, value_name = "NAME_OR_PATH", conflicts_with_all = ["format", "sections"])]
+    pub template: Option<String>,
+
+    /// Also split the top senders' mail between the accounts fetched with `fetch --profile`
+    #[arg(long)]
+    pub by_account: bool,
+
+    /// Write the report to this file instead of printing it
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+
+    /// Open the written report in the default browser
+    #[arg(long, requires = "out")]
+    pub open: bool,
+
+    /// Also email the report, as text and HTML, to this address from your Gmail account. The
+    /// first time, Google asks for permission to send mail, which fetching never needs
+    #[arg(long, value_name = "ADDRESS")]
+    pub email_to: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ReportView {
+    /// Histograms of messages by hour of day and day of week
+    When(WhenArgs),
+    /// Message counts over time
+    Trend(TrendArgs),
+    /// Message counts and top senders per Gmail category
+    Categories(CategoriesArgs),
+    /// Senders whose mail mostly goes unread
+    Ignored(IgnoredArgs),
+    /// Top attachment senders and attachment types
+    Attachments(AttachmentsArgs),
+    /// How often I reply to each sender
+    Engagement(EngagementArgs),
+    /// How quickly I reply
+    Latency(LatencyArgs),
+    /// Senders who used to mail a lot but stopped
+    Stale(StaleArgs),
+    /// What changed in recent fetches
+    Delta(DeltaArgs),
+    /// Volume from freemail, email service providers and direct senders
+    Classes,
+    /// Mail from my own domains versus everyone else
+    Internal(InternalArgs),
+    /// Senders first seen within a period
+    New(NewArgs),
+    /// Who lands in my spam folder, and senders seen in both spam and the rest of my mail
+    Spam(SpamArgs),
+    /// The shape of the inbox: how concentrated mail is among senders
+    Distribution,
+    /// The most common words in subjects, overall or from one sender
+    Subjects(SubjectsArgs),
+    /// Storage used per label and category, and the largest messages
+    Storage(StorageArgs),
+    /// The longest threads
+    Threads(ThreadsArgs),
+    /// How much mail is machine-generated, by kind and by sender
+    Automated(AutomatedArgs),
+    /// SPF, DKIM and DMARC failure rates per sender domain
+    Auth(AuthArgs),
+    /// Sender domains that look like a trusted domain, possible phishing
+    Lookalikes(LookalikesArgs),
+    /// Senders whose volume grew the most, recent period versus the one before
+    Growth(GrowthArgs),
+    /// Who sends the most meeting invites
+    Calendar(CalendarArgs),
+    /// Addresses which probably belong to the same sender, with suggested aliases
+    Duplicates,
+    /// Addresses that bounce my mail or reply that they're out of office
+    Bounces(BouncesArgs),
+    /// Draw the top senders or the monthly trend as a PNG or SVG chart
+    Chart(ChartArgs),
+    /// What changed over the last week or month, in one short report
+    Digest(DigestArgs),
+    /// Which of my addresses each sender mails, flagging plus-tags and aliases given to someone
+    /// else
+    Aliases(AliasesArgs),
+    /// Messages fetches skipped because they couldn't be fetched or parsed
+    Errors(ErrorsArgs),
+    /// Gmail filters to skip the inbox for high-volume automated senders
+    Filters(FiltersArgs),
+    /// Mail unwrapped by `fetch --unwrap-forwarded`, by original sender or by forwarder
+    Forwarded(ForwardedArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct WhenArgs {
+    /// Only count mail from this sender
+    #[arg(long)]
+    pub sender: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct TrendArgs {
+    #[arg(long, value_enum, default_value_t = Granularity::Month)]
+    pub granularity: Granularity,
+
+    /// Only count mail from this sender
+    #[arg(long, conflicts_with = "domain")]
+    pub sender: Option<String>,
+
+    /// Only count mail from senders at this domain
+    #[arg(long)]
+    pub domain: Option<String>,
+
+    /// First day to include (YYYY-MM-DD), defaults to the oldest stored message
+    #[arg(long)]
+    pub since: Option<String>,
+
+    /// Day to stop before (YYYY-MM-DD), defaults to after the newest stored message
+    #[arg(long)]
+    pub before: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct CategoriesArgs {
+    /// Number of top senders to list per category
+    #[arg(long, default_value_t = 5)]
+    pub senders: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct IgnoredArgs {
+    /// Number of senders to show
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct AttachmentsArgs {
+    /// Number of senders to show
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct EngagementArgs {
+    /// Number of senders to show in each list
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+
+    /// Skip senders with fewer threads than this
+    #[arg(long, default_value_t = 3)]
+    pub min_threads: i64,
+}
+
+#[derive(Debug, Args)]
+pub struct LatencyArgs {
+    /// Number of correspondents to break out
+    #[arg(long, default_value_t = 10)]
+    pub top: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct StaleArgs {
+    /// How long a sender must have been quiet, e.g. 30d, 6m or 1y
+    #[arg(long, default_value = "6m")]
+    pub inactive_for: Period,
+
+    /// Number of senders to show
+    #[arg(long, default_value_t = 50)]
+    pub top: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct DeltaArgs {
+    /// Show changes made by this run and every run after it, a run id or `last`
+    #[arg(long, default_value = "last")]
+    pub since_run: RunRef,
+
+    /// Number of senders to show in each list
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+}
+
+/// A fetch run, either by id or the most recent one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunRef {
+    Id(i64),
+    Last,
+}
+
+impl FromStr for RunRef {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<RunRef, String> {
+        if s == "last" {
+            return Ok(RunRef::Last);
+        }
+        s.parse()
+            .map(RunRef::Id)
+            .map_err(|_| format!("expected a run id or `last`, got {:?}", s))
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct InternalArgs {
+    /// Number of external senders to show
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct NewArgs {
+    /// First day of the period (YYYY-MM-DD)
+    #[arg(long)]
+    pub since: String,
+
+    /// Day to stop before (YYYY-MM-DD), defaults to now
+    #[arg(long)]
+    pub before: Option<String>,
+
+    /// How to order the senders
+    #[arg(long, value_enum, default_value_t = NewOrder::Count)]
+    pub order: NewOrder,
+}
+
+#[derive(Debug, Args)]
+pub struct SpamArgs {
+    /// Number of senders to show in each list
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct SubjectsArgs {
+    /// Only count mail from this sender
+    #[arg(long)]
+    pub sender: Option<String>,
+
+    /// Only count subjects matching these words, written as for `search`
+    #[arg(long, value_name = "TERMS")]
+    pub search: Option<String>,
+
+    /// Number of words to show
+    #[arg(long, default_value_t = 30)]
+    pub top: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct StorageArgs {
+    /// Number of messages to show
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct ThreadsArgs {
+    /// Number of threads to show
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+
+    /// Which threads to include
+    #[arg(long, value_enum, default_value_t = ThreadKind::All)]
+    pub kind: ThreadKind,
+}
+
+#[derive(Debug, Args)]
+pub struct AutomatedArgs {
+    /// Number of senders to show
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct AuthArgs {
+    /// Number of domains to show
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct LookalikesArgs {
+    /// How many of the busiest domains to treat as trusted, along with my own domains
+    #[arg(long, default_value_t = 50)]
+    pub trusted: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct GrowthArgs {
+    /// Length of each period compared, e.g. 30d, 2w or 3m
+    #[arg(long, default_value = "30d", value_parser = parse_window)]
+    pub window: Period,
+
+    /// Number of senders to show in each list
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct CalendarArgs {
+    /// Number of organizers to show
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct BouncesArgs {
+    /// Number of addresses to show in each list
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct DigestArgs {
+    /// The last complete week (from Monday) or calendar month in the report's timezone,
+    /// compared with the one before
+    #[arg(long, value_enum, default_value_t = DigestPeriod::Week)]
+    pub period: DigestPeriod,
+
+    #[arg(long, value_enum, default_value_t = DigestFormat::Text)]
+    pub format: DigestFormat,
+
+    /// Also email the digest to this address, as `report --email-to` does the senders report
+    #[arg(long, value_name = "ADDRESS")]
+    pub email_to: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct AliasesArgs {
+    /// Number of sender and address pairs to show
+    #[arg(long, default_value_t = 50)]
+    pub top: usize,
+
+    /// Only show senders mailing an address of mine given to someone else
+    #[arg(long)]
+    pub unexpected: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ErrorsArgs {
+    /// Number of recent errors to list
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct ForwardedArgs {
+    /// Which sender of the forwarded mail to count it against
+    #[arg(long, value_enum, default_value_t = SenderSide::Original)]
+    pub show: SenderSide,
+
+    /// Number of senders to show
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+}
+
+/// The two senders a forwarded message has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SenderSide {
+    /// Who wrote it, from the forwarding headers
+    Original,
+    /// The forwarder it came through, from its From header
+    Envelope,
+}
+
+#[derive(Debug, Args)]
+pub struct FiltersArgs {
+    /// Only suggest filters for senders with at least this many mails
+    #[arg(long, default_value_t = 50)]
+    pub min_count: i64,
+
+    /// Include senders whose mail is mostly written by a person
+    #[arg(long)]
+    pub include_personal: bool,
+
+    /// Include senders I've replied to
+    #[arg(long)]
+    pub include_replied: bool,
+
+    /// Label the filters apply
+    #[arg(long, default_value = "Newsletters")]
+    pub label: String,
+
+    /// Write the filters as XML to import in Gmail's settings instead of listing them
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ChartArgs {
+    #[arg(long, value_enum, default_value_t = ChartKind::TopSenders)]
+    pub kind: ChartKind,
+
+    /// File to write, SVG if it ends in .svg and PNG otherwise
+    #[arg(long)]
+    pub out: PathBuf,
+
+    /// Number of senders to chart
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+
+    /// Image width in pixels
+    #[arg(long, default_value_t = 1024)]
+    pub width: u32,
+
+    /// Image height in pixels
+    #[arg(long, default_value_t = 768)]
+    pub height: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ChartKind {
+    /// Horizontal bars of the busiest senders
+    TopSenders,
+    /// Messages per month as a line
+    Trend,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ThreadKind {
+    All,
+    /// Threads with more than one sender
+    Conversations,
+    /// Threads where every message is from the same sender, such as automated reminders
+    SingleSender,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum NewOrder {
+    /// Most messages first
+    Count,
+    /// Earliest first seen first
+    FirstSeen,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DigestPeriod {
+    Week,
+    Month,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DigestFormat {
+    Text,
+    /// GitHub-flavored Markdown tables
+    Markdown,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Html,
+    /// GitHub-flavored Markdown tables
+    Markdown,
+}
+
+/// A section of the HTML or Markdown report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Section {
+    /// The top senders table
+    Senders,
+    /// The busiest sender domains
+    Domains,
+    /// Messages per month
+    Trend,
+    /// Messages by hour of day
+    Hours,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SortBy {
+    /// Most messages first
+    Count,
+    /// Most bytes first
+    Bytes,
+    /// Most recently seen first
+    Recent,
+}
+
+/// The error for a command line asking for `what`, which this build left out along with
+/// `feature`.
+pub fn needs_feature(what: &str, feature: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "`{}` needs the `{}` feature; rebuild with `cargo build --features {}`",
+        what,
+        feature,
+        feature
+    )
+}
+
+// Profile names end up in the token cache's file name, so they're kept to a safe alphabet
+fn parse_profile(name: &str) -> Result<String, String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err("use only letters, digits, '-' and '_'".to_string());
+    }
+    Ok(name.to_string())
+}
+
+// Two empty windows would compare nothing, and print empty lists as if no one had grown
+fn parse_window(s: &str) -> anyhow::Result<Period> {
+    let window = s.parse::<Period>()?;
+    if window.is_zero() {
+        anyhow::bail!("the window can't be empty");
+    }
+    Ok(window)
+}
@@ -0,0 +1,21 @@
+use chrono::{DateTime, Utc};
+
+/// Where the current time comes from. Everything which stamps or measures against "now" (run
+/// times, `fetched_at`, report headers, `stale` and `growth` windows) asks a clock rather than
+/// the system, so a fixed one gives repeatable output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Clock {
+    #[default]
+    System,
+    /// Always this instant.
+    Fixed(DateTime<Utc>),
+}
+
+impl Clock {
+    pub fn now(&self) -> DateTime<Utc> {
+        match self {
+            Clock::System => Utc::now(),
+            Clock::Fixed(now) => *now,
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::dates::Period;
+use crate::domains::DomainClassConfig;
+use crate::own::Identity;
+
+/// Settings read from the optional TOML config file (`gmail_stats.toml` by default).
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Addresses which forward mail from another account into this mailbox. Only messages whose
+    /// From matches one of these are unwrapped by `--unwrap-forwarded`.
+    pub forwarders: Vec<String>,
+    /// IANA timezone name used to bucket dates in reports, UTC when unset.
+    pub timezone: Option<String>,
+    /// My own addresses, mail with one of these in To is addressed to me directly.
+    pub my_addresses: Vec<String>,
+    /// My own domains, mail from these or their subdomains is internal.
+    pub own_domains: Vec<String>,
+    /// Domains to add to the built-in freemail and infrastructure lists.
+    pub domain_classes: DomainClassConfig,
+    /// Extra addresses of the same person, mapped to the address their mail is counted under in
+    /// reports. Read from the `[aliases]` section.
+    pub aliases: BTreeMap<String, String>,
+    /// Senders which aren't counted at all: exact addresses, `@domain` or glob patterns.
+    pub ignore: Vec<String>,
+    /// The server `import-imap` reads, from the `[imap]` section.
+    pub imap: Option<ImapConfig>,
+    /// How many backups of the database to keep, `backup::DEFAULT_KEEP` when unset.
+    pub backups: Option<usize>,
+    /// How long per-message details are kept, from the `[retention]` section.
+    pub retention: RetentionConfig,
+    /// More of what's mine, from the `[identity]` section.
+    #[serde(rename = "identity")]
+    pub identity_config: IdentityConfig,
+    /// Everything of mine, `my_addresses` and `own_domains` included, built by `load`.
+    #[serde(skip)]
+    pub identity: Identity,
+}
+
+/// My addresses and domains, on top of `my_addresses` and `own_domains`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct IdentityConfig {
+    pub addresses: Vec<String>,
+    /// Domains whose senders are internal, like `own_domains`.
+    pub domains: Vec<String>,
+    /// Domains where any address is mine, such as one whose mail all forwards to me.
+    pub catch_all_domains: Vec<String>,
+}
+
+/// How long to keep what's stored about each message, forever when unset. Sender counts are
+/// kept whatever these say.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Delete message rows older than this, such as `"180d"`.
+    #[serde(deserialize_with = "period")]
+    pub messages: Option<Period>,
+    /// Forget the subjects of messages older than this.
+    #[serde(deserialize_with = "period")]
+    pub subjects: Option<Period>,
+}
+
+// A period written as a string, `30d`, `6m` and so on
+fn period<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Period>, D::Error> {
+    let period = String::deserialize(deserializer)?;
+    period.parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+/// Where `import-imap` signs in, and as whom.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ImapConfig {
+    pub host: String,
+    /// 993 with TLS and 143 without when unset.
+    pub port: Option<u16>,
+    /// Connect over TLS, which is the default; only turn it off for a server on this machine.
+    pub tls: Option<bool>,
+    pub username: String,
+    /// Sign in with this password.
+    pub password: Option<String>,
+    /// Or with this OAuth 2.0 access token, using OAUTHBEARER.
+    pub oauth_token: Option<String>,
+}
+
+impl ImapConfig {
+    pub fn tls(&self) -> bool {
+        self.tls.unwrap_or(true)
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(if self.tls() { 993 } else { 143 })
+    }
+}
+
+impl Config {
+    // A missing config file is fine, everything has a default
+    pub fn load(path: &Path) -> anyhow::Result<Config> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let contents = std::fs::read_to_string(path)?;
+        let mut config: Config = toml::from_str(&contents)?;
+        config.identity = Identity::new(&config);
+        Ok(config)
+    }
+}
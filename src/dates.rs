@@ -0,0 +1,285 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Duration, LocalResult, Months, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Granularity {
+    Day,
+    /// Weeks start on Monday
+    Week,
+    Month,
+}
+
+impl Granularity {
+    /// The first day of the bucket containing `date`.
+    pub fn bucket_start(&self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Granularity::Day => date,
+            Granularity::Week => {
+                date - Duration::days(date.weekday().num_days_from_monday().into())
+            }
+            Granularity::Month => date.with_day(1).unwrap(),
+        }
+    }
+
+    /// The first day of the bucket after the one starting at `start`.
+    pub fn next(&self, start: NaiveDate) -> NaiveDate {
+        match self {
+            Granularity::Day => start + Duration::days(1),
+            Granularity::Week => start + Duration::days(7),
+            Granularity::Month => start
+                .checked_add_months(Months::new(1))
+                .expect("date out of range"),
+        }
+    }
+
+    pub fn label(&self, start: NaiveDate) -> String {
+        match self {
+            Granularity::Day | Granularity::Week => start.format("%Y-%m-%d").to_string(),
+            Granularity::Month => start.format("%Y-%m").to_string(),
+        }
+    }
+}
+
+// Epoch milliseconds to the local calendar date in `tz`
+pub fn local_date(millis: i64, tz: Tz) -> Option<NaiveDate> {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .map(|utc| utc.with_timezone(&tz).date_naive())
+}
+
+// Epoch milliseconds of local midnight at the start of `date`. Midnight can be skipped by a DST
+// change in a few zones, in which case the earliest valid time that day is used.
+pub fn start_of_day(date: NaiveDate, tz: Tz) -> i64 {
+    let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+    let local = match tz.from_local_datetime(&midnight) {
+        LocalResult::Single(local) | LocalResult::Ambiguous(local, _) => local,
+        LocalResult::None => tz
+            .from_local_datetime(&(midnight + Duration::hours(1)))
+            .earliest()
+            .expect("no valid time an hour after midnight"),
+    };
+    local.with_timezone(&Utc).timestamp_millis()
+}
+
+pub fn parse_date(s: &str) -> anyhow::Result<NaiveDate> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| anyhow::anyhow!("invalid date {:?}, expected YYYY-MM-DD", s))
+}
+
+/// The Gmail API's daily quotas reset at midnight Pacific time.
+pub const QUOTA_TZ: Tz = chrono_tz::America::Los_Angeles;
+
+/// When the Gmail API's daily quotas next reset after `now`.
+pub fn quota_reset(now: DateTime<Utc>) -> DateTime<Utc> {
+    let tomorrow = now.with_timezone(&QUOTA_TZ).date_naive() + Duration::days(1);
+    Utc.timestamp_millis_opt(start_of_day(tomorrow, QUOTA_TZ))
+        .single()
+        .expect("midnight is a valid time")
+}
+
+/// A length of time such as `90s`, `30m`, `2h` or `1h30m`: hours, minutes and seconds, largest
+/// first.
+pub fn parse_duration(s: &str) -> Result<std::time::Duration, String> {
+    let invalid = || format!("invalid duration {:?}, expected e.g. 90s, 30m or 1h30m", s);
+    let mut seconds = 0u64;
+    let mut digits = String::new();
+    let mut units = Vec::new();
+    for c in s.trim().chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let n: u64 = digits.parse().map_err(|_| invalid())?;
+        digits.clear();
+        let unit = match c.to_ascii_lowercase() {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        // Each unit once, largest first
+        if units.last().is_some_and(|last| *last <= unit) {
+            return Err(invalid());
+        }
+        units.push(unit);
+        seconds = n
+            .checked_mul(unit)
+            .and_then(|part| seconds.checked_add(part))
+            .ok_or_else(|| format!("duration {:?} is too long", s))?;
+    }
+    if !digits.is_empty() || units.is_empty() || seconds == 0 {
+        return Err(invalid());
+    }
+    Ok(std::time::Duration::from_secs(seconds))
+}
+
+/// A calendar period such as `30d`, `2w`, `6m` or `1y`, used for "older than" style arguments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Period {
+    Days(u32),
+    Months(u32),
+}
+
+impl Period {
+    /// The longest period accepted, far more than any mailbox goes back but short enough to be
+    /// taken from any date chrono can hold.
+    pub const MAX_YEARS: u32 = 10_000;
+
+    pub fn is_zero(&self) -> bool {
+        matches!(self, Period::Days(0) | Period::Months(0))
+    }
+
+    /// The instant `self` before `now`. Months are calendar months, clamped to the end of
+    /// shorter months. Fails if that's earlier than chrono can hold.
+    pub fn before(&self, now: DateTime<Utc>) -> anyhow::Result<DateTime<Utc>> {
+        match self {
+            Period::Days(days) => now.checked_sub_signed(Duration::days((*days).into())),
+            Period::Months(months) => now.checked_sub_months(Months::new(*months)),
+        }
+        .ok_or_else(|| anyhow::anyhow!("{:?} before {} is out of range", self, now))
+    }
+}
+
+impl FromStr for Period {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Period> {
+        let invalid = || anyhow::anyhow!("invalid period {:?}, expected e.g. 30d, 2w, 6m or 1y", s);
+        let too_long = || {
+            anyhow::anyhow!(
+                "period {:?} is too long, the most is {} years",
+                s,
+                Period::MAX_YEARS
+            )
+        };
+        let s = s.trim();
+        let unit = s.chars().last().ok_or_else(invalid)?;
+        let n: u32 = s[..s.len() - unit.len_utf8()]
+            .parse()
+            .map_err(|_| invalid())?;
+        let period = match unit.to_ascii_lowercase() {
+            'd' => Period::Days(n),
+            'w' => Period::Days(n.checked_mul(7).ok_or_else(too_long)?),
+            'm' => Period::Months(n),
+            'y' => Period::Months(n.checked_mul(12).ok_or_else(too_long)?),
+            _ => return Err(invalid()),
+        };
+        // A day over so leap days don't make the limit depend on the unit
+        let longest = match period {
+            Period::Days(days) => days / 366,
+            Period::Months(months) => months / 12,
+        };
+        if longest > Period::MAX_YEARS {
+            return Err(too_long());
+        }
+        Ok(period)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(y: i32, m: u32, d: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn durations_parse_largest_unit_first() {
+        let cases = [
+            ("90s", 90),
+            ("30m", 1_800),
+            ("2h", 7_200),
+            ("1h30m", 5_400),
+            ("1H30M15S", 5_415),
+            (" 45m10s ", 2_710),
+        ];
+
+        for (text, seconds) in cases {
+            assert_eq!(
+                parse_duration(text).unwrap(),
+                std::time::Duration::from_secs(seconds),
+                "{:?}",
+                text
+            );
+        }
+        for text in ["", "0s", "30", "h", "30m1h", "1h1h", "1d", "1.5h", "-5m"] {
+            assert!(parse_duration(text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn durations_too_long_to_count_are_rejected() {
+        let most = format!("{}s", u64::MAX);
+        assert_eq!(
+            parse_duration(&most).unwrap(),
+            std::time::Duration::from_secs(u64::MAX)
+        );
+        for text in [
+            format!("{}h", u64::MAX),
+            format!("{}m16s", u64::MAX / 60),
+            "99999999999999999999s".to_string(),
+        ] {
+            assert!(parse_duration(&text).is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn each_unit_of_a_period_parses() {
+        let cases = [
+            ("30d", Period::Days(30)),
+            ("2w", Period::Days(14)),
+            ("6m", Period::Months(6)),
+            ("1y", Period::Months(12)),
+            (" 3Y ", Period::Months(36)),
+            ("0d", Period::Days(0)),
+        ];
+
+        for (text, period) in cases {
+            assert_eq!(text.parse::<Period>().unwrap(), period, "{:?}", text);
+        }
+        for text in ["", "d", "30", "30x", "-1d", "1.5y", "1d2h"] {
+            assert!(text.parse::<Period>().is_err(), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn periods_too_long_to_take_from_a_date_are_rejected() {
+        for text in [
+            "999999999y",
+            "999999999w",
+            "99999999d",
+            "4294967295m",
+            "10001y",
+        ] {
+            let err = text.parse::<Period>().unwrap_err().to_string();
+            assert!(err.contains("too long"), "{:?}: {}", text, err);
+        }
+        assert!("10000y".parse::<Period>().is_ok());
+        assert!("99999999999d".parse::<Period>().is_err());
+    }
+
+    #[test]
+    fn a_period_before_a_fixed_instant() {
+        let now = at(2024, 3, 31);
+
+        assert_eq!(Period::Days(30).before(now).unwrap(), at(2024, 3, 1));
+        assert_eq!(Period::Days(0).before(now).unwrap(), now);
+        // A month back from the 31st clamps to the end of February, a leap year's here
+        assert_eq!(Period::Months(1).before(now).unwrap(), at(2024, 2, 29));
+        assert_eq!(Period::Months(13).before(now).unwrap(), at(2023, 2, 28));
+        assert_eq!(Period::Months(12).before(now).unwrap(), at(2023, 3, 31));
+        let longest = format!("{}y", Period::MAX_YEARS).parse::<Period>().unwrap();
+        assert_eq!(longest.before(now).unwrap(), at(-7976, 3, 31));
+    }
+
+    #[test]
+    fn a_period_reaching_past_the_earliest_date_fails() {
+        assert!(Period::Days(u32::MAX).before(at(2024, 1, 1)).is_err());
+        assert!(Period::Months(u32::MAX).before(at(2024, 1, 1)).is_err());
+        assert!(Period::Days(1).before(DateTime::<Utc>::MIN_UTC).is_err());
+    }
+}
@@ -0,0 +1,778 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+use sqlx::{Pool, Row, Sqlite, SqliteConnection, SqliteExecutor};
+
+use crate::analyze::SenderCounts;
+use crate::domains::{self, Classifier};
+use crate::error::GmailStatsError;
+use crate::ignore::IgnoreList;
+use crate::own::Identity;
+use crate::parse::MessageInfo;
+use crate::source::Cursor;
+
+// Create any missing tables. Databases created by hand before migrations existed are fine,
+// the initial migration only creates tables which don't exist yet.
+pub async fn migrate(pool: &Pool<Sqlite>) -> anyhow::Result<()> {
+    sqlx::migrate!("./migrations").run(pool).await?;
+    crate::forget::digest_old_tombstones(pool).await?;
+    Ok(())
+}
+
+/// The newest migration this build knows about.
+pub fn latest_schema_version() -> i64 {
+    sqlx::migrate!("./migrations")
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .unwrap_or(0)
+}
+
+/// The newest migration applied to a database, `None` if it has never been migrated.
+pub async fn schema_version(pool: &Pool<Sqlite>) -> anyhow::Result<Option<i64>> {
+    let (migrated,): (bool,) = sqlx::query_as(
+        "SELECT count(*) > 0 FROM sqlite_master
+        WHERE type = 'table' AND name = '_sqlx_migrations'",
+    )
+    .fetch_one(pool)
+    .await?;
+    if !migrated {
+        return Ok(None);
+    }
+    let (version,): (Option<i64>,) =
+        sqlx::query_as("SELECT max(version) FROM _sqlx_migrations WHERE success")
+            .fetch_one(pool)
+            .await?;
+    Ok(version)
+}
+
+/// The account of mail fetched without `--profile`, and of everything fetched before profiles.
+pub const DEFAULT_ACCOUNT: &str = "default";
+
+// Record the start of a fetch of `account`, returning the new run's id
+pub async fn start_run(
+    account: &str,
+    now: DateTime<Utc>,
+    executor: impl SqliteExecutor<'_>,
+) -> Result<i64, GmailStatsError> {
+    let id = sqlx::query("INSERT INTO runs (started_at, status, account) VALUES (?, 'running', ?)")
+        .bind(now.timestamp_millis())
+        .bind(account)
+        .execute(executor)
+        .await?
+        .last_insert_rowid();
+    Ok(id)
+}
+
+// Record the end of a fetch, as interrupted if it didn't get through the listings, for `why` if
+// it wasn't Ctrl-C, such as `deadline`
+pub async fn finish_run(
+    run_id: i64,
+    interrupted: bool,
+    why: Option<&str>,
+    now: DateTime<Utc>,
+    executor: impl SqliteExecutor<'_>,
+) -> Result<(), GmailStatsError> {
+    sqlx::query(
+        "UPDATE runs SET finished_at = ?, status = ?,
+            messages = (SELECT count(*) FROM messages WHERE run_id = runs.id),
+            skipped = (SELECT count(*) FROM errors WHERE run_id = runs.id)
+        WHERE id = ?",
+    )
+    .bind(now.timestamp_millis())
+    .bind(match (interrupted, why) {
+        (true, Some(why)) => format!("interrupted ({})", why),
+        (true, None) => "interrupted".to_string(),
+        (false, _) => "complete".to_string(),
+    })
+    .bind(run_id)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+// Note where an interrupted run stopped in `listing`, for the next fetch to carry on from
+pub async fn record_cursor(
+    run_id: i64,
+    listing: &str,
+    cursor: &Cursor,
+    executor: impl SqliteExecutor<'_>,
+) -> Result<(), GmailStatsError> {
+    sqlx::query(
+        "UPDATE runs SET cursor_listing = ?, cursor_label = ?, cursor_page_token = ?
+        WHERE id = ?",
+    )
+    .bind(listing)
+    .bind(cursor.label.as_deref())
+    .bind(cursor.page_token.as_deref())
+    .bind(run_id)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Where to carry on listing `account` through `listing`: where the last run stopped, if it was
+/// interrupted going through the same listing.
+pub async fn resume_cursor(
+    account: &str,
+    listing: &str,
+    executor: impl SqliteExecutor<'_>,
+) -> Result<Option<Cursor>, GmailStatsError> {
+    let cursor: Option<(Option<String>, Option<String>)> = sqlx::query_as(
+        "SELECT cursor_label, cursor_page_token FROM (
+            SELECT * FROM runs WHERE account = ? AND status != 'running' ORDER BY id DESC LIMIT 1
+        )
+        WHERE status LIKE 'interrupted%' AND cursor_listing = ?",
+    )
+    .bind(account)
+    .bind(listing)
+    .fetch_optional(executor)
+    .await?;
+    Ok(cursor.map(|(label, page_token)| Cursor { label, page_token }))
+}
+
+pub async fn seen_mail(
+    message_id: &str,
+    account: &str,
+    executor: impl SqliteExecutor<'_>,
+) -> Result<bool, GmailStatsError> {
+    let mut res =
+        sqlx::query("SELECT count(1) AS ct FROM seen_mails WHERE account = ? AND mail_id = ?")
+            .bind(account)
+            .bind(message_id)
+            .fetch(executor);
+    while let Some(row) = res.try_next().await? {
+        let count: u32 = row.try_get("ct")?;
+        if count > 0 {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// The size of the database in bytes, going by its pages.
+pub async fn size(executor: impl SqliteExecutor<'_>) -> Result<i64, GmailStatsError> {
+    let (size,): (i64,) = sqlx::query_as(
+        "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+    )
+    .fetch_one(executor)
+    .await?;
+    Ok(size)
+}
+
+/// How many messages of `account` have been seen.
+pub async fn seen_count(
+    account: &str,
+    executor: impl SqliteExecutor<'_>,
+) -> Result<u64, GmailStatsError> {
+    let (count,): (i64,) = sqlx::query_as("SELECT count(*) FROM seen_mails WHERE account = ?")
+        .bind(account)
+        .fetch_one(executor)
+        .await?;
+    Ok(count as u64)
+}
+
+/// Whether `account` already has a message with `info`'s Message-ID under another id, as when
+/// the same mail is both fetched and imported from an mbox file.
+pub async fn recorded_elsewhere(
+    info: &MessageInfo,
+    account: &str,
+    executor: impl SqliteExecutor<'_>,
+) -> Result<bool, GmailStatsError> {
+    let Some(message_id) = &info.message_id else {
+        return Ok(false);
+    };
+    let (found,): (bool,) = sqlx::query_as(
+        "SELECT EXISTS (SELECT 1 FROM messages
+            WHERE message_id = ? AND account = ? AND mail_id != ?)",
+    )
+    .bind(message_id)
+    .bind(account)
+    .bind(&info.id)
+    .fetch_one(executor)
+    .await?;
+    Ok(found)
+}
+
+/// Bind parameters per statement, SQLite's default limit before 3.32.
+const MAX_BINDS: usize = 999;
+
+/// Mark `message_ids` of `account` seen, in as few statements as the bind limit allows. Ids
+/// already marked are left as they are.
+pub async fn mark_seen(
+    message_ids: &[String],
+    account: &str,
+    conn: &mut SqliteConnection,
+) -> Result<(), GmailStatsError> {
+    for chunk in message_ids.chunks(MAX_BINDS / 2) {
+        let sql = format!(
+            "INSERT OR IGNORE INTO seen_mails (mail_id, account) VALUES {}",
+            vec!["(?, ?)"; chunk.len()].join(", ")
+        );
+        let mut query = sqlx::query(&sql);
+        for id in chunk {
+            query = query.bind(id).bind(account);
+        }
+        query.execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+/// Note why a seen message of `account` has no row, one of the `seen_mails.unrecorded` reasons,
+/// so it isn't taken for an orphan.
+pub async fn mark_unrecorded(
+    message_id: &str,
+    reason: &str,
+    account: &str,
+    executor: impl SqliteExecutor<'_>,
+) -> Result<(), GmailStatsError> {
+    sqlx::query("UPDATE seen_mails SET unrecorded = ? WHERE account = ? AND mail_id = ?")
+        .bind(reason)
+        .bind(account)
+        .bind(message_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+// Note a message passed over because it couldn't be fetched or parsed, replacing any earlier
+// failure of the same message. It isn't marked seen, so a later fetch tries it again.
+pub async fn record_error(
+    message_id: Option<&str>,
+    run_id: Option<i64>,
+    class: &str,
+    message: &str,
+    now: DateTime<Utc>,
+    conn: &mut SqliteConnection,
+) -> Result<(), GmailStatsError> {
+    if let Some(message_id) = message_id {
+        clear_errors(message_id, &mut *conn).await?;
+    }
+    sqlx::query(
+        "INSERT INTO errors (mail_id, run_id, class, message, recorded_at) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(message_id)
+    .bind(run_id)
+    .bind(class)
+    .bind(message)
+    .bind(now.timestamp_millis())
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Note a change `cleanup` made to a message in the mailbox, or with `error` why it couldn't.
+pub async fn record_action(
+    action: &str,
+    message_id: &str,
+    sender: &str,
+    error: Option<&str>,
+    now: DateTime<Utc>,
+    executor: impl SqliteExecutor<'_>,
+) -> Result<(), GmailStatsError> {
+    sqlx::query(
+        "INSERT INTO actions (performed_at, action, mail_id, sender, error) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(now.timestamp_millis())
+    .bind(action)
+    .bind(message_id)
+    .bind(sender)
+    .bind(error)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+pub async fn clear_errors(
+    message_id: &str,
+    executor: impl SqliteExecutor<'_>,
+) -> Result<(), GmailStatsError> {
+    sqlx::query("DELETE FROM errors WHERE mail_id = ?")
+        .bind(message_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// Senders with more counted message rows than their `mails_sent`, with both numbers. The
+/// message row and the count are written in one transaction, so any here mean one was committed
+/// without the other. Mail fetched before message rows were kept only adds to `mails_sent`, so
+/// the check is one-sided; spam is counted elsewhere and ignored senders' sent mail isn't
+/// counted at all.
+pub async fn undercounted_senders(
+    pool: &Pool<Sqlite>,
+) -> Result<Vec<(String, i64, i64)>, GmailStatsError> {
+    let rows = sqlx::query_as(
+        "SELECT senders.sender, senders.mails_sent, count(*) AS recorded
+        FROM messages JOIN senders ON senders.sender = messages.sender
+        WHERE NOT messages.is_spam AND NOT senders.is_ignored
+        GROUP BY senders.sender
+        HAVING recorded > senders.mails_sent
+        ORDER BY senders.sender",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Ids of the messages which failed last time they were tried, for `fetch --retry-errors`.
+pub async fn errored_ids(
+    account: &str,
+    pool: &Pool<Sqlite>,
+) -> Result<Vec<String>, GmailStatsError> {
+    let rows: Vec<(String,)> = sqlx::query_as(
+        "SELECT DISTINCT mail_id FROM errors
+        WHERE mail_id IS NOT NULL AND run_id IN (SELECT id FROM runs WHERE account = ?)
+        ORDER BY mail_id",
+    )
+    .bind(account)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|(id,)| id).collect())
+}
+
+pub async fn record_message(
+    info: &MessageInfo,
+    account: &str,
+    run_id: Option<i64>,
+    fetched_at: DateTime<Utc>,
+    conn: &mut SqliteConnection,
+) -> Result<(), GmailStatsError> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO messages
+            (mail_id, sender, envelope_sender, original_sender, is_bulk, bulk_signal,
+            internal_date, size_estimate, is_unread, fetched_at,
+            attachment_count, attachment_bytes, thread_id, run_id, is_spam, subject, auto_kind,
+            spf, dkim, dmarc, is_calendar, calendar_method, calendar_organizer, sender_name,
+            notice, notice_address, account, message_id, delivered_to)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&info.id)
+    .bind(&info.sender.sender)
+    .bind(&info.sender.envelope_sender)
+    .bind(&info.sender.original_sender)
+    .bind(info.is_bulk())
+    .bind(info.bulk.map(|signal| signal.as_str()))
+    .bind(info.date)
+    .bind(info.size_estimate)
+    .bind(info.is_unread())
+    .bind(fetched_at.timestamp_millis())
+    .bind(info.attachments.len() as i64)
+    .bind(info.attachment_bytes())
+    .bind(&info.thread_id)
+    .bind(run_id)
+    .bind(info.is_spam())
+    .bind(&info.subject)
+    .bind(info.auto.map(|kind| kind.as_str()))
+    .bind(info.auth.as_ref().and_then(|auth| auth.spf.as_deref()))
+    .bind(info.auth.as_ref().and_then(|auth| auth.dkim.as_deref()))
+    .bind(info.auth.as_ref().and_then(|auth| auth.dmarc.as_deref()))
+    .bind(info.calendar.is_some())
+    .bind(info.calendar.as_ref().and_then(|c| c.method.as_deref()))
+    .bind(info.calendar.as_ref().and_then(|c| c.organizer.as_deref()))
+    .bind(&info.sender.name)
+    .bind(info.notice.as_ref().map(|notice| notice.kind.as_str()))
+    .bind(
+        info.notice
+            .as_ref()
+            .and_then(|notice| notice.address.as_deref()),
+    )
+    .bind(account)
+    .bind(&info.message_id)
+    .bind(&info.delivered_to)
+    .execute(&mut *conn)
+    .await?;
+
+    // Another account's message with the same id is left alone
+    sqlx::query("DELETE FROM message_labels WHERE account = ? AND mail_id = ?")
+        .bind(account)
+        .bind(&info.id)
+        .execute(&mut *conn)
+        .await?;
+    for label in &info.labels {
+        sqlx::query("INSERT INTO message_labels (account, mail_id, label) VALUES (?, ?, ?)")
+            .bind(account)
+            .bind(&info.id)
+            .bind(label)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    sqlx::query("DELETE FROM message_recipients WHERE account = ? AND mail_id = ?")
+        .bind(account)
+        .bind(&info.id)
+        .execute(&mut *conn)
+        .await?;
+    for recipient in &info.recipients {
+        sqlx::query(
+            "INSERT INTO message_recipients (account, mail_id, field, address) VALUES (?, ?, ?, ?)",
+        )
+        .bind(account)
+        .bind(&info.id)
+        .bind(recipient.field.as_str())
+        .bind(&recipient.address)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    sqlx::query("DELETE FROM attachments WHERE account = ? AND mail_id = ?")
+        .bind(account)
+        .bind(&info.id)
+        .execute(&mut *conn)
+        .await?;
+    for attachment in &info.attachments {
+        sqlx::query(
+            "INSERT INTO attachments (account, mail_id, mime_type, size, inline)
+            VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(account)
+        .bind(&info.id)
+        .bind(&attachment.mime_type)
+        .bind(attachment.size)
+        .bind(attachment.inline)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Add `counts` to `sender`'s row, creating it for a new sender. Returns whether the sender is
+/// new.
+pub async fn add_sender_counts(
+    sender: &str,
+    counts: &SenderCounts,
+    conn: &mut SqliteConnection,
+) -> Result<bool, GmailStatsError> {
+    // min()/max() return NULL if either side is, so fall back to whichever date we do have
+    let updated = sqlx::query(
+        "UPDATE senders SET mails_sent = coalesce(mails_sent, 0) + ?, bulk_count = bulk_count + ?,
+            unread_count = unread_count + ?, auto_count = auto_count + ?, bytes = bytes + ?,
+            first_seen = coalesce(min(first_seen, ?), first_seen, ?),
+            last_seen = coalesce(max(last_seen, ?), last_seen, ?)
+        WHERE sender = ?",
+    )
+    .bind(counts.mails)
+    .bind(counts.bulk)
+    .bind(counts.unread)
+    .bind(counts.automated)
+    .bind(counts.bytes)
+    .bind(counts.first_seen)
+    .bind(counts.first_seen)
+    .bind(counts.last_seen)
+    .bind(counts.last_seen)
+    .bind(sender)
+    .execute(&mut *conn)
+    .await?;
+    if updated.rows_affected() > 0 {
+        return Ok(false);
+    }
+
+    sqlx::query(
+        "INSERT INTO senders
+            (sender, mails_sent, bulk_count, unread_count, auto_count, bytes, first_seen,
+            last_seen)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(sender)
+    .bind(counts.mails)
+    .bind(counts.bulk)
+    .bind(counts.unread)
+    .bind(counts.automated)
+    .bind(counts.bytes)
+    .bind(counts.first_seen)
+    .bind(counts.last_seen)
+    .execute(&mut *conn)
+    .await?;
+    Ok(true)
+}
+
+pub async fn increment_spam_sender(
+    info: &MessageInfo,
+    executor: impl SqliteExecutor<'_>,
+) -> Result<(), GmailStatsError> {
+    sqlx::query(
+        "INSERT INTO spam_senders (sender, mails_sent, last_seen) VALUES (?, 1, ?)
+        ON CONFLICT (sender) DO UPDATE SET mails_sent = mails_sent + 1,
+            last_seen = coalesce(max(last_seen, excluded.last_seen), last_seen, excluded.last_seen)",
+    )
+    .bind(&info.sender.sender)
+    .bind(info.date)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// The fingerprint of the settings the database was last classified with, see
+/// `Classifier::fingerprint`. `None` before it ever was.
+pub async fn classified_with(pool: &Pool<Sqlite>) -> anyhow::Result<Option<String>> {
+    let fingerprint: Option<(String,)> =
+        sqlx::query_as("SELECT fingerprint FROM classified_with WHERE id = 1")
+            .fetch_optional(pool)
+            .await?;
+    Ok(fingerprint.map(|(fingerprint,)| fingerprint))
+}
+
+/// Mark every message and sender classified, with the settings `fingerprint` stands for.
+pub async fn finish_classifying(pool: &Pool<Sqlite>, fingerprint: &str) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("UPDATE messages SET classified = 1 WHERE NOT classified")
+        .execute(&mut tx)
+        .await?;
+    sqlx::query("UPDATE senders SET classified = 1 WHERE NOT classified")
+        .execute(&mut tx)
+        .await?;
+    sqlx::query("INSERT OR REPLACE INTO classified_with (id, fingerprint) VALUES (1, ?)")
+        .bind(fingerprint)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// Which rows to classify: all of them, or only those not classified yet
+fn unclassified(everything: bool) -> &'static str {
+    match everything {
+        true => "1",
+        false => "NOT classified",
+    }
+}
+
+// Classify the domains of senders and flag those at my own domains, for every sender when
+// `everything` so changes to the classification lists or own domains apply to mail fetched in
+// earlier runs too, else only for senders new since the last time
+pub async fn classify_senders(
+    pool: &Pool<Sqlite>,
+    classifier: &Classifier,
+    everything: bool,
+) -> anyhow::Result<()> {
+    let senders: Vec<(String,)> = sqlx::query_as(&format!(
+        "SELECT sender FROM senders WHERE {}",
+        unclassified(everything)
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    let mut tx = pool.begin().await?;
+    if everything {
+        sqlx::query("DELETE FROM domains").execute(&mut tx).await?;
+        sqlx::query("UPDATE senders SET is_internal = 0")
+            .execute(&mut tx)
+            .await?;
+    }
+    let mut seen = HashSet::new();
+    for (sender,) in senders {
+        let Some(domain) = domains::domain_of(&sender) else {
+            continue;
+        };
+        if seen.insert(domain.clone()) {
+            sqlx::query("INSERT OR REPLACE INTO domains (domain, class) VALUES (?, ?)")
+                .bind(&domain)
+                .bind(classifier.classify(&domain).as_str())
+                .execute(&mut tx)
+                .await?;
+        }
+        // New senders start out external
+        if classifier.is_internal(&domain) {
+            sqlx::query("UPDATE senders SET is_internal = 1 WHERE sender = ?")
+                .bind(&sender)
+                .execute(&mut tx)
+                .await?;
+        }
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+// Replace the stored aliases with `aliases`, (alias, canonical) pairs from aliases::resolve.
+// The canonical address takes the case it's stored with in senders, so it groups with its own mail
+pub async fn apply_aliases(
+    pool: &Pool<Sqlite>,
+    aliases: &[(String, String)],
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM sender_aliases")
+        .execute(&mut tx)
+        .await?;
+    for (alias, canonical) in aliases {
+        sqlx::query(
+            "INSERT INTO sender_aliases (sender, canonical)
+            SELECT ?, coalesce((SELECT sender FROM senders WHERE lower(sender) = ? LIMIT 1), ?)",
+        )
+        .bind(alias)
+        .bind(canonical)
+        .bind(canonical)
+        .execute(&mut tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn increment_run_ignored(
+    run_id: Option<i64>,
+    executor: impl SqliteExecutor<'_>,
+) -> Result<(), GmailStatsError> {
+    sqlx::query("UPDATE runs SET ignored = ignored + 1 WHERE id = ?")
+        .bind(run_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+pub async fn increment_run_erased(
+    run_id: Option<i64>,
+    executor: impl SqliteExecutor<'_>,
+) -> Result<(), GmailStatsError> {
+    sqlx::query("UPDATE runs SET erased = erased + 1 WHERE id = ?")
+        .bind(run_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+pub async fn increment_run_self_excluded(
+    run_id: Option<i64>,
+    executor: impl SqliteExecutor<'_>,
+) -> Result<(), GmailStatsError> {
+    sqlx::query("UPDATE runs SET self_excluded = self_excluded + 1 WHERE id = ?")
+        .bind(run_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+// Flag stored senders matching the ignore list, so it applies to mail fetched before a pattern
+// was added. Their counts are kept and come back if the pattern is removed.
+pub async fn flag_ignored_senders(pool: &Pool<Sqlite>, ignore: &IgnoreList) -> anyhow::Result<()> {
+    let senders: Vec<(String,)> = sqlx::query_as("SELECT sender FROM senders")
+        .fetch_all(pool)
+        .await?;
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("UPDATE senders SET is_ignored = 0")
+        .execute(&mut tx)
+        .await?;
+    for (sender,) in senders {
+        if ignore.is_ignored(&sender) {
+            sqlx::query("UPDATE senders SET is_ignored = 1 WHERE sender = ?")
+                .bind(&sender)
+                .execute(&mut tx)
+                .await?;
+        }
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+// A message's account, id, stored arrival, Delivered-To and recipients as a JSON array
+type ArrivalRow = (String, String, Option<String>, Option<String>, String);
+
+// Work out which of my addresses each message arrived at, as written so plus-tags and aliases
+// stay apart: the first of mine in To, then Cc, then Delivered-To. For every message when
+// `everything`, as my addresses or the aliases may have changed, else only for new ones.
+pub async fn classify_arrival(
+    pool: &Pool<Sqlite>,
+    identity: &Identity,
+    everything: bool,
+) -> anyhow::Result<()> {
+    let rows: Vec<ArrivalRow> = sqlx::query_as(&format!(
+        "SELECT m.account, m.mail_id, m.arrived_at, m.delivered_to,
+            (SELECT json_group_array(address) FROM (SELECT address FROM message_recipients r
+                WHERE r.account = m.account AND r.mail_id = m.mail_id
+                ORDER BY r.field = 'cc', r.rowid))
+        FROM messages m
+        WHERE {}",
+        unclassified(everything)
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    let mut tx = pool.begin().await?;
+    for (account, mail_id, stored, delivered_to, recipients) in rows {
+        let recipients: Vec<String> = serde_json::from_str(&recipients)?;
+        let arrived_at = identity.first_alias(
+            recipients
+                .iter()
+                .map(String::as_str)
+                .chain(delivered_to.as_deref()),
+        );
+        if arrived_at == stored {
+            continue;
+        }
+        sqlx::query("UPDATE messages SET arrived_at = ? WHERE account = ? AND mail_id = ?")
+            .bind(arrived_at)
+            .bind(&account)
+            .bind(&mail_id)
+            .execute(&mut tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+// Work out whether each message was sent to me directly, cc'd to me or neither (mailing lists
+// and Bcc), then total those per sender. For every message when `everything`, as my addresses
+// may have changed, else only for new ones and their senders.
+pub async fn classify_addressing(
+    pool: &Pool<Sqlite>,
+    identity: &Identity,
+    everything: bool,
+) -> anyhow::Result<()> {
+    let rows: Vec<(String, String, Option<String>, String)> = sqlx::query_as(&format!(
+        "SELECT m.account, m.mail_id, m.addressed,
+            (SELECT json_group_array(json_array(field, address)) FROM message_recipients r
+                WHERE r.account = m.account AND r.mail_id = m.mail_id)
+        FROM messages m
+        WHERE {}",
+        unclassified(everything)
+    ))
+    .fetch_all(pool)
+    .await?;
+
+    let mut tx = pool.begin().await?;
+    for (account, mail_id, stored, recipients) in rows {
+        let recipients: Vec<(String, String)> = serde_json::from_str(&recipients)?;
+        let mine = |field: &str| {
+            recipients
+                .iter()
+                .any(|(f, address)| f == field && identity.is_mine(address))
+        };
+        // An address in both To and Cc counts as direct
+        let addressed = if recipients.is_empty() {
+            None
+        } else if mine("to") {
+            Some("direct")
+        } else if mine("cc") {
+            Some("cc")
+        } else {
+            Some("other")
+        };
+        if addressed == stored.as_deref() {
+            continue;
+        }
+        sqlx::query("UPDATE messages SET addressed = ? WHERE account = ? AND mail_id = ?")
+            .bind(addressed)
+            .bind(&account)
+            .bind(&mail_id)
+            .execute(&mut tx)
+            .await?;
+    }
+    sqlx::query(&format!(
+        "UPDATE senders SET
+            direct_count = (SELECT count(*) FROM messages m
+                WHERE m.sender = senders.sender AND NOT m.is_spam AND m.addressed = 'direct'),
+            cc_count = (SELECT count(*) FROM messages m
+                WHERE m.sender = senders.sender AND NOT m.is_spam AND m.addressed = 'cc')
+        WHERE {}",
+        match everything {
+            true => "1",
+            false => "sender IN (SELECT sender FROM messages WHERE NOT classified)",
+        }
+    ))
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
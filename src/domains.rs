@@ -0,0 +1,297 @@
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::own::Identity;
+
+/// Free webmail providers, mail from these is almost always from a person.
+const FREEMAIL: &[&str] = &[
+    "gmail.com",
+    "googlemail.com",
+    "outlook.com",
+    "hotmail.*",
+    "live.com",
+    "msn.com",
+    "yahoo.*",
+    "ymail.com",
+    "aol.com",
+    "icloud.com",
+    "me.com",
+    "mac.com",
+    "proton.me",
+    "protonmail.com",
+    "gmx.*",
+    "mail.com",
+    "zoho.com",
+    "fastmail.com",
+    "yandex.*",
+];
+
+/// Email service providers which send on behalf of other companies, mostly marketing.
+const INFRASTRUCTURE: &[&str] = &[
+    "amazonses.com",
+    "sendgrid.net",
+    "sendgrid.com",
+    "mailchimp.com",
+    "mcsv.net",
+    "mcdlv.net",
+    "rsgsv.net",
+    "list-manage.com",
+    "mailgun.org",
+    "mailgun.net",
+    "mandrillapp.com",
+    "sparkpostmail.com",
+    "mailjet.com",
+    "sendinblue.com",
+    "constantcontact.com",
+    "createsend.com",
+    "cmail19.com",
+    "cmail20.com",
+    "exacttarget.com",
+    "hubspotemail.net",
+    "klaviyomail.com",
+    "substack.com",
+    "postmarkapp.com",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DomainClass {
+    Freemail,
+    Infrastructure,
+    Direct,
+}
+
+impl DomainClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DomainClass::Freemail => "freemail",
+            DomainClass::Infrastructure => "infrastructure",
+            DomainClass::Direct => "direct",
+        }
+    }
+}
+
+/// Extra domains to classify, added to the built-in lists. Read from the `[domain_classes]`
+/// section of the config file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DomainClassConfig {
+    pub freemail: Vec<String>,
+    pub infrastructure: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Classifier {
+    freemail: Vec<String>,
+    infrastructure: Vec<String>,
+    identity: Identity,
+}
+
+impl Classifier {
+    pub fn new(config: &Config) -> Classifier {
+        let with_builtin = |builtin: &[&str], extra: &[String]| {
+            builtin
+                .iter()
+                .map(|d| d.to_string())
+                .chain(extra.iter().map(|d| d.to_ascii_lowercase()))
+                .collect()
+        };
+        let classes = &config.domain_classes;
+        Classifier {
+            freemail: with_builtin(FREEMAIL, &classes.freemail),
+            infrastructure: with_builtin(INFRASTRUCTURE, &classes.infrastructure),
+            identity: config.identity.clone(),
+        }
+    }
+
+    /// The class lists and my identity, the same whenever the configuration is.
+    pub fn fingerprint(&self) -> String {
+        format!(
+            "{};{};{}",
+            self.freemail.join(","),
+            self.infrastructure.join(","),
+            self.identity.fingerprint()
+        )
+    }
+
+    /// Whether `domain` is one of mine, see `Identity::is_internal`.
+    pub fn is_internal(&self, domain: &str) -> bool {
+        self.identity.is_internal(domain)
+    }
+
+    pub fn classify(&self, domain: &str) -> DomainClass {
+        let domain = domain.to_ascii_lowercase();
+        if self.infrastructure.iter().any(|p| matches(p, &domain)) {
+            DomainClass::Infrastructure
+        } else if self.freemail.iter().any(|p| matches(p, &domain)) {
+            DomainClass::Freemail
+        } else {
+            DomainClass::Direct
+        }
+    }
+}
+
+// `example.com` matches itself and any subdomain. `yahoo.*` matches yahoo under any public
+// suffix and its subdomains, going by the registrable domain so `yahoo.co.jp` and
+// `mail.yahoo.com` match but `yahoo.example.com` doesn't.
+fn matches(pattern: &str, domain: &str) -> bool {
+    if let Some(name) = pattern.strip_suffix(".*") {
+        return registrable_domain(domain)
+            .strip_prefix(name)
+            .is_some_and(|suffix| suffix.starts_with('.'));
+    }
+    domain == pattern || domain.ends_with(&format!(".{}", pattern))
+}
+
+/// The domain one level below the public suffix, e.g. `hr.mycorp.co.uk` -> `mycorp.co.uk`.
+/// Anything the public suffix list doesn't know is returned as is.
+pub fn registrable_domain(domain: &str) -> String {
+    let domain = domain.trim().trim_end_matches('.').to_ascii_lowercase();
+    psl::domain_str(&domain)
+        .map(|registrable| registrable.to_string())
+        .unwrap_or(domain)
+}
+
+/// The lowercased domain part of an address.
+pub fn domain_of(address: &str) -> Option<String> {
+    address
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.trim().to_ascii_lowercase())
+        .filter(|domain| !domain.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyze::SenderCounts;
+    use crate::db;
+    use crate::testsupport::{self, info};
+
+    fn classifier(own_domains: &[&str]) -> Classifier {
+        let mut config = Config {
+            own_domains: own_domains.iter().map(|d| d.to_string()).collect(),
+            ..Default::default()
+        };
+        config.identity = Identity::new(&config);
+        Classifier::new(&config)
+    }
+
+    #[test]
+    fn subdomains_of_my_domains_are_internal() {
+        let classifier = classifier(&["mycorp.co.uk"]);
+
+        assert!(classifier.is_internal("mycorp.co.uk"));
+        assert!(classifier.is_internal("hr.mycorp.co.uk"));
+        assert!(classifier.is_internal("Mail.HR.MyCorp.co.uk"));
+        assert!(!classifier.is_internal("othercorp.co.uk"));
+        assert!(!classifier.is_internal("mycorp.co.uk.example.com"));
+        assert!(!classifier.is_internal("co.uk"));
+    }
+
+    #[test]
+    fn known_domains_have_their_classes() {
+        let classifier = classifier(&[]);
+
+        for (domain, class) in [
+            ("gmail.com", DomainClass::Freemail),
+            ("GoogleMail.com", DomainClass::Freemail),
+            ("yahoo.com", DomainClass::Freemail),
+            ("yahoo.co.jp", DomainClass::Freemail),
+            ("mail.yahoo.com", DomainClass::Freemail),
+            ("hotmail.co.uk", DomainClass::Freemail),
+            ("gmx.de", DomainClass::Freemail),
+            ("amazonses.com", DomainClass::Infrastructure),
+            ("us-west-2.amazonses.com", DomainClass::Infrastructure),
+            ("mail123.mcsv.net", DomainClass::Infrastructure),
+            ("em.sendgrid.net", DomainClass::Infrastructure),
+            ("example.com", DomainClass::Direct),
+            ("github.com", DomainClass::Direct),
+        ] {
+            assert_eq!(classifier.classify(domain), class, "{}", domain);
+        }
+    }
+
+    #[test]
+    fn any_tld_patterns_only_match_the_registrable_name() {
+        let classifier = classifier(&[]);
+
+        for domain in [
+            "yahoo.example.com",
+            "news.yahoo.evil.com",
+            "notyahoo.com",
+            "yahoo-mail.com",
+            "gmx.example.org",
+            "hotmailer.com",
+        ] {
+            assert_eq!(
+                classifier.classify(domain),
+                DomainClass::Direct,
+                "{}",
+                domain
+            );
+        }
+    }
+
+    #[test]
+    fn configured_domains_are_added_to_the_builtin_ones() {
+        let mut config = Config::default();
+        config.domain_classes.freemail = vec!["Posteo.DE".to_string()];
+        config.domain_classes.infrastructure = vec!["esp.*".to_string()];
+        let classifier = Classifier::new(&config);
+
+        assert_eq!(classifier.classify("posteo.de"), DomainClass::Freemail);
+        assert_eq!(
+            classifier.classify("bounce.esp.io"),
+            DomainClass::Infrastructure
+        );
+        assert_eq!(classifier.classify("gmail.com"), DomainClass::Freemail);
+    }
+
+    #[test]
+    fn nothing_is_internal_without_my_domains() {
+        let classifier = classifier(&[]);
+
+        for domain in ["gmail.com", "mycorp.co.uk", "localhost", ""] {
+            assert!(!classifier.is_internal(domain), "{}", domain);
+        }
+    }
+
+    #[tokio::test]
+    async fn classifying_flags_senders_at_my_subdomains() {
+        let pool = testsupport::pool().await;
+        let mut conn = pool.acquire().await.unwrap();
+        for sender in ["boss@hr.mycorp.co.uk", "jane@gmail.com"] {
+            let mut counts = SenderCounts::default();
+            counts.add(&info("x", sender, 0, &[]));
+            db::add_sender_counts(sender, &counts, &mut conn)
+                .await
+                .unwrap();
+        }
+        drop(conn);
+
+        db::classify_senders(&pool, &classifier(&["mycorp.co.uk"]), true)
+            .await
+            .unwrap();
+        let internal: Vec<(String, bool)> =
+            sqlx::query_as("SELECT sender, is_internal FROM senders ORDER BY sender")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            internal,
+            [
+                ("boss@hr.mycorp.co.uk".to_string(), true),
+                ("jane@gmail.com".to_string(), false)
+            ]
+        );
+
+        // Taking the domain out of the config takes the flag off again
+        db::classify_senders(&pool, &classifier(&[]), true)
+            .await
+            .unwrap();
+        let (internal,): (i64,) = sqlx::query_as("SELECT count(*) FROM senders WHERE is_internal")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(internal, 0);
+    }
+}
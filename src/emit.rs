@@ -0,0 +1,174 @@
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+
+use chrono::{SecondsFormat, TimeZone, Utc};
+use serde::Serialize;
+
+use crate::parse::MessageInfo;
+
+/// One line of `fetch --emit-jsonl` output.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Record<'a> {
+    pub mail_id: &'a str,
+    /// The cleaned sender the message is attributed to.
+    pub sender: &'a str,
+    /// The From header as sent.
+    pub from: &'a str,
+    /// When Gmail received the message, RFC 3339 in UTC.
+    pub date: Option<String>,
+    /// Gmail's size estimate in bytes.
+    pub size: i64,
+    pub labels: &'a [String],
+}
+
+impl<'a> Record<'a> {
+    pub fn new(info: &'a MessageInfo) -> Record<'a> {
+        Record {
+            mail_id: &info.id,
+            sender: &info.sender.sender,
+            from: &info.sender.from,
+            date: info
+                .date
+                .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+                .map(|date| date.to_rfc3339_opts(SecondsFormat::Millis, true)),
+            size: info.size_estimate,
+            labels: &info.labels,
+        }
+    }
+}
+
+/// Writes a `Record` per message as it's fetched. Problems are reported on stderr rather than
+/// failing the fetch: a record which won't serialize is skipped, and after a write error (say the
+/// reader of a pipe went away) nothing more is written.
+#[derive(Default)]
+pub struct Emitter {
+    out: Option<Box<dyn Write + Send>>,
+}
+
+impl Emitter {
+    /// Emit to `path`, or stdout for `-`. With no path, `emit` does nothing.
+    pub fn open(path: Option<&Path>) -> io::Result<Emitter> {
+        let out: Option<Box<dyn Write + Send>> = match path {
+            None => None,
+            Some(path) if path == Path::new("-") => Some(Box::new(io::stdout())),
+            Some(path) => Some(Box::new(LineWriter::new(File::create(path)?))),
+        };
+        Ok(Emitter { out })
+    }
+
+    /// Whether there's anywhere to write, so callers can skip holding on to messages for it.
+    pub fn is_open(&self) -> bool {
+        self.out.is_some()
+    }
+
+    pub fn emit(&mut self, info: &MessageInfo) {
+        let Some(out) = &mut self.out else {
+            return;
+        };
+        let line = match serde_json::to_string(&Record::new(info)) {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("skipping JSONL record for {}: {}", info.id, err);
+                return;
+            }
+        };
+        // Flushed per line so a reader downstream sees each message as it arrives
+        if let Err(err) = writeln!(out, "{}", line).and_then(|_| out.flush()) {
+            eprintln!("stopped writing JSONL: {}", err);
+            self.out = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::testsupport::info;
+
+    // Fails every write, as a pipe does once its reader has gone
+    struct Closed;
+
+    impl Write for Closed {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn a_record_has_every_field() {
+        let mut message = info(
+            "m1",
+            "Jane <jane@example.com>",
+            1_718_010_000_123,
+            &["INBOX", "CATEGORY_UPDATES"],
+        );
+        message.size_estimate = 2_048;
+        let record: Value = serde_json::to_value(Record::new(&message)).unwrap();
+        assert_eq!(
+            record,
+            json!({
+                "mail_id": "m1",
+                "sender": "jane@example.com",
+                "from": "Jane <jane@example.com>",
+                "date": "2024-06-10T09:00:00.123Z",
+                "size": 2048,
+                "labels": ["INBOX", "CATEGORY_UPDATES"],
+            })
+        );
+    }
+
+    #[test]
+    fn a_message_without_a_date_has_a_null_one() {
+        let mut message = info("m1", "jane@example.com", 0, &[]);
+        message.date = None;
+        let record: Value = serde_json::to_value(Record::new(&message)).unwrap();
+        assert_eq!(record["date"], Value::Null);
+        assert_eq!(record["labels"], json!([]));
+    }
+
+    #[test]
+    fn each_message_is_a_line_of_its_own() {
+        let dir = std::env::temp_dir().join(format!("gmail-stats-emit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("fetched.jsonl");
+
+        let mut emitter = Emitter::open(Some(&path)).unwrap();
+        assert!(emitter.is_open());
+        emitter.emit(&info("m1", "jane@example.com", 1_000, &["INBOX"]));
+        emitter.emit(&info("m2", "bob@example.org", 2_000, &["SPAM"]));
+        drop(emitter);
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        let ids: Vec<String> = written
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<Value>(line).unwrap()["mail_id"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(ids, ["m1", "m2"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nothing_more_is_written_after_a_write_fails() {
+        let mut emitter = Emitter {
+            out: Some(Box::new(Closed)),
+        };
+        emitter.emit(&info("m1", "jane@example.com", 1_000, &[]));
+        assert!(!emitter.is_open());
+
+        let mut closed = Emitter::open(None).unwrap();
+        assert!(!closed.is_open());
+        closed.emit(&info("m1", "jane@example.com", 1_000, &[]));
+    }
+}
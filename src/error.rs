@@ -0,0 +1,294 @@
+use std::time::Duration;
+
+use hyper::header::{HeaderMap, RETRY_AFTER};
+use serde_json::Value;
+
+/// What went wrong while fetching, in enough detail to decide whether to try again.
+#[derive(Debug, thiserror::Error)]
+pub enum GmailStatsError {
+    /// No token, or Gmail turned the one we had down.
+    #[error("couldn't authenticate with Gmail: {0}")]
+    Auth(String),
+    /// Gmail wants us to slow down, for `retry_after` if it said how long.
+    #[error("rate limited by Gmail")]
+    RateLimited { retry_after: Option<Duration> },
+    /// Gmail answered a call with an error, with its HTTP status if there was one.
+    #[error("Gmail API error{}: {message}", status.map(|s| format!(" {}", s)).unwrap_or_default())]
+    Api {
+        status: Option<u16>,
+        message: String,
+    },
+    /// The request never got an answer.
+    #[error("couldn't reach Gmail: {0}")]
+    Network(String),
+    #[error("database error: {0}")]
+    Db(#[from] sqlx::Error),
+    /// A response or message we couldn't make sense of.
+    #[error("couldn't parse {0}")]
+    Parse(String),
+    /// A setting or path we were given which doesn't work.
+    #[error("{0}")]
+    Config(String),
+    /// Stopped by Ctrl-C or SIGTERM, after recording the run as interrupted.
+    #[error("fetch interrupted")]
+    Interrupted,
+    /// Stopped at `--max-duration` the way Ctrl-C stops it, after recording the run as
+    /// interrupted by the deadline.
+    #[error("fetch stopped at --max-duration")]
+    DeadlineReached,
+    /// The API project's quota for the day is used up: every call fails until it resets at
+    /// midnight Pacific time, so there's no point retrying.
+    #[error("Gmail's daily quota for the API project is used up")]
+    QuotaExhausted,
+}
+
+impl GmailStatsError {
+    /// A rate limit which didn't say how long to wait takes `retry_after` from the response's
+    /// header instead. Anything else is returned as it is.
+    pub fn with_retry_after(self, retry_after: Option<Duration>) -> GmailStatsError {
+        match self {
+            GmailStatsError::RateLimited { retry_after: None } => {
+                GmailStatsError::RateLimited { retry_after }
+            }
+            err => err,
+        }
+    }
+
+    /// Whether trying again might work: rate limits, dropped connections, database locks and
+    /// Gmail's own 5xx errors.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            GmailStatsError::RateLimited { .. } | GmailStatsError::Network(_) => true,
+            GmailStatsError::Db(err) => is_transient_db(err),
+            GmailStatsError::Api { status, .. } => status.is_some_and(|status| status >= 500),
+            GmailStatsError::Auth(_)
+            | GmailStatsError::Parse(_)
+            | GmailStatsError::Config(_)
+            | GmailStatsError::Interrupted
+            | GmailStatsError::DeadlineReached
+            | GmailStatsError::QuotaExhausted => false,
+        }
+    }
+
+    /// What to do about an error retrying won't fix, where there's something better to say than
+    /// the error itself.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            GmailStatsError::Auth(_) => Some(
+                "Gmail turned down the saved sign-in; delete tokencache.json and fetch again to \
+                sign in afresh, or check credentials.json",
+            ),
+            GmailStatsError::Api {
+                status: Some(403), ..
+            } => Some("check the Gmail API is enabled for the project credentials.json is from"),
+            GmailStatsError::QuotaExhausted => Some(
+                "nothing gets through until the quota resets at midnight Pacific time; run it \
+                again then, or ask for more quota in the Google Cloud console",
+            ),
+            GmailStatsError::Db(_) => Some(
+                "stats.db rejected a query; it may be damaged or from a newer gmail_stats, check \
+                it with `sqlite3 stats.db 'PRAGMA integrity_check'`",
+            ),
+            _ => None,
+        }
+    }
+}
+
+// SQLITE_BUSY and SQLITE_LOCKED, with any extended code, clear up once the other writer is
+// done; a missing table or bad query won't
+fn is_transient_db(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Database(err) => err
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .is_some_and(|code| matches!(code & 0xff, 5 | 6)),
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::WorkerCrashed => true,
+        _ => false,
+    }
+}
+
+/// How long a response's `Retry-After` header says to wait. Only the delay-seconds form is read,
+/// Gmail doesn't send dates.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .map(Duration::from_secs)
+}
+
+// Gmail's JSON error body: {"error": {"code": 429, "message": "...", "errors": [{"reason": ...}]}}
+fn classify_body(body: &Value) -> GmailStatsError {
+    let error = &body["error"];
+    let status = error["code"].as_u64().map(|code| code as u16);
+    let message = error["message"]
+        .as_str()
+        .map(str::to_string)
+        .unwrap_or_else(|| body.to_string());
+    // Quota errors come back as 403s, told apart only by their reason: per-user limits clear
+    // up in seconds, the project's daily one not until tomorrow
+    let reason = |reasons: &[&str]| {
+        error["errors"].as_array().is_some_and(|errors| {
+            errors
+                .iter()
+                .any(|err| err["reason"].as_str().is_some_and(|r| reasons.contains(&r)))
+        })
+    };
+    let rate_limited = reason(&["rateLimitExceeded", "userRateLimitExceeded"]);
+    let quota_exhausted = reason(&["dailyLimitExceeded"]);
+    match status {
+        _ if quota_exhausted => GmailStatsError::QuotaExhausted,
+        Some(429) => GmailStatsError::RateLimited { retry_after: None },
+        _ if rate_limited => GmailStatsError::RateLimited { retry_after: None },
+        Some(401) => GmailStatsError::Auth(message),
+        _ => GmailStatsError::Api { status, message },
+    }
+}
+
+impl From<google_gmail1::Error> for GmailStatsError {
+    fn from(err: google_gmail1::Error) -> GmailStatsError {
+        use google_gmail1::Error;
+
+        match err {
+            Error::Failure(response) => {
+                let status = response.status();
+                match status.as_u16() {
+                    429 => GmailStatsError::RateLimited {
+                        retry_after: retry_after(response.headers()),
+                    },
+                    401 => GmailStatsError::Auth(status.to_string()),
+                    code => GmailStatsError::Api {
+                        status: Some(code),
+                        message: status.to_string(),
+                    },
+                }
+            }
+            Error::BadRequest(body) => classify_body(&body),
+            Error::MissingToken(err) => GmailStatsError::Auth(err.to_string()),
+            Error::MissingAPIKey => GmailStatsError::Auth("no API key".to_string()),
+            Error::HttpError(err) => GmailStatsError::Network(err.to_string()),
+            Error::Io(err) => GmailStatsError::Network(err.to_string()),
+            Error::JsonDecodeError(body, err) => {
+                GmailStatsError::Parse(format!("Gmail response {:?}: {}", body, err))
+            }
+            err
+            @ (Error::UploadSizeLimitExceeded(..) | Error::Cancelled | Error::FieldClash(_)) => {
+                GmailStatsError::Api {
+                    status: None,
+                    message: err.to_string(),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use google_gmail1::Error;
+    use hyper::{Body, Response};
+    use serde_json::json;
+
+    use super::*;
+
+    fn failure(status: u16, retry_after: Option<&str>) -> Error {
+        let mut response = Response::builder().status(status);
+        if let Some(value) = retry_after {
+            response = response.header(RETRY_AFTER, value);
+        }
+        Error::Failure(response.body(Body::empty()).unwrap())
+    }
+
+    fn body(code: u16, reason: &str) -> Error {
+        Error::BadRequest(json!({
+            "error": {
+                "code": code,
+                "message": "nope",
+                "errors": [{"reason": reason}],
+            }
+        }))
+    }
+
+    fn classify(err: Error) -> GmailStatsError {
+        GmailStatsError::from(err)
+    }
+
+    #[test]
+    fn rate_limits_carry_their_retry_after() {
+        assert!(matches!(
+            classify(failure(429, Some(" 30 "))),
+            GmailStatsError::RateLimited { retry_after: Some(wait) } if wait == Duration::from_secs(30)
+        ));
+        assert!(matches!(
+            classify(failure(429, Some("Wed, 21 Oct 2015 07:28:00 GMT"))),
+            GmailStatsError::RateLimited { retry_after: None }
+        ));
+    }
+
+    #[test]
+    fn json_rate_limits_take_the_header_they_came_with() {
+        let err =
+            classify(body(429, "rateLimitExceeded")).with_retry_after(Some(Duration::from_secs(5)));
+        assert!(matches!(
+            err,
+            GmailStatsError::RateLimited { retry_after: Some(wait) } if wait == Duration::from_secs(5)
+        ));
+        // A 403 is a rate limit only by its reason
+        assert!(matches!(
+            classify(body(403, "userRateLimitExceeded")),
+            GmailStatsError::RateLimited { retry_after: None }
+        ));
+        // Other errors keep no wait
+        assert!(matches!(
+            classify(body(404, "notFound")).with_retry_after(Some(Duration::from_secs(5))),
+            GmailStatsError::Api {
+                status: Some(404),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn the_daily_quota_is_not_retried() {
+        let err = classify(body(403, "dailyLimitExceeded"));
+        assert!(matches!(err, GmailStatsError::QuotaExhausted));
+        assert!(!err.is_transient());
+    }
+
+    #[test]
+    fn statuses_decide_what_is_retried() {
+        assert!(matches!(
+            classify(failure(401, None)),
+            GmailStatsError::Auth(_)
+        ));
+        assert!(matches!(
+            classify(body(401, "authError")),
+            GmailStatsError::Auth(_)
+        ));
+        let unavailable = classify(failure(503, None));
+        assert!(matches!(
+            unavailable,
+            GmailStatsError::Api {
+                status: Some(503),
+                ..
+            }
+        ));
+        assert!(unavailable.is_transient());
+        let forbidden = classify(body(403, "forbidden"));
+        assert!(!forbidden.is_transient());
+        assert!(forbidden.hint().is_some());
+    }
+
+    #[test]
+    fn transport_failures_are_network_errors_and_bad_json_a_parse_error() {
+        let io = Error::Io(std::io::Error::new(
+            std::io::ErrorKind::ConnectionReset,
+            "reset",
+        ));
+        assert!(matches!(classify(io), GmailStatsError::Network(_)));
+        let decode = serde_json::from_str::<Value>("{").unwrap_err();
+        assert!(matches!(
+            classify(Error::JsonDecodeError("{".to_string(), decode)),
+            GmailStatsError::Parse(_)
+        ));
+    }
+}
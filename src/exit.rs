@@ -0,0 +1,171 @@
+use crate::error::GmailStatsError;
+use crate::interrupt;
+use crate::lock;
+use crate::summary::Summary;
+
+// The `gmail_stats` binary's exit statuses, for cron wrappers and scripts to tell apart. They
+// stay the same from one version to the next; new ones get new numbers.
+
+/// Everything worked.
+pub const SUCCESS: i32 = 0;
+/// Anything not covered below.
+pub const FAILURE: i32 = 1;
+/// The run got to the end, but passed over some messages; `report errors` lists them.
+pub const PARTIAL: i32 = 2;
+/// Gmail needs signing in to again, or turned the sign-in down.
+pub const AUTH_REQUIRED: i32 = 3;
+/// Another run holds the lock on the database.
+pub const LOCKED: i32 = 4;
+/// Stopped at `--max-duration`, having recorded where it got to; run it again to carry on.
+pub const DEADLINE: i32 = 5;
+/// Stopped because Gmail's daily quota is used up, having recorded where it got to; run it
+/// again after midnight Pacific time.
+pub const QUOTA_EXHAUSTED: i32 = 6;
+/// Stopped by Ctrl-C or SIGTERM.
+pub const INTERRUPTED: i32 = interrupt::EXIT_CODE;
+
+/// How a command which ran to the end went.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Completion {
+    #[default]
+    Clean,
+    /// Some messages were skipped.
+    Partial,
+}
+
+impl Completion {
+    /// How a fetch or import went, by its summary.
+    pub fn of(summary: &Summary) -> Completion {
+        if summary.skipped > 0 {
+            Completion::Partial
+        } else {
+            Completion::Clean
+        }
+    }
+}
+
+/// The exit status for how a command ended.
+pub fn code(result: &anyhow::Result<Completion>) -> i32 {
+    match result {
+        Ok(Completion::Clean) => SUCCESS,
+        Ok(Completion::Partial) => PARTIAL,
+        Err(err) => error_code(err),
+    }
+}
+
+/// The exit status for a command which failed with `err`.
+pub fn error_code(err: &anyhow::Error) -> i32 {
+    if err.downcast_ref::<lock::Held>().is_some() {
+        return LOCKED;
+    }
+    match err.downcast_ref::<GmailStatsError>() {
+        Some(GmailStatsError::Interrupted) => INTERRUPTED,
+        Some(GmailStatsError::DeadlineReached) => DEADLINE,
+        Some(GmailStatsError::QuotaExhausted) => QUOTA_EXHAUSTED,
+        Some(GmailStatsError::Auth(_)) => AUTH_REQUIRED,
+        _ => FAILURE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::fetch::{self, Backoff, FetchOptions};
+    use crate::interrupt::CancelToken;
+    use crate::testsupport::{self, message, MockMailSource};
+
+    fn mailbox() -> MockMailSource {
+        MockMailSource::new().page([
+            message("m1", &[("From", "jane@example.com")], &["INBOX"]),
+            message("m2", &[("From", "news@example.org")], &["INBOX"]),
+        ])
+    }
+
+    // The exit status of a fetch from `source`, worked out the way `run` does
+    async fn fetch_code(source: &MockMailSource, cancel: CancelToken) -> i32 {
+        let pool = testsupport::pool().await;
+        let opts = FetchOptions {
+            cancel,
+            max_retries: 3,
+            backoff: Backoff {
+                initial: Duration::ZERO,
+                max: Duration::ZERO,
+            },
+            quiet: true,
+            ..Default::default()
+        };
+        let result = fetch::run(&pool, source, &opts)
+            .await
+            .map(|summary| Completion::of(&summary))
+            .map_err(anyhow::Error::from);
+        code(&result)
+    }
+
+    #[tokio::test]
+    async fn a_fetch_of_every_message_succeeds() {
+        assert_eq!(fetch_code(&mailbox(), Default::default()).await, SUCCESS);
+    }
+
+    #[tokio::test]
+    async fn a_fetch_which_skips_a_message_is_partial() {
+        let source = mailbox().listing(&["gone"]);
+
+        assert_eq!(fetch_code(&source, Default::default()).await, PARTIAL);
+    }
+
+    #[tokio::test]
+    async fn each_way_a_fetch_stops_has_its_own_code() {
+        let cancel = CancelToken::default();
+        let interrupt = cancel.clone();
+        let interrupted = mailbox().fail_get("m2", 1, move || {
+            interrupt.cancel();
+            GmailStatsError::Network("connection reset".to_string())
+        });
+        assert_eq!(fetch_code(&interrupted, cancel).await, INTERRUPTED);
+
+        let cancel = CancelToken::default();
+        let expire = cancel.clone();
+        let out_of_time = mailbox().fail_get("m2", 1, move || {
+            expire.expire();
+            GmailStatsError::Network("connection reset".to_string())
+        });
+        assert_eq!(fetch_code(&out_of_time, cancel).await, DEADLINE);
+
+        let out_of_quota = mailbox().fail_get("m2", 1, || GmailStatsError::QuotaExhausted);
+        assert_eq!(
+            fetch_code(&out_of_quota, Default::default()).await,
+            QUOTA_EXHAUSTED
+        );
+
+        let signed_out =
+            mailbox().fail_list(0, 1, || GmailStatsError::Auth("invalid_grant".to_string()));
+        assert_eq!(
+            fetch_code(&signed_out, Default::default()).await,
+            AUTH_REQUIRED
+        );
+
+        let refused = mailbox().fail_list(0, 1, || GmailStatsError::Api {
+            status: Some(400),
+            message: "Invalid query".to_string(),
+        });
+        assert_eq!(fetch_code(&refused, Default::default()).await, FAILURE);
+    }
+
+    #[test]
+    fn a_held_lock_is_locked_whatever_wraps_it() {
+        let held = anyhow::Error::new(lock::Held {
+            pid: Some(42),
+            started: None,
+            stale: false,
+        });
+
+        assert_eq!(code(&Err(held.context("couldn't fetch"))), LOCKED);
+    }
+
+    #[test]
+    fn other_errors_are_failures() {
+        assert_eq!(code(&Err(anyhow::anyhow!("no such report"))), FAILURE);
+    }
+}
@@ -0,0 +1,183 @@
+use sqlx::{Pool, Sqlite};
+
+use crate::anonymize::Anonymizer;
+use crate::cli::{ExportArgs, ExportCommand, ExportFormat};
+use crate::config::Config;
+
+pub mod ids;
+#[cfg(feature = "export-parquet")]
+mod parquet;
+#[cfg(feature = "export-xlsx")]
+mod xlsx;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnType {
+    Text,
+    /// An email address, hashed by `--anonymize`.
+    Address,
+    /// Free text which could identify someone, like a display name or subject. Left out by
+    /// `--anonymize`.
+    Personal,
+    Int,
+    Bool,
+    /// Epoch milliseconds, exported as a UTC timestamp.
+    Timestamp,
+}
+
+/// Exportable columns of the messages table, in export order.
+pub const MESSAGE_COLUMNS: [(&str, ColumnType); 27] = [
+    ("mail_id", ColumnType::Text),
+    ("sender", ColumnType::Address),
+    ("envelope_sender", ColumnType::Address),
+    ("original_sender", ColumnType::Address),
+    ("sender_name", ColumnType::Personal),
+    ("subject", ColumnType::Personal),
+    ("internal_date", ColumnType::Timestamp),
+    ("fetched_at", ColumnType::Timestamp),
+    ("size_estimate", ColumnType::Int),
+    ("is_bulk", ColumnType::Bool),
+    ("bulk_signal", ColumnType::Text),
+    ("is_unread", ColumnType::Bool),
+    ("is_spam", ColumnType::Bool),
+    ("attachment_count", ColumnType::Int),
+    ("attachment_bytes", ColumnType::Int),
+    ("thread_id", ColumnType::Text),
+    ("run_id", ColumnType::Int),
+    ("addressed", ColumnType::Text),
+    ("auto_kind", ColumnType::Text),
+    ("spf", ColumnType::Text),
+    ("dkim", ColumnType::Text),
+    ("dmarc", ColumnType::Text),
+    ("is_calendar", ColumnType::Bool),
+    ("calendar_method", ColumnType::Text),
+    ("calendar_organizer", ColumnType::Address),
+    ("notice", ColumnType::Text),
+    ("notice_address", ColumnType::Address),
+];
+
+/// Columns of the senders table, in export order.
+pub const SENDER_COLUMNS: [(&str, ColumnType); 12] = [
+    ("sender", ColumnType::Address),
+    ("mails_sent", ColumnType::Int),
+    ("bulk_count", ColumnType::Int),
+    ("unread_count", ColumnType::Int),
+    ("direct_count", ColumnType::Int),
+    ("cc_count", ColumnType::Int),
+    ("auto_count", ColumnType::Int),
+    ("bytes", ColumnType::Int),
+    ("first_seen", ColumnType::Timestamp),
+    ("last_seen", ColumnType::Timestamp),
+    ("is_internal", ColumnType::Bool),
+    ("is_ignored", ColumnType::Bool),
+];
+
+/// The entries of `columns` named in `selected`, in the order given. An empty selection means
+/// every column.
+pub fn select_columns(
+    columns: &[(&'static str, ColumnType)],
+    selected: &[String],
+) -> anyhow::Result<Vec<(&'static str, ColumnType)>> {
+    if selected.is_empty() {
+        return Ok(columns.to_vec());
+    }
+    selected
+        .iter()
+        .map(|name| {
+            columns
+                .iter()
+                .find(|(column, _)| column.eq_ignore_ascii_case(name.trim()))
+                .copied()
+                .ok_or_else(|| {
+                    let known = columns
+                        .iter()
+                        .map(|(column, _)| *column)
+                        .collect::<Vec<_>>();
+                    anyhow::anyhow!(
+                        "unknown column {:?}, expected one of {}",
+                        name,
+                        known.join(", ")
+                    )
+                })
+        })
+        .collect()
+}
+
+// A query for `columns` of `table` in `order`. Columns declared `string` have numeric affinity,
+// so an all-digit id may be stored as an integer and text columns are cast back.
+#[cfg(any(feature = "export-parquet", feature = "export-xlsx"))]
+fn select(columns: &[(&str, ColumnType)], table: &str, order: &str) -> String {
+    let names = columns
+        .iter()
+        .map(|(name, column_type)| match column_type {
+            ColumnType::Text | ColumnType::Address | ColumnType::Personal => {
+                format!("CAST({} AS TEXT)", name)
+            }
+            _ => name.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("SELECT {} FROM {} ORDER BY {}", names, table, order)
+}
+
+// Without either format there's nothing to write, only the error to give
+#[cfg_attr(
+    not(any(feature = "export-parquet", feature = "export-xlsx")),
+    allow(unused_variables)
+)]
+pub async fn run(pool: &Pool<Sqlite>, args: &ExportArgs, config: &Config) -> anyhow::Result<()> {
+    if let Some(ExportCommand::Ids(args)) = &args.command {
+        return ids::run(pool, args, config).await;
+    }
+    let out = args
+        .out
+        .as_deref()
+        .expect("clap requires --out without a subcommand");
+    if args.format != ExportFormat::Parquet
+        && (!args.columns.is_empty() || args.senders_out.is_some())
+    {
+        anyhow::bail!("--columns and --senders-out only apply to --format parquet");
+    }
+    // One anonymizer for every file, so tokens match across them
+    let anonymizer = match (&args.anonymize_key, args.anonymize) {
+        (Some(key), _) => Some(Anonymizer::new(key.as_bytes(), args.anonymize_domains)),
+        (None, true) => Some(Anonymizer::random(args.anonymize_domains)?),
+        (None, false) => None,
+    };
+    let anonymizer = anonymizer.as_ref();
+    match args.format {
+        #[cfg(feature = "export-parquet")]
+        ExportFormat::Parquet => {
+            let columns = select_columns(&MESSAGE_COLUMNS, &args.columns)?;
+            let rows = parquet::write_parquet(pool, "messages", &columns, out, anonymizer).await?;
+            println!("wrote {} messages to {}", rows, out.display());
+            if let Some(senders_out) = &args.senders_out {
+                let rows = parquet::write_parquet(
+                    pool,
+                    "senders",
+                    &SENDER_COLUMNS,
+                    senders_out,
+                    anonymizer,
+                )
+                .await?;
+                println!("wrote {} senders to {}", rows, senders_out.display());
+            }
+            Ok(())
+        }
+        #[cfg(feature = "export-xlsx")]
+        ExportFormat::Xlsx => {
+            xlsx::write(pool, out, anonymizer).await?;
+            println!("wrote {}", out.display());
+            Ok(())
+        }
+        #[cfg(not(feature = "export-parquet"))]
+        ExportFormat::Parquet => Err(crate::cli::needs_feature(
+            "export --format parquet",
+            "export-parquet",
+        )),
+        #[cfg(not(feature = "export-xlsx"))]
+        ExportFormat::Xlsx => Err(crate::cli::needs_feature(
+            "export --format xlsx",
+            "export-xlsx",
+        )),
+    }
+}
@@ -0,0 +1,231 @@
+use std::io::{self, BufWriter, Write};
+
+use futures::TryStreamExt;
+use sqlx::{Pool, Sqlite};
+
+use crate::cli::ExportIdsArgs;
+use crate::config::Config;
+use crate::dates;
+use crate::parse::cleanup_sender;
+use crate::report;
+
+/// The address `sender` is stored under, lowercased: the address itself from anything a From
+/// header could hold, such as `Jane <jane@example.com>`.
+pub fn normalize(sender: &str) -> String {
+    cleanup_sender(sender.trim()).to_lowercase()
+}
+
+/// Write the ids of `sender`'s stored messages to `out`, one per line as they're read, oldest
+/// first. That's the mail of every address `sender` is aliased with, going by `sender_aliases`,
+/// received before `before` (epoch milliseconds) if it's set and in `account` if that is.
+/// Returns how many were written.
+pub async fn write_ids(
+    pool: &Pool<Sqlite>,
+    sender: &str,
+    before: Option<i64>,
+    account: Option<&str>,
+    out: &mut impl Write,
+) -> anyhow::Result<u64> {
+    // Mail ids are declared `string`, which has numeric affinity, so an all-digit id may be
+    // stored as an integer
+    let mut rows = sqlx::query_as::<_, (String,)>(
+        "WITH wanted AS (SELECT lower(coalesce(
+                (SELECT canonical FROM sender_aliases WHERE sender = ?1), ?1)) AS canonical)
+        SELECT CAST(m.mail_id AS TEXT)
+        FROM messages m LEFT JOIN sender_aliases a ON a.sender = lower(m.sender)
+        WHERE lower(coalesce(a.canonical, m.sender)) = (SELECT canonical FROM wanted)
+            AND (?2 IS NULL OR m.internal_date < ?2) AND (?3 IS NULL OR m.account = ?3)
+        ORDER BY m.internal_date, m.mail_id",
+    )
+    .bind(normalize(sender))
+    .bind(before)
+    .bind(account)
+    .fetch(pool);
+    let mut written = 0;
+    while let Some((id,)) = rows.try_next().await? {
+        writeln!(out, "{}", id)?;
+        written += 1;
+    }
+    out.flush()?;
+    Ok(written)
+}
+
+/// `export ids`: print a sender's message ids to stdout. A reader which stops early, like
+/// `head`, isn't an error.
+pub async fn run(pool: &Pool<Sqlite>, args: &ExportIdsArgs, config: &Config) -> anyhow::Result<()> {
+    let before = match &args.before {
+        Some(before) => Some(dates::start_of_day(
+            dates::parse_date(before)?,
+            report::timezone(None, config)?,
+        )),
+        None => None,
+    };
+    let mut out = BufWriter::new(io::stdout());
+    match write_ids(
+        pool,
+        &args.sender,
+        before,
+        args.profile.as_deref(),
+        &mut out,
+    )
+    .await
+    {
+        Ok(0) => eprintln!("no stored messages from {}", normalize(&args.sender)),
+        Ok(_) => {}
+        Err(err)
+            if err
+                .downcast_ref::<io::Error>()
+                .is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe) => {}
+        Err(err) => return Err(err),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, DEFAULT_ACCOUNT};
+    use crate::testsupport::{self, info};
+
+    // jane writes from two addresses, one aliased to the other, and bob from one
+    async fn fixture() -> Pool<Sqlite> {
+        let pool = testsupport::pool().await;
+        let mut conn = pool.acquire().await.unwrap();
+        for (id, from, date, account) in [
+            ("j3", "Jane@Example.com", 3_000, DEFAULT_ACCOUNT),
+            ("j1", "jane@example.com", 1_000, DEFAULT_ACCOUNT),
+            ("w2", "jane.work@example.org", 2_000, DEFAULT_ACCOUNT),
+            ("w4", "jane.work@example.org", 4_000, "work"),
+            ("b1", "bob@example.net", 1_500, DEFAULT_ACCOUNT),
+            ("1234", "bob@example.net", 2_500, DEFAULT_ACCOUNT),
+        ] {
+            db::record_message(
+                &info(id, from, date, &["INBOX"]),
+                account,
+                None,
+                chrono::DateTime::UNIX_EPOCH,
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        }
+        drop(conn);
+        db::apply_aliases(
+            &pool,
+            &[(
+                "jane.work@example.org".to_string(),
+                "jane@example.com".to_string(),
+            )],
+        )
+        .await
+        .unwrap();
+        pool
+    }
+
+    async fn ids(
+        pool: &Pool<Sqlite>,
+        sender: &str,
+        before: Option<i64>,
+        account: Option<&str>,
+    ) -> Vec<String> {
+        let mut out = Vec::new();
+        let written = write_ids(pool, sender, before, account, &mut out)
+            .await
+            .unwrap();
+        let ids = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(written, ids.len() as u64);
+        ids
+    }
+
+    #[tokio::test]
+    async fn a_sender_and_its_aliases_are_one_sender() {
+        let pool = fixture().await;
+
+        // Oldest first, whichever address of jane's is asked for and however it's written
+        let everything = ["j1", "w2", "j3", "w4"];
+        assert_eq!(ids(&pool, "jane@example.com", None, None).await, everything);
+        assert_eq!(
+            ids(&pool, "Jane <JANE@example.com>", None, None).await,
+            everything
+        );
+        assert_eq!(
+            ids(&pool, " jane.work@example.org ", None, None).await,
+            everything
+        );
+        assert_eq!(
+            ids(&pool, "bob@example.net", None, None).await,
+            ["b1", "1234"]
+        );
+        assert!(ids(&pool, "nobody@example.com", None, None)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn before_and_account_narrow_it_down() {
+        let pool = fixture().await;
+
+        // Mail received exactly at `before` is left out
+        assert_eq!(
+            ids(&pool, "jane@example.com", Some(3_000), None).await,
+            ["j1", "w2"]
+        );
+        assert_eq!(
+            ids(
+                &pool,
+                "jane@example.com",
+                Some(3_001),
+                Some(DEFAULT_ACCOUNT)
+            )
+            .await,
+            ["j1", "w2", "j3"]
+        );
+        assert_eq!(
+            ids(&pool, "jane@example.com", None, Some("work")).await,
+            ["w4"]
+        );
+        assert!(ids(&pool, "jane@example.com", Some(1_000), None)
+            .await
+            .is_empty());
+    }
+
+    // A reader which went away, like `head` once it has its lines
+    struct ClosedPipe;
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn a_closed_pipe_stops_the_ids_as_an_io_error() {
+        let pool = fixture().await;
+
+        let err = write_ids(&pool, "jane@example.com", None, None, &mut ClosedPipe)
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err.downcast_ref::<io::Error>().map(io::Error::kind),
+            Some(io::ErrorKind::BrokenPipe)
+        );
+    }
+
+    #[test]
+    fn senders_are_normalized_like_a_from_header() {
+        assert_eq!(
+            normalize(" Jane Doe <Jane@Example.COM> "),
+            "jane@example.com"
+        );
+        assert_eq!(normalize("jane@example.com"), "jane@example.com");
+    }
+}
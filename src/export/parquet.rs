@@ -0,0 +1,340 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::basic::Compression;
+use ::parquet::file::properties::WriterProperties;
+use arrow_array::builder::{
+    ArrayBuilder, BooleanBuilder, Int64Builder, StringBuilder, TimestampMillisecondBuilder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use futures::TryStreamExt;
+use sqlx::sqlite::SqliteRow;
+use sqlx::{Pool, Row, Sqlite};
+
+use super::{select, ColumnType};
+use crate::anonymize::Anonymizer;
+
+/// Rows per record batch and per Parquet row group, which bounds how much of a table is held in
+/// memory at once.
+const BATCH_ROWS: usize = 65_536;
+
+pub fn schema(columns: &[(&str, ColumnType)]) -> Schema {
+    Schema::new(
+        columns
+            .iter()
+            .map(|(name, column_type)| {
+                let data_type = match column_type {
+                    ColumnType::Text | ColumnType::Address | ColumnType::Personal => DataType::Utf8,
+                    ColumnType::Int => DataType::Int64,
+                    ColumnType::Bool => DataType::Boolean,
+                    ColumnType::Timestamp => {
+                        DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()))
+                    }
+                };
+                Field::new(*name, data_type, true)
+            })
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn builder(column_type: ColumnType) -> Box<dyn ArrayBuilder> {
+    match column_type {
+        ColumnType::Text | ColumnType::Address | ColumnType::Personal => {
+            Box::new(StringBuilder::new())
+        }
+        ColumnType::Int => Box::new(Int64Builder::new()),
+        ColumnType::Bool => Box::new(BooleanBuilder::new()),
+        ColumnType::Timestamp => Box::new(TimestampMillisecondBuilder::new().with_timezone("UTC")),
+    }
+}
+
+// Append column `index` of `row` to `builder`, which was made by `builder(column_type)`
+fn append(
+    builder: &mut dyn ArrayBuilder,
+    column_type: ColumnType,
+    row: &SqliteRow,
+    index: usize,
+    anonymizer: Option<&Anonymizer>,
+) -> anyhow::Result<()> {
+    let any = builder.as_any_mut();
+    match column_type {
+        ColumnType::Text | ColumnType::Address | ColumnType::Personal => {
+            let mut value = row.try_get::<Option<String>, _>(index)?;
+            if let Some(anonymizer) = anonymizer {
+                value = match column_type {
+                    ColumnType::Address => value.map(|address| anonymizer.address(&address)),
+                    ColumnType::Personal => None,
+                    _ => value,
+                };
+            }
+            any.downcast_mut::<StringBuilder>()
+                .expect("text builder")
+                .append_option(value)
+        }
+        ColumnType::Int => any
+            .downcast_mut::<Int64Builder>()
+            .expect("int builder")
+            .append_option(row.try_get::<Option<i64>, _>(index)?),
+        ColumnType::Bool => any
+            .downcast_mut::<BooleanBuilder>()
+            .expect("bool builder")
+            .append_option(row.try_get::<Option<bool>, _>(index)?),
+        ColumnType::Timestamp => any
+            .downcast_mut::<TimestampMillisecondBuilder>()
+            .expect("timestamp builder")
+            .append_option(row.try_get::<Option<i64>, _>(index)?),
+    }
+    Ok(())
+}
+
+/// Write `columns` of `table` to a Parquet file at `out`, a batch at a time, anonymizing addresses
+/// if given an `anonymizer`. Returns the number of rows written.
+pub async fn write_parquet(
+    pool: &Pool<Sqlite>,
+    table: &str,
+    columns: &[(&str, ColumnType)],
+    out: &Path,
+    anonymizer: Option<&Anonymizer>,
+) -> anyhow::Result<u64> {
+    let schema = Arc::new(schema(columns));
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_max_row_group_row_count(Some(BATCH_ROWS))
+        .build();
+    let mut writer = ArrowWriter::try_new(File::create(out)?, schema.clone(), Some(properties))?;
+
+    let query = select(columns, table, "rowid");
+    let mut rows = sqlx::query(&query).fetch(pool);
+
+    // Finishing a builder empties it, ready for the next batch
+    let mut builders = columns
+        .iter()
+        .map(|(_, column_type)| builder(*column_type))
+        .collect::<Vec<_>>();
+    let mut buffered = 0;
+    let mut written = 0;
+    while let Some(row) = rows.try_next().await? {
+        for (index, ((_, column_type), builder)) in columns.iter().zip(&mut builders).enumerate() {
+            append(builder.as_mut(), *column_type, &row, index, anonymizer)?;
+        }
+        buffered += 1;
+        if buffered == BATCH_ROWS {
+            let arrays = builders
+                .iter_mut()
+                .map(|b| b.finish())
+                .collect::<Vec<ArrayRef>>();
+            writer.write(&RecordBatch::try_new(schema.clone(), arrays)?)?;
+            written += buffered as u64;
+            buffered = 0;
+        }
+    }
+    if buffered > 0 {
+        let arrays = builders
+            .iter_mut()
+            .map(|b| b.finish())
+            .collect::<Vec<ArrayRef>>();
+        writer.write(&RecordBatch::try_new(schema.clone(), arrays)?)?;
+        written += buffered as u64;
+    }
+    writer.close()?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Int64Type, TimestampMillisecondType};
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::analyze::SenderCounts;
+    use crate::db::{self, DEFAULT_ACCOUNT};
+    use crate::export::{MESSAGE_COLUMNS, SENDER_COLUMNS};
+    use crate::parse::{MessageInfo, ParseOptions};
+    use crate::testsupport::{self, message};
+
+    // Two messages from Jane, one with a numeric id, and one from Bob in spam
+    async fn fixture() -> Pool<Sqlite> {
+        let pool = testsupport::pool().await;
+        let mut conn = pool.acquire().await.unwrap();
+        for (id, from, date, labels) in [
+            ("m1", "Jane Doe <jane@example.com>", 1_000, &["INBOX"][..]),
+            ("1234", "jane@example.com", 2_000, &["INBOX", "UNREAD"]),
+            ("m3", "bob@example.org", 3_000, &["SPAM"]),
+        ] {
+            let mut message = message(id, &[("From", from), ("Subject", "Hello")], labels);
+            message.internal_date = Some(date.to_string());
+            let info = MessageInfo::from_message(&message, &ParseOptions::default()).unwrap();
+            db::record_message(
+                &info,
+                DEFAULT_ACCOUNT,
+                None,
+                DateTime::UNIX_EPOCH,
+                &mut conn,
+            )
+            .await
+            .unwrap();
+            let mut counts = SenderCounts::default();
+            counts.add(&info);
+            db::add_sender_counts(&info.sender.sender, &counts, &mut conn)
+                .await
+                .unwrap();
+        }
+        drop(conn);
+        pool
+    }
+
+    fn path(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("gmail-stats-parquet-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(name)
+    }
+
+    // The file at `path`, which the fixture's few rows fit in one batch of
+    fn read(path: &Path) -> RecordBatch {
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches.len(), 1);
+        batches.remove(0)
+    }
+
+    fn text(batch: &RecordBatch, column: &str) -> Vec<Option<String>> {
+        batch
+            .column_by_name(column)
+            .unwrap()
+            .as_string::<i32>()
+            .iter()
+            .map(|value| value.map(str::to_string))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn messages_come_back_as_they_were_stored() {
+        let pool = fixture().await;
+        let out = path("messages.parquet");
+
+        let rows = write_parquet(&pool, "messages", &MESSAGE_COLUMNS, &out, None)
+            .await
+            .unwrap();
+
+        assert_eq!(rows, 3);
+        let batch = read(&out);
+        assert_eq!(batch.schema().as_ref(), &schema(&MESSAGE_COLUMNS));
+        assert_eq!(
+            text(&batch, "mail_id"),
+            [Some("m1".into()), Some("1234".into()), Some("m3".into())]
+        );
+        assert_eq!(
+            text(&batch, "sender"),
+            [
+                Some("jane@example.com".into()),
+                Some("jane@example.com".into()),
+                Some("bob@example.org".into())
+            ]
+        );
+        assert_eq!(text(&batch, "subject")[0].as_deref(), Some("Hello"));
+        let dates = batch
+            .column_by_name("internal_date")
+            .unwrap()
+            .as_primitive::<TimestampMillisecondType>();
+        assert_eq!(dates.values().to_vec(), [1_000, 2_000, 3_000]);
+        let spam = batch.column_by_name("is_spam").unwrap().as_boolean();
+        assert_eq!(
+            spam.iter().collect::<Vec<_>>(),
+            [Some(false), Some(false), Some(true)]
+        );
+        assert!(batch.column_by_name("original_sender").unwrap().is_null(0));
+    }
+
+    #[tokio::test]
+    async fn anonymized_senders_keep_their_counts_and_lose_their_names() {
+        let pool = fixture().await;
+        let anonymizer = Anonymizer::new(b"key", true);
+        let messages = path("anonymized-messages.parquet");
+        let senders = path("anonymized-senders.parquet");
+
+        write_parquet(
+            &pool,
+            "messages",
+            &MESSAGE_COLUMNS,
+            &messages,
+            Some(&anonymizer),
+        )
+        .await
+        .unwrap();
+        write_parquet(
+            &pool,
+            "senders",
+            &SENDER_COLUMNS,
+            &senders,
+            Some(&anonymizer),
+        )
+        .await
+        .unwrap();
+
+        let messages = read(&messages);
+        let jane = Some(anonymizer.address("jane@example.com"));
+        assert_eq!(text(&messages, "sender")[..2], [jane.clone(), jane.clone()]);
+        assert!(text(&messages, "subject").iter().all(Option::is_none));
+        assert!(text(&messages, "sender_name").iter().all(Option::is_none));
+        let senders = read(&senders);
+        let names = text(&senders, "sender");
+        let mails = senders
+            .column_by_name("mails_sent")
+            .unwrap()
+            .as_primitive::<Int64Type>();
+        let at = names.iter().position(|name| *name == jane).unwrap();
+        assert_eq!(mails.value(at), 2);
+
+        // Nothing anywhere in either file gives an address away
+        for batch in [&messages, &senders] {
+            for field in batch.schema().fields() {
+                if field.data_type() != &DataType::Utf8 {
+                    continue;
+                }
+                for value in text(batch, field.name()).into_iter().flatten() {
+                    assert!(
+                        !["jane", "bob", "example"]
+                            .iter()
+                            .any(|raw| value.contains(raw)),
+                        "{} = {:?}",
+                        field.name(),
+                        value
+                    );
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_chosen_columns_are_written() {
+        let pool = fixture().await;
+        let out = path("columns.parquet");
+        let columns = crate::export::select_columns(
+            &MESSAGE_COLUMNS,
+            &["size_estimate".to_string(), "MAIL_ID".to_string()],
+        )
+        .unwrap();
+
+        write_parquet(&pool, "messages", &columns, &out, None)
+            .await
+            .unwrap();
+
+        let batch = read(&out);
+        let names = batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["size_estimate", "mail_id"]);
+        assert_eq!(batch.num_rows(), 3);
+    }
+}
@@ -0,0 +1,343 @@
+use std::path::Path;
+
+use chrono_tz::Tz;
+use futures::TryStreamExt;
+use rust_xlsxwriter::{Format, Workbook, Worksheet};
+use sqlx::{Pool, Row, Sqlite};
+
+use super::{select, ColumnType, SENDER_COLUMNS};
+use crate::anonymize::Anonymizer;
+use crate::dates::Granularity;
+use crate::report::pattern::SenderScope;
+use crate::report::{classes, trend};
+
+/// Rows in a worksheet, the header included.
+const MAX_ROWS: u32 = 1_048_576;
+
+/// Excel stores dates as days since 1899-12-30, which is this many days before the Unix epoch.
+const EXCEL_EPOCH_OFFSET_DAYS: f64 = 25_569.0;
+const MILLIS_PER_DAY: f64 = 86_400_000.0;
+
+/// Columns of the runs table, in export order.
+const RUN_COLUMNS: [(&str, ColumnType); 6] = [
+    ("id", ColumnType::Int),
+    ("started_at", ColumnType::Timestamp),
+    ("finished_at", ColumnType::Timestamp),
+    ("status", ColumnType::Text),
+    ("messages", ColumnType::Int),
+    ("ignored", ColumnType::Int),
+];
+
+struct Formats {
+    header: Format,
+    count: Format,
+    date: Format,
+}
+
+impl Formats {
+    fn new() -> Formats {
+        Formats {
+            header: Format::new().set_bold(),
+            count: Format::new().set_num_format("#,##0"),
+            date: Format::new().set_num_format("yyyy-mm-dd hh:mm"),
+        }
+    }
+}
+
+// A bold header row which stays put while scrolling
+fn header(sheet: &mut Worksheet, names: &[&str], formats: &Formats) -> anyhow::Result<()> {
+    for (col, name) in names.iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, *name, &formats.header)?;
+    }
+    sheet.set_freeze_panes(1, 0)?;
+    Ok(())
+}
+
+// Strings are always written as text cells, never as formulas, so a sender like
+// `=HYPERLINK(…)` shows up as typed rather than being evaluated
+fn text(sheet: &mut Worksheet, row: u32, col: u16, value: &str) -> anyhow::Result<()> {
+    sheet.write_string(row, col, value)?;
+    Ok(())
+}
+
+fn count(
+    sheet: &mut Worksheet,
+    row: u32,
+    col: u16,
+    value: i64,
+    formats: &Formats,
+) -> anyhow::Result<()> {
+    sheet.write_number_with_format(row, col, value as f64, &formats.count)?;
+    Ok(())
+}
+
+// Rows of `query` written under a header of `columns`, one row per result
+async fn write_query(
+    sheet: &mut Worksheet,
+    pool: &Pool<Sqlite>,
+    query: &str,
+    columns: &[(&str, ColumnType)],
+    formats: &Formats,
+    anonymizer: Option<&Anonymizer>,
+) -> anyhow::Result<()> {
+    let names = columns.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+    header(sheet, &names, formats)?;
+
+    let mut rows = sqlx::query(query).fetch(pool);
+    let mut row_number = 0;
+    while let Some(row) = rows.try_next().await? {
+        row_number += 1;
+        if row_number >= MAX_ROWS {
+            anyhow::bail!("too many rows for one worksheet");
+        }
+        for (index, (_, column_type)) in columns.iter().enumerate() {
+            let col = index as u16;
+            match column_type {
+                ColumnType::Text | ColumnType::Address | ColumnType::Personal => {
+                    let value = match (column_type, anonymizer) {
+                        (ColumnType::Personal, Some(_)) => None,
+                        (ColumnType::Address, Some(anonymizer)) => row
+                            .try_get::<Option<String>, _>(index)?
+                            .map(|address| anonymizer.address(&address)),
+                        _ => row.try_get::<Option<String>, _>(index)?,
+                    };
+                    if let Some(value) = value {
+                        text(sheet, row_number, col, &value)?;
+                    }
+                }
+                ColumnType::Int => {
+                    if let Some(value) = row.try_get::<Option<i64>, _>(index)? {
+                        count(sheet, row_number, col, value, formats)?;
+                    }
+                }
+                ColumnType::Bool => {
+                    if let Some(value) = row.try_get::<Option<bool>, _>(index)? {
+                        sheet.write_boolean(row_number, col, value)?;
+                    }
+                }
+                ColumnType::Timestamp => {
+                    if let Some(millis) = row.try_get::<Option<i64>, _>(index)? {
+                        let days = millis as f64 / MILLIS_PER_DAY + EXCEL_EPOCH_OFFSET_DAYS;
+                        sheet.write_number_with_format(row_number, col, days, &formats.date)?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write a workbook with sheets of senders, domains, messages per month and fetch runs. Dates
+/// are in UTC.
+pub async fn write(
+    pool: &Pool<Sqlite>,
+    out: &Path,
+    anonymizer: Option<&Anonymizer>,
+) -> anyhow::Result<()> {
+    let formats = Formats::new();
+    let mut workbook = Workbook::new();
+
+    let sheet = workbook.add_worksheet().set_name("senders")?;
+    let query = select(&SENDER_COLUMNS, "senders", "mails_sent DESC, sender");
+    write_query(sheet, pool, &query, &SENDER_COLUMNS, &formats, anonymizer).await?;
+    sheet.autofit();
+
+    let sheet = workbook.add_worksheet().set_name("domains")?;
+    header(
+        sheet,
+        &["domain", "class", "senders", "messages", "bytes"],
+        &formats,
+    )?;
+    for (i, domain) in classes::domain_volumes(pool, &SenderScope::default())
+        .await?
+        .iter()
+        .enumerate()
+    {
+        let row = i as u32 + 1;
+        let name = match anonymizer {
+            Some(anonymizer) => anonymizer.domain(&domain.domain),
+            None => domain.domain.clone(),
+        };
+        text(sheet, row, 0, &name)?;
+        text(sheet, row, 1, &domain.class)?;
+        count(sheet, row, 2, domain.senders, &formats)?;
+        count(sheet, row, 3, domain.messages, &formats)?;
+        count(sheet, row, 4, domain.bytes, &formats)?;
+    }
+    sheet.autofit();
+
+    let sheet = workbook.add_worksheet().set_name("trend")?;
+    header(sheet, &["month", "messages"], &formats)?;
+    let dates = trend::message_dates(pool, &trend::Filter::default()).await?;
+    let buckets = trend::buckets(&dates, Granularity::Month, Tz::UTC, None, None);
+    for (i, bucket) in buckets.iter().enumerate() {
+        let row = i as u32 + 1;
+        text(sheet, row, 0, &bucket.label)?;
+        count(sheet, row, 1, bucket.count, &formats)?;
+    }
+    sheet.autofit();
+
+    let sheet = workbook.add_worksheet().set_name("runs")?;
+    let query = select(&RUN_COLUMNS, "runs", "id");
+    write_query(sheet, pool, &query, &RUN_COLUMNS, &formats, None).await?;
+    sheet.autofit();
+
+    workbook.save(out)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs::File;
+    use std::io::Read;
+
+    use regex::Regex;
+    use sqlx::Executor;
+    use zip::ZipArchive;
+
+    use super::*;
+    use crate::testsupport;
+
+    // Jane, a sender who looks like a formula and a freemail domain, with mail in January and
+    // March 2024, and one fetch
+    const FIXTURE: &str = r#"
+        INSERT INTO senders (sender, mails_sent, bytes, first_seen) VALUES
+            ('jane@example.com', 5, 5000, 86400000), ('=HYPERLINK("x")@mail.example', 3, 300, 0);
+        INSERT INTO domains (domain, class) VALUES ('example.com', 'direct');
+        INSERT INTO messages (account, mail_id, sender, envelope_sender, internal_date) VALUES
+            ('default', 'm1', 'jane@example.com', 'jane@example.com', 1704153600000),
+            ('default', 'm2', 'jane@example.com', 'jane@example.com', 1709510400000),
+            ('default', 'm3', 'jane@example.com', 'jane@example.com', 1709596800000);
+        INSERT INTO runs (started_at, finished_at, status, messages) VALUES
+            (1709596800000, 1709596860000, 'complete', 3);"#;
+
+    // The workbook at `path`, read back as each sheet's name and its cells by reference (`B2`).
+    // Shared strings are looked up, and every other value is as stored
+    fn read(path: &Path) -> Vec<(String, BTreeMap<String, String>)> {
+        let mut archive = ZipArchive::new(File::open(path).unwrap()).unwrap();
+        let mut part = |name: &str| {
+            let mut xml = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut xml)
+                .unwrap();
+            xml
+        };
+        let unescape = |text: &str| {
+            text.replace("&quot;", "\"")
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&amp;", "&")
+        };
+        let strings = Regex::new(r"<t[^>]*>([^<]*)</t>")
+            .unwrap()
+            .captures_iter(&part("xl/sharedStrings.xml"))
+            .map(|string| unescape(&string[1]))
+            .collect::<Vec<_>>();
+        let names = Regex::new(r#"<sheet name="([^"]+)""#)
+            .unwrap()
+            .captures_iter(&part("xl/workbook.xml"))
+            .map(|sheet| sheet[1].to_string())
+            .collect::<Vec<_>>();
+        let cell =
+            Regex::new(r#"<c r="([A-Z]+[0-9]+)"([^>]*)>(<f>)?(?:<v>([^<]*)</v>)?</c>"#).unwrap();
+        names
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let xml = part(&format!("xl/worksheets/sheet{}.xml", i + 1));
+                let cells = cell
+                    .captures_iter(&xml)
+                    .map(|c| {
+                        assert!(c.get(3).is_none(), "formula in {}", &c[1]);
+                        let value = c.get(4).map_or("", |v| v.as_str());
+                        let value = match c[2].contains(r#"t="s""#) {
+                            true => strings[value.parse::<usize>().unwrap()].clone(),
+                            false => value.to_string(),
+                        };
+                        (c[1].to_string(), value)
+                    })
+                    .collect();
+                (name, cells)
+            })
+            .collect()
+    }
+
+    fn at<'a>(sheet: &'a BTreeMap<String, String>, cell: &str) -> &'a str {
+        sheet.get(cell).map_or("", String::as_str)
+    }
+
+    async fn workbook(
+        name: &str,
+        anonymizer: Option<&Anonymizer>,
+    ) -> Vec<(String, BTreeMap<String, String>)> {
+        let pool = testsupport::pool().await;
+        pool.execute(FIXTURE).await.unwrap();
+        let dir = std::env::temp_dir().join(format!("gmail-stats-xlsx-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        write(&pool, &path, anonymizer).await.unwrap();
+        read(&path)
+    }
+
+    #[tokio::test]
+    async fn the_workbook_reads_back() {
+        let sheets = workbook("stats.xlsx", None).await;
+
+        let names = sheets
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["senders", "domains", "trend", "runs"]);
+
+        let senders = &sheets[0].1;
+        assert_eq!(
+            ["A1", "B1", "I1", "L1"].map(|cell| at(senders, cell)),
+            ["sender", "mails_sent", "first_seen", "is_ignored"]
+        );
+        assert_eq!(
+            ["A2", "B2", "H2", "I2", "L2"].map(|cell| at(senders, cell)),
+            ["jane@example.com", "5", "5000", "25570", "0"]
+        );
+        // Text which looks like a formula stays text
+        assert_eq!(at(senders, "A3"), "=HYPERLINK(\"x\")@mail.example");
+
+        let domains = &sheets[1].1;
+        assert_eq!(
+            ["A2", "B2", "C2", "D2", "E2"].map(|cell| at(domains, cell)),
+            ["example.com", "direct", "1", "5", "5000"]
+        );
+
+        let trend = &sheets[2].1;
+        assert_eq!(
+            ["A2", "B2", "A3", "B3", "A4", "B4"].map(|cell| at(trend, cell)),
+            ["2024-01", "1", "2024-02", "0", "2024-03", "2"]
+        );
+
+        let runs = &sheets[3].1;
+        // Dates are days since 1899-12-30
+        assert_eq!(
+            ["A2", "B2", "D2", "E2"].map(|cell| at(runs, cell)),
+            ["1", "45356", "complete", "3"]
+        );
+    }
+
+    #[tokio::test]
+    async fn anonymized_workbooks_hold_no_addresses() {
+        let anonymizer = Anonymizer::new(b"key", true);
+        let sheets = workbook("anonymized.xlsx", Some(&anonymizer)).await;
+
+        assert_eq!(
+            at(&sheets[0].1, "A2"),
+            anonymizer.address("jane@example.com")
+        );
+        assert_eq!(at(&sheets[1].1, "A2"), anonymizer.domain("example.com"));
+        for (name, cells) in &sheets {
+            for value in cells.values() {
+                assert!(!value.contains("example"), "{}: {}", name, value);
+            }
+        }
+    }
+}
@@ -0,0 +1,944 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use sqlx::{Pool, Sqlite, SqliteConnection};
+use tokio::sync::mpsc;
+
+use crate::analyze::SenderCounts;
+use crate::cli::FetchArgs;
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::db;
+use crate::emit::Emitter;
+use crate::error::GmailStatsError;
+use crate::forget::{self, Tombstones};
+use crate::format;
+use crate::ignore::IgnoreList;
+use crate::interrupt::CancelToken;
+use crate::metrics::{self, FetchStats, Phase};
+use crate::own::Identity;
+use crate::parse::{Category, MessageInfo, ParseOptions, SENT};
+use crate::source::{Cursor, MailSource, MessagePage};
+use crate::summary::Summary;
+use crate::trace::Trace;
+
+/// Attempts in a row which can fail with a transient error before the fetch gives up, unless
+/// `--max-retries` says otherwise.
+pub const MAX_RETRIES: u32 = 5;
+
+/// Backoff before the first retry, doubled for each one after.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+
+/// The longest backoff, however many retries there have been.
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// How long to wait before each retry after a transient error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    /// Before the first retry, doubled for each one after.
+    pub initial: Duration,
+    /// The longest wait, however many retries there have been.
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff {
+            initial: INITIAL_BACKOFF,
+            max: MAX_BACKOFF,
+        }
+    }
+}
+
+impl Backoff {
+    /// How long to wait before retrying after `err`, the `retries`th transient error in a row:
+    /// however long Gmail asked for, or doubling from `initial` up to `max`.
+    pub fn wait(&self, err: &GmailStatsError, retries: u32) -> Duration {
+        match err {
+            GmailStatsError::RateLimited {
+                retry_after: Some(retry_after),
+            } => *retry_after,
+            // Past 2^16 the wait is long since at the cap, and the doubling can't overflow
+            _ => self
+                .initial
+                .saturating_mul(2u32.pow(retries.min(16)))
+                .min(self.max),
+        }
+    }
+}
+
+/// Most messages the writer records in one transaction.
+pub const WRITE_BATCH: usize = 50;
+
+/// Fetched messages which can wait for the writer before fetching pauses: the next batch ready
+/// while one commits, and no more.
+pub const QUEUE_CAPACITY: usize = 2 * WRITE_BATCH;
+
+/// How far the messages seen can be from the mailbox's own count, as a share of it, before
+/// the difference is worth explaining.
+pub const TOTAL_TOLERANCE: f64 = 0.01;
+
+/// The messages a complete run has seen, against how many the mailbox says it holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TotalCheck {
+    pub seen: u64,
+    pub total: u64,
+    /// Messages waiting in the errors table, which aren't marked seen.
+    pub errored: u64,
+}
+
+impl TotalCheck {
+    /// Messages the mailbox has which haven't been seen, negative if more have been seen.
+    pub fn missing(&self) -> i64 {
+        self.total as i64 - self.seen as i64
+    }
+
+    /// Whether the difference is more than `TOTAL_TOLERANCE` of the total.
+    pub fn is_off(&self) -> bool {
+        self.missing().unsigned_abs() as f64 > self.total as f64 * TOTAL_TOLERANCE
+    }
+
+    /// The likely reasons for a difference, most likely first.
+    pub fn explanations(&self, include_spam_trash: bool) -> Vec<&'static str> {
+        let mut reasons = Vec::new();
+        if self.missing() < 0 {
+            reasons.push("mail deleted from Gmail since it was fetched stays counted here");
+            return reasons;
+        }
+        if self.errored > 0 {
+            reasons.push(
+                "messages which failed are left unseen, `fetch --retry-errors` tries them again",
+            );
+        }
+        if !include_spam_trash {
+            reasons.push("spam and trash aren't listed without --include-spam-trash");
+        }
+        reasons.push("Gmail counts chats and drafts, which aren't listed as mail");
+        reasons
+    }
+
+    /// A line for the end of the run, with the reasons when the difference is large.
+    pub fn describe(&self, include_spam_trash: bool) -> String {
+        let mut line = format!(
+            "seen {} of the {} messages Gmail counts",
+            format::thousands(self.seen as i64),
+            format::thousands(self.total as i64)
+        );
+        if self.missing() != 0 {
+            line += &format!(", {} off", format::thousands(self.missing().abs()));
+        }
+        if self.is_off() {
+            for reason in self.explanations(include_spam_trash) {
+                line += &format!("\n  {}", reason);
+            }
+        }
+        line
+    }
+}
+
+/// Options controlling which messages are fetched and how they're parsed.
+#[derive(Clone, Debug, Default)]
+pub struct FetchOptions {
+    pub parse: ParseOptions,
+    /// Only fetch mail Gmail filed under this category.
+    pub category: Option<Category>,
+    /// Also list mail in spam and trash.
+    pub include_spam_trash: bool,
+    /// Senders whose mail is marked seen without being counted.
+    pub ignore: IgnoreList,
+    /// Senders erased with `forget`, whose mail is marked seen without being recorded. Set by
+    /// `run()`.
+    pub erased: Tombstones,
+    /// My own addresses, whose mail isn't counted as received. Empty with `--include-self`.
+    pub own: Identity,
+    /// The Gmail account fetched, `--profile`.
+    pub account: Option<String>,
+    /// The `runs` row messages are recorded against, set by `run()`.
+    pub run_id: Option<i64>,
+    /// Where to stream a JSON line per fetched message, `-` meaning stdout.
+    pub emit_jsonl: Option<PathBuf>,
+    /// Where to write Prometheus metrics for the run once it finishes.
+    pub metrics_out: Option<PathBuf>,
+    /// Stops the fetch between messages when cancelled.
+    pub cancel: CancelToken,
+    /// Transient errors in a row the fetch retries before giving up.
+    pub max_retries: u32,
+    pub backoff: Backoff,
+    /// Fetch only the messages in the errors table rather than listing the mailbox.
+    pub retry_errors: bool,
+    /// Compare the messages seen with the mailbox's own count after a complete run.
+    pub check_total: bool,
+    /// Print where each page and the run spent their time.
+    pub timing: bool,
+    /// Leave out progress and notes, printing only warnings, for `--quiet-unless-changed`.
+    pub quiet: bool,
+    /// Where to write the run's spans as a Chrome trace.
+    pub trace_out: Option<PathBuf>,
+    pub clock: Clock,
+}
+
+impl FetchOptions {
+    pub fn new(args: &FetchArgs, config: &Config) -> FetchOptions {
+        FetchOptions {
+            parse: ParseOptions {
+                forwarders: if args.unwrap_forwarded {
+                    config.forwarders.clone()
+                } else {
+                    Vec::new()
+                },
+                exclude_inline: args.exclude_inline,
+            },
+            category: args.category,
+            include_spam_trash: args.include_spam_trash,
+            ignore: IgnoreList::new(&config.ignore),
+            erased: Tombstones::default(),
+            own: if args.include_self {
+                Identity::default()
+            } else {
+                config.identity.clone()
+            },
+            account: args.profile.clone(),
+            run_id: None,
+            emit_jsonl: args.emit_jsonl.clone(),
+            metrics_out: args.metrics_out.clone(),
+            cancel: CancelToken::default(),
+            max_retries: args.max_retries.unwrap_or(MAX_RETRIES),
+            backoff: Backoff::default(),
+            retry_errors: args.retry_errors,
+            check_total: !args.no_total_check,
+            timing: args.timing,
+            quiet: args.quiet_unless_changed,
+            trace_out: args.trace_out.clone(),
+            clock: args.clock,
+        }
+    }
+
+    /// The account mail is recorded under, `db::DEFAULT_ACCOUNT` without a profile.
+    pub fn account(&self) -> &str {
+        self.account.as_deref().unwrap_or(db::DEFAULT_ACCOUNT)
+    }
+
+    /// The label each listing pass is restricted to, `None` meaning all mail. Restricting to a
+    /// category would leave out my sent mail, so it gets its own pass for the engagement reports.
+    pub fn listings(&self) -> Vec<Option<&'static str>> {
+        match self.category {
+            Some(category) => vec![Some(category.label_id()), Some(SENT)],
+            None => vec![None],
+        }
+    }
+}
+
+pub async fn run(
+    pool: &Pool<Sqlite>,
+    source: &impl MailSource,
+    opts: &FetchOptions,
+) -> Result<Summary, GmailStatsError> {
+    let started = Instant::now();
+    let mut stats = FetchStats {
+        trace: opts.trace_out.as_ref().map(|_| Trace::new()),
+        ..Default::default()
+    };
+    let mut emitter = Emitter::open(opts.emit_jsonl.as_deref()).map_err(|err| {
+        GmailStatsError::Config(format!("couldn't open --emit-jsonl output: {}", err))
+    })?;
+    let size_before = db::size(pool).await?;
+    // Page tokens only carry on a listing made the same way, and a run over another category
+    // would skip this one's if it carried on in the sent mail
+    let listing = source.listing_id().map(|id| {
+        let mut listing = id;
+        if opts.include_spam_trash {
+            listing.push_str(" with spam and trash");
+        }
+        if let Some(category) = opts.category {
+            listing.push_str(&format!(" in {}", category.label_id()));
+        }
+        listing
+    });
+    let mut position = match &listing {
+        Some(listing) if !opts.retry_errors => resume_from(listing, pool, opts).await?,
+        _ => Cursor::default(),
+    };
+    let run_id = db::start_run(opts.account(), opts.clock.now(), pool).await?;
+    let opts = &FetchOptions {
+        run_id: Some(run_id),
+        erased: forget::tombstones(pool).await?,
+        ..opts.clone()
+    };
+
+    // A message which fails is retried where it is, and any other error lists the page it was on
+    // again, skipping mail already seen. Only failures in a row count towards the limit, so a long
+    // fetch isn't stopped by the odd lock now and then.
+    let mut quota_exhausted = false;
+    let interrupted = loop {
+        let err = match work(pool, source, opts, &mut emitter, &mut stats, &mut position).await {
+            Ok(interrupted) => break interrupted,
+            Err(err) => err,
+        };
+
+        stats.record_error(&err);
+        // Nothing gets through until tomorrow, so the run stops where it got to, as if cancelled
+        if let GmailStatsError::QuotaExhausted = err {
+            eprintln!("{}, stopping", err);
+            if let Some(hint) = err.hint() {
+                eprintln!("{}", hint);
+            }
+            quota_exhausted = true;
+            break true;
+        }
+        if !err.is_transient() {
+            if let Some(hint) = err.hint() {
+                eprintln!("{}", hint);
+            }
+            return Err(err);
+        }
+        if stats.failures_in_a_row >= opts.max_retries {
+            eprintln!(
+                "giving up after {} retries in a row",
+                stats.failures_in_a_row
+            );
+            return Err(err);
+        }
+        wait_to_retry(&err, opts, &mut stats).await;
+    };
+
+    stats.bytes_received = source.bytes_received();
+    let why = if quota_exhausted {
+        Some("quota")
+    } else if opts.cancel.is_deadline() {
+        Some("deadline")
+    } else {
+        None
+    };
+    db::finish_run(run_id, interrupted, why, opts.clock.now(), pool).await?;
+    if let Some(listing) = listing
+        .as_deref()
+        .filter(|_| interrupted && !opts.retry_errors)
+    {
+        db::record_cursor(run_id, listing, &position, pool).await?;
+    }
+    if stats.skipped > 0 {
+        eprintln!("{} messages skipped, see `report errors`", stats.skipped);
+    }
+    if stats.duplicates > 0 && !opts.quiet {
+        eprintln!(
+            "{} messages already counted under another id, with the same Message-ID",
+            stats.duplicates
+        );
+    }
+    if stats.erased > 0 && !opts.quiet {
+        eprintln!("{} messages from erased senders not recorded", stats.erased);
+    }
+    if stats.self_excluded > 0 && !opts.quiet {
+        eprintln!(
+            "{} messages from my own addresses not counted, `--include-self` counts them",
+            stats.self_excluded
+        );
+    }
+    // Only a listing of the whole mailbox should come near its total
+    if opts.check_total && !interrupted && opts.category.is_none() && !opts.retry_errors {
+        // The fetch itself is done, so not getting the count only warns
+        match source.messages_total().await {
+            Ok(Some(total)) => {
+                let check = TotalCheck {
+                    seen: db::seen_count(opts.account(), pool).await?,
+                    total,
+                    errored: db::errored_ids(opts.account(), pool).await?.len() as u64,
+                };
+                eprintln!("{}", check.describe(opts.include_spam_trash));
+            }
+            Ok(None) => {}
+            Err(err) => eprintln!("couldn't get Gmail's count of the mailbox: {}", err),
+        }
+    }
+    // A cheap check in debug builds that no message was recorded without being counted
+    if cfg!(debug_assertions) {
+        let undercounted = db::undercounted_senders(pool).await?;
+        // My own sent mail is recorded but not counted
+        for (sender, counted, recorded) in undercounted
+            .iter()
+            .filter(|(sender, ..)| !opts.own.is_mine(sender))
+            .take(5)
+        {
+            eprintln!(
+                "warning: {} has {} messages recorded but a count of {}",
+                sender, recorded, counted
+            );
+        }
+    }
+    if opts.timing {
+        let run_total = stats.timing.run_total().as_secs_f64();
+        let received = stats
+            .bytes_received
+            .map(|bytes| format!(", {} received", format::bytes(bytes as i64)))
+            .unwrap_or_default();
+        eprintln!(
+            "{} pages in {:.3}s{}, queue peak {}/{}: {}",
+            stats.timing.pages(),
+            started.elapsed().as_secs_f64(),
+            received,
+            stats.queue_peak,
+            QUEUE_CAPACITY,
+            Phase::ALL
+                .iter()
+                .map(|phase| {
+                    let total = stats.timing.total(*phase).as_secs_f64();
+                    let share = if run_total > 0.0 {
+                        total / run_total * 100.0
+                    } else {
+                        0.0
+                    };
+                    format!("{} {:.3}s ({:.0}%)", phase.name(), total, share)
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    if let Some(path) = &opts.metrics_out {
+        let totals = metrics::totals(pool).await?;
+        let metrics = metrics::render(
+            &stats,
+            &totals,
+            started.elapsed(),
+            opts.clock.now().timestamp(),
+        );
+        metrics::write_atomically(path, &metrics).map_err(|err| {
+            GmailStatsError::Config(format!("couldn't write {}: {}", path.display(), err))
+        })?;
+    }
+
+    if let (Some(path), Some(trace)) = (&opts.trace_out, &stats.trace) {
+        trace.write(path).map_err(|err| {
+            GmailStatsError::Config(format!("couldn't write {}: {}", path.display(), err))
+        })?;
+    }
+
+    if interrupted {
+        return Err(if quota_exhausted {
+            GmailStatsError::QuotaExhausted
+        } else if opts.cancel.is_deadline() {
+            GmailStatsError::DeadlineReached
+        } else {
+            GmailStatsError::Interrupted
+        });
+    }
+    let size_delta = db::size(pool).await? - size_before;
+    Ok(Summary::new(&stats, started.elapsed(), size_delta))
+}
+
+/// How long to wait before retrying after `err`, the `retries`th transient error in a row, with
+/// the default backoff.
+pub fn backoff(err: &GmailStatsError, retries: u32) -> Duration {
+    Backoff::default().wait(err, retries)
+}
+
+// Wait out the backoff before retrying after a transient `err`, counting it towards the limit.
+// Cancelling cuts the wait short, and the next attempt stops straight away.
+async fn wait_to_retry(err: &GmailStatsError, opts: &FetchOptions, stats: &mut FetchStats) {
+    let backoff = opts.backoff.wait(err, stats.failures_in_a_row);
+    stats.failures_in_a_row += 1;
+    eprintln!(
+        "{}, retrying in {}s ({}/{})",
+        err,
+        backoff.as_secs(),
+        stats.failures_in_a_row,
+        opts.max_retries
+    );
+    tokio::select! {
+        _ = tokio::time::sleep(backoff) => {}
+        _ = opts.cancel.cancelled() => {}
+    }
+}
+
+// Where the last fetch of the account stopped, if it was interrupted going through `listing`
+async fn resume_from(
+    listing: &str,
+    pool: &Pool<Sqlite>,
+    opts: &FetchOptions,
+) -> Result<Cursor, GmailStatsError> {
+    let Some(cursor) = db::resume_cursor(opts.account(), listing, pool).await? else {
+        return Ok(Cursor::default());
+    };
+    if !opts
+        .listings()
+        .iter()
+        .any(|label| label.map(str::to_string) == cursor.label)
+    {
+        return Ok(Cursor::default());
+    }
+    if !opts.quiet && cursor != Cursor::default() {
+        eprintln!("carrying on from where the last fetch stopped");
+    }
+    Ok(cursor)
+}
+
+/// Work through the listings from `position`, keeping it on the page being worked through, and
+/// return whether it was cancelled first. A fetch which stopped carries on from that page,
+/// skipping the mail on it it has already seen.
+pub async fn work(
+    pool: &Pool<Sqlite>,
+    source: &impl MailSource,
+    opts: &FetchOptions,
+    emitter: &mut Emitter,
+    stats: &mut FetchStats,
+    position: &mut Cursor,
+) -> Result<bool, GmailStatsError> {
+    if opts.retry_errors {
+        let started = Instant::now();
+        let ids = db::errored_ids(opts.account(), pool).await?;
+        stats.time(Phase::Db, started);
+        parse_messages(pool, ids, 0, source, opts, emitter, stats).await?;
+        finish_page(opts, stats);
+        return Ok(opts.cancel.is_cancelled());
+    }
+
+    let listings = opts.listings();
+    let start = listings
+        .iter()
+        .position(|label| label.map(str::to_string) == position.label)
+        .unwrap_or(0);
+    for label in listings[start..].iter().copied() {
+        if position.label.as_deref() != label {
+            *position = Cursor {
+                label: label.map(str::to_string),
+                page_token: None,
+            };
+        }
+        if opts.cancel.is_cancelled() {
+            return Ok(true);
+        }
+        stats.list_calls += 1;
+        let started = Instant::now();
+        let mut page = first_page(source, label, opts, position).await?;
+        stats.time(Phase::List, started);
+        loop {
+            // The next page is listed while this one is worked through, so listing doesn't hold
+            // up processing. It's only looked at once this page is committed, so a failed listing
+            // never costs this page's work.
+            let next_token = page.next_page_token.take();
+            if next_token.is_some() {
+                stats.list_calls += 1;
+            }
+            let prefetch = async {
+                let token = next_token.as_deref()?;
+                let started = Instant::now();
+                let next = source
+                    .list_page(label, opts.include_spam_trash, Some(token))
+                    .await;
+                Some((next, started, started.elapsed()))
+            };
+            let (processed, next) = tokio::join!(
+                parse_messages(
+                    pool,
+                    page.ids,
+                    page.missing_ids,
+                    source,
+                    opts,
+                    emitter,
+                    stats
+                ),
+                prefetch
+            );
+            processed?;
+            finish_page(opts, stats);
+            // A page cut short is listed again when the fetch carries on, and so is a prefetched
+            // one
+            if opts.cancel.is_cancelled() {
+                return Ok(true);
+            }
+            position.page_token = next_token;
+
+            let Some((next, started, elapsed)) = next else {
+                break;
+            };
+            stats.time_span(Phase::List, started, elapsed);
+            page = next?;
+        }
+    }
+
+    Ok(false)
+}
+
+// List the page `position` is on. A saved token Gmail no longer takes, which it answers with a
+// 400, lists the label from the top instead.
+async fn first_page(
+    source: &impl MailSource,
+    label: Option<&str>,
+    opts: &FetchOptions,
+    position: &mut Cursor,
+) -> Result<MessagePage, GmailStatsError> {
+    let token = position.page_token.as_deref();
+    match source
+        .list_page(label, opts.include_spam_trash, token)
+        .await
+    {
+        Err(GmailStatsError::Api {
+            status: Some(400), ..
+        }) if token.is_some() => {
+            eprintln!("the page the last fetch stopped on has expired, listing from the top");
+            position.page_token = None;
+            source.list_page(label, opts.include_spam_trash, None).await
+        }
+        page => page,
+    }
+}
+
+fn finish_page(opts: &FetchOptions, stats: &mut FetchStats) {
+    let times = stats.timing.finish_page();
+    if opts.timing {
+        eprintln!(
+            "page {}: {}",
+            stats.timing.pages(),
+            metrics::breakdown(&times)
+        );
+    }
+}
+
+// Pass over a message which couldn't be fetched or parsed, rather than failing the whole fetch.
+// The caller counts it once the error row is committed.
+async fn skip(
+    id: Option<&str>,
+    err: &GmailStatsError,
+    opts: &FetchOptions,
+    conn: &mut SqliteConnection,
+) -> Result<(), GmailStatsError> {
+    eprintln!("skipping {}: {}", id.unwrap_or("a listed message"), err);
+    db::record_error(
+        id,
+        opts.run_id,
+        metrics::error_class(err),
+        &err.to_string(),
+        opts.clock.now(),
+        conn,
+    )
+    .await
+}
+
+// A listed message as the writer needs it: parsed, or passed over and why. A listing entry
+// without an id is passed over too.
+enum Fetched {
+    Message(Box<MessageInfo>),
+    Skipped(Option<String>, GmailStatsError),
+}
+
+// What a batch has written, or a page once its batches are committed: seen marks and
+// per-sender counts to flush in a few statements rather than some per message, and what to
+// count and emit once it's all in the database
+#[derive(Default)]
+struct Writes {
+    senders: HashMap<String, SenderCounts>,
+    /// Ids recorded in this batch, marked seen in bulk at the end of it.
+    seen: Vec<String>,
+    /// Those of them left without a row, and why.
+    unrecorded: Vec<(String, &'static str)>,
+    processed: u64,
+    new_senders: u64,
+    skipped: u64,
+    self_excluded: u64,
+    duplicates: u64,
+    erased: u64,
+    /// Kept only when there's somewhere to emit them.
+    to_emit: Vec<MessageInfo>,
+    /// When each batch's transaction started and how long it took.
+    spans: Vec<(Instant, Duration)>,
+}
+
+impl Writes {
+    // Add a committed batch's counts to the page's
+    fn add(&mut self, batch: Writes) {
+        self.processed += batch.processed;
+        self.new_senders += batch.new_senders;
+        self.skipped += batch.skipped;
+        self.self_excluded += batch.self_excluded;
+        self.duplicates += batch.duplicates;
+        self.erased += batch.erased;
+        self.to_emit.extend(batch.to_emit);
+        self.spans.extend(batch.spans);
+    }
+}
+
+async fn parse_messages(
+    pool: &Pool<Sqlite>,
+    ids: Vec<String>,
+    missing_ids: u64,
+    source: &impl MailSource,
+    opts: &FetchOptions,
+    emitter: &mut Emitter,
+    stats: &mut FetchStats,
+) -> Result<(), GmailStatsError> {
+    // Fetching and writing run side by side, joined by a bounded queue. The writer records a
+    // batch at a time, holding the write lock only while it does, and fetching pauses while the
+    // queue is full rather than parsed mail piling up behind a slow disk. When either side stops,
+    // on an error or a cancel, the other finishes what's queued: only a dropped future loses it.
+    let already_seen = stats.already_seen;
+    let (queue, fetched) = mpsc::channel(QUEUE_CAPACITY);
+    let mut written = Writes::default();
+    let (fetch, write) = tokio::join!(
+        fetch_messages(pool, ids, missing_ids, source, opts, queue, stats),
+        write_messages(pool, fetched, opts, emitter.is_open(), &mut written)
+    );
+    // Only counted and emitted once it's in the database: an error or a dropped future before
+    // a batch commits rolls it back, and its messages are fetched afresh next time
+    for (started, elapsed) in written.spans {
+        stats.time_span(Phase::Db, started, elapsed);
+    }
+    stats.processed += written.processed;
+    stats.new_senders += written.new_senders;
+    stats.skipped += written.skipped;
+    stats.self_excluded += written.self_excluded;
+    stats.duplicates += written.duplicates;
+    stats.erased += written.erased;
+    for info in &written.to_emit {
+        emitter.emit(info);
+    }
+    // Progress goes to stderr, leaving stdout for `--emit-jsonl -`
+    if !opts.quiet {
+        eprintln!(
+            "{} new messages, {} already seen, {} skipped",
+            written.processed,
+            stats.already_seen - already_seen,
+            written.skipped
+        );
+    }
+    write?;
+    fetch
+}
+
+// Fetch and parse the page's messages which haven't been seen, one at a time: spawning a task
+// per message deadlocked SQLite. A transient error is retried on the message in hand rather
+// than starting the page over.
+async fn fetch_messages(
+    pool: &Pool<Sqlite>,
+    ids: Vec<String>,
+    missing_ids: u64,
+    source: &impl MailSource,
+    opts: &FetchOptions,
+    queue: mpsc::Sender<Fetched>,
+    stats: &mut FetchStats,
+) -> Result<(), GmailStatsError> {
+    // Entries without an id are recorded with the page's messages, and counted once they commit
+    for _ in 0..missing_ids {
+        let err = GmailStatsError::Parse("a listing entry without an id".to_string());
+        if queue.send(Fetched::Skipped(None, err)).await.is_err() {
+            return Ok(());
+        }
+    }
+    // A listing can repeat an id, which isn't marked seen in the database until its batch commits
+    let mut taken = HashSet::new();
+    for id in ids {
+        if opts.cancel.is_cancelled() {
+            break;
+        }
+        let started = Instant::now();
+        let seen = taken.contains(&id) || db::seen_mail(&id, opts.account(), pool).await?;
+        stats.time(Phase::Db, started);
+        if seen {
+            stats.already_seen += 1;
+            continue;
+        }
+        let Some(message) = fetch_message(&id, source, opts, stats).await? else {
+            break;
+        };
+        taken.insert(id);
+        // The writer only stops taking messages on an error, which it returns itself
+        if queue.send(message).await.is_err() {
+            break;
+        }
+        stats.queue_peak = stats.queue_peak.max(QUEUE_CAPACITY - queue.capacity());
+    }
+    Ok(())
+}
+
+// Fetch and parse one message, retrying it while its errors are transient and within the limit.
+// `None` if the fetch was cancelled while waiting to retry.
+async fn fetch_message(
+    id: &str,
+    source: &impl MailSource,
+    opts: &FetchOptions,
+    stats: &mut FetchStats,
+) -> Result<Option<Fetched>, GmailStatsError> {
+    let mut message = loop {
+        stats.get_calls += 1;
+        let started = Instant::now();
+        let fetched = source.get_message(id).await;
+        stats.time(Phase::Fetch, started);
+        match fetched {
+            Ok(message) => break message,
+            // Gone since it was listed, an id Gmail won't take, or a message an mbox file has
+            // mangled: nothing to do with the others
+            Err(
+                err @ (GmailStatsError::Api {
+                    status: Some(400 | 404 | 410),
+                    ..
+                }
+                | GmailStatsError::Parse(_)),
+            ) => return Ok(Some(Fetched::Skipped(Some(id.to_string()), err))),
+            // The last retry's error goes back to `run()`, which counts it and gives up
+            Err(err) if err.is_transient() && stats.failures_in_a_row < opts.max_retries => {
+                stats.record_error(&err);
+                wait_to_retry(&err, opts, stats).await;
+                if opts.cancel.is_cancelled() {
+                    return Ok(None);
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    };
+    stats.failures_in_a_row = 0;
+    // The id we asked for will do if Gmail left it out
+    message.id.get_or_insert_with(|| id.to_string());
+
+    let started = Instant::now();
+    let parsed = MessageInfo::from_message(&message, &opts.parse);
+    stats.time(Phase::Parse, started);
+    match parsed {
+        Ok(info) => Ok(Some(Fetched::Message(Box::new(info)))),
+        Err(err @ GmailStatsError::Parse(_)) => {
+            Ok(Some(Fetched::Skipped(Some(id.to_string()), err)))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// Take fetched messages off the queue until the fetcher is done, writing a batch whenever one
+// fills and whatever's left at the end
+async fn write_messages(
+    pool: &Pool<Sqlite>,
+    mut fetched: mpsc::Receiver<Fetched>,
+    opts: &FetchOptions,
+    emitting: bool,
+    written: &mut Writes,
+) -> Result<(), GmailStatsError> {
+    let mut batch = Vec::with_capacity(WRITE_BATCH);
+    loop {
+        let next = fetched.recv().await;
+        let done = next.is_none();
+        batch.extend(next);
+        if batch.len() == WRITE_BATCH || (done && !batch.is_empty()) {
+            let writes = write_batch(pool, std::mem::take(&mut batch), opts, emitting).await?;
+            written.add(writes);
+        }
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+// Write a batch in one short transaction, so its seen marks and sender counts commit together
+async fn write_batch(
+    pool: &Pool<Sqlite>,
+    batch: Vec<Fetched>,
+    opts: &FetchOptions,
+    emitting: bool,
+) -> Result<Writes, GmailStatsError> {
+    let started = Instant::now();
+    let mut writes = Writes::default();
+    let mut tx = pool.begin().await?;
+    for item in batch {
+        match item {
+            Fetched::Message(info) => record(&mut tx, *info, opts, emitting, &mut writes).await?,
+            Fetched::Skipped(id, err) => {
+                skip(id.as_deref(), &err, opts, &mut tx).await?;
+                writes.skipped += 1;
+            }
+        }
+    }
+    db::mark_seen(&writes.seen, opts.account(), &mut tx).await?;
+    for (id, reason) in &writes.unrecorded {
+        db::mark_unrecorded(id, reason, opts.account(), &mut *tx).await?;
+    }
+    for (sender, counts) in &writes.senders {
+        if db::add_sender_counts(sender, counts, &mut tx).await? {
+            writes.new_senders += 1;
+        }
+    }
+    tx.commit().await?;
+    writes.spans.push((started, started.elapsed()));
+    Ok(writes)
+}
+
+// Record one fetched message into its batch's transaction
+async fn record(
+    conn: &mut SqliteConnection,
+    info: MessageInfo,
+    opts: &FetchOptions,
+    emitting: bool,
+    writes: &mut Writes,
+) -> Result<(), GmailStatsError> {
+    let id = info.id.clone();
+    db::clear_errors(&id, &mut *conn).await?;
+    writes.seen.push(id.clone());
+    // Erased senders stay erased: nothing of their mail is kept but the seen mark
+    if opts.erased.is_erased(&info.sender.sender) {
+        db::increment_run_erased(opts.run_id, &mut *conn).await?;
+        writes.unrecorded.push((id, "erased"));
+        writes.erased += 1;
+        return Ok(());
+    }
+    if db::recorded_elsewhere(&info, opts.account(), &mut *conn).await? {
+        writes.unrecorded.push((id, "duplicate"));
+        writes.duplicates += 1;
+        return Ok(());
+    }
+    let ignored = opts.ignore.is_ignored(&info.sender.sender);
+    // Notes to myself and mail from my other clients aren't mail I received
+    let own = !ignored && opts.own.is_mine(&info.sender.sender);
+    // My own sent mail is recorded even when ignored or mine, so the engagement and latency
+    // reports can still pair up replies
+    if !(ignored || own) || info.has_label(SENT) {
+        db::record_message(&info, opts.account(), opts.run_id, opts.clock.now(), conn).await?;
+    } else {
+        writes
+            .unrecorded
+            .push((id, if ignored { "ignored" } else { "own" }));
+    }
+    if ignored {
+        db::increment_run_ignored(opts.run_id, &mut *conn).await?;
+    } else if own {
+        db::increment_run_self_excluded(opts.run_id, &mut *conn).await?;
+        writes.self_excluded += 1;
+    } else if info.is_spam() {
+        // Spam is counted separately so it doesn't end up in the main sender ranking
+        db::increment_spam_sender(&info, &mut *conn).await?;
+    } else {
+        writes
+            .senders
+            .entry(info.sender.sender.clone())
+            .or_default()
+            .add(&info);
+    }
+    writes.processed += 1;
+    if emitting {
+        writes.to_emit.push(info);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network() -> GmailStatsError {
+        GmailStatsError::Network("reset".to_string())
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_its_cap() {
+        let waits = [0, 1, 2, 7, 8, 33, u32::MAX].map(|retries| backoff(&network(), retries));
+        assert_eq!(
+            waits.map(|wait| wait.as_secs()),
+            [2, 4, 8, 256, 300, 300, 300]
+        );
+    }
+
+    #[test]
+    fn gmail_says_how_long_to_wait_for_a_rate_limit() {
+        let err = GmailStatsError::RateLimited {
+            retry_after: Some(Duration::from_secs(7)),
+        };
+        assert_eq!(backoff(&err, 20), Duration::from_secs(7));
+        let err = GmailStatsError::RateLimited { retry_after: None };
+        assert_eq!(backoff(&err, 1), Duration::from_secs(4));
+    }
+}
@@ -0,0 +1,485 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use ring::digest;
+use sqlx::{Pool, Sqlite};
+
+use crate::backup;
+use crate::cli::ForgetArgs;
+use crate::format;
+use crate::ignore::IgnoreList;
+
+/// The pattern `forget` erases: an address as given, or `@domain` for a domain, which takes in
+/// its subdomains. Wildcards aren't taken, so a typo can't erase more than was meant.
+pub fn pattern(target: &str) -> anyhow::Result<String> {
+    let target = target.trim().to_lowercase();
+    if target.is_empty() || target.contains(['*', '?']) {
+        anyhow::bail!("forget takes an address or a domain, not {:?}", target);
+    }
+    match target.find('@') {
+        Some(0) | None => Ok(format!("@{}", target.trim_start_matches('@'))),
+        Some(_) => Ok(target),
+    }
+}
+
+/// What a tombstone keeps of `pattern`: its SHA-256 in hex, so the database says someone was
+/// erased without saying who.
+pub fn digest(pattern: &str) -> String {
+    let mut hex = String::with_capacity(64);
+    for byte in digest::digest(&digest::SHA256, pattern.as_bytes()).as_ref() {
+        write!(hex, "{:02x}", byte).unwrap();
+    }
+    hex
+}
+
+/// Senders erased with `forget`, for fetches to leave out, known only by their digests.
+#[derive(Clone, Debug, Default)]
+pub struct Tombstones {
+    digests: HashSet<String>,
+}
+
+impl Tombstones {
+    /// Tombstones for `patterns` as `pattern` makes them.
+    pub fn new(patterns: &[String]) -> Tombstones {
+        Tombstones {
+            digests: patterns.iter().map(|pattern| digest(pattern)).collect(),
+        }
+    }
+
+    /// Whether `address` was erased, itself or by its domain or any domain above it.
+    pub fn is_erased(&self, address: &str) -> bool {
+        if self.digests.is_empty() {
+            return false;
+        }
+        let address = address.trim().to_lowercase();
+        if self.digests.contains(&digest(&address)) {
+            return true;
+        }
+        let Some((_, mut domain)) = address.rsplit_once('@') else {
+            return false;
+        };
+        loop {
+            if self.digests.contains(&digest(&format!("@{}", domain))) {
+                return true;
+            }
+            match domain.split_once('.') {
+                Some((_, parent)) => domain = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+/// What erasing a sender deleted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Erased {
+    /// The stored addresses the pattern matched.
+    pub senders: Vec<String>,
+    pub messages: u64,
+    /// Recipient rows, both of their messages and of my mail to them.
+    pub recipients: u64,
+    pub errors: u64,
+    /// Seen marks, only deleted when the mail may be fetched again.
+    pub seen: u64,
+}
+
+/// Delete every trace of the senders `pattern` matches in one transaction: their counts, spam
+/// counts and aliases, their messages with those messages' labels, recipients, attachments and
+/// errors, cleanup's record of their mail, and their address wherever it's a recipient. Unless
+/// `allow_refetch` is set, their mail stays marked seen and a tombstone makes later fetches count
+/// new mail from them as erased rather than recording it; with it, their mail is fetched afresh.
+pub async fn erase(
+    pool: &Pool<Sqlite>,
+    pattern: &str,
+    allow_refetch: bool,
+    now: DateTime<Utc>,
+) -> anyhow::Result<Erased> {
+    let matcher = IgnoreList::new(&[pattern.to_string()]);
+    let mut tx = pool.begin().await?;
+    let stored: Vec<(String,)> = sqlx::query_as(
+        "SELECT sender FROM senders UNION SELECT sender FROM spam_senders
+        UNION SELECT sender FROM messages UNION SELECT sender FROM sender_aliases
+        UNION SELECT canonical FROM sender_aliases",
+    )
+    .fetch_all(&mut tx)
+    .await?;
+    let mut erased = Erased {
+        senders: stored
+            .into_iter()
+            .map(|(sender,)| sender)
+            .filter(|sender| matcher.is_ignored(sender))
+            .collect(),
+        ..Default::default()
+    };
+    erased.senders.sort();
+    erased.senders.dedup();
+    let senders = serde_json::to_string(&erased.senders)?;
+
+    // The message ids are gathered first, since everything else about a message hangs off them
+    sqlx::query(
+        "CREATE TEMP TABLE IF NOT EXISTS erased_mail (
+            account string NOT NULL,
+            mail_id string NOT NULL,
+            PRIMARY KEY (account, mail_id)
+        )",
+    )
+    .execute(&mut tx)
+    .await?;
+    sqlx::query("DELETE FROM erased_mail")
+        .execute(&mut tx)
+        .await?;
+    sqlx::query(
+        "INSERT OR IGNORE INTO erased_mail
+        SELECT account, mail_id FROM messages WHERE sender IN (SELECT value FROM json_each(?))",
+    )
+    .bind(&senders)
+    .execute(&mut tx)
+    .await?;
+
+    for table in ["message_labels", "attachments"] {
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE (account, mail_id) IN (SELECT account, mail_id FROM erased_mail)",
+            table
+        ))
+        .execute(&mut tx)
+        .await?;
+    }
+    // Actions and errors aren't kept per account
+    sqlx::query("DELETE FROM actions WHERE mail_id IN (SELECT mail_id FROM erased_mail)")
+        .execute(&mut tx)
+        .await?;
+    erased.recipients = sqlx::query(
+        "DELETE FROM message_recipients
+            WHERE (account, mail_id) IN (SELECT account, mail_id FROM erased_mail)
+            OR lower(address) IN (SELECT lower(value) FROM json_each(?))",
+    )
+    .bind(&senders)
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+    erased.errors =
+        sqlx::query("DELETE FROM errors WHERE mail_id IN (SELECT mail_id FROM erased_mail)")
+            .execute(&mut tx)
+            .await?
+            .rows_affected();
+    if allow_refetch {
+        erased.seen = sqlx::query(
+            "DELETE FROM seen_mails
+            WHERE (account, mail_id) IN (SELECT account, mail_id FROM erased_mail)",
+        )
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+    } else {
+        sqlx::query(
+            "UPDATE seen_mails SET unrecorded = 'erased'
+            WHERE (account, mail_id) IN (SELECT account, mail_id FROM erased_mail)",
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+    erased.messages = sqlx::query(
+        "DELETE FROM messages
+            WHERE (account, mail_id) IN (SELECT account, mail_id FROM erased_mail)",
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+
+    for statement in [
+        "DELETE FROM senders WHERE sender IN (SELECT value FROM json_each(?))",
+        "DELETE FROM spam_senders WHERE sender IN (SELECT value FROM json_each(?))",
+        "DELETE FROM sender_aliases WHERE sender IN (SELECT value FROM json_each(?1))
+            OR canonical IN (SELECT value FROM json_each(?1))",
+        "DELETE FROM actions WHERE sender IN (SELECT value FROM json_each(?))",
+    ] {
+        sqlx::query(statement)
+            .bind(&senders)
+            .execute(&mut tx)
+            .await?;
+    }
+    if let Some(domain) = pattern.strip_prefix('@') {
+        sqlx::query("DELETE FROM domains WHERE domain = ? OR domain LIKE '%.' || ?")
+            .bind(domain)
+            .bind(domain)
+            .execute(&mut tx)
+            .await?;
+    }
+    sqlx::query("DELETE FROM erased_mail")
+        .execute(&mut tx)
+        .await?;
+
+    if allow_refetch {
+        sqlx::query("DELETE FROM erased_senders WHERE digest = ?")
+            .bind(digest(pattern))
+            .execute(&mut tx)
+            .await?;
+    } else {
+        sqlx::query("INSERT OR REPLACE INTO erased_senders (digest, erased_at) VALUES (?, ?)")
+            .bind(digest(pattern))
+            .bind(now.timestamp_millis())
+            .execute(&mut tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(erased)
+}
+
+/// The senders erased so far, for fetches to leave out.
+pub async fn tombstones(pool: &Pool<Sqlite>) -> Result<Tombstones, sqlx::Error> {
+    let digests: Vec<(String,)> = sqlx::query_as("SELECT digest FROM erased_senders")
+        .fetch_all(pool)
+        .await?;
+    Ok(Tombstones {
+        digests: digests.into_iter().map(|(digest,)| digest).collect(),
+    })
+}
+
+/// Replace the patterns tombstones kept before they were digested with their digests. Every
+/// pattern has an `@` and no digest does.
+pub async fn digest_old_tombstones(pool: &Pool<Sqlite>) -> Result<(), sqlx::Error> {
+    let old: Vec<(String,)> =
+        sqlx::query_as("SELECT digest FROM erased_senders WHERE instr(digest, '@') > 0")
+            .fetch_all(pool)
+            .await?;
+    if old.is_empty() {
+        return Ok(());
+    }
+    let mut tx = pool.begin().await?;
+    for (pattern,) in old {
+        sqlx::query("UPDATE OR REPLACE erased_senders SET digest = ? WHERE digest = ?")
+            .bind(digest(&pattern))
+            .bind(&pattern)
+            .execute(&mut tx)
+            .await?;
+    }
+    tx.commit().await
+}
+
+/// `forget`: erase a sender or domain and say what went.
+pub async fn run(pool: &Pool<Sqlite>, args: &ForgetArgs) -> anyhow::Result<()> {
+    let pattern = pattern(&args.target)?;
+    let erased = erase(pool, &pattern, args.allow_refetch, args.clock.now()).await?;
+    if erased.senders.is_empty() {
+        println!("nothing stored from {}", pattern);
+    } else {
+        println!(
+            "erased {}: {} messages, {} recipient rows and {} errors",
+            erased.senders.join(", "),
+            format::thousands(erased.messages as i64),
+            format::thousands(erased.recipients as i64),
+            format::thousands(erased.errors as i64)
+        );
+    }
+    if args.allow_refetch {
+        println!(
+            "{} seen marks removed, so the next fetch records their mail again",
+            format::thousands(erased.seen as i64)
+        );
+    } else {
+        println!(
+            "later fetches count mail from {} as erased without recording it",
+            pattern
+        );
+    }
+    // No backup is taken before forgetting, but the ones from earlier runs still hold it all
+    let backups = backup::list(Path::new(backup::DATABASE))?;
+    if backups.is_empty() || erased.senders.is_empty() {
+        return Ok(());
+    }
+    if args.keep_backups {
+        eprintln!(
+            "warning: {} backups still hold what was erased: {}",
+            backups.len(),
+            backups
+                .iter()
+                .map(|backup| backup.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        return Ok(());
+    }
+    for removed in backup::rotate(Path::new(backup::DATABASE), 0)? {
+        println!(
+            "removed the backup {}, which held it too",
+            removed.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::analyze::SenderCounts;
+    use crate::cli::{Cli, Command};
+    use crate::db::{self, DEFAULT_ACCOUNT};
+    use crate::testsupport::{self, info};
+
+    const SENDERS: [&str; 4] = [
+        "jane@example.com",
+        "bob@mail.example.com",
+        "eve@notexample.com",
+        "news@example.org",
+    ];
+
+    // A message from each of `SENDERS`, counted and seen
+    async fn mailbox() -> Pool<Sqlite> {
+        let pool = testsupport::pool().await;
+        let mut conn = pool.acquire().await.unwrap();
+        let mut ids = Vec::new();
+        for (n, sender) in SENDERS.iter().enumerate() {
+            let message = info(&format!("m{}", n), sender, 0, &["INBOX"]);
+            db::record_message(
+                &message,
+                DEFAULT_ACCOUNT,
+                None,
+                DateTime::UNIX_EPOCH,
+                &mut conn,
+            )
+            .await
+            .unwrap();
+            let mut counts = SenderCounts::default();
+            counts.add(&message);
+            db::add_sender_counts(sender, &counts, &mut conn)
+                .await
+                .unwrap();
+            ids.push(message.id);
+        }
+        db::mark_seen(&ids, DEFAULT_ACCOUNT, &mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+        pool
+    }
+
+    async fn senders(pool: &Pool<Sqlite>) -> Vec<String> {
+        let rows: Vec<(String,)> = sqlx::query_as("SELECT sender FROM senders ORDER BY sender")
+            .fetch_all(pool)
+            .await
+            .unwrap();
+        rows.into_iter().map(|(sender,)| sender).collect()
+    }
+
+    #[test]
+    fn targets_become_an_address_or_a_domain() {
+        assert_eq!(pattern(" Jane@Example.com ").unwrap(), "jane@example.com");
+        assert_eq!(pattern("example.com").unwrap(), "@example.com");
+        assert_eq!(pattern("@Example.com").unwrap(), "@example.com");
+        for target in ["", "  ", "*@example.com", "jane?@example.com"] {
+            assert!(pattern(target).is_err(), "{:?}", target);
+        }
+    }
+
+    #[tokio::test]
+    async fn an_address_erases_only_that_sender() {
+        let pool = mailbox().await;
+
+        let erased = erase(&pool, "jane@example.com", false, DateTime::UNIX_EPOCH)
+            .await
+            .unwrap();
+
+        assert_eq!(erased.senders, ["jane@example.com"]);
+        assert_eq!(erased.messages, 1);
+        assert_eq!(
+            senders(&pool).await,
+            [
+                "bob@mail.example.com",
+                "eve@notexample.com",
+                "news@example.org"
+            ]
+        );
+        // Still seen, so it isn't fetched again
+        assert_eq!(db::seen_count(DEFAULT_ACCOUNT, &pool).await.unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn a_domain_erases_its_subdomains_but_not_lookalikes() {
+        let pool = mailbox().await;
+
+        let erased = erase(&pool, "@example.com", false, DateTime::UNIX_EPOCH)
+            .await
+            .unwrap();
+
+        assert_eq!(erased.senders, ["bob@mail.example.com", "jane@example.com"]);
+        assert_eq!(erased.messages, 2);
+        assert_eq!(
+            senders(&pool).await,
+            ["eve@notexample.com", "news@example.org"]
+        );
+    }
+
+    #[tokio::test]
+    async fn the_tombstone_keeps_a_digest_rather_than_the_address() {
+        let pool = mailbox().await;
+
+        erase(&pool, "jane@example.com", false, DateTime::UNIX_EPOCH)
+            .await
+            .unwrap();
+
+        let stored: Vec<(String,)> = sqlx::query_as("SELECT digest FROM erased_senders")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, [(digest("jane@example.com"),)]);
+        assert!(!stored[0].0.contains("jane"));
+        let erased = tombstones(&pool).await.unwrap();
+        assert!(erased.is_erased("Jane@Example.com"));
+        assert!(!erased.is_erased("bob@mail.example.com"));
+
+        // Refetching takes the tombstone away again
+        erase(&pool, "jane@example.com", true, DateTime::UNIX_EPOCH)
+            .await
+            .unwrap();
+        assert!(!tombstones(&pool)
+            .await
+            .unwrap()
+            .is_erased("jane@example.com"));
+    }
+
+    #[test]
+    fn a_domain_tombstone_covers_its_subdomains() {
+        let tombstones = Tombstones::new(&["@example.com".to_string()]);
+
+        assert!(tombstones.is_erased("jane@example.com"));
+        assert!(tombstones.is_erased("bob@mail.EXAMPLE.com"));
+        assert!(!tombstones.is_erased("eve@notexample.com"));
+        assert!(!tombstones.is_erased("example.com"));
+        assert!(!Tombstones::default().is_erased("jane@example.com"));
+    }
+
+    #[tokio::test]
+    async fn tombstones_from_before_digests_are_digested() {
+        let pool = testsupport::pool().await;
+        sqlx::query("INSERT INTO erased_senders (digest, erased_at) VALUES ('@example.com', 0)")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        digest_old_tombstones(&pool).await.unwrap();
+
+        let stored: Vec<(String,)> = sqlx::query_as("SELECT digest FROM erased_senders")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, [(digest("@example.com"),)]);
+        assert!(tombstones(&pool)
+            .await
+            .unwrap()
+            .is_erased("jane@example.com"));
+    }
+
+    #[test]
+    fn forgetting_takes_no_backup() {
+        let forget = Cli::try_parse_from(["gmail_stats", "forget", "jane@example.com"]).unwrap();
+        let fetch = Cli::try_parse_from(["gmail_stats", "fetch"]).unwrap();
+
+        assert!(matches!(forget.command, Some(Command::Forget(_))));
+        assert!(!forget.command.unwrap().backs_up());
+        assert!(fetch.command.unwrap().backs_up());
+    }
+}
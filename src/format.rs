@@ -0,0 +1,62 @@
+use chrono::{TimeZone, Utc};
+
+// 1234567 -> "1,234,567"
+pub fn thousands(n: i64) -> String {
+    let digits = n.unsigned_abs().to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3 + 1);
+    if n < 0 {
+        out.push('-');
+    }
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+// Human readable byte counts using binary units, e.g. "1.5 MiB"
+pub fn bytes(n: i64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = n as f64;
+    let mut unit = 0;
+    while value.abs() >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", n, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+// Epoch milliseconds to a UTC date, "-" when unknown
+pub fn date(millis: Option<i64>) -> String {
+    millis
+        .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+// A bar of `#` scaled so that `max` fills `width` characters
+pub fn bar(value: i64, max: i64, width: usize) -> String {
+    if max <= 0 {
+        return String::new();
+    }
+    "#".repeat((value as f64 / max as f64 * width as f64).round() as usize)
+}
+
+// Compact durations from milliseconds: "45m", "3h 12m", "2d 4h"
+pub fn duration(millis: i64) -> String {
+    let minutes = millis / 60_000;
+    let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m", minutes)
+    }
+}
@@ -0,0 +1,157 @@
+/// Senders to leave out of collection and reports, from the `ignore` config list.
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreList {
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Pattern {
+    /// `me@example.com`
+    Address(String),
+    /// `@example.com`, the domain and its subdomains but not `notexample.com`
+    Domain(String),
+    /// `alerts-*@example.com`, `*` is any run of characters and `?` any single one
+    Glob(Vec<char>),
+}
+
+impl IgnoreList {
+    pub fn new(patterns: &[String]) -> IgnoreList {
+        IgnoreList {
+            patterns: patterns
+                .iter()
+                .map(|pattern| pattern.trim().to_lowercase())
+                .filter(|pattern| !pattern.is_empty())
+                .map(|pattern| {
+                    if pattern.contains(['*', '?']) {
+                        Pattern::Glob(pattern.chars().collect())
+                    } else if let Some(domain) = pattern.strip_prefix('@') {
+                        Pattern::Domain(domain.to_string())
+                    } else {
+                        Pattern::Address(pattern)
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_ignored(&self, address: &str) -> bool {
+        let address = address.trim().to_lowercase();
+        self.patterns.iter().any(|pattern| match pattern {
+            Pattern::Address(ignored) => address == *ignored,
+            Pattern::Domain(domain) => address
+                .rsplit_once('@')
+                .map(|(_, d)| d == domain || d.ends_with(&format!(".{}", domain)))
+                .unwrap_or(false),
+            Pattern::Glob(glob) => glob_matches(glob, &address.chars().collect::<Vec<_>>()),
+        })
+    }
+}
+
+// Iterative wildcard matching, backtracking to the last `*` on a mismatch
+pub fn glob_matches(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str, text: &str) -> bool {
+        glob_matches(
+            &pattern.chars().collect::<Vec<_>>(),
+            &text.chars().collect::<Vec<_>>(),
+        )
+    }
+
+    fn list(patterns: &[&str]) -> IgnoreList {
+        IgnoreList::new(&patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn globs_match_the_whole_text() {
+        assert!(glob("*", ""));
+        assert!(glob("*", "anything"));
+        assert!(glob("alerts-*@example.com", "alerts-disk@example.com"));
+        assert!(glob("alerts-*@example.com", "alerts-@example.com"));
+        assert!(!glob(
+            "alerts-*@example.com",
+            "alerts-disk@example.com.evil"
+        ));
+        assert!(!glob("alerts-*@example.com", "my-alerts-disk@example.com"));
+        assert!(glob("bot?@example.com", "bot1@example.com"));
+        assert!(!glob("bot?@example.com", "bot@example.com"));
+        assert!(!glob("bot?@example.com", "bot12@example.com"));
+    }
+
+    #[test]
+    fn stars_backtrack_to_find_a_match() {
+        assert!(glob("*@*.example.com", "a@b.c.example.com"));
+        assert!(glob("*ab*ab", "xabyabab"));
+        assert!(glob("a**b", "ab"));
+        assert!(!glob("*ab*ab", "xabyab_"));
+        assert!(glob("ü*@例え.jp", "üser@例え.jp"));
+    }
+
+    #[test]
+    fn a_domain_takes_in_its_subdomains_but_not_lookalikes() {
+        let ignored = list(&["@example.com"]);
+
+        assert!(ignored.is_ignored("jane@example.com"));
+        assert!(ignored.is_ignored("alerts@mail.example.com"));
+        assert!(ignored.is_ignored("JANE@EXAMPLE.COM"));
+        assert!(!ignored.is_ignored("jane@notexample.com"));
+        assert!(!ignored.is_ignored("jane@example.com.evil.org"));
+        assert!(!ignored.is_ignored("example.com@elsewhere.org"));
+        assert!(!ignored.is_ignored("example.com"));
+    }
+
+    #[test]
+    fn an_address_matches_itself_not_a_substring() {
+        let ignored = list(&[" Me@Example.com "]);
+
+        assert!(ignored.is_ignored("me@example.com"));
+        assert!(ignored.is_ignored(" ME@example.com"));
+        assert!(!ignored.is_ignored("someme@example.com"));
+        assert!(!ignored.is_ignored("me@example.com.au"));
+    }
+
+    #[test]
+    fn blank_patterns_ignore_nothing() {
+        let ignored = list(&["", "   "]);
+
+        assert!(!ignored.is_ignored(""));
+        assert!(!ignored.is_ignored("jane@example.com"));
+        assert_eq!(list(&[]).patterns, []);
+    }
+
+    #[test]
+    fn any_pattern_in_the_list_is_enough() {
+        let ignored = list(&["me@example.com", "@monitoring.example.org", "noreply-*@*"]);
+
+        for address in [
+            "me@example.com",
+            "pager@monitoring.example.org",
+            "noreply-builds@ci.example.net",
+        ] {
+            assert!(ignored.is_ignored(address), "{}", address);
+        }
+        assert!(!ignored.is_ignored("noreply@ci.example.net"));
+    }
+}
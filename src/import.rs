@@ -0,0 +1,276 @@
+//! Counting mail from somewhere other than the API: an mbox file such as Google Takeout's, a
+//! Maildir tree, or with the `imap` feature a mailbox on any IMAP server. Each is a
+//! [`MailSource`] whose messages are turned into what the API would
+//! have returned, so they go through the same parsing, deduplication and counting as fetched
+//! mail.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use base64::engine::general_purpose::URL_SAFE;
+use base64::Engine;
+use google_gmail1::api::{Message, MessagePart, MessagePartBody, MessagePartHeader};
+use mailparse::{DispositionType, ParsedMail};
+use ring::digest::{digest, SHA256};
+use sqlx::{Pool, Sqlite};
+
+use crate::cli::{FetchArgs, ImportArgs};
+use crate::config::Config;
+use crate::error::GmailStatsError;
+use crate::fetch::{self, FetchOptions};
+use crate::interrupt;
+use crate::retention;
+use crate::source::MailSource;
+use crate::summary::Summary;
+
+#[cfg(feature = "imap")]
+pub mod imap;
+pub mod maildir;
+pub mod mbox;
+
+/// Messages read per page, as many as a Gmail listing page has.
+pub const PAGE_SIZE: usize = 500;
+
+/// Names Takeout and IMAP folders give Gmail's system labels. Categories are written
+/// `Category Promotions` and so on.
+const SYSTEM_LABELS: [(&str, &str); 11] = [
+    ("Inbox", "INBOX"),
+    ("Sent", "SENT"),
+    ("Sent Mail", "SENT"),
+    ("Spam", "SPAM"),
+    ("Trash", "TRASH"),
+    ("Unread", "UNREAD"),
+    ("Starred", "STARRED"),
+    ("Important", "IMPORTANT"),
+    ("Draft", "DRAFT"),
+    ("Drafts", "DRAFT"),
+    ("Chat", "CHAT"),
+];
+
+/// Names which aren't labels in the API at all: Takeout's read and archived markers, and the
+/// folder everything is in.
+const NOT_LABELS: [&str; 3] = ["Opened", "Archived", "All Mail"];
+
+/// The label id the API would give a label or folder called `name`, `None` if it has none.
+/// Other names are user labels, and kept as they are.
+pub fn label_id(name: &str) -> Option<String> {
+    let name = name
+        .strip_prefix("[Gmail]/")
+        .or_else(|| name.strip_prefix("[Google Mail]/"))
+        .unwrap_or(name);
+    if name.is_empty() || NOT_LABELS.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+        return None;
+    }
+    if let Some(category) = name.strip_prefix("Category ") {
+        return Some(format!("CATEGORY_{}", category.to_ascii_uppercase()));
+    }
+    Some(
+        SYSTEM_LABELS
+            .iter()
+            .find(|(system, _)| system.eq_ignore_ascii_case(name))
+            .map_or_else(|| name.to_string(), |(_, id)| id.to_string()),
+    )
+}
+
+/// Takeout's `X-Gmail-Labels` as the label ids the API would give.
+pub fn label_ids(header: &str) -> Vec<String> {
+    header
+        .split(',')
+        .filter_map(|name| label_id(name.trim().trim_matches('"')))
+        .collect()
+}
+
+/// An id for a message with no Gmail id: `prefix` and a hash of `key`.
+pub fn hashed_id(prefix: &str, key: &str) -> String {
+    let hash = digest(&SHA256, key.as_bytes());
+    format!(
+        "{}-{}",
+        prefix,
+        hash.as_ref()[..8]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    )
+}
+
+/// A message read from a file, or why it couldn't be parsed.
+pub type Parsed = Result<Message, String>;
+
+/// Parse `raw` into the message the API would have returned, with the id `id` gives for its
+/// Message-ID (`None` if it has none or couldn't be parsed) and the labels from Takeout's
+/// `X-Gmail-Labels` if it's there.
+pub fn parse(raw: &[u8], id: impl FnOnce(Option<&str>) -> String) -> (String, Parsed) {
+    let mail = match mailparse::parse_mail(raw) {
+        Ok(mail) => mail,
+        Err(err) => return (id(None), Err(err.to_string())),
+    };
+    let header = |name: &str| {
+        mail.headers
+            .iter()
+            .find(|header| header.get_key().eq_ignore_ascii_case(name))
+            .map(|header| header.get_value())
+    };
+    let id = id(header("Message-ID")
+        .as_deref()
+        .map(str::trim)
+        .filter(|id| !id.is_empty()));
+    let thread_id = header("X-GM-THRID")
+        .and_then(|thread| thread.trim().parse::<u64>().ok())
+        .map(|thread| format!("{:x}", thread));
+    let date = header("Date")
+        .and_then(|date| mailparse::dateparse(&date).ok())
+        .map(|seconds| (seconds * 1000).to_string());
+    let message = Message {
+        id: Some(id.clone()),
+        thread_id,
+        label_ids: Some(
+            header("X-Gmail-Labels")
+                .map(|labels| label_ids(&labels))
+                .unwrap_or_default(),
+        ),
+        internal_date: date,
+        size_estimate: Some(raw.len().try_into().unwrap_or(i32::MAX)),
+        payload: Some(to_part(&mail)),
+        ..Default::default()
+    };
+    (id, Ok(message))
+}
+
+// A MIME part as the API describes it. Like the API, bodies are only included for parts which
+// aren't attachments.
+fn to_part(mail: &ParsedMail) -> MessagePart {
+    let disposition = mail.get_content_disposition();
+    let filename = disposition
+        .params
+        .get("filename")
+        .or_else(|| mail.ctype.params.get("name"))
+        .cloned()
+        .unwrap_or_default();
+    let body = mail.get_body_raw().unwrap_or_default();
+    let is_attachment =
+        !filename.is_empty() || disposition.disposition == DispositionType::Attachment;
+    MessagePart {
+        headers: Some(
+            mail.headers
+                .iter()
+                .map(|header| MessagePartHeader {
+                    name: Some(header.get_key()),
+                    value: Some(header.get_value()),
+                })
+                .collect(),
+        ),
+        mime_type: Some(mail.ctype.mimetype.clone()),
+        filename: Some(filename),
+        body: Some(MessagePartBody {
+            size: Some(body.len().try_into().unwrap_or(i32::MAX)),
+            data: (mail.subparts.is_empty() && !is_attachment && !body.is_empty())
+                .then(|| URL_SAFE.encode(&body)),
+            attachment_id: None,
+        }),
+        parts: (!mail.subparts.is_empty()).then(|| mail.subparts.iter().map(to_part).collect()),
+        ..Default::default()
+    }
+}
+
+/// Messages of the last two pages read, waiting for `get_message`: a fetch lists the next page
+/// while it works through the current one. A message that couldn't be parsed holds why not, so
+/// it's skipped like a message Gmail couldn't give us. Messages already seen are never asked
+/// for, and go when their page does.
+#[derive(Default)]
+pub struct Pending {
+    pages: Mutex<VecDeque<HashMap<String, Parsed>>>,
+}
+
+impl Pending {
+    /// Keep a page's messages, returning the ids a listing restricted to `label` would give. As
+    /// Gmail's listing does, spam and trash are left out unless `include_spam_trash` is set.
+    pub fn add(
+        &self,
+        messages: Vec<(String, Parsed)>,
+        label: Option<&str>,
+        include_spam_trash: bool,
+    ) -> Vec<String> {
+        let mut page = HashMap::with_capacity(messages.len());
+        let mut ids = Vec::with_capacity(messages.len());
+        for (id, message) in messages {
+            let labels = message
+                .as_ref()
+                .ok()
+                .and_then(|message| message.label_ids.clone())
+                .unwrap_or_default();
+            let hidden = !include_spam_trash
+                && labels
+                    .iter()
+                    .any(|label| label == "SPAM" || label == "TRASH");
+            let wanted = label.is_none_or(|label| labels.iter().any(|l| l == label));
+            if hidden || !wanted {
+                continue;
+            }
+            ids.push(id.clone());
+            page.insert(id, message);
+        }
+        let mut pages = self.pages.lock().unwrap();
+        pages.push_back(page);
+        if pages.len() > 2 {
+            pages.pop_front();
+        }
+        ids
+    }
+
+    /// The message `id` from one of the pages kept.
+    pub fn take(&self, id: &str) -> Result<Message, GmailStatsError> {
+        let found = self
+            .pages
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find_map(|page| page.remove(id));
+        match found {
+            Some(Ok(message)) => Ok(message),
+            Some(Err(err)) => Err(GmailStatsError::Parse(format!("{}: {}", id, err))),
+            None => Err(GmailStatsError::Api {
+                status: Some(404),
+                message: format!("{} isn't on the page being read", id),
+            }),
+        }
+    }
+}
+
+/// Count the mail in `source` as if it had been fetched, then classify its senders, returning
+/// the run's summary once it's printed. Ctrl-C and `--max-duration` stop it the way they stop a
+/// fetch.
+pub async fn run(
+    pool: &Pool<Sqlite>,
+    source: &impl MailSource,
+    args: &ImportArgs,
+    config: &Config,
+) -> anyhow::Result<Summary> {
+    let fetch_args = FetchArgs {
+        clock: args.clock,
+        profile: args.profile.clone(),
+        unwrap_forwarded: args.unwrap_forwarded,
+        exclude_inline: args.exclude_inline,
+        include_spam_trash: args.include_spam_trash,
+        include_self: args.include_self,
+        ..Default::default()
+    };
+    let opts = FetchOptions::new(&fetch_args, config);
+    interrupt::cancel_on_signal(opts.cancel.clone());
+    if let Some(max) = args.max_duration {
+        interrupt::cancel_after(opts.cancel.clone(), max);
+    }
+    let imported = fetch::run(pool, source, &opts).await;
+    if matches!(
+        imported,
+        Ok(_)
+            | Err(GmailStatsError::Interrupted
+                | GmailStatsError::DeadlineReached
+                | GmailStatsError::QuotaExhausted)
+    ) {
+        retention::enforce(pool, &config.retention, args.clock.now(), false).await?;
+        crate::classify(pool, config).await?;
+    }
+    let summary = imported?;
+    summary.print(args.summary_json);
+    Ok(summary)
+}
@@ -0,0 +1,315 @@
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use async_imap::error::Error as ImapError;
+use async_imap::types::Flag;
+use async_imap::{Authenticator, Client, Session};
+use futures::TryStreamExt;
+use google_gmail1::api::Message;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio_rustls::rustls::{self, ClientConfig, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+
+use super::PAGE_SIZE;
+use crate::config::ImapConfig;
+use crate::error::GmailStatsError;
+use crate::format;
+use crate::source::{MailSource, MessagePage};
+
+/// What's asked for of each unseen message. The whole header is fetched rather than a list of
+/// fields, since parsing looks at a good many of them; bodies are never downloaded, so
+/// attachments aren't counted.
+const QUERY: &str = "(UID FLAGS INTERNALDATE RFC822.SIZE BODY.PEEK[HEADER])";
+
+/// A connection to the server, over TLS or not.
+pub trait ImapStream: AsyncRead + AsyncWrite + Unpin + Send + Debug {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Debug> ImapStream for T {}
+
+type ImapSession = Session<Box<dyn ImapStream>>;
+
+/// A mailbox on any IMAP server, read as if it were a Gmail listing. The UIDs in it are listed
+/// once, when it's opened, and page tokens are indexes into them. Message ids are the mailbox,
+/// its UIDVALIDITY and the UID, so mail seen in an earlier import isn't fetched again unless the
+/// server has renumbered the mailbox.
+pub struct ImapSource {
+    config: ImapConfig,
+    mailbox: String,
+    /// The label id the mailbox's name gives its mail, if it's a label.
+    label: Option<String>,
+    validity: u32,
+    uids: Vec<u32>,
+    /// `None` once the connection has dropped, until the next message reconnects.
+    session: Mutex<Option<ImapSession>>,
+    received: AtomicU64,
+}
+
+impl ImapSource {
+    /// Sign in to the server `config` describes and list the UIDs in `mailbox`.
+    pub async fn open(config: &ImapConfig, mailbox: &str) -> Result<ImapSource, GmailStatsError> {
+        if config.host.is_empty() || config.username.is_empty() {
+            return Err(GmailStatsError::Config(
+                "import-imap needs a host and username in the [imap] section of the config file"
+                    .to_string(),
+            ));
+        }
+        let mut session = connect(config).await?;
+        let validity = select(&mut session, mailbox).await?;
+        let mut uids: Vec<u32> = session
+            .uid_search("ALL")
+            .await
+            .map_err(imap_error)?
+            .into_iter()
+            .collect();
+        uids.sort_unstable();
+        eprintln!(
+            "found {} messages in {}",
+            format::thousands(uids.len() as i64),
+            mailbox
+        );
+        Ok(ImapSource {
+            config: config.clone(),
+            mailbox: mailbox.to_string(),
+            label: super::label_id(mailbox),
+            validity,
+            uids,
+            session: Mutex::new(Some(session)),
+            received: AtomicU64::new(0),
+        })
+    }
+
+    fn id(&self, uid: u32) -> String {
+        format!("imap-{}-{}-{}", self.mailbox, self.validity, uid)
+    }
+
+    // The UID in an id this source gave, `None` for one from another mailbox or numbering
+    fn uid(&self, id: &str) -> Option<u32> {
+        let (prefix, uid) = id.rsplit_once('-')?;
+        (prefix == format!("imap-{}-{}", self.mailbox, self.validity))
+            .then(|| uid.parse().ok())
+            .flatten()
+    }
+
+    // Fetch what's wanted of message `uid`, reconnecting first if the connection has dropped
+    async fn fetch(&self, uid: u32) -> Result<Option<Message>, GmailStatsError> {
+        let mut session = self.session.lock().await;
+        if session.is_none() {
+            let mut reconnected = connect(&self.config).await?;
+            if select(&mut reconnected, &self.mailbox).await? != self.validity {
+                return Err(GmailStatsError::Config(format!(
+                    "the server renumbered {} during the import, import it again",
+                    self.mailbox
+                )));
+            }
+            *session = Some(reconnected);
+        }
+        let fetched = match session.as_mut() {
+            Some(connected) => match connected.uid_fetch(uid.to_string(), QUERY).await {
+                Ok(stream) => stream.try_collect::<Vec<_>>().await,
+                Err(err) => Err(err),
+            },
+            None => unreachable!("connected above"),
+        };
+        let fetched = match fetched {
+            Ok(fetched) => fetched,
+            Err(err) => {
+                let err = imap_error(err);
+                if err.is_transient() {
+                    *session = None;
+                }
+                return Err(err);
+            }
+        };
+        let Some(fetch) = fetched.iter().find(|fetch| fetch.uid == Some(uid)) else {
+            return Ok(None);
+        };
+        let header = fetch.header().unwrap_or_default();
+        self.received
+            .fetch_add(header.len() as u64, Ordering::Relaxed);
+
+        let id = self.id(uid);
+        let (_, parsed) = super::parse(header, |_| id.clone());
+        let mut message =
+            parsed.map_err(|err| GmailStatsError::Parse(format!("{}: {}", id, err)))?;
+        let labels = message.label_ids.get_or_insert_with(Vec::new);
+        labels.extend(self.label.clone());
+        if !fetch.flags().any(|flag| flag == Flag::Seen) {
+            labels.push("UNREAD".to_string());
+        }
+        labels.sort();
+        labels.dedup();
+        // When the server got it, like Gmail's internalDate, rather than when it says it was sent
+        if let Some(date) = fetch.internal_date() {
+            message.internal_date = Some(date.timestamp_millis().to_string());
+        }
+        if let Some(size) = fetch.size {
+            message.size_estimate = Some(size.try_into().unwrap_or(i32::MAX));
+        }
+        Ok(Some(message))
+    }
+}
+
+impl MailSource for ImapSource {
+    async fn list_page(
+        &self,
+        label: Option<&str>,
+        include_spam_trash: bool,
+        page_token: Option<&str>,
+    ) -> Result<MessagePage, GmailStatsError> {
+        let hidden = matches!(self.label.as_deref(), Some("SPAM" | "TRASH"));
+        if hidden && !include_spam_trash {
+            return Err(GmailStatsError::Config(format!(
+                "{} holds spam or trash, pass --include-spam-trash to import it",
+                self.mailbox
+            )));
+        }
+        // Everything in the mailbox has its label, so a listing of another label is empty
+        if label.is_some() && label != self.label.as_deref() {
+            return Ok(MessagePage::default());
+        }
+        let start = match page_token {
+            Some(token) => token
+                .parse()
+                .map_err(|_| GmailStatsError::Config(format!("bad IMAP page token {:?}", token)))?,
+            None => 0,
+        };
+        let end = (start + PAGE_SIZE).min(self.uids.len());
+        let ids = self.uids[start.min(end)..end]
+            .iter()
+            .map(|&uid| self.id(uid))
+            .collect();
+
+        eprintln!(
+            "listed {} of {} messages ({:.0}%)",
+            format::thousands(end as i64),
+            format::thousands(self.uids.len() as i64),
+            end as f64 / self.uids.len().max(1) as f64 * 100.0
+        );
+        Ok(MessagePage {
+            ids,
+            next_page_token: (end < self.uids.len()).then(|| end.to_string()),
+            missing_ids: 0,
+        })
+    }
+
+    async fn get_message(&self, id: &str) -> Result<Message, GmailStatsError> {
+        let gone = || GmailStatsError::Api {
+            status: Some(404),
+            message: format!("{} isn't in {} any more", id, self.mailbox),
+        };
+        let uid = self.uid(id).ok_or_else(gone)?;
+        self.fetch(uid).await?.ok_or_else(gone)
+    }
+
+    fn bytes_received(&self) -> Option<u64> {
+        Some(self.received.load(Ordering::Relaxed))
+    }
+}
+
+/// Sign in with an OAuth 2.0 access token, RFC 7628's OAUTHBEARER.
+struct OAuthBearer {
+    initial: String,
+    sent: bool,
+}
+
+impl Authenticator for OAuthBearer {
+    type Response = String;
+
+    // A second challenge is the server's error, which is answered with ^A to end the exchange
+    fn process(&mut self, _challenge: &[u8]) -> String {
+        if std::mem::replace(&mut self.sent, true) {
+            return "\x01".to_string();
+        }
+        self.initial.clone()
+    }
+}
+
+// Connect and sign in as `config` says
+async fn connect(config: &ImapConfig) -> Result<ImapSession, GmailStatsError> {
+    let tcp = TcpStream::connect((config.host.as_str(), config.port()))
+        .await
+        .map_err(|err| GmailStatsError::Network(format!("{}: {}", config.host, err)))?;
+    let stream: Box<dyn ImapStream> = if config.tls() {
+        Box::new(tls(config, tcp).await?)
+    } else {
+        Box::new(tcp)
+    };
+    let mut client = Client::new(stream);
+    client
+        .read_response()
+        .await
+        .ok_or_else(|| GmailStatsError::Network(format!("{} hung up", config.host)))?
+        .map_err(|err| GmailStatsError::Network(format!("{}: {}", config.host, err)))?;
+
+    let signed_in = match (&config.password, &config.oauth_token) {
+        (Some(password), None) => client.login(&config.username, password).await,
+        (None, Some(token)) => {
+            let bearer = OAuthBearer {
+                initial: format!(
+                    "n,a={},\x01host={}\x01port={}\x01auth=Bearer {}\x01\x01",
+                    config.username,
+                    config.host,
+                    config.port(),
+                    token
+                ),
+                sent: false,
+            };
+            client.authenticate("OAUTHBEARER", bearer).await
+        }
+        _ => {
+            return Err(GmailStatsError::Config(
+                "the [imap] section needs one of password or oauth_token".to_string(),
+            ))
+        }
+    };
+    signed_in.map_err(|(err, _)| GmailStatsError::Auth(format!("{}: {}", config.host, err)))
+}
+
+async fn tls(
+    config: &ImapConfig,
+    tcp: TcpStream,
+) -> Result<tokio_rustls::client::TlsStream<TcpStream>, GmailStatsError> {
+    let mut roots = RootCertStore::empty();
+    let certs = rustls_native_certs::load_native_certs().map_err(|err| {
+        GmailStatsError::Config(format!("couldn't load the system's certificates: {}", err))
+    })?;
+    for cert in certs {
+        // One certificate rustls can't use shouldn't stop the rest from working
+        let _ = roots.add(&rustls::Certificate(cert.0));
+    }
+    let tls_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from(config.host.as_str())
+        .map_err(|_| GmailStatsError::Config(format!("{:?} isn't a host name", config.host)))?;
+    TlsConnector::from(Arc::new(tls_config))
+        .connect(name, tcp)
+        .await
+        .map_err(|err| GmailStatsError::Network(format!("{}: {}", config.host, err)))
+}
+
+// Select `mailbox` read-only, returning its UIDVALIDITY
+async fn select(session: &mut ImapSession, mailbox: &str) -> Result<u32, GmailStatsError> {
+    let selected = session.examine(mailbox).await.map_err(imap_error)?;
+    selected
+        .uid_validity
+        .ok_or_else(|| GmailStatsError::Parse(format!("{} without a UIDVALIDITY", mailbox)))
+}
+
+// Dropped connections are worth retrying, and are reconnected; the server turning a command
+// down isn't
+fn imap_error(err: ImapError) -> GmailStatsError {
+    match err {
+        ImapError::Io(err) => GmailStatsError::Network(err.to_string()),
+        ImapError::ConnectionLost => GmailStatsError::Network("connection lost".to_string()),
+        ImapError::No(message) | ImapError::Bad(message) => GmailStatsError::Api {
+            status: None,
+            message,
+        },
+        err => GmailStatsError::Parse(format!("the server's answer: {}", err)),
+    }
+}
@@ -0,0 +1,390 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use google_gmail1::api::Message;
+
+use super::{Pending, PAGE_SIZE};
+use crate::error::GmailStatsError;
+use crate::format;
+use crate::source::{MailSource, MessagePage};
+
+/// One message file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MaildirFile {
+    pub path: PathBuf,
+    /// The label id of the folder it's in, if that's a label.
+    pub label: Option<String>,
+    /// In `new/`, or in `cur/` without the Seen flag.
+    pub unread: bool,
+}
+
+/// A Maildir tree, such as one offlineimap syncs, read as if it were a mailbox listing. Every
+/// folder under the root is read, its name taken as a label, and page tokens are indexes into
+/// the files found when it was opened.
+pub struct MaildirSource {
+    root: PathBuf,
+    files: Vec<MaildirFile>,
+    pending: Pending,
+}
+
+impl MaildirSource {
+    pub fn open(root: &Path) -> Result<MaildirSource, GmailStatsError> {
+        if !root.is_dir() {
+            return Err(GmailStatsError::Config(format!(
+                "{} isn't a directory",
+                root.display()
+            )));
+        }
+        let mut files = Vec::new();
+        let folders = walk(root, root, &mut files);
+        if folders == 0 {
+            return Err(GmailStatsError::Config(format!(
+                "{} isn't a Maildir, there's no cur or new directory under it",
+                root.display()
+            )));
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        eprintln!(
+            "found {} messages in {} folders",
+            format::thousands(files.len() as i64),
+            format::thousands(folders as i64)
+        );
+        Ok(MaildirSource {
+            root: root.to_path_buf(),
+            files,
+            pending: Pending::default(),
+        })
+    }
+
+    // The message in `file`, with its id and the labels its folder and flags give it
+    fn read(&self, file: &MaildirFile) -> (String, super::Parsed) {
+        let name = file
+            .path
+            .strip_prefix(&self.root)
+            .unwrap_or(&file.path)
+            .to_string_lossy()
+            .into_owned();
+        // The same message in two folders has one Message-ID, so it's only counted once
+        let id =
+            |message_id: Option<&str>| super::hashed_id("maildir", message_id.unwrap_or(&name));
+        let raw = match fs::read(&file.path) {
+            Ok(raw) => raw,
+            Err(err) => return (id(None), Err(format!("couldn't read {}: {}", name, err))),
+        };
+        let (id, mut parsed) = super::parse(&raw, id);
+        if let Ok(message) = &mut parsed {
+            let labels = message.label_ids.get_or_insert_with(Vec::new);
+            labels.extend(file.label.clone());
+            if file.unread {
+                labels.push("UNREAD".to_string());
+            }
+            labels.sort();
+            labels.dedup();
+        }
+        (id, parsed)
+    }
+}
+
+impl MailSource for MaildirSource {
+    async fn list_page(
+        &self,
+        label: Option<&str>,
+        include_spam_trash: bool,
+        page_token: Option<&str>,
+    ) -> Result<MessagePage, GmailStatsError> {
+        let start = match page_token {
+            Some(token) => token.parse().map_err(|_| {
+                GmailStatsError::Config(format!("bad Maildir page token {:?}", token))
+            })?,
+            None => 0,
+        };
+        let end = (start + PAGE_SIZE).min(self.files.len());
+        let messages = self.files[start.min(end)..end]
+            .iter()
+            .map(|file| self.read(file))
+            .collect();
+        let ids = self.pending.add(messages, label, include_spam_trash);
+
+        eprintln!(
+            "read {} of {} messages ({:.0}%)",
+            format::thousands(end as i64),
+            format::thousands(self.files.len() as i64),
+            end as f64 / self.files.len().max(1) as f64 * 100.0
+        );
+        Ok(MessagePage {
+            ids,
+            next_page_token: (end < self.files.len()).then(|| end.to_string()),
+            missing_ids: 0,
+        })
+    }
+
+    async fn get_message(&self, id: &str) -> Result<Message, GmailStatsError> {
+        self.pending.take(id)
+    }
+}
+
+/// The label of the folder at `relative`, the root being the inbox. Maildir++ folders are
+/// hidden directories with dots between levels, `.Archive.2019`, and nested directories
+/// work too, so both come out as `Archive/2019`.
+pub fn folder_label(relative: &Path) -> Option<String> {
+    let name = relative
+        .iter()
+        .map(|part| {
+            let part = part.to_string_lossy();
+            part.strip_prefix('.').unwrap_or(&part).replace('.', "/")
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    if name.is_empty() {
+        return Some("INBOX".to_string());
+    }
+    super::label_id(&name)
+}
+
+/// Whether a file in `cur/` is unread, going by the flags after `:2,` in its name.
+pub fn is_unread(file_name: &str) -> bool {
+    match file_name.rsplit_once(":2,") {
+        Some((_, flags)) => !flags.contains('S'),
+        None => true,
+    }
+}
+
+// Add the messages in the folders at or under `dir` to `files`, returning how many folders there
+// were. A directory which can't be read is skipped with a warning rather than ending the import.
+fn walk(root: &Path, dir: &Path, files: &mut Vec<MaildirFile>) -> usize {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("skipping {}: {}", dir.display(), err);
+            return 0;
+        }
+    };
+    let mut folders = 0;
+    let mut is_folder = false;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+        match entry.file_name().to_str() {
+            Some(sub @ ("cur" | "new")) => {
+                is_folder = true;
+                let label = folder_label(dir.strip_prefix(root).unwrap_or(dir));
+                let messages = match fs::read_dir(&path) {
+                    Ok(messages) => messages,
+                    Err(err) => {
+                        eprintln!("skipping {}: {}", path.display(), err);
+                        continue;
+                    }
+                };
+                for message in messages.flatten() {
+                    let path = message.path();
+                    if !path.is_file() {
+                        continue;
+                    }
+                    files.push(MaildirFile {
+                        unread: sub == "new" || is_unread(&message.file_name().to_string_lossy()),
+                        label: label.clone(),
+                        path,
+                    });
+                }
+            }
+            Some("tmp") => {}
+            _ => folders += walk(root, &path, files),
+        }
+    }
+    folders + is_folder as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::{self, FetchOptions};
+    use crate::import::hashed_id;
+    use crate::testsupport;
+
+    // (path under the root, From, Message-ID)
+    const FILES: [(&str, &str, Option<&str>); 8] = [
+        (
+            "cur/1.host:2,S",
+            "jane@example.org",
+            Some("<hello@example.org>"),
+        ),
+        ("new/2.host", "bob@example.net", Some("<hi@example.net>")),
+        ("tmp/3.host", "half@example.com", Some("<half@example.com>")),
+        (
+            ".Archive.2019/cur/4.host:2,",
+            "old@example.com",
+            Some("<old@example.com>"),
+        ),
+        (
+            ".Archive.2019/cur/5.host:2,RS",
+            "jane@example.org",
+            Some("<hello@example.org>"),
+        ),
+        (
+            ".Sent/cur/6.host:2,S",
+            "me@example.com",
+            Some("<sent@example.com>"),
+        ),
+        (
+            ".Spam/cur/7.host:2,S",
+            "win@prize.example.net",
+            Some("<win@prize.example.net>"),
+        ),
+        ("Work/Projects/new/8.host", "boss@example.com", None),
+    ];
+
+    // The fixture Maildir, in a directory of its own named after `name`
+    fn fixture(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!(
+            "gmail-stats-maildir-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&root);
+        for (path, from, message_id) in FILES {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let message_id = message_id
+                .map(|id| format!("Message-ID: {}\n", id))
+                .unwrap_or_default();
+            fs::write(
+                &path,
+                format!(
+                    "{}From: {}\nSubject: test\nDate: Mon, 01 Jan 2024 09:00:00 +0000\n\nbody\n",
+                    message_id, from
+                ),
+            )
+            .unwrap();
+        }
+        root
+    }
+
+    // The sender and labels of every message listed
+    async fn listed(
+        source: &MaildirSource,
+        include_spam_trash: bool,
+    ) -> Vec<(String, Vec<String>)> {
+        let page = source
+            .list_page(None, include_spam_trash, None)
+            .await
+            .unwrap();
+        assert_eq!(page.next_page_token, None);
+        let mut listed = Vec::new();
+        let mut ids = page.ids;
+        // A message in two folders is listed twice, as a repeated id, and read once
+        let mut seen = std::collections::HashSet::new();
+        ids.retain(|id| seen.insert(id.clone()));
+        for id in ids {
+            let message = source.get_message(&id).await.unwrap();
+            let headers = message.payload.unwrap().headers.unwrap();
+            let from = headers
+                .into_iter()
+                .find(|header| header.name.as_deref() == Some("From"))
+                .and_then(|header| header.value)
+                .unwrap();
+            listed.push((from, message.label_ids.unwrap()));
+        }
+        listed.sort();
+        listed
+    }
+
+    #[tokio::test]
+    async fn folders_become_labels_and_flags_unread() {
+        let root = fixture("labels");
+        let source = MaildirSource::open(&root).unwrap();
+
+        let listed = listed(&source, false).await;
+        fs::remove_dir_all(&root).unwrap();
+
+        let labels = |labels: &[&str]| labels.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+        // tmp/ is left alone, spam left out, and jane's message is the copy found last
+        assert_eq!(
+            listed,
+            [
+                ("bob@example.net".to_string(), labels(&["INBOX", "UNREAD"])),
+                (
+                    "boss@example.com".to_string(),
+                    labels(&["UNREAD", "Work/Projects"])
+                ),
+                ("jane@example.org".to_string(), labels(&["INBOX"])),
+                ("me@example.com".to_string(), labels(&["SENT"])),
+                (
+                    "old@example.com".to_string(),
+                    labels(&["Archive/2019", "UNREAD"])
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn ids_come_from_the_message_id_or_the_file() {
+        let root = fixture("ids");
+        let source = MaildirSource::open(&root).unwrap();
+
+        let page = source.list_page(None, true, None).await.unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(page
+            .ids
+            .contains(&hashed_id("maildir", "<hello@example.org>")));
+        assert!(page.ids.contains(&hashed_id(
+            "maildir",
+            &Path::new("Work/Projects/new/8.host").to_string_lossy()
+        )));
+        assert!(page
+            .ids
+            .contains(&hashed_id("maildir", "<win@prize.example.net>")));
+    }
+
+    #[tokio::test]
+    async fn the_same_message_in_two_folders_is_counted_once() {
+        let root = fixture("import");
+        let source = MaildirSource::open(&root).unwrap();
+        let pool = testsupport::pool().await;
+        let opts = FetchOptions {
+            quiet: true,
+            ..Default::default()
+        };
+
+        let summary = fetch::run(&pool, &source, &opts).await.unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(summary.processed, 5);
+        let (jane,): (i64,) =
+            sqlx::query_as("SELECT mails_sent FROM senders WHERE sender = 'jane@example.org'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(jane, 1);
+    }
+
+    #[test]
+    fn a_directory_without_mail_folders_isnt_a_maildir() {
+        let root =
+            std::env::temp_dir().join(format!("gmail-stats-not-maildir-{}", std::process::id()));
+        fs::create_dir_all(root.join("photos")).unwrap();
+        let opened = MaildirSource::open(&root);
+        fs::remove_dir_all(&root).unwrap();
+        assert!(matches!(opened, Err(GmailStatsError::Config(_))));
+    }
+
+    #[test]
+    fn folder_names_and_flags() {
+        assert_eq!(folder_label(Path::new("")).as_deref(), Some("INBOX"));
+        assert_eq!(
+            folder_label(Path::new(".Archive.2019")).as_deref(),
+            Some("Archive/2019")
+        );
+        assert_eq!(
+            folder_label(Path::new("Archive/2019")).as_deref(),
+            Some("Archive/2019")
+        );
+        assert_eq!(folder_label(Path::new(".Sent")).as_deref(), Some("SENT"));
+        assert_eq!(folder_label(Path::new("All Mail")), None);
+        assert!(!is_unread("1.host:2,FS"));
+        assert!(is_unread("1.host:2,F"));
+        assert!(is_unread("1.host"));
+    }
+}
@@ -0,0 +1,320 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use google_gmail1::api::Message;
+
+use super::{Pending, PAGE_SIZE};
+use crate::error::GmailStatsError;
+use crate::format;
+use crate::source::{MailSource, MessagePage};
+
+/// A message as it is in the file, separator line and all, with the offset it starts at.
+type RawMessage = (u64, Vec<u8>);
+
+/// An mbox file, such as Google Takeout's `All mail Including Spam and Trash.mbox`, read as if
+/// it were a mailbox listing. The file is streamed a page at a time, and page tokens are byte
+/// offsets into it, so starting the listing over just seeks back.
+pub struct MboxSource {
+    path: PathBuf,
+    reader: Mutex<BufReader<File>>,
+    size: u64,
+    pending: Pending,
+    read: AtomicU64,
+}
+
+impl MboxSource {
+    pub fn open(path: &Path) -> Result<MboxSource, GmailStatsError> {
+        let err = |err: std::io::Error| {
+            GmailStatsError::Config(format!("couldn't read {}: {}", path.display(), err))
+        };
+        let file = File::open(path).map_err(err)?;
+        let size = file.metadata().map_err(err)?.len();
+        let mut reader = BufReader::new(file);
+        if size > 0 && !reader.fill_buf().map_err(err)?.starts_with(b"From ") {
+            return Err(GmailStatsError::Config(format!(
+                "{} isn't an mbox file, it doesn't start with a \"From \" line",
+                path.display()
+            )));
+        }
+        Ok(MboxSource {
+            path: path.to_path_buf(),
+            reader: Mutex::new(reader),
+            size,
+            pending: Pending::default(),
+            read: AtomicU64::new(0),
+        })
+    }
+
+    // Up to PAGE_SIZE messages from `offset`, with the offset of the next one if there is one
+    fn read_page(&self, offset: u64) -> std::io::Result<(Vec<RawMessage>, Option<u64>)> {
+        let mut reader = self.reader.lock().unwrap();
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut messages: Vec<RawMessage> = Vec::new();
+        let mut position = offset;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            if read == 0 {
+                return Ok((messages, None));
+            }
+            // Only the separator starts with "From "; a body line that did is quoted as ">From "
+            if line.starts_with(b"From ") {
+                if messages.len() == PAGE_SIZE {
+                    return Ok((messages, Some(position)));
+                }
+                messages.push((position, Vec::new()));
+            }
+            if let Some((_, raw)) = messages.last_mut() {
+                raw.extend_from_slice(&line);
+            }
+            position += read as u64;
+            self.read.fetch_max(position, Ordering::Relaxed);
+        }
+    }
+}
+
+impl MailSource for MboxSource {
+    async fn list_page(
+        &self,
+        label: Option<&str>,
+        include_spam_trash: bool,
+        page_token: Option<&str>,
+    ) -> Result<MessagePage, GmailStatsError> {
+        let offset = match page_token {
+            Some(token) => token
+                .parse()
+                .map_err(|_| GmailStatsError::Config(format!("bad mbox page token {:?}", token)))?,
+            None => 0,
+        };
+        let (raw, next) = self.read_page(offset).map_err(|err| {
+            GmailStatsError::Config(format!("couldn't read {}: {}", self.path.display(), err))
+        })?;
+
+        let messages = raw
+            .into_iter()
+            .map(|(offset, raw)| {
+                let (separator, rest) = match raw.iter().position(|&b| b == b'\n') {
+                    Some(end) => (&raw[..end], &raw[end + 1..]),
+                    None => (&raw[..], &raw[raw.len()..]),
+                };
+                super::parse(rest, |message_id| id(separator, message_id, offset))
+            })
+            .collect();
+        let ids = self.pending.add(messages, label, include_spam_trash);
+
+        let read = self.read.load(Ordering::Relaxed);
+        eprintln!(
+            "read {} of {} ({:.0}%)",
+            format::bytes(read as i64),
+            format::bytes(self.size as i64),
+            percent(read, self.size)
+        );
+        Ok(MessagePage {
+            ids,
+            next_page_token: next.map(|offset| offset.to_string()),
+            missing_ids: 0,
+        })
+    }
+
+    async fn get_message(&self, id: &str) -> Result<Message, GmailStatsError> {
+        self.pending.take(id)
+    }
+
+    fn bytes_received(&self) -> Option<u64> {
+        Some(self.read.load(Ordering::Relaxed))
+    }
+}
+
+fn percent(done: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 100.0;
+    }
+    done as f64 / total as f64 * 100.0
+}
+
+/// The message's Gmail id: Takeout's separator line carries X-GM-MSGID in decimal, and the
+/// API's id is the same number in hex, so mail fetched both ways is only counted once.
+/// Other mbox files fall back to a hash of the Message-ID, or of where the message is in the
+/// file.
+pub fn id(separator: &[u8], message_id: Option<&str>, offset: u64) -> String {
+    let takeout = separator
+        .strip_prefix(b"From ")
+        .and_then(|rest| rest.split(|&b| b == b'@').next())
+        .and_then(|number| std::str::from_utf8(number).ok())
+        .and_then(|number| number.parse::<u64>().ok());
+    if let Some(number) = takeout {
+        return format!("{:x}", number);
+    }
+    match message_id {
+        Some(message_id) => super::hashed_id("mbox", message_id),
+        None => super::hashed_id("mbox", &format!("offset {}", offset)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use base64::engine::general_purpose::URL_SAFE;
+    use base64::Engine;
+
+    use super::*;
+    use crate::fetch::{self, FetchOptions};
+    use crate::import::hashed_id;
+    use crate::testsupport::{self, message, MockMailSource};
+
+    fn fixture() -> MboxSource {
+        MboxSource::open(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/takeout.mbox"))
+            .unwrap()
+    }
+
+    fn body(message: &Message) -> String {
+        let data = message
+            .payload
+            .as_ref()
+            .unwrap()
+            .body
+            .as_ref()
+            .unwrap()
+            .data
+            .as_ref()
+            .unwrap();
+        String::from_utf8(URL_SAFE.decode(data).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn takeout_messages_keep_their_gmail_ids_and_labels() {
+        let mbox = fixture();
+
+        let page = mbox.list_page(None, false, None).await.unwrap();
+        let unidentified = fs::read_to_string(&mbox.path)
+            .unwrap()
+            .find("From MAILER-DAEMON")
+            .unwrap();
+
+        // The spam is left out as the API leaves it out of a listing
+        assert_eq!(
+            page.ids,
+            [
+                format!("{:x}", 1789012345678901234u64),
+                format!("{:x}", 1789012345678905678u64),
+                hashed_id("mbox", "<hello@example.org>"),
+                hashed_id("mbox", &format!("offset {}", unidentified)),
+            ]
+        );
+        assert_eq!(page.ids[0], "18d3d93ff2faaff2");
+        assert_eq!(page.next_page_token, None);
+        let sale = mbox.get_message(&page.ids[0]).await.unwrap();
+        assert_eq!(
+            sale.thread_id,
+            Some(format!("{:x}", 1789012345678901000u64))
+        );
+        assert_eq!(
+            sale.label_ids.as_deref(),
+            Some(&["INBOX".to_string(), "CATEGORY_PROMOTIONS".to_string()][..])
+        );
+        assert_eq!(sale.internal_date.as_deref(), Some("1704099600000"));
+        let reply = mbox.get_message(&page.ids[1]).await.unwrap();
+        assert_eq!(reply.label_ids.as_deref(), Some(&["SENT".to_string()][..]));
+        assert_eq!(reply.thread_id, sale.thread_id);
+
+        let with_spam = mbox.list_page(None, true, None).await.unwrap();
+        assert_eq!(with_spam.ids.len(), 5);
+        assert_eq!(with_spam.ids[2], format!("{:x}", 1789012345678909999u64));
+    }
+
+    #[tokio::test]
+    async fn quoted_from_lines_stay_in_the_body() {
+        let mbox = fixture();
+        let page = mbox.list_page(None, false, None).await.unwrap();
+
+        let sale = mbox.get_message(&page.ids[0]).await.unwrap();
+        assert_eq!(
+            body(&sale),
+            "Everything must go.\n>From the team at Shop, see you next week.\n\n"
+        );
+        let hello = mbox.get_message(&page.ids[2]).await.unwrap();
+        assert_eq!(body(&hello), ">>From a mail client that quotes twice.\n\n");
+    }
+
+    #[tokio::test]
+    async fn page_tokens_are_offsets_of_the_next_message() {
+        let path =
+            std::env::temp_dir().join(format!("gmail-stats-mbox-{}.mbox", std::process::id()));
+        let raw = (0..PAGE_SIZE + 2)
+            .map(|n| {
+                format!(
+                    "From {}@xxx Mon Jan 01 09:00:00 +0000 2024\nFrom: sender{}@example.com\n\
+                    Subject: {}\n\n>From the body\n",
+                    1_000_000 + n,
+                    n,
+                    n
+                )
+            })
+            .collect::<String>();
+        fs::write(&path, &raw).unwrap();
+        let mbox = MboxSource::open(&path).unwrap();
+
+        let first = mbox.list_page(None, false, None).await.unwrap();
+        let offset = raw
+            .find(&format!("From {}@", 1_000_000 + PAGE_SIZE))
+            .unwrap();
+        assert_eq!(first.ids.len(), PAGE_SIZE);
+        assert_eq!(first.next_page_token, Some(offset.to_string()));
+        let second = mbox
+            .list_page(None, false, first.next_page_token.as_deref())
+            .await
+            .unwrap();
+        assert_eq!(
+            second.ids,
+            [
+                format!("{:x}", 1_000_000 + PAGE_SIZE),
+                format!("{:x}", 1_000_001 + PAGE_SIZE)
+            ]
+        );
+        assert_eq!(second.next_page_token, None);
+        assert_eq!(mbox.bytes_received(), Some(raw.len() as u64));
+        // Starting the listing over seeks back rather than carrying on
+        let again = mbox.list_page(None, false, None).await.unwrap();
+        assert_eq!(again.ids, first.ids);
+        assert!(matches!(
+            mbox.list_page(None, false, Some("page2")).await,
+            Err(GmailStatsError::Config(_))
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn mail_imported_from_takeout_isnt_fetched_again() {
+        let pool = testsupport::pool().await;
+        let opts = FetchOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        fetch::run(&pool, &fixture(), &opts).await.unwrap();
+        let api = MockMailSource::new().page([message(
+            "18d3d93ff2faaff2",
+            &[("From", "news@shop.example.com")],
+            &["INBOX"],
+        )]);
+
+        let summary = fetch::run(&pool, &api, &opts).await.unwrap();
+
+        assert_eq!((summary.processed, summary.already_seen), (0, 1));
+        assert!(api.fetched().is_empty());
+    }
+
+    #[test]
+    fn a_file_not_starting_with_a_from_line_isnt_an_mbox() {
+        let path =
+            std::env::temp_dir().join(format!("gmail-stats-not-mbox-{}.mbox", std::process::id()));
+        fs::write(&path, "Subject: hi\n\nbody\n").unwrap();
+        let opened = MboxSource::open(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(opened, Err(GmailStatsError::Config(_))));
+    }
+}
@@ -0,0 +1,97 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+/// Exit status after a fetch was interrupted, the shell's own for a process killed by SIGINT.
+pub const EXIT_CODE: i32 = 130;
+
+/// Asks a fetch to stop. It's checked between messages, so the one in hand is still committed
+/// and the run finished as interrupted rather than left half written.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<Inner>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    /// Cancelled because the run reached `--max-duration`.
+    deadline: AtomicBool,
+    notify: Notify,
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Cancel because the run has gone on as long as it may.
+    pub fn expire(&self) {
+        self.0.deadline.store(true, Ordering::SeqCst);
+        self.cancel();
+    }
+
+    /// Whether it was cancelled by `expire` rather than a signal.
+    pub fn is_deadline(&self) -> bool {
+        self.0.deadline.load(Ordering::SeqCst)
+    }
+
+    /// Wait until the token is cancelled, to cut a wait such as a retry's backoff short.
+    pub async fn cancelled(&self) {
+        // Registered before checking, so a cancel in between isn't missed
+        let notified = self.0.notify.notified();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// Cancel `token` on the first Ctrl-C or SIGTERM. A second one exits straight away, removing the
+/// lock on the database on the way out.
+pub fn cancel_on_signal(token: CancelToken) {
+    tokio::spawn(async move {
+        if signal().await.is_err() {
+            return;
+        }
+        eprintln!("interrupted, finishing the current message (again to quit now)");
+        token.cancel();
+        if signal().await.is_ok() {
+            crate::lock::release_all();
+            std::process::exit(EXIT_CODE);
+        }
+    });
+}
+
+/// Cancel `token` once `max` has passed, the way Ctrl-C does, unless something else has first.
+pub fn cancel_after(token: CancelToken, max: Duration) {
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = tokio::time::sleep(max) => {}
+            _ = token.cancelled() => return,
+        }
+        eprintln!("reached --max-duration, finishing the current message");
+        token.expire();
+    });
+}
+
+#[cfg(unix)]
+async fn signal() -> std::io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        res = tokio::signal::ctrl_c() => res,
+        _ = terminate.recv() => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+async fn signal() -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
@@ -0,0 +1,430 @@
+use std::future::Future;
+
+use google_gmail1::api::{BatchModifyMessagesRequest, Label, Scope};
+use google_gmail1::Gmail;
+use sqlx::{Pool, Sqlite};
+
+use crate::auth;
+use crate::cli::ApplyLabelsArgs;
+use crate::config::Config;
+use crate::db;
+use crate::error::GmailStatsError;
+use crate::format;
+use crate::report::MERGED_SENDERS;
+
+/// Most message ids `messages.batchModify` takes in one call.
+pub const BATCH_SIZE: usize = 1000;
+
+/// Somewhere messages can be labelled: Gmail itself, or a stand-in for it.
+pub trait LabelTarget {
+    /// The id of the user label called `name`, creating it if there isn't one.
+    fn label_id(&self, name: &str) -> impl Future<Output = Result<String, GmailStatsError>> + Send;
+
+    /// Add the labels `add` to and take `remove` off every message in `ids`, at most
+    /// `BATCH_SIZE` of them. Archiving is taking off `INBOX`, trashing adding `TRASH`.
+    fn modify(
+        &self,
+        ids: &[String],
+        add: &[&str],
+        remove: &[&str],
+    ) -> impl Future<Output = Result<(), GmailStatsError>> + Send;
+}
+
+// Every call needs the modify scope, which Google asks for the first time it's used
+impl LabelTarget for Gmail {
+    async fn label_id(&self, name: &str) -> Result<String, GmailStatsError> {
+        let labels = self
+            .users()
+            .labels_list("me")
+            .add_scope(Scope::Modify)
+            .doit()
+            .await?
+            .1
+            .labels
+            .unwrap_or_default();
+        // Gmail won't have two labels whose names differ only in case
+        if let Some(id) = labels
+            .into_iter()
+            .find(|label| {
+                label
+                    .name
+                    .as_deref()
+                    .is_some_and(|n| n.eq_ignore_ascii_case(name))
+            })
+            .and_then(|label| label.id)
+        {
+            return Ok(id);
+        }
+
+        let label = Label {
+            name: Some(name.to_string()),
+            label_list_visibility: Some("labelShow".to_string()),
+            message_list_visibility: Some("show".to_string()),
+            ..Default::default()
+        };
+        let created = self
+            .users()
+            .labels_create(label, "me")
+            .add_scope(Scope::Modify)
+            .doit()
+            .await?
+            .1;
+        created.id.ok_or_else(|| {
+            GmailStatsError::Parse(format!("label {:?} created without an id", name))
+        })
+    }
+
+    async fn modify(
+        &self,
+        ids: &[String],
+        add: &[&str],
+        remove: &[&str],
+    ) -> Result<(), GmailStatsError> {
+        let labels = |labels: &[&str]| {
+            (!labels.is_empty()).then(|| labels.iter().map(|label| label.to_string()).collect())
+        };
+        let request = BatchModifyMessagesRequest {
+            add_label_ids: labels(add),
+            ids: Some(ids.to_vec()),
+            remove_label_ids: labels(remove),
+        };
+        self.users()
+            .messages_batch_modify(request, "me")
+            .add_scope(Scope::Modify)
+            .doit()
+            .await?;
+        Ok(())
+    }
+}
+
+/// A sender picked for labelling and the stored messages of theirs which will get the label.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Selection {
+    pub sender: String,
+    pub mails_sent: i64,
+    pub ids: Vec<String>,
+}
+
+/// The `top` senders by message count, aliases folded and ignored senders left out, with the
+/// ids of their stored messages in `account`. Spam isn't in the counts, so it isn't labelled
+/// either.
+pub async fn select(
+    pool: &Pool<Sqlite>,
+    top: usize,
+    account: &str,
+) -> anyhow::Result<Vec<Selection>> {
+    let senders: Vec<(String, i64)> = sqlx::query_as(&format!(
+        "SELECT sender, mails_sent FROM {} ORDER BY mails_sent DESC, sender LIMIT ?",
+        MERGED_SENDERS
+    ))
+    .bind(top as i64)
+    .fetch_all(pool)
+    .await?;
+
+    let mut selections = Vec::with_capacity(senders.len());
+    for (sender, mails_sent) in senders {
+        let ids: Vec<(String,)> = sqlx::query_as(
+            "SELECT m.mail_id FROM messages m LEFT JOIN sender_aliases a ON a.sender = lower(m.sender)
+            WHERE coalesce(a.canonical, m.sender) = ? AND m.account = ? AND NOT m.is_spam
+            ORDER BY m.mail_id",
+        )
+        .bind(&sender)
+        .bind(account)
+        .fetch_all(pool)
+        .await?;
+        selections.push(Selection {
+            sender,
+            mails_sent,
+            ids: ids.into_iter().map(|(id,)| id).collect(),
+        });
+    }
+    Ok(selections)
+}
+
+/// Label every message in `selections` with `label`, creating it if need be, a batch at a time.
+/// Returns how many messages were labelled.
+pub async fn apply(
+    target: &impl LabelTarget,
+    label: &str,
+    selections: &[Selection],
+) -> Result<usize, GmailStatsError> {
+    let label_id = target.label_id(label).await?;
+    let ids = selections
+        .iter()
+        .flat_map(|selection| selection.ids.iter().cloned())
+        .collect::<Vec<_>>();
+    for batch in ids.chunks(BATCH_SIZE) {
+        target.modify(batch, &[&label_id], &[]).await?;
+        eprintln!("labelled {} messages", batch.len());
+    }
+    Ok(ids.len())
+}
+
+/// `apply-labels`: pick the senders, then list them for `--dry-run` or label their mail.
+pub async fn run(
+    pool: &Pool<Sqlite>,
+    args: &ApplyLabelsArgs,
+    config: &Config,
+) -> anyhow::Result<()> {
+    crate::classify(pool, config).await?;
+    let selections = select(
+        pool,
+        args.top,
+        args.profile.as_deref().unwrap_or(db::DEFAULT_ACCOUNT),
+    )
+    .await?;
+    let messages = selections
+        .iter()
+        .map(|selection| selection.ids.len())
+        .sum::<usize>();
+    // Only mail fetched since message rows were kept can be labelled, hence "of"
+    for selection in &selections {
+        println!(
+            "{:>8} of {:>8}  {}",
+            format::thousands(selection.ids.len() as i64),
+            format::thousands(selection.mails_sent),
+            selection.sender
+        );
+    }
+    if args.dry_run {
+        println!(
+            "would label {} messages from {} senders with {:?}",
+            format::thousands(messages as i64),
+            selections.len(),
+            args.label
+        );
+        return Ok(());
+    }
+
+    let hub = auth::hub(args.profile.as_deref()).await?;
+    let labelled = apply(&hub, &args.label, &selections).await?;
+    println!(
+        "labelled {} messages from {} senders with {:?}",
+        format::thousands(labelled as i64),
+        selections.len(),
+        args.label
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyze::SenderCounts;
+    use crate::ignore::IgnoreList;
+    use crate::testsupport::{self, info, MockMailSource, Modification};
+
+    // Record a message, counting it against its sender unless it's spam
+    async fn store(pool: &Pool<Sqlite>, id: &str, from: &str, labels: &[&str], account: &str) {
+        let message = info(id, from, 0, labels);
+        let mut conn = pool.acquire().await.unwrap();
+        db::record_message(
+            &message,
+            account,
+            None,
+            chrono::DateTime::UNIX_EPOCH,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        if !message.is_spam() {
+            let mut counts = SenderCounts::default();
+            counts.add(&message);
+            db::add_sender_counts(&message.sender.sender, &counts, &mut conn)
+                .await
+                .unwrap();
+        }
+    }
+
+    fn ids(prefix: &str, count: usize) -> Vec<String> {
+        (0..count).map(|n| format!("{}{:04}", prefix, n)).collect()
+    }
+
+    #[tokio::test]
+    async fn the_top_senders_are_picked_with_their_aliases_mail() {
+        let pool = testsupport::pool().await;
+        for id in ["j1", "j2", "j3"] {
+            store(
+                &pool,
+                id,
+                "jane@example.com",
+                &["INBOX"],
+                db::DEFAULT_ACCOUNT,
+            )
+            .await;
+        }
+        store(
+            &pool,
+            "jd",
+            "Jane.Doe@example.com",
+            &["INBOX"],
+            db::DEFAULT_ACCOUNT,
+        )
+        .await;
+        store(
+            &pool,
+            "js",
+            "jane@example.com",
+            &["SPAM"],
+            db::DEFAULT_ACCOUNT,
+        )
+        .await;
+        store(
+            &pool,
+            "b1",
+            "bob@example.org",
+            &["INBOX"],
+            db::DEFAULT_ACCOUNT,
+        )
+        .await;
+        store(
+            &pool,
+            "b2",
+            "bob@example.org",
+            &["INBOX"],
+            db::DEFAULT_ACCOUNT,
+        )
+        .await;
+        store(&pool, "bw", "bob@example.org", &["INBOX"], "work").await;
+        for id in ["n1", "n2", "n3", "n4", "n5"] {
+            store(
+                &pool,
+                id,
+                "news@example.net",
+                &["INBOX"],
+                db::DEFAULT_ACCOUNT,
+            )
+            .await;
+        }
+        store(
+            &pool,
+            "z1",
+            "zed@example.com",
+            &["INBOX"],
+            db::DEFAULT_ACCOUNT,
+        )
+        .await;
+        db::apply_aliases(
+            &pool,
+            &[(
+                "jane.doe@example.com".to_string(),
+                "jane@example.com".to_string(),
+            )],
+        )
+        .await
+        .unwrap();
+        db::flag_ignored_senders(&pool, &IgnoreList::new(&["news@example.net".to_string()]))
+            .await
+            .unwrap();
+
+        let selections = select(&pool, 2, db::DEFAULT_ACCOUNT).await.unwrap();
+
+        assert_eq!(
+            selections,
+            [
+                Selection {
+                    sender: "jane@example.com".to_string(),
+                    mails_sent: 4,
+                    ids: ["j1", "j2", "j3", "jd"].map(String::from).to_vec(),
+                },
+                Selection {
+                    sender: "bob@example.org".to_string(),
+                    mails_sent: 3,
+                    ids: ["b1", "b2"].map(String::from).to_vec(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn labelling_goes_a_batch_at_a_time() {
+        let target = MockMailSource::new();
+        let selections = [
+            Selection {
+                sender: "jane@example.com".to_string(),
+                mails_sent: 1800,
+                ids: ids("j", 1800),
+            },
+            Selection {
+                sender: "bob@example.org".to_string(),
+                mails_sent: 700,
+                ids: ids("b", 700),
+            },
+        ];
+
+        let labelled = apply(&target, "Newsletters", &selections).await.unwrap();
+
+        assert_eq!(labelled, 2500);
+        assert_eq!(
+            target.labels(),
+            [("Newsletters".to_string(), "Label_1".to_string())]
+        );
+        let modified = target.modified();
+        assert_eq!(
+            modified.iter().map(|m| m.ids.len()).collect::<Vec<_>>(),
+            [1000, 1000, 500]
+        );
+        assert!(modified
+            .iter()
+            .all(|m| m.add == ["Label_1"] && m.remove.is_empty()));
+        // Every id once, in order, the second batch running from one sender into the next
+        let sent = modified.into_iter().flat_map(|m| m.ids).collect::<Vec<_>>();
+        assert_eq!(sent, [ids("j", 1800), ids("b", 700)].concat());
+    }
+
+    #[tokio::test]
+    async fn an_existing_label_is_used_whatever_its_case() {
+        let target = MockMailSource::new().label("newsletters", "Label_9");
+        let selections = [Selection {
+            sender: "jane@example.com".to_string(),
+            mails_sent: 1,
+            ids: ids("j", 1),
+        }];
+
+        apply(&target, "Newsletters", &selections).await.unwrap();
+
+        assert_eq!(target.labels().len(), 1);
+        assert_eq!(
+            target.modified(),
+            [Modification {
+                ids: ids("j", 1),
+                add: vec!["Label_9".to_string()],
+                remove: Vec::new(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_failed_batch_stops_labelling() {
+        let target = MockMailSource::new().fail_modify(1, || GmailStatsError::Api {
+            status: Some(500),
+            message: "Backend Error".to_string(),
+        });
+        let selections = [Selection {
+            sender: "jane@example.com".to_string(),
+            mails_sent: 1500,
+            ids: ids("j", 1500),
+        }];
+
+        let err = apply(&target, "Newsletters", &selections)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            GmailStatsError::Api {
+                status: Some(500),
+                ..
+            }
+        ));
+        assert!(target.modified().is_empty());
+    }
+
+    #[tokio::test]
+    async fn nothing_selected_labels_nothing() {
+        let target = MockMailSource::new();
+
+        assert_eq!(apply(&target, "Newsletters", &[]).await.unwrap(), 0);
+        assert!(target.modified().is_empty());
+    }
+}
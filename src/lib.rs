@@ -0,0 +1,566 @@
+//! Fetch Gmail message metadata into a SQLite database and report on who sends the mail.
+//!
+//! The `gmail_stats` binary is a thin wrapper around [`run`]. [`fetch_messages`] and
+//! [`generate_report`] do what its `fetch` and `report` commands do, against a [`Storage`]
+//! opened with [`open_storage`]. [`analyze_headers`] counts senders from headers alone, with
+//! neither Gmail nor a database.
+
+use std::path::Path;
+
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{Pool, Sqlite};
+
+pub mod aliases;
+pub mod analyze;
+pub mod anonymize;
+pub mod auth;
+pub mod backfill;
+pub mod backup;
+pub mod cleanup;
+pub mod cli;
+pub mod clock;
+pub mod config;
+pub mod dates;
+pub mod db;
+pub mod domains;
+pub mod emit;
+pub mod error;
+pub mod exit;
+pub mod export;
+pub mod fetch;
+pub mod forget;
+pub mod format;
+pub mod ignore;
+pub mod import;
+pub mod interrupt;
+pub mod labels;
+pub mod lock;
+pub mod metrics;
+pub mod opener;
+pub mod own;
+pub mod parse;
+pub mod report;
+pub mod retention;
+pub mod search;
+#[cfg(feature = "sheets")]
+pub mod sheets;
+pub mod source;
+pub mod summary;
+#[doc(hidden)]
+pub mod testsupport;
+pub mod trace;
+pub mod verify;
+
+pub use analyze::{analyze_headers, RawHeaders, SenderStats};
+pub use config::Config;
+pub use error::GmailStatsError;
+pub use parse::SenderInfo;
+
+use cli::{Cli, Command, DbArgs, DbCommand, FetchArgs, ReportArgs};
+use domains::Classifier;
+use exit::Completion;
+use fetch::FetchOptions;
+use ignore::IgnoreList;
+use import::maildir::MaildirSource;
+use import::mbox::MboxSource;
+use source::GmailSource;
+use summary::Summary;
+
+/// The stats database.
+pub type Storage = Pool<Sqlite>;
+
+/// Open `stats.db` in the working directory, creating it if need be and migrating it to the
+/// latest schema.
+pub async fn open_storage() -> anyhow::Result<Storage> {
+    open(Path::new(backup::DATABASE), true).await
+}
+
+/// Open `stats.db` in the working directory as it is, for commands which only read it, so that
+/// looking never creates or migrates a database. It has to be there already, and be up to date.
+pub async fn open_storage_to_read() -> anyhow::Result<Storage> {
+    open(Path::new(backup::DATABASE), false).await
+}
+
+// Open the database at `path`, creating and migrating it if `writing`
+async fn open(path: &Path, writing: bool) -> anyhow::Result<Storage> {
+    if !writing && !path.exists() {
+        anyhow::bail!(
+            "there's no {} here yet, `fetch` or an import creates it",
+            path.display()
+        );
+    }
+    // TODO: use tokio::spawn and sqlite transactions to make fetching concurrent
+    // TODO: there's a rate limit on google's side, so we should have some kind of backpressure
+    let options = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(writing);
+    // WAL mode should be much faster for concurrent reads and writes
+    // .journal_mode(SqliteJournalMode::Wal)
+    // Synchronous mode is OK because a transaction may roll back during a crash, however
+    // all mail listings are re-fetched during each run.
+    // .synchronous(SqliteSynchronous::Normal)
+    // .shared_cache(true);
+
+    // let pool = Pool::<Sqlite>::connect_with(options).await?;
+    let pool = SqlitePoolOptions::new()
+        .max_connections(100)
+        .connect_with(options)
+        .await?;
+    if !writing {
+        if db::schema_version(&pool).await? < Some(db::latest_schema_version()) {
+            anyhow::bail!(
+                "{} is from an older version, `fetch` or an import brings it up to date",
+                path.display()
+            );
+        }
+        return Ok(pool);
+    }
+    db::migrate(&pool).await?;
+    // Without FTS5 there's just no subject index, which only `search` needs
+    search::ensure_index(&pool).await?;
+    Ok(pool)
+}
+
+/// Fetch new mail into `storage` and classify its senders, signing in to Gmail first, returning
+/// the run's summary once it's printed. Ctrl-C or SIGTERM stops the fetch after the message in
+/// hand, classifies what was fetched and fails with `GmailStatsError::Interrupted`; reaching
+/// `--max-duration` does the same, failing with `GmailStatsError::DeadlineReached`, and so does
+/// using up Gmail's daily quota, failing with `GmailStatsError::QuotaExhausted`.
+pub async fn fetch_messages(
+    storage: &Storage,
+    args: &FetchArgs,
+    config: &Config,
+) -> anyhow::Result<Summary> {
+    // Refused before fetching, rather than after a run that can't be logged
+    #[cfg(not(feature = "sheets"))]
+    if args.sheet_id.is_some() {
+        return Err(cli::needs_feature("fetch --sheet-id", "sheets"));
+    }
+    let opts = FetchOptions::new(args, config);
+    interrupt::cancel_on_signal(opts.cancel.clone());
+    if let Some(max) = args.max_duration {
+        interrupt::cancel_after(opts.cancel.clone(), max);
+    }
+    let source = GmailSource::new(
+        auth::hub(args.profile.as_deref()).await?,
+        !args.no_compression,
+    );
+    let fetched = fetch::run(storage, &source, &opts).await;
+    // An interrupted fetch committed what it got through, so that's classified too
+    if matches!(
+        fetched,
+        Ok(_)
+            | Err(GmailStatsError::Interrupted
+                | GmailStatsError::DeadlineReached
+                | GmailStatsError::QuotaExhausted)
+    ) {
+        retention::enforce(
+            storage,
+            &config.retention,
+            args.clock.now(),
+            args.quiet_unless_changed,
+        )
+        .await?;
+        classify(storage, config).await?;
+    }
+    let summary = fetched?;
+    // The fetch itself succeeded, so a failed append only warns
+    #[cfg(feature = "sheets")]
+    if let Some(sheet_id) = &args.sheet_id {
+        if let Err(err) =
+            sheets::append_summary(storage, sheet_id, args.profile.as_deref(), args.clock.now())
+                .await
+        {
+            eprintln!("couldn't append the run to the sheet: {}", err);
+        }
+    }
+    if summary.is_printed(args.quiet_unless_changed, args.notify_threshold) {
+        summary.print(args.summary_json);
+    }
+    Ok(summary)
+}
+
+// Derive the per-sender flags and aliases reports rely on from the fetched mail and the config.
+// Only commands which hold the lock do it, so reading the database never writes to it, and an
+// edit to the config shows up in reports after the next fetch or import. Only mail and senders
+// new since the last time are classified, unless the settings they're classified with changed.
+pub(crate) async fn classify(storage: &Storage, config: &Config) -> anyhow::Result<()> {
+    let classifier = Classifier::new(config);
+    let fingerprint = classifier.fingerprint();
+    let everything = db::classified_with(storage).await?.as_ref() != Some(&fingerprint);
+    db::classify_senders(storage, &classifier, everything).await?;
+    db::flag_ignored_senders(storage, &IgnoreList::new(&config.ignore)).await?;
+    db::classify_addressing(storage, &config.identity, everything).await?;
+    db::classify_arrival(storage, &config.identity, everything).await?;
+    db::apply_aliases(storage, &aliases::resolve(&config.aliases)?).await?;
+    db::finish_classifying(storage, &fingerprint).await?;
+    Ok(())
+}
+
+/// Print or write the report `args` asks for.
+pub async fn generate_report(
+    storage: &Storage,
+    args: &ReportArgs,
+    config: &Config,
+) -> anyhow::Result<()> {
+    report::run(storage, args, config).await
+}
+
+/// Run a parsed command line, fetching when no command was given. `exit::code` turns what it
+/// returns into the binary's exit status.
+pub async fn run(cli: Cli) -> anyhow::Result<Completion> {
+    // Comparing two other databases has no use for the local one
+    if let Some(Command::Diff(args)) = &cli.command {
+        report::diff::run(args).await?;
+        return Ok(Completion::Clean);
+    }
+    let config = Config::load(&cli.config)?;
+    let command = cli
+        .command
+        .unwrap_or_else(|| Command::Fetch(FetchArgs::default()));
+    // Commands which change what's stored take turns, so two fetches don't interleave their
+    // retries or record a run twice
+    let _lock = if command.writes() {
+        Some(lock::acquire(
+            Path::new(lock::LOCK_FILE),
+            cli.force,
+            command.clock().now(),
+        )?)
+    } else {
+        None
+    };
+    // The database is replaced rather than opened
+    if let Command::Db(DbArgs {
+        command: DbCommand::Restore(args),
+    }) = &command
+    {
+        backup::run_restore(args, !cli.no_backup, command.clock().now()).await?;
+        return Ok(Completion::Clean);
+    }
+    let existed = Path::new(backup::DATABASE).exists();
+    let storage = if command.writes() {
+        open_storage().await?
+    } else {
+        open_storage_to_read().await?
+    };
+    if command.backs_up() && existed && !cli.no_backup {
+        backup::before_run(
+            &storage,
+            config.backups.unwrap_or(backup::DEFAULT_KEEP),
+            command.clock().now(),
+            command.quiet(),
+        )
+        .await?;
+    }
+
+    let mut completion = Completion::Clean;
+    match command {
+        Command::Fetch(args) => {
+            completion = Completion::of(&fetch_messages(&storage, &args, &config).await?)
+        }
+        Command::Report(args) => generate_report(&storage, &args, &config).await?,
+        Command::Export(args) => export::run(&storage, &args, &config).await?,
+        #[cfg(feature = "serve")]
+        Command::Serve(args) => report::serve::run(&storage, &args, &config).await?,
+        #[cfg(not(feature = "serve"))]
+        Command::Serve(_) => return Err(cli::needs_feature("serve", "serve")),
+        #[cfg(feature = "tui")]
+        Command::Tui(args) => report::tui::run(&storage, &args).await?,
+        #[cfg(not(feature = "tui"))]
+        Command::Tui(_) => return Err(cli::needs_feature("tui", "tui")),
+        Command::ApplyLabels(args) => labels::run(&storage, &args, &config).await?,
+        Command::Cleanup(args) => cleanup::run(&storage, &args, &config).await?,
+        Command::ImportMbox(args) => {
+            let source = MboxSource::open(&args.path)?;
+            completion =
+                Completion::of(&import::run(&storage, &source, &args.import, &config).await?)
+        }
+        Command::ImportMaildir(args) => {
+            let source = MaildirSource::open(&args.dir)?;
+            completion =
+                Completion::of(&import::run(&storage, &source, &args.import, &config).await?)
+        }
+        #[cfg(feature = "imap")]
+        Command::ImportImap(args) => {
+            let imap = config.imap.clone().unwrap_or_default();
+            let source = import::imap::ImapSource::open(&imap, &args.mailbox).await?;
+            completion =
+                Completion::of(&import::run(&storage, &source, &args.import, &config).await?)
+        }
+        #[cfg(not(feature = "imap"))]
+        Command::ImportImap(_) => return Err(cli::needs_feature("import-imap", "imap")),
+        Command::Forget(args) => forget::run(&storage, &args).await?,
+        Command::Verify(args) => verify::run(&storage, &args).await?,
+        Command::Db(DbArgs {
+            command: DbCommand::RepairOrphans(args),
+        }) => verify::repair_orphans(&storage, &args, &config).await?,
+        Command::Db(DbArgs {
+            command: DbCommand::Reindex,
+        }) => search::run_reindex(&storage).await?,
+        Command::Search(args) => search::run(&storage, &args).await?,
+        Command::Backfill(args) => backfill::run_backfill(&storage, &args, &config).await?,
+        Command::Diff(_)
+        | Command::Db(DbArgs {
+            command: DbCommand::Restore(_),
+        }) => unreachable!("handled before opening stats.db"),
+    }
+
+    Ok(completion)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::analyze::SenderCounts;
+    use crate::db::DEFAULT_ACCOUNT;
+    use crate::parse::{MessageInfo, ParseOptions};
+    use crate::testsupport::{self, message};
+
+    // Record mail from `from` to `to` as a fetch would
+    async fn receive(pool: &Storage, id: &str, from: &str, to: &str) {
+        receive_headers(pool, id, &[("From", from), ("To", to)]).await;
+    }
+
+    // Record mail with `headers`, which must include From, as a fetch would
+    async fn receive_headers(pool: &Storage, id: &str, headers: &[(&str, &str)]) {
+        let message = message(id, headers, &["INBOX"]);
+        let info = MessageInfo::from_message(&message, &ParseOptions::default()).unwrap();
+        let mut counts = SenderCounts::default();
+        counts.add(&info);
+        let mut conn = pool.acquire().await.unwrap();
+        db::record_message(
+            &info,
+            DEFAULT_ACCOUNT,
+            None,
+            DateTime::UNIX_EPOCH,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        db::add_sender_counts(&info.sender.sender, &counts, &mut conn)
+            .await
+            .unwrap();
+    }
+
+    async fn addressed(pool: &Storage) -> Vec<(String, Option<String>, Option<String>)> {
+        sqlx::query_as("SELECT mail_id, addressed, arrived_at FROM messages ORDER BY mail_id")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    fn config(addresses: &[&str], own_domains: &[&str]) -> Config {
+        let mut config = Config {
+            my_addresses: addresses.iter().map(|a| a.to_string()).collect(),
+            own_domains: own_domains.iter().map(|d| d.to_string()).collect(),
+            ..Default::default()
+        };
+        config.identity = own::Identity::new(&config);
+        config
+    }
+
+    fn row(
+        id: &str,
+        addressed: &str,
+        arrived_at: Option<&str>,
+    ) -> (String, Option<String>, Option<String>) {
+        (
+            id.to_string(),
+            Some(addressed.to_string()),
+            arrived_at.map(str::to_string),
+        )
+    }
+
+    #[tokio::test]
+    async fn mail_is_classified_by_where_my_address_appears() {
+        let pool = testsupport::pool().await;
+        let settings = config(&["me@example.com", "me@work.example"], &[]);
+        let fixtures: &[(&str, &[(&str, &str)])] = &[
+            ("direct", &[("To", "Me <ME@example.com>, bob@example.org")]),
+            (
+                "cc",
+                &[("To", "bob@example.org"), ("Cc", "me@work.example")],
+            ),
+            (
+                "both",
+                &[
+                    ("To", "\"Me, at work\" <me@work.example>"),
+                    ("Cc", "me@example.com"),
+                ],
+            ),
+            ("group", &[("To", "Team: bob@example.org, me@example.com;")]),
+            (
+                "list",
+                &[
+                    ("To", "list@lists.example.org"),
+                    ("List-Id", "<list.example.org>"),
+                ],
+            ),
+            ("undisclosed", &[("To", "undisclosed-recipients:;")]),
+        ];
+        for (id, headers) in fixtures {
+            let mut headers = headers.to_vec();
+            headers.push(("From", "jane@example.org"));
+            receive_headers(&pool, id, &headers).await;
+        }
+
+        classify(&pool, &settings).await.unwrap();
+
+        let classes: Vec<(String, Option<String>)> =
+            sqlx::query_as("SELECT mail_id, addressed FROM messages ORDER BY mail_id")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        let class = |id: &str| {
+            classes
+                .iter()
+                .find(|(mail_id, _)| mail_id == id)
+                .unwrap()
+                .1
+                .clone()
+        };
+        assert_eq!(class("direct").as_deref(), Some("direct"));
+        assert_eq!(class("cc").as_deref(), Some("cc"));
+        assert_eq!(class("both").as_deref(), Some("direct"));
+        assert_eq!(class("group").as_deref(), Some("direct"));
+        assert_eq!(class("list").as_deref(), Some("other"));
+        assert_eq!(class("undisclosed"), None);
+        let counts: (i64, i64) = sqlx::query_as(
+            "SELECT direct_count, cc_count FROM senders WHERE sender = 'jane@example.org'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(counts, (3, 1));
+    }
+
+    #[tokio::test]
+    async fn only_new_mail_is_classified_while_the_settings_stay_the_same() {
+        let pool = testsupport::pool().await;
+        let settings = config(&["me@example.com"], &["mycorp.example"]);
+        receive(&pool, "m1", "jane@example.org", "me@example.com").await;
+        classify(&pool, &settings).await.unwrap();
+        assert_eq!(
+            addressed(&pool).await,
+            [row("m1", "direct", Some("me@example.com"))]
+        );
+
+        // Were m1 classified again, it would be put right
+        sqlx::query("UPDATE messages SET addressed = 'other', arrived_at = NULL")
+            .execute(&pool)
+            .await
+            .unwrap();
+        receive(&pool, "m2", "boss@mycorp.example", "me@example.com").await;
+        classify(&pool, &settings).await.unwrap();
+
+        assert_eq!(
+            addressed(&pool).await,
+            [
+                row("m1", "other", None),
+                row("m2", "direct", Some("me@example.com"))
+            ]
+        );
+        let (internal,): (bool,) =
+            sqlx::query_as("SELECT is_internal FROM senders WHERE sender = 'boss@mycorp.example'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert!(internal);
+        let (unclassified,): (i64,) = sqlx::query_as(
+            "SELECT (SELECT count(*) FROM messages WHERE NOT classified)
+                + (SELECT count(*) FROM senders WHERE NOT classified)",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(unclassified, 0);
+    }
+
+    #[tokio::test]
+    async fn changed_settings_classify_everything_again() {
+        let pool = testsupport::pool().await;
+        receive(&pool, "m1", "jane@example.org", "me@example.com").await;
+        receive(&pool, "m2", "bob@example.org", "other@example.com").await;
+        classify(&pool, &config(&["me@example.com"], &[]))
+            .await
+            .unwrap();
+        assert_eq!(
+            addressed(&pool).await,
+            [
+                row("m1", "direct", Some("me@example.com")),
+                row("m2", "other", None)
+            ]
+        );
+
+        classify(&pool, &config(&["other@example.com"], &["example.org"]))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            addressed(&pool).await,
+            [
+                row("m1", "other", None),
+                row("m2", "direct", Some("other@example.com"))
+            ]
+        );
+        let counts: Vec<(String, i64, bool)> =
+            sqlx::query_as("SELECT sender, direct_count, is_internal FROM senders ORDER BY sender")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            counts,
+            [
+                ("bob@example.org".to_string(), 1, true),
+                ("jane@example.org".to_string(), 0, true)
+            ]
+        );
+    }
+
+    // A database path of its own for each test, in a directory cleared first
+    fn database(test: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("gmail-stats-open-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(backup::DATABASE)
+    }
+
+    #[tokio::test]
+    async fn reading_never_creates_the_database() {
+        let path = database("missing");
+
+        assert!(open(&path, false).await.is_err());
+        assert!(!path.exists());
+
+        open(&path, true).await.unwrap().close().await;
+        let pool = open(&path, false).await.unwrap();
+        assert_eq!(
+            db::schema_version(&pool).await.unwrap(),
+            Some(db::latest_schema_version())
+        );
+    }
+
+    #[tokio::test]
+    async fn reading_an_out_of_date_database_leaves_it_as_it_is() {
+        let path = database("old");
+        let options = SqliteConnectOptions::new()
+            .filename(&path)
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options)
+            .await
+            .unwrap();
+        sqlx::query("CREATE TABLE senders (sender string PRIMARY KEY)")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let err = open(&path, false).await.unwrap_err();
+
+        assert!(err.to_string().contains("older version"), "{}", err);
+        assert_eq!(db::schema_version(&pool).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,239 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+
+/// The lock file of `stats.db` in the working directory.
+pub const LOCK_FILE: &str = "stats.db.lock";
+
+// The locks this process holds, for `release_all` to remove on the way out of a forced exit,
+// which runs no destructors
+static HELD: Mutex<Vec<(u64, PathBuf)>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Another run holds the lock.
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "another run is in progress (pid {}, started at {}){}",
+    pid.map_or_else(|| "unknown".to_string(), |pid| pid.to_string()),
+    started.as_deref().unwrap_or("an unknown time"),
+    if *stale { "; it isn't running any more, pass --force to take the lock over" } else { "" }
+)]
+pub struct Held {
+    /// Whoever wrote the lock file, if it could be read.
+    pub pid: Option<u32>,
+    pub started: Option<String>,
+    /// The process is known to have died without removing the lock.
+    pub stale: bool,
+}
+
+/// The lock on the database, held until it's dropped.
+#[derive(Debug)]
+pub struct Lock {
+    path: PathBuf,
+    // Tells this lock apart from a later one at the same path, once it's been released
+    id: u64,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let mut held = HELD.lock().unwrap_or_else(|err| err.into_inner());
+        // Already gone if `release_all` removed it
+        if let Some(at) = held.iter().position(|(id, _)| *id == self.id) {
+            held.remove(at);
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Remove every lock this process holds, for exiting without dropping them.
+pub fn release_all() {
+    let mut held = HELD.lock().unwrap_or_else(|err| err.into_inner());
+    for (_, path) in held.drain(..) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// Take the lock at `path`, creating it with this process's id and `now`. A lock left by a
+/// process which has died is only taken over with `force`; one whose process is still running
+/// never is.
+pub fn acquire(path: &Path, force: bool, now: DateTime<Utc>) -> anyhow::Result<Lock> {
+    match create(path, now) {
+        Ok(lock) => return Ok(lock),
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+        Err(err) => anyhow::bail!("couldn't create {}: {}", path.display(), err),
+    }
+    let held = holder(path);
+    if !(force && held.stale) {
+        return Err(held.into());
+    }
+    eprintln!(
+        "taking over the lock of pid {}, which isn't running",
+        held.pid.unwrap_or_default()
+    );
+    take_over(path, &held)?;
+    // Another run taking it over at the same moment gets it first, and this one stops
+    create(path, now).map_err(|err| match err.kind() {
+        ErrorKind::AlreadyExists => holder(path).into(),
+        _ => anyhow::anyhow!("couldn't create {}: {}", path.display(), err),
+    })
+}
+
+// Move the stale lock `held` out of the way. Renaming is atomic, so of two runs taking it over
+// at once only one moves it, and the other finds it gone. If the file moved turns out not to be
+// the stale one, because another run took over and locked in between, it's put back.
+fn take_over(path: &Path, held: &Held) -> anyhow::Result<()> {
+    let claimed = path.with_extension(format!("lock.stale.{}", std::process::id()));
+    match fs::rename(path, &claimed) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => anyhow::bail!("couldn't move {} aside: {}", path.display(), err),
+    }
+    let moved = holder(&claimed);
+    if moved.pid != held.pid {
+        // Linking fails rather than replace a lock taken since
+        let _ = fs::hard_link(&claimed, path);
+        let _ = fs::remove_file(&claimed);
+        return Err(moved.into());
+    }
+    fs::remove_file(&claimed)?;
+    Ok(())
+}
+
+// Create the lock file, failing if it's already there
+fn create(path: &Path, now: DateTime<Utc>) -> std::io::Result<Lock> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    let lock = Lock {
+        path: path.to_path_buf(),
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+    };
+    HELD.lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .push((lock.id, lock.path.clone()));
+    writeln!(file, "{}\n{}", std::process::id(), now.to_rfc3339())?;
+    Ok(lock)
+}
+
+/// Who holds the lock at `path`, going by what it says. A file still being written reads as
+/// held by an unknown process, which is never stale.
+pub fn holder(path: &Path) -> Held {
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    let mut lines = contents.lines();
+    let pid = lines.next().and_then(|pid| pid.trim().parse().ok());
+    let started = lines
+        .next()
+        .map(|started| started.trim().to_string())
+        .filter(|started| !started.is_empty());
+    Held {
+        pid,
+        started,
+        stale: pid.and_then(is_running) == Some(false),
+    }
+}
+
+/// Whether process `pid` is running, `None` where that can't be told.
+#[cfg(unix)]
+pub fn is_running(pid: u32) -> Option<bool> {
+    let pid = libc::pid_t::try_from(pid).ok()?;
+    // Signal 0 checks the process exists without signalling it. Another user's process can't be
+    // signalled, but is running all the same.
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return Some(true);
+    }
+    match std::io::Error::last_os_error().raw_os_error() {
+        Some(libc::ESRCH) => Some(false),
+        Some(libc::EPERM) => Some(true),
+        _ => None,
+    }
+}
+
+/// Whether process `pid` is running, `None` where that can't be told.
+#[cfg(not(unix))]
+pub fn is_running(_pid: u32) -> Option<bool> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A lock path of its own for each test, in a directory cleared first
+    fn lock_path(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("gmail-stats-lock-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(LOCK_FILE)
+    }
+
+    // A pid no process can have, above any pid_max
+    const DEAD: u32 = i32::MAX as u32;
+
+    #[test]
+    fn a_lock_is_held_until_dropped() {
+        let path = lock_path("held");
+        let lock = acquire(&path, false, DateTime::UNIX_EPOCH).unwrap();
+
+        let held = acquire(&path, true, DateTime::UNIX_EPOCH)
+            .unwrap_err()
+            .downcast::<Held>()
+            .unwrap();
+        assert_eq!(held.pid, Some(std::process::id()));
+        assert_eq!(held.started.as_deref(), Some("1970-01-01T00:00:00+00:00"));
+        assert!(!held.stale);
+
+        drop(lock);
+        assert!(!path.exists());
+        acquire(&path, false, DateTime::UNIX_EPOCH).unwrap();
+    }
+
+    #[test]
+    fn a_stale_lock_is_only_taken_over_with_force() {
+        let path = lock_path("stale");
+        fs::write(&path, format!("{}\n2024-05-01T12:00:00+00:00\n", DEAD)).unwrap();
+
+        let held = acquire(&path, false, DateTime::UNIX_EPOCH)
+            .unwrap_err()
+            .downcast::<Held>()
+            .unwrap();
+        assert!(held.stale);
+
+        let _lock = acquire(&path, true, DateTime::UNIX_EPOCH).unwrap();
+        assert_eq!(holder(&path).pid, Some(std::process::id()));
+    }
+
+    #[test]
+    fn a_lock_of_a_running_process_is_never_taken_over() {
+        let path = lock_path("running");
+        fs::write(&path, format!("{}\n", std::process::id())).unwrap();
+
+        assert!(acquire(&path, true, DateTime::UNIX_EPOCH).is_err());
+        // A lock being written, with no pid yet, isn't stale either
+        fs::write(&path, "").unwrap();
+        assert!(acquire(&path, true, DateTime::UNIX_EPOCH).is_err());
+    }
+
+    #[test]
+    fn a_lock_taken_since_it_was_judged_stale_is_put_back() {
+        let path = lock_path("raced");
+        fs::write(&path, format!("{}\n", std::process::id())).unwrap();
+        let judged = Held {
+            pid: Some(DEAD),
+            started: None,
+            stale: true,
+        };
+
+        assert!(take_over(&path, &judged).is_err());
+        assert_eq!(holder(&path).pid, Some(std::process::id()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn running_processes_are_told_from_dead_ones() {
+        assert_eq!(is_running(std::process::id()), Some(true));
+        assert_eq!(is_running(DEAD), Some(false));
+    }
+}
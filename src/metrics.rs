@@ -0,0 +1,530 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use sqlx::{Pool, Sqlite};
+
+use crate::error::GmailStatsError;
+use crate::trace::{Trace, Track};
+
+/// Gmail API quota units charged per `messages.list` and per `messages.get` call.
+pub const LIST_QUOTA_UNITS: u64 = 5;
+pub const GET_QUOTA_UNITS: u64 = 5;
+
+/// Every `class` label `gmail_stats_run_errors` can carry. All are written on every run, zero or
+/// not, so the series don't come and go.
+pub const ERROR_CLASSES: [&str; 7] = [
+    "rate_limited",
+    "api",
+    "network",
+    "auth",
+    "database",
+    "parse",
+    "other",
+];
+
+/// The parts of a fetch timed separately, in the order they're reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// `messages.list` calls.
+    List,
+    /// `messages.get` calls.
+    Fetch,
+    /// Turning a message into what's recorded.
+    Parse,
+    /// Reading and writing stats.db.
+    Db,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 4] = [Phase::List, Phase::Fetch, Phase::Parse, Phase::Db];
+
+    /// What a span of this phase is called in `--trace-out` output.
+    pub fn span_name(self) -> &'static str {
+        match self {
+            Phase::List => "messages.list",
+            Phase::Fetch => "messages.get",
+            Phase::Parse => "parse",
+            Phase::Db => "db",
+        }
+    }
+
+    /// The `phase` label on `gmail_stats_run_phase_seconds`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::List => "list",
+            Phase::Fetch => "fetch",
+            Phase::Parse => "parse",
+            Phase::Db => "db",
+        }
+    }
+}
+
+/// Time spent in each `Phase`, for the listing page in hand and for the run so far.
+#[derive(Clone, Debug, Default)]
+pub struct PhaseTimer {
+    page: [Duration; 4],
+    run: [Duration; 4],
+    pages: u64,
+}
+
+impl PhaseTimer {
+    pub fn add(&mut self, phase: Phase, elapsed: Duration) {
+        self.page[phase as usize] += elapsed;
+        self.run[phase as usize] += elapsed;
+    }
+
+    /// Close off the current page, returning its times and starting the next from zero. The run
+    /// totals carry on.
+    pub fn finish_page(&mut self) -> [Duration; 4] {
+        self.pages += 1;
+        std::mem::take(&mut self.page)
+    }
+
+    /// Pages finished so far.
+    pub fn pages(&self) -> u64 {
+        self.pages
+    }
+
+    /// Time spent in `phase` over the whole run.
+    pub fn total(&self, phase: Phase) -> Duration {
+        self.run[phase as usize]
+    }
+
+    /// Time spent in every phase over the whole run.
+    pub fn run_total(&self) -> Duration {
+        self.run.iter().sum()
+    }
+}
+
+/// `times` as "list 0.120s, fetch 3.402s, ...", for `--timing`.
+pub fn breakdown(times: &[Duration; 4]) -> String {
+    Phase::ALL
+        .iter()
+        .map(|phase| {
+            format!(
+                "{} {:.3}s",
+                phase.name(),
+                times[*phase as usize].as_secs_f64()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Counters kept while fetching.
+#[derive(Clone, Debug, Default)]
+pub struct FetchStats {
+    pub list_calls: u64,
+    pub get_calls: u64,
+    /// Messages fetched and recorded this run, ignored ones included.
+    pub processed: u64,
+    /// Listed messages which had been seen before, and weren't fetched.
+    pub already_seen: u64,
+    /// Senders counted for the first time this run.
+    pub new_senders: u64,
+    /// Messages passed over rather than failing the run, recorded in the errors table.
+    pub skipped: u64,
+    /// Messages from my own addresses, recorded only if sent but not counted.
+    pub self_excluded: u64,
+    /// Messages with the Message-ID of one already recorded under another id, not counted again.
+    pub duplicates: u64,
+    /// Messages from senders erased with `forget`, marked seen without being recorded.
+    pub erased: u64,
+    /// Failed attempts by `ERROR_CLASSES` entry.
+    pub errors: BTreeMap<&'static str, u64>,
+    /// Transient errors since a message was last fetched, whether retried on the message in hand
+    /// or by starting the listing over, against `--max-retries`.
+    pub failures_in_a_row: u32,
+    /// Most fetched messages waiting for the database writer at once.
+    pub queue_peak: usize,
+    pub timing: PhaseTimer,
+    /// Response bytes read from Gmail, compressed as they came, if the source counted them.
+    pub bytes_received: Option<u64>,
+    /// Every timed span, kept only for `--trace-out`.
+    pub trace: Option<Trace>,
+}
+
+impl FetchStats {
+    pub fn quota_units(&self) -> u64 {
+        self.list_calls * LIST_QUOTA_UNITS + self.get_calls * GET_QUOTA_UNITS
+    }
+
+    /// Count the time since `started` towards `phase`.
+    pub fn time(&mut self, phase: Phase, started: Instant) {
+        self.time_span(phase, started, started.elapsed());
+    }
+
+    /// Count `elapsed` from `started` towards `phase`, for work that finished a while before
+    /// it was looked at.
+    pub fn time_span(&mut self, phase: Phase, started: Instant, elapsed: Duration) {
+        self.timing.add(phase, elapsed);
+        if let Some(trace) = &mut self.trace {
+            let track = match phase {
+                Phase::List => Track::Listing,
+                _ => Track::Messages,
+            };
+            trace.span(phase.span_name(), track, started, elapsed);
+        }
+    }
+
+    pub fn record_error(&mut self, err: &GmailStatsError) {
+        *self.errors.entry(error_class(err)).or_default() += 1;
+    }
+}
+
+/// Which of `ERROR_CLASSES` an error from a fetch falls under.
+pub fn error_class(err: &GmailStatsError) -> &'static str {
+    match err {
+        GmailStatsError::RateLimited { .. } | GmailStatsError::QuotaExhausted => "rate_limited",
+        GmailStatsError::Api { .. } => "api",
+        GmailStatsError::Network(_) => "network",
+        GmailStatsError::Auth(_) => "auth",
+        GmailStatsError::Db(_) => "database",
+        GmailStatsError::Parse(_) => "parse",
+        GmailStatsError::Config(_)
+        | GmailStatsError::Interrupted
+        | GmailStatsError::DeadlineReached => "other",
+    }
+}
+
+/// Totals over the whole database rather than one run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Totals {
+    pub messages_seen: i64,
+    pub senders: i64,
+}
+
+pub async fn totals(pool: &Pool<Sqlite>) -> Result<Totals, GmailStatsError> {
+    let (messages_seen,): (i64,) = sqlx::query_as("SELECT count(*) FROM seen_mails")
+        .fetch_one(pool)
+        .await?;
+    let (senders,): (i64,) = sqlx::query_as("SELECT count(*) FROM senders")
+        .fetch_one(pool)
+        .await?;
+    Ok(Totals {
+        messages_seen,
+        senders,
+    })
+}
+
+// One metric family: its HELP and TYPE lines, then a sample per (labels, value)
+fn family(out: &mut String, name: &str, help: &str, samples: &[(String, String)]) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} gauge", name).unwrap();
+    for (labels, value) in samples {
+        writeln!(out, "{}{} {}", name, labels, value).unwrap();
+    }
+}
+
+fn sample(value: impl ToString) -> Vec<(String, String)> {
+    vec![(String::new(), value.to_string())]
+}
+
+/// The metrics for a finished run in the Prometheus text exposition format. Every metric is a
+/// gauge describing the latest run or the database as it stands after it; the names and labels
+/// are part of the interface dashboards rely on, so don't rename them.
+pub fn render(stats: &FetchStats, totals: &Totals, duration: Duration, finished_at: i64) -> String {
+    let mut out = String::new();
+    family(
+        &mut out,
+        "gmail_stats_messages_seen",
+        "Messages fetched by any run so far.",
+        &sample(totals.messages_seen),
+    );
+    family(
+        &mut out,
+        "gmail_stats_senders",
+        "Distinct senders in the database.",
+        &sample(totals.senders),
+    );
+    family(
+        &mut out,
+        "gmail_stats_run_messages_processed",
+        "Messages fetched and recorded by the last run.",
+        &sample(stats.processed),
+    );
+    family(
+        &mut out,
+        "gmail_stats_run_malformed_skipped",
+        "Messages the last run skipped as malformed or unfetchable.",
+        &sample(stats.skipped),
+    );
+    family(
+        &mut out,
+        "gmail_stats_run_api_calls",
+        "Gmail API calls made by the last run, by method.",
+        &[
+            (
+                "{method=\"list\"}".to_string(),
+                stats.list_calls.to_string(),
+            ),
+            ("{method=\"get\"}".to_string(), stats.get_calls.to_string()),
+        ],
+    );
+    family(
+        &mut out,
+        "gmail_stats_run_quota_units",
+        "Gmail API quota units used by the last run.",
+        &sample(stats.quota_units()),
+    );
+    if let Some(bytes) = stats.bytes_received {
+        family(
+            &mut out,
+            "gmail_stats_run_bytes_received",
+            "Bytes of Gmail API responses the last run read off the wire.",
+            &sample(bytes),
+        );
+    }
+    family(
+        &mut out,
+        "gmail_stats_run_errors",
+        "Failed fetch attempts in the last run, by class.",
+        &ERROR_CLASSES
+            .iter()
+            .map(|class| {
+                (
+                    format!("{{class=\"{}\"}}", class),
+                    stats.errors.get(class).copied().unwrap_or(0).to_string(),
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+    family(
+        &mut out,
+        "gmail_stats_run_phase_seconds",
+        "Time the last run spent listing, fetching, parsing and in the database.",
+        &Phase::ALL
+            .iter()
+            .map(|phase| {
+                (
+                    format!("{{phase=\"{}\"}}", phase.name()),
+                    format!("{:.3}", stats.timing.total(*phase).as_secs_f64()),
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+    family(
+        &mut out,
+        "gmail_stats_run_queue_peak",
+        "Most fetched messages the last run had waiting for the database writer at once.",
+        &sample(stats.queue_peak),
+    );
+    family(
+        &mut out,
+        "gmail_stats_run_duration_seconds",
+        "Wall time of the last run.",
+        &sample(format!("{:.3}", duration.as_secs_f64())),
+    );
+    family(
+        &mut out,
+        "gmail_stats_run_finished_timestamp_seconds",
+        "When the last run finished, in seconds since the epoch.",
+        &sample(finished_at),
+    );
+    out
+}
+
+/// Replace `path` with `contents` by writing a temporary file beside it and renaming it over,
+/// so a collector never reads a partial file. The temporary name starts with a dot and doesn't
+/// end in `.prom`, which node_exporter's textfile collector skips.
+pub fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "metrics path has no file name")
+    })?;
+    let temporary = path.with_file_name(format!(".{}.tmp", name.to_string_lossy()));
+    let mut file = File::create(&temporary)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temporary, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A run which listed twice, fetched three messages, hit a rate limit once and spent a second
+    // and a half in Gmail
+    fn run() -> FetchStats {
+        let mut stats = FetchStats {
+            list_calls: 2,
+            get_calls: 3,
+            processed: 3,
+            skipped: 1,
+            queue_peak: 2,
+            bytes_received: Some(40_960),
+            ..Default::default()
+        };
+        stats.record_error(&GmailStatsError::RateLimited { retry_after: None });
+        stats.timing.add(Phase::List, Duration::from_millis(250));
+        stats.timing.add(Phase::Fetch, Duration::from_millis(1_250));
+        stats.timing.add(Phase::Db, Duration::from_micros(1_750));
+        stats
+    }
+
+    #[test]
+    fn phase_times_add_up_per_page_and_per_run() {
+        let mut timer = PhaseTimer::default();
+        timer.add(Phase::List, Duration::from_millis(100));
+        timer.add(Phase::Fetch, Duration::from_millis(300));
+        timer.add(Phase::Fetch, Duration::from_millis(200));
+
+        let first = timer.finish_page();
+        assert_eq!(
+            first,
+            [
+                Duration::from_millis(100),
+                Duration::from_millis(500),
+                Duration::ZERO,
+                Duration::ZERO
+            ]
+        );
+        assert_eq!(
+            breakdown(&first),
+            "list 0.100s, fetch 0.500s, parse 0.000s, db 0.000s"
+        );
+
+        timer.add(Phase::Fetch, Duration::from_millis(50));
+        timer.add(Phase::Db, Duration::from_millis(25));
+        let second = timer.finish_page();
+        assert_eq!(second[Phase::List as usize], Duration::ZERO);
+        assert_eq!(second[Phase::Fetch as usize], Duration::from_millis(50));
+
+        assert_eq!(timer.pages(), 2);
+        assert_eq!(timer.total(Phase::Fetch), Duration::from_millis(550));
+        assert_eq!(timer.total(Phase::Db), Duration::from_millis(25));
+        assert_eq!(timer.run_total(), Duration::from_millis(675));
+        // Time in a page not yet finished counts towards the run already
+        timer.add(Phase::Parse, Duration::from_millis(5));
+        assert_eq!(timer.total(Phase::Parse), Duration::from_millis(5));
+        assert_eq!(timer.pages(), 2);
+    }
+
+    #[test]
+    fn timed_spans_go_to_the_timer_and_the_trace() {
+        let mut stats = FetchStats {
+            trace: Some(Trace::default()),
+            ..Default::default()
+        };
+        let started = Instant::now();
+        stats.time_span(Phase::List, started, Duration::from_millis(40));
+        stats.time_span(Phase::Fetch, started, Duration::from_millis(60));
+        assert_eq!(stats.timing.run_total(), Duration::from_millis(100));
+        let json = stats.trace.unwrap().to_json();
+        let spans = json["traceEvents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|event| event["ph"] == "X")
+            .map(|event| {
+                (
+                    event["name"].as_str().unwrap(),
+                    event["tid"].as_u64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(spans, [("messages.list", 1), ("messages.get", 2)]);
+    }
+
+    #[test]
+    fn a_run_is_written_in_the_exposition_format() {
+        let totals = Totals {
+            messages_seen: 1_200,
+            senders: 87,
+        };
+        let out = render(&run(), &totals, Duration::from_millis(2_345), 1_718_010_000);
+        assert_eq!(
+            out,
+            r#"# HELP gmail_stats_messages_seen Messages fetched by any run so far.
+# TYPE gmail_stats_messages_seen gauge
+gmail_stats_messages_seen 1200
+# HELP gmail_stats_senders Distinct senders in the database.
+# TYPE gmail_stats_senders gauge
+gmail_stats_senders 87
+# HELP gmail_stats_run_messages_processed Messages fetched and recorded by the last run.
+# TYPE gmail_stats_run_messages_processed gauge
+gmail_stats_run_messages_processed 3
+# HELP gmail_stats_run_malformed_skipped Messages the last run skipped as malformed or unfetchable.
+# TYPE gmail_stats_run_malformed_skipped gauge
+gmail_stats_run_malformed_skipped 1
+# HELP gmail_stats_run_api_calls Gmail API calls made by the last run, by method.
+# TYPE gmail_stats_run_api_calls gauge
+gmail_stats_run_api_calls{method="list"} 2
+gmail_stats_run_api_calls{method="get"} 3
+# HELP gmail_stats_run_quota_units Gmail API quota units used by the last run.
+# TYPE gmail_stats_run_quota_units gauge
+gmail_stats_run_quota_units 25
+# HELP gmail_stats_run_bytes_received Bytes of Gmail API responses the last run read off the wire.
+# TYPE gmail_stats_run_bytes_received gauge
+gmail_stats_run_bytes_received 40960
+# HELP gmail_stats_run_errors Failed fetch attempts in the last run, by class.
+# TYPE gmail_stats_run_errors gauge
+gmail_stats_run_errors{class="rate_limited"} 1
+gmail_stats_run_errors{class="api"} 0
+gmail_stats_run_errors{class="network"} 0
+gmail_stats_run_errors{class="auth"} 0
+gmail_stats_run_errors{class="database"} 0
+gmail_stats_run_errors{class="parse"} 0
+gmail_stats_run_errors{class="other"} 0
+# HELP gmail_stats_run_phase_seconds Time the last run spent listing, fetching, parsing and in the database.
+# TYPE gmail_stats_run_phase_seconds gauge
+gmail_stats_run_phase_seconds{phase="list"} 0.250
+gmail_stats_run_phase_seconds{phase="fetch"} 1.250
+gmail_stats_run_phase_seconds{phase="parse"} 0.000
+gmail_stats_run_phase_seconds{phase="db"} 0.002
+# HELP gmail_stats_run_queue_peak Most fetched messages the last run had waiting for the database writer at once.
+# TYPE gmail_stats_run_queue_peak gauge
+gmail_stats_run_queue_peak 2
+# HELP gmail_stats_run_duration_seconds Wall time of the last run.
+# TYPE gmail_stats_run_duration_seconds gauge
+gmail_stats_run_duration_seconds 2.345
+# HELP gmail_stats_run_finished_timestamp_seconds When the last run finished, in seconds since the epoch.
+# TYPE gmail_stats_run_finished_timestamp_seconds gauge
+gmail_stats_run_finished_timestamp_seconds 1718010000
+"#
+        );
+    }
+
+    #[test]
+    fn received_bytes_are_left_out_when_not_counted() {
+        let stats = FetchStats {
+            bytes_received: None,
+            ..run()
+        };
+        let out = render(&stats, &Totals::default(), Duration::ZERO, 0);
+        assert!(!out.contains("gmail_stats_run_bytes_received"));
+        // Every sample still follows its family's HELP and TYPE
+        let mut family = "";
+        for line in out.lines() {
+            if let Some(help) = line.strip_prefix("# HELP ") {
+                family = help.split(' ').next().unwrap();
+            } else if let Some(kind) = line.strip_prefix("# TYPE ") {
+                assert_eq!(kind, format!("{} gauge", family));
+            } else {
+                let (name, value) = line.rsplit_once(' ').unwrap();
+                assert!(name == family || name.starts_with(&format!("{}{{", family)));
+                assert!(value.parse::<f64>().is_ok(), "{}", line);
+            }
+        }
+    }
+
+    #[test]
+    fn the_file_is_replaced_whole() {
+        let dir = std::env::temp_dir().join(format!("gmail-stats-metrics-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("gmail_stats.prom");
+        write_atomically(&path, "old\n").unwrap();
+        write_atomically(&path, "new\n").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new\n");
+        let names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["gmail_stats.prom"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
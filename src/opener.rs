@@ -0,0 +1,159 @@
+use std::io;
+use std::path::{self, Path};
+use std::process::{Command, Stdio};
+
+/// Open `path` in the default browser without waiting for it. When there's no way to, such as
+/// over SSH to a headless server, say where the file is instead.
+pub fn open(path: &Path) -> anyhow::Result<()> {
+    let url = file_url(path)?;
+    if !has_display() {
+        eprintln!("no display to open a browser on, the report is at {}", url);
+        return Ok(());
+    }
+    match launch(&url) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            eprintln!("no browser opener found, the report is at {}", url);
+            Ok(())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The `file://` URL for `path`, made absolute against the current directory.
+pub fn file_url(path: &Path) -> io::Result<String> {
+    let absolute = path::absolute(path)?;
+    Ok(url_from(&absolute.to_string_lossy(), cfg!(windows)))
+}
+
+// The URL for an absolute path, which uses backslashes and starts with a drive letter or a UNC
+// share when `windows`:
+// /home/me/my report.html -> file:///home/me/my%20report.html
+// C:\Users\me\report.html -> file:///C:/Users/me/report.html
+// \\server\share\report.html -> file://server/share/report.html
+fn url_from(path: &str, windows: bool) -> String {
+    if !windows {
+        return format!("file://{}", encode(path));
+    }
+    let path = path.replace('\\', "/");
+    // Verbatim paths, as canonicalize returns, are \\?\C:\… or \\?\UNC\server\share\…
+    let path = if let Some(share) = path.strip_prefix("//?/UNC/") {
+        format!("//{}", share)
+    } else if let Some(local) = path.strip_prefix("//?/") {
+        local.to_string()
+    } else {
+        path
+    };
+    match path.strip_prefix("//") {
+        Some(share) => format!("file://{}", encode(share)),
+        None => format!("file:///{}", encode(&path)),
+    }
+}
+
+// Percent-encode everything but unreserved characters, `/` and `:`. That covers spaces, `#` and
+// `?`, which would otherwise end the path, and `&`, which cmd.exe would otherwise split on.
+fn encode(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+// Desktop Linux and the BSDs have an X11 or Wayland display; macOS and Windows always do
+fn has_display() -> bool {
+    if cfg!(any(target_os = "macos", target_os = "windows")) {
+        return true;
+    }
+    ["DISPLAY", "WAYLAND_DISPLAY"]
+        .iter()
+        .any(|name| std::env::var_os(name).is_some_and(|value| !value.is_empty()))
+}
+
+fn launch(url: &str) -> io::Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(target_os = "windows") {
+        // The empty argument is the window title, which start otherwise takes from a quoted URL
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        Command::new("xdg-open")
+    };
+    command
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unix_paths_become_file_urls() {
+        assert_eq!(
+            url_from("/home/me/report.html", false),
+            "file:///home/me/report.html"
+        );
+        assert_eq!(
+            url_from("/home/me/my report #2?.html", false),
+            "file:///home/me/my%20report%20%232%3F.html"
+        );
+        assert_eq!(
+            url_from("/tmp/rapport-été & co.html", false),
+            "file:///tmp/rapport-%C3%A9t%C3%A9%20%26%20co.html"
+        );
+        // Backslashes are just characters outside Windows
+        assert_eq!(url_from("/tmp/a\\b.html", false), "file:///tmp/a%5Cb.html");
+    }
+
+    #[test]
+    fn windows_paths_become_file_urls() {
+        assert_eq!(
+            url_from("C:\\Users\\me\\report.html", true),
+            "file:///C:/Users/me/report.html"
+        );
+        assert_eq!(
+            url_from("C:\\Users\\me\\My Reports\\a&b.html", true),
+            "file:///C:/Users/me/My%20Reports/a%26b.html"
+        );
+        assert_eq!(
+            url_from("\\\\server\\share\\report.html", true),
+            "file://server/share/report.html"
+        );
+    }
+
+    #[test]
+    fn verbatim_windows_paths_lose_their_prefix() {
+        assert_eq!(
+            url_from("\\\\?\\C:\\Users\\me\\report.html", true),
+            "file:///C:/Users/me/report.html"
+        );
+        assert_eq!(
+            url_from("\\\\?\\UNC\\server\\share\\report.html", true),
+            "file://server/share/report.html"
+        );
+    }
+
+    #[test]
+    fn relative_paths_are_made_absolute() {
+        let cwd = std::env::current_dir().unwrap();
+        let url = file_url(Path::new("report.html")).unwrap();
+        assert_eq!(
+            url,
+            url_from(&cwd.join("report.html").to_string_lossy(), cfg!(windows))
+        );
+        assert!(url.starts_with("file:///"));
+        assert!(url.ends_with("/report.html"));
+    }
+}
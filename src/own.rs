@@ -0,0 +1,278 @@
+use std::collections::HashSet;
+
+use crate::config::Config;
+use crate::domains::{domain_of, registrable_domain};
+
+/// What's mine: my addresses, from `my_addresses`, `[identity]` addresses and any aliases folded
+/// onto one of them, compared after `normalize` so `Jane.Doe+notes@gmail.com` is still me; every
+/// address at a catch-all domain; and my own domains, whose senders are internal. Built once when
+/// the config is loaded, and empty with no config.
+#[derive(Clone, Debug, Default)]
+pub struct Identity {
+    /// My addresses as configured, lowercased.
+    primary: HashSet<String>,
+    /// The same and the aliases folded onto them, normalized.
+    addresses: HashSet<String>,
+    /// Registrable domains of my own domains.
+    domains: Vec<String>,
+    /// Domains where any address is mine, lowercased.
+    catch_all: Vec<String>,
+}
+
+impl Identity {
+    pub fn new(config: &Config) -> Identity {
+        let identity = &config.identity_config;
+        let primary = config
+            .my_addresses
+            .iter()
+            .chain(&identity.addresses)
+            .map(|address| address.trim().to_lowercase())
+            .collect::<HashSet<_>>();
+        let mut addresses = primary
+            .iter()
+            .map(|address| normalize(address))
+            .collect::<HashSet<_>>();
+        let aliased = config
+            .aliases
+            .iter()
+            .filter(|(_, canonical)| addresses.contains(&normalize(canonical)))
+            .map(|(alias, _)| normalize(alias))
+            .collect::<Vec<_>>();
+        addresses.extend(aliased);
+        let mut domains = config
+            .own_domains
+            .iter()
+            .chain(&identity.domains)
+            .map(|domain| registrable_domain(domain.trim_start_matches('@')))
+            .collect::<Vec<_>>();
+        domains.sort();
+        domains.dedup();
+        Identity {
+            primary,
+            addresses,
+            domains,
+            catch_all: identity
+                .catch_all_domains
+                .iter()
+                .map(|domain| domain.trim().trim_start_matches('@').to_lowercase())
+                .collect(),
+        }
+    }
+
+    /// Whether `address` is one of mine: one of my addresses with or without a plus-tag, one of
+    /// their aliases, or any address at a catch-all domain or its subdomains.
+    pub fn is_mine(&self, address: &str) -> bool {
+        if self.addresses.contains(&normalize(address)) {
+            return true;
+        }
+        domain_of(address).is_some_and(|domain| self.is_catch_all(&domain))
+    }
+
+    /// The alias `address` is, when it's mine: the address as written, lowercased but with its
+    /// plus-tag and dots kept, so each one I've handed out stays apart.
+    pub fn matched_alias(&self, address: &str) -> Option<String> {
+        self.is_mine(address).then(|| address.trim().to_lowercase())
+    }
+
+    /// The alias of the first of `addresses` which is mine.
+    pub fn first_alias<'a>(&self, addresses: impl IntoIterator<Item = &'a str>) -> Option<String> {
+        addresses
+            .into_iter()
+            .find_map(|address| self.matched_alias(address))
+    }
+
+    /// Whether `address` is one of my addresses exactly as configured, rather than a plus-tag,
+    /// an alias or a catch-all address, so it's handed out to everyone.
+    pub fn is_primary(&self, address: &str) -> bool {
+        self.primary.contains(&address.trim().to_lowercase())
+    }
+
+    /// Whether `domain` belongs to one of my own domains, compared by registrable domain so any
+    /// subdomain counts, or is a catch-all domain. Nothing is internal when neither is configured.
+    pub fn is_internal(&self, domain: &str) -> bool {
+        self.domains.contains(&registrable_domain(domain)) || self.is_catch_all(domain)
+    }
+
+    // A catch-all domain or one of its subdomains
+    fn is_catch_all(&self, domain: &str) -> bool {
+        let domain = domain.trim().to_lowercase();
+        self.catch_all
+            .iter()
+            .any(|catch_all| domain == *catch_all || domain.ends_with(&format!(".{}", catch_all)))
+    }
+
+    /// Registrable domains of my own domains.
+    pub fn domains(&self) -> &[String] {
+        &self.domains
+    }
+
+    /// Everything that decides what's mine, the same whenever the configuration is, for telling
+    /// whether mail classified before was classified with the identity in use now.
+    pub fn fingerprint(&self) -> String {
+        let sorted = |items: &mut dyn Iterator<Item = &String>| {
+            let mut items = items.map(String::as_str).collect::<Vec<_>>();
+            items.sort_unstable();
+            items.join(",")
+        };
+        [
+            sorted(&mut self.primary.iter()),
+            sorted(&mut self.addresses.iter()),
+            self.domains.join(","),
+            self.catch_all.join(","),
+        ]
+        .join(";")
+    }
+
+    pub fn has_addresses(&self) -> bool {
+        !self.addresses.is_empty() || !self.catch_all.is_empty()
+    }
+
+    pub fn has_domains(&self) -> bool {
+        !self.domains.is_empty() || !self.catch_all.is_empty()
+    }
+}
+
+/// Lowercased with any plus-tag dropped from the local part, and for Gmail, which also ignores
+/// dots and takes googlemail.com for gmail.com, folded onto the plain address.
+pub fn normalize(address: &str) -> String {
+    let address = address.trim().to_lowercase();
+    let Some((local, domain)) = address.rsplit_once('@') else {
+        return address;
+    };
+    let local = local.split('+').next().unwrap_or_default();
+    if domain != "gmail.com" && domain != "googlemail.com" {
+        return format!("{}@{}", local, domain);
+    }
+    format!("{}@gmail.com", local.replace('.', ""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity() -> Identity {
+        let config: Config = toml::from_str(
+            r#"
+            my_addresses = ["Jane.Doe@gmail.com"]
+            own_domains = ["@corp.example.co.uk"]
+
+            [identity]
+            addresses = ["me@example.org"]
+            domains = ["example.net"]
+            catch_all_domains = ["@Jane.dev"]
+
+            [aliases]
+            "old@example.com" = "me@example.org"
+            "news@example.com" = "someone@example.com"
+            "#,
+        )
+        .unwrap();
+        Identity::new(&config)
+    }
+
+    #[test]
+    fn addresses_are_mine_with_tags_aliases_and_catch_all_domains() {
+        let identity = identity();
+        let matrix = [
+            // Configured addresses, in any case and Gmail's spellings of them
+            ("jane.doe@gmail.com", true),
+            (" JANE.DOE@GMAIL.COM ", true),
+            ("janedoe@gmail.com", true),
+            ("jane.doe+lists@googlemail.com", true),
+            ("me@example.org", true),
+            ("me+shop@example.org", true),
+            // Dots only fold away at Gmail
+            ("m.e@example.org", false),
+            ("jane.doe@example.com", false),
+            // Aliases count for the address they're folded onto, if it's mine
+            ("old@example.com", true),
+            ("old+tag@example.com", true),
+            ("news@example.com", false),
+            // Anything at a catch-all domain or its subdomains
+            ("whoever@jane.dev", true),
+            ("whoever@mail.Jane.dev", true),
+            ("whoever@notjane.dev", false),
+            // Own domains make senders internal, not mine
+            ("boss@corp.example.co.uk", false),
+            ("colleague@example.net", false),
+            ("not an address", false),
+            ("", false),
+        ];
+
+        for (address, mine) in matrix {
+            assert_eq!(identity.is_mine(address), mine, "{:?}", address);
+        }
+    }
+
+    #[test]
+    fn domains_are_internal_by_registrable_domain() {
+        let identity = identity();
+        let matrix = [
+            ("corp.example.co.uk", true),
+            ("mail.corp.example.co.uk", true),
+            // The registrable domain of corp.example.co.uk is example.co.uk
+            ("other.example.co.uk", true),
+            ("co.uk", false),
+            ("example.net", true),
+            ("EU.Example.net", true),
+            ("jane.dev", true),
+            ("mail.jane.dev", true),
+            ("example.com", false),
+            ("gmail.com", false),
+        ];
+
+        for (domain, internal) in matrix {
+            assert_eq!(identity.is_internal(domain), internal, "{:?}", domain);
+        }
+        assert_eq!(identity.domains(), ["example.co.uk", "example.net"]);
+    }
+
+    #[test]
+    fn an_alias_keeps_its_tag_and_dots() {
+        let identity = identity();
+
+        assert_eq!(
+            identity.matched_alias(" Jane.Doe+Lists@gmail.com"),
+            Some("jane.doe+lists@gmail.com".to_string())
+        );
+        assert_eq!(
+            identity.matched_alias("shop@jane.dev"),
+            Some("shop@jane.dev".to_string())
+        );
+        assert_eq!(identity.matched_alias("someone@example.com"), None);
+        assert_eq!(
+            identity.first_alias(["someone@example.com", "ME+x@example.org", "old@example.com"]),
+            Some("me+x@example.org".to_string())
+        );
+        assert_eq!(identity.first_alias([]), None);
+    }
+
+    #[test]
+    fn only_addresses_as_configured_are_primary() {
+        let identity = identity();
+
+        assert!(identity.is_primary("jane.doe@gmail.com"));
+        assert!(identity.is_primary("ME@example.org"));
+        assert!(!identity.is_primary("janedoe@gmail.com"));
+        assert!(!identity.is_primary("me+shop@example.org"));
+        assert!(!identity.is_primary("old@example.com"));
+        assert!(!identity.is_primary("any@jane.dev"));
+    }
+
+    #[test]
+    fn nothing_is_mine_without_a_config() {
+        let identity = Identity::default();
+
+        assert!(!identity.is_mine("jane.doe@gmail.com"));
+        assert!(!identity.is_internal("example.com"));
+        assert!(!identity.has_addresses());
+        assert!(!identity.has_domains());
+    }
+
+    #[test]
+    fn gmail_addresses_fold_onto_the_plain_one() {
+        assert_eq!(normalize("Jane.Doe+x@GoogleMail.com"), "janedoe@gmail.com");
+        assert_eq!(normalize("Jane.Doe+x@example.com"), "jane.doe@example.com");
+        assert_eq!(normalize("no-at-sign"), "no-at-sign");
+    }
+}
@@ -0,0 +1,239 @@
+use google_gmail1::api::MessagePart;
+
+/// A file attached to a message, found by walking its MIME parts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attachment {
+    pub mime_type: String,
+    pub size: i64,
+    /// Shown in the body rather than offered as a download, e.g. images in HTML newsletters.
+    pub inline: bool,
+}
+
+// Case-insensitive header lookup on a single part
+pub fn part_header<'a>(part: &'a MessagePart, name: &str) -> Option<&'a str> {
+    part.headers
+        .as_ref()?
+        .iter()
+        .find(|header| {
+            header
+                .name
+                .as_deref()
+                .is_some_and(|n| n.eq_ignore_ascii_case(name))
+        })
+        .and_then(|header| header.value.as_deref())
+}
+
+fn is_inline(part: &MessagePart) -> bool {
+    match part_header(part, "Content-Disposition") {
+        Some(disposition) => disposition
+            .trim_start()
+            .to_ascii_lowercase()
+            .starts_with("inline"),
+        // Without a disposition, a Content-ID means the part is referenced from the body
+        None => part_header(part, "Content-ID").is_some(),
+    }
+}
+
+/// Every attachment in `part`, recursing into nested multiparts. Any leaf part with a filename
+/// counts as an attachment.
+pub fn attachments(part: &MessagePart) -> Vec<Attachment> {
+    let mut found = Vec::new();
+    walk(part, &mut found);
+    found
+}
+
+fn walk(part: &MessagePart, found: &mut Vec<Attachment>) {
+    if let Some(parts) = &part.parts {
+        for child in parts {
+            walk(child, found);
+        }
+        return;
+    }
+
+    if part.filename.as_deref().unwrap_or_default().is_empty() {
+        return;
+    }
+
+    found.push(Attachment {
+        mime_type: part
+            .mime_type
+            .as_deref()
+            .unwrap_or("application/octet-stream")
+            .to_ascii_lowercase(),
+        size: part
+            .body
+            .as_ref()
+            .and_then(|body| body.size)
+            .unwrap_or_default()
+            .into(),
+        inline: is_inline(part),
+    });
+}
+
+/// Coarse grouping of MIME types for the attachment breakdown.
+pub fn kind(mime_type: &str) -> &'static str {
+    match mime_type {
+        "application/pdf" => "pdf",
+        "text/calendar" | "application/ics" => "calendar",
+        t if t.starts_with("image/") => "image",
+        t if t.starts_with("audio/") || t.starts_with("video/") => "media",
+        "application/zip" | "application/gzip" | "application/x-zip-compressed" => "archive",
+        t if t.contains("word") || t.contains("excel") || t.contains("spreadsheet") => "document",
+        t if t.contains("presentation") || t.contains("powerpoint") => "document",
+        "text/plain" | "text/csv" | "text/html" => "text",
+        _ => "other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use google_gmail1::api::{MessagePartBody, MessagePartHeader};
+
+    use super::*;
+
+    fn multipart(mime_type: &str, parts: Vec<MessagePart>) -> MessagePart {
+        MessagePart {
+            mime_type: Some(mime_type.to_string()),
+            parts: Some(parts),
+            ..Default::default()
+        }
+    }
+
+    fn leaf(mime_type: &str, filename: &str, size: i32, headers: &[(&str, &str)]) -> MessagePart {
+        MessagePart {
+            mime_type: Some(mime_type.to_string()),
+            filename: Some(filename.to_string()),
+            body: Some(MessagePartBody {
+                size: Some(size),
+                ..Default::default()
+            }),
+            headers: Some(
+                headers
+                    .iter()
+                    .map(|(name, value)| MessagePartHeader {
+                        name: Some(name.to_string()),
+                        value: Some(value.to_string()),
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    // A newsletter-style message: text and HTML alternatives with an embedded logo, a PDF and a
+    // calendar invite attached, and a forwarded message holding a spreadsheet
+    fn mixed() -> MessagePart {
+        multipart(
+            "multipart/mixed",
+            vec![
+                multipart(
+                    "multipart/related",
+                    vec![
+                        multipart(
+                            "multipart/alternative",
+                            vec![
+                                leaf("text/plain", "", 120, &[]),
+                                leaf("text/html", "", 480, &[]),
+                            ],
+                        ),
+                        leaf("image/png", "logo.png", 2_000, &[("Content-ID", "<logo>")]),
+                    ],
+                ),
+                leaf(
+                    "Application/PDF",
+                    "invoice.pdf",
+                    50_000,
+                    &[("Content-Disposition", "attachment; filename=invoice.pdf")],
+                ),
+                leaf(
+                    "text/calendar",
+                    "invite.ics",
+                    900,
+                    &[("content-disposition", "  INLINE; filename=invite.ics")],
+                ),
+                multipart(
+                    "message/rfc822",
+                    vec![multipart(
+                        "multipart/mixed",
+                        vec![
+                            leaf("text/plain", "", 60, &[]),
+                            leaf(
+                                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+                                "q3.xlsx",
+                                12_000,
+                                &[("Content-Disposition", "attachment")],
+                            ),
+                        ],
+                    )],
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn nested_multiparts_are_walked_for_files() {
+        let found = attachments(&mixed());
+
+        let summary = found
+            .iter()
+            .map(|a| (kind(&a.mime_type), a.size, a.inline))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("image", 2_000, true),
+                ("pdf", 50_000, false),
+                ("calendar", 900, true),
+                ("document", 12_000, false)
+            ]
+        );
+        assert_eq!(found[1].mime_type, "application/pdf");
+    }
+
+    #[test]
+    fn a_message_without_files_has_no_attachments() {
+        let part = multipart(
+            "multipart/alternative",
+            vec![
+                leaf("text/plain", "", 100, &[]),
+                leaf("text/html", "", 300, &[]),
+            ],
+        );
+        assert!(attachments(&part).is_empty());
+        assert!(attachments(&MessagePart::default()).is_empty());
+    }
+
+    #[test]
+    fn a_file_without_a_type_or_size_still_counts() {
+        let part = MessagePart {
+            filename: Some("mystery".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            attachments(&part),
+            [Attachment {
+                mime_type: "application/octet-stream".to_string(),
+                size: 0,
+                inline: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn inline_parts_are_left_out_when_asked() {
+        let mut message = crate::testsupport::message("m1", &[("From", "a@example.com")], &[]);
+        message.payload.as_mut().unwrap().parts = Some(vec![mixed()]);
+        let parse = |exclude_inline| {
+            let options = crate::parse::ParseOptions {
+                exclude_inline,
+                ..Default::default()
+            };
+            crate::parse::MessageInfo::from_message(&message, &options).unwrap()
+        };
+
+        assert_eq!(parse(false).attachments.len(), 4);
+        let excluded = parse(true);
+        assert_eq!(excluded.attachments.len(), 2);
+        assert_eq!(excluded.attachment_bytes(), 62_000);
+    }
+}
@@ -0,0 +1,236 @@
+use google_gmail1::api::Message;
+
+/// The server whose `Authentication-Results` we trust. Other copies of the header may have been
+/// added by anyone along the way, including the sender.
+const AUTHSERV_ID: &str = "mx.google.com";
+
+/// SPF, DKIM and DMARC results as Gmail recorded them, lowercased (`pass`, `fail`, `softfail`,
+/// `none`, ...). `None` when the header didn't mention the mechanism.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AuthResults {
+    pub spf: Option<String>,
+    pub dkim: Option<String>,
+    pub dmarc: Option<String>,
+}
+
+/// Results from Gmail's `Authentication-Results` header, if there is one.
+pub fn auth_results(message: &Message) -> Option<AuthResults> {
+    message
+        .payload
+        .as_ref()?
+        .headers
+        .as_ref()?
+        .iter()
+        .filter(|header| {
+            header
+                .name
+                .as_deref()
+                .is_some_and(|n| n.eq_ignore_ascii_case("Authentication-Results"))
+        })
+        .filter_map(|header| header.value.as_deref())
+        .find_map(parse)
+}
+
+/// Parse an `Authentication-Results` value from `AUTHSERV_ID`, best effort. The header is a list
+/// of `method=result property=value ...` clauses separated by `;`, with comments in parentheses
+/// anywhere. Unknown methods and anything malformed are skipped. When a method appears more than
+/// once, as DKIM does for mail with several signatures, any pass wins.
+pub fn parse(value: &str) -> Option<AuthResults> {
+    let value = strip_comments(value);
+    let mut clauses = value.split(';');
+    let authserv_id = clauses.next()?.split_whitespace().next()?;
+    if !authserv_id.eq_ignore_ascii_case(AUTHSERV_ID) {
+        return None;
+    }
+
+    let mut results = AuthResults::default();
+    for clause in clauses {
+        // Whitespace is allowed around the `=`
+        let Some((method, rest)) = clause.split_once('=') else {
+            continue;
+        };
+        let method = method.trim();
+        let Some(result) = rest.split_whitespace().next() else {
+            continue;
+        };
+        if method.contains(char::is_whitespace) {
+            continue;
+        }
+        // A method may carry a version, `dkim/1=pass`
+        let method = method
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let result = result.trim_matches('"').to_ascii_lowercase();
+        let slot = match method.as_str() {
+            "spf" => &mut results.spf,
+            "dkim" => &mut results.dkim,
+            "dmarc" => &mut results.dmarc,
+            _ => continue,
+        };
+        if slot.is_none() || result == "pass" {
+            *slot = Some(result);
+        }
+    }
+    Some(results)
+}
+
+// Drop (possibly nested) comments, leaving quoted strings alone
+fn strip_comments(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let (mut depth, mut quoted, mut escaped) = (0, false, false);
+    for c in value.chars() {
+        if escaped {
+            escaped = false;
+            if depth == 0 {
+                out.push(c);
+            }
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '"' if depth == 0 => quoted = !quoted,
+            '(' if !quoted => {
+                depth += 1;
+                continue;
+            }
+            ')' if !quoted && depth > 0 => {
+                depth -= 1;
+                continue;
+            }
+            _ => {}
+        }
+        if depth == 0 {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testsupport::message;
+
+    fn results(spf: Option<&str>, dkim: Option<&str>, dmarc: Option<&str>) -> AuthResults {
+        AuthResults {
+            spf: spf.map(str::to_string),
+            dkim: dkim.map(str::to_string),
+            dmarc: dmarc.map(str::to_string),
+        }
+    }
+
+    // SPF, DKIM and DMARC, as `AuthResults` holds them
+    type Expected = (
+        Option<&'static str>,
+        Option<&'static str>,
+        Option<&'static str>,
+    );
+
+    /// Header values as Gmail writes them, and what each parses to.
+    const CORPUS: &[(&str, Expected)] = &[
+        (
+            "mx.google.com;\r\n       dkim=pass header.i=@example.com header.s=s1 header.b=Ab12Cd34;\r\n       \
+            spf=pass (google.com: domain of bounce@mail.example.com designates 192.0.2.1 as \
+            permitted sender) smtp.mailfrom=bounce@mail.example.com;\r\n       \
+            dmarc=pass (p=REJECT sp=REJECT dis=NONE) header.from=example.com",
+            (Some("pass"), Some("pass"), Some("pass")),
+        ),
+        (
+            "mx.google.com; spf=softfail (google.com: domain of transitioning \
+            jane@example.org does not designate 198.51.100.7 as permitted sender) \
+            smtp.mailfrom=jane@example.org; dmarc=fail (p=NONE sp=NONE dis=NONE) \
+            header.from=example.org",
+            (Some("softfail"), None, Some("fail")),
+        ),
+        (
+            "mx.google.com; spf=neutral (google.com: 203.0.113.9 is neither permitted nor \
+            denied by best guess record for domain of news@example.net) \
+            smtp.mailfrom=news@example.net",
+            (Some("neutral"), None, None),
+        ),
+        (
+            "mx.google.com; spf=none (google.com: example.biz does not designate permitted \
+            sender hosts) smtp.mailfrom=shop@example.biz",
+            (Some("none"), None, None),
+        ),
+        // Several signatures: any that passed counts
+        (
+            "mx.google.com; dkim=fail header.i=@esp.example header.s=old; \
+            dkim=pass header.i=@example.com header.s=new; dkim=neutral (no key)",
+            (None, Some("pass"), None),
+        ),
+        // Unknown methods, versions, spacing around `=` and quoted results
+        (
+            "mx.google.com; arc=pass (i=1 spf=fail dkim=fail); bimi=skipped; \
+            iprev=pass policy.iprev=192.0.2.1; dkim/1 = \"PermError\" header.d=example.com; \
+            SPF=TempError smtp.mailfrom=x@example.com",
+            (Some("temperror"), Some("permerror"), None),
+        ),
+        // An authserv-id with a version, and a comment before the first clause
+        (
+            "mx.google.com 1 (comment; spf=fail); spf=pass smtp.mailfrom=a@example.com",
+            (Some("pass"), None, None),
+        ),
+        ("MX.Google.Com; none", (None, None, None)),
+    ];
+
+    #[test]
+    fn gmails_headers_parse_to_their_results() {
+        for (value, (spf, dkim, dmarc)) in CORPUS {
+            assert_eq!(
+                parse(value),
+                Some(results(*spf, *dkim, *dmarc)),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn headers_from_other_servers_are_not_trusted() {
+        assert_eq!(
+            parse("evil.example.com; spf=pass; dkim=pass; dmarc=pass"),
+            None
+        );
+        assert_eq!(parse("(mx.google.com) evil.example.com; spf=pass"), None);
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("   "), None);
+    }
+
+    #[test]
+    fn malformed_clauses_are_skipped() {
+        assert_eq!(
+            parse("mx.google.com; spf; dkim=; =pass; dmarc pass; spf=fail; dkim fail=pass"),
+            Some(results(Some("fail"), None, None))
+        );
+        assert_eq!(
+            parse("mx.google.com; spf=pass (unclosed comment; dkim=pass"),
+            Some(results(Some("pass"), None, None))
+        );
+    }
+
+    #[test]
+    fn gmails_copy_of_the_header_is_the_one_used() {
+        let received = message(
+            "m1",
+            &[
+                (
+                    "Authentication-Results",
+                    "relay.example.com; spf=pass; dkim=pass; dmarc=pass",
+                ),
+                (
+                    "authentication-results",
+                    "mx.google.com; spf=fail smtp.mailfrom=x@example.com; dmarc=fail",
+                ),
+            ],
+            &[],
+        );
+        assert_eq!(
+            auth_results(&received),
+            Some(results(Some("fail"), None, Some("fail")))
+        );
+        assert_eq!(auth_results(&message("m2", &[], &[])), None);
+    }
+}
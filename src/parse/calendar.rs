@@ -0,0 +1,235 @@
+use google_gmail1::api::{Message, MessagePart};
+
+use super::attachments::part_header;
+use super::body_text;
+
+/// The Content-Class Outlook puts on meeting requests.
+const CALENDAR_CLASS: &str = "urn:content-classes:calendarmessage";
+
+/// A meeting invite, or a reply to or cancellation of one.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CalendarInfo {
+    /// The iTIP method, uppercase (`REQUEST`, `REPLY`, `CANCEL`, ...), when it could be found.
+    pub method: Option<String>,
+    /// Lowercased address from the ORGANIZER line.
+    pub organizer: Option<String>,
+}
+
+/// Calendar details if the message carries a `text/calendar` part or is marked as a calendar
+/// message.
+pub fn calendar(message: &Message) -> Option<CalendarInfo> {
+    let payload = message.payload.as_ref()?;
+    let mut parts = Vec::new();
+    calendar_parts(payload, &mut parts);
+
+    let is_calendar_class = part_header(payload, "Content-Class")
+        .is_some_and(|class| class.trim().eq_ignore_ascii_case(CALENDAR_CLASS));
+    if parts.is_empty() && !is_calendar_class {
+        return None;
+    }
+
+    let mut info = CalendarInfo::default();
+    for part in parts {
+        // The method is often repeated in the Content-Type, which is there even when the body
+        // was too large to be included
+        let content_type_method = part_header(part, "Content-Type").and_then(|value| {
+            value.split(';').find_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("method")
+                    .then(|| value.trim().trim_matches('"').to_ascii_uppercase())
+            })
+        });
+        let sniffed = body_text(part).map(|ics| sniff(&ics)).unwrap_or_default();
+
+        info.method = info.method.or(content_type_method).or(sniffed.method);
+        info.organizer = info.organizer.or(sniffed.organizer);
+    }
+    Some(info)
+}
+
+fn calendar_parts<'a>(part: &'a MessagePart, found: &mut Vec<&'a MessagePart>) {
+    if let Some(parts) = &part.parts {
+        for child in parts {
+            calendar_parts(child, found);
+        }
+        return;
+    }
+    let mime_type = part.mime_type.as_deref().unwrap_or_default();
+    if mime_type.eq_ignore_ascii_case("text/calendar")
+        || mime_type.eq_ignore_ascii_case("application/ics")
+    {
+        found.push(part);
+    }
+}
+
+/// Pick METHOD and ORGANIZER out of an iCalendar body without parsing the rest of it.
+pub fn sniff(ics: &str) -> CalendarInfo {
+    // Long lines are folded by starting the continuation with a space or tab
+    let unfolded = ics
+        .replace("\r\n ", "")
+        .replace("\r\n\t", "")
+        .replace("\n ", "")
+        .replace("\n\t", "");
+
+    let mut info = CalendarInfo::default();
+    for line in unfolded.lines() {
+        let Some((key, value)) = split_property(line) else {
+            continue;
+        };
+        // Properties may carry parameters, `ORGANIZER;CN=Jane:mailto:jane@example.com`
+        let name = key.split(';').next().unwrap_or_default().trim();
+        if name.eq_ignore_ascii_case("METHOD") && info.method.is_none() {
+            info.method = Some(value.trim().to_ascii_uppercase());
+        } else if name.eq_ignore_ascii_case("ORGANIZER") && info.organizer.is_none() {
+            let value = value.trim();
+            let address = match value.get(..7) {
+                Some(scheme) if scheme.eq_ignore_ascii_case("mailto:") => &value[7..],
+                _ => value,
+            };
+            info.organizer = Some(address.to_lowercase()).filter(|a| a.contains('@'));
+        }
+        if info.method.is_some() && info.organizer.is_some() {
+            break;
+        }
+    }
+    info
+}
+
+// Split a content line at the colon ending its name and parameters. Quoted parameter values can
+// hold colons of their own, `SENT-BY="mailto:assistant@example.com"`.
+fn split_property(line: &str) -> Option<(&str, &str)> {
+    let mut quoted = false;
+    let at = line.find(|c| {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        c == ':' && !quoted
+    })?;
+    Some((&line[..at], &line[at + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testsupport::{message, part, with_parts};
+
+    const INVITE: &str = "BEGIN:VCALENDAR\r\nPRODID:-//Google Inc//Google Calendar 70.9054//EN\r\n\
+        VERSION:2.0\r\nCALSCALE:GREGORIAN\r\nMETHOD:REQUEST\r\nBEGIN:VEVENT\r\n\
+        DTSTART:20240610T150000Z\r\nDTEND:20240610T160000Z\r\n\
+        ORGANIZER;CN=Jane Doe:mailto:Jane.Doe@Example.com\r\n\
+        ATTENDEE;CUTYPE=INDIVIDUAL;ROLE=REQ-PARTICIPANT;PARTSTAT=NEEDS-ACTION;RSVP=TRUE\r\n \
+        ;CN=me@example.org;X-NUM-GUESTS=0:mailto:me@example.org\r\n\
+        SUMMARY:Planning\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+
+    // An invite as a calendar client sends it: text and HTML alternatives and the iCalendar
+    // part, with the same body attached as invite.ics
+    fn invite(calendar_type: &str, ics: &str) -> Message {
+        with_parts(
+            message("m1", &[("From", "jane.doe@example.com")], &["INBOX"]),
+            "multipart/mixed",
+            vec![
+                with_parts(
+                    Message::default(),
+                    "multipart/alternative",
+                    vec![
+                        part("text/plain", &[], "Planning, Monday 3pm"),
+                        part("text/html", &[], "<p>Planning</p>"),
+                        part("text/calendar", &[("Content-Type", calendar_type)], ics),
+                    ],
+                )
+                .payload
+                .unwrap(),
+                part(
+                    "application/ics",
+                    &[("Content-Disposition", "attachment; filename=\"invite.ics\"")],
+                    ics,
+                ),
+            ],
+        )
+    }
+
+    fn found(method: Option<&str>, organizer: Option<&str>) -> Option<CalendarInfo> {
+        Some(CalendarInfo {
+            method: method.map(str::to_string),
+            organizer: organizer.map(str::to_string),
+        })
+    }
+
+    #[test]
+    fn an_invite_gives_its_method_and_organizer() {
+        assert_eq!(
+            calendar(&invite(
+                "text/calendar; charset=\"UTF-8\"; method=REQUEST",
+                INVITE
+            )),
+            found(Some("REQUEST"), Some("jane.doe@example.com"))
+        );
+    }
+
+    #[test]
+    fn the_content_type_method_stands_in_for_a_missing_body() {
+        assert_eq!(
+            calendar(&invite("text/calendar; method=\"cancel\"", "")),
+            found(Some("CANCEL"), None)
+        );
+        // It's preferred to the body's too
+        assert_eq!(
+            calendar(&invite("text/calendar; method=CANCEL", INVITE)),
+            found(Some("CANCEL"), Some("jane.doe@example.com"))
+        );
+    }
+
+    #[test]
+    fn replies_and_cancellations_are_read_from_the_body() {
+        let reply = "BEGIN:VCALENDAR\nMETHOD:REPLY\nBEGIN:VEVENT\n\
+            ORGANIZER:MAILTO:jane@example.com\nATTENDEE;PARTSTAT=ACCEPTED:mailto:me@example.org\n\
+            END:VEVENT\nEND:VCALENDAR\n";
+        assert_eq!(
+            calendar(&invite("text/calendar", reply)),
+            found(Some("REPLY"), Some("jane@example.com"))
+        );
+        let cancel = "BEGIN:VCALENDAR\r\nmethod:cancel\r\nBEGIN:VEVENT\r\n\
+            ORGANIZER;CN=\"Doe, Jane\";SENT-BY=\"mailto:assistant@example.com\":mailto:jane@ex\r\n\
+            \tample.com\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+        assert_eq!(
+            calendar(&invite("text/calendar", cancel)),
+            found(Some("CANCEL"), Some("jane@example.com"))
+        );
+    }
+
+    #[test]
+    fn an_organizer_without_an_address_is_left_out() {
+        assert_eq!(
+            sniff("METHOD:PUBLISH\nORGANIZER;CN=Holidays:invalid:nomail\n"),
+            CalendarInfo {
+                method: Some("PUBLISH".to_string()),
+                organizer: None,
+            }
+        );
+        assert_eq!(sniff("not a calendar"), CalendarInfo::default());
+    }
+
+    #[test]
+    fn outlook_marks_meeting_requests_without_a_calendar_part() {
+        let request = message(
+            "m1",
+            &[
+                ("From", "jane@example.com"),
+                ("Content-Class", "urn:content-classes:calendarmessage"),
+            ],
+            &["INBOX"],
+        );
+        assert_eq!(calendar(&request), found(None, None));
+
+        let plain = with_parts(
+            message("m2", &[("From", "jane@example.com")], &["INBOX"]),
+            "multipart/alternative",
+            vec![
+                part("text/plain", &[], "METHOD:REQUEST"),
+                part("text/html", &[], ""),
+            ],
+        );
+        assert_eq!(calendar(&plain), None);
+    }
+}
@@ -0,0 +1,185 @@
+//! From headers as they turn up in real mail, and the sender each is counted against. The table
+//! locks in how senders are extracted, so a change to `EMAIL_RE_1` or `EMAIL_RE_2` shows up as a
+//! change to an expectation here.
+
+use super::SenderInfo;
+use crate::testsupport::message;
+
+/// (From header, sender) pairs.
+const FROM_HEADERS: &[(&str, &str)] = &[
+    // Bare addresses and the usual display names
+    ("jane@example.com", "jane@example.com"),
+    ("Jane Doe <jane@example.com>", "jane@example.com"),
+    ("\"Jane Doe\" <jane@example.com>", "jane@example.com"),
+    ("\"Doe, Jane\" <jane@example.com>", "jane@example.com"),
+    ("Doe, Jane <jane@example.com>", "jane@example.com"),
+    ("<jane@example.com>", "jane@example.com"),
+    ("< jane@example.com >", "jane@example.com"),
+    ("<jane@example.com >", "jane@example.com"),
+    ("  jane@example.com  ", "jane@example.com"),
+    ("JANE@EXAMPLE.COM", "JANE@EXAMPLE.COM"),
+    ("Jane <Jane.Doe@Example.COM>", "Jane.Doe@Example.COM"),
+    // Plus tags, apostrophes, underscores and long or short top-level domains
+    ("me+tag@example.com", "me+tag@example.com"),
+    (
+        "Me <me+newsletters@example.com>",
+        "me+newsletters@example.com",
+    ),
+    ("o'brien@example.ie", "o'brien@example.ie"),
+    ("Conan O'Brien <o'brien@example.ie>", "o'brien@example.ie"),
+    (
+        "first.last@sub.domain.example.co.uk",
+        "first.last@sub.domain.example.co.uk",
+    ),
+    (
+        "\"Support\" <support@help.example.technology>",
+        "support@help.example.technology",
+    ),
+    ("curator@collections.museum", "curator@collections.museum"),
+    ("team@startup.io", "team@startup.io"),
+    ("x@y.co", "x@y.co"),
+    ("a@b.c", "a@b.c"),
+    (
+        "no-reply@accounts.google.com",
+        "no-reply@accounts.google.com",
+    ),
+    (
+        "\"Google\" <no-reply@accounts.google.com>",
+        "no-reply@accounts.google.com",
+    ),
+    (
+        "noreply+abc-123@mail.example-site.com",
+        "noreply+abc-123@mail.example-site.com",
+    ),
+    (
+        "\"Amazon.com\" <shipment-tracking@amazon.com>",
+        "shipment-tracking@amazon.com",
+    ),
+    ("jane@exa_mple.com", "jane@exa_mple.com"),
+    ("jane_doe@example.com", "jane_doe@example.com"),
+    ("jane-doe@example.com", "jane-doe@example.com"),
+    ("1234567890@example.com", "1234567890@example.com"),
+    ("user%relay@example.com", "user%relay@example.com"),
+    ("{weird}@example.com", "{weird}@example.com"),
+    (
+        "bounce-mc.us5_123.456-jane=example.com@mail123.mcsv.net",
+        "bounce-mc.us5_123.456-jane=example.com@mail123.mcsv.net",
+    ),
+    (
+        "\"Newsletter\" <bounce+12345@em.example.com>",
+        "bounce+12345@em.example.com",
+    ),
+    // Encoded words and unicode in display names, which don't get in the way of the address
+    (
+        "=?UTF-8?B?SsO8cmdlbg==?= <juergen@example.de>",
+        "juergen@example.de",
+    ),
+    (
+        "=?utf-8?Q?Caf=C3=A9_Ren=C3=A9?= <cafe@example.fr>",
+        "cafe@example.fr",
+    ),
+    (
+        "\"=?UTF-8?Q?M=C3=BCller?=\" <mueller@example.de>",
+        "mueller@example.de",
+    ),
+    ("Jürgen Müller <juergen@example.de>", "juergen@example.de"),
+    ("\"José Ñúñez\" <jose@example.es>", "jose@example.es"),
+    ("山田太郎 <taro@example.jp>", "taro@example.jp"),
+    // Addresses and quotes in display names, odd spacing and trailing comments. With several
+    // addresses in brackets the first outside quotes is the sender
+    (
+        "\"Jane <jane@old.example>\" <jane@example.com>",
+        "jane@example.com",
+    ),
+    (
+        "\"jane@example.com\" <jane@example.com>",
+        "jane@example.com",
+    ),
+    ("jane@example.com <jane@example.com>", "jane@example.com"),
+    (
+        "\"Jane via Team\" <team@groups.example.com>",
+        "team@groups.example.com",
+    ),
+    (
+        "Jane Doe <jane@example.com> (Marketing)",
+        "jane@example.com",
+    ),
+    ("Jane Doe<jane@example.com>", "jane@example.com"),
+    (
+        "\"Jane \\\"JD\\\" Doe\" <jane@example.com>",
+        "jane@example.com",
+    ),
+    (
+        "Jane Doe <jane@example.com>, John Roe <john@example.com>",
+        "jane@example.com",
+    ),
+    // Bounces and system senders
+    ("undisclosed-recipients:;", "undisclosed-recipients:;"),
+    ("Mailer-Daemon", "Mailer-Daemon"),
+    (
+        "MAILER-DAEMON@mx.example.com",
+        "MAILER-DAEMON@mx.example.com",
+    ),
+    (
+        "\"Mail Delivery Subsystem\" <mailer-daemon@googlemail.com>",
+        "mailer-daemon@googlemail.com",
+    ),
+    // Garbage, kept as it came (trimmed) so it shows up in reports rather than vanishing
+    ("<>", "<>"),
+    ("not an address", "not an address"),
+    ("jane@", "jane@"),
+    ("@example.com", "@example.com"),
+    ("jane@example", "jane@example"),
+    ("jane at example dot com", "jane at example dot com"),
+    ("<jane@example.com", "jane@example.com"),
+    ("jane@@example.com", "jane@@example.com"),
+    ("\"Jane\" <>", "\"Jane\" <>"),
+    ("Jane <not-an-address>", "Jane <not-an-address>"),
+    ("jane@123.123.123.123", "jane@123.123.123.123"),
+    ("jane@[192.168.0.1]", "jane@[192.168.0.1]"),
+    ("", ""),
+    ("Jane Doe", "Jane Doe"),
+];
+
+/// Headers still extracted wrongly, with the sender they should give. Move one to `FROM_HEADERS`
+/// once it's fixed.
+const KNOWN_WRONG: &[(&str, &str)] = &[
+    // A bare list of addresses isn't split, unlike one in brackets
+    ("jane@example.com, john@example.com", "jane@example.com"),
+    // Nor is a stray closing bracket dropped
+    ("jane@example.com>", "jane@example.com"),
+];
+
+fn sender(from: &str) -> String {
+    SenderInfo::from_message(&message("m1", &[("From", from)], &[]), &[]).sender
+}
+
+#[test]
+fn from_headers_give_their_senders() {
+    let wrong = FROM_HEADERS
+        .iter()
+        .map(|(from, expected)| (*from, sender(from), *expected))
+        .filter(|(_, actual, expected)| actual != expected)
+        .collect::<Vec<_>>();
+    assert!(wrong.is_empty(), "(from, sender, expected): {:#?}", wrong);
+    assert!(FROM_HEADERS.len() >= 60);
+}
+
+#[test]
+fn known_wrong_headers_are_still_wrong() {
+    for (from, expected) in KNOWN_WRONG {
+        assert_ne!(
+            sender(from),
+            *expected,
+            "{:?} is right now, move it to FROM_HEADERS",
+            from
+        );
+    }
+}
+
+#[test]
+fn return_path_stands_in_for_a_missing_from() {
+    let message = message("m1", &[("Return-Path", "<bounce@example.com>")], &[]);
+    let info = SenderInfo::from_message(&message, &[]);
+    assert_eq!(info.sender, "bounce@example.com");
+}
@@ -0,0 +1,569 @@
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use clap::ValueEnum;
+use google_gmail1::api::{Message, MessagePart};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::error::GmailStatsError;
+
+mod attachments;
+mod authentication;
+mod calendar;
+#[cfg(test)]
+mod fixtures;
+mod notices;
+mod recipients;
+
+pub use attachments::{kind as attachment_kind, Attachment};
+pub use authentication::AuthResults;
+pub use calendar::CalendarInfo;
+pub use notices::{Notice, NoticeKind};
+pub use recipients::Recipient;
+
+// The local part allows everything RFC 5322 does outside quotes, so `me+tag@example.com` and
+// `o'brien@example.com` are kept whole, and top-level domains can be any length (`.museum`,
+// `.technology`).
+lazy_static! {
+    static ref EMAIL_RE_1: Regex =
+        Regex::new(r"<\s*([\w.!#$%&'*+/=?^`{|}~-]+@([\w-]+\.)+[\w-]{2,})").unwrap();
+    static ref EMAIL_RE_2: Regex =
+        Regex::new(r"^([\w.!#$%&'*+/=?^`{|}~-]+@([\w-]+\.)+[\w-]{2,})$").unwrap();
+}
+
+/// Gmail's base64url body data, which may or may not be padded.
+const BODY_DATA: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Headers that may carry the original sender of a forwarded message, in the order they are
+/// preferred when unwrapping. `X-Original-From` is the most specific (it is set by the forwarding
+/// bridge to exactly the original From), followed by `X-Forwarded-For` and finally `Resent-From`.
+pub const FORWARDED_HEADERS: [&str; 3] = ["X-Original-From", "X-Forwarded-For", "Resent-From"];
+
+/// Why a message was considered bulk mail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BulkSignal {
+    /// `Precedence: bulk`, `list` or `junk`
+    Precedence,
+    /// A `List-Id` header is present
+    ListId,
+    /// `Auto-Submitted`, or a non-standard equivalent, marks it machine-generated
+    AutoSubmitted,
+}
+
+impl BulkSignal {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BulkSignal::Precedence => "precedence",
+            BulkSignal::ListId => "list-id",
+            BulkSignal::AutoSubmitted => "auto-submitted",
+        }
+    }
+}
+
+/// What kind of machine-generated mail a message is, from `Auto-Submitted` (RFC 3834) or the
+/// Microsoft equivalents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AutoKind {
+    /// `Auto-Submitted: auto-generated`, or an `X-Auto-Response-Suppress` header, which
+    /// automated senders set so they don't get out-of-office replies back
+    Generated,
+    /// `Auto-Submitted: auto-replied` or `X-Autoreply`, e.g. out-of-office replies
+    Replied,
+    /// `Auto-Submitted: auto-notified`, delivery notifications from Sieve
+    Notified,
+    /// Any other `Auto-Submitted` value except `no`
+    Other,
+}
+
+impl AutoKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AutoKind::Generated => "auto-generated",
+            AutoKind::Replied => "auto-replied",
+            AutoKind::Notified => "auto-notified",
+            AutoKind::Other => "other",
+        }
+    }
+}
+
+/// The inbox tabs Gmail sorts mail into, each backed by a `CATEGORY_*` system label.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Category {
+    /// The "Primary" tab
+    Personal,
+    Social,
+    Promotions,
+    Updates,
+    Forums,
+}
+
+impl Category {
+    pub fn label_id(&self) -> &'static str {
+        match self {
+            Category::Personal => "CATEGORY_PERSONAL",
+            Category::Social => "CATEGORY_SOCIAL",
+            Category::Promotions => "CATEGORY_PROMOTIONS",
+            Category::Updates => "CATEGORY_UPDATES",
+            Category::Forums => "CATEGORY_FORUMS",
+        }
+    }
+}
+
+/// System label Gmail puts on mail I sent.
+pub const SENT: &str = "SENT";
+
+/// System label Gmail puts on mail in the spam folder.
+pub const SPAM: &str = "SPAM";
+
+/// Settings which change how a message is parsed.
+#[derive(Clone, Debug, Default)]
+pub struct ParseOptions {
+    /// Forwarder addresses to unwrap, empty unless `--unwrap-forwarded` was passed.
+    pub forwarders: Vec<String>,
+    /// Ignore inline attachments such as images embedded in HTML mail.
+    pub exclude_inline: bool,
+}
+
+/// Everything we keep about a single fetched message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MessageInfo {
+    pub id: String,
+    pub sender: SenderInfo,
+    /// The first signal which marked this message as bulk, if any.
+    pub bulk: Option<BulkSignal>,
+    /// Set when a header marks the message as machine-generated.
+    pub auto: Option<AutoKind>,
+    /// internalDate in epoch milliseconds.
+    pub date: Option<i64>,
+    pub size_estimate: i64,
+    pub labels: Vec<String>,
+    pub attachments: Vec<Attachment>,
+    pub thread_id: Option<String>,
+    /// The Message-ID header, angle brackets and all.
+    pub message_id: Option<String>,
+    pub subject: Option<String>,
+    /// Everyone in To and Cc.
+    pub recipients: Vec<Recipient>,
+    /// The address Gmail delivered the message to, as written.
+    pub delivered_to: Option<String>,
+    /// SPF, DKIM and DMARC outcomes from Gmail's `Authentication-Results`.
+    pub auth: Option<AuthResults>,
+    /// Set for meeting invites and their replies and cancellations.
+    pub calendar: Option<CalendarInfo>,
+    /// Set for bounces and out-of-office replies.
+    pub notice: Option<Notice>,
+}
+
+impl MessageInfo {
+    pub fn from_message(
+        message: &Message,
+        opts: &ParseOptions,
+    ) -> Result<MessageInfo, GmailStatsError> {
+        let mut attachments = message
+            .payload
+            .as_ref()
+            .map(attachments::attachments)
+            .unwrap_or_default();
+        if opts.exclude_inline {
+            attachments.retain(|attachment| !attachment.inline);
+        }
+
+        let sender = SenderInfo::from_message(message, &opts.forwarders);
+        let subject = get_header(message, "Subject").map(|subject| subject.trim().to_string());
+        let auto = auto_kind(message);
+        Ok(MessageInfo {
+            id: message
+                .id
+                .clone()
+                .ok_or_else(|| GmailStatsError::Parse("a message without an id".to_string()))?,
+            notice: notices::notice(message, &sender.sender, subject.as_deref(), auto),
+            sender,
+            bulk: bulk_signal(message),
+            auto,
+            date: message
+                .internal_date
+                .as_deref()
+                .and_then(|date| date.parse().ok()),
+            size_estimate: message.size_estimate.unwrap_or_default().into(),
+            labels: message.label_ids.clone().unwrap_or_default(),
+            attachments,
+            thread_id: message.thread_id.clone(),
+            message_id: get_header(message, "Message-ID")
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+                .map(str::to_string),
+            subject,
+            recipients: recipients::recipients(message),
+            delivered_to: recipients::delivered_to(message),
+            auth: authentication::auth_results(message),
+            calendar: calendar::calendar(message),
+        })
+    }
+
+    pub fn is_bulk(&self) -> bool {
+        self.bulk.is_some()
+    }
+
+    pub fn is_automated(&self) -> bool {
+        self.auto.is_some()
+    }
+
+    pub fn is_unread(&self) -> bool {
+        self.has_label("UNREAD")
+    }
+
+    pub fn is_spam(&self) -> bool {
+        self.has_label(SPAM)
+    }
+
+    pub fn has_label(&self, label: &str) -> bool {
+        self.labels.iter().any(|l| l == label)
+    }
+
+    pub fn attachment_bytes(&self) -> i64 {
+        self.attachments
+            .iter()
+            .map(|attachment| attachment.size)
+            .sum()
+    }
+}
+
+/// Who a message should be counted against.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SenderInfo {
+    /// The cleaned sender the message is attributed to.
+    pub sender: String,
+    /// The cleaned sender from the From header, regardless of forwarding.
+    pub envelope_sender: String,
+    /// The cleaned original sender when a forwarded message was unwrapped.
+    pub original_sender: Option<String>,
+    /// Display name of `sender`, e.g. `Jane Doe` from `"Jane Doe" <jane@example.com>`.
+    pub name: Option<String>,
+    /// The From header as sent.
+    pub from: String,
+}
+
+impl SenderInfo {
+    /// Extract the sender of a message. When `forwarders` is non-empty and the envelope sender is
+    /// one of them, the original sender is taken from the first of `FORWARDED_HEADERS` present.
+    pub fn from_message(message: &Message, forwarders: &[String]) -> SenderInfo {
+        let from = get_sender(message);
+        let envelope_sender = cleanup_sender(from);
+
+        let original_sender = if forwarders
+            .iter()
+            .any(|forwarder| forwarder.eq_ignore_ascii_case(envelope_sender))
+        {
+            get_forwarded_sender(message)
+        } else {
+            None
+        };
+
+        let name = match &original_sender {
+            Some(_) => FORWARDED_HEADERS
+                .iter()
+                .find_map(|name| get_header(message, name))
+                .and_then(display_name),
+            None => display_name(from),
+        };
+
+        SenderInfo {
+            name,
+            sender: original_sender.unwrap_or(envelope_sender).to_string(),
+            envelope_sender: envelope_sender.to_string(),
+            original_sender: original_sender.map(str::to_string),
+            from: from.to_string(),
+        }
+    }
+}
+
+// The decoded body of a part, if Gmail included it rather than leaving it to be fetched as an
+// attachment
+pub fn body_text(part: &MessagePart) -> Option<String> {
+    let data = part.body.as_ref()?.data.as_deref()?;
+    let bytes = BODY_DATA.decode(data).ok()?;
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// Return the value of the first header matching `name` case-insensitively, skipping any Gmail
+// sent without a value
+pub fn get_header<'a>(message: &'a Message, name: &str) -> Option<&'a str> {
+    message
+        .payload
+        .as_ref()?
+        .headers
+        .as_ref()?
+        .iter()
+        .filter(|header| {
+            header
+                .name
+                .as_deref()
+                .is_some_and(|n| n.eq_ignore_ascii_case(name))
+        })
+        .find_map(|header| header.value.as_deref())
+}
+
+fn get_forwarded_sender(message: &Message) -> Option<&str> {
+    FORWARDED_HEADERS
+        .iter()
+        .filter_map(|name| get_header(message, name))
+        .map(|value| {
+            if value.contains('<') {
+                return cleanup_sender(value);
+            }
+            // X-Forwarded-For may list several bare addresses, the first one is the original
+            let first = value.split([',', ' ']).find(|s| !s.is_empty());
+            cleanup_sender(first.unwrap_or_default())
+        })
+        .find(|sender| !sender.is_empty())
+}
+
+// `"Doe, Jane" <jane@example.com>` -> `Doe, Jane`, None for a bare address
+pub fn display_name(value: &str) -> Option<String> {
+    let (name, _) = value.split_once('<')?;
+    let name = name.trim().trim_matches('"').trim();
+    if name.is_empty() || name.contains('@') {
+        return None;
+    }
+    Some(name.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+// The bulk signals are ORed together, checked in the order Precedence, List-Id, Auto-Submitted
+// so the first of them is the one recorded when a message carries several.
+pub fn bulk_signal(message: &Message) -> Option<BulkSignal> {
+    let precedence = get_header(message, "Precedence")
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+    if ["bulk", "list", "junk"].contains(&precedence.as_str()) {
+        return Some(BulkSignal::Precedence);
+    }
+
+    if get_header(message, "List-Id").is_some() {
+        return Some(BulkSignal::ListId);
+    }
+
+    if auto_kind(message).is_some() {
+        return Some(BulkSignal::AutoSubmitted);
+    }
+
+    None
+}
+
+// Auto-Submitted is a case-insensitive token with optional parameters, e.g.
+// `Auto-Generated; owner-email="me@example.com"`. It wins over the non-standard headers.
+pub fn auto_kind(message: &Message) -> Option<AutoKind> {
+    if let Some(value) = get_header(message, "Auto-Submitted") {
+        let token = value
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        return match token.as_str() {
+            "" | "no" => None,
+            "auto-generated" => Some(AutoKind::Generated),
+            "auto-replied" => Some(AutoKind::Replied),
+            "auto-notified" => Some(AutoKind::Notified),
+            _ => Some(AutoKind::Other),
+        };
+    }
+
+    let autoreply = get_header(message, "X-Autoreply")
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+    if !autoreply.is_empty() && autoreply != "no" {
+        return Some(AutoKind::Replied);
+    }
+
+    if get_header(message, "X-Auto-Response-Suppress").is_some() {
+        return Some(AutoKind::Generated);
+    }
+
+    None
+}
+
+// Attempt to extract a formatted email address, or just return the original value trimmed.
+// With angle brackets the first address outside quotes wins, since a quoted display name can
+// hold one too: `"Jane <jane@old.example>" <jane@example.com>` -> `jane@example.com`
+pub fn cleanup_sender(sender: &str) -> &str {
+    let sender = sender.trim();
+    if !sender.contains('<') {
+        return EMAIL_RE_2
+            .captures(sender)
+            .and_then(|cap| cap.get(1))
+            .map_or(sender, |address| address.as_str());
+    }
+    let mut quoted = None;
+    for cap in EMAIL_RE_1.captures_iter(sender) {
+        let (Some(whole), Some(address)) = (cap.get(0), cap.get(1)) else {
+            continue;
+        };
+        if !in_quotes(&sender[..whole.start()]) {
+            return address.as_str();
+        }
+        quoted = Some(address.as_str());
+    }
+    quoted.unwrap_or(sender)
+}
+
+// Whether `before` leaves a quoted string open, going by its unescaped double quotes
+fn in_quotes(before: &str) -> bool {
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in before.chars() {
+        match c {
+            '\\' if !escaped => {
+                escaped = true;
+                continue;
+            }
+            '"' if !escaped => quoted = !quoted,
+            _ => {}
+        }
+        escaped = false;
+    }
+    quoted
+}
+
+pub fn get_sender(message: &Message) -> &str {
+    // Headers Gmail sent without a value are passed over, as if they weren't there
+    match get_header(message, "From").or_else(|| get_header(message, "Return-Path")) {
+        Some(sender) => sender,
+        None => {
+            eprintln!(
+                "weird email without from header: {}",
+                message.id.as_deref().unwrap_or("(no id)")
+            );
+            ""
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testsupport::message;
+
+    fn signal(headers: &[(&str, &str)]) -> Option<BulkSignal> {
+        bulk_signal(&message("m1", headers, &[]))
+    }
+
+    #[test]
+    fn each_header_marks_mail_as_bulk() {
+        assert_eq!(
+            signal(&[("Precedence", "bulk")]),
+            Some(BulkSignal::Precedence)
+        );
+        assert_eq!(
+            signal(&[("Precedence", " List ")]),
+            Some(BulkSignal::Precedence)
+        );
+        assert_eq!(
+            signal(&[("Precedence", "junk")]),
+            Some(BulkSignal::Precedence)
+        );
+        assert_eq!(
+            signal(&[("List-Id", "<news.example.com>")]),
+            Some(BulkSignal::ListId)
+        );
+        assert_eq!(
+            signal(&[("Auto-Submitted", "auto-generated")]),
+            Some(BulkSignal::AutoSubmitted)
+        );
+        assert_eq!(
+            signal(&[("X-Auto-Response-Suppress", "All")]),
+            Some(BulkSignal::AutoSubmitted)
+        );
+    }
+
+    #[test]
+    fn mail_without_a_signal_is_not_bulk() {
+        assert_eq!(signal(&[]), None);
+        assert_eq!(signal(&[("Precedence", "first-class")]), None);
+        assert_eq!(signal(&[("Auto-Submitted", "no")]), None);
+    }
+
+    #[test]
+    fn the_first_signal_is_the_one_recorded() {
+        let all = [
+            ("Auto-Submitted", "auto-generated"),
+            ("List-Id", "<news.example.com>"),
+            ("Precedence", "bulk"),
+        ];
+        assert_eq!(signal(&all), Some(BulkSignal::Precedence));
+        assert_eq!(signal(&all[..2]), Some(BulkSignal::ListId));
+    }
+
+    #[test]
+    fn automated_mail_counts_as_bulk() {
+        let message = message("m1", &[("Auto-Submitted", "auto-replied")], &[]);
+        let info = MessageInfo::from_message(&message, &ParseOptions::default()).unwrap();
+        assert!(info.is_bulk());
+        assert_eq!(info.auto, Some(AutoKind::Replied));
+    }
+
+    #[test]
+    fn auto_submitted_values_are_tokens_with_parameters() {
+        let kind = |headers: &[(&str, &str)]| auto_kind(&message("m1", headers, &[]));
+        assert_eq!(
+            kind(&[(
+                "Auto-Submitted",
+                "Auto-Generated; owner-email=\"me@example.com\""
+            )]),
+            Some(AutoKind::Generated)
+        );
+        assert_eq!(
+            kind(&[("Auto-Submitted", " AUTO-REPLIED ")]),
+            Some(AutoKind::Replied)
+        );
+        assert_eq!(
+            kind(&[("Auto-Submitted", "auto-notified;")]),
+            Some(AutoKind::Notified)
+        );
+        assert_eq!(
+            kind(&[("Auto-Submitted", "auto-forwarded")]),
+            Some(AutoKind::Other)
+        );
+        assert_eq!(kind(&[("Auto-Submitted", "No")]), None);
+        // Auto-Submitted wins over the non-standard headers
+        assert_eq!(
+            kind(&[("Auto-Submitted", "no"), ("X-Autoreply", "yes")]),
+            None
+        );
+        assert_eq!(kind(&[("X-Autoreply", "YES")]), Some(AutoKind::Replied));
+        assert_eq!(kind(&[("X-Autoreply", "no")]), None);
+        assert_eq!(
+            kind(&[("X-Auto-Response-Suppress", "DR, OOF, AutoReply")]),
+            Some(AutoKind::Generated)
+        );
+    }
+
+    #[test]
+    fn a_message_without_an_id_is_an_error() {
+        let mut message = message("m1", &[("From", "jane@example.com")], &[]);
+        message.id = None;
+        assert!(matches!(
+            MessageInfo::from_message(&message, &ParseOptions::default()),
+            Err(GmailStatsError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn a_header_without_a_value_is_passed_over() {
+        let mut message = message(
+            "m1",
+            &[
+                ("From", "jane@example.com"),
+                ("Return-Path", "<bounce@example.com>"),
+            ],
+            &[],
+        );
+        message.payload.as_mut().unwrap().headers.as_mut().unwrap()[0].value = None;
+        assert_eq!(get_sender(&message), "<bounce@example.com>");
+        message.payload.as_mut().unwrap().headers.as_mut().unwrap()[1].value = None;
+        assert_eq!(get_sender(&message), "");
+    }
+}
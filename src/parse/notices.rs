@@ -0,0 +1,323 @@
+use google_gmail1::api::{Message, MessagePart};
+
+use super::{body_text, cleanup_sender, get_header, AutoKind};
+
+/// Subject prefixes of out-of-office replies from the common mail systems, lowercase.
+const OUT_OF_OFFICE_SUBJECTS: &[&str] = &[
+    "out of office",
+    "out of the office",
+    "automatic reply",
+    "auto reply",
+    "autoreply",
+    "auto-reply",
+    "auto:",
+    "abwesenheitsnotiz",
+    "réponse automatique",
+    "respuesta automática",
+];
+
+/// Local parts that only ever send delivery status notifications.
+const BOUNCE_SENDERS: [&str; 2] = ["mailer-daemon", "postmaster"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoticeKind {
+    OutOfOffice,
+    /// A delivery status notification for mail I sent
+    Bounce,
+}
+
+impl NoticeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NoticeKind::OutOfOffice => "out-of-office",
+            NoticeKind::Bounce => "bounce",
+        }
+    }
+}
+
+/// An automatic notice about someone I wrote to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Notice {
+    pub kind: NoticeKind,
+    /// Lowercased address the notice is about: whoever is away, or the recipient my mail couldn't
+    /// be delivered to. `None` when a bounce doesn't say.
+    pub address: Option<String>,
+}
+
+/// Classify a message as a bounce or an out-of-office reply. Bounces are checked first since
+/// some mail servers mark them auto-replied too.
+pub fn notice(
+    message: &Message,
+    sender: &str,
+    subject: Option<&str>,
+    auto: Option<AutoKind>,
+) -> Option<Notice> {
+    if is_bounce(message, sender) {
+        return Some(Notice {
+            kind: NoticeKind::Bounce,
+            address: failed_recipient(message),
+        });
+    }
+
+    let subject = subject.unwrap_or_default().trim_start().to_lowercase();
+    if auto == Some(AutoKind::Replied)
+        || OUT_OF_OFFICE_SUBJECTS
+            .iter()
+            .any(|prefix| subject.starts_with(prefix))
+    {
+        return Some(Notice {
+            kind: NoticeKind::OutOfOffice,
+            address: Some(sender.to_lowercase()).filter(|s| !s.is_empty()),
+        });
+    }
+    None
+}
+
+fn is_bounce(message: &Message, sender: &str) -> bool {
+    let local = sender
+        .rsplit_once('@')
+        .map(|(local, _)| local)
+        .unwrap_or(sender);
+    if BOUNCE_SENDERS
+        .iter()
+        .any(|name| local.eq_ignore_ascii_case(name))
+    {
+        return true;
+    }
+    // Read receipts are reports too
+    let is_report = message
+        .payload
+        .as_ref()
+        .and_then(|payload| payload.mime_type.as_deref())
+        .is_some_and(|mime_type| mime_type.eq_ignore_ascii_case("multipart/report"));
+    let is_receipt = get_header(message, "Content-Type")
+        .is_some_and(|t| t.to_ascii_lowercase().contains("disposition-notification"));
+    is_report && !is_receipt
+}
+
+/// Who the bounced mail was for, from `X-Failed-Recipients` (added by Exim and Gmail) or the
+/// `message/delivery-status` part of the report when its body was included.
+pub fn failed_recipient(message: &Message) -> Option<String> {
+    if let Some(value) = get_header(message, "X-Failed-Recipients") {
+        let first = value.split(',').next().unwrap_or_default().trim();
+        let address = cleanup_sender(first).to_lowercase();
+        if address.contains('@') {
+            return Some(address);
+        }
+    }
+
+    let mut found = None;
+    if let Some(payload) = &message.payload {
+        walk(payload, &mut found);
+    }
+    found
+}
+
+fn walk(part: &MessagePart, found: &mut Option<String>) {
+    if found.is_some() {
+        return;
+    }
+    if let Some(parts) = &part.parts {
+        for child in parts {
+            walk(child, found);
+        }
+        return;
+    }
+    let is_status = part
+        .mime_type
+        .as_deref()
+        .is_some_and(|t| t.eq_ignore_ascii_case("message/delivery-status"));
+    if is_status {
+        *found = body_text(part).and_then(|status| status_recipient(&status));
+    }
+}
+
+/// The recipient from a delivery-status body, preferring `Final-Recipient` over
+/// `Original-Recipient`. Both look like `Final-Recipient: rfc822; jane@example.com`.
+pub fn status_recipient(status: &str) -> Option<String> {
+    let field = |name: &str| {
+        status.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            if !key.trim().eq_ignore_ascii_case(name) {
+                return None;
+            }
+            // The address type before the `;` is optional in practice
+            let address = value.rsplit(';').next()?.trim().trim_matches(['<', '>']);
+            Some(address.to_lowercase()).filter(|a| a.contains('@'))
+        })
+    };
+    field("Final-Recipient").or_else(|| field("Original-Recipient"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{MessageInfo, ParseOptions};
+    use crate::testsupport::{message, part, with_parts};
+
+    fn notice_of(message: &Message) -> Option<Notice> {
+        MessageInfo::from_message(message, &ParseOptions::default())
+            .unwrap()
+            .notice
+    }
+
+    fn bounce(address: Option<&str>) -> Option<Notice> {
+        Some(Notice {
+            kind: NoticeKind::Bounce,
+            address: address.map(str::to_string),
+        })
+    }
+
+    // A delivery status notification: the explanation, the machine-readable status and the
+    // headers of the mail that bounced
+    fn report(headers: &[(&str, &str)], status: &str) -> Message {
+        let mut headers = headers.to_vec();
+        headers.push((
+            "Content-Type",
+            "multipart/report; report-type=delivery-status; boundary=\"b1\"",
+        ));
+        with_parts(
+            message("m1", &headers, &["INBOX"]),
+            "multipart/report",
+            vec![
+                part("text/plain", &[], "Your message couldn't be delivered."),
+                part("message/delivery-status", &[], status),
+                part(
+                    "message/rfc822-headers",
+                    &[],
+                    "From: me@example.com\r\nTo: jane@example.com\r\n",
+                ),
+            ],
+        )
+    }
+
+    const GMAIL_STATUS: &str = "Reporting-MTA: dns; googlemail.com\r\n\
+        Arrival-Date: Mon, 10 Jun 2024 09:00:00 -0700 (PDT)\r\n\
+        X-Original-Message-ID: <abc@mail.example.com>\r\n\r\n\
+        Final-Recipient: rfc822; jane@example.com\r\nAction: failed\r\nStatus: 5.1.1\r\n\
+        Diagnostic-Code: smtp; 550-5.1.1 The email account that you tried to reach does not exist\r\n";
+
+    #[test]
+    fn gmails_bounces_name_the_failed_recipient() {
+        let gmail = report(
+            &[
+                (
+                    "From",
+                    "Mail Delivery Subsystem <mailer-daemon@googlemail.com>",
+                ),
+                ("X-Failed-Recipients", "Jane@Example.com, bob@example.com"),
+                ("Auto-Submitted", "auto-replied"),
+                ("Subject", "Delivery Status Notification (Failure)"),
+            ],
+            GMAIL_STATUS,
+        );
+        assert_eq!(notice_of(&gmail), bounce(Some("jane@example.com")));
+    }
+
+    #[test]
+    fn the_delivery_status_part_names_it_otherwise() {
+        let postfix = report(
+            &[
+                (
+                    "From",
+                    "MAILER-DAEMON@mail.example.org (Mail Delivery System)",
+                ),
+                ("Subject", "Undelivered Mail Returned to Sender"),
+            ],
+            "Reporting-MTA: dns; mail.example.org\n\n\
+            Final-Recipient: rfc822; bob@example.org\n\
+            Original-Recipient: rfc822;Bob.Alias@Example.org\nAction: failed\nStatus: 5.0.0\n",
+        );
+        assert_eq!(notice_of(&postfix), bounce(Some("bob@example.org")));
+
+        let exchange = report(
+            &[("From", "postmaster@corp.example")],
+            "Reporting-MTA: dns;EX01.corp.example\r\n\r\n\
+            Original-Recipient: rfc822;<Carol@Corp.Example>\r\nAction: failed\r\n",
+        );
+        assert_eq!(notice_of(&exchange), bounce(Some("carol@corp.example")));
+
+        // Any report which isn't a read receipt is a bounce, whoever sent it
+        let relay = report(&[("From", "no-reply@relay.example.net")], GMAIL_STATUS);
+        assert_eq!(notice_of(&relay), bounce(Some("jane@example.com")));
+    }
+
+    #[test]
+    fn a_bounce_without_its_status_body_is_unattributed() {
+        let mut truncated = report(&[("From", "mailer-daemon@example.net")], GMAIL_STATUS);
+        let parts = truncated.payload.as_mut().unwrap().parts.as_mut().unwrap();
+        parts[1].body.as_mut().unwrap().data = None;
+        assert_eq!(notice_of(&truncated), bounce(None));
+
+        let unparsable = report(
+            &[("From", "mailer-daemon@example.net")],
+            "Final-Recipient: rfc822; unknown\n",
+        );
+        assert_eq!(notice_of(&unparsable), bounce(None));
+    }
+
+    #[test]
+    fn read_receipts_are_not_bounces() {
+        let receipt = with_parts(
+            message(
+                "m1",
+                &[
+                    ("From", "jane@example.com"),
+                    (
+                        "Content-Type",
+                        "multipart/report; report-type=disposition-notification",
+                    ),
+                    ("Subject", "Read: Planning"),
+                ],
+                &["INBOX"],
+            ),
+            "multipart/report",
+            vec![
+                part("text/plain", &[], "Your message was read."),
+                part(
+                    "message/disposition-notification",
+                    &[],
+                    "Final-Recipient: rfc822; jane@example.com\nDisposition: manual-action/MDN-sent-manually; displayed\n",
+                ),
+            ],
+        );
+        assert_eq!(notice_of(&receipt), None);
+    }
+
+    #[test]
+    fn out_of_office_replies_name_whoever_is_away() {
+        let away = |headers: &[(&str, &str)]| {
+            let mut headers = headers.to_vec();
+            headers.push(("From", "Jane Doe <Jane@Example.com>"));
+            notice_of(&message("m1", &headers, &["INBOX"]))
+        };
+        let out_of_office = Some(Notice {
+            kind: NoticeKind::OutOfOffice,
+            address: Some("jane@example.com".to_string()),
+        });
+        assert_eq!(
+            away(&[("Subject", "Automatic reply: Planning")]),
+            out_of_office
+        );
+        assert_eq!(
+            away(&[("Subject", "  Out of Office: back Monday")]),
+            out_of_office
+        );
+        assert_eq!(
+            away(&[("Subject", "Abwesenheitsnotiz: Planung")]),
+            out_of_office
+        );
+        assert_eq!(
+            away(&[
+                ("Subject", "Re: Planning"),
+                ("Auto-Submitted", "auto-replied")
+            ]),
+            out_of_office
+        );
+        assert_eq!(away(&[("Subject", "Re: out of office plans")]), None);
+        assert_eq!(
+            away(&[("Subject", "Digest"), ("Auto-Submitted", "auto-generated")]),
+            None
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use google_gmail1::api::Message;
+
+use super::{cleanup_sender, get_header};
+
+/// Which header a recipient was listed in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecipientField {
+    To,
+    Cc,
+}
+
+impl RecipientField {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RecipientField::To => "to",
+            RecipientField::Cc => "cc",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recipient {
+    pub field: RecipientField,
+    /// Lowercased address.
+    pub address: String,
+}
+
+/// Everyone in the To and Cc headers. Bcc isn't visible to recipients, so mail I was Bcc'd on
+/// simply doesn't list me.
+pub fn recipients(message: &Message) -> Vec<Recipient> {
+    [RecipientField::To, RecipientField::Cc]
+        .into_iter()
+        .flat_map(|field| {
+            let name = match field {
+                RecipientField::To => "To",
+                RecipientField::Cc => "Cc",
+            };
+            addresses(get_header(message, name).unwrap_or_default())
+                .into_iter()
+                .map(move |address| Recipient { field, address })
+        })
+        .collect()
+}
+
+/// The address in Delivered-To, lowercased with any plus-tag kept. Gmail adds it for the address
+/// the mail was delivered to, which mailing lists and Bcc leave out of To and Cc.
+pub fn delivered_to(message: &Message) -> Option<String> {
+    addresses(get_header(message, "Delivered-To")?)
+        .into_iter()
+        .next()
+}
+
+/// Split an address list header into lowercased addresses. Commas inside quoted display names,
+/// angle brackets or comments don't split, and group syntax (`Team: a@x.com, b@x.com;`) is
+/// flattened into its members. Entries without an address, like `undisclosed-recipients:;`,
+/// are dropped.
+pub fn addresses(value: &str) -> Vec<String> {
+    let mut entries = Vec::new();
+    let mut current = String::new();
+    let (mut quoted, mut angle, mut comment) = (false, false, 0);
+    let mut escaped = false;
+    for c in value.chars() {
+        if escaped {
+            if comment == 0 {
+                current.push(c);
+            }
+            escaped = false;
+            continue;
+        }
+        let in_comment = comment > 0;
+        match c {
+            '\\' if quoted || comment > 0 => escaped = true,
+            '"' if comment == 0 => quoted = !quoted,
+            '(' if !quoted => comment += 1,
+            ')' if !quoted && comment > 0 => comment -= 1,
+            '<' if !quoted && comment == 0 => angle = true,
+            '>' if !quoted && comment == 0 => angle = false,
+            _ => {}
+        }
+        // Comments are dropped along with their parentheses
+        if in_comment || comment > 0 {
+            continue;
+        }
+        let top_level = !quoted && !angle;
+        if top_level && (c == ',' || c == ';') {
+            entries.push(std::mem::take(&mut current));
+        } else if top_level && c == ':' {
+            // Everything so far was the group's display name
+            current.clear();
+        } else {
+            current.push(c);
+        }
+    }
+    entries.push(current);
+
+    entries
+        .into_iter()
+        .map(|entry| cleanup_sender(entry.trim()).to_lowercase())
+        .filter(|address| address.contains('@') && !address.contains(char::is_whitespace))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testsupport::message;
+
+    #[test]
+    fn commas_in_quotes_brackets_and_comments_dont_split() {
+        assert_eq!(
+            addresses("\"Doe, Jane\" <jane@example.com>, Bob <bob@example.com>"),
+            ["jane@example.com", "bob@example.com"]
+        );
+        assert_eq!(
+            addresses("\"Say \\\"hi, there\\\"\" <jane@example.com>"),
+            ["jane@example.com"]
+        );
+        assert_eq!(
+            addresses("jane@example.com (Jane, at home), bob@example.com (Bob (work), desk)"),
+            ["jane@example.com", "bob@example.com"]
+        );
+    }
+
+    #[test]
+    fn groups_are_flattened_into_their_members() {
+        assert_eq!(
+            addresses("Team: jane@example.com, \"Bob, B.\" <bob@example.com>;, me@example.com"),
+            ["jane@example.com", "bob@example.com", "me@example.com"]
+        );
+        assert_eq!(
+            addresses("\"Team: all\" <team@example.com>"),
+            ["team@example.com"]
+        );
+        assert!(addresses("undisclosed-recipients:;").is_empty());
+        assert_eq!(
+            addresses("undisclosed-recipients:; me@example.com"),
+            ["me@example.com"]
+        );
+    }
+
+    #[test]
+    fn encoded_display_names_are_left_alone() {
+        assert_eq!(
+            addresses(
+                "=?UTF-8?Q?M=C3=BCller=2C_Hans?= <hans@example.de>, \
+                =?utf-8?B?5bGx55Sw5aSq6YOO?= <Yamada@Example.JP>"
+            ),
+            ["hans@example.de", "yamada@example.jp"]
+        );
+        assert_eq!(
+            addresses("\"=?ISO-8859-1?Q?Andr=E9?= Pirard\" <pirard@example.be>"),
+            ["pirard@example.be"]
+        );
+    }
+
+    #[test]
+    fn entries_without_an_address_are_dropped() {
+        assert!(addresses("").is_empty());
+        assert!(addresses(" , ;").is_empty());
+        assert_eq!(
+            addresses("Jane Doe, jane@example.com, \"not an @ddress\""),
+            ["jane@example.com"]
+        );
+    }
+
+    #[test]
+    fn recipients_keep_the_header_they_came_from() {
+        let addressed = message(
+            "m1",
+            &[
+                ("To", "Jane <JANE@example.com>, me@example.com"),
+                ("Cc", "Team: me@example.com;"),
+                ("Delivered-To", "me+lists@example.com"),
+            ],
+            &[],
+        );
+        let recipient = |field, address: &str| Recipient {
+            field,
+            address: address.to_string(),
+        };
+        assert_eq!(
+            recipients(&addressed),
+            [
+                recipient(RecipientField::To, "jane@example.com"),
+                recipient(RecipientField::To, "me@example.com"),
+                recipient(RecipientField::Cc, "me@example.com"),
+            ]
+        );
+        assert_eq!(
+            delivered_to(&addressed).as_deref(),
+            Some("me+lists@example.com")
+        );
+        let unaddressed = message("m2", &[("From", "jane@example.com")], &[]);
+        assert!(recipients(&unaddressed).is_empty());
+        assert_eq!(delivered_to(&unaddressed), None);
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use comfy_table::Cell;
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::table::{self, Style};
+use super::SenderRow;
+use crate::format;
+
+#[derive(Clone, Debug, FromRow)]
+pub struct AccountCount {
+    pub sender: String,
+    pub account: String,
+    pub count: i64,
+}
+
+/// Every account with stored mail, in name order.
+pub async fn accounts(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<String>> {
+    let rows: Vec<(String,)> =
+        sqlx::query_as("SELECT DISTINCT account FROM messages ORDER BY account")
+            .fetch_all(pool)
+            .await?;
+    Ok(rows.into_iter().map(|(account,)| account).collect())
+}
+
+/// Stored messages from each of `senders` per account, with aliases folded into the address
+/// they're counted under and spam left out, as in the senders table.
+pub async fn counts(pool: &Pool<Sqlite>, senders: &[String]) -> anyhow::Result<Vec<AccountCount>> {
+    let rows = sqlx::query_as::<_, AccountCount>(
+        "SELECT coalesce(a.canonical, m.sender) AS sender, m.account, count(*) AS count
+        FROM messages m LEFT JOIN sender_aliases a ON a.sender = lower(m.sender)
+        WHERE NOT m.is_spam AND coalesce(a.canonical, m.sender) IN (SELECT value FROM json_each(?))
+        GROUP BY 1, 2",
+    )
+    .bind(serde_json::to_string(senders)?)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// The top senders' mail split between `accounts`. Counts come from the stored messages, so mail
+/// fetched before messages were stored isn't in any account.
+pub fn render(
+    rows: &[SenderRow],
+    accounts: &[String],
+    counts: &[AccountCount],
+    style: Style,
+) -> String {
+    let by_sender = counts
+        .iter()
+        .map(|count| ((count.sender.as_str(), count.account.as_str()), count.count))
+        .collect::<HashMap<_, _>>();
+    let mut header = accounts.iter().map(String::as_str).collect::<Vec<_>>();
+    header.push("sender");
+    let mut table = table::new(style, &header, 1);
+    let mut totals = vec![0; accounts.len()];
+    for row in rows {
+        let mut cells = Vec::with_capacity(header.len());
+        for (i, account) in accounts.iter().enumerate() {
+            let count = by_sender
+                .get(&(row.sender.as_str(), account.as_str()))
+                .copied()
+                .unwrap_or(0);
+            totals[i] += count;
+            cells.push(Cell::new(format::thousands(count)));
+        }
+        cells.push(Cell::new(&row.sender));
+        table.add_row(cells);
+    }
+
+    let mut out = String::from("by account\n");
+    out.push_str(&table::render(&mut table));
+    let totals = accounts
+        .iter()
+        .zip(totals)
+        .map(|(account, total)| format!("{} {}", format::thousands(total), account))
+        .collect::<Vec<_>>();
+    writeln!(out, "\nthese senders' stored mail: {}", totals.join(", ")).unwrap();
+    out
+}
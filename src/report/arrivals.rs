@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::config::Config;
+use crate::domains;
+use crate::format;
+
+/// Mail from one sender to one of my addresses, as it was written.
+#[derive(Clone, Debug, FromRow)]
+pub struct AliasUse {
+    pub sender: String,
+    pub alias: String,
+    pub count: i64,
+    pub first_seen: Option<i64>,
+    /// The domain the alias was given to, when it's not the sender's: someone else gave it out.
+    pub given_to: Option<String>,
+}
+
+impl AliasUse {
+    pub fn is_unexpected(&self) -> bool {
+        self.given_to.is_some()
+    }
+}
+
+/// Who each of my addresses was first used by, taken to be who it was given to.
+pub async fn first_senders(pool: &Pool<Sqlite>) -> anyhow::Result<HashMap<String, String>> {
+    let rows: Vec<(String, String)> = sqlx::query_as(
+        "SELECT arrived_at, sender FROM (
+            SELECT arrived_at, sender, row_number() OVER (PARTITION BY arrived_at
+                ORDER BY coalesce(internal_date, fetched_at), mail_id) AS n
+            FROM messages WHERE arrived_at IS NOT NULL)
+        WHERE n = 1",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().collect())
+}
+
+/// Senders and the addresses of mine they mail, most mail first. An address other than one of
+/// my addresses as configured, such as a plus-tag or an alias, is taken to have been given to the
+/// first sender to use it; anyone outside that sender's domain using it too is flagged.
+pub async fn uses(
+    pool: &Pool<Sqlite>,
+    config: &Config,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<AliasUse>> {
+    let mut rows = sqlx::query_as::<_, AliasUse>(&format!(
+        "SELECT sender, arrived_at AS alias, count(*) AS count,
+            min(coalesce(internal_date, fetched_at)) AS first_seen, NULL AS given_to
+        FROM messages
+        WHERE arrived_at IS NOT NULL AND {}
+        GROUP BY sender, arrived_at
+        HAVING count >= ?
+        ORDER BY count DESC, sender, alias",
+        scope.patterns("sender")
+    ))
+    .bind(scope.min_count.unwrap_or(1))
+    .fetch_all(pool)
+    .await?;
+
+    let first = first_senders(pool).await?;
+    let domain =
+        |address: &str| domains::domain_of(address).map(|d| domains::registrable_domain(&d));
+    for row in &mut rows {
+        if config.identity.is_primary(&row.alias) {
+            continue;
+        }
+        let Some(given_to) = first.get(&row.alias).and_then(|sender| domain(sender)) else {
+            continue;
+        };
+        if domain(&row.sender).as_ref() != Some(&given_to) {
+            row.given_to = Some(given_to);
+        }
+    }
+    Ok(rows)
+}
+
+pub fn render(rows: &[AliasUse], config: &Config) -> String {
+    let mut out = String::new();
+    if !config.identity.has_addresses() {
+        writeln!(
+            out,
+            "no addresses of mine configured, so no mail is known to have arrived at one of them\n"
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "{:>8} {:>10}  {:<32} sender",
+        "mails", "first", "arrived at"
+    )
+    .unwrap();
+    for row in rows {
+        write!(
+            out,
+            "{:>8} {:>10}  {:<32} {}",
+            format::thousands(row.count),
+            format::date(row.first_seen),
+            row.alias,
+            row.sender
+        )
+        .unwrap();
+        if let Some(given_to) = &row.given_to {
+            write!(out, "  ! given to {}", given_to).unwrap();
+        }
+        out.push('\n');
+    }
+    let mut unexpected = rows
+        .iter()
+        .filter(|row| row.is_unexpected())
+        .map(|row| &row.sender)
+        .collect::<Vec<_>>();
+    unexpected.sort();
+    unexpected.dedup();
+    if !unexpected.is_empty() {
+        writeln!(
+            out,
+            "\n{} senders use an address of mine given to someone else",
+            format::thousands(unexpected.len() as i64)
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::IdentityConfig;
+    use crate::fetch::{self, FetchOptions};
+    use crate::own::Identity;
+    use crate::testsupport::{self, message, MockMailSource};
+    use crate::Storage;
+
+    fn config() -> Config {
+        let mut config = Config {
+            my_addresses: vec!["jane@gmail.com".to_string()],
+            identity_config: IdentityConfig {
+                catch_all_domains: vec!["janedoe.net".to_string()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        config.identity = Identity::new(&config);
+        config
+    }
+
+    // Mail to a plus-tag handed to a shop and to a catch-all address handed to an airline, each
+    // passed on to someone else, and mail to my plain address from everyone
+    async fn fixture(config: &Config) -> Storage {
+        let pool = testsupport::pool().await;
+        let mail = |id, from, headers: &[(&'static str, &'static str)]| {
+            let mut headers = headers.to_vec();
+            headers.push(("From", from));
+            message(id, &headers, &["INBOX"])
+        };
+        let source = MockMailSource::new().page([
+            mail(
+                "m01",
+                "shop@shop.example",
+                &[("To", "Jane <Jane+shop@gmail.com>")],
+            ),
+            mail("m02", "shop@shop.example", &[("To", "jane+shop@gmail.com")]),
+            mail(
+                "m03",
+                "news@mail.shop.example",
+                &[("To", "jane+shop@gmail.com")],
+            ),
+            mail(
+                "m04",
+                "spam@broker.example.net",
+                &[("To", "jane+shop@gmail.com")],
+            ),
+            mail("m05", "friend@example.org", &[("To", "jane@gmail.com")]),
+            mail(
+                "m06",
+                "bank@bank.example",
+                &[("To", "someone@example.com"), ("Cc", "jane@gmail.com")],
+            ),
+            mail(
+                "m07",
+                "airline@airline.example",
+                &[("To", "airline@janedoe.net")],
+            ),
+            mail(
+                "m08",
+                "offers@partner.example",
+                &[
+                    ("To", "undisclosed-recipients:;"),
+                    ("Delivered-To", "airline@janedoe.net"),
+                ],
+            ),
+            mail(
+                "m09",
+                "list@lists.example.com",
+                &[("To", "list@lists.example.com")],
+            ),
+        ]);
+        let opts = FetchOptions {
+            own: config.identity.clone(),
+            quiet: true,
+            ..Default::default()
+        };
+        fetch::run(&pool, &source, &opts).await.unwrap();
+        crate::classify(&pool, config).await.unwrap();
+        pool
+    }
+
+    // (sender, alias, count, given_to) of every row
+    fn summarize(rows: &[AliasUse]) -> Vec<(&str, &str, i64, Option<&str>)> {
+        rows.iter()
+            .map(|row| {
+                (
+                    row.sender.as_str(),
+                    row.alias.as_str(),
+                    row.count,
+                    row.given_to.as_deref(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn addresses_given_out_are_flagged_when_someone_else_uses_them() {
+        let config = config();
+        let pool = fixture(&config).await;
+
+        let rows = uses(&pool, &config, &SenderScope::default()).await.unwrap();
+
+        // Plus-tags are kept as written, lowercased; the shop's own subdomain isn't someone else,
+        // and my plain address is given to everyone
+        assert_eq!(
+            summarize(&rows),
+            [
+                ("shop@shop.example", "jane+shop@gmail.com", 2, None),
+                ("airline@airline.example", "airline@janedoe.net", 1, None),
+                ("bank@bank.example", "jane@gmail.com", 1, None),
+                ("friend@example.org", "jane@gmail.com", 1, None),
+                ("news@mail.shop.example", "jane+shop@gmail.com", 1, None),
+                (
+                    "offers@partner.example",
+                    "airline@janedoe.net",
+                    1,
+                    Some("airline.example")
+                ),
+                (
+                    "spam@broker.example.net",
+                    "jane+shop@gmail.com",
+                    1,
+                    Some("shop.example")
+                ),
+            ]
+        );
+        let first = first_senders(&pool).await.unwrap();
+        assert_eq!(first["jane+shop@gmail.com"], "shop@shop.example");
+        assert_eq!(first["airline@janedoe.net"], "airline@airline.example");
+    }
+
+    #[tokio::test]
+    async fn the_minimum_count_applies_to_each_pair() {
+        let config = config();
+        let pool = fixture(&config).await;
+        let mut scope = SenderScope::default();
+        scope.min_count = Some(2);
+
+        let rows = uses(&pool, &config, &scope).await.unwrap();
+
+        assert_eq!(
+            summarize(&rows),
+            [("shop@shop.example", "jane+shop@gmail.com", 2, None)]
+        );
+    }
+
+    #[tokio::test]
+    async fn the_report_marks_and_counts_unexpected_senders() {
+        let config = config();
+        let pool = fixture(&config).await;
+        let rows = uses(&pool, &config, &SenderScope::default()).await.unwrap();
+
+        let out = render(&rows, &config);
+
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1 + 7 + 2);
+        assert!(lines[0].starts_with("   mails      first  arrived at"));
+        assert!(lines[6].ends_with(
+            "airline@janedoe.net              offers@partner.example  ! given to airline.example"
+        ));
+        assert_eq!(
+            lines[9],
+            "2 senders use an address of mine given to someone else"
+        );
+        assert!(render(&rows, &Config::default()).starts_with("no addresses of mine configured"));
+    }
+}
@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::format;
+use crate::parse::attachment_kind;
+
+#[derive(Clone, Debug, FromRow)]
+pub struct AttachmentSender {
+    pub sender: String,
+    pub attachments: i64,
+    pub bytes: i64,
+}
+
+/// Attachment totals for one coarse kind (pdf, image, ...) and its MIME types.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KindTotal {
+    pub kind: &'static str,
+    pub count: i64,
+    pub bytes: i64,
+    /// (mime type, count, bytes), largest first.
+    pub mime_types: Vec<(String, i64, i64)>,
+}
+
+pub async fn top_senders(
+    pool: &Pool<Sqlite>,
+    limit: usize,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<AttachmentSender>> {
+    let rows = sqlx::query_as::<_, AttachmentSender>(&format!(
+        "SELECT sender, sum(attachment_count) AS attachments, sum(attachment_bytes) AS bytes
+        FROM messages
+        WHERE {}
+        GROUP BY sender
+        HAVING attachments > 0
+        ORDER BY bytes DESC, attachments DESC, sender
+        LIMIT ?",
+        scope.condition("sender", None)
+    ))
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn by_type(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<KindTotal>> {
+    let rows: Vec<(String, i64, i64)> = sqlx::query_as(
+        "SELECT mime_type, count(*), sum(size) FROM attachments
+        GROUP BY mime_type
+        ORDER BY sum(size) DESC, mime_type",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(group_by_kind(rows))
+}
+
+pub fn group_by_kind(rows: Vec<(String, i64, i64)>) -> Vec<KindTotal> {
+    let mut kinds: BTreeMap<&'static str, KindTotal> = BTreeMap::new();
+    for (mime_type, count, bytes) in rows {
+        let kind = attachment_kind(&mime_type);
+        let total = kinds.entry(kind).or_insert_with(|| KindTotal {
+            kind,
+            ..Default::default()
+        });
+        total.count += count;
+        total.bytes += bytes;
+        total.mime_types.push((mime_type, count, bytes));
+    }
+
+    let mut totals = kinds.into_values().collect::<Vec<_>>();
+    totals.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.kind.cmp(b.kind)));
+    totals
+}
+
+pub fn render(senders: &[AttachmentSender], kinds: &[KindTotal]) -> String {
+    let mut out = String::new();
+
+    writeln!(out, "top attachment senders").unwrap();
+    writeln!(out, "{:>10} {:>10}  sender", "files", "bytes").unwrap();
+    for sender in senders {
+        writeln!(
+            out,
+            "{:>10} {:>10}  {}",
+            format::thousands(sender.attachments),
+            format::bytes(sender.bytes),
+            sender.sender
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "\nattachment types").unwrap();
+    writeln!(out, "{:>10} {:>10}  type", "files", "bytes").unwrap();
+    for kind in kinds {
+        writeln!(
+            out,
+            "{:>10} {:>10}  {}",
+            format::thousands(kind.count),
+            format::bytes(kind.bytes),
+            kind.kind
+        )
+        .unwrap();
+        for (mime_type, count, bytes) in &kind.mime_types {
+            writeln!(
+                out,
+                "{:>10} {:>10}    {}",
+                format::thousands(*count),
+                format::bytes(*bytes),
+                mime_type
+            )
+            .unwrap();
+        }
+    }
+    out
+}
@@ -0,0 +1,122 @@
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::format;
+
+#[derive(Clone, Debug, Default, FromRow)]
+pub struct DomainAuth {
+    pub domain: String,
+    /// Messages with any authentication results.
+    pub messages: i64,
+    pub spf_fail: i64,
+    pub dkim_fail: i64,
+    pub dmarc_fail: i64,
+    /// Messages failing at least one mechanism.
+    pub failed: i64,
+}
+
+// Anything other than a pass or the absence of a policy counts as a failure, so softfail,
+/// Domains with fewer messages are left out unless `--min-count` says otherwise.
+pub const MIN_COUNT: i64 = 1;
+
+// temperror and permerror show up too
+fn failed(column: &str) -> String {
+    format!("coalesce({} NOT IN ('pass', 'none', 'neutral'), 0)", column)
+}
+
+fn aggregates() -> String {
+    format!(
+        "count(*) AS messages, sum({spf}) AS spf_fail, sum({dkim}) AS dkim_fail,
+        sum({dmarc}) AS dmarc_fail, sum({spf} OR {dkim} OR {dmarc}) AS failed",
+        spf = failed("spf"),
+        dkim = failed("dkim"),
+        dmarc = failed("dmarc")
+    )
+}
+
+/// Every message with authentication results, as one row.
+pub async fn overall(pool: &Pool<Sqlite>, scope: &SenderScope) -> anyhow::Result<DomainAuth> {
+    let row = sqlx::query_as::<_, DomainAuth>(&format!(
+        "SELECT 'all' AS domain, {} FROM messages
+        WHERE coalesce(spf, dkim, dmarc) IS NOT NULL AND {}",
+        aggregates(),
+        scope.patterns("sender")
+    ))
+    .fetch_one(pool)
+    .await?;
+    Ok(row)
+}
+
+/// Sender domains with failures, highest failure rate first.
+pub async fn failing_domains(
+    pool: &Pool<Sqlite>,
+    min_count: i64,
+    limit: usize,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<DomainAuth>> {
+    let rows = sqlx::query_as::<_, DomainAuth>(&format!(
+        "SELECT lower(substr(sender, instr(sender, '@') + 1)) AS domain, {}
+        FROM messages
+        WHERE coalesce(spf, dkim, dmarc) IS NOT NULL AND {}
+        GROUP BY domain
+        HAVING failed > 0 AND messages >= ?
+        ORDER BY failed * 1.0 / messages DESC, messages DESC, domain
+        LIMIT ?",
+        aggregates(),
+        scope.patterns("sender")
+    ))
+    .bind(min_count)
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+fn percent(part: i64, total: i64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    part as f64 * 100.0 / total as f64
+}
+
+fn render_row(out: &mut String, row: &DomainAuth) {
+    writeln!(
+        out,
+        "{:>8} {:>5.1}% {:>5.1}% {:>5.1}% {:>5.1}%  {}",
+        format::thousands(row.messages),
+        percent(row.failed, row.messages),
+        percent(row.spf_fail, row.messages),
+        percent(row.dkim_fail, row.messages),
+        percent(row.dmarc_fail, row.messages),
+        row.domain
+    )
+    .unwrap();
+}
+
+pub fn render(overall: &DomainAuth, domains: &[DomainAuth]) -> String {
+    let mut out = String::new();
+    if overall.messages == 0 {
+        writeln!(
+            out,
+            "no authentication results recorded, they're only stored for mail fetched since this \
+            was added"
+        )
+        .unwrap();
+        return out;
+    }
+
+    writeln!(
+        out,
+        "{:>8} {:>6} {:>6} {:>6} {:>6}  domain",
+        "mails", "failed", "spf", "dkim", "dmarc"
+    )
+    .unwrap();
+    render_row(&mut out, overall);
+    out.push('\n');
+    for domain in domains {
+        render_row(&mut out, domain);
+    }
+    out
+}
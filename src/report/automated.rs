@@ -0,0 +1,109 @@
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::{pattern::SenderScope, MERGED_SENDERS};
+use crate::format;
+
+#[derive(Clone, Debug, FromRow)]
+pub struct KindCount {
+    /// A parse::AutoKind, `None` for mail a person wrote.
+    pub kind: Option<String>,
+    pub count: i64,
+}
+
+#[derive(Clone, Debug, FromRow)]
+pub struct AutomatedSender {
+    pub sender: String,
+    pub mails_sent: i64,
+    pub auto_count: i64,
+}
+
+/// Received mail by kind of automation, most first.
+pub async fn by_kind(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<KindCount>> {
+    let rows = sqlx::query_as::<_, KindCount>(
+        "SELECT auto_kind AS kind, count(*) AS count FROM messages
+        WHERE NOT is_spam
+        GROUP BY auto_kind
+        ORDER BY count DESC, kind",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn top_senders(
+    pool: &Pool<Sqlite>,
+    limit: usize,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<AutomatedSender>> {
+    let rows = sqlx::query_as::<_, AutomatedSender>(&format!(
+        "SELECT sender, mails_sent, auto_count FROM {}
+        WHERE auto_count > 0 AND {}
+        ORDER BY auto_count DESC, sender
+        LIMIT ?",
+        MERGED_SENDERS,
+        scope.condition("sender", Some("mails_sent"))
+    ))
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub fn render(kinds: &[KindCount], senders: &[AutomatedSender]) -> String {
+    let mut out = String::new();
+    let total: i64 = kinds.iter().map(|kind| kind.count).sum();
+    let automated: i64 = kinds
+        .iter()
+        .filter(|kind| kind.kind.is_some())
+        .map(|kind| kind.count)
+        .sum();
+    let percent = |count: i64| {
+        if total > 0 {
+            count as f64 * 100.0 / total as f64
+        } else {
+            0.0
+        }
+    };
+
+    writeln!(
+        out,
+        "{} of {} messages ({:.1}%) are machine-generated\n",
+        format::thousands(automated),
+        format::thousands(total),
+        percent(automated)
+    )
+    .unwrap();
+    writeln!(out, "{:>10} {:>6}  kind", "mails", "%").unwrap();
+    for kind in kinds {
+        writeln!(
+            out,
+            "{:>10} {:>5.1}%  {}",
+            format::thousands(kind.count),
+            percent(kind.count),
+            kind.kind.as_deref().unwrap_or("written by a person")
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "\ntop automated senders").unwrap();
+    writeln!(
+        out,
+        "{:>10} {:>10} {:>5}  sender",
+        "automated", "mails", "%"
+    )
+    .unwrap();
+    for sender in senders {
+        writeln!(
+            out,
+            "{:>10} {:>10} {:>4.0}%  {}",
+            format::thousands(sender.auto_count),
+            format::thousands(sender.mails_sent),
+            sender.auto_count as f64 * 100.0 / sender.mails_sent as f64,
+            sender.sender
+        )
+        .unwrap();
+    }
+    out
+}
@@ -0,0 +1,197 @@
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::format;
+use crate::parse::NoticeKind;
+
+#[derive(Clone, Debug, FromRow)]
+pub struct NoticeCount {
+    pub address: String,
+    pub count: i64,
+    pub last_seen: Option<i64>,
+}
+
+/// Addresses with the most notices of `kind`, most first.
+pub async fn top_addresses(
+    pool: &Pool<Sqlite>,
+    kind: NoticeKind,
+    limit: usize,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<NoticeCount>> {
+    let rows = sqlx::query_as::<_, NoticeCount>(&format!(
+        "SELECT notice_address AS address, count(*) AS count, max(internal_date) AS last_seen
+        FROM messages
+        WHERE notice = ? AND notice_address IS NOT NULL AND {}
+        GROUP BY notice_address
+        HAVING count >= ?
+        ORDER BY count DESC, address
+        LIMIT ?",
+        scope.patterns("notice_address")
+    ))
+    .bind(kind.as_str())
+    .bind(scope.min_count.unwrap_or(1))
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Bounces which didn't say who they were for.
+pub async fn unattributed_bounces(pool: &Pool<Sqlite>) -> anyhow::Result<i64> {
+    let (count,): (i64,) =
+        sqlx::query_as("SELECT count(*) FROM messages WHERE notice = ? AND notice_address IS NULL")
+            .bind(NoticeKind::Bounce.as_str())
+            .fetch_one(pool)
+            .await?;
+    Ok(count)
+}
+
+fn render_rows(out: &mut String, title: &str, rows: &[NoticeCount]) {
+    writeln!(out, "{}", title).unwrap();
+    writeln!(out, "{:>8} {:>10}  address", "notices", "last").unwrap();
+    for row in rows {
+        writeln!(
+            out,
+            "{:>8} {:>10}  {}",
+            format::thousands(row.count),
+            format::date(row.last_seen),
+            row.address
+        )
+        .unwrap();
+    }
+}
+
+pub fn render(bounces: &[NoticeCount], unattributed: i64, away: &[NoticeCount]) -> String {
+    let mut out = String::new();
+    render_rows(&mut out, "addresses my mail bounced from", bounces);
+    if unattributed > 0 {
+        writeln!(
+            out,
+            "plus {} bounces that didn't name the recipient",
+            format::thousands(unattributed)
+        )
+        .unwrap();
+    }
+    out.push('\n');
+    render_rows(&mut out, "most out-of-office replies", away);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::db::{self, DEFAULT_ACCOUNT};
+    use crate::parse::{MessageInfo, ParseOptions};
+    use crate::testsupport::{self, message, part, with_parts};
+
+    // Two bounces for Jane, one found only in the status part, one for Bob, one which didn't
+    // say, and an out-of-office reply from Jane
+    async fn fixture() -> Pool<Sqlite> {
+        let pool = testsupport::pool().await;
+        let daemon = ("From", "mailer-daemon@googlemail.com");
+        let fixtures = [
+            with_parts(
+                message(
+                    "m1",
+                    &[daemon, ("X-Failed-Recipients", "jane@example.com")],
+                    &[],
+                ),
+                "multipart/report",
+                vec![],
+            ),
+            with_parts(
+                message("m2", &[("From", "postmaster@mx.example.org")], &[]),
+                "multipart/report",
+                vec![part(
+                    "message/delivery-status",
+                    &[],
+                    "Final-Recipient: rfc822; Jane@Example.com\nAction: failed\n",
+                )],
+            ),
+            message(
+                "m3",
+                &[daemon, ("X-Failed-Recipients", "bob@example.org")],
+                &[],
+            ),
+            message("m4", &[daemon], &[]),
+            message(
+                "m5",
+                &[("From", "jane@example.com"), ("Subject", "Automatic reply")],
+                &[],
+            ),
+        ];
+        let mut conn = pool.acquire().await.unwrap();
+        for (date, mut message) in fixtures.into_iter().enumerate() {
+            message.internal_date = Some((date as i64 * 86_400_000).to_string());
+            let info = MessageInfo::from_message(&message, &ParseOptions::default()).unwrap();
+            db::record_message(
+                &info,
+                DEFAULT_ACCOUNT,
+                None,
+                DateTime::UNIX_EPOCH,
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        }
+        drop(conn);
+        pool
+    }
+
+    fn counts(rows: &[NoticeCount]) -> Vec<(&str, i64, Option<i64>)> {
+        rows.iter()
+            .map(|row| (row.address.as_str(), row.count, row.last_seen))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn bounces_are_counted_per_recipient() {
+        let pool = fixture().await;
+        let scope = SenderScope::default();
+
+        let bounces = top_addresses(&pool, NoticeKind::Bounce, 10, &scope)
+            .await
+            .unwrap();
+        assert_eq!(
+            counts(&bounces),
+            [
+                ("jane@example.com", 2, Some(86_400_000)),
+                ("bob@example.org", 1, Some(2 * 86_400_000))
+            ]
+        );
+        assert_eq!(unattributed_bounces(&pool).await.unwrap(), 1);
+        let away = top_addresses(&pool, NoticeKind::OutOfOffice, 10, &scope)
+            .await
+            .unwrap();
+        assert_eq!(
+            counts(&away),
+            [("jane@example.com", 1, Some(4 * 86_400_000))]
+        );
+
+        let out = render(&bounces, 1, &away);
+        assert!(out.contains("plus 1 bounces that didn't name the recipient"));
+        assert_eq!(out.matches("jane@example.com").count(), 2);
+    }
+
+    #[tokio::test]
+    async fn the_top_and_minimum_count_are_applied() {
+        let pool = fixture().await;
+        let mut scope = SenderScope::default();
+        scope.min_count = Some(2);
+        let bounces = top_addresses(&pool, NoticeKind::Bounce, 10, &scope)
+            .await
+            .unwrap();
+        assert_eq!(
+            counts(&bounces),
+            [("jane@example.com", 2, Some(86_400_000))]
+        );
+        let top = top_addresses(&pool, NoticeKind::Bounce, 1, &SenderScope::default())
+            .await
+            .unwrap();
+        assert_eq!(top.len(), 1);
+    }
+}
@@ -0,0 +1,282 @@
+use std::cmp::Ordering;
+
+use super::SenderRow;
+use crate::cli::SortBy;
+
+/// The sender list the TUI shows, apart from any drawing: every loaded sender, the sort and
+/// search applied to them, and which one is selected.
+#[derive(Clone, Debug)]
+pub struct SenderList {
+    rows: Vec<SenderRow>,
+    sort: SortBy,
+    /// Smallest or oldest first rather than largest or latest first.
+    reversed: bool,
+    search: String,
+    /// Indexes into `rows` which match the search, in sort order.
+    visible: Vec<usize>,
+    /// Index into `visible`.
+    selected: usize,
+}
+
+impl SenderList {
+    /// `rows` sorted by count, unfiltered, with the first selected.
+    pub fn new(rows: Vec<SenderRow>) -> SenderList {
+        let mut list = SenderList {
+            rows,
+            sort: SortBy::Count,
+            reversed: false,
+            search: String::new(),
+            visible: Vec::new(),
+            selected: 0,
+        };
+        list.refresh();
+        list
+    }
+
+    pub fn sort(&self) -> SortBy {
+        self.sort
+    }
+
+    pub fn reversed(&self) -> bool {
+        self.reversed
+    }
+
+    pub fn search(&self) -> &str {
+        &self.search
+    }
+
+    /// Number of senders loaded, whether or not they match the search.
+    pub fn total(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Senders matching the search, in sort order.
+    pub fn visible(&self) -> impl Iterator<Item = &SenderRow> {
+        self.visible.iter().map(|&i| &self.rows[i])
+    }
+
+    pub fn len(&self) -> usize {
+        self.visible.len()
+    }
+
+    /// Position of the selection among the visible senders, `None` when nothing matches.
+    pub fn selected_index(&self) -> Option<usize> {
+        (!self.visible.is_empty()).then_some(self.selected)
+    }
+
+    pub fn selected(&self) -> Option<&SenderRow> {
+        self.visible.get(self.selected).map(|&i| &self.rows[i])
+    }
+
+    /// Sort by `sort`, or reverse the order if already sorted by it. The selected sender stays
+    /// selected.
+    pub fn toggle_sort(&mut self, sort: SortBy) {
+        if self.sort == sort {
+            self.reversed = !self.reversed;
+        } else {
+            self.sort = sort;
+            self.reversed = false;
+        }
+        self.refresh();
+    }
+
+    /// Show only senders containing `search`, ignoring case.
+    pub fn set_search(&mut self, search: &str) {
+        self.search = search.to_string();
+        self.refresh();
+    }
+
+    pub fn push_search(&mut self, c: char) {
+        self.search.push(c);
+        self.refresh();
+    }
+
+    pub fn pop_search(&mut self) {
+        self.search.pop();
+        self.refresh();
+    }
+
+    /// Move the selection by `delta` rows, stopping at either end.
+    pub fn move_by(&mut self, delta: isize) {
+        let last = self.visible.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    pub fn select_first(&mut self) {
+        self.selected = 0;
+    }
+
+    pub fn select_last(&mut self) {
+        self.selected = self.visible.len().saturating_sub(1);
+    }
+
+    // Recompute `visible` after the sort or search changed, keeping the selected sender selected
+    // if it's still visible and starting from the top otherwise
+    fn refresh(&mut self) {
+        let previous = self.selected().map(|row| row.sender.clone());
+        let search = self.search.to_lowercase();
+        let mut visible = (0..self.rows.len())
+            .filter(|&i| self.rows[i].sender.to_lowercase().contains(&search))
+            .collect::<Vec<_>>();
+        visible.sort_by(|&a, &b| compare(&self.rows[a], &self.rows[b], self.sort, self.reversed));
+        self.selected = previous
+            .and_then(|sender| visible.iter().position(|&i| self.rows[i].sender == sender))
+            .unwrap_or(0);
+        self.visible = visible;
+    }
+}
+
+// `a` before `b` when it has more mail, more bytes or later mail, the other way round if
+// `reversed`. Senders never seen go last either way, and ties go by address so the order
+// doesn't change between toggles.
+fn compare(a: &SenderRow, b: &SenderRow, sort: SortBy, reversed: bool) -> Ordering {
+    let key = match sort {
+        SortBy::Count => b.mails_sent.cmp(&a.mails_sent),
+        SortBy::Bytes => b.bytes.cmp(&a.bytes),
+        SortBy::Recent => match (a.last_seen, b.last_seen) {
+            (Some(x), Some(y)) => y.cmp(&x),
+            (x, y) => {
+                return x
+                    .is_none()
+                    .cmp(&y.is_none())
+                    .then_with(|| a.sender.cmp(&b.sender))
+            }
+        },
+    };
+    let key = if reversed { key.reverse() } else { key };
+    key.then_with(|| a.sender.cmp(&b.sender))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(sender: &str, mails_sent: i64, bytes: i64, last_seen: Option<i64>) -> SenderRow {
+        SenderRow {
+            sender: sender.to_string(),
+            mails_sent,
+            bulk_count: 0,
+            unread_count: 0,
+            direct_count: 0,
+            cc_count: 0,
+            auto_count: 0,
+            bytes,
+            last_seen,
+        }
+    }
+
+    fn list() -> SenderList {
+        SenderList::new(vec![
+            row("jane@example.com", 3, 9_000, Some(300)),
+            row("news@shop.example", 9, 90_000, Some(100)),
+            row("bob@example.org", 3, 1_000, None),
+            row("alerts@bank.example", 1, 50_000, Some(200)),
+        ])
+    }
+
+    fn order(list: &SenderList) -> Vec<&str> {
+        list.visible().map(|row| row.sender.as_str()).collect()
+    }
+
+    #[test]
+    fn senders_start_by_count_with_the_first_selected() {
+        let list = list();
+        assert_eq!(
+            order(&list),
+            [
+                "news@shop.example",
+                "bob@example.org",
+                "jane@example.com",
+                "alerts@bank.example"
+            ]
+        );
+        assert_eq!((list.sort(), list.reversed()), (SortBy::Count, false));
+        assert_eq!(list.selected_index(), Some(0));
+        assert_eq!((list.len(), list.total()), (4, 4));
+    }
+
+    #[test]
+    fn sorting_again_reverses_and_keeps_the_selection() {
+        let mut list = list();
+        list.move_by(2);
+        assert_eq!(list.selected().unwrap().sender, "jane@example.com");
+
+        list.toggle_sort(SortBy::Bytes);
+        assert_eq!(
+            order(&list),
+            [
+                "news@shop.example",
+                "alerts@bank.example",
+                "jane@example.com",
+                "bob@example.org"
+            ]
+        );
+        assert_eq!(list.selected_index(), Some(2));
+
+        list.toggle_sort(SortBy::Bytes);
+        assert!(list.reversed());
+        assert_eq!(order(&list)[0], "bob@example.org");
+        assert_eq!(list.selected().unwrap().sender, "jane@example.com");
+    }
+
+    #[test]
+    fn senders_never_seen_go_last_either_way() {
+        let mut list = list();
+        list.toggle_sort(SortBy::Recent);
+        assert_eq!(
+            order(&list),
+            [
+                "jane@example.com",
+                "alerts@bank.example",
+                "news@shop.example",
+                "bob@example.org"
+            ]
+        );
+        list.toggle_sort(SortBy::Recent);
+        assert_eq!(
+            order(&list),
+            [
+                "news@shop.example",
+                "alerts@bank.example",
+                "jane@example.com",
+                "bob@example.org"
+            ]
+        );
+    }
+
+    #[test]
+    fn the_search_narrows_the_list_ignoring_case() {
+        let mut list = list();
+        list.select_last();
+        list.set_search("EXAMPLE.");
+        assert_eq!(order(&list), ["bob@example.org", "jane@example.com"]);
+        // The selected sender no longer matches, so the selection goes back to the top
+        assert_eq!(list.selected_index(), Some(0));
+
+        list.push_search('o');
+        assert_eq!(order(&list), ["bob@example.org"]);
+        list.push_search('x');
+        assert_eq!(list.selected_index(), None);
+        assert!(list.selected().is_none());
+        list.pop_search();
+        list.pop_search();
+        assert_eq!(list.search(), "EXAMPLE.");
+        assert_eq!(list.len(), 2);
+    }
+
+    #[test]
+    fn the_selection_stops_at_either_end() {
+        let mut list = list();
+        list.move_by(-5);
+        assert_eq!(list.selected_index(), Some(0));
+        list.move_by(20);
+        assert_eq!(list.selected_index(), Some(3));
+        list.select_first();
+        assert_eq!(list.selected_index(), Some(0));
+
+        let mut empty = SenderList::new(Vec::new());
+        empty.move_by(1);
+        empty.select_last();
+        assert_eq!(empty.selected_index(), None);
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::format;
+
+#[derive(Clone, Debug, FromRow)]
+pub struct Organizer {
+    /// The ORGANIZER address, or the sender when the invite didn't say.
+    pub organizer: String,
+    pub messages: i64,
+    pub requests: i64,
+    pub replies: i64,
+    pub cancels: i64,
+}
+
+/// Calendar mail per organizer, most first.
+pub async fn organizers(
+    pool: &Pool<Sqlite>,
+    limit: usize,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<Organizer>> {
+    let rows = sqlx::query_as::<_, Organizer>(&format!(
+        "SELECT coalesce(calendar_organizer, lower(sender)) AS organizer, count(*) AS messages,
+            sum(calendar_method = 'REQUEST') AS requests,
+            sum(calendar_method = 'REPLY') AS replies,
+            sum(calendar_method = 'CANCEL') AS cancels
+        FROM messages
+        WHERE is_calendar AND NOT is_spam AND {}
+        GROUP BY organizer
+        HAVING messages >= ?
+        ORDER BY messages DESC, organizer
+        LIMIT ?",
+        scope.patterns("coalesce(calendar_organizer, sender)")
+    ))
+    .bind(scope.min_count.unwrap_or(1))
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub fn render(organizers: &[Organizer]) -> String {
+    let mut out = String::new();
+    if organizers.is_empty() {
+        writeln!(out, "no calendar mail recorded").unwrap();
+        return out;
+    }
+
+    writeln!(
+        out,
+        "{:>8} {:>8} {:>8} {:>8} {:>8}  organizer",
+        "mails", "invites", "replies", "cancels", "other"
+    )
+    .unwrap();
+    for organizer in organizers {
+        writeln!(
+            out,
+            "{:>8} {:>8} {:>8} {:>8} {:>8}  {}",
+            format::thousands(organizer.messages),
+            format::thousands(organizer.requests),
+            format::thousands(organizer.replies),
+            format::thousands(organizer.cancels),
+            format::thousands(
+                organizer.messages - organizer.requests - organizer.replies - organizer.cancels
+            ),
+            organizer.organizer
+        )
+        .unwrap();
+    }
+    out
+}
@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::format;
+
+/// Bucket for messages without any `CATEGORY_*` label.
+pub const UNCATEGORIZED: &str = "uncategorized";
+
+#[derive(Clone, Debug, FromRow)]
+pub struct CategorySenderCount {
+    pub category: String,
+    pub sender: String,
+    pub count: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CategorySummary {
+    pub category: String,
+    pub count: i64,
+    /// Top senders in this category, most messages first.
+    pub senders: Vec<(String, i64)>,
+}
+
+pub async fn sender_counts(
+    pool: &Pool<Sqlite>,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<CategorySenderCount>> {
+    let rows = sqlx::query_as::<_, CategorySenderCount>(&format!(
+        "SELECT category, sender, count(*) AS count FROM (
+            SELECT m.sender, coalesce(
+                (SELECT lower(substr(l.label, 10)) FROM message_labels l
+                WHERE l.account = m.account AND l.mail_id = m.mail_id
+                    AND l.label LIKE 'CATEGORY\\_%' ESCAPE '\\'
+                ORDER BY l.label LIMIT 1),
+                ?) AS category
+            FROM messages m
+            WHERE {}
+        )
+        GROUP BY category, sender
+        ORDER BY category, count DESC, sender",
+        scope.condition("m.sender", None)
+    ))
+    .bind(UNCATEGORIZED)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Fold per-sender counts into per-category totals, keeping the `top` senders of each.
+pub fn summarize(counts: Vec<CategorySenderCount>, top: usize) -> Vec<CategorySummary> {
+    let mut categories: BTreeMap<String, CategorySummary> = BTreeMap::new();
+    // Rows arrive ordered by count within each category
+    for row in counts {
+        let summary = categories
+            .entry(row.category.clone())
+            .or_insert_with(|| CategorySummary {
+                category: row.category,
+                count: 0,
+                senders: Vec::new(),
+            });
+        summary.count += row.count;
+        if summary.senders.len() < top {
+            summary.senders.push((row.sender, row.count));
+        }
+    }
+
+    let mut summaries = categories.into_values().collect::<Vec<_>>();
+    summaries.sort_by(|a, b| b.count.cmp(&a.count).then(a.category.cmp(&b.category)));
+    summaries
+}
+
+pub fn render(summaries: &[CategorySummary]) -> String {
+    let mut out = String::new();
+    let total: i64 = summaries.iter().map(|summary| summary.count).sum();
+
+    for summary in summaries {
+        writeln!(
+            out,
+            "{:<14} {:>10} {:>5.1}%",
+            summary.category,
+            format::thousands(summary.count),
+            if total > 0 {
+                summary.count as f64 * 100.0 / total as f64
+            } else {
+                0.0
+            }
+        )
+        .unwrap();
+        for (sender, count) in &summary.senders {
+            writeln!(out, "    {:>10}  {}", format::thousands(*count), sender).unwrap();
+        }
+    }
+    if summaries.is_empty() {
+        writeln!(out, "no messages").unwrap();
+    }
+    out
+}
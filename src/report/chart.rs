@@ -0,0 +1,204 @@
+use std::path::Path;
+
+use plotters::coord::Shift;
+use plotters::prelude::*;
+
+use super::trend::Bucket;
+use super::SenderRow;
+use crate::cli::ChartKind;
+
+/// Sender labels longer than this many characters are cut short with an ellipsis.
+const MAX_LABEL_CHARS: usize = 32;
+
+/// Roughly how wide a label character is at `LABEL_FONT_SIZE`, for sizing the label area.
+const LABEL_CHAR_WIDTH: u32 = 8;
+const LABEL_FONT_SIZE: u32 = 13;
+const CAPTION_FONT_SIZE: u32 = 20;
+
+/// Longer trends are drawn as a bare line.
+const MAX_MARKED_POINTS: usize = 48;
+
+/// One bar of a bar chart, or one point of a line chart.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Point {
+    pub label: String,
+    pub value: i64,
+}
+
+// "a-very-long-sender@example.com" -> "a-very-long-send…"
+pub fn truncate_label(label: &str, max_chars: usize) -> String {
+    if label.chars().count() <= max_chars {
+        return label.to_string();
+    }
+    let mut truncated = label
+        .chars()
+        .take(max_chars.saturating_sub(1))
+        .collect::<String>();
+    truncated.push('\u{2026}');
+    truncated
+}
+
+/// One bar per sender, in the order given, with labels truncated to fit.
+pub fn sender_points(rows: &[SenderRow]) -> Vec<Point> {
+    rows.iter()
+        .map(|row| Point {
+            label: truncate_label(&row.sender, MAX_LABEL_CHARS),
+            value: row.mails_sent,
+        })
+        .collect()
+}
+
+pub fn month_points(buckets: &[Bucket]) -> Vec<Point> {
+    buckets
+        .iter()
+        .map(|bucket| Point {
+            label: bucket.label.clone(),
+            value: bucket.count,
+        })
+        .collect()
+}
+
+/// Draw `points` to `out`, as SVG if it ends in `.svg` and PNG otherwise. Fails rather than
+/// writing an empty chart when there's nothing to plot.
+pub fn draw(
+    kind: ChartKind,
+    points: &[Point],
+    out: &Path,
+    width: u32,
+    height: u32,
+) -> anyhow::Result<()> {
+    if points.iter().all(|point| point.value == 0) {
+        anyhow::bail!("nothing to chart, no messages are stored yet");
+    }
+
+    let svg = out
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("svg"));
+    if svg {
+        draw_on(
+            kind,
+            points,
+            SVGBackend::new(out, (width, height)).into_drawing_area(),
+        )
+    } else {
+        draw_on(
+            kind,
+            points,
+            BitMapBackend::new(out, (width, height)).into_drawing_area(),
+        )
+    }
+}
+
+fn draw_on<DB: DrawingBackend>(
+    kind: ChartKind,
+    points: &[Point],
+    root: DrawingArea<DB, Shift>,
+) -> anyhow::Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+    match kind {
+        ChartKind::TopSenders => bar_chart(points, &root)?,
+        ChartKind::Trend => line_chart(points, &root)?,
+    }
+    root.present()?;
+    Ok(())
+}
+
+fn bar_chart<DB: DrawingBackend>(
+    points: &[Point],
+    root: &DrawingArea<DB, Shift>,
+) -> anyhow::Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let max = points.iter().map(|point| point.value).max().unwrap_or(0);
+    let longest = points
+        .iter()
+        .map(|point| point.label.chars().count())
+        .max()
+        .unwrap_or(0);
+    let count = points.len() as i32;
+
+    let mut chart = ChartBuilder::on(root)
+        .caption("Top senders", ("sans-serif", CAPTION_FONT_SIZE))
+        .margin(10)
+        .x_label_area_size(30)
+        .y_label_area_size(longest as u32 * LABEL_CHAR_WIDTH + 10)
+        // One segment per bar, though plotters needs a non-empty range
+        .build_cartesian_2d(
+            0..max + max / 20 + 1,
+            (0..(count - 1).max(1)).into_segmented(),
+        )?;
+    chart
+        .configure_mesh()
+        .disable_y_mesh()
+        .y_labels(points.len())
+        .y_label_style(("sans-serif", LABEL_FONT_SIZE))
+        // The busiest sender is drawn at the top
+        .y_label_formatter(&|segment| match segment {
+            SegmentValue::CenterOf(i) => points
+                .get((count - 1 - i) as usize)
+                .map(|point| point.label.clone())
+                .unwrap_or_default(),
+            _ => String::new(),
+        })
+        .x_desc("messages")
+        .draw()?;
+
+    chart.draw_series(points.iter().enumerate().map(|(i, point)| {
+        let row = count - 1 - i as i32;
+        let mut bar = Rectangle::new(
+            [
+                (0, SegmentValue::Exact(row)),
+                (point.value, SegmentValue::Exact(row + 1)),
+            ],
+            BLUE.mix(0.6).filled(),
+        );
+        bar.set_margin(2, 2, 0, 0);
+        bar
+    }))?;
+    Ok(())
+}
+
+fn line_chart<DB: DrawingBackend>(
+    points: &[Point],
+    root: &DrawingArea<DB, Shift>,
+) -> anyhow::Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let max = points.iter().map(|point| point.value).max().unwrap_or(0);
+    let last = points.len().saturating_sub(1) as i32;
+
+    let mut chart = ChartBuilder::on(root)
+        .caption("Messages per month", ("sans-serif", CAPTION_FONT_SIZE))
+        .margin(10)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0..last.max(1), 0..max + max / 20 + 1)?;
+    chart
+        .configure_mesh()
+        .x_labels(points.len().min(12))
+        .x_label_style(("sans-serif", LABEL_FONT_SIZE))
+        .x_label_formatter(&|i| {
+            points
+                .get(*i as usize)
+                .map(|point| point.label.clone())
+                .unwrap_or_default()
+        })
+        .y_desc("messages")
+        .draw()?;
+
+    let series = points
+        .iter()
+        .enumerate()
+        .map(|(i, point)| (i as i32, point.value));
+    chart.draw_series(LineSeries::new(series.clone(), BLUE.stroke_width(2)))?;
+    // Markers only help when they don't run together
+    if points.len() <= MAX_MARKED_POINTS {
+        chart.draw_series(series.map(|point| Circle::new(point, 3, BLUE.filled())))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use std::fmt::Write;
+
+use serde::Serialize;
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::format;
+
+/// Number of domains listed under each class.
+const TOP_DOMAINS: usize = 5;
+
+#[derive(Clone, Debug, FromRow, Serialize)]
+pub struct DomainVolume {
+    pub domain: String,
+    pub class: String,
+    pub senders: i64,
+    pub messages: i64,
+    pub bytes: i64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ClassSummary {
+    pub class: String,
+    pub senders: i64,
+    pub messages: i64,
+    pub bytes: i64,
+    /// Busiest domains in the class, most messages first.
+    pub domains: Vec<DomainVolume>,
+}
+
+/// Per-domain volumes, most messages first. Reads the domains table, so it should be freshly
+/// classified.
+pub async fn domain_volumes(
+    pool: &Pool<Sqlite>,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<DomainVolume>> {
+    let rows = sqlx::query_as::<_, DomainVolume>(&format!(
+        "SELECT d.domain, d.class, count(*) AS senders, sum(s.mails_sent) AS messages,
+            sum(s.bytes) AS bytes
+        FROM senders s
+        JOIN domains d ON d.domain = lower(substr(s.sender, instr(s.sender, '@') + 1))
+        WHERE NOT s.is_ignored AND {}
+        GROUP BY d.domain
+        ORDER BY messages DESC, d.domain",
+        scope.condition("s.sender", Some("s.mails_sent"))
+    ))
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub fn summarize(domains: Vec<DomainVolume>) -> Vec<ClassSummary> {
+    let mut classes: Vec<ClassSummary> = Vec::new();
+    for domain in domains {
+        let index = match classes.iter().position(|c| c.class == domain.class) {
+            Some(index) => index,
+            None => {
+                classes.push(ClassSummary {
+                    class: domain.class.clone(),
+                    ..Default::default()
+                });
+                classes.len() - 1
+            }
+        };
+        let class = &mut classes[index];
+        class.senders += domain.senders;
+        class.messages += domain.messages;
+        class.bytes += domain.bytes;
+        if class.domains.len() < TOP_DOMAINS {
+            class.domains.push(domain);
+        }
+    }
+    classes.sort_by(|a, b| b.messages.cmp(&a.messages).then(a.class.cmp(&b.class)));
+    classes
+}
+
+pub fn render(classes: &[ClassSummary]) -> String {
+    let mut out = String::new();
+    let total: i64 = classes.iter().map(|class| class.messages).sum();
+
+    writeln!(
+        out,
+        "{:<16} {:>8} {:>10} {:>6} {:>10}",
+        "class", "senders", "mails", "%", "bytes"
+    )
+    .unwrap();
+    for class in classes {
+        writeln!(
+            out,
+            "{:<16} {:>8} {:>10} {:>5.1}% {:>10}",
+            class.class,
+            format::thousands(class.senders),
+            format::thousands(class.messages),
+            if total > 0 {
+                class.messages as f64 * 100.0 / total as f64
+            } else {
+                0.0
+            },
+            format::bytes(class.bytes)
+        )
+        .unwrap();
+        for domain in &class.domains {
+            writeln!(
+                out,
+                "  {:<14} {:>8} {:>10}",
+                domain.domain,
+                format::thousands(domain.senders),
+                format::thousands(domain.messages)
+            )
+            .unwrap();
+        }
+    }
+    out
+}
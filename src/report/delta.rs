@@ -0,0 +1,236 @@
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::cli::RunRef;
+use crate::format;
+
+#[derive(Clone, Debug, FromRow)]
+pub struct Run {
+    pub id: i64,
+    pub started_at: i64,
+    /// Messages from ignored senders skipped by this run and every run after it.
+    pub ignored: i64,
+}
+
+const RUN_COLUMNS: &str = "id, started_at,
+    (SELECT sum(later.ignored) FROM runs later WHERE later.id >= runs.id) AS ignored";
+
+#[derive(Clone, Debug, FromRow)]
+pub struct Mover {
+    pub sender: String,
+    /// Messages recorded since the run.
+    pub added: i64,
+    /// All messages from the sender.
+    pub total: i64,
+}
+
+impl Mover {
+    /// Every message from this sender arrived since the run.
+    pub fn is_new(&self) -> bool {
+        self.added >= self.total
+    }
+}
+
+pub async fn resolve_run(pool: &Pool<Sqlite>, run: RunRef) -> anyhow::Result<Option<Run>> {
+    let run = match run {
+        RunRef::Id(id) => {
+            sqlx::query_as::<_, Run>(&format!("SELECT {} FROM runs WHERE id = ?", RUN_COLUMNS))
+                .bind(id)
+                .fetch_optional(pool)
+                .await?
+        }
+        RunRef::Last => {
+            sqlx::query_as::<_, Run>(&format!(
+                "SELECT {} FROM runs ORDER BY id DESC LIMIT 1",
+                RUN_COLUMNS
+            ))
+            .fetch_optional(pool)
+            .await?
+        }
+    };
+    Ok(run)
+}
+
+/// What to say when `resolve_run` finds nothing for `run`.
+pub fn no_such_run(run: RunRef) -> String {
+    match run {
+        RunRef::Id(id) => format!("no run {}", id),
+        RunRef::Last => "no runs recorded yet".to_string(),
+    }
+}
+
+/// Senders with messages recorded by run `since` or later, most added first.
+pub async fn movers(
+    pool: &Pool<Sqlite>,
+    since: i64,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<Mover>> {
+    let rows = sqlx::query_as::<_, Mover>(&format!(
+        "SELECT m.sender, count(*) AS added, max(s.mails_sent) AS total
+        FROM messages m JOIN senders s ON s.sender = m.sender
+        WHERE m.run_id >= ? AND NOT s.is_ignored AND {}
+        GROUP BY m.sender
+        ORDER BY added DESC, m.sender",
+        scope.condition("m.sender", Some("s.mails_sent"))
+    ))
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+fn render_movers<'a>(out: &mut String, title: &str, movers: impl Iterator<Item = &'a Mover>) {
+    writeln!(out, "\n{}", title).unwrap();
+    writeln!(out, "{:>8} {:>10}  sender", "added", "total").unwrap();
+    for mover in movers {
+        writeln!(
+            out,
+            "{:>8} {:>10}  {}",
+            format!("+{}", format::thousands(mover.added)),
+            format::thousands(mover.total),
+            mover.sender
+        )
+        .unwrap();
+    }
+}
+
+pub fn render(run: &Run, movers: &[Mover], top: usize) -> String {
+    let mut out = String::new();
+    let added: i64 = movers.iter().map(|mover| mover.added).sum();
+
+    writeln!(
+        out,
+        "since run {} ({}): {} new messages from {} senders, {} of them new",
+        run.id,
+        format::date(Some(run.started_at)),
+        format::thousands(added),
+        format::thousands(movers.len() as i64),
+        format::thousands(movers.iter().filter(|mover| mover.is_new()).count() as i64)
+    )
+    .unwrap();
+    if run.ignored > 0 {
+        writeln!(
+            out,
+            "plus {} messages from ignored senders",
+            format::thousands(run.ignored)
+        )
+        .unwrap();
+    }
+    render_movers(
+        &mut out,
+        "new senders",
+        movers.iter().filter(|mover| mover.is_new()).take(top),
+    );
+    render_movers(
+        &mut out,
+        "grown senders",
+        movers.iter().filter(|mover| !mover.is_new()).take(top),
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use super::*;
+    use crate::analyze::SenderCounts;
+    use crate::db::{self, DEFAULT_ACCOUNT};
+    use crate::testsupport::{self, info};
+
+    // Run 1 records two messages from jane, and run 2 another from jane and one from news
+    async fn two_runs() -> Pool<Sqlite> {
+        let pool = testsupport::pool().await;
+        let runs = [
+            (
+                "2024-04-01",
+                vec![("m1", "jane@example.com"), ("m2", "jane@example.com")],
+            ),
+            (
+                "2024-05-01",
+                vec![("m3", "jane@example.com"), ("m4", "news@example.com")],
+            ),
+        ];
+        for (day, messages) in runs {
+            let started: DateTime<Utc> =
+                Utc.from_utc_datetime(&format!("{}T12:00:00", day).parse().unwrap());
+            let run = db::start_run(DEFAULT_ACCOUNT, started, &pool)
+                .await
+                .unwrap();
+            let mut conn = pool.acquire().await.unwrap();
+            for (id, sender) in messages {
+                let message = info(id, sender, 0, &[]);
+                db::record_message(&message, DEFAULT_ACCOUNT, Some(run), started, &mut conn)
+                    .await
+                    .unwrap();
+                let mut counts = SenderCounts::default();
+                counts.add(&message);
+                db::add_sender_counts(sender, &counts, &mut conn)
+                    .await
+                    .unwrap();
+            }
+            drop(conn);
+            db::finish_run(run, false, None, started, &pool)
+                .await
+                .unwrap();
+        }
+        pool
+    }
+
+    #[tokio::test]
+    async fn movers_since_the_last_run() {
+        let pool = two_runs().await;
+
+        let run = resolve_run(&pool, RunRef::Last).await.unwrap().unwrap();
+        let movers = movers(&pool, run.id, &SenderScope::default())
+            .await
+            .unwrap();
+
+        assert_eq!(run.id, 2);
+        assert_eq!(
+            render(&run, &movers, 10),
+            "since run 2 (2024-05-01): 2 new messages from 2 senders, 1 of them new
+
+new senders
+   added      total  sender
+      +1          1  news@example.com
+
+grown senders
+   added      total  sender
+      +1          3  jane@example.com
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn movers_since_the_first_run_take_in_both() {
+        let pool = two_runs().await;
+
+        let run = resolve_run(&pool, RunRef::Id(1)).await.unwrap().unwrap();
+        let movers = movers(&pool, run.id, &SenderScope::default())
+            .await
+            .unwrap();
+
+        assert!(movers.iter().all(Mover::is_new));
+        assert_eq!(
+            movers
+                .iter()
+                .map(|mover| (mover.sender.as_str(), mover.added))
+                .collect::<Vec<_>>(),
+            [("jane@example.com", 3), ("news@example.com", 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_missing_run_is_named() {
+        let pool = two_runs().await;
+        assert!(resolve_run(&pool, RunRef::Id(999)).await.unwrap().is_none());
+        assert_eq!(no_such_run(RunRef::Id(999)), "no run 999");
+
+        let empty = testsupport::pool().await;
+        assert!(resolve_run(&empty, RunRef::Last).await.unwrap().is_none());
+        assert_eq!(no_such_run(RunRef::Last), "no runs recorded yet");
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{Pool, Sqlite};
+
+use super::MERGED_SENDERS;
+use crate::cli::{DiffArgs, DiffFormat};
+use crate::db;
+use crate::format;
+
+/// One sender's message count in each database, `None` where it never appears.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SenderDiff {
+    pub sender: String,
+    pub a: Option<i64>,
+    pub b: Option<i64>,
+}
+
+impl SenderDiff {
+    /// Messages in `b` less messages in `a`.
+    pub fn delta(&self) -> i64 {
+        self.b.unwrap_or(0) - self.a.unwrap_or(0)
+    }
+}
+
+/// Senders only one database has, and how much mail they sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Unique {
+    pub senders: i64,
+    pub messages: i64,
+}
+
+/// Open a stats database without changing it, not even to migrate it.
+pub async fn open(path: &Path) -> anyhow::Result<Pool<Sqlite>> {
+    if !path.is_file() {
+        anyhow::bail!("{} doesn't exist", path.display());
+    }
+    let options = SqliteConnectOptions::new().filename(path).read_only(true);
+    Ok(SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await?)
+}
+
+/// Message counts by sender as `report` counts them, with aliases merged and ignored senders
+/// left out as of the last time the database was classified.
+pub async fn sender_counts(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<(String, i64)>> {
+    Ok(sqlx::query_as(&format!(
+        "SELECT sender, mails_sent FROM {}",
+        MERGED_SENDERS
+    ))
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Line up the senders of both databases, ignoring case, largest change first and then by
+/// address.
+pub fn align(a: &[(String, i64)], b: &[(String, i64)]) -> Vec<SenderDiff> {
+    let mut by_sender = BTreeMap::<String, SenderDiff>::new();
+    for (side, counts) in [(false, a), (true, b)] {
+        for (sender, count) in counts {
+            let key = sender.to_lowercase();
+            let diff = by_sender.entry(key.clone()).or_insert(SenderDiff {
+                sender: key,
+                a: None,
+                b: None,
+            });
+            let slot = if side { &mut diff.b } else { &mut diff.a };
+            *slot = Some(slot.unwrap_or(0) + count);
+        }
+    }
+    let mut diffs = by_sender.into_values().collect::<Vec<_>>();
+    diffs.sort_by(|x, y| {
+        y.delta()
+            .abs()
+            .cmp(&x.delta().abs())
+            .then_with(|| x.sender.cmp(&y.sender))
+    });
+    diffs
+}
+
+/// Senders only in `a` and senders only in `b`.
+pub fn unique(diffs: &[SenderDiff]) -> (Unique, Unique) {
+    let mut only_a = Unique::default();
+    let mut only_b = Unique::default();
+    for diff in diffs {
+        match (diff.a, diff.b) {
+            (Some(count), None) => {
+                only_a.senders += 1;
+                only_a.messages += count;
+            }
+            (None, Some(count)) => {
+                only_b.senders += 1;
+                only_b.messages += count;
+            }
+            _ => {}
+        }
+    }
+    (only_a, only_b)
+}
+
+fn count(count: Option<i64>) -> String {
+    count
+        .map(format::thousands)
+        .unwrap_or_else(|| "-".to_string())
+}
+
+// "+12", "-3" or "0"
+fn signed(n: i64) -> String {
+    if n > 0 {
+        format!("+{}", format::thousands(n))
+    } else {
+        format::thousands(n)
+    }
+}
+
+/// The `top` largest changes side by side, then how many senders only each database has.
+pub fn render(a: &Path, b: &Path, diffs: &[SenderDiff], top: usize) -> String {
+    let mut out = String::new();
+    writeln!(out, "a: {}", a.display()).unwrap();
+    writeln!(out, "b: {}", b.display()).unwrap();
+    out.push('\n');
+    writeln!(out, "{:>8} {:>8} {:>8}  sender", "a", "b", "delta").unwrap();
+    for diff in diffs.iter().filter(|diff| diff.delta() != 0).take(top) {
+        writeln!(
+            out,
+            "{:>8} {:>8} {:>8}  {}",
+            count(diff.a),
+            count(diff.b),
+            signed(diff.delta()),
+            diff.sender
+        )
+        .unwrap();
+    }
+    let changed = diffs.iter().filter(|diff| diff.delta() != 0).count();
+    if changed > top {
+        writeln!(
+            out,
+            "and {} more changed senders",
+            format::thousands((changed - top) as i64)
+        )
+        .unwrap();
+    }
+
+    let (only_a, only_b) = unique(diffs);
+    out.push('\n');
+    for (name, unique) in [("a", only_a), ("b", only_b)] {
+        writeln!(
+            out,
+            "only in {}: {} senders, {} messages",
+            name,
+            format::thousands(unique.senders),
+            format::thousands(unique.messages)
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "in both: {} senders",
+        format::thousands(
+            diffs
+                .iter()
+                .filter(|diff| diff.a.is_some() && diff.b.is_some())
+                .count() as i64
+        )
+    )
+    .unwrap();
+    out
+}
+
+// A CSV field, quoted when it holds a comma, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Every sender, with empty counts where a database doesn't have them.
+pub fn render_csv(diffs: &[SenderDiff]) -> String {
+    let mut out = String::from("sender,a,b,delta\n");
+    let count = |count: Option<i64>| count.map(|count| count.to_string()).unwrap_or_default();
+    for diff in diffs {
+        writeln!(
+            out,
+            "{},{},{},{}",
+            csv_field(&diff.sender),
+            count(diff.a),
+            count(diff.b),
+            diff.delta()
+        )
+        .unwrap();
+    }
+    out
+}
+
+// Both databases must be fully migrated, since they can't be migrated read-only and
+// `MERGED_SENDERS` needs every table
+async fn check_schema(path: &Path, pool: &Pool<Sqlite>) -> anyhow::Result<i64> {
+    let latest = db::latest_schema_version();
+    match db::schema_version(pool).await? {
+        None => anyhow::bail!("{} isn't a gmail_stats database", path.display()),
+        Some(version) if version > latest => anyhow::bail!(
+            "{} has schema version {}, newer than this build's {}; update gmail_stats",
+            path.display(),
+            version,
+            latest
+        ),
+        Some(version) => Ok(version),
+    }
+}
+
+pub async fn run(args: &DiffArgs) -> anyhow::Result<()> {
+    let a = open(&args.a).await?;
+    let b = open(&args.b).await?;
+    let version_a = check_schema(&args.a, &a).await?;
+    let version_b = check_schema(&args.b, &b).await?;
+    let latest = db::latest_schema_version();
+    if version_a != latest || version_b != latest {
+        anyhow::bail!(
+            "{} has schema version {} and {} has {}, but both need {}. `gmail_stats fetch` or an \
+            import in the directory of each older database brings it up to date",
+            args.a.display(),
+            version_a,
+            args.b.display(),
+            version_b,
+            latest
+        );
+    }
+
+    let diffs = align(&sender_counts(&a).await?, &sender_counts(&b).await?);
+    match args.format {
+        DiffFormat::Plain => print!("{}", render(&args.a, &args.b, &diffs, args.top)),
+        DiffFormat::Csv => print!("{}", render_csv(&diffs)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::Executor;
+
+    use super::*;
+
+    // A migrated database at `name` in a directory of the test's own, holding `sql`
+    async fn database(test: &str, name: &str, sql: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("gmail-stats-diff-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let _ = std::fs::remove_file(&path);
+        let pool = crate::open(&path, true).await.unwrap();
+        pool.execute(sql).await.unwrap();
+        pool.close().await;
+        path
+    }
+
+    // Work mail: Jane under two spellings, an alias of hers, a newsletter and a sender ignored
+    const WORK: &str = "
+        INSERT INTO senders (sender, mails_sent) VALUES
+            ('jane@example.com', 10), ('Jane@Example.com', 2), ('jane.doe@mycorp.example', 3),
+            ('news@shop.example', 4), ('noise@spam.example', 50), ('bob@example.org', 7);
+        INSERT INTO sender_aliases (sender, canonical) VALUES
+            ('jane.doe@mycorp.example', 'jane@example.com');
+        UPDATE senders SET is_ignored = 1 WHERE sender = 'noise@spam.example';";
+
+    // Personal mail: more from Jane, the same from Bob and someone new
+    const PERSONAL: &str = "
+        INSERT INTO senders (sender, mails_sent) VALUES
+            ('JANE@example.com', 20), ('bob@example.org', 7), ('mum@family.example', 5);";
+
+    fn diff(sender: &str, a: Option<i64>, b: Option<i64>) -> SenderDiff {
+        SenderDiff {
+            sender: sender.to_string(),
+            a,
+            b,
+        }
+    }
+
+    #[tokio::test]
+    async fn two_databases_line_up_by_sender() {
+        let a = database("align", "work.db", WORK).await;
+        let b = database("align", "personal.db", PERSONAL).await;
+
+        let diffs = align(
+            &sender_counts(&open(&a).await.unwrap()).await.unwrap(),
+            &sender_counts(&open(&b).await.unwrap()).await.unwrap(),
+        );
+
+        assert_eq!(
+            diffs,
+            [
+                diff("jane@example.com", Some(15), Some(20)),
+                diff("mum@family.example", None, Some(5)),
+                diff("news@shop.example", Some(4), None),
+                diff("bob@example.org", Some(7), Some(7)),
+            ]
+        );
+        assert_eq!(
+            unique(&diffs),
+            (
+                Unique {
+                    senders: 1,
+                    messages: 4
+                },
+                Unique {
+                    senders: 1,
+                    messages: 5
+                }
+            )
+        );
+        assert_eq!(
+            render(&a, &b, &diffs, 2)
+                .lines()
+                .skip(3)
+                .collect::<Vec<_>>(),
+            [
+                "       a        b    delta  sender",
+                "      15       20       +5  jane@example.com",
+                "       -        5       +5  mum@family.example",
+                "and 1 more changed senders",
+                "",
+                "only in a: 1 senders, 4 messages",
+                "only in b: 1 senders, 5 messages",
+                "in both: 2 senders",
+            ]
+        );
+    }
+
+    #[test]
+    fn every_sender_is_in_the_csv() {
+        let diffs = align(
+            &[
+                ("a,b@example.com".to_string(), 2),
+                ("x@example.com".to_string(), 1),
+            ],
+            &[("X@example.com".to_string(), 1)],
+        );
+        assert_eq!(
+            render_csv(&diffs),
+            "sender,a,b,delta\n\"a,b@example.com\",2,,-2\nx@example.com,1,1,0\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn only_gmail_stats_databases_are_compared() {
+        let missing = std::env::temp_dir().join("gmail-stats-diff-nowhere/stats.db");
+        assert!(open(&missing)
+            .await
+            .unwrap_err()
+            .to_string()
+            .contains("doesn't exist"));
+
+        let path = database("schema", "stats.db", "").await;
+        let pool = open(&path).await.unwrap();
+        assert_eq!(
+            check_schema(&path, &pool).await.unwrap(),
+            db::latest_schema_version()
+        );
+        // Opened read-only, so nothing can be written through it
+        assert!(sqlx::query("DELETE FROM senders")
+            .execute(&pool)
+            .await
+            .is_err());
+
+        let other = path.with_file_name("other.db");
+        let _ = std::fs::remove_file(&other);
+        let options = SqliteConnectOptions::new()
+            .filename(&other)
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options)
+            .await
+            .unwrap();
+        pool.execute("CREATE TABLE notes (text string)")
+            .await
+            .unwrap();
+        pool.close().await;
+        let err = check_schema(&other, &open(&other).await.unwrap())
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("isn't a gmail_stats database"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,318 @@
+use std::fmt::Write;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::growth::{self, Growth};
+use super::new::{self, NewSender};
+use super::pattern::SenderScope;
+use super::{ignored, markdown};
+use crate::cli::{DigestFormat, DigestPeriod, NewOrder};
+use crate::dates::{self, Granularity};
+use crate::format;
+use crate::parse::SENT;
+
+/// Rows in each of the digest's lists.
+const ROWS: usize = 5;
+
+/// Share of a sender's mail left unread which makes them an unsubscribe candidate.
+pub const UNSUBSCRIBE_UNREAD: f64 = 0.9;
+
+/// A sender who became an unsubscribe candidate during the period: by its end they'd sent at
+/// least `ignored::MIN_COUNT` messages and most were left unread, which wasn't so at its start.
+#[derive(Clone, Debug, FromRow, PartialEq, Eq)]
+pub struct Candidate {
+    pub sender: String,
+    pub mails: i64,
+    pub unread: i64,
+}
+
+impl Candidate {
+    pub fn unread_share(&self) -> f64 {
+        self.unread as f64 / self.mails as f64
+    }
+}
+
+/// What changed over the last complete week or month.
+#[derive(Clone, Debug)]
+pub struct Digest {
+    pub period: DigestPeriod,
+    /// The first day of the period, in the report's timezone.
+    pub start: NaiveDate,
+    /// Mail received in the period, and in the one before it.
+    pub messages: i64,
+    pub prior_messages: i64,
+    /// Senders whose mail grew the most on the period before.
+    pub growing: Vec<Growth>,
+    /// Senders first seen in the period, most mail first, and how many there were in all.
+    pub new_senders: Vec<NewSender>,
+    pub new_sender_count: usize,
+    pub unsubscribe: Vec<Candidate>,
+}
+
+fn granularity(period: DigestPeriod) -> Granularity {
+    match period {
+        DigestPeriod::Week => Granularity::Week,
+        DigestPeriod::Month => Granularity::Month,
+    }
+}
+
+/// The last complete period before `today`, and the one before that, as the first days of the
+/// prior period, the period and the one in progress. Weeks start on Monday.
+pub fn bounds(period: DigestPeriod, today: NaiveDate) -> (NaiveDate, NaiveDate, NaiveDate) {
+    let granularity = granularity(period);
+    let end = granularity.bucket_start(today);
+    let start = granularity.bucket_start(end - Duration::days(1));
+    let prior = granularity.bucket_start(start - Duration::days(1));
+    (prior, start, end)
+}
+
+/// Received mail between `start` and `end`, epoch milliseconds, leaving out spam, my own sent
+/// mail and ignored senders.
+pub async fn received(
+    pool: &Pool<Sqlite>,
+    start: i64,
+    end: i64,
+    scope: &SenderScope,
+) -> anyhow::Result<i64> {
+    let (count,): (i64,) = sqlx::query_as(&format!(
+        "SELECT count(*) FROM messages
+        WHERE internal_date >= ? AND internal_date < ? AND NOT is_spam
+            AND (account, mail_id) NOT IN
+                (SELECT account, mail_id FROM message_labels WHERE label = ?)
+            AND sender NOT IN (SELECT sender FROM senders WHERE is_ignored) AND {}",
+        scope.patterns("sender")
+    ))
+    .bind(start)
+    .bind(end)
+    .bind(SENT)
+    .fetch_one(pool)
+    .await?;
+    Ok(count)
+}
+
+/// Senders who became unsubscribe candidates between `start` and `end`, epoch milliseconds,
+/// most mail first. Read state is as of the last fetch, not as it was at `start`.
+pub async fn new_candidates(
+    pool: &Pool<Sqlite>,
+    start: i64,
+    end: i64,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<Candidate>> {
+    let rows = sqlx::query_as::<_, Candidate>(&format!(
+        "SELECT sender, count(*) AS mails, sum(is_unread) AS unread,
+            sum(internal_date < ?1) AS mails_before,
+            sum(is_unread AND internal_date < ?1) AS unread_before
+        FROM messages
+        WHERE internal_date < ?2 AND NOT is_spam
+            AND (account, mail_id) NOT IN
+                (SELECT account, mail_id FROM message_labels WHERE label = ?3)
+            AND sender NOT IN (SELECT sender FROM senders WHERE is_ignored) AND {}
+        GROUP BY sender
+        HAVING mails >= ?4 AND unread >= mails * ?5
+            AND (mails_before < ?4 OR unread_before < mails_before * ?5)
+        ORDER BY mails DESC, sender",
+        scope.patterns("sender")
+    ))
+    .bind(start)
+    .bind(end)
+    .bind(SENT)
+    .bind(ignored::MIN_COUNT)
+    .bind(UNSUBSCRIBE_UNREAD)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Put the digest together from the growth, new sender and unsubscribe queries, for the last
+/// complete period before `now` in `tz`.
+pub async fn gather(
+    pool: &Pool<Sqlite>,
+    period: DigestPeriod,
+    now: DateTime<Utc>,
+    tz: Tz,
+    scope: &SenderScope,
+) -> anyhow::Result<Digest> {
+    let (prior, start, end) = bounds(period, now.with_timezone(&tz).date_naive());
+    let [prior_ms, start_ms, end_ms] = [prior, start, end].map(|day| dates::start_of_day(day, tz));
+
+    let growing = growth::growing_senders(pool, prior_ms, start_ms, end_ms, scope).await?;
+    let mut growing = growth::by_increase(&growing);
+    growing.truncate(ROWS);
+    let mut new_senders =
+        new::first_seen_between(pool, start_ms, Some(end_ms), NewOrder::Count, scope).await?;
+    let new_sender_count = new_senders.len();
+    new_senders.truncate(ROWS);
+    let mut unsubscribe = new_candidates(pool, start_ms, end_ms, scope).await?;
+    unsubscribe.truncate(ROWS);
+
+    Ok(Digest {
+        period,
+        start,
+        messages: received(pool, start_ms, end_ms, scope).await?,
+        prior_messages: received(pool, prior_ms, start_ms, scope).await?,
+        growing,
+        new_senders,
+        new_sender_count,
+        unsubscribe,
+    })
+}
+
+impl Digest {
+    /// What it covers, e.g. `week of 2024-03-04`.
+    pub fn title(&self) -> String {
+        match self.period {
+            DigestPeriod::Week => format!("week of {}", self.start.format("%Y-%m-%d")),
+            DigestPeriod::Month => format!("month of {}", self.start.format("%B %Y")),
+        }
+    }
+
+    // The change in volume, e.g. `+12% on the week before`
+    fn change(&self) -> String {
+        let before = match self.period {
+            DigestPeriod::Week => "the week before",
+            DigestPeriod::Month => "the month before",
+        };
+        if self.prior_messages == 0 {
+            return format!("none {}", before);
+        }
+        let change =
+            (self.messages - self.prior_messages) as f64 * 100.0 / self.prior_messages as f64;
+        format!("{:+.0}% on {}", change, before)
+    }
+}
+
+pub fn render(digest: &Digest, format: DigestFormat) -> String {
+    match format {
+        DigestFormat::Text => render_text(digest),
+        DigestFormat::Markdown => render_markdown(digest),
+    }
+}
+
+fn render_text(digest: &Digest) -> String {
+    let mut out = String::new();
+    writeln!(out, "digest for the {}", digest.title()).unwrap();
+    writeln!(
+        out,
+        "{} messages received, {}",
+        format::thousands(digest.messages),
+        digest.change()
+    )
+    .unwrap();
+
+    writeln!(out, "\ngrowing senders").unwrap();
+    for row in &digest.growing {
+        writeln!(
+            out,
+            "{:>8} {:>+8}  {}",
+            format::thousands(row.recent),
+            row.increase(),
+            row.sender
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        out,
+        "\n{} new senders",
+        format::thousands(digest.new_sender_count as i64)
+    )
+    .unwrap();
+    for row in &digest.new_senders {
+        writeln!(
+            out,
+            "{:>8} {:>10}  {}",
+            format::thousands(row.mails_sent),
+            format::date(Some(row.first_seen)),
+            row.sender
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "\nnew unsubscribe candidates").unwrap();
+    for row in &digest.unsubscribe {
+        let unread = format!("{:.0}%", row.unread_share() * 100.0);
+        writeln!(
+            out,
+            "{:>8} {:>7}  {}",
+            format::thousands(row.mails),
+            unread,
+            row.sender
+        )
+        .unwrap();
+    }
+    out
+}
+
+fn render_markdown(digest: &Digest) -> String {
+    let mut out = String::new();
+    writeln!(out, "# Digest for the {}\n", digest.title()).unwrap();
+    writeln!(
+        out,
+        "{} messages received, {}.\n",
+        format::thousands(digest.messages),
+        digest.change()
+    )
+    .unwrap();
+
+    writeln!(out, "## Growing senders\n").unwrap();
+    writeln!(out, "| Sender | Messages | Change |\n|---|---:|---:|").unwrap();
+    for row in &digest.growing {
+        writeln!(
+            out,
+            "| {} | {} | {:+} |",
+            markdown::escape(&row.sender),
+            format::thousands(row.recent),
+            row.increase()
+        )
+        .unwrap();
+    }
+
+    writeln!(
+        out,
+        "\n## {} new senders\n",
+        format::thousands(digest.new_sender_count as i64)
+    )
+    .unwrap();
+    writeln!(out, "| Sender | Messages | First seen |\n|---|---:|---|").unwrap();
+    for row in &digest.new_senders {
+        writeln!(
+            out,
+            "| {} | {} | {} |",
+            markdown::escape(&row.sender),
+            format::thousands(row.mails_sent),
+            format::date(Some(row.first_seen))
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "\n## New unsubscribe candidates\n").unwrap();
+    writeln!(out, "| Sender | Messages | Unread |\n|---|---:|---:|").unwrap();
+    for row in &digest.unsubscribe {
+        writeln!(
+            out,
+            "| {} | {} | {:.0}% |",
+            markdown::escape(&row.sender),
+            format::thousands(row.mails),
+            row.unread_share() * 100.0
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// The digest as an email: Markdown text, with the plain text digest as the HTML alternative.
+pub fn email(digest: &Digest, to: &str) -> String {
+    let html = format!(
+        "<!DOCTYPE html>\n<html><body><pre>{}</pre></body></html>\n",
+        super::filters::escape(&render_text(digest))
+    );
+    super::email::compose(
+        to,
+        &format!("gmail_stats digest for the {}", digest.title()),
+        &render_markdown(digest),
+        &html,
+        &super::email::boundary(),
+    )
+}
@@ -0,0 +1,165 @@
+use std::fmt::Write;
+
+use sqlx::{Pool, Sqlite};
+
+use super::{pattern::SenderScope, MERGED_SENDERS};
+use crate::format;
+
+/// Shares of all mail for which we report how many senders it takes.
+const SHARES: [u32; 3] = [50, 80, 95];
+
+/// Histogram buckets as inclusive (min, max) messages per sender.
+const BUCKETS: [(i64, i64, &str); 5] = [
+    (1, 1, "1"),
+    (2, 5, "2-5"),
+    (6, 20, "6-20"),
+    (21, 100, "21-100"),
+    (101, i64::MAX, "100+"),
+];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Distribution {
+    pub senders: i64,
+    pub messages: i64,
+    /// (share percent, senders needed to reach it).
+    pub senders_for_share: Vec<(u32, i64)>,
+    pub median: f64,
+    /// Senders per entry of `BUCKETS`.
+    pub buckets: Vec<i64>,
+}
+
+/// Messages per sender with aliases merged, most first.
+pub async fn sender_counts(pool: &Pool<Sqlite>, scope: &SenderScope) -> anyhow::Result<Vec<i64>> {
+    let rows: Vec<(i64,)> = sqlx::query_as(&format!(
+        "SELECT mails_sent FROM {} WHERE mails_sent > 0 AND {} ORDER BY mails_sent DESC",
+        MERGED_SENDERS,
+        scope.condition("sender", Some("mails_sent"))
+    ))
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|(count,)| count).collect())
+}
+
+/// `counts` must be sorted in descending order. The senders needed for a share is the smallest
+/// number of top senders whose mail reaches it, so ties at the boundary don't matter.
+pub fn distribution(counts: &[i64]) -> Distribution {
+    let messages: i64 = counts.iter().sum();
+    let mut distribution = Distribution {
+        senders: counts.len() as i64,
+        messages,
+        buckets: vec![0; BUCKETS.len()],
+        ..Default::default()
+    };
+    if counts.is_empty() {
+        return distribution;
+    }
+
+    let mut cumulative = 0;
+    let mut shares = SHARES.iter().peekable();
+    for (i, count) in counts.iter().enumerate() {
+        cumulative += count;
+        // Integer maths so exactly 50% counts as reaching 50%
+        while let Some(share) =
+            shares.next_if(|share| cumulative * 100 >= messages * **share as i64)
+        {
+            distribution.senders_for_share.push((*share, i as i64 + 1));
+        }
+
+        let bucket = BUCKETS
+            .iter()
+            .position(|(min, max, _)| (*min..=*max).contains(count))
+            .unwrap();
+        distribution.buckets[bucket] += 1;
+    }
+
+    let mid = counts.len() / 2;
+    distribution.median = if counts.len().is_multiple_of(2) {
+        (counts[mid - 1] + counts[mid]) as f64 / 2.0
+    } else {
+        counts[mid] as f64
+    };
+    distribution
+}
+
+pub fn render(distribution: &Distribution) -> String {
+    let mut out = String::new();
+    if distribution.senders == 0 {
+        writeln!(out, "no senders recorded").unwrap();
+        return out;
+    }
+
+    writeln!(
+        out,
+        "{} messages from {} senders, median {} per sender\n",
+        format::thousands(distribution.messages),
+        format::thousands(distribution.senders),
+        distribution.median
+    )
+    .unwrap();
+    for (share, senders) in &distribution.senders_for_share {
+        writeln!(
+            out,
+            "{:>3}% of mail comes from {} senders ({:.1}%)",
+            share,
+            format::thousands(*senders),
+            *senders as f64 * 100.0 / distribution.senders as f64
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "\n{:>8} {:>8} {:>6}", "mails", "senders", "%").unwrap();
+    for ((_, _, label), senders) in BUCKETS.iter().zip(&distribution.buckets) {
+        writeln!(
+            out,
+            "{:>8} {:>8} {:>5.1}%",
+            label,
+            format::thousands(*senders),
+            *senders as f64 * 100.0 / distribution.senders as f64
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_empty_database_has_no_distribution() {
+        let distribution = distribution(&[]);
+        assert_eq!(distribution.senders, 0);
+        assert!(distribution.senders_for_share.is_empty());
+        assert_eq!(distribution.buckets, [0; BUCKETS.len()]);
+        assert_eq!(render(&distribution), "no senders recorded\n");
+    }
+
+    #[test]
+    fn shares_are_reached_by_the_fewest_top_senders() {
+        // 200 messages, of which the top two senders have exactly 80% and the top three 95%
+        let distribution = distribution(&[101, 59, 30, 6, 2, 1, 1]);
+
+        assert_eq!((distribution.senders, distribution.messages), (7, 200));
+        assert_eq!(distribution.senders_for_share, [(50, 1), (80, 2), (95, 3)]);
+        assert_eq!(distribution.median, 6.0);
+        assert_eq!(distribution.buckets, [2, 1, 1, 2, 1]);
+    }
+
+    #[test]
+    fn ties_at_a_boundary_dont_change_the_count() {
+        // Any one of the four reaches 25%, so two reach 50% whichever they are
+        let distribution = distribution(&[5, 5, 5, 5]);
+
+        assert_eq!(distribution.senders_for_share, [(50, 2), (80, 4), (95, 4)]);
+        assert_eq!(distribution.median, 5.0);
+        assert_eq!(distribution.buckets, [0, 4, 0, 0, 0]);
+    }
+
+    #[test]
+    fn one_sender_reaches_every_share_at_once() {
+        let distribution = distribution(&[1]);
+
+        assert_eq!(distribution.senders_for_share, [(50, 1), (80, 1), (95, 1)]);
+        assert_eq!(distribution.median, 1.0);
+    }
+}
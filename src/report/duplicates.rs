@@ -0,0 +1,354 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use sqlx::{Pool, Sqlite};
+
+use super::{pattern::SenderScope, MERGED_SENDERS};
+use crate::domains::registrable_domain;
+use crate::format;
+
+/// Display names shared by more addresses than this are generic ("Support", "Notifications")
+/// rather than one person or service.
+const MAX_NAME_GROUP: usize = 4;
+
+/// A heuristic for spotting addresses which probably belong to the same sender.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rule {
+    /// `jane.doe+news@example.com` and `janedoe@example.com`
+    PlusOrDots,
+    /// `billing@acme.com`, `billing@mail.acme.com` and `billing@acme.co.uk`
+    RelatedDomains,
+    /// Both send as "Jane Doe"
+    DisplayName,
+}
+
+impl Rule {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Rule::PlusOrDots => "differ only by +tags or dots",
+            Rule::RelatedDomains => "same mailbox at related domains",
+            Rule::DisplayName => "same display name",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Address {
+    pub sender: String,
+    pub mails_sent: i64,
+    /// Display names seen on mail from this address.
+    pub names: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Group {
+    pub rule: Rule,
+    /// Most mail first, so the first address is the suggested canonical one.
+    pub addresses: Vec<Address>,
+}
+
+/// Every sender with its message count and display names.
+pub async fn addresses(pool: &Pool<Sqlite>, scope: &SenderScope) -> anyhow::Result<Vec<Address>> {
+    let senders: Vec<(String, i64)> = sqlx::query_as(&format!(
+        "SELECT sender, mails_sent FROM {} WHERE {} ORDER BY mails_sent DESC, sender",
+        MERGED_SENDERS,
+        scope.condition("sender", Some("mails_sent"))
+    ))
+    .fetch_all(pool)
+    .await?;
+    let names: Vec<(String, String)> = sqlx::query_as(
+        "SELECT DISTINCT sender, sender_name FROM messages WHERE sender_name IS NOT NULL",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut names_by_sender: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (sender, name) in names {
+        names_by_sender.entry(sender).or_default().push(name);
+    }
+    Ok(senders
+        .into_iter()
+        .map(|(sender, mails_sent)| Address {
+            names: names_by_sender.remove(&sender).unwrap_or_default(),
+            sender,
+            mails_sent,
+        })
+        .collect())
+}
+
+/// The address with any `+tag` removed and dots dropped from the local part.
+pub fn plus_or_dots_key(address: &str) -> Option<String> {
+    let address = address.to_lowercase();
+    let (local, domain) = address.rsplit_once('@')?;
+    let local = local.split('+').next().unwrap_or_default().replace('.', "");
+    Some(format!("{}@{}", local, domain))
+}
+
+/// The local part with the domain's name minus its public suffix and subdomains, so
+/// `billing@mail.acme.com` and `billing@acme.co.uk` both become `billing@acme`.
+pub fn related_domains_key(address: &str) -> Option<String> {
+    let address = address.to_lowercase();
+    let (local, domain) = address.rsplit_once('@')?;
+    let registrable = registrable_domain(domain);
+    let name = registrable.split('.').next().unwrap_or_default();
+    if local.is_empty() || name.is_empty() {
+        return None;
+    }
+    Some(format!("{}@{}", local, name))
+}
+
+/// Lowercased display name, only for names of at least two words so that one-word names like
+/// "Support" don't group unrelated senders.
+pub fn display_name_key(name: &str) -> Option<String> {
+    let words = name
+        .split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>();
+    if words.len() < 2 {
+        return None;
+    }
+    Some(words.join(" "))
+}
+
+/// The keys of every display name an address sends as, each once.
+pub fn display_name_keys(names: &[String]) -> Vec<String> {
+    let mut keys = names
+        .iter()
+        .filter_map(|name| display_name_key(name))
+        .collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+    keys
+}
+
+fn keys(rule: Rule, address: &Address) -> Vec<String> {
+    match rule {
+        Rule::PlusOrDots => plus_or_dots_key(&address.sender).into_iter().collect(),
+        Rule::RelatedDomains => related_domains_key(&address.sender).into_iter().collect(),
+        Rule::DisplayName => display_name_keys(&address.names),
+    }
+}
+
+/// Groups of two or more addresses sharing a key under `rule`. `addresses` should be ordered by
+/// mail, most first.
+pub fn group(rule: Rule, addresses: &[Address]) -> Vec<Group> {
+    let mut by_key: BTreeMap<String, Vec<Address>> = BTreeMap::new();
+    for address in addresses {
+        for key in keys(rule, address) {
+            by_key.entry(key).or_default().push(address.clone());
+        }
+    }
+
+    let mut groups = by_key
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .filter(|members| rule != Rule::DisplayName || members.len() <= MAX_NAME_GROUP)
+        .map(|addresses| Group { rule, addresses })
+        .collect::<Vec<_>>();
+    groups.sort_by_key(|group| {
+        std::cmp::Reverse(group.addresses.iter().map(|a| a.mails_sent).sum::<i64>())
+    });
+    groups
+}
+
+/// Groups from every rule, leaving out any group another rule already found.
+pub fn all_groups(addresses: &[Address]) -> Vec<Group> {
+    let mut groups: Vec<Group> = Vec::new();
+    for rule in [Rule::PlusOrDots, Rule::RelatedDomains, Rule::DisplayName] {
+        for group in group(rule, addresses) {
+            if !groups.iter().any(|g| g.addresses == group.addresses) {
+                groups.push(group);
+            }
+        }
+    }
+    groups
+}
+
+pub fn render(groups: &[Group]) -> String {
+    let mut out = String::new();
+    if groups.is_empty() {
+        writeln!(out, "no likely duplicates found").unwrap();
+        return out;
+    }
+
+    for group in groups {
+        writeln!(out, "{}:", group.rule.description()).unwrap();
+        for address in &group.addresses {
+            writeln!(
+                out,
+                "{:>10}  {}",
+                format::thousands(address.mails_sent),
+                address.sender
+            )
+            .unwrap();
+        }
+        let canonical = &group.addresses[0].sender;
+        writeln!(out, "  [aliases]").unwrap();
+        for address in &group.addresses[1..] {
+            writeln!(out, "  {:?} = {:?}", address.sender, canonical).unwrap();
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(sender: &str, mails_sent: i64, names: &[&str]) -> Address {
+        Address {
+            sender: sender.to_string(),
+            mails_sent,
+            names: names.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    // Whether `rule` puts `a` and `b` in a group together
+    fn same_sender(rule: Rule, a: &Address, b: &Address) -> bool {
+        let theirs = keys(rule, b);
+        keys(rule, a).iter().any(|key| theirs.contains(key))
+    }
+
+    fn senders(group: &Group) -> Vec<&str> {
+        group.addresses.iter().map(|a| a.sender.as_str()).collect()
+    }
+
+    #[test]
+    fn tags_and_dots_fold_onto_the_same_key() {
+        let key = Some("janedoe@example.com".to_string());
+        assert_eq!(plus_or_dots_key("jane.doe+news@example.com"), key);
+        assert_eq!(plus_or_dots_key("JaneDoe@Example.com"), key);
+        assert!(same_sender(
+            Rule::PlusOrDots,
+            &address("j.a.n.e.doe@example.com", 1, &[]),
+            &address("janedoe+shop@example.com", 1, &[]),
+        ));
+    }
+
+    #[test]
+    fn other_mailboxes_or_domains_keep_apart_by_tags_and_dots() {
+        assert_eq!(plus_or_dots_key("not an address"), None);
+        assert!(!same_sender(
+            Rule::PlusOrDots,
+            &address("jane.doe@example.com", 1, &[]),
+            &address("jane.doe@example.org", 1, &[]),
+        ));
+        assert!(!same_sender(
+            Rule::PlusOrDots,
+            &address("jane@example.com", 1, &[]),
+            &address("janet@example.com", 1, &[]),
+        ));
+    }
+
+    #[test]
+    fn a_mailbox_at_related_domains_shares_a_key() {
+        let key = Some("billing@acme".to_string());
+        assert_eq!(related_domains_key("billing@acme.com"), key);
+        assert_eq!(related_domains_key("billing@mail.acme.com"), key);
+        assert_eq!(related_domains_key("Billing@acme.co.uk"), key);
+        assert!(same_sender(
+            Rule::RelatedDomains,
+            &address("billing@acme.com", 1, &[]),
+            &address("billing@acme.co.uk", 1, &[]),
+        ));
+    }
+
+    #[test]
+    fn another_mailbox_or_organisation_isnt_related() {
+        assert_eq!(related_domains_key("@acme.com"), None);
+        assert_eq!(related_domains_key("no-domain"), None);
+        assert!(!same_sender(
+            Rule::RelatedDomains,
+            &address("billing@acme.com", 1, &[]),
+            &address("support@acme.com", 1, &[]),
+        ));
+        assert!(!same_sender(
+            Rule::RelatedDomains,
+            &address("billing@acme.com", 1, &[]),
+            &address("billing@acmecorp.com", 1, &[]),
+        ));
+    }
+
+    #[test]
+    fn a_shared_full_name_is_the_same_sender() {
+        assert_eq!(
+            display_name_key("  Jane   DOE "),
+            Some("jane doe".to_string())
+        );
+        assert_eq!(
+            display_name_keys(&["Jane Doe".to_string(), "jane doe".to_string()]),
+            ["jane doe"]
+        );
+        assert!(same_sender(
+            Rule::DisplayName,
+            &address("jane@example.com", 1, &["Jane Doe"]),
+            &address("jd@work.example", 1, &["J", "JANE DOE"]),
+        ));
+    }
+
+    #[test]
+    fn one_word_or_different_names_arent_the_same_sender() {
+        assert_eq!(display_name_key("Support"), None);
+        assert!(display_name_keys(&[]).is_empty());
+        assert!(!same_sender(
+            Rule::DisplayName,
+            &address("help@a.example", 1, &["Support"]),
+            &address("help@b.example", 1, &["Support"]),
+        ));
+        assert!(!same_sender(
+            Rule::DisplayName,
+            &address("jane@example.com", 1, &["Jane Doe"]),
+            &address("john@example.com", 1, &["John Doe"]),
+        ));
+    }
+
+    #[test]
+    fn groups_put_the_most_mail_first() {
+        let addresses = [
+            address("jane.doe@example.com", 9, &[]),
+            address("bob@example.com", 5, &[]),
+            address("janedoe+news@example.com", 2, &[]),
+            address("bob+x@example.com", 1, &[]),
+            address("alone@example.com", 1, &[]),
+        ];
+
+        let groups = group(Rule::PlusOrDots, &addresses);
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(
+            senders(&groups[0]),
+            ["jane.doe@example.com", "janedoe+news@example.com"]
+        );
+        assert_eq!(
+            senders(&groups[1]),
+            ["bob@example.com", "bob+x@example.com"]
+        );
+    }
+
+    #[test]
+    fn a_name_too_many_addresses_share_is_generic() {
+        let common = (0..=MAX_NAME_GROUP)
+            .map(|i| address(&format!("n{}@example.com", i), 1, &["Customer Service"]))
+            .collect::<Vec<_>>();
+        assert!(group(Rule::DisplayName, &common).is_empty());
+        assert_eq!(group(Rule::DisplayName, &common[..MAX_NAME_GROUP]).len(), 1);
+    }
+
+    #[test]
+    fn a_group_found_by_an_earlier_rule_isnt_repeated() {
+        // Dots and display name both group these, related domains nothing
+        let addresses = [
+            address("jane.doe@example.com", 2, &["Jane Doe"]),
+            address("janedoe@example.com", 1, &["Jane Doe"]),
+        ];
+
+        let groups = all_groups(&addresses);
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].rule, Rule::PlusOrDots);
+        let rendered = render(&groups);
+        assert!(rendered.contains("  \"janedoe@example.com\" = \"jane.doe@example.com\"\n"));
+        assert_eq!(render(&[]), "no likely duplicates found\n");
+    }
+}
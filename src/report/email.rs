@@ -0,0 +1,157 @@
+use std::io::Cursor;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use google_gmail1::api::{Message, Scope};
+use mime::{Mime, SubLevel, TopLevel};
+
+use crate::auth;
+
+/// RFC 2045 caps base64 lines at 76 characters.
+const LINE_CHARS: usize = 76;
+
+/// A MIME boundary that can't occur in a base64 body, since `=` is only ever padding there and
+/// `_` isn't in the alphabet.
+pub fn boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or(0);
+    format!("=_gmail_stats_{:x}", nanos)
+}
+
+// `text` in base64, wrapped to `LINE_CHARS` with CRLF line ends
+fn encode_body(text: &str) -> String {
+    let encoded = STANDARD.encode(text);
+    let mut out = String::with_capacity(encoded.len() + encoded.len() / LINE_CHARS * 2 + 2);
+    for line in encoded.as_bytes().chunks(LINE_CHARS) {
+        // base64 output is ASCII
+        out.push_str(std::str::from_utf8(line).unwrap());
+        out.push_str("\r\n");
+    }
+    out
+}
+
+// An RFC 2047 encoded word for a header value that isn't plain ASCII
+fn encode_header(value: &str) -> String {
+    if value.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+        value.to_string()
+    } else {
+        format!("=?UTF-8?B?{}?=", STANDARD.encode(value))
+    }
+}
+
+/// A multipart/alternative message to `to` with a plain text part and an HTML part, which mail
+/// clients pick between. There's no From header: Gmail fills in the account sending it.
+pub fn compose(to: &str, subject: &str, text: &str, html: &str, boundary: &str) -> String {
+    let mut out = String::new();
+    out.push_str(&format!("To: {}\r\n", to.trim()));
+    out.push_str(&format!("Subject: {}\r\n", encode_header(subject)));
+    out.push_str("MIME-Version: 1.0\r\n");
+    out.push_str(&format!(
+        "Content-Type: multipart/alternative; boundary=\"{}\"\r\n",
+        boundary
+    ));
+    out.push_str("\r\n");
+    // Least preferred first
+    for (content_type, body) in [("text/plain", text), ("text/html", html)] {
+        out.push_str(&format!("--{}\r\n", boundary));
+        out.push_str(&format!(
+            "Content-Type: {}; charset=utf-8\r\n",
+            content_type
+        ));
+        out.push_str("Content-Transfer-Encoding: base64\r\n");
+        out.push_str("\r\n");
+        out.push_str(&encode_body(body));
+    }
+    out.push_str(&format!("--{}--\r\n", boundary));
+    out
+}
+
+// google-gmail1 takes mime 0.2, which has no constant for this
+fn rfc822() -> Mime {
+    Mime(
+        TopLevel::Message,
+        SubLevel::Ext("rfc822".to_string()),
+        Vec::new(),
+    )
+}
+
+/// Send `raw`, a complete RFC 822 message, from the signed in account. This is the only call
+/// asking for the `gmail.send` scope, so Google prompts for it separately the first time rather
+/// than with the read-only consent fetching uses.
+pub async fn send(raw: String) -> anyhow::Result<()> {
+    let hub = auth::hub(None).await?;
+    hub.users()
+        .messages_send(Message::default(), "me")
+        .add_scope(Scope::Send)
+        .upload(Cursor::new(raw.into_bytes()), rfc822())
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use mailparse::MailHeaderMap;
+
+    use super::*;
+
+    #[test]
+    fn the_message_parses_back_into_both_parts() {
+        let text = "Top senders this week\n\n  12 jane@example.com\n".repeat(10);
+        let html = "<h1>Top senders</h1><p>Café ☕ — 12 jane@example.com</p>";
+        let raw = compose(
+            " me@example.com ",
+            "Weekly report ☕",
+            &text,
+            html,
+            &boundary(),
+        );
+
+        let mail = mailparse::parse_mail(raw.as_bytes()).unwrap();
+        assert_eq!(
+            mail.headers.get_first_value("To").as_deref(),
+            Some("me@example.com")
+        );
+        assert_eq!(
+            mail.headers.get_first_value("Subject").as_deref(),
+            Some("Weekly report ☕")
+        );
+        assert_eq!(mail.headers.get_first_value("From"), None);
+        assert_eq!(mail.ctype.mimetype, "multipart/alternative");
+        let parts = mail
+            .subparts
+            .iter()
+            .map(|part| (part.ctype.mimetype.as_str(), part.get_body().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parts,
+            [("text/plain", text), ("text/html", html.to_string())]
+        );
+    }
+
+    #[test]
+    fn every_line_is_short_and_ends_in_crlf() {
+        let raw = compose(
+            "me@example.com",
+            "Report",
+            &"x".repeat(500),
+            &"é".repeat(300),
+            "=_gmail_stats_1",
+        );
+        assert!(raw.ends_with("--=_gmail_stats_1--\r\n"));
+        for line in raw.split_inclusive('\n') {
+            assert!(line.ends_with("\r\n"), "{:?}", line);
+            assert!(line.len() <= LINE_CHARS + 2, "{:?}", line);
+        }
+        assert!(!raw.replace("\r\n", "").contains('\n'));
+    }
+
+    #[test]
+    fn plain_subjects_are_left_as_they_are() {
+        assert_eq!(encode_header("Weekly report"), "Weekly report");
+        assert_eq!(encode_header("Tab\there"), "=?UTF-8?B?VGFiCWhlcmU=?=");
+        assert!(boundary().starts_with("=_gmail_stats_"));
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::format;
+use crate::parse::SENT;
+
+#[derive(Clone, Debug, FromRow)]
+pub struct EngagementRow {
+    pub sender: String,
+    pub messages: i64,
+    /// Threads containing mail from this sender.
+    pub threads: i64,
+    /// How many of those threads also contain mail from me.
+    pub replied: i64,
+}
+
+impl EngagementRow {
+    pub fn reply_rate(&self) -> f64 {
+        if self.threads == 0 {
+            return 0.0;
+        }
+        self.replied as f64 / self.threads as f64
+    }
+}
+
+/// Reply rates for every sender of received mail with at least `min_threads` threads.
+pub async fn reply_rates(
+    pool: &Pool<Sqlite>,
+    min_threads: i64,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<EngagementRow>> {
+    let rows = sqlx::query_as::<_, EngagementRow>(&format!(
+        "WITH sent AS (
+            SELECT account, mail_id FROM message_labels WHERE label = ?
+        ), sent_threads AS (
+            SELECT DISTINCT thread_id FROM messages
+            WHERE thread_id IS NOT NULL AND (account, mail_id) IN sent
+        )
+        SELECT sender,
+            count(*) AS messages,
+            count(DISTINCT thread_id) AS threads,
+            count(DISTINCT CASE WHEN thread_id IN sent_threads THEN thread_id END) AS replied
+        FROM messages
+        WHERE thread_id IS NOT NULL AND (account, mail_id) NOT IN sent AND {}
+        GROUP BY sender
+        HAVING threads >= ?
+        ORDER BY messages DESC, sender",
+        scope.condition("sender", None)
+    ))
+    .bind(SENT)
+    .bind(min_threads)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+fn render_rows<'a>(out: &mut String, title: &str, rows: impl Iterator<Item = &'a EngagementRow>) {
+    writeln!(out, "{}", title).unwrap();
+    writeln!(
+        out,
+        "{:>10} {:>8} {:>8} {:>6}  sender",
+        "mails", "threads", "replied", "rate"
+    )
+    .unwrap();
+    for row in rows {
+        writeln!(
+            out,
+            "{:>10} {:>8} {:>8} {:>5.0}%  {}",
+            format::thousands(row.messages),
+            format::thousands(row.threads),
+            format::thousands(row.replied),
+            row.reply_rate() * 100.0,
+            row.sender
+        )
+        .unwrap();
+    }
+}
+
+/// `rows` are ordered by volume, so the never-replied list is the highest volume senders I've
+/// never answered, while correspondents are re-sorted by how often I reply.
+pub fn render(rows: &[EngagementRow], top: usize) -> String {
+    let mut out = String::new();
+
+    render_rows(
+        &mut out,
+        "never replied to (unsubscribe candidates)",
+        rows.iter().filter(|row| row.replied == 0).take(top),
+    );
+
+    let mut correspondents = rows
+        .iter()
+        .filter(|row| row.replied > 0)
+        .collect::<Vec<_>>();
+    correspondents.sort_by(|a, b| {
+        b.reply_rate()
+            .total_cmp(&a.reply_rate())
+            .then(b.replied.cmp(&a.replied))
+            .then(a.sender.cmp(&b.sender))
+    });
+    out.push('\n');
+    render_rows(
+        &mut out,
+        "frequent correspondents",
+        correspondents.into_iter().take(top),
+    );
+    out
+}
@@ -0,0 +1,83 @@
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use crate::format;
+
+#[derive(Clone, Debug, FromRow)]
+pub struct ClassCount {
+    pub class: String,
+    pub count: i64,
+    pub last_seen: i64,
+}
+
+#[derive(Clone, Debug, FromRow)]
+pub struct ErrorRow {
+    pub mail_id: Option<String>,
+    pub class: String,
+    pub message: String,
+    pub recorded_at: i64,
+}
+
+/// Outstanding errors per class, most first.
+pub async fn by_class(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<ClassCount>> {
+    let rows = sqlx::query_as::<_, ClassCount>(
+        "SELECT class, count(*) AS count, max(recorded_at) AS last_seen
+        FROM errors GROUP BY class ORDER BY count DESC, class",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// The latest errors, newest first.
+pub async fn recent(pool: &Pool<Sqlite>, limit: usize) -> anyhow::Result<Vec<ErrorRow>> {
+    let rows = sqlx::query_as::<_, ErrorRow>(
+        "SELECT mail_id, class, message, recorded_at FROM errors
+        ORDER BY recorded_at DESC, rowid DESC LIMIT ?",
+    )
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub fn render(classes: &[ClassCount], recent: &[ErrorRow]) -> String {
+    let mut out = String::new();
+    if classes.is_empty() {
+        writeln!(out, "no messages have been skipped").unwrap();
+        return out;
+    }
+    writeln!(out, "skipped messages by class").unwrap();
+    writeln!(out, "{:>8} {:>10}  class", "messages", "last").unwrap();
+    for row in classes {
+        writeln!(
+            out,
+            "{:>8} {:>10}  {}",
+            format::thousands(row.count),
+            format::date(Some(row.last_seen)),
+            row.class
+        )
+        .unwrap();
+    }
+
+    out.push('\n');
+    writeln!(out, "most recent").unwrap();
+    for row in recent {
+        writeln!(
+            out,
+            "{:>10}  {:<16}  {:<12}  {}",
+            format::date(Some(row.recorded_at)),
+            row.mail_id.as_deref().unwrap_or("-"),
+            row.class,
+            row.message
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "\n`fetch --retry-errors` tries these again, clearing the ones that work"
+    )
+    .unwrap();
+    out
+}
@@ -0,0 +1,138 @@
+use std::fmt::Write;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::{pattern::SenderScope, MERGED_SENDERS};
+use crate::format;
+use crate::parse::SENT;
+
+#[derive(Clone, Debug, FromRow)]
+pub struct FilterCandidate {
+    pub sender: String,
+    pub mails_sent: i64,
+    pub auto_count: i64,
+    /// Whether I've replied in any thread with mail from this sender.
+    pub replied: bool,
+}
+
+impl FilterCandidate {
+    pub fn is_automated(&self) -> bool {
+        self.auto_count * 2 > self.mails_sent
+    }
+}
+
+/// Senders with at least `min_count` mails, busiest first. Replies are matched through aliases,
+/// so answering any of a sender's addresses counts.
+pub async fn candidates(
+    pool: &Pool<Sqlite>,
+    min_count: i64,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<FilterCandidate>> {
+    let rows = sqlx::query_as::<_, FilterCandidate>(&format!(
+        "WITH sent AS (
+            SELECT account, mail_id FROM message_labels WHERE label = ?
+        ), sent_threads AS (
+            SELECT DISTINCT thread_id FROM messages
+            WHERE thread_id IS NOT NULL AND (account, mail_id) IN sent
+        ), replied AS (
+            SELECT DISTINCT coalesce(a.canonical, m.sender) AS sender
+            FROM messages m LEFT JOIN sender_aliases a ON a.sender = lower(m.sender)
+            WHERE m.thread_id IN sent_threads AND (m.account, m.mail_id) NOT IN sent
+        )
+        SELECT sender, mails_sent, auto_count, sender IN replied AS replied FROM {}
+        WHERE mails_sent >= ? AND {}
+        ORDER BY mails_sent DESC, sender",
+        MERGED_SENDERS,
+        scope.condition("sender", Some("mails_sent"))
+    ))
+    .bind(SENT)
+    .bind(min_count)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub fn render(filters: &[FilterCandidate], label: &str) -> String {
+    let mut out = String::new();
+    if filters.is_empty() {
+        writeln!(out, "no senders need a filter").unwrap();
+        return out;
+    }
+    for filter in filters {
+        writeln!(
+            out,
+            "from:({}) → skip inbox, apply label {}  ({} mails, {} automated)",
+            filter.sender,
+            label,
+            format::thousands(filter.mails_sent),
+            format::thousands(filter.auto_count)
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// Escapes text for an XML attribute value, quoted with either kind of quote.
+pub(super) fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\'' => out.push_str("&apos;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// The filters in the Atom feed format Gmail exports and imports under Settings → Filters and
+/// Blocked Addresses. Gmail assigns its own ids on import, so ours only have to be unique.
+pub fn to_xml(filters: &[FilterCandidate], label: &str, now: DateTime<Utc>) -> String {
+    let updated = now.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let stamp = now.timestamp_millis();
+    let mut out = String::new();
+    writeln!(out, "<?xml version='1.0' encoding='UTF-8'?>").unwrap();
+    writeln!(
+        out,
+        "<feed xmlns='http://www.w3.org/2005/Atom' xmlns:apps='http://schemas.google.com/apps/2006'>"
+    )
+    .unwrap();
+    writeln!(out, "\t<title>Mail Filters</title>").unwrap();
+    writeln!(out, "\t<id>tag:mail.google.com,2008:filters:{}</id>", stamp).unwrap();
+    writeln!(out, "\t<updated>{}</updated>", updated).unwrap();
+    for (i, filter) in filters.iter().enumerate() {
+        writeln!(out, "\t<entry>").unwrap();
+        writeln!(out, "\t\t<category term='filter'></category>").unwrap();
+        writeln!(out, "\t\t<title>Mail Filter</title>").unwrap();
+        writeln!(
+            out,
+            "\t\t<id>tag:mail.google.com,2008:filter:{}{:04}</id>",
+            stamp, i
+        )
+        .unwrap();
+        writeln!(out, "\t\t<updated>{}</updated>", updated).unwrap();
+        writeln!(out, "\t\t<content></content>").unwrap();
+        for (name, value) in [
+            ("from", filter.sender.as_str()),
+            ("label", label),
+            ("shouldArchive", "true"),
+            ("sizeOperator", "s_sl"),
+            ("sizeUnit", "s_smb"),
+        ] {
+            writeln!(
+                out,
+                "\t\t<apps:property name='{}' value='{}'/>",
+                name,
+                escape(value)
+            )
+            .unwrap();
+        }
+        writeln!(out, "\t</entry>").unwrap();
+    }
+    writeln!(out, "</feed>").unwrap();
+    out
+}
@@ -0,0 +1,194 @@
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::cli::SenderSide;
+use crate::format;
+
+/// Forwarded mail counted against one of its two senders.
+#[derive(Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct ForwardedSender {
+    pub sender: String,
+    pub messages: i64,
+    /// How many of the other side it goes with: the forwarders an original sender's mail came
+    /// through, or the original senders a forwarder passed on.
+    pub others: i64,
+    pub last_seen: Option<i64>,
+}
+
+// The messages column holding `side`, and the one holding the other side
+fn columns(side: SenderSide) -> (&'static str, &'static str) {
+    match side {
+        SenderSide::Original => ("original_sender", "envelope_sender"),
+        SenderSide::Envelope => ("envelope_sender", "original_sender"),
+    }
+}
+
+/// The `limit` senders on `side` with the most unwrapped forwarded mail. Mail fetched without
+/// `--unwrap-forwarded` has no original sender, so isn't counted.
+pub async fn senders(
+    pool: &Pool<Sqlite>,
+    side: SenderSide,
+    limit: usize,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<ForwardedSender>> {
+    let (column, other) = columns(side);
+    let rows = sqlx::query_as::<_, ForwardedSender>(&format!(
+        "SELECT sender, messages, others, last_seen FROM (
+            SELECT lower({column}) AS sender, count(*) AS messages,
+                count(DISTINCT lower({other})) AS others, max(internal_date) AS last_seen
+            FROM messages
+            WHERE original_sender IS NOT NULL
+            GROUP BY lower({column})
+        )
+        WHERE {}
+        ORDER BY messages DESC, sender
+        LIMIT ?",
+        scope.condition("sender", Some("messages")),
+    ))
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub fn render(rows: &[ForwardedSender], side: SenderSide) -> String {
+    let mut out = String::new();
+    let (title, others) = match side {
+        SenderSide::Original => ("forwarded mail by original sender", "forwarders"),
+        SenderSide::Envelope => ("forwarded mail by forwarder", "senders"),
+    };
+    writeln!(out, "{}", title).unwrap();
+    writeln!(
+        out,
+        "{:>8} {:>10} {:>10}  sender",
+        "mails", others, "last seen"
+    )
+    .unwrap();
+    for row in rows {
+        writeln!(
+            out,
+            "{:>8} {:>10} {:>10}  {}",
+            format::thousands(row.messages),
+            format::thousands(row.others),
+            format::date(row.last_seen),
+            row.sender
+        )
+        .unwrap();
+    }
+    if rows.is_empty() {
+        writeln!(
+            out,
+            "\nno forwarded mail unwrapped, list `forwarders` in the config and fetch with \
+            --unwrap-forwarded"
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::db::{self, DEFAULT_ACCOUNT};
+    use crate::parse::{MessageInfo, ParseOptions};
+    use crate::testsupport::{self, message};
+
+    // Two people's mail through the old account's bridge and one through another, and mail
+    // straight from one of them, which isn't forwarded
+    async fn fixture() -> Pool<Sqlite> {
+        let pool = testsupport::pool().await;
+        let opts = ParseOptions {
+            forwarders: vec![
+                "bridge@old.example".to_string(),
+                "relay@work.example".to_string(),
+            ],
+            ..Default::default()
+        };
+        let mut conn = pool.acquire().await.unwrap();
+        for (id, from, original, date) in [
+            (
+                "m1",
+                "Bridge <bridge@old.example>",
+                Some("Jane <jane@example.com>"),
+                1,
+            ),
+            ("m2", "bridge@old.example", Some("jane@example.com"), 2),
+            ("m3", "bridge@old.example", Some("bob@example.org"), 3),
+            ("m4", "relay@work.example", Some("Jane@example.com"), 4),
+            ("m5", "jane@example.com", None, 5),
+        ] {
+            let mut headers = vec![("From", from)];
+            headers.extend(original.map(|original| ("X-Original-From", original)));
+            let mut message = message(id, &headers, &["INBOX"]);
+            message.internal_date = Some(date.to_string());
+            let info = MessageInfo::from_message(&message, &opts).unwrap();
+            db::record_message(
+                &info,
+                DEFAULT_ACCOUNT,
+                None,
+                DateTime::UNIX_EPOCH,
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        }
+        drop(conn);
+        pool
+    }
+
+    fn row(sender: &str, messages: i64, others: i64, last_seen: i64) -> ForwardedSender {
+        ForwardedSender {
+            sender: sender.to_string(),
+            messages,
+            others,
+            last_seen: Some(last_seen),
+        }
+    }
+
+    #[tokio::test]
+    async fn forwarded_mail_is_counted_against_its_original_sender() {
+        let pool = fixture().await;
+
+        let rows = senders(&pool, SenderSide::Original, 10, &SenderScope::default())
+            .await
+            .unwrap();
+
+        // Jane's own message isn't forwarded, and her forwarded mail came through both
+        assert_eq!(
+            rows,
+            [
+                row("jane@example.com", 3, 2, 4),
+                row("bob@example.org", 1, 1, 3)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn forwarded_mail_is_counted_against_its_forwarder() {
+        let pool = fixture().await;
+
+        let rows = senders(&pool, SenderSide::Envelope, 10, &SenderScope::default())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            rows,
+            [
+                row("bridge@old.example", 3, 2, 3),
+                row("relay@work.example", 1, 1, 4)
+            ]
+        );
+        let rendered = render(&rows, SenderSide::Envelope);
+        assert!(rendered.starts_with("forwarded mail by forwarder\n"));
+        assert!(rendered.contains("   mails    senders  last seen  sender\n"));
+    }
+
+    #[test]
+    fn nothing_forwarded_says_how_to_unwrap_it() {
+        assert!(render(&[], SenderSide::Original).contains("--unwrap-forwarded"));
+    }
+}
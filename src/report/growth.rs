@@ -0,0 +1,269 @@
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::format;
+use crate::parse::SENT;
+
+#[derive(Clone, Debug, FromRow, PartialEq, Eq)]
+pub struct Growth {
+    pub sender: String,
+    /// Messages in the window before the recent one.
+    pub prior: i64,
+    /// Messages in the most recent window.
+    pub recent: i64,
+}
+
+impl Growth {
+    pub fn increase(&self) -> i64 {
+        self.recent - self.prior
+    }
+
+    /// Recent over prior volume, `None` for senders who were silent in the prior window.
+    pub fn ratio(&self) -> Option<f64> {
+        if self.prior == 0 {
+            return None;
+        }
+        Some(self.recent as f64 / self.prior as f64)
+    }
+}
+
+/// Recent messages a sender needs for the fastest growing list, unless `--min-count` says
+/// otherwise.
+pub const MIN_RECENT: i64 = 5;
+
+/// Senders with more received mail in `[recent_start, end)` than in `[prior_start, recent_start)`,
+/// all epoch milliseconds.
+pub async fn growing_senders(
+    pool: &Pool<Sqlite>,
+    prior_start: i64,
+    recent_start: i64,
+    end: i64,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<Growth>> {
+    let rows = sqlx::query_as::<_, Growth>(&format!(
+        "SELECT sender, sum(internal_date < ?) AS prior, sum(internal_date >= ?) AS recent
+        FROM messages
+        WHERE internal_date >= ? AND internal_date < ? AND NOT is_spam
+            AND (account, mail_id) NOT IN
+                (SELECT account, mail_id FROM message_labels WHERE label = ?)
+            AND sender NOT IN (SELECT sender FROM senders WHERE is_ignored) AND {}
+        GROUP BY sender
+        HAVING recent > prior
+        ORDER BY sender",
+        scope.patterns("sender")
+    ))
+    .bind(recent_start)
+    .bind(recent_start)
+    .bind(prior_start)
+    .bind(end)
+    .bind(SENT)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Biggest increases in messages, most first.
+pub fn by_increase(rows: &[Growth]) -> Vec<Growth> {
+    let mut rows = rows.to_vec();
+    rows.sort_by(|a, b| {
+        b.increase()
+            .cmp(&a.increase())
+            .then(a.sender.cmp(&b.sender))
+    });
+    rows
+}
+
+/// Biggest relative increases among senders with at least `min_recent` recent messages. New
+/// senders have no ratio and come first, by recent volume.
+pub fn by_ratio(rows: &[Growth], min_recent: i64) -> Vec<Growth> {
+    let mut rows = rows
+        .iter()
+        .filter(|row| row.recent >= min_recent)
+        .cloned()
+        .collect::<Vec<_>>();
+    rows.sort_by(|a, b| {
+        let ratio = |row: &Growth| row.ratio().unwrap_or(f64::INFINITY);
+        ratio(b)
+            .total_cmp(&ratio(a))
+            .then(b.recent.cmp(&a.recent))
+            .then(a.sender.cmp(&b.sender))
+    });
+    rows
+}
+
+fn render_rows(out: &mut String, title: &str, rows: &[Growth]) {
+    writeln!(out, "{}", title).unwrap();
+    writeln!(
+        out,
+        "{:>8} {:>8} {:>8} {:>7}  sender",
+        "before", "recent", "change", "ratio"
+    )
+    .unwrap();
+    for row in rows {
+        writeln!(
+            out,
+            "{:>8} {:>8} {:>8} {:>7}  {}",
+            format::thousands(row.prior),
+            format::thousands(row.recent),
+            format!("+{}", format::thousands(row.increase())),
+            row.ratio()
+                .map(|ratio| format!("{:.1}x", ratio))
+                .unwrap_or_else(|| "new".to_string()),
+            row.sender
+        )
+        .unwrap();
+    }
+}
+
+pub fn render(by_increase: &[Growth], by_ratio: &[Growth], top: usize) -> String {
+    let mut out = String::new();
+    render_rows(
+        &mut out,
+        "biggest increases",
+        &by_increase[..top.min(by_increase.len())],
+    );
+    out.push('\n');
+    render_rows(
+        &mut out,
+        "fastest growing",
+        &by_ratio[..top.min(by_ratio.len())],
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use super::*;
+    use crate::db::{self, DEFAULT_ACCOUNT};
+    use crate::testsupport::{self, info};
+
+    const DAY: i64 = 86_400_000;
+
+    fn growth(sender: &str, prior: i64, recent: i64) -> Growth {
+        Growth {
+            sender: sender.to_string(),
+            prior,
+            recent,
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_are_counted_in_the_window_they_fall_in() {
+        let start = Utc
+            .with_ymd_and_hms(2024, 1, 1, 0, 0, 0)
+            .unwrap()
+            .timestamp_millis();
+        // Two 30 day windows from the start: prior is days 0-29 and recent days 30-59
+        let (prior_start, recent_start, end) = (start, start + 30 * DAY, start + 60 * DAY);
+        let pool = testsupport::pool().await;
+        let mut conn = pool.acquire().await.unwrap();
+        let mut n = 0;
+        for (sender, days, labels) in [
+            // Once before and three times since, the first exactly at the window's start
+            ("jane@example.com", &[10, 30, 45, 59][..], &[][..]),
+            // Steady, so not growing
+            ("bob@example.com", &[5, 35], &[]),
+            // Nothing before, and mail outside both windows doesn't count
+            ("new@example.com", &[-1, 31, 32, 60], &[]),
+            // Mine, and so left out
+            ("me@example.com", &[40, 41], &["SENT"]),
+        ] {
+            for day in days {
+                n += 1;
+                let message = info(&format!("m{}", n), sender, start + day * DAY, labels);
+                db::record_message(
+                    &message,
+                    DEFAULT_ACCOUNT,
+                    None,
+                    DateTime::UNIX_EPOCH,
+                    &mut conn,
+                )
+                .await
+                .unwrap();
+            }
+        }
+        drop(conn);
+
+        let rows = growing_senders(
+            &pool,
+            prior_start,
+            recent_start,
+            end,
+            &SenderScope::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            rows,
+            [
+                growth("jane@example.com", 1, 3),
+                growth("new@example.com", 0, 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn senders_silent_before_have_no_ratio_and_come_first() {
+        let rows = [
+            growth("double@example.com", 5, 10),
+            growth("new@example.com", 0, 6),
+            growth("triple@example.com", 2, 6),
+            growth("quiet@example.com", 0, 1),
+        ];
+
+        assert_eq!(rows[1].ratio(), None);
+        assert_eq!(rows[2].ratio(), Some(3.0));
+        let by_ratio = by_ratio(&rows, MIN_RECENT)
+            .into_iter()
+            .map(|row| row.sender)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            by_ratio,
+            [
+                "new@example.com",
+                "triple@example.com",
+                "double@example.com"
+            ]
+        );
+        let by_increase = by_increase(&rows)
+            .into_iter()
+            .map(|row| row.sender)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            by_increase,
+            [
+                "new@example.com",
+                "double@example.com",
+                "triple@example.com",
+                "quiet@example.com"
+            ]
+        );
+    }
+
+    #[test]
+    fn new_senders_show_as_new_rather_than_a_ratio() {
+        let rows = [
+            growth("new@example.com", 0, 6),
+            growth("a@example.com", 2, 5),
+        ];
+
+        let out = render(&rows, &rows, 10);
+
+        assert!(
+            out.contains("       0        6       +6     new  new@example.com"),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains("       2        5       +3    2.5x  a@example.com"),
+            "{}",
+            out
+        );
+        assert!(!out.contains("inf"), "{}", out);
+    }
+}
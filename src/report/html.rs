@@ -0,0 +1,139 @@
+use askama::Template;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+
+use super::{percent, Overview, TopSenders};
+use crate::format;
+
+/// A row of the senders table, formatted for display.
+pub struct SenderLine {
+    pub sender: String,
+    pub mails: String,
+    pub percent: String,
+    pub cumulative: String,
+    pub bytes: String,
+    pub bulk: String,
+    pub unread: String,
+    pub last_seen: String,
+}
+
+/// A labelled bar, with `width` as a percentage of the largest bar in its chart.
+pub struct BarLine {
+    pub label: String,
+    pub count: String,
+    pub width: String,
+}
+
+pub struct DomainLine {
+    pub domain: String,
+    pub class: String,
+    pub senders: String,
+    pub messages: String,
+    pub bytes: String,
+}
+
+/// The senders table with its totals.
+pub struct Senders {
+    pub rows: Vec<SenderLine>,
+    pub total_messages: String,
+    /// Summary of the senders that didn't make the table, empty if there are none.
+    pub tail: String,
+}
+
+/// The default report as a single self-contained HTML page. Sections which weren't selected
+/// are `None`.
+#[derive(Template)]
+#[template(path = "report.html")]
+pub struct Report {
+    pub generated: String,
+    pub senders: Option<Senders>,
+    pub domains: Option<Vec<DomainLine>>,
+    pub months: Option<Vec<BarLine>>,
+    pub hours: Option<Vec<BarLine>>,
+}
+
+fn bars(buckets: impl Iterator<Item = (String, i64)> + Clone) -> Vec<BarLine> {
+    let max = buckets.clone().map(|(_, count)| count).max().unwrap_or(0);
+    buckets
+        .map(|(label, count)| BarLine {
+            label,
+            count: format::thousands(count),
+            width: format!("{:.1}", percent(count, max)),
+        })
+        .collect()
+}
+
+fn senders(top: &TopSenders) -> Senders {
+    let mut cumulative = 0;
+    let rows = top
+        .rows
+        .iter()
+        .map(|row| {
+            cumulative += row.mails_sent;
+            SenderLine {
+                sender: row.sender.clone(),
+                mails: format::thousands(row.mails_sent),
+                percent: format!("{:.1}%", percent(row.mails_sent, top.total_messages)),
+                cumulative: format!("{:.1}%", percent(cumulative, top.total_messages)),
+                bytes: format::bytes(row.bytes),
+                bulk: format!("{:.0}%", row.bulk_fraction() * 100.0),
+                unread: format!("{:.0}%", row.unread_fraction() * 100.0),
+                last_seen: format::date(row.last_seen),
+            }
+        })
+        .collect();
+    let tail = if top.tail_senders > 0 {
+        format!(
+            "plus {} senders with \u{2264}{} messages each",
+            format::thousands(top.tail_senders),
+            format::thousands(top.tail_max)
+        )
+    } else {
+        String::new()
+    };
+    Senders {
+        rows,
+        total_messages: format::thousands(top.total_messages),
+        tail,
+    }
+}
+
+impl Report {
+    pub fn new(overview: &Overview, generated: DateTime<Utc>, tz: Tz) -> Report {
+        Report {
+            generated: generated
+                .with_timezone(&tz)
+                .format("%Y-%m-%d %H:%M %Z")
+                .to_string(),
+            senders: overview.senders.as_ref().map(senders),
+            domains: overview.domains.as_ref().map(|domains| {
+                domains
+                    .iter()
+                    .map(|domain| DomainLine {
+                        domain: domain.domain.clone(),
+                        class: domain.class.clone(),
+                        senders: format::thousands(domain.senders),
+                        messages: format::thousands(domain.messages),
+                        bytes: format::bytes(domain.bytes),
+                    })
+                    .collect()
+            }),
+            months: overview.trend.as_ref().map(|buckets| {
+                bars(
+                    buckets
+                        .iter()
+                        .map(|bucket| (bucket.label.clone(), bucket.count)),
+                )
+            }),
+            hours: overview.hours.as_ref().map(|histograms| {
+                bars(
+                    histograms
+                        .hours
+                        .iter()
+                        .enumerate()
+                        .map(|(hour, count)| (format!("{:02}h", hour), *count)),
+                )
+            }),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt::Write;
+
+use sqlx::{Pool, Sqlite};
+
+use super::{pattern::SenderScope, SenderRow};
+use crate::format;
+
+/// `--min-count` unless given.
+pub const MIN_COUNT: i64 = 10;
+
+pub async fn least_read(
+    pool: &Pool<Sqlite>,
+    limit: usize,
+    min_count: i64,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<SenderRow>> {
+    let rows = sqlx::query_as::<_, SenderRow>(&format!(
+        "SELECT sender, mails_sent, bulk_count, unread_count, direct_count, cc_count, auto_count,
+            bytes, last_seen
+        FROM senders
+        WHERE mails_sent >= ? AND NOT is_ignored AND {}
+        ORDER BY unread_count * 1.0 / mails_sent DESC, mails_sent DESC, sender
+        LIMIT ?",
+        scope.patterns("sender")
+    ))
+    .bind(min_count)
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Oldest and newest fetch times of the stored messages, in epoch milliseconds.
+pub async fn fetch_span(pool: &Pool<Sqlite>) -> anyhow::Result<(Option<i64>, Option<i64>)> {
+    Ok(
+        sqlx::query_as("SELECT min(fetched_at), max(fetched_at) FROM messages")
+            .fetch_one(pool)
+            .await?,
+    )
+}
+
+pub fn render(rows: &[SenderRow], fetched: (Option<i64>, Option<i64>)) -> String {
+    let mut out = String::new();
+
+    writeln!(out, "{:>10} {:>10} {:>6}  sender", "mails", "unread", "%").unwrap();
+    for row in rows {
+        writeln!(
+            out,
+            "{:>10} {:>10} {:>5.0}%  {}",
+            format::thousands(row.mails_sent),
+            format::thousands(row.unread_count),
+            row.unread_fraction() * 100.0,
+            row.sender
+        )
+        .unwrap();
+    }
+
+    // Read status is captured once per message and never refreshed
+    if let (Some(first), Some(last)) = fetched {
+        writeln!(
+            out,
+            "\nunread status as of when each message was fetched ({} to {})",
+            format::date(Some(first)),
+            format::date(Some(last))
+        )
+        .unwrap();
+    }
+    out
+}
@@ -0,0 +1,82 @@
+use std::fmt::Write;
+
+use sqlx::{Pool, Sqlite};
+
+use super::{percent, SenderRow};
+use crate::config::Config;
+use crate::format;
+
+/// Sender and message totals for internal and external mail.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Split {
+    pub internal_senders: i64,
+    pub internal_messages: i64,
+    pub external_senders: i64,
+    pub external_messages: i64,
+}
+
+pub async fn split(pool: &Pool<Sqlite>) -> anyhow::Result<Split> {
+    let rows: Vec<(bool, i64, i64)> = sqlx::query_as(
+        "SELECT is_internal, count(*), sum(mails_sent) FROM senders WHERE NOT is_ignored
+        GROUP BY is_internal",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut split = Split::default();
+    for (internal, senders, messages) in rows {
+        if internal {
+            split.internal_senders = senders;
+            split.internal_messages = messages;
+        } else {
+            split.external_senders = senders;
+            split.external_messages = messages;
+        }
+    }
+    Ok(split)
+}
+
+pub fn render(split: &Split, external: &[SenderRow], config: &Config) -> String {
+    let mut out = String::new();
+    let total = split.internal_messages + split.external_messages;
+
+    if !config.identity.has_domains() {
+        writeln!(
+            out,
+            "no own domains configured, every sender counts as external\n"
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "{:<10} {:>8} {:>10} {:>6}",
+        "", "senders", "mails", "%"
+    )
+    .unwrap();
+    for (label, senders, messages) in [
+        ("internal", split.internal_senders, split.internal_messages),
+        ("external", split.external_senders, split.external_messages),
+    ] {
+        writeln!(
+            out,
+            "{:<10} {:>8} {:>10} {:>5.1}%",
+            label,
+            format::thousands(senders),
+            format::thousands(messages),
+            percent(messages, total)
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "\ntop external senders").unwrap();
+    for row in external {
+        writeln!(
+            out,
+            "{:>10}  {}",
+            format::thousands(row.mails_sent),
+            row.sender
+        )
+        .unwrap();
+    }
+    out
+}
@@ -0,0 +1,269 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use crate::format;
+use crate::parse::SENT;
+
+/// Upper bounds (exclusive, in hours) and labels of the distribution buckets.
+const BUCKETS: [(i64, &str); 6] = [
+    (1, "< 1h"),
+    (4, "1-4h"),
+    (24, "4-24h"),
+    (72, "1-3d"),
+    (168, "3-7d"),
+    (i64::MAX, "> 7d"),
+];
+
+#[derive(Clone, Debug, FromRow)]
+pub struct ThreadMessage {
+    pub thread_id: String,
+    pub sender: String,
+    pub internal_date: i64,
+    pub sent: bool,
+}
+
+/// How long I took to answer one sender in one thread.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Latency {
+    pub sender: String,
+    pub millis: i64,
+}
+
+/// Messages in threads I've sent mail to, ordered by thread then date.
+pub async fn thread_messages(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<ThreadMessage>> {
+    let rows = sqlx::query_as::<_, ThreadMessage>(
+        "WITH sent AS (
+            SELECT account, mail_id FROM message_labels WHERE label = ?
+        )
+        SELECT thread_id, sender, internal_date, (account, mail_id) IN sent AS sent
+        FROM messages
+        WHERE internal_date IS NOT NULL AND thread_id IN (
+            SELECT thread_id FROM messages WHERE (account, mail_id) IN sent
+        )
+        ORDER BY thread_id, internal_date, mail_id",
+    )
+    .bind(SENT)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Walk each thread in date order. Incoming messages wait for my next sent message, and when it
+/// comes each sender waiting is answered, measured from their earliest unanswered message. Sent
+/// messages with nothing waiting (e.g. starting a thread) and mail I never answered are ignored.
+pub fn latencies(messages: &[ThreadMessage]) -> Vec<Latency> {
+    let mut latencies = Vec::new();
+    let mut waiting: BTreeMap<&str, i64> = BTreeMap::new();
+    let mut thread = None;
+
+    for message in messages {
+        if thread != Some(&message.thread_id) {
+            waiting.clear();
+            thread = Some(&message.thread_id);
+        }
+
+        if message.sent {
+            for (sender, since) in std::mem::take(&mut waiting) {
+                latencies.push(Latency {
+                    sender: sender.to_string(),
+                    millis: message.internal_date - since,
+                });
+            }
+        } else {
+            waiting
+                .entry(&message.sender)
+                .or_insert(message.internal_date);
+        }
+    }
+
+    latencies
+}
+
+// Nearest-rank percentile of sorted values
+fn percentile(sorted: &[i64], p: f64) -> i64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (p * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// The overall distribution, then the `top` correspondents with at least `min_replies` replies.
+pub fn render(latencies: &[Latency], top: usize, min_replies: i64) -> String {
+    let mut out = String::new();
+    if latencies.is_empty() {
+        writeln!(out, "no replies found").unwrap();
+        return out;
+    }
+
+    let mut all = latencies.iter().map(|l| l.millis).collect::<Vec<_>>();
+    all.sort_unstable();
+    writeln!(
+        out,
+        "{} replies, median {}, p90 {}\n",
+        format::thousands(all.len() as i64),
+        format::duration(percentile(&all, 0.5)),
+        format::duration(percentile(&all, 0.9))
+    )
+    .unwrap();
+
+    let mut counts = [0i64; BUCKETS.len()];
+    for millis in &all {
+        let hours = millis / 3_600_000;
+        let bucket = BUCKETS.iter().position(|(max, _)| hours < *max).unwrap();
+        counts[bucket] += 1;
+    }
+    for ((_, label), count) in BUCKETS.iter().zip(counts) {
+        writeln!(
+            out,
+            "{:>6} {:>8} {:>5.1}%",
+            label,
+            format::thousands(count),
+            count as f64 * 100.0 / all.len() as f64
+        )
+        .unwrap();
+    }
+
+    let mut by_sender: HashMap<&str, Vec<i64>> = HashMap::new();
+    for latency in latencies {
+        by_sender
+            .entry(&latency.sender)
+            .or_default()
+            .push(latency.millis);
+    }
+    let mut senders = by_sender
+        .into_iter()
+        .filter(|(_, millis)| millis.len() as i64 >= min_replies)
+        .collect::<Vec<_>>();
+    senders.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(b.0)));
+
+    writeln!(
+        out,
+        "\n{:>8} {:>8} {:>8}  correspondent",
+        "replies", "median", "p90"
+    )
+    .unwrap();
+    for (sender, mut millis) in senders.into_iter().take(top) {
+        millis.sort_unstable();
+        writeln!(
+            out,
+            "{:>8} {:>8} {:>8}  {}",
+            format::thousands(millis.len() as i64),
+            format::duration(percentile(&millis, 0.5)),
+            format::duration(percentile(&millis, 0.9)),
+            sender
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = 3_600_000;
+
+    // (thread, sender, hours in, sent by me)
+    fn timeline(messages: &[(&str, &str, i64, bool)]) -> Vec<ThreadMessage> {
+        messages
+            .iter()
+            .map(|(thread, sender, hours, sent)| ThreadMessage {
+                thread_id: thread.to_string(),
+                sender: sender.to_string(),
+                internal_date: hours * HOUR,
+                sent: *sent,
+            })
+            .collect()
+    }
+
+    fn hours(latencies: &[Latency]) -> Vec<(&str, i64)> {
+        latencies
+            .iter()
+            .map(|latency| (latency.sender.as_str(), latency.millis / HOUR))
+            .collect()
+    }
+
+    #[test]
+    fn a_reply_is_timed_from_the_first_unanswered_message() {
+        let messages = timeline(&[
+            ("t1", "jane@example.com", 0, false),
+            ("t1", "jane@example.com", 2, false),
+            ("t1", "me@example.com", 5, true),
+            ("t1", "jane@example.com", 6, false),
+            ("t1", "me@example.com", 7, true),
+        ]);
+        assert_eq!(
+            hours(&latencies(&messages)),
+            [("jane@example.com", 5), ("jane@example.com", 1)]
+        );
+    }
+
+    #[test]
+    fn threads_i_started_and_mail_i_never_answered_are_left_out() {
+        let messages = timeline(&[
+            // I started it, and the answer to me isn't mine to time
+            ("t1", "me@example.com", 0, true),
+            ("t1", "jane@example.com", 3, false),
+            // Never answered, and not carried over into the next thread
+            ("t2", "news@example.com", 0, false),
+            ("t3", "me@example.com", 10, true),
+        ]);
+        assert!(latencies(&messages).is_empty());
+    }
+
+    #[test]
+    fn one_reply_answers_everyone_waiting_in_the_thread() {
+        let messages = timeline(&[
+            ("t1", "jane@example.com", 0, false),
+            ("t1", "bob@example.com", 1, false),
+            ("t1", "jane@example.com", 2, false),
+            ("t1", "me@example.com", 4, true),
+            ("t1", "me@example.com", 5, true),
+        ]);
+        assert_eq!(
+            hours(&latencies(&messages)),
+            [("bob@example.com", 3), ("jane@example.com", 4)]
+        );
+    }
+
+    #[test]
+    fn percentiles_are_nearest_rank() {
+        let sorted = (1..=10).collect::<Vec<_>>();
+        assert_eq!(percentile(&sorted, 0.5), 5);
+        assert_eq!(percentile(&sorted, 0.9), 9);
+        assert_eq!(percentile(&[7], 0.9), 7);
+        assert_eq!(percentile(&[], 0.5), 0);
+    }
+
+    #[test]
+    fn the_distribution_is_bucketed_by_hours() {
+        let latencies = [
+            ("jane@example.com", 0),
+            ("jane@example.com", 2),
+            ("bob@example.com", 200),
+        ]
+        .map(|(sender, hours)| Latency {
+            sender: sender.to_string(),
+            millis: hours * HOUR,
+        });
+        assert_eq!(
+            render(&latencies, 5, 2),
+            "3 replies, median 2h 0m, p90 8d 8h
+
+  < 1h        1  33.3%
+  1-4h        1  33.3%
+ 4-24h        0   0.0%
+  1-3d        0   0.0%
+  3-7d        0   0.0%
+  > 7d        1  33.3%
+
+ replies   median      p90  correspondent
+       2       0m    2h 0m  jane@example.com
+"
+        );
+        assert_eq!(render(&[], 5, 2), "no replies found\n");
+    }
+}
@@ -0,0 +1,259 @@
+use std::fmt::Write;
+
+use super::classes::DomainVolume;
+use crate::domains::registrable_domain;
+use crate::format;
+
+/// Characters commonly swapped in for others in phishing domains, with what they imitate.
+const CONFUSABLES: [(&str, &str); 9] = [
+    ("rn", "m"),
+    ("vv", "w"),
+    ("cl", "d"),
+    ("0", "o"),
+    ("1", "l"),
+    ("i", "l"),
+    ("3", "e"),
+    ("5", "s"),
+    ("-", ""),
+];
+
+/// Names shorter than this are too easy to hit by accident with one edit.
+const MIN_NAME_LEN: usize = 4;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lookalike {
+    pub domain: String,
+    pub messages: i64,
+    /// The trusted domain it resembles.
+    pub resembles: String,
+    pub reason: &'static str,
+}
+
+/// Collapse confusable characters and hyphens so `paypa1.com` and `pay-pal.com` both become
+/// `paypal.com`.
+pub fn skeleton(domain: &str) -> String {
+    let mut skeleton = domain.to_lowercase();
+    for (from, to) in CONFUSABLES {
+        skeleton = skeleton.replace(from, to);
+    }
+    skeleton
+}
+
+/// Levenshtein distance over characters.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Why `domain` looks like `trusted`, if it does. Both should be registrable domains and must
+/// differ. One edit is allowed for short names and two from ten characters up.
+pub fn resemblance(domain: &str, trusted: &str) -> Option<&'static str> {
+    let name_len = trusted
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count();
+    if domain == trusted || name_len < MIN_NAME_LEN {
+        return None;
+    }
+    if skeleton(domain) == skeleton(trusted) {
+        return Some("confusable characters");
+    }
+    let allowed = if name_len >= 10 { 2 } else { 1 };
+    if edit_distance(domain, trusted) <= allowed {
+        return Some("one or two characters off");
+    }
+    None
+}
+
+/// Domains resembling one of the `trusted_count` busiest domains or my own domains, most
+/// messages first. `volumes` must be ordered by messages, most first. A busy domain which itself
+/// resembles a busier trusted one isn't trusted, so lookalikes can't hide in the top N.
+pub fn find(
+    volumes: &[DomainVolume],
+    trusted_count: usize,
+    own_domains: &[String],
+) -> Vec<Lookalike> {
+    let mut trusted = own_domains
+        .iter()
+        .map(|domain| registrable_domain(domain))
+        .collect::<Vec<_>>();
+    for volume in volumes.iter().take(trusted_count) {
+        let domain = registrable_domain(&volume.domain);
+        if !trusted
+            .iter()
+            .any(|t| *t == domain || resemblance(&domain, t).is_some())
+        {
+            trusted.push(domain);
+        }
+    }
+
+    let mut found: Vec<Lookalike> = Vec::new();
+    for volume in volumes {
+        let domain = registrable_domain(&volume.domain);
+        if trusted.contains(&domain) {
+            continue;
+        }
+        let Some((resembles, reason)) = trusted
+            .iter()
+            .find_map(|t| resemblance(&domain, t).map(|reason| (t, reason)))
+        else {
+            continue;
+        };
+        // Subdomains of the same lookalike are reported together
+        match found.iter_mut().find(|l| l.domain == domain) {
+            Some(lookalike) => lookalike.messages += volume.messages,
+            None => found.push(Lookalike {
+                domain,
+                messages: volume.messages,
+                resembles: resembles.clone(),
+                reason,
+            }),
+        }
+    }
+    found.sort_by(|a, b| b.messages.cmp(&a.messages).then(a.domain.cmp(&b.domain)));
+    found
+}
+
+pub fn render(lookalikes: &[Lookalike]) -> String {
+    let mut out = String::new();
+    if lookalikes.is_empty() {
+        writeln!(out, "no lookalike domains found").unwrap();
+        return out;
+    }
+
+    writeln!(
+        out,
+        "{:>8}  {:<30} {:<30} why",
+        "mails", "domain", "resembles"
+    )
+    .unwrap();
+    for lookalike in lookalikes {
+        writeln!(
+            out,
+            "{:>8}  {:<30} {:<30} {}",
+            format::thousands(lookalike.messages),
+            lookalike.domain,
+            lookalike.resembles,
+            lookalike.reason
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume(domain: &str, messages: i64) -> DomainVolume {
+        DomainVolume {
+            domain: domain.to_string(),
+            class: "direct".to_string(),
+            senders: 1,
+            messages,
+            bytes: 0,
+        }
+    }
+
+    #[test]
+    fn edit_distance_counts_single_character_edits() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("amazon", "amazon"), 0);
+        assert_eq!(edit_distance("amazon", "amazom"), 1);
+        assert_eq!(edit_distance("amazon", "amzaon"), 2);
+        // Characters rather than bytes
+        assert_eq!(edit_distance("café", "cafe"), 1);
+    }
+
+    #[test]
+    fn confusables_collapse_onto_what_they_imitate() {
+        assert_eq!(skeleton("paypa1.com"), skeleton("paypal.com"));
+        assert_eq!(skeleton("PAY-PAL.com"), skeleton("paypal.com"));
+        assert_eq!(skeleton("rnicrosoft.com"), skeleton("microsoft.com"));
+        assert_eq!(skeleton("vvalmart.com"), skeleton("walmart.com"));
+        assert_eq!(skeleton("g00gle.com"), skeleton("google.com"));
+        assert_eq!(skeleton("clropbox.com"), skeleton("dropbox.com"));
+        assert_ne!(skeleton("paypal.com"), skeleton("paypals.com"));
+    }
+
+    #[test]
+    fn lookalikes_are_confusable_or_close() {
+        assert_eq!(
+            resemblance("paypa1.com", "paypal.com"),
+            Some("confusable characters")
+        );
+        assert_eq!(
+            resemblance("arnazon.com", "amazon.com"),
+            Some("confusable characters")
+        );
+        assert_eq!(
+            resemblance("amazom.com", "amazon.com"),
+            Some("one or two characters off")
+        );
+        assert_eq!(
+            resemblance("ebey.com", "ebay.com"),
+            Some("one or two characters off")
+        );
+        // Two edits only from ten characters up
+        assert_eq!(resemblance("amzaon.com", "amazon.com"), None);
+        assert_eq!(
+            resemblance("salesfarse.com", "salesforce.com"),
+            Some("one or two characters off")
+        );
+        // Short names and the domain itself never count
+        assert_eq!(resemblance("abd.com", "abc.com"), None);
+        assert_eq!(resemblance("amazon.com", "amazon.com"), None);
+        assert_eq!(resemblance("example.org", "amazon.com"), None);
+    }
+
+    #[test]
+    fn lookalikes_of_busy_and_own_domains_are_found() {
+        let volumes = [
+            volume("amazon.com", 100),
+            volume("gmail.com", 80),
+            // Busy enough to be trusted, but it resembles a busier domain
+            volume("amazom.com", 50),
+            volume("example.org", 10),
+            volume("mail.arnazon.com", 5),
+            volume("arnazon.com", 3),
+            volume("myc0rp.co.uk", 2),
+        ];
+
+        let found = find(&volumes, 3, &["MyCorp.co.uk".to_string()]);
+
+        let summary = found
+            .iter()
+            .map(|l| {
+                (
+                    l.domain.as_str(),
+                    l.messages,
+                    l.resembles.as_str(),
+                    l.reason,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("amazom.com", 50, "amazon.com", "one or two characters off"),
+                ("arnazon.com", 8, "amazon.com", "confusable characters"),
+                ("myc0rp.co.uk", 2, "mycorp.co.uk", "confusable characters")
+            ]
+        );
+        assert!(render(&found).contains("arnazon.com"));
+        assert_eq!(render(&[]), "no lookalike domains found\n");
+    }
+}
@@ -0,0 +1,148 @@
+use std::fmt::Write;
+
+use super::{percent, Overview, TopSenders};
+use crate::format;
+
+/// Make `cell` safe inside a GitHub-flavored Markdown table cell. Pipes would end the cell and
+/// angle brackets could be read as HTML, so both are escaped; line breaks would end the row.
+pub fn escape(cell: &str) -> String {
+    let mut out = String::with_capacity(cell.len());
+    for c in cell.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '|' => out.push_str("\\|"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\r' | '\n' => out.push(' '),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// A table with right-aligned number columns; `left` is how many columns from the end are text
+fn table(out: &mut String, header: &[&str], left: usize, rows: &[Vec<String>]) {
+    writeln!(out, "| {} |", header.join(" | ")).unwrap();
+    let alignments = (0..header.len())
+        .map(|i| {
+            if i + left >= header.len() {
+                ":--"
+            } else {
+                "--:"
+            }
+        })
+        .collect::<Vec<_>>();
+    writeln!(out, "| {} |", alignments.join(" | ")).unwrap();
+    for row in rows {
+        let cells = row.iter().map(|cell| escape(cell)).collect::<Vec<_>>();
+        writeln!(out, "| {} |", cells.join(" | ")).unwrap();
+    }
+    out.push('\n');
+}
+
+fn senders(out: &mut String, top: &TopSenders) {
+    writeln!(out, "## Top senders\n").unwrap();
+    let mut cumulative = 0;
+    let rows = top
+        .rows
+        .iter()
+        .map(|row| {
+            cumulative += row.mails_sent;
+            vec![
+                format::thousands(row.mails_sent),
+                format!("{:.1}%", percent(row.mails_sent, top.total_messages)),
+                format!("{:.1}%", percent(cumulative, top.total_messages)),
+                format::bytes(row.bytes),
+                format!("{:.0}%", row.bulk_fraction() * 100.0),
+                format!("{:.0}%", row.unread_fraction() * 100.0),
+                format::date(row.last_seen),
+                row.sender.clone(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    table(
+        out,
+        &[
+            "mails",
+            "%",
+            "cum %",
+            "bytes",
+            "bulk",
+            "unread",
+            "last seen",
+            "sender",
+        ],
+        1,
+        &rows,
+    );
+    write!(
+        out,
+        "These {} senders are {:.1}% of {} messages",
+        format::thousands(top.rows.len() as i64),
+        percent(cumulative, top.total_messages),
+        format::thousands(top.total_messages)
+    )
+    .unwrap();
+    if top.tail_senders > 0 {
+        write!(
+            out,
+            ", plus {} senders with \u{2264}{} messages each",
+            format::thousands(top.tail_senders),
+            format::thousands(top.tail_max)
+        )
+        .unwrap();
+    }
+    out.push_str(".\n\n");
+}
+
+/// The selected sections as Markdown. Contains nothing that changes between runs over the same
+/// data, so two reports can be diffed.
+pub fn render(overview: &Overview) -> String {
+    let mut out = String::new();
+    writeln!(out, "# gmail_stats report\n").unwrap();
+    if let Some(top) = &overview.senders {
+        senders(&mut out, top);
+    }
+    if let Some(domains) = &overview.domains {
+        writeln!(out, "## Domains\n").unwrap();
+        let rows = domains
+            .iter()
+            .map(|domain| {
+                vec![
+                    format::thousands(domain.messages),
+                    format::thousands(domain.senders),
+                    format::bytes(domain.bytes),
+                    domain.class.clone(),
+                    domain.domain.clone(),
+                ]
+            })
+            .collect::<Vec<_>>();
+        table(
+            &mut out,
+            &["mails", "senders", "bytes", "class", "domain"],
+            2,
+            &rows,
+        );
+    }
+    if let Some(buckets) = &overview.trend {
+        writeln!(out, "## Messages per month\n").unwrap();
+        let rows = buckets
+            .iter()
+            .map(|bucket| vec![bucket.label.clone(), format::thousands(bucket.count)])
+            .collect::<Vec<_>>();
+        table(&mut out, &["month", "mails"], 0, &rows);
+    }
+    if let Some(histograms) = &overview.hours {
+        writeln!(out, "## Messages by hour of day\n").unwrap();
+        let rows = histograms
+            .hours
+            .iter()
+            .enumerate()
+            .map(|(hour, count)| vec![format!("{:02}h", hour), format::thousands(*count)])
+            .collect::<Vec<_>>();
+        table(&mut out, &["hour", "mails"], 0, &rows);
+    }
+    out.truncate(out.trim_end().len());
+    out.push('\n');
+    out
+}
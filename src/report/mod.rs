@@ -0,0 +1,826 @@
+use std::fmt::Write;
+use std::fs;
+use std::str::FromStr;
+
+use askama::Template;
+use chrono_tz::Tz;
+use comfy_table::{Attribute, Cell, Color};
+use serde::Serialize;
+use sqlx::{FromRow, Pool, Sqlite};
+
+use crate::cli::{self, OutputFormat, ReportArgs, ReportView, Section, SortBy};
+use crate::config::Config;
+use crate::dates::{self, Granularity};
+use crate::format;
+use crate::opener;
+use crate::parse::NoticeKind;
+use crate::search;
+use pattern::SenderScope;
+use table::Style;
+
+mod accounts;
+mod arrivals;
+mod attachments;
+mod auth;
+mod automated;
+mod bounces;
+#[cfg(feature = "tui")]
+mod browse;
+mod calendar;
+mod categories;
+#[cfg(feature = "charts")]
+mod chart;
+pub mod classes;
+mod delta;
+pub mod diff;
+mod digest;
+mod distribution;
+mod duplicates;
+mod email;
+mod engagement;
+mod errors;
+mod filters;
+mod forwarded;
+mod growth;
+mod html;
+mod ignored;
+mod internal;
+mod latency;
+mod lookalikes;
+mod markdown;
+mod new;
+pub mod pattern;
+#[cfg(feature = "serve")]
+pub mod serve;
+#[cfg(test)]
+mod snapshots;
+mod spam;
+mod stale;
+mod storage;
+mod subjects;
+mod table;
+mod template;
+mod threads;
+pub mod trend;
+#[cfg(feature = "tui")]
+pub mod tui;
+mod when;
+
+/// Senders whose mail is at least this fraction bulk are hidden by `--no-bulk`.
+const MOSTLY_BULK: f64 = 0.9;
+
+/// Senders whose mail is at least this fraction machine-generated are highlighted.
+const MOSTLY_AUTOMATED: f64 = 0.5;
+
+/// How many of the top senders are highlighted.
+const HIGHLIGHTED_ROWS: usize = 3;
+
+/// The senders table with aliases folded onto their canonical address and ignored senders left
+/// out, usable in place of it.
+pub(crate) const MERGED_SENDERS: &str = "(
+    SELECT coalesce(a.canonical, s.sender) AS sender, sum(s.mails_sent) AS mails_sent,
+        sum(s.bulk_count) AS bulk_count, sum(s.unread_count) AS unread_count,
+        sum(s.direct_count) AS direct_count, sum(s.cc_count) AS cc_count,
+        sum(s.auto_count) AS auto_count, sum(s.bytes) AS bytes, min(s.first_seen) AS first_seen,
+        max(s.last_seen) AS last_seen, max(s.is_internal) AS is_internal
+    FROM senders s LEFT JOIN sender_aliases a ON a.sender = lower(s.sender)
+    WHERE NOT s.is_ignored
+    GROUP BY coalesce(a.canonical, s.sender)
+)";
+
+#[derive(Clone, Debug, FromRow, Serialize)]
+pub struct SenderRow {
+    pub sender: String,
+    pub mails_sent: i64,
+    pub bulk_count: i64,
+    pub unread_count: i64,
+    /// Messages with one of my addresses in To.
+    pub direct_count: i64,
+    /// Messages with one of my addresses in Cc but not To.
+    pub cc_count: i64,
+    /// Machine-generated messages, see `parse::AutoKind`.
+    pub auto_count: i64,
+    pub bytes: i64,
+    pub last_seen: Option<i64>,
+}
+
+impl SenderRow {
+    pub fn bulk_fraction(&self) -> f64 {
+        if self.mails_sent == 0 {
+            return 0.0;
+        }
+        self.bulk_count as f64 / self.mails_sent as f64
+    }
+
+    pub fn unread_fraction(&self) -> f64 {
+        if self.mails_sent == 0 {
+            return 0.0;
+        }
+        self.unread_count as f64 / self.mails_sent as f64
+    }
+
+    pub fn direct_fraction(&self) -> f64 {
+        if self.mails_sent == 0 {
+            return 0.0;
+        }
+        self.direct_count as f64 / self.mails_sent as f64
+    }
+
+    pub fn cc_fraction(&self) -> f64 {
+        if self.mails_sent == 0 {
+            return 0.0;
+        }
+        self.cc_count as f64 / self.mails_sent as f64
+    }
+
+    pub fn is_automated(&self) -> bool {
+        self.auto_count as f64 >= self.mails_sent as f64 * MOSTLY_AUTOMATED
+    }
+}
+
+/// The top senders plus enough about the rest to summarize the long tail.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TopSenders {
+    pub rows: Vec<SenderRow>,
+    /// Messages from every sender, including those hidden by filters.
+    pub total_messages: i64,
+    /// Senders which matched the filters but didn't make the top N.
+    pub tail_senders: i64,
+    /// The most messages any one of the tail senders has sent.
+    pub tail_max: i64,
+}
+
+/// Which senders the senders report includes.
+#[derive(Clone, Debug, Default)]
+pub struct SenderFilter {
+    /// Hide senders whose mail is mostly bulk.
+    pub no_bulk: bool,
+    /// Hide senders from my own domains.
+    pub external_only: bool,
+    /// Hide senders who have never sent mail addressed to me directly.
+    pub direct_only: bool,
+    /// `--match`, `--exclude` and `--min-count`.
+    pub scope: SenderScope,
+}
+
+impl SenderFilter {
+    pub fn new(args: &ReportArgs, scope: SenderScope) -> SenderFilter {
+        SenderFilter {
+            no_bulk: args.no_bulk,
+            external_only: args.external_only,
+            direct_only: args.direct_only,
+            scope,
+        }
+    }
+
+    /// SQL condition on the senders table.
+    fn condition(&self) -> String {
+        let mut conditions = vec!["1".to_string()];
+        if self.no_bulk {
+            conditions.push(format!("bulk_count < mails_sent * {}", MOSTLY_BULK));
+        }
+        if self.external_only {
+            conditions.push("NOT is_internal".to_string());
+        }
+        if self.direct_only {
+            conditions.push("direct_count > 0".to_string());
+        }
+        conditions.push(self.scope.condition("sender", Some("mails_sent")));
+        conditions.join(" AND ")
+    }
+}
+
+fn order_by(by: SortBy) -> &'static str {
+    match by {
+        SortBy::Count => "mails_sent DESC, sender",
+        SortBy::Bytes => "bytes DESC, sender",
+        SortBy::Recent => "last_seen IS NULL, last_seen DESC, sender",
+    }
+}
+
+// Merged senders matching `filter` in `by` order, as SenderRows
+fn filtered_senders(filter: &SenderFilter, by: SortBy) -> String {
+    format!(
+        "SELECT sender, mails_sent, bulk_count, unread_count, direct_count, cc_count, auto_count,
+            bytes, last_seen
+        FROM {}
+        WHERE {}
+        ORDER BY {}",
+        MERGED_SENDERS,
+        filter.condition(),
+        order_by(by)
+    )
+}
+
+/// Every sender matching `filter`, for browsing rather than a report.
+pub async fn all_senders(
+    pool: &Pool<Sqlite>,
+    filter: &SenderFilter,
+    by: SortBy,
+) -> anyhow::Result<Vec<SenderRow>> {
+    Ok(
+        sqlx::query_as::<_, SenderRow>(&filtered_senders(filter, by))
+            .fetch_all(pool)
+            .await?,
+    )
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, FromRow)]
+pub struct RecentMessage {
+    pub subject: Option<String>,
+    pub internal_date: Option<i64>,
+}
+
+/// The latest `limit` messages from `sender`, counting mail from its aliases as `report` does.
+pub async fn recent_messages(
+    pool: &Pool<Sqlite>,
+    sender: &str,
+    limit: usize,
+) -> anyhow::Result<Vec<RecentMessage>> {
+    Ok(sqlx::query_as::<_, RecentMessage>(
+        "SELECT m.subject, m.internal_date
+        FROM messages m LEFT JOIN sender_aliases a ON a.sender = lower(m.sender)
+        WHERE coalesce(a.canonical, m.sender) = ?
+        ORDER BY m.internal_date IS NULL, m.internal_date DESC
+        LIMIT ?",
+    )
+    .bind(sender)
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn top_senders(
+    pool: &Pool<Sqlite>,
+    limit: usize,
+    filter: &SenderFilter,
+    by: SortBy,
+) -> anyhow::Result<TopSenders> {
+    let filtered = filtered_senders(filter, by);
+    let rows = sqlx::query_as::<_, SenderRow>(&format!("{} LIMIT ?", filtered))
+        .bind(limit as i64)
+        .fetch_all(pool)
+        .await?;
+
+    let (tail_senders, tail_max): (i64, Option<i64>) = sqlx::query_as(&format!(
+        "SELECT count(*), max(mails_sent) FROM ({} LIMIT -1 OFFSET ?)",
+        filtered
+    ))
+    .bind(limit as i64)
+    .fetch_one(pool)
+    .await?;
+
+    let (total_messages,): (Option<i64>,) =
+        sqlx::query_as("SELECT sum(mails_sent) FROM senders WHERE NOT is_ignored")
+            .fetch_one(pool)
+            .await?;
+
+    Ok(TopSenders {
+        rows,
+        total_messages: total_messages.unwrap_or_default(),
+        tail_senders,
+        tail_max: tail_max.unwrap_or_default(),
+    })
+}
+
+fn percent(part: i64, total: i64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    part as f64 * 100.0 / total as f64
+}
+
+pub fn render_top_senders(top: &TopSenders, style: Style) -> String {
+    let mut cumulative = 0;
+    let mut table = table::new(
+        style,
+        &[
+            "mails",
+            "%",
+            "cum %",
+            "bytes",
+            "bulk",
+            "unread",
+            "direct",
+            "cc",
+            "last seen",
+            "sender",
+        ],
+        1,
+    );
+    for (i, row) in top.rows.iter().enumerate() {
+        cumulative += row.mails_sent;
+        let mut mails = Cell::new(format::thousands(row.mails_sent));
+        let mut sender = Cell::new(&row.sender);
+        if style.fancy && i < HIGHLIGHTED_ROWS {
+            mails = mails.add_attribute(Attribute::Bold).fg(Color::Green);
+            sender = sender.add_attribute(Attribute::Bold);
+        }
+        if style.fancy && row.is_automated() {
+            sender = sender.fg(Color::Cyan);
+        }
+        table.add_row(vec![
+            mails,
+            Cell::new(format!(
+                "{:.1}%",
+                percent(row.mails_sent, top.total_messages)
+            )),
+            Cell::new(format!("{:.1}%", percent(cumulative, top.total_messages))),
+            Cell::new(format::bytes(row.bytes)),
+            Cell::new(format!("{:.0}%", row.bulk_fraction() * 100.0)),
+            Cell::new(format!("{:.0}%", row.unread_fraction() * 100.0)),
+            Cell::new(format!("{:.0}%", row.direct_fraction() * 100.0)),
+            Cell::new(format!("{:.0}%", row.cc_fraction() * 100.0)),
+            Cell::new(format::date(row.last_seen)),
+            sender,
+        ]);
+    }
+    let mut out = table::render(&mut table);
+
+    writeln!(
+        out,
+        "\nthese {} senders are {:.1}% of {} messages",
+        format::thousands(top.rows.len() as i64),
+        percent(cumulative, top.total_messages),
+        format::thousands(top.total_messages)
+    )
+    .unwrap();
+    if top.tail_senders > 0 {
+        writeln!(
+            out,
+            "plus {} senders with \u{2264}{} messages each",
+            format::thousands(top.tail_senders),
+            format::thousands(top.tail_max)
+        )
+        .unwrap();
+    }
+    if style.fancy && top.rows.iter().any(SenderRow::is_automated) {
+        writeln!(out, "senders of mostly automated mail are in cyan").unwrap();
+    }
+
+    out
+}
+
+// The --timezone flag wins over the config file
+// `name`, else the configured timezone, else UTC
+pub(crate) fn timezone(name: Option<&str>, config: &Config) -> anyhow::Result<Tz> {
+    let name = name.or(config.timezone.as_deref()).unwrap_or("UTC");
+    Tz::from_str(name).map_err(|_| anyhow::anyhow!("unknown timezone {:?}", name))
+}
+
+/// The sections of the default report for the HTML and Markdown formats, each `None` unless
+/// selected with `--sections`.
+#[derive(Clone, Debug, Default)]
+pub struct Overview {
+    pub senders: Option<TopSenders>,
+    pub domains: Option<Vec<classes::DomainVolume>>,
+    /// Messages per month.
+    pub trend: Option<Vec<trend::Bucket>>,
+    pub hours: Option<when::Histograms>,
+}
+
+async fn overview(
+    pool: &Pool<Sqlite>,
+    args: &ReportArgs,
+    filter: &SenderFilter,
+    tz: Tz,
+) -> anyhow::Result<Overview> {
+    gather_overview(pool, &args.sections, args.top, filter, args.by, tz).await
+}
+
+// Gather the `sections` (all of them if empty), built on the same queries as the text views
+async fn gather_overview(
+    pool: &Pool<Sqlite>,
+    sections: &[Section],
+    top: usize,
+    filter: &SenderFilter,
+    by: SortBy,
+    tz: Tz,
+) -> anyhow::Result<Overview> {
+    let selected = |section| sections.is_empty() || sections.contains(&section);
+    let mut overview = Overview::default();
+    if selected(Section::Senders) {
+        overview.senders = Some(top_senders(pool, top, filter, by).await?);
+    }
+    if selected(Section::Domains) {
+        let mut domains = classes::domain_volumes(pool, &filter.scope).await?;
+        domains.truncate(top);
+        overview.domains = Some(domains);
+    }
+    if selected(Section::Trend) {
+        let trend_filter = trend::Filter {
+            scope: filter.scope.clone(),
+            ..Default::default()
+        };
+        let dates = trend::message_dates(pool, &trend_filter).await?;
+        overview.trend = Some(trend::buckets(&dates, Granularity::Month, tz, None, None));
+    }
+    if selected(Section::Hours) {
+        let dates = when::message_dates(pool, None, &filter.scope).await?;
+        overview.hours = Some(when::Histograms::new(&dates, tz));
+    }
+    Ok(overview)
+}
+
+// Email the overview to `to` as Markdown text with an HTML alternative
+async fn email_report(
+    pool: &Pool<Sqlite>,
+    args: &ReportArgs,
+    filter: &SenderFilter,
+    tz: Tz,
+    to: &str,
+) -> anyhow::Result<()> {
+    let overview = overview(pool, args, filter, tz).await?;
+    let now = args.clock.now();
+    let subject = format!(
+        "gmail_stats report for {}",
+        now.with_timezone(&tz).format("%Y-%m-%d")
+    );
+    let html = html::Report::new(&overview, now, tz).render()?;
+    let raw = email::compose(
+        to,
+        &subject,
+        &markdown::render(&overview),
+        &html,
+        &email::boundary(),
+    );
+    email::send(raw).await
+}
+
+pub async fn run(pool: &Pool<Sqlite>, args: &ReportArgs, config: &Config) -> anyhow::Result<()> {
+    let tz = timezone(args.timezone.as_deref(), config)?;
+    if args.view.is_some()
+        && (args.format != OutputFormat::Text
+            || args.out.is_some()
+            || args.template.is_some()
+            || args.email_to.is_some())
+    {
+        anyhow::bail!(
+            "--format, --out, --template and --email-to only apply to the default senders report"
+        );
+    }
+    if args.by_account
+        && (args.view.is_some() || args.format != OutputFormat::Text || args.template.is_some())
+    {
+        anyhow::bail!("--by-account only applies to the default senders report as text");
+    }
+    if args.format == OutputFormat::Text && args.email_to.is_none() && !args.sections.is_empty() {
+        anyhow::bail!("--sections needs --format html or markdown, or --email-to");
+    }
+    let scope = SenderScope::new(pool, &args.matches, &args.exclude, args.min_count).await?;
+    let filter = SenderFilter::new(args, scope.clone());
+
+    match &args.view {
+        None => {
+            let report = match (&args.template, args.format) {
+                (Some(template), _) if template == template::HTML => html::Report::new(
+                    &overview(pool, args, &filter, tz).await?,
+                    args.clock.now(),
+                    tz,
+                )
+                .render()?,
+                (Some(template), _) => {
+                    let sections = [Section::Senders, Section::Domains, Section::Trend];
+                    let overview =
+                        gather_overview(pool, &sections, args.top, &filter, args.by, tz).await?;
+                    let context = template::ReportContext::new(
+                        &overview,
+                        template::latest_run(pool).await?,
+                        args.clock.now(),
+                        tz,
+                    );
+                    template::render(template, &context, tz)?
+                }
+                (None, OutputFormat::Text) => {
+                    let top = top_senders(pool, args.top, &filter, args.by).await?;
+                    let style = Style::detect(args.no_color);
+                    let mut report = render_top_senders(&top, style);
+                    if args.by_account {
+                        let senders = top
+                            .rows
+                            .iter()
+                            .map(|row| row.sender.clone())
+                            .collect::<Vec<_>>();
+                        let counts = accounts::counts(pool, &senders).await?;
+                        report.push('\n');
+                        report.push_str(&accounts::render(
+                            &top.rows,
+                            &accounts::accounts(pool).await?,
+                            &counts,
+                            style,
+                        ));
+                    }
+                    report
+                }
+                (None, OutputFormat::Html) => html::Report::new(
+                    &overview(pool, args, &filter, tz).await?,
+                    args.clock.now(),
+                    tz,
+                )
+                .render()?,
+                (None, OutputFormat::Markdown) => {
+                    markdown::render(&overview(pool, args, &filter, tz).await?)
+                }
+            };
+            match &args.out {
+                Some(path) => {
+                    fs::write(path, report)?;
+                    if args.open {
+                        opener::open(path)?;
+                    }
+                }
+                None => print!("{}", report),
+            }
+            // The report is done by now, so a failed send only warns
+            if let Some(to) = &args.email_to {
+                match email_report(pool, args, &filter, tz, to).await {
+                    Ok(()) => eprintln!("emailed the report to {}", to),
+                    Err(err) => eprintln!("couldn't email the report to {}: {}", to, err),
+                }
+            }
+        }
+        Some(ReportView::When(when_args)) => {
+            let dates = when::message_dates(pool, when_args.sender.as_deref(), &scope).await?;
+            print!("{}", when::render(&when::Histograms::new(&dates, tz)));
+        }
+        Some(ReportView::Trend(trend_args)) => {
+            let since = trend_args
+                .since
+                .as_deref()
+                .map(dates::parse_date)
+                .transpose()?;
+            let before = trend_args
+                .before
+                .as_deref()
+                .map(dates::parse_date)
+                .transpose()?;
+            let filter = trend::Filter {
+                sender: trend_args.sender.clone(),
+                domain: trend_args.domain.clone(),
+                since: since.map(|date| dates::start_of_day(date, tz)),
+                before: before.map(|date| dates::start_of_day(date, tz)),
+                scope,
+            };
+            let dates = trend::message_dates(pool, &filter).await?;
+            let buckets = trend::buckets(&dates, trend_args.granularity, tz, since, before);
+            print!("{}", trend::render(&buckets));
+        }
+        Some(ReportView::Categories(categories_args)) => {
+            let counts = categories::sender_counts(pool, &scope).await?;
+            let summaries = categories::summarize(counts, categories_args.senders);
+            print!("{}", categories::render(&summaries));
+        }
+        Some(ReportView::Ignored(ignored_args)) => {
+            let min_count = scope.min_count.unwrap_or(ignored::MIN_COUNT);
+            let rows = ignored::least_read(pool, ignored_args.top, min_count, &scope).await?;
+            let fetched = ignored::fetch_span(pool).await?;
+            print!("{}", ignored::render(&rows, fetched));
+        }
+        Some(ReportView::Attachments(attachments_args)) => {
+            let senders = attachments::top_senders(pool, attachments_args.top, &scope).await?;
+            let types = attachments::by_type(pool).await?;
+            print!("{}", attachments::render(&senders, &types));
+        }
+        Some(ReportView::Engagement(engagement_args)) => {
+            let rows = engagement::reply_rates(pool, engagement_args.min_threads, &scope).await?;
+            print!("{}", engagement::render(&rows, engagement_args.top));
+        }
+        Some(ReportView::Latency(latency_args)) => {
+            let messages = latency::thread_messages(pool).await?;
+            let mut latencies = latency::latencies(&messages);
+            latencies.retain(|latency| scope.is_match(&latency.sender));
+            print!(
+                "{}",
+                latency::render(&latencies, latency_args.top, scope.min_count.unwrap_or(1))
+            );
+        }
+        Some(ReportView::Stale(stale_args)) => {
+            let cutoff = stale_args.inactive_for.before(args.clock.now())?;
+            let mut rows = stale::stale_senders(
+                pool,
+                cutoff.timestamp_millis(),
+                scope.min_count.unwrap_or(stale::MIN_COUNT),
+                stale_args.top,
+                &scope,
+            )
+            .await?;
+            stale::fill_peak_months(pool, &mut rows, tz).await?;
+            print!("{}", stale::render(&rows, cutoff));
+        }
+        Some(ReportView::Delta(delta_args)) => {
+            let Some(run) = delta::resolve_run(pool, delta_args.since_run).await? else {
+                println!("{}", delta::no_such_run(delta_args.since_run));
+                return Ok(());
+            };
+            let movers = delta::movers(pool, run.id, &scope).await?;
+            print!("{}", delta::render(&run, &movers, delta_args.top));
+        }
+        Some(ReportView::Internal(internal_args)) => {
+            let split = internal::split(pool).await?;
+            let external = top_senders(
+                pool,
+                internal_args.top,
+                &SenderFilter {
+                    external_only: true,
+                    scope,
+                    ..Default::default()
+                },
+                SortBy::Count,
+            )
+            .await?;
+            print!("{}", internal::render(&split, &external.rows, config));
+        }
+        Some(ReportView::New(new_args)) => {
+            let since = dates::start_of_day(dates::parse_date(&new_args.since)?, tz);
+            let before = match &new_args.before {
+                Some(before) => Some(dates::start_of_day(dates::parse_date(before)?, tz)),
+                None => None,
+            };
+            let rows = new::first_seen_between(pool, since, before, new_args.order, &scope).await?;
+            let earliest = new::earliest_message(pool).await?;
+            print!("{}", new::render(&rows, since, earliest));
+        }
+        Some(ReportView::Spam(spam_args)) => {
+            let top = spam::top_spam_senders(pool, spam_args.top, &scope).await?;
+            let overlap = spam::overlap(pool, spam_args.top, &scope).await?;
+            print!("{}", spam::render(&top, &overlap));
+        }
+        Some(ReportView::Distribution) => {
+            let counts = distribution::sender_counts(pool, &scope).await?;
+            print!(
+                "{}",
+                distribution::render(&distribution::distribution(&counts))
+            );
+        }
+        Some(ReportView::Subjects(subjects_args)) => {
+            if subjects_args.search.is_some() && !search::is_available(pool).await? {
+                anyhow::bail!(search::UNAVAILABLE);
+            }
+            let subjects = subjects::subjects(
+                pool,
+                subjects_args.sender.as_deref(),
+                subjects_args.search.as_deref(),
+                &scope,
+            )
+            .await?;
+            let counts = subjects::term_counts(&subjects);
+            print!(
+                "{}",
+                subjects::render(&counts, subjects.len(), subjects_args.top)
+            );
+        }
+        Some(ReportView::Storage(storage_args)) => {
+            let total = storage::total(pool, &scope).await?;
+            let categories = storage::by_category(pool, &scope).await?;
+            let labels = storage::by_label(pool, &scope).await?;
+            let largest = storage::largest_messages(pool, storage_args.top, &scope).await?;
+            print!(
+                "{}",
+                storage::render(&total, &categories, &labels, &largest)
+            );
+        }
+        Some(ReportView::Threads(threads_args)) => {
+            let threads =
+                threads::largest(pool, threads_args.kind, threads_args.top, &scope).await?;
+            print!("{}", threads::render(&threads));
+        }
+        Some(ReportView::Automated(automated_args)) => {
+            let kinds = automated::by_kind(pool).await?;
+            let senders = automated::top_senders(pool, automated_args.top, &scope).await?;
+            print!("{}", automated::render(&kinds, &senders));
+        }
+        Some(ReportView::Auth(auth_args)) => {
+            let overall = auth::overall(pool, &scope).await?;
+            let min_count = scope.min_count.unwrap_or(auth::MIN_COUNT);
+            let domains = auth::failing_domains(pool, min_count, auth_args.top, &scope).await?;
+            print!("{}", auth::render(&overall, &domains));
+        }
+        Some(ReportView::Lookalikes(lookalikes_args)) => {
+            let volumes = classes::domain_volumes(pool, &scope).await?;
+            let lookalikes =
+                lookalikes::find(&volumes, lookalikes_args.trusted, config.identity.domains());
+            print!("{}", lookalikes::render(&lookalikes));
+        }
+        Some(ReportView::Growth(growth_args)) => {
+            let now = args.clock.now();
+            let recent_start = growth_args.window.before(now)?;
+            let prior_start = growth_args.window.before(recent_start)?;
+            let rows = growth::growing_senders(
+                pool,
+                prior_start.timestamp_millis(),
+                recent_start.timestamp_millis(),
+                now.timestamp_millis(),
+                &scope,
+            )
+            .await?;
+            print!(
+                "{}",
+                growth::render(
+                    &growth::by_increase(&rows),
+                    &growth::by_ratio(&rows, scope.min_count.unwrap_or(growth::MIN_RECENT)),
+                    growth_args.top
+                )
+            );
+        }
+        Some(ReportView::Calendar(calendar_args)) => {
+            let organizers = calendar::organizers(pool, calendar_args.top, &scope).await?;
+            print!("{}", calendar::render(&organizers));
+        }
+        Some(ReportView::Duplicates) => {
+            let addresses = duplicates::addresses(pool, &scope).await?;
+            print!(
+                "{}",
+                duplicates::render(&duplicates::all_groups(&addresses))
+            );
+        }
+        Some(ReportView::Bounces(bounces_args)) => {
+            let bounces =
+                bounces::top_addresses(pool, NoticeKind::Bounce, bounces_args.top, &scope).await?;
+            let unattributed = bounces::unattributed_bounces(pool).await?;
+            let away =
+                bounces::top_addresses(pool, NoticeKind::OutOfOffice, bounces_args.top, &scope)
+                    .await?;
+            print!("{}", bounces::render(&bounces, unattributed, &away));
+        }
+        #[cfg(feature = "charts")]
+        Some(ReportView::Chart(chart_args)) => {
+            let points = match chart_args.kind {
+                cli::ChartKind::TopSenders => {
+                    let top = top_senders(pool, chart_args.top, &filter, args.by).await?;
+                    chart::sender_points(&top.rows)
+                }
+                cli::ChartKind::Trend => {
+                    let trend_filter = trend::Filter {
+                        scope,
+                        ..Default::default()
+                    };
+                    let dates = trend::message_dates(pool, &trend_filter).await?;
+                    let buckets = trend::buckets(&dates, Granularity::Month, tz, None, None);
+                    chart::month_points(&buckets)
+                }
+            };
+            chart::draw(
+                chart_args.kind,
+                &points,
+                &chart_args.out,
+                chart_args.width,
+                chart_args.height,
+            )?;
+        }
+        #[cfg(not(feature = "charts"))]
+        Some(ReportView::Chart(_)) => {
+            return Err(cli::needs_feature("report chart", "charts"));
+        }
+        Some(ReportView::Digest(digest_args)) => {
+            let digest =
+                digest::gather(pool, digest_args.period, args.clock.now(), tz, &scope).await?;
+            print!("{}", digest::render(&digest, digest_args.format));
+            // The digest is printed by now, so a failed send only warns
+            if let Some(to) = &digest_args.email_to {
+                match email::send(digest::email(&digest, to)).await {
+                    Ok(()) => eprintln!("emailed the digest to {}", to),
+                    Err(err) => eprintln!("couldn't email the digest to {}: {}", to, err),
+                }
+            }
+        }
+        Some(ReportView::Aliases(aliases_args)) => {
+            let mut rows = arrivals::uses(pool, config, &scope).await?;
+            if aliases_args.unexpected {
+                rows.retain(|row| row.is_unexpected());
+            }
+            rows.truncate(aliases_args.top);
+            print!("{}", arrivals::render(&rows, config));
+        }
+        Some(ReportView::Errors(errors_args)) => {
+            let classes = errors::by_class(pool).await?;
+            let recent = errors::recent(pool, errors_args.top).await?;
+            print!("{}", errors::render(&classes, &recent));
+        }
+        Some(ReportView::Filters(filters_args)) => {
+            let mut candidates = filters::candidates(pool, filters_args.min_count, &scope).await?;
+            candidates.retain(|candidate| {
+                (filters_args.include_personal || candidate.is_automated())
+                    && (filters_args.include_replied || !candidate.replied)
+            });
+            match &filters_args.out {
+                Some(path) => {
+                    let xml = filters::to_xml(&candidates, &filters_args.label, args.clock.now());
+                    fs::write(path, xml)?;
+                    println!("wrote {} filters to {}", candidates.len(), path.display());
+                }
+                None => print!("{}", filters::render(&candidates, &filters_args.label)),
+            }
+        }
+        Some(ReportView::Classes) => {
+            let domains = classes::domain_volumes(pool, &scope).await?;
+            print!("{}", classes::render(&classes::summarize(domains)));
+        }
+        Some(ReportView::Forwarded(forwarded_args)) => {
+            let rows =
+                forwarded::senders(pool, forwarded_args.show, forwarded_args.top, &scope).await?;
+            print!("{}", forwarded::render(&rows, forwarded_args.show));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::cli::NewOrder;
+use crate::format;
+
+#[derive(Clone, Debug, FromRow)]
+pub struct NewSender {
+    pub sender: String,
+    pub mails_sent: i64,
+    pub first_seen: i64,
+}
+
+/// Senders whose first message is at or after `since` and before `before`.
+pub async fn first_seen_between(
+    pool: &Pool<Sqlite>,
+    since: i64,
+    before: Option<i64>,
+    order: NewOrder,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<NewSender>> {
+    let order_by = match order {
+        NewOrder::Count => "mails_sent DESC, first_seen, sender",
+        NewOrder::FirstSeen => "first_seen, sender",
+    };
+    let rows = sqlx::query_as::<_, NewSender>(&format!(
+        "SELECT sender, mails_sent, first_seen FROM senders
+        WHERE first_seen >= ? AND (? IS NULL OR first_seen < ?) AND NOT is_ignored AND {}
+        ORDER BY {}",
+        scope.condition("sender", Some("mails_sent")),
+        order_by
+    ))
+    .bind(since)
+    .bind(before)
+    .bind(before)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// The date of the oldest message the database knows about.
+pub async fn earliest_message(pool: &Pool<Sqlite>) -> anyhow::Result<Option<i64>> {
+    let (earliest,): (Option<i64>,) = sqlx::query_as("SELECT min(first_seen) FROM senders")
+        .fetch_one(pool)
+        .await?;
+    Ok(earliest)
+}
+
+pub fn render(rows: &[NewSender], since: i64, earliest: Option<i64>) -> String {
+    let mut out = String::new();
+
+    // If we've never fetched mail from before the window, every sender looks new
+    if earliest.is_none_or(|earliest| earliest > since) {
+        writeln!(
+            out,
+            "warning: the oldest stored message is from {}, after the start of the period, so \
+            \"new\" means first seen by this tool rather than first ever\n",
+            format::date(earliest)
+        )
+        .unwrap();
+    }
+
+    writeln!(out, "{:>10} {:>10}  sender", "mails", "first seen").unwrap();
+    for row in rows {
+        writeln!(
+            out,
+            "{:>10} {:>10}  {}",
+            format::thousands(row.mails_sent),
+            format::date(Some(row.first_seen)),
+            row.sender
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "\n{} new senders",
+        format::thousands(rows.len() as i64)
+    )
+    .unwrap();
+    out
+}
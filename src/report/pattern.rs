@@ -0,0 +1,261 @@
+use regex::{Regex, RegexBuilder};
+use sqlx::{Pool, Sqlite};
+
+/// A `--match` or `--exclude` pattern. Both ignore case.
+#[derive(Clone, Debug)]
+pub enum Pattern {
+    /// `*@*.substack.com`, matching the whole address, where `*` is any run of characters and
+    /// `?` any single one. Without either it's just the address.
+    Glob(String),
+    /// `/^news(letter)?@/`, matching anywhere in the address. SQLite has no regexes, so these
+    /// are matched against every known address up front and the hits used in SQL.
+    Regex(Regex, Vec<String>),
+}
+
+/// A pattern between slashes is a regex, anything else a glob.
+pub fn parse(pattern: &str) -> anyhow::Result<Pattern> {
+    let pattern = pattern.trim();
+    match pattern
+        .strip_prefix('/')
+        .and_then(|rest| rest.strip_suffix('/'))
+    {
+        Some(regex) => RegexBuilder::new(regex)
+            .case_insensitive(true)
+            .build()
+            .map(|regex| Pattern::Regex(regex, Vec::new()))
+            .map_err(|err| anyhow::anyhow!("invalid regex {}: {}", pattern, err)),
+        None if pattern.is_empty() => anyhow::bail!("empty sender pattern"),
+        None => Ok(Pattern::Glob(pattern.to_lowercase())),
+    }
+}
+
+/// The LIKE pattern for `glob`, to be used with `ESCAPE '\'`. LIKE's own wildcards `%` and `_`
+/// and the escape character are escaped so they only match themselves:
+/// `*_bot@*` -> `%\_bot@%`
+pub fn like(glob: &str) -> String {
+    let mut out = String::with_capacity(glob.len());
+    for c in glob.chars() {
+        match c {
+            '*' => out.push('%'),
+            '?' => out.push('_'),
+            '%' | '_' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// A SQL string literal
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+impl Pattern {
+    /// SQL condition true when the address in `column` matches.
+    pub fn sql(&self, column: &str) -> String {
+        match self {
+            Pattern::Glob(glob) => {
+                format!("lower({}) LIKE {} ESCAPE '\\'", column, quote(&like(glob)))
+            }
+            Pattern::Regex(_, hits) => format!(
+                "lower({}) IN (SELECT value FROM json_each({}))",
+                column,
+                quote(&serde_json::to_string(hits).unwrap())
+            ),
+        }
+    }
+}
+
+/// Which senders report views include, from `--match`, `--exclude` and `--min-count`.
+#[derive(Clone, Debug, Default)]
+pub struct SenderScope {
+    /// Senders must match one of these, if there are any.
+    matches: Vec<Pattern>,
+    /// Senders mustn't match any of these.
+    excludes: Vec<Pattern>,
+    /// Leave out rows with fewer messages than this. What counts as a row's messages is up to
+    /// the view, and some have their own default.
+    pub min_count: Option<i64>,
+}
+
+impl SenderScope {
+    pub async fn new(
+        pool: &Pool<Sqlite>,
+        matches: &[String],
+        excludes: &[String],
+        min_count: Option<i64>,
+    ) -> anyhow::Result<SenderScope> {
+        let mut scope = SenderScope {
+            matches: matches.iter().map(|p| parse(p)).collect::<Result<_, _>>()?,
+            excludes: excludes
+                .iter()
+                .map(|p| parse(p))
+                .collect::<Result<_, _>>()?,
+            min_count,
+        };
+        let has_regex = |patterns: &[Pattern]| {
+            patterns
+                .iter()
+                .any(|pattern| matches!(pattern, Pattern::Regex(..)))
+        };
+        if has_regex(&scope.matches) || has_regex(&scope.excludes) {
+            let addresses = addresses(pool).await?;
+            for pattern in scope.matches.iter_mut().chain(scope.excludes.iter_mut()) {
+                if let Pattern::Regex(regex, hits) = pattern {
+                    *hits = addresses
+                        .iter()
+                        .filter(|address| regex.is_match(address))
+                        .cloned()
+                        .collect();
+                }
+            }
+        }
+        Ok(scope)
+    }
+
+    /// SQL condition true when the address in `column` passes `--match` and `--exclude`.
+    pub fn patterns(&self, column: &str) -> String {
+        let mut conditions = vec!["1".to_string()];
+        if !self.matches.is_empty() {
+            let any = self
+                .matches
+                .iter()
+                .map(|pattern| pattern.sql(column))
+                .collect::<Vec<_>>();
+            conditions.push(format!("({})", any.join(" OR ")));
+        }
+        for pattern in &self.excludes {
+            conditions.push(format!("NOT {}", pattern.sql(column)));
+        }
+        conditions.join(" AND ")
+    }
+
+    /// SQL condition true when the address in `column` passes the patterns and has at least
+    /// `--min-count` messages, counted by the `count` expression or else from the senders
+    /// table.
+    pub fn condition(&self, column: &str, count: Option<&str>) -> String {
+        let patterns = self.patterns(column);
+        match (self.min_count, count) {
+            (None, _) => patterns,
+            (Some(min_count), Some(count)) => {
+                format!("{} AND {} >= {}", patterns, count, min_count)
+            }
+            (Some(min_count), None) => format!(
+                "{} AND lower({}) IN (SELECT lower(sender) FROM senders WHERE mails_sent >= {})",
+                patterns, column, min_count
+            ),
+        }
+    }
+
+    /// Whether `address` passes `--match` and `--exclude`, for views which filter in Rust.
+    pub fn is_match(&self, address: &str) -> bool {
+        let address = address.to_lowercase();
+        let matches = |pattern: &Pattern| match pattern {
+            Pattern::Glob(glob) => glob_matches(glob, &address),
+            Pattern::Regex(regex, _) => regex.is_match(&address),
+        };
+        (self.matches.is_empty() || self.matches.iter().any(matches))
+            && !self.excludes.iter().any(matches)
+    }
+}
+
+fn glob_matches(glob: &str, address: &str) -> bool {
+    crate::ignore::glob_matches(
+        &glob.chars().collect::<Vec<_>>(),
+        &address.chars().collect::<Vec<_>>(),
+    )
+}
+
+// Every address a view might list, lowercased
+async fn addresses(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<String>> {
+    let rows: Vec<(String,)> = sqlx::query_as(
+        "SELECT lower(sender) FROM senders
+        UNION SELECT lower(sender) FROM spam_senders
+        UNION SELECT canonical FROM sender_aliases
+        UNION SELECT lower(calendar_organizer) FROM messages WHERE calendar_organizer IS NOT NULL
+        UNION SELECT lower(notice_address) FROM messages WHERE notice_address IS NOT NULL",
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|(address,)| address).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testsupport;
+
+    #[test]
+    fn globs_become_like_patterns() {
+        assert_eq!(like("*@*.substack.com"), "%@%.substack.com");
+        assert_eq!(like("news?@example.com"), "news_@example.com");
+        assert_eq!(like("jane@example.com"), "jane@example.com");
+    }
+
+    #[test]
+    fn like_wildcards_only_match_themselves() {
+        assert_eq!(like("*_bot@*"), "%\\_bot@%");
+        assert_eq!(like("100%@example.com"), "100\\%@example.com");
+        assert_eq!(like("back\\slash@*"), "back\\\\slash@%");
+    }
+
+    #[test]
+    fn slashes_make_a_regex_and_anything_else_a_glob() {
+        let Pattern::Regex(regex, hits) = parse(" /^News(letter)?@/ ").unwrap() else {
+            panic!("not a regex");
+        };
+        assert!(regex.is_match("newsletter@example.com"));
+        assert!(regex.is_match("NEWS@example.com"));
+        assert!(hits.is_empty());
+
+        assert!(matches!(
+            parse(" *@Example.COM ").unwrap(),
+            Pattern::Glob(glob) if glob == "*@example.com"
+        ));
+        // A lone slash isn't between two
+        assert!(matches!(parse("/").unwrap(), Pattern::Glob(glob) if glob == "/"));
+    }
+
+    #[test]
+    fn bad_patterns_are_errors() {
+        assert!(parse("").is_err());
+        assert!(parse("   ").is_err());
+        let err = parse("/(unclosed/").unwrap_err();
+        assert!(err.to_string().starts_with("invalid regex /(unclosed/"));
+    }
+
+    #[tokio::test]
+    async fn escaped_wildcards_match_only_themselves_in_sqlite() {
+        let pool = testsupport::pool().await;
+        let matching = |pattern: &str| {
+            let sql = format!(
+                "SELECT address FROM (
+                    SELECT 'a_bot@example.com' AS address
+                    UNION SELECT 'axbot@example.com'
+                    UNION SELECT '100%@example.com'
+                    UNION SELECT '100x@example.com'
+                    UNION SELECT 'it''s@example.com'
+                ) WHERE {} ORDER BY address",
+                parse(pattern).unwrap().sql("address")
+            );
+            let pool = pool.clone();
+            async move {
+                sqlx::query_scalar::<_, String>(&sql)
+                    .fetch_all(&pool)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        assert_eq!(matching("*_bot@*").await, ["a_bot@example.com"]);
+        assert_eq!(matching("100%@*").await, ["100%@example.com"]);
+        assert_eq!(
+            matching("100?@*").await,
+            ["100%@example.com", "100x@example.com"]
+        );
+        assert_eq!(matching("it's@*").await, ["it's@example.com"]);
+    }
+}
@@ -0,0 +1,443 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use askama::Template;
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request, Response, Server, StatusCode};
+use serde::Serialize;
+use sqlx::{Pool, Sqlite};
+
+use super::{gather_overview, html, timezone, top_senders, trend, SenderFilter};
+use crate::cli::{ServeArgs, SortBy};
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::dates::{self, Granularity};
+
+const DEFAULT_TOP: usize = 50;
+
+/// The most senders one request can ask for.
+const MAX_TOP: usize = 1000;
+
+// What every request needs. The server only ever reads the stats database: nothing from the
+// Gmail API or its tokens is loaded, so nothing can leak through it.
+#[derive(Clone)]
+struct State {
+    pool: Pool<Sqlite>,
+    tz: Tz,
+    clock: Clock,
+}
+
+// Why a request didn't get its page
+enum Failure {
+    BadRequest(String),
+    Internal(anyhow::Error),
+}
+
+impl From<anyhow::Error> for Failure {
+    fn from(err: anyhow::Error) -> Failure {
+        Failure::Internal(err)
+    }
+}
+
+/// Serve until killed: the HTML report at `/`, and JSON at `/api/senders` and `/api/trend`.
+pub async fn run(pool: &Pool<Sqlite>, args: &ServeArgs, config: &Config) -> anyhow::Result<()> {
+    let tz = timezone(args.timezone.as_deref(), config)?;
+
+    let state = State {
+        pool: pool.clone(),
+        tz,
+        clock: args.clock,
+    };
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(respond(&state, request).await) }
+            }))
+        }
+    });
+    let server = Server::try_bind(&SocketAddr::new(args.bind, args.port))?.serve(make_service);
+    if !args.bind.is_loopback() {
+        eprintln!(
+            "warning: listening on {}, so anyone who can reach this machine can read your stats",
+            args.bind
+        );
+    }
+    println!("serving on http://{}", server.local_addr());
+    server.await?;
+    Ok(())
+}
+
+async fn respond(state: &State, request: Request<Body>) -> Response<Body> {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return text(StatusCode::METHOD_NOT_ALLOWED, "only GET is supported\n");
+    }
+    let query = parse_query(request.uri().query().unwrap_or(""));
+    let result = match request.uri().path() {
+        "/" => index(state, &query).await,
+        "/api/senders" => senders(state, &query).await,
+        "/api/trend" => trend(state, &query).await,
+        _ => return text(StatusCode::NOT_FOUND, "not found\n"),
+    };
+    match result {
+        Ok(response) => response,
+        Err(Failure::BadRequest(message)) => {
+            text(StatusCode::BAD_REQUEST, &format!("{}\n", message))
+        }
+        Err(Failure::Internal(err)) => {
+            eprintln!("error serving {}: {}", request.uri(), err);
+            text(StatusCode::INTERNAL_SERVER_ERROR, "internal error\n")
+        }
+    }
+}
+
+// GET /?top=50
+async fn index(state: &State, query: &[(String, String)]) -> Result<Response<Body>, Failure> {
+    let top = top_param(query)?;
+    let overview = gather_overview(
+        &state.pool,
+        &[],
+        top,
+        &SenderFilter::default(),
+        SortBy::Count,
+        state.tz,
+    )
+    .await?;
+    let page = html::Report::new(&overview, state.clock.now(), state.tz)
+        .render()
+        .map_err(anyhow::Error::from)?;
+    Ok(respond_with(
+        StatusCode::OK,
+        "text/html; charset=utf-8",
+        page,
+    ))
+}
+
+// GET /api/senders?top=50&by=count&no_bulk=true&external_only=true&direct_only=true
+async fn senders(state: &State, query: &[(String, String)]) -> Result<Response<Body>, Failure> {
+    let filter = SenderFilter {
+        no_bulk: flag_param(query, "no_bulk")?,
+        external_only: flag_param(query, "external_only")?,
+        direct_only: flag_param(query, "direct_only")?,
+        ..Default::default()
+    };
+    let by = enum_param(query, "by")?.unwrap_or(SortBy::Count);
+    let top = top_senders(&state.pool, top_param(query)?, &filter, by).await?;
+    json(&top)
+}
+
+// GET /api/trend?granularity=month&sender=a@b.com&domain=b.com&since=2024-01-01&before=2025-01-01
+async fn trend(state: &State, query: &[(String, String)]) -> Result<Response<Body>, Failure> {
+    let granularity = enum_param(query, "granularity")?.unwrap_or(Granularity::Month);
+    let date = |name| {
+        param(query, name)
+            .map(dates::parse_date)
+            .transpose()
+            .map_err(|err| Failure::BadRequest(err.to_string()))
+    };
+    let since = date("since")?;
+    let before = date("before")?;
+    let filter = trend::Filter {
+        sender: param(query, "sender").map(str::to_string),
+        domain: param(query, "domain").map(str::to_string),
+        since: since.map(|date| dates::start_of_day(date, state.tz)),
+        before: before.map(|date| dates::start_of_day(date, state.tz)),
+        ..Default::default()
+    };
+    let dates = trend::message_dates(&state.pool, &filter).await?;
+    json(&trend::buckets(
+        &dates,
+        granularity,
+        state.tz,
+        since,
+        before,
+    ))
+}
+
+// "a=1&b=x%40y.com" -> [("a", "1"), ("b", "x@y.com")]
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(name), decode(value))
+        })
+        .collect()
+}
+
+// Undo form encoding: `+` is a space and `%XX` a byte. Malformed escapes are kept as they are.
+fn decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let byte = s
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match byte {
+                    Some(byte) => {
+                        out.push(byte);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// The last non-empty value of `name`
+fn param<'a>(query: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    query
+        .iter()
+        .rev()
+        .find(|(key, value)| key == name && !value.is_empty())
+        .map(|(_, value)| value.as_str())
+}
+
+fn top_param(query: &[(String, String)]) -> Result<usize, Failure> {
+    match param(query, "top") {
+        None => Ok(DEFAULT_TOP),
+        Some(value) => match value.parse::<usize>() {
+            Ok(top) if (1..=MAX_TOP).contains(&top) => Ok(top),
+            _ => Err(Failure::BadRequest(format!(
+                "top must be a number from 1 to {}",
+                MAX_TOP
+            ))),
+        },
+    }
+}
+
+fn flag_param(query: &[(String, String)], name: &str) -> Result<bool, Failure> {
+    match param(query, name) {
+        None | Some("false" | "0") => Ok(false),
+        Some("true" | "1") => Ok(true),
+        Some(value) => Err(Failure::BadRequest(format!(
+            "{} must be true or false, not {:?}",
+            name, value
+        ))),
+    }
+}
+
+// A clap value enum by its command line name, like `by=recent`
+fn enum_param<T: ValueEnum>(query: &[(String, String)], name: &str) -> Result<Option<T>, Failure> {
+    param(query, name)
+        .map(|value| {
+            T::from_str(value, true).map_err(|_| {
+                let names = T::value_variants()
+                    .iter()
+                    .filter_map(|variant| variant.to_possible_value())
+                    .map(|possible| possible.get_name().to_string())
+                    .collect::<Vec<_>>();
+                Failure::BadRequest(format!(
+                    "{} must be one of {}, not {:?}",
+                    name,
+                    names.join(", "),
+                    value
+                ))
+            })
+        })
+        .transpose()
+}
+
+fn json(value: &impl Serialize) -> Result<Response<Body>, Failure> {
+    let body = serde_json::to_string(value).map_err(anyhow::Error::from)?;
+    Ok(respond_with(StatusCode::OK, "application/json", body))
+}
+
+fn text(status: StatusCode, body: &str) -> Response<Body> {
+    respond_with(status, "text/plain; charset=utf-8", body.to_string())
+}
+
+fn respond_with(status: StatusCode, content_type: &str, body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+    // Pages are built from the current database on every request
+    headers.insert(header::CACHE_CONTROL, "no-store".parse().unwrap());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use serde_json::Value;
+    use sqlx::Executor;
+
+    use super::*;
+    use crate::testsupport;
+
+    async fn state() -> State {
+        let pool = testsupport::pool().await;
+        pool.execute(include_str!("../../tests/fixtures/report.sql"))
+            .await
+            .unwrap();
+        State {
+            pool,
+            tz: Tz::UTC,
+            clock: Clock::Fixed(Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()),
+        }
+    }
+
+    // The status, Content-Type and body `state` answers `method uri` with
+    async fn get(state: &State, method: Method, uri: &str) -> (StatusCode, String, String) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap();
+        let response = respond(state, request).await;
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+        let status = response.status();
+        let content_type = response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (
+            status,
+            content_type,
+            String::from_utf8(body.to_vec()).unwrap(),
+        )
+    }
+
+    async fn json_at(state: &State, uri: &str) -> Value {
+        let (status, content_type, body) = get(state, Method::GET, uri).await;
+        assert_eq!(
+            (status, content_type.as_str()),
+            (StatusCode::OK, "application/json")
+        );
+        serde_json::from_str(&body).unwrap()
+    }
+
+    fn senders_in(top: &Value) -> Vec<&str> {
+        top["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row["sender"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn the_index_is_the_html_report() {
+        let state = state().await;
+        let (status, content_type, body) = get(&state, Method::GET, "/?top=2").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "text/html; charset=utf-8");
+        assert!(body.starts_with("<!DOCTYPE html>"), "{}", &body[..40]);
+        assert!(body.contains("news@shop.example.com"));
+        assert!(!body.contains("alerts@bank.example"));
+    }
+
+    #[tokio::test]
+    async fn senders_are_listed_as_json() {
+        let state = state().await;
+        let top = json_at(&state, "/api/senders?top=3").await;
+        assert_eq!(
+            senders_in(&top),
+            [
+                "news@shop.example.com",
+                "deals@promo.example.net",
+                "alerts@bank.example"
+            ]
+        );
+        assert_eq!(top["rows"][0]["mails_sent"], 9);
+        assert_eq!(top["rows"][0]["bulk_count"], 9);
+        assert_eq!(top["tail_senders"], 3);
+
+        let personal = json_at(&state, "/api/senders?no_bulk=true&top=2&by=recent").await;
+        assert_eq!(
+            senders_in(&personal),
+            ["jane@example.org", "bob|pipes@odd.example"]
+        );
+    }
+
+    #[tokio::test]
+    async fn the_trend_is_bucketed_by_the_query() {
+        let state = state().await;
+        let trend = json_at(
+            &state,
+            "/api/trend?granularity=month&domain=example.org&since=2024-03-01&before=2024-05-01",
+        )
+        .await;
+        assert_eq!(
+            trend,
+            serde_json::json!([
+                {"label": "2024-03", "count": 1},
+                {"label": "2024-04", "count": 2},
+            ])
+        );
+        let jane = json_at(&state, "/api/trend?sender=Jane+%3Cjane%40example.org%3E").await;
+        let total: i64 = jane
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|bucket| bucket["count"].as_i64().unwrap())
+            .sum();
+        assert_eq!(total, 3);
+    }
+
+    #[tokio::test]
+    async fn bad_parameters_are_rejected() {
+        let state = state().await;
+        for (uri, message) in [
+            ("/?top=0", "top must be a number from 1 to 1000"),
+            (
+                "/api/senders?top=many",
+                "top must be a number from 1 to 1000",
+            ),
+            ("/api/senders?no_bulk=yes", "no_bulk must be true or false"),
+            ("/api/senders?by=size", "by must be one of"),
+            (
+                "/api/trend?granularity=fortnight",
+                "granularity must be one of",
+            ),
+            ("/api/trend?since=last+week", ""),
+        ] {
+            let (status, content_type, body) = get(&state, Method::GET, uri).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+            assert_eq!(content_type, "text/plain; charset=utf-8");
+            assert!(body.starts_with(message), "{}: {}", uri, body);
+        }
+    }
+
+    #[tokio::test]
+    async fn only_known_pages_are_served_and_only_read() {
+        let state = state().await;
+        let (status, _, _) = get(&state, Method::GET, "/api/other").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _, _) = get(&state, Method::POST, "/api/senders").await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        let (status, _, _) = get(&state, Method::HEAD, "/api/senders").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[test]
+    fn queries_are_form_decoded() {
+        assert_eq!(
+            parse_query("a=1&&b=x%40y.com&c=two+words&d=%zz&e"),
+            [
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), "x@y.com".to_string()),
+                ("c".to_string(), "two words".to_string()),
+                ("d".to_string(), "%zz".to_string()),
+                ("e".to_string(), String::new()),
+            ]
+        );
+        // The last value given wins, and an empty one doesn't count
+        let query = parse_query("top=5&top=7&top=");
+        assert_eq!(param(&query, "top"), Some("7"));
+    }
+}
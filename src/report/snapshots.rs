@@ -0,0 +1,167 @@
+//! The reports rendered from the fixture mailbox in `tests/fixtures/report.sql`, compared with
+//! the golden files in `tests/snapshots`. Set `UPDATE_SNAPSHOTS=1` to rewrite the golden files
+//! after an intended change to the output.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use askama::Template;
+use chrono::{TimeZone, Utc};
+use chrono_tz::Tz;
+use sqlx::Executor;
+
+use super::pattern::SenderScope;
+use super::table::Style;
+use super::SenderFilter;
+use super::{
+    digest, distribution, filters, gather_overview, html, markdown, render_top_senders, template,
+    top_senders,
+};
+use crate::cli::{DigestFormat, DigestPeriod, Section, SortBy};
+use crate::clock::Clock;
+use crate::{testsupport, Storage};
+
+fn clock() -> Clock {
+    Clock::Fixed(Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap())
+}
+
+async fn fixture() -> Storage {
+    let pool = testsupport::pool().await;
+    pool.execute(include_str!("../../tests/fixtures/report.sql"))
+        .await
+        .unwrap();
+    pool
+}
+
+fn check(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(name);
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("{}: {} (UPDATE_SNAPSHOTS=1 writes it)", name, err));
+    assert!(
+        expected == actual,
+        "{} differs from its snapshot, UPDATE_SNAPSHOTS=1 rewrites it\n--- expected\n{}\n--- actual\n{}",
+        name,
+        expected,
+        actual
+    );
+}
+
+#[tokio::test]
+async fn top_senders_as_text() {
+    let pool = fixture().await;
+    let top = top_senders(&pool, 6, &SenderFilter::default(), SortBy::Count)
+        .await
+        .unwrap();
+    check("top_senders.txt", &render_top_senders(&top, Style::plain()));
+}
+
+#[tokio::test]
+async fn overview_as_markdown() {
+    let pool = fixture().await;
+    let overview = gather_overview(
+        &pool,
+        &[],
+        6,
+        &SenderFilter::default(),
+        SortBy::Count,
+        Tz::UTC,
+    )
+    .await
+    .unwrap();
+    check("overview.md", &markdown::render(&overview));
+}
+
+#[tokio::test]
+async fn overview_as_html() {
+    let pool = fixture().await;
+    let overview = gather_overview(
+        &pool,
+        &[],
+        6,
+        &SenderFilter::default(),
+        SortBy::Count,
+        Tz::UTC,
+    )
+    .await
+    .unwrap();
+    let page = html::Report::new(&overview, clock().now(), Tz::UTC)
+        .render()
+        .unwrap();
+    check("overview.html", &page);
+}
+
+#[tokio::test]
+async fn digest_as_text_and_markdown() {
+    let pool = fixture().await;
+    let digest = digest::gather(
+        &pool,
+        DigestPeriod::Month,
+        clock().now(),
+        Tz::UTC,
+        &SenderScope::default(),
+    )
+    .await
+    .unwrap();
+    check("digest.txt", &digest::render(&digest, DigestFormat::Text));
+    check(
+        "digest.md",
+        &digest::render(&digest, DigestFormat::Markdown),
+    );
+}
+
+#[tokio::test]
+async fn distribution_as_text() {
+    let pool = fixture().await;
+    let counts = distribution::sender_counts(&pool, &SenderScope::default())
+        .await
+        .unwrap();
+    check(
+        "distribution.txt",
+        &distribution::render(&distribution::distribution(&counts)),
+    );
+}
+
+#[tokio::test]
+async fn the_built_in_text_template() {
+    let pool = fixture().await;
+    let overview = gather_overview(
+        &pool,
+        &[Section::Senders, Section::Domains, Section::Trend],
+        6,
+        &SenderFilter::default(),
+        SortBy::Count,
+        Tz::UTC,
+    )
+    .await
+    .unwrap();
+    let context = template::ReportContext::new(
+        &overview,
+        template::latest_run(&pool).await.unwrap(),
+        clock().now(),
+        Tz::UTC,
+    );
+    check(
+        "template.txt",
+        &template::render("text", &context, Tz::UTC).unwrap(),
+    );
+}
+
+#[tokio::test]
+async fn filters_as_gmail_xml() {
+    let pool = fixture().await;
+    let candidates = filters::candidates(&pool, 2, &SenderScope::default())
+        .await
+        .unwrap();
+    // The label is escaped like any other value
+    check(
+        "filters.xml",
+        &filters::to_xml(&candidates, "Bulk & \"Promos\"", clock().now()),
+    );
+}
@@ -0,0 +1,94 @@
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::format;
+
+#[derive(Clone, Debug, FromRow)]
+pub struct SpamSender {
+    pub sender: String,
+    pub spam: i64,
+    /// Messages from the same sender outside of spam.
+    pub other: i64,
+    pub last_seen: Option<i64>,
+}
+
+pub async fn top_spam_senders(
+    pool: &Pool<Sqlite>,
+    limit: usize,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<SpamSender>> {
+    let rows = sqlx::query_as::<_, SpamSender>(&format!(
+        "SELECT p.sender, p.mails_sent AS spam, coalesce(s.mails_sent, 0) AS other, p.last_seen
+        FROM spam_senders p LEFT JOIN senders s ON s.sender = p.sender
+        WHERE {}
+        ORDER BY spam DESC, p.sender
+        LIMIT ?",
+        scope.condition("p.sender", Some("p.mails_sent"))
+    ))
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Senders with mail both in spam and outside it, i.e. possible misclassifications.
+pub async fn overlap(
+    pool: &Pool<Sqlite>,
+    limit: usize,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<SpamSender>> {
+    let rows = sqlx::query_as::<_, SpamSender>(&format!(
+        "SELECT p.sender, p.mails_sent AS spam, s.mails_sent AS other, p.last_seen
+        FROM spam_senders p JOIN senders s ON s.sender = p.sender
+        WHERE {}
+        ORDER BY min(p.mails_sent, s.mails_sent) DESC, p.sender
+        LIMIT ?",
+        scope.condition("p.sender", Some("p.mails_sent + s.mails_sent"))
+    ))
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+fn render_rows(out: &mut String, title: &str, rows: &[SpamSender]) {
+    writeln!(out, "{}", title).unwrap();
+    writeln!(
+        out,
+        "{:>8} {:>8} {:>10}  sender",
+        "spam", "other", "last spam"
+    )
+    .unwrap();
+    for row in rows {
+        writeln!(
+            out,
+            "{:>8} {:>8} {:>10}  {}",
+            format::thousands(row.spam),
+            format::thousands(row.other),
+            format::date(row.last_seen),
+            row.sender
+        )
+        .unwrap();
+    }
+}
+
+pub fn render(top: &[SpamSender], overlap: &[SpamSender]) -> String {
+    let mut out = String::new();
+    render_rows(&mut out, "top spam senders", top);
+    out.push('\n');
+    render_rows(
+        &mut out,
+        "in both spam and other mail (misclassification candidates)",
+        overlap,
+    );
+    if top.is_empty() {
+        writeln!(
+            out,
+            "\nno spam recorded, fetch with --include-spam-trash to collect it"
+        )
+        .unwrap();
+    }
+    out
+}
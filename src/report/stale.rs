@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use sqlx::{Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::dates::{self, Granularity};
+use crate::format;
+
+#[derive(Clone, Debug)]
+pub struct StaleSender {
+    pub sender: String,
+    pub mails_sent: i64,
+    pub last_seen: i64,
+    /// Busiest month and its message count, filled in from the messages table.
+    pub peak_month: Option<(String, i64)>,
+}
+
+/// `--min-count` unless given.
+pub const MIN_COUNT: i64 = 10;
+
+/// Senders with at least `min_count` messages whose last message predates `cutoff`.
+pub async fn stale_senders(
+    pool: &Pool<Sqlite>,
+    cutoff: i64,
+    min_count: i64,
+    limit: usize,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<StaleSender>> {
+    let rows: Vec<(String, i64, i64)> = sqlx::query_as(&format!(
+        "SELECT sender, mails_sent, last_seen FROM senders
+        WHERE last_seen < ? AND mails_sent >= ? AND NOT is_ignored AND {}
+        ORDER BY mails_sent DESC, sender
+        LIMIT ?",
+        scope.patterns("sender")
+    ))
+    .bind(cutoff)
+    .bind(min_count)
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(sender, mails_sent, last_seen)| StaleSender {
+            sender,
+            mails_sent,
+            last_seen,
+            peak_month: None,
+        })
+        .collect())
+}
+
+/// Messages are counted by the quarter hour in SQL and summed into months here, in the report's
+/// time zone. Every zone's offset from UTC is a whole number of quarter hours, so no quarter hour
+/// straddles two months.
+const SLOT_MILLIS: i64 = 15 * 60 * 1000;
+
+/// Fill in each row's busiest month, the earliest one on ties, from one query over all their
+/// messages.
+pub async fn fill_peak_months(
+    pool: &Pool<Sqlite>,
+    rows: &mut [StaleSender],
+    tz: Tz,
+) -> anyhow::Result<()> {
+    let senders = serde_json::to_string(&rows.iter().map(|row| &row.sender).collect::<Vec<_>>())?;
+    let slots: Vec<(String, i64, i64)> = sqlx::query_as(
+        "SELECT sender, internal_date / ?1 * ?1 AS slot, count(*) FROM messages
+        WHERE internal_date IS NOT NULL AND sender IN (SELECT value FROM json_each(?2))
+        GROUP BY sender, slot",
+    )
+    .bind(SLOT_MILLIS)
+    .bind(senders)
+    .fetch_all(pool)
+    .await?;
+    let mut months: BTreeMap<&str, BTreeMap<NaiveDate, i64>> = BTreeMap::new();
+    for (sender, slot, count) in &slots {
+        if let Some(local) = dates::local_date(*slot, tz) {
+            *months
+                .entry(sender.as_str())
+                .or_default()
+                .entry(Granularity::Month.bucket_start(local))
+                .or_default() += count;
+        }
+    }
+    for row in rows {
+        row.peak_month = months.remove(row.sender.as_str()).and_then(peak_month);
+    }
+    Ok(())
+}
+
+// The month with the most messages, the earliest one on ties
+fn peak_month(months: BTreeMap<NaiveDate, i64>) -> Option<(String, i64)> {
+    let peak = months.values().copied().max()?;
+    months
+        .into_iter()
+        .find(|(_, count)| *count == peak)
+        .map(|(month, count)| (Granularity::Month.label(month), count))
+}
+
+pub fn render(rows: &[StaleSender], cutoff: DateTime<Utc>) -> String {
+    let mut out = String::new();
+
+    writeln!(
+        out,
+        "senders with no mail since {}",
+        cutoff.format("%Y-%m-%d")
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "{:>10} {:>10} {:>16}  sender",
+        "mails", "last seen", "peak month"
+    )
+    .unwrap();
+    for row in rows {
+        let peak = match &row.peak_month {
+            Some((month, count)) => format!("{} ({})", month, format::thousands(*count)),
+            None => "-".to_string(),
+        };
+        writeln!(
+            out,
+            "{:>10} {:>10} {:>16}  {}",
+            format::thousands(row.mails_sent),
+            format::date(Some(row.last_seen)),
+            peak,
+            row.sender
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::db::{self, DEFAULT_ACCOUNT};
+    use crate::testsupport::{self, info};
+
+    fn millis(date: &str) -> i64 {
+        Utc.from_utc_datetime(&date.parse().unwrap())
+            .timestamp_millis()
+    }
+
+    fn stale(sender: &str) -> StaleSender {
+        StaleSender {
+            sender: sender.to_string(),
+            mails_sent: 0,
+            last_seen: 0,
+            peak_month: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn the_peak_month_is_in_the_reports_time_zone() {
+        let pool = testsupport::pool().await;
+        let mut conn = pool.acquire().await.unwrap();
+        for (id, sender, date) in [
+            ("m1", "jane@example.com", "2024-01-31T18:40:00"),
+            ("m2", "jane@example.com", "2024-01-31T18:50:00"),
+            ("m3", "jane@example.com", "2024-02-15T12:00:00"),
+            ("m4", "news@example.com", "2024-03-01T12:00:00"),
+            ("m5", "news@example.com", "2024-04-01T12:00:00"),
+        ] {
+            let message = info(id, sender, millis(date), &[]);
+            db::record_message(
+                &message,
+                DEFAULT_ACCOUNT,
+                None,
+                DateTime::UNIX_EPOCH,
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        }
+        drop(conn);
+        let mut rows = [
+            stale("jane@example.com"),
+            stale("news@example.com"),
+            stale("gone@example.com"),
+        ];
+
+        fill_peak_months(&pool, &mut rows, Tz::UTC).await.unwrap();
+        let peaks = rows
+            .iter()
+            .map(|row| row.peak_month.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            peaks,
+            [
+                Some(("2024-01".to_string(), 2)),
+                // A tie goes to the earlier month
+                Some(("2024-03".to_string(), 1)),
+                None
+            ]
+        );
+
+        // Half past midnight on the first of February in India, 18:50 is still January in UTC
+        fill_peak_months(&pool, &mut rows, Tz::Asia__Kolkata)
+            .await
+            .unwrap();
+        assert_eq!(rows[0].peak_month, Some(("2024-02".to_string(), 3)));
+    }
+}
@@ -0,0 +1,160 @@
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::{categories::UNCATEGORIZED, pattern::SenderScope};
+use crate::format;
+
+#[derive(Clone, Debug, FromRow)]
+pub struct Usage {
+    /// A label ID, or a lowercase category name.
+    pub name: String,
+    pub messages: i64,
+    pub bytes: i64,
+}
+
+#[derive(Clone, Debug, FromRow)]
+pub struct LargeMessage {
+    pub sender: String,
+    pub subject: Option<String>,
+    pub internal_date: Option<i64>,
+    pub size_estimate: i64,
+}
+
+/// Every stored message counted once.
+pub async fn total(pool: &Pool<Sqlite>, scope: &SenderScope) -> anyhow::Result<Usage> {
+    let (messages, bytes): (i64, Option<i64>) = sqlx::query_as(&format!(
+        "SELECT count(*), sum(size_estimate) FROM messages WHERE {}",
+        scope.condition("sender", None)
+    ))
+    .fetch_one(pool)
+    .await?;
+    Ok(Usage {
+        name: "total".to_string(),
+        messages,
+        bytes: bytes.unwrap_or_default(),
+    })
+}
+
+/// Usage per label other than the categories, largest first. A message counts toward each of
+/// its labels.
+pub async fn by_label(pool: &Pool<Sqlite>, scope: &SenderScope) -> anyhow::Result<Vec<Usage>> {
+    let rows = sqlx::query_as::<_, Usage>(&format!(
+        "SELECT l.label AS name, count(*) AS messages, sum(m.size_estimate) AS bytes
+        FROM message_labels l JOIN messages m ON m.account = l.account AND m.mail_id = l.mail_id
+        WHERE l.label NOT LIKE 'CATEGORY\\_%' ESCAPE '\\' AND {}
+        GROUP BY l.label
+        ORDER BY bytes DESC, l.label",
+        scope.condition("m.sender", None)
+    ))
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Usage per Gmail category, largest first. Each message is in at most one category, the rest
+/// are uncategorized, so these add up to the total.
+pub async fn by_category(pool: &Pool<Sqlite>, scope: &SenderScope) -> anyhow::Result<Vec<Usage>> {
+    let rows = sqlx::query_as::<_, Usage>(&format!(
+        "SELECT category AS name, count(*) AS messages, sum(size_estimate) AS bytes FROM (
+            SELECT m.size_estimate, coalesce(
+                (SELECT lower(substr(l.label, 10)) FROM message_labels l
+                WHERE l.account = m.account AND l.mail_id = m.mail_id
+                    AND l.label LIKE 'CATEGORY\\_%' ESCAPE '\\'
+                ORDER BY l.label LIMIT 1),
+                ?) AS category
+            FROM messages m
+            WHERE {}
+        )
+        GROUP BY category
+        ORDER BY bytes DESC, category",
+        scope.condition("m.sender", None)
+    ))
+    .bind(UNCATEGORIZED)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn largest_messages(
+    pool: &Pool<Sqlite>,
+    limit: usize,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<LargeMessage>> {
+    let rows = sqlx::query_as::<_, LargeMessage>(&format!(
+        "SELECT sender, subject, internal_date, size_estimate FROM messages
+        WHERE {}
+        ORDER BY size_estimate DESC, internal_date DESC
+        LIMIT ?",
+        scope.condition("sender", None)
+    ))
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+fn render_usage(out: &mut String, title: &str, rows: &[Usage], total: &Usage) {
+    writeln!(out, "{}", title).unwrap();
+    writeln!(out, "{:>10} {:>6} {:>10}  name", "bytes", "%", "mails").unwrap();
+    for row in rows {
+        writeln!(
+            out,
+            "{:>10} {:>5.1}% {:>10}  {}",
+            format::bytes(row.bytes),
+            if total.bytes > 0 {
+                row.bytes as f64 * 100.0 / total.bytes as f64
+            } else {
+                0.0
+            },
+            format::thousands(row.messages),
+            row.name
+        )
+        .unwrap();
+    }
+}
+
+pub fn render(
+    total: &Usage,
+    categories: &[Usage],
+    labels: &[Usage],
+    largest: &[LargeMessage],
+) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{} in {} messages, each counted once\n",
+        format::bytes(total.bytes),
+        format::thousands(total.messages)
+    )
+    .unwrap();
+
+    render_usage(
+        &mut out,
+        "by category (adds up to the total)",
+        categories,
+        total,
+    );
+    out.push('\n');
+    render_usage(
+        &mut out,
+        "by label (messages with several labels count toward each)",
+        labels,
+        total,
+    );
+
+    writeln!(out, "\nlargest messages").unwrap();
+    writeln!(out, "{:>10} {:>10}  sender / subject", "bytes", "date").unwrap();
+    for message in largest {
+        writeln!(
+            out,
+            "{:>10} {:>10}  {}  {}",
+            format::bytes(message.size_estimate),
+            format::date(message.internal_date),
+            message.sender,
+            message.subject.as_deref().unwrap_or("-")
+        )
+        .unwrap();
+    }
+    out
+}
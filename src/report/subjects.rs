@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use sqlx::{Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::format;
+use crate::parse::SENT;
+use crate::search;
+
+/// Reply and forward markers stripped from the start of subjects, lowercase and without the colon.
+const PREFIXES: [&str; 6] = ["re", "fw", "fwd", "aw", "wg", "tr"];
+
+/// Words too common to say anything about a sender.
+const STOPWORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "has", "have", "in", "is",
+    "it", "its", "of", "on", "or", "our", "that", "the", "this", "to", "was", "we", "with", "you",
+    "your",
+];
+
+/// Subjects of received mail, optionally from one sender or matching a search of the subject
+/// index.
+pub async fn subjects(
+    pool: &Pool<Sqlite>,
+    sender: Option<&str>,
+    search: Option<&str>,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<String>> {
+    let rows: Vec<(String,)> = sqlx::query_as(&format!(
+        "SELECT subject FROM messages
+        WHERE subject IS NOT NULL AND (? IS NULL OR sender = ?) AND {}
+            AND (account, mail_id) NOT IN
+                (SELECT account, mail_id FROM message_labels WHERE label = ?) {}",
+        scope.condition("sender", None),
+        if search.is_some() {
+            "AND rowid IN (SELECT rowid FROM subjects_fts WHERE subjects_fts MATCH ?)"
+        } else {
+            ""
+        }
+    ))
+    .bind(sender)
+    .bind(sender)
+    .bind(SENT)
+    .bind(search.map(search::fts_query))
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|(subject,)| subject).collect())
+}
+
+// "Re: Fwd: re:Hello" -> "Hello"
+fn strip_prefixes(subject: &str) -> &str {
+    let mut subject = subject.trim_start();
+    while let Some((prefix, rest)) = subject.split_once(':') {
+        if !PREFIXES.contains(&prefix.trim().to_lowercase().as_str()) {
+            break;
+        }
+        subject = rest.trim_start();
+    }
+    subject
+}
+
+/// Lowercased words of a subject. Anything that isn't a letter or digit separates words, so
+/// punctuation and emoji drop out while accented and non-Latin words are kept whole. Stopwords,
+/// single characters and bare numbers are skipped.
+pub fn terms(subject: &str) -> Vec<String> {
+    strip_prefixes(subject)
+        .split(|c: char| !c.is_alphanumeric())
+        .map(|word| word.to_lowercase())
+        .filter(|word| word.chars().count() > 1)
+        .filter(|word| !word.chars().all(|c| c.is_numeric()))
+        .filter(|word| !STOPWORDS.contains(&word.as_str()))
+        .collect()
+}
+
+/// Every term and the number of subjects it appears in, most first. A term repeated within one
+/// subject counts once.
+pub fn term_counts(subjects: &[String]) -> Vec<(String, i64)> {
+    let mut counts: HashMap<String, i64> = HashMap::new();
+    for subject in subjects {
+        let mut terms = terms(subject);
+        terms.sort();
+        terms.dedup();
+        for term in terms {
+            *counts.entry(term).or_default() += 1;
+        }
+    }
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts
+}
+
+pub fn render(counts: &[(String, i64)], subjects: usize, top: usize) -> String {
+    let mut out = String::new();
+    if subjects == 0 {
+        writeln!(
+            out,
+            "no subjects recorded, they're only stored for mail fetched since this was added"
+        )
+        .unwrap();
+        return out;
+    }
+
+    writeln!(
+        out,
+        "most common words in {} subjects\n",
+        format::thousands(subjects as i64)
+    )
+    .unwrap();
+    writeln!(out, "{:>8} {:>6}  term", "subjects", "%").unwrap();
+    for (term, count) in counts.iter().take(top) {
+        writeln!(
+            out,
+            "{:>8} {:>5.1}%  {}",
+            format::thousands(*count),
+            *count as f64 * 100.0 / subjects as f64,
+            term
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Subjects a noisy sender might send, in a few languages
+    fn fixture() -> Vec<String> {
+        [
+            "Re: Fwd: Your invoice for March",
+            "RE:re: your INVOICE is ready 🎉",
+            "AW: Rechnung für März",
+            "WG: Rechnung für April",
+            "Fw: 請求書 の ご案内",
+            "🎉🎉 Big sale 🎉🎉",
+            "Invoice #12345 — thank you!",
+            "Re:",
+            "Résumé: café meetup",
+        ]
+        .map(str::to_string)
+        .to_vec()
+    }
+
+    #[test]
+    fn reply_and_forward_prefixes_are_stripped() {
+        assert_eq!(strip_prefixes("Re: Fwd: re:Hello"), "Hello");
+        assert_eq!(strip_prefixes("  AW:  WG: Termin"), "Termin");
+        assert_eq!(strip_prefixes("Re:"), "");
+        // Only known markers, so a colon in the subject itself stays
+        assert_eq!(strip_prefixes("Résumé: café meetup"), "Résumé: café meetup");
+        assert_eq!(strip_prefixes("Update: Re: build"), "Update: Re: build");
+    }
+
+    #[test]
+    fn words_split_on_punctuation_and_emoji_without_mangling_others() {
+        assert_eq!(
+            terms("Re: Fwd: Your invoice for March"),
+            ["invoice", "march"]
+        );
+        assert_eq!(terms("🎉🎉 Big sale 🎉🎉"), ["big", "sale"]);
+        assert_eq!(terms("AW: Rechnung für März"), ["rechnung", "für", "märz"]);
+        assert_eq!(terms("Résumé: café meetup"), ["résumé", "café", "meetup"]);
+        assert_eq!(terms("Fw: 請求書 の ご案内"), ["請求書", "ご案内"]);
+        // Bare numbers and single characters say nothing
+        assert_eq!(terms("Invoice #12345 — a b 2fa"), ["invoice", "2fa"]);
+        assert!(terms("Re:").is_empty());
+    }
+
+    #[test]
+    fn terms_are_counted_once_per_subject() {
+        let counts = term_counts(&fixture());
+
+        assert_eq!(
+            &counts[..4],
+            [
+                ("invoice".to_string(), 3),
+                // The stopwords are English only
+                ("für".to_string(), 2),
+                ("rechnung".to_string(), 2),
+                ("april".to_string(), 1)
+            ]
+        );
+        assert!(counts
+            .iter()
+            .all(|(term, _)| !STOPWORDS.contains(&term.as_str())));
+        assert_eq!(
+            term_counts(&["sale sale SALE".to_string()]),
+            [("sale".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn the_top_terms_are_rendered_as_shares_of_subjects() {
+        let subjects = fixture();
+        let out = render(&term_counts(&subjects), subjects.len(), 3);
+
+        assert_eq!(
+            out,
+            "most common words in 9 subjects
+
+subjects      %  term
+       3  33.3%  invoice
+       2  22.2%  für
+       2  22.2%  rechnung
+"
+        );
+        assert!(render(&[], 0, 2).starts_with("no subjects recorded"));
+    }
+}
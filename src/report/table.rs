@@ -0,0 +1,171 @@
+use std::io::IsTerminal;
+
+use comfy_table::{CellAlignment, ColumnConstraint, ContentArrangement, Table, Width};
+
+// Only a line under the header, in box-drawing characters or ASCII. See comfy_table::presets for
+// what each position means.
+const FANCY: &str = "     ──            ";
+const PLAIN: &str = "     --            ";
+
+/// Text columns are never narrowed past this.
+const MIN_TEXT_WIDTH: u16 = 16;
+
+/// How report tables are drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Style {
+    /// Width to fit tables into, truncating long cells. `None` never truncates.
+    pub width: Option<u16>,
+    /// Color and box-drawing characters rather than plain ASCII.
+    pub fancy: bool,
+}
+
+impl Style {
+    /// Fit the terminal and use color when stdout is one, unless `no_color` or the `NO_COLOR`
+    /// environment variable is set. Anything else, like a pipe, gets plain ASCII at full width.
+    pub fn detect(no_color: bool) -> Style {
+        if !std::io::stdout().is_terminal() {
+            return Style::plain();
+        }
+        let no_color =
+            no_color || std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        Style {
+            // A fresh table reports the terminal's width
+            width: Table::new().width(),
+            fancy: !no_color,
+        }
+    }
+
+    pub fn plain() -> Style {
+        Style {
+            width: None,
+            fancy: false,
+        }
+    }
+}
+
+/// An empty table with `header`. The last `text_columns` columns are left-aligned and give up
+/// space first when the table is too wide; the rest are numbers, right-aligned and never
+/// wrapped.
+pub fn new(style: Style, header: &[&str], text_columns: usize) -> Table {
+    let mut table = Table::new();
+    table
+        .load_preset(if style.fancy { FANCY } else { PLAIN })
+        .set_truncation_indicator(if style.fancy { "\u{2026}" } else { "..." })
+        .set_header(header.to_vec())
+        // Styling and width come from `style` rather than comfy-table's own detection
+        .force_no_tty();
+    if style.fancy {
+        table.enforce_styling();
+    }
+    if let Some(width) = style.width {
+        table
+            .set_width(width)
+            .set_content_arrangement(ContentArrangement::Dynamic);
+    }
+
+    let numbers = header.len().saturating_sub(text_columns);
+    for (i, column) in table.column_iter_mut().enumerate() {
+        // The preset's blank vertical lines already separate columns
+        column.set_padding((0, 0));
+        if i < numbers {
+            column.set_cell_alignment(CellAlignment::Right);
+            column.set_constraint(ColumnConstraint::ContentWidth);
+        } else {
+            // On a narrow terminal it's better to overflow than to squeeze text to nothing
+            column.set_constraint(ColumnConstraint::LowerBoundary(Width::Fixed(
+                MIN_TEXT_WIDTH,
+            )));
+        }
+    }
+    table
+}
+
+/// `table` as a string ending in a newline, with one line per row: cells that don't fit are cut
+/// short with an ellipsis rather than wrapped.
+pub fn render(table: &mut Table) -> String {
+    for row in table.row_iter_mut() {
+        row.max_height(1);
+    }
+    let mut out = String::new();
+    for line in table.lines() {
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn senders(style: Style) -> String {
+        let mut table = new(style, &["mails", "bytes", "sender"], 1);
+        table.add_row(vec![
+            "1,234",
+            "5.6 MB",
+            "newsletters-and-announcements@mail.example-long-domain.com",
+        ]);
+        table.add_row(vec!["7", "12 KB", "jane@example.com"]);
+        render(&mut table)
+    }
+
+    #[test]
+    fn without_a_width_nothing_is_cut() {
+        assert_eq!(
+            senders(Style::plain()),
+            "\
+mails  bytes sender
+-----------------------------------------------------------------------
+1,234 5.6 MB newsletters-and-announcements@mail.example-long-domain.com
+    7  12 KB jane@example.com
+"
+        );
+    }
+
+    #[test]
+    fn long_text_is_cut_to_the_width() {
+        let narrow = Style {
+            width: Some(40),
+            fancy: false,
+        };
+        assert_eq!(
+            senders(narrow),
+            "\
+mails  bytes sender
+----------------------------------------
+1,234 5.6 MB newsletters-and-announce...
+    7  12 KB jane@example.com
+"
+        );
+        assert_eq!(
+            senders(Style {
+                fancy: true,
+                ..narrow
+            }),
+            "\
+mails  bytes sender
+────────────────────────────────────────
+1,234 5.6 MB newsletters-and-announceme…
+    7  12 KB jane@example.com
+"
+        );
+    }
+
+    #[test]
+    fn text_columns_keep_their_minimum_width() {
+        let out = senders(Style {
+            width: Some(10),
+            fancy: false,
+        });
+        assert_eq!(
+            out,
+            "\
+mails  bytes sender
+-----------------------------
+1,234 5.6 MB newsletters-a...
+    7  12 KB jane@example.com
+"
+        );
+        assert!(out.lines().all(|line| line.len() > 10));
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::error::Error as _;
+use std::fs;
+use std::path::Path;
+
+use chrono::{DateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{FromRow, Pool, Sqlite};
+use tera::{Context, Tera};
+
+use super::{classes, percent, trend, Overview, SenderRow};
+use crate::format;
+
+/// Templates shipped with gmail_stats, chosen with `--template NAME`.
+const BUILT_IN: [(&str, &str, &str); 1] = [(
+    "text",
+    "report.txt.tera",
+    include_str!("../../templates/report.txt.tera"),
+)];
+
+/// The built-in HTML template, which is the page `--format html` renders rather than a Tera
+/// template of its own, so there's one HTML report to keep up.
+pub const HTML: &str = "html";
+
+/// One sender as templates see it: every column of the senders table plus its share of all
+/// mail.
+#[derive(Clone, Debug, Serialize)]
+pub struct SenderContext {
+    #[serde(flatten)]
+    pub row: SenderRow,
+    /// Percentage of every sender's messages, ignored senders aside.
+    pub percent: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Totals {
+    /// Messages from every sender, including those hidden by filters.
+    pub messages: i64,
+    /// Senders matching the filters, whether or not they made the top N.
+    pub senders: i64,
+    /// Senders which matched the filters but didn't make the top N.
+    pub tail_senders: i64,
+    /// The most messages any one of the tail senders has sent.
+    pub tail_max: i64,
+}
+
+/// The latest fetch.
+#[derive(Clone, Debug, FromRow, Serialize)]
+pub struct RunContext {
+    pub id: i64,
+    /// Epoch milliseconds.
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub status: String,
+    /// New messages it recorded.
+    pub messages: i64,
+    /// Messages from ignored senders it skipped.
+    pub ignored: i64,
+    /// Messages it passed over because Gmail's response for them was malformed.
+    pub skipped: i64,
+    /// Messages from my own addresses it left uncounted.
+    pub self_excluded: i64,
+}
+
+/// Everything a report template can use. The README documents it; renaming a field breaks
+/// people's templates.
+#[derive(Clone, Debug, Serialize)]
+pub struct ReportContext {
+    /// When the report was made, in the report's timezone.
+    pub generated_at: String,
+    pub timezone: String,
+    pub totals: Totals,
+    pub senders: Vec<SenderContext>,
+    pub domains: Vec<classes::DomainVolume>,
+    /// Messages per month.
+    pub trend: Vec<trend::Bucket>,
+    /// `None` before the first fetch.
+    pub run: Option<RunContext>,
+}
+
+pub async fn latest_run(pool: &Pool<Sqlite>) -> anyhow::Result<Option<RunContext>> {
+    Ok(sqlx::query_as::<_, RunContext>(
+        "SELECT id, started_at, finished_at, CAST(status AS TEXT) AS status, messages, ignored,
+            skipped, self_excluded
+        FROM runs ORDER BY id DESC LIMIT 1",
+    )
+    .fetch_optional(pool)
+    .await?)
+}
+
+impl ReportContext {
+    pub fn new(
+        overview: &Overview,
+        run: Option<RunContext>,
+        generated: DateTime<Utc>,
+        tz: Tz,
+    ) -> ReportContext {
+        let top = overview.senders.clone().unwrap_or_default();
+        ReportContext {
+            generated_at: generated
+                .with_timezone(&tz)
+                .format("%Y-%m-%d %H:%M %Z")
+                .to_string(),
+            timezone: tz.name().to_string(),
+            totals: Totals {
+                messages: top.total_messages,
+                senders: top.rows.len() as i64 + top.tail_senders,
+                tail_senders: top.tail_senders,
+                tail_max: top.tail_max,
+            },
+            senders: top
+                .rows
+                .iter()
+                .map(|row| SenderContext {
+                    row: row.clone(),
+                    percent: percent(row.mails_sent, top.total_messages),
+                })
+                .collect(),
+            domains: overview.domains.clone().unwrap_or_default(),
+            trend: overview.trend.clone().unwrap_or_default(),
+            run,
+        }
+    }
+}
+
+fn integer(value: &Value, filter: &str) -> tera::Result<i64> {
+    value
+        .as_i64()
+        .or_else(|| value.as_f64().map(|n| n as i64))
+        .ok_or_else(|| tera::Error::msg(format!("{} needs a number, not {}", filter, value)))
+}
+
+// Filters for the formatting the built-in reports use, since Tera's own date and file size
+// filters aren't compiled in
+fn register_filters(tera: &mut Tera, tz: Tz) {
+    tera.register_filter("thousands", |value: &Value, _: &HashMap<String, Value>| {
+        Ok(Value::from(format::thousands(integer(value, "thousands")?)))
+    });
+    tera.register_filter("bytes", |value: &Value, _: &HashMap<String, Value>| {
+        Ok(Value::from(format::bytes(integer(value, "bytes")?)))
+    });
+    tera.register_filter("date", move |value: &Value, _: &HashMap<String, Value>| {
+        if value.is_null() {
+            return Ok(Value::from("-"));
+        }
+        let date = tz
+            .timestamp_millis_opt(integer(value, "date")?)
+            .single()
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .unwrap_or_else(|| "-".to_string());
+        Ok(Value::from(date))
+    });
+}
+
+// Tera reports the template and what it was doing at the top of the chain and the mistake,
+// with its line and column for syntax errors, at the bottom
+fn describe(err: &tera::Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        message.push_str(":\n");
+        message.push_str(err.to_string().trim_end());
+        source = err.source();
+    }
+    message
+}
+
+/// Render `template`, the name of a built-in Tera template or the path of one, with
+/// `context`. Templates whose names end in `.html`, `.htm` or `.xml`, with or without a
+/// trailing `.tera`, have their values escaped.
+pub fn render(template: &str, context: &ReportContext, tz: Tz) -> anyhow::Result<String> {
+    let (name, source) = match BUILT_IN.iter().find(|(short, _, _)| *short == template) {
+        Some((_, name, source)) => (name.to_string(), source.to_string()),
+        None => {
+            let path = Path::new(template);
+            let source = fs::read_to_string(path).map_err(|err| {
+                anyhow::anyhow!(
+                    "{} isn't a built-in template ({}) or a readable file: {}",
+                    template,
+                    BUILT_IN
+                        .iter()
+                        .map(|(short, _, _)| *short)
+                        .chain([HTML])
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    err
+                )
+            })?;
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| template.to_string());
+            (name, source)
+        }
+    };
+
+    let mut tera = Tera::default();
+    tera.autoescape_on(vec![
+        ".html",
+        ".htm",
+        ".xml",
+        ".html.tera",
+        ".htm.tera",
+        ".xml.tera",
+    ]);
+    register_filters(&mut tera, tz);
+    tera.add_raw_template(&name, &source)
+        .map_err(|err| anyhow::anyhow!("{}", describe(&err)))?;
+    let context = Context::from_serialize(context)?;
+    tera.render(&name, &context)
+        .map_err(|err| anyhow::anyhow!("{}", describe(&err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::TopSenders;
+
+    fn sender(sender: &str, mails_sent: i64, last_seen: Option<i64>) -> SenderRow {
+        SenderRow {
+            sender: sender.to_string(),
+            mails_sent,
+            bulk_count: 0,
+            unread_count: 0,
+            direct_count: 0,
+            cc_count: 0,
+            auto_count: 0,
+            bytes: mails_sent * 1024,
+            last_seen,
+        }
+    }
+
+    fn overview() -> Overview {
+        Overview {
+            senders: Some(TopSenders {
+                rows: vec![
+                    sender("news@example.com", 1500, Some(1_714_564_800_000)),
+                    sender("<b>@example.org", 500, None),
+                ],
+                total_messages: 4000,
+                tail_senders: 3,
+                tail_max: 20,
+            }),
+            domains: Some(vec![classes::DomainVolume {
+                domain: "example.com".to_string(),
+                class: "direct".to_string(),
+                senders: 2,
+                messages: 1500,
+                bytes: 1024,
+            }]),
+            trend: Some(vec![trend::Bucket {
+                label: "2024-05".to_string(),
+                count: 2000,
+            }]),
+            hours: None,
+        }
+    }
+
+    fn context(run: Option<RunContext>) -> ReportContext {
+        let generated = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        ReportContext::new(&overview(), run, generated, chrono_tz::America::New_York)
+    }
+
+    // A template file of its own for each test
+    fn template_file(name: &str, source: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("gmail-stats-template-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, source).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn the_context_holds_the_overview_and_totals() {
+        let context = context(None);
+
+        assert_eq!(context.generated_at, "2024-05-01 08:00 EDT");
+        assert_eq!(context.timezone, "America/New_York");
+        assert_eq!(context.totals.messages, 4000);
+        // Those shown and those that didn't make the top
+        assert_eq!(context.totals.senders, 5);
+        assert_eq!(context.totals.tail_max, 20);
+        assert_eq!(context.senders.len(), 2);
+        assert_eq!(context.senders[0].percent, 37.5);
+        assert_eq!(context.senders[1].percent, 12.5);
+        assert_eq!(context.domains[0].domain, "example.com");
+        assert_eq!(context.trend[0].count, 2000);
+        assert!(context.run.is_none());
+    }
+
+    #[test]
+    fn senders_are_flattened_for_templates() {
+        let value = serde_json::to_value(context(None)).unwrap();
+
+        let first = &value["senders"][0];
+        assert_eq!(first["sender"], "news@example.com");
+        assert_eq!(first["mails_sent"], 1500);
+        assert_eq!(first["percent"], 37.5);
+        assert_eq!(value["senders"][1]["last_seen"], Value::Null);
+        assert_eq!(value["run"], Value::Null);
+    }
+
+    #[test]
+    fn the_text_template_uses_every_part_of_the_context() {
+        let run = RunContext {
+            id: 7,
+            started_at: 1_714_564_800_000,
+            finished_at: None,
+            status: "complete".to_string(),
+            messages: 1234,
+            ignored: 0,
+            skipped: 0,
+            self_excluded: 0,
+        };
+
+        let text = render("text", &context(Some(run)), Tz::UTC).unwrap();
+
+        assert!(text.starts_with("gmail_stats report, generated 2024-05-01 08:00 EDT\n"));
+        assert!(text.contains("4,000 messages from 5 senders, last fetched 2024-05-01 (1,234 new)"));
+        assert!(text.contains("1,500 (37.5%)  news@example.com, 1.5 MiB, last seen 2024-05-01\n"));
+        assert!(text.contains("500 (12.5%)  <b>@example.org, 500.0 KiB, last seen -\n"));
+        assert!(text.contains("plus 3 senders with at most 20 messages each"));
+        assert!(text.contains("1,500  example.com (direct, 2 senders)"));
+        assert!(text.contains("2024-05  2,000"));
+    }
+
+    #[test]
+    fn html_templates_are_escaped_and_others_arent() {
+        let source = "{% for s in senders %}{{ s.sender }};{% endfor %}";
+        let html = render(
+            &template_file("mine.html.tera", source),
+            &context(None),
+            Tz::UTC,
+        );
+        let text = render(&template_file("mine.tera", source), &context(None), Tz::UTC);
+
+        assert_eq!(html.unwrap(), "news@example.com;&lt;b&gt;@example.org;");
+        assert_eq!(text.unwrap(), "news@example.com;<b>@example.org;");
+    }
+
+    #[test]
+    fn a_template_mistake_says_where_it_is() {
+        let path = template_file(
+            "broken.tera",
+            "line one\n{{ totals.messages | thousands }\n",
+        );
+
+        let err = render(&path, &context(None), Tz::UTC)
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("broken.tera"), "{}", err);
+        assert!(err.contains("2:"), "{}", err);
+    }
+
+    #[test]
+    fn a_filter_given_the_wrong_value_says_so() {
+        let path = template_file("filter.tera", "{{ timezone | thousands }}");
+
+        let err = render(&path, &context(None), Tz::UTC)
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("thousands needs a number"), "{}", err);
+    }
+
+    #[test]
+    fn an_unknown_template_lists_the_built_in_ones() {
+        let err = render("fancy", &context(None), Tz::UTC)
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("(text, html)"), "{}", err);
+    }
+}
@@ -0,0 +1,86 @@
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::cli::ThreadKind;
+use crate::format;
+
+#[derive(Clone, Debug, FromRow)]
+pub struct Thread {
+    pub messages: i64,
+    /// Distinct senders in the thread, me included.
+    pub participants: i64,
+    pub first: Option<i64>,
+    pub last: Option<i64>,
+    /// Subject of the earliest message.
+    pub subject: Option<String>,
+}
+
+impl Thread {
+    /// Every message came from the same sender, usually reminders or notifications rather than
+    /// a conversation.
+    pub fn is_single_sender(&self) -> bool {
+        self.participants <= 1
+    }
+}
+
+/// The largest threads of the given kind, most messages first.
+pub async fn largest(
+    pool: &Pool<Sqlite>,
+    kind: ThreadKind,
+    limit: usize,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<Thread>> {
+    let having = match kind {
+        ThreadKind::All => "1",
+        ThreadKind::Conversations => "participants > 1",
+        ThreadKind::SingleSender => "participants = 1",
+    };
+    let rows = sqlx::query_as::<_, Thread>(&format!(
+        "SELECT count(*) AS messages, count(DISTINCT sender) AS participants,
+            min(internal_date) AS first, max(internal_date) AS last,
+            (SELECT f.subject FROM messages f WHERE f.thread_id = m.thread_id
+            ORDER BY f.internal_date IS NULL, f.internal_date LIMIT 1) AS subject
+        FROM messages m
+        WHERE thread_id IS NOT NULL AND NOT is_spam
+        GROUP BY thread_id
+        HAVING {} AND sum({}) > 0
+        ORDER BY messages DESC, last DESC, thread_id
+        LIMIT ?",
+        having,
+        scope.condition("sender", None)
+    ))
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub fn render(threads: &[Thread]) -> String {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{:>8} {:>6} {:>10} {:>10}  subject",
+        "mails", "people", "first", "last"
+    )
+    .unwrap();
+    for thread in threads {
+        writeln!(
+            out,
+            "{:>8} {:>6} {:>10} {:>10}  {}{}",
+            format::thousands(thread.messages),
+            format::thousands(thread.participants),
+            format::date(thread.first),
+            format::date(thread.last),
+            if thread.is_single_sender() {
+                "(one sender) "
+            } else {
+                ""
+            },
+            thread.subject.as_deref().unwrap_or("-")
+        )
+        .unwrap();
+    }
+    out
+}
@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use serde::Serialize;
+use sqlx::{Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::dates::{self, Granularity};
+use crate::export::ids::normalize;
+use crate::format;
+
+const BAR_WIDTH: usize = 40;
+
+/// Which messages to include in a trend.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    pub sender: Option<String>,
+    pub domain: Option<String>,
+    /// Inclusive lower bound in epoch milliseconds.
+    pub since: Option<i64>,
+    /// Exclusive upper bound in epoch milliseconds.
+    pub before: Option<i64>,
+    pub scope: SenderScope,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Bucket {
+    pub label: String,
+    pub count: i64,
+}
+
+/// The dates of the messages `filter` lets through. Its sender can be written any way a From
+/// header could hold it, as for `--sender`.
+pub async fn message_dates(pool: &Pool<Sqlite>, filter: &Filter) -> anyhow::Result<Vec<i64>> {
+    let sender = filter.sender.as_deref().map(normalize);
+    let dates: Vec<(i64,)> = sqlx::query_as(&format!(
+        "SELECT internal_date FROM messages
+        WHERE internal_date IS NOT NULL
+            AND (? IS NULL OR lower(sender) = ?)
+            AND (? IS NULL OR lower(substr(sender, instr(sender, '@') + 1)) = lower(?))
+            AND (? IS NULL OR internal_date >= ?)
+            AND (? IS NULL OR internal_date < ?)
+            AND {}",
+        filter.scope.condition("sender", None)
+    ))
+    .bind(&sender)
+    .bind(&sender)
+    .bind(&filter.domain)
+    .bind(&filter.domain)
+    .bind(filter.since)
+    .bind(filter.since)
+    .bind(filter.before)
+    .bind(filter.before)
+    .fetch_all(pool)
+    .await?;
+    Ok(dates.into_iter().map(|(date,)| date).collect())
+}
+
+/// Count `dates` per bucket, including empty buckets, from `since` (or the oldest date) up to
+/// `before` (or the newest date).
+pub fn buckets(
+    dates: &[i64],
+    granularity: Granularity,
+    tz: Tz,
+    since: Option<NaiveDate>,
+    before: Option<NaiveDate>,
+) -> Vec<Bucket> {
+    let mut counts = BTreeMap::new();
+    for date in dates {
+        if let Some(local) = dates::local_date(*date, tz) {
+            *counts.entry(granularity.bucket_start(local)).or_insert(0) += 1;
+        }
+    }
+
+    let first = since
+        .map(|since| granularity.bucket_start(since))
+        .or_else(|| counts.keys().next().copied());
+    let last = before
+        .and_then(|before| before.pred_opt())
+        .map(|last| granularity.bucket_start(last))
+        .or_else(|| counts.keys().next_back().copied());
+    let (Some(mut start), Some(last)) = (first, last) else {
+        return Vec::new();
+    };
+
+    let mut buckets = Vec::new();
+    while start <= last {
+        buckets.push(Bucket {
+            label: granularity.label(start),
+            count: counts.get(&start).copied().unwrap_or(0),
+        });
+        start = granularity.next(start);
+    }
+    buckets
+}
+
+// Percentage change versus the previous bucket, blank when there's nothing to compare against
+fn change(previous: Option<i64>, count: i64) -> String {
+    match previous {
+        Some(previous) if previous > 0 => {
+            format!(
+                "{:+.0}%",
+                (count - previous) as f64 * 100.0 / previous as f64
+            )
+        }
+        Some(_) if count > 0 => "new".to_string(),
+        _ => String::new(),
+    }
+}
+
+pub fn render(buckets: &[Bucket]) -> String {
+    let mut out = String::new();
+    let max = buckets.iter().map(|bucket| bucket.count).max().unwrap_or(0);
+
+    writeln!(out, "{:>10} {:>8} {:>7}", "period", "mails", "change").unwrap();
+    let mut previous = None;
+    for bucket in buckets {
+        writeln!(
+            out,
+            "{:>10} {:>8} {:>7} {}",
+            bucket.label,
+            format::thousands(bucket.count),
+            change(previous, bucket.count),
+            format::bar(bucket.count, max, BAR_WIDTH)
+        )
+        .unwrap();
+        previous = Some(bucket.count);
+    }
+    if buckets.is_empty() {
+        writeln!(out, "no dated messages").unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, DEFAULT_ACCOUNT};
+    use crate::testsupport::{self, info};
+
+    #[tokio::test]
+    async fn a_sender_is_found_however_it_is_written() {
+        let pool = testsupport::pool().await;
+        let mut conn = pool.acquire().await.unwrap();
+        for (id, from, date) in [
+            ("m1", "Jane <Jane.Doe@Example.com>", 1_000),
+            ("m2", "news@example.com", 2_000),
+        ] {
+            db::record_message(
+                &info(id, from, date, &[]),
+                DEFAULT_ACCOUNT,
+                None,
+                chrono::DateTime::UNIX_EPOCH,
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        }
+        drop(conn);
+
+        for sender in [
+            "JANE.DOE@EXAMPLE.COM",
+            "\"Jane Doe\" <jane.doe@example.com>",
+        ] {
+            let filter = Filter {
+                sender: Some(sender.to_string()),
+                ..Default::default()
+            };
+            assert_eq!(
+                message_dates(&pool, &filter).await.unwrap(),
+                [1_000],
+                "{}",
+                sender
+            );
+        }
+    }
+}
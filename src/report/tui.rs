@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use sqlx::{Pool, Sqlite};
+
+use super::browse::SenderList;
+use super::{all_senders, recent_messages, RecentMessage, SenderFilter};
+use crate::cli::{SortBy, TuiArgs};
+use crate::format;
+
+/// Messages listed in the detail pane, more than fit on most screens.
+const RECENT_MESSAGES: usize = 100;
+
+/// Rows moved by Page Up and Page Down.
+const PAGE_ROWS: isize = 20;
+
+const HELP: &str = "↑↓ move  / search  c count  b bytes  l last seen (again to reverse)  q quit";
+
+/// Whether keys go to the search or move around the list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    Browse,
+    Search,
+}
+
+/// Browse senders in the terminal until the user quits. Everything comes from the database;
+/// senders are loaded once, and each sender's recent messages the first time it's selected.
+pub async fn run(pool: &Pool<Sqlite>, args: &TuiArgs) -> anyhow::Result<()> {
+    let filter = SenderFilter {
+        no_bulk: args.no_bulk,
+        external_only: args.external_only,
+        direct_only: args.direct_only,
+        ..Default::default()
+    };
+    let mut list = SenderList::new(all_senders(pool, &filter, SortBy::Count).await?);
+
+    let mut terminal = ratatui::try_init()?;
+    let result = browse(&mut terminal, pool, &mut list).await;
+    ratatui::restore();
+    result
+}
+
+async fn browse(
+    terminal: &mut DefaultTerminal,
+    pool: &Pool<Sqlite>,
+    list: &mut SenderList,
+) -> anyhow::Result<()> {
+    let mut mode = Mode::Browse;
+    let mut recent = HashMap::<String, Vec<RecentMessage>>::new();
+    loop {
+        if let Some(row) = list.selected() {
+            if !recent.contains_key(&row.sender) {
+                let messages = recent_messages(pool, &row.sender, RECENT_MESSAGES).await?;
+                recent.insert(row.sender.clone(), messages);
+            }
+        }
+        terminal.draw(|frame| draw(frame, list, &recent, mode))?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            return Ok(());
+        }
+        match mode {
+            Mode::Search => match key.code {
+                KeyCode::Enter => mode = Mode::Browse,
+                KeyCode::Esc => {
+                    list.set_search("");
+                    mode = Mode::Browse;
+                }
+                KeyCode::Backspace => list.pop_search(),
+                KeyCode::Char(c) => list.push_search(c),
+                _ => navigate(list, key),
+            },
+            Mode::Browse => match key.code {
+                KeyCode::Char('q') => return Ok(()),
+                KeyCode::Esc if list.search().is_empty() => return Ok(()),
+                KeyCode::Esc => list.set_search(""),
+                KeyCode::Char('/') => mode = Mode::Search,
+                KeyCode::Char('c') => list.toggle_sort(SortBy::Count),
+                KeyCode::Char('b') => list.toggle_sort(SortBy::Bytes),
+                KeyCode::Char('l') => list.toggle_sort(SortBy::Recent),
+                KeyCode::Char('j') => list.move_by(1),
+                KeyCode::Char('k') => list.move_by(-1),
+                KeyCode::Char('g') => list.select_first(),
+                KeyCode::Char('G') => list.select_last(),
+                _ => navigate(list, key),
+            },
+        }
+    }
+}
+
+// Keys which move the selection whether or not a search is being typed
+fn navigate(list: &mut SenderList, key: KeyEvent) {
+    match key.code {
+        KeyCode::Down => list.move_by(1),
+        KeyCode::Up => list.move_by(-1),
+        KeyCode::PageDown => list.move_by(PAGE_ROWS),
+        KeyCode::PageUp => list.move_by(-PAGE_ROWS),
+        KeyCode::Home => list.select_first(),
+        KeyCode::End => list.select_last(),
+        _ => {}
+    }
+}
+
+fn draw(
+    frame: &mut Frame,
+    list: &SenderList,
+    recent: &HashMap<String, Vec<RecentMessage>>,
+    mode: Mode,
+) {
+    let [main, status] =
+        Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+    let [senders, detail] =
+        Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(main);
+    draw_senders(frame, senders, list);
+    draw_detail(frame, detail, list, recent);
+
+    let status_line = match mode {
+        Mode::Search => format!("/{}", list.search()),
+        Mode::Browse if !list.search().is_empty() => {
+            format!("matching {:?}, Esc to clear   {}", list.search(), HELP)
+        }
+        Mode::Browse => HELP.to_string(),
+    };
+    frame.render_widget(Paragraph::new(status_line), status);
+    if mode == Mode::Search {
+        let x = status.x + 1 + list.search().chars().count() as u16;
+        frame.set_cursor_position((x.min(status.right().saturating_sub(1)), status.y));
+    }
+}
+
+fn draw_senders(frame: &mut Frame, area: Rect, list: &SenderList) {
+    // The sorted column is marked with the direction it's sorted in
+    let arrow = if list.reversed() { " ▲" } else { " ▼" };
+    let heading = |name: &str, sort| {
+        if list.sort() == sort {
+            format!("{}{}", name, arrow)
+        } else {
+            name.to_string()
+        }
+    };
+    let header = Row::new(vec![
+        "sender".to_string(),
+        heading("mails", SortBy::Count),
+        heading("bytes", SortBy::Bytes),
+        heading("last seen", SortBy::Recent),
+    ])
+    .style(Style::new().add_modifier(Modifier::BOLD));
+    let rows = list.visible().map(|row| {
+        Row::new(vec![
+            row.sender.clone(),
+            format::thousands(row.mails_sent),
+            format::bytes(row.bytes),
+            format::date(row.last_seen),
+        ])
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Min(16),
+            Constraint::Length(9),
+            Constraint::Length(10),
+            Constraint::Length(12),
+        ],
+    )
+    .header(header)
+    .block(Block::bordered().title(format!(" senders {}/{} ", list.len(), list.total())))
+    .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+
+    let mut state = TableState::default().with_selected(list.selected_index());
+    frame.render_stateful_widget(table, area, &mut state);
+}
+
+fn draw_detail(
+    frame: &mut Frame,
+    area: Rect,
+    list: &SenderList,
+    recent: &HashMap<String, Vec<RecentMessage>>,
+) {
+    let Some(row) = list.selected() else {
+        frame.render_widget(
+            Paragraph::new("no senders match").block(Block::bordered()),
+            area,
+        );
+        return;
+    };
+    let lines = recent
+        .get(&row.sender)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .map(|message| {
+            Line::from(format!(
+                "{}  {}",
+                format::date(message.internal_date),
+                message.subject.as_deref().unwrap_or("(no subject)")
+            ))
+        })
+        .collect::<Vec<_>>();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(format!(" {} ", row.sender))),
+        area,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::backend::TestBackend;
+    use ratatui::crossterm::event::KeyEventState;
+    use ratatui::Terminal;
+
+    use super::*;
+    use crate::report::SenderRow;
+
+    fn list() -> SenderList {
+        let row = |sender: &str, mails_sent| SenderRow {
+            sender: sender.to_string(),
+            mails_sent,
+            bulk_count: 0,
+            unread_count: 0,
+            direct_count: 0,
+            cc_count: 0,
+            auto_count: 0,
+            bytes: mails_sent * 1_000,
+            last_seen: Some(1_714_521_600_000),
+        };
+        SenderList::new(
+            (0..30)
+                .map(|i| row(&format!("s{:02}@example.com", i), 100 - i))
+                .collect(),
+        )
+    }
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent {
+            code,
+            modifiers: KeyModifiers::NONE,
+            kind: KeyEventKind::Press,
+            state: KeyEventState::NONE,
+        }
+    }
+
+    // The screen as lines of text
+    fn screen(
+        list: &SenderList,
+        recent: &HashMap<String, Vec<RecentMessage>>,
+        mode: Mode,
+    ) -> Vec<String> {
+        let mut terminal = Terminal::new(TestBackend::new(100, 12)).unwrap();
+        terminal
+            .draw(|frame| draw(frame, list, recent, mode))
+            .unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..buffer.area.height)
+            .map(|y| {
+                (0..buffer.area.width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect::<String>()
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn movement_keys_page_and_jump() {
+        let mut list = list();
+        navigate(&mut list, key(KeyCode::Down));
+        assert_eq!(list.selected_index(), Some(1));
+        navigate(&mut list, key(KeyCode::PageDown));
+        assert_eq!(list.selected_index(), Some(21));
+        navigate(&mut list, key(KeyCode::PageDown));
+        assert_eq!(list.selected_index(), Some(29));
+        navigate(&mut list, key(KeyCode::PageUp));
+        assert_eq!(list.selected_index(), Some(9));
+        navigate(&mut list, key(KeyCode::Home));
+        assert_eq!(list.selected_index(), Some(0));
+        navigate(&mut list, key(KeyCode::End));
+        assert_eq!(list.selected_index(), Some(29));
+        navigate(&mut list, key(KeyCode::Char('x')));
+        assert_eq!(list.selected_index(), Some(29));
+    }
+
+    #[test]
+    fn the_screen_shows_the_list_the_selection_and_its_mail() {
+        let mut list = list();
+        list.move_by(1);
+        let recent = HashMap::from([(
+            "s01@example.com".to_string(),
+            vec![
+                RecentMessage {
+                    subject: Some("Your receipt".to_string()),
+                    internal_date: Some(1_714_521_600_000),
+                },
+                RecentMessage {
+                    subject: None,
+                    internal_date: None,
+                },
+            ],
+        )]);
+
+        let lines = screen(&list, &recent, Mode::Browse);
+
+        assert!(lines[0].contains(" senders 30/30 "), "{:?}", lines);
+        assert!(lines[0].contains(" s01@example.com "), "{:?}", lines);
+        assert!(lines[1].contains("mails ▼"), "{:?}", lines);
+        assert!(lines[2].contains("s00@example.com"));
+        assert!(lines[1].contains("2024-05-01  Your receipt"), "{:?}", lines);
+        assert!(lines[2].contains("(no subject)"), "{:?}", lines);
+        assert_eq!(lines[11], HELP);
+    }
+
+    #[test]
+    fn a_search_shows_in_the_status_line() {
+        let mut list = list();
+        list.set_search("s2");
+        let lines = screen(&list, &HashMap::new(), Mode::Search);
+        assert_eq!(lines[11], "/s2");
+        assert!(lines[0].contains(" senders 10/30 "), "{:?}", lines);
+
+        let lines = screen(&list, &HashMap::new(), Mode::Browse);
+        assert!(lines[11].starts_with("matching \"s2\", Esc to clear"));
+
+        list.set_search("nobody");
+        let lines = screen(&list, &HashMap::new(), Mode::Browse);
+        assert!(lines[1].contains("no senders match"), "{:?}", lines);
+    }
+}
@@ -0,0 +1,141 @@
+use std::fmt::Write;
+
+use chrono::{Datelike, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use sqlx::{Pool, Sqlite};
+
+use super::pattern::SenderScope;
+use crate::export::ids::normalize;
+use crate::format;
+
+const BAR_WIDTH: usize = 50;
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// Message counts bucketed by local hour of day and day of week.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histograms {
+    pub hours: [i64; 24],
+    /// Monday first.
+    pub weekdays: [i64; 7],
+}
+
+impl Histograms {
+    /// Bucket epoch millisecond timestamps in `tz`. Each instant is converted on its own so
+    /// messages either side of a DST change land in the right local hour.
+    pub fn new(dates: &[i64], tz: Tz) -> Histograms {
+        let mut histograms = Histograms::default();
+        for date in dates {
+            let Some(utc) = Utc.timestamp_millis_opt(*date).single() else {
+                continue;
+            };
+            let local = utc.with_timezone(&tz);
+            histograms.hours[local.hour() as usize] += 1;
+            histograms.weekdays[local.weekday().num_days_from_monday() as usize] += 1;
+        }
+        histograms
+    }
+}
+
+/// The dates of the messages in `scope`, only `sender`'s if it's given. `sender` can be written
+/// any way a From header could hold it, as for `--sender`.
+pub async fn message_dates(
+    pool: &Pool<Sqlite>,
+    sender: Option<&str>,
+    scope: &SenderScope,
+) -> anyhow::Result<Vec<i64>> {
+    let sender = sender.map(normalize);
+    let dates: Vec<(i64,)> = sqlx::query_as(&format!(
+        "SELECT internal_date FROM messages
+        WHERE internal_date IS NOT NULL AND (?1 IS NULL OR lower(sender) = ?1) AND {}",
+        scope.condition("sender", None)
+    ))
+    .bind(sender)
+    .fetch_all(pool)
+    .await?;
+    Ok(dates.into_iter().map(|(date,)| date).collect())
+}
+
+fn render_histogram<'a>(
+    out: &mut String,
+    title: &str,
+    buckets: impl Iterator<Item = (String, &'a i64)> + Clone,
+) {
+    let max = buckets.clone().map(|(_, count)| *count).max().unwrap_or(0);
+    writeln!(out, "{}", title).unwrap();
+    for (label, count) in buckets {
+        writeln!(
+            out,
+            "{:>5} {:>8} {}",
+            label,
+            format::thousands(*count),
+            format::bar(*count, max, BAR_WIDTH)
+        )
+        .unwrap();
+    }
+}
+
+pub fn render(histograms: &Histograms) -> String {
+    let mut out = String::new();
+    render_histogram(
+        &mut out,
+        "by hour of day",
+        histograms
+            .hours
+            .iter()
+            .enumerate()
+            .map(|(hour, count)| (format!("{:02}h", hour), count)),
+    );
+    out.push('\n');
+    render_histogram(
+        &mut out,
+        "by day of week",
+        WEEKDAYS
+            .iter()
+            .zip(histograms.weekdays.iter())
+            .map(|(day, count)| (day.to_string(), count)),
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, DEFAULT_ACCOUNT};
+    use crate::testsupport::{self, info};
+
+    #[tokio::test]
+    async fn a_sender_is_found_however_it_is_written() {
+        let pool = testsupport::pool().await;
+        let mut conn = pool.acquire().await.unwrap();
+        for (id, from, date) in [
+            ("m1", "Jane <Jane.Doe@Example.com>", 1_000),
+            ("m2", "news@example.com", 2_000),
+        ] {
+            db::record_message(
+                &info(id, from, date, &[]),
+                DEFAULT_ACCOUNT,
+                None,
+                chrono::DateTime::UNIX_EPOCH,
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        }
+        drop(conn);
+        let scope = SenderScope::default();
+
+        for sender in [
+            "jane.doe@example.com",
+            "JANE.DOE@EXAMPLE.COM",
+            " \"Jane Doe\" <jane.doe@example.com> ",
+        ] {
+            assert_eq!(
+                message_dates(&pool, Some(sender), &scope).await.unwrap(),
+                [1_000],
+                "{}",
+                sender
+            );
+        }
+        assert_eq!(message_dates(&pool, None, &scope).await.unwrap().len(), 2);
+    }
+}
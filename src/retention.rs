@@ -0,0 +1,394 @@
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{Pool, Sqlite};
+
+use crate::backup;
+use crate::config::RetentionConfig;
+use crate::dates::Period;
+use crate::db;
+use crate::error::GmailStatsError;
+use crate::format;
+
+/// What one pass of the retention policy removed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Expired {
+    /// Messages whose subject was forgotten.
+    pub subjects: u64,
+    /// Message rows deleted, with their labels, recipients and attachments.
+    pub messages: u64,
+}
+
+/// The epoch milliseconds before which something kept for `period` has expired.
+pub fn cutoff(period: Period, now: DateTime<Utc>) -> Result<i64, GmailStatsError> {
+    period
+        .before(now)
+        .map(|cutoff| cutoff.timestamp_millis())
+        .map_err(|err| GmailStatsError::Config(format!("[retention]: {}", err)))
+}
+
+/// Forget subjects and delete message rows older than `retention` allows, going by when each
+/// message was received, or fetched if that isn't known. Senders' counts and the seen marks
+/// are kept, so expired mail stays counted and isn't fetched again.
+pub async fn apply(
+    pool: &Pool<Sqlite>,
+    retention: &RetentionConfig,
+    now: DateTime<Utc>,
+) -> Result<Expired, GmailStatsError> {
+    let mut expired = Expired::default();
+    let mut tx = pool.begin().await?;
+    if let Some(period) = retention.subjects {
+        expired.subjects = sqlx::query(
+            "UPDATE messages SET subject = NULL
+            WHERE subject IS NOT NULL AND coalesce(internal_date, fetched_at) < ?",
+        )
+        .bind(cutoff(period, now)?)
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+    }
+    if let Some(period) = retention.messages {
+        let cutoff = cutoff(period, now)?;
+        for table in ["message_labels", "message_recipients", "attachments"] {
+            sqlx::query(&format!(
+                "DELETE FROM {} WHERE (account, mail_id) IN (SELECT account, mail_id FROM messages
+                    WHERE coalesce(internal_date, fetched_at) < ?)",
+                table
+            ))
+            .bind(cutoff)
+            .execute(&mut tx)
+            .await?;
+        }
+        sqlx::query(
+            "UPDATE seen_mails SET unrecorded = 'expired'
+            WHERE (account, mail_id) IN (SELECT account, mail_id FROM messages
+                WHERE coalesce(internal_date, fetched_at) < ?)",
+        )
+        .bind(cutoff)
+        .execute(&mut tx)
+        .await?;
+        expired.messages =
+            sqlx::query("DELETE FROM messages WHERE coalesce(internal_date, fetched_at) < ?")
+                .bind(cutoff)
+                .execute(&mut tx)
+                .await?
+                .rows_affected();
+    }
+    tx.commit().await?;
+    Ok(expired)
+}
+
+/// Apply `retention` to every backup of `database` as well, so what's expired doesn't live on
+/// in them. Each is migrated first, as restoring it would, and vacuumed after so the deleted rows
+/// and subjects are gone from the file rather than left in its free pages.
+pub async fn expire_backups(
+    database: &Path,
+    retention: &RetentionConfig,
+    now: DateTime<Utc>,
+) -> anyhow::Result<Expired> {
+    let mut expired = Expired::default();
+    if retention.messages.is_none() && retention.subjects.is_none() {
+        return Ok(expired);
+    }
+    for path in backup::list(database)? {
+        let options = SqliteConnectOptions::new().filename(&path);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await?;
+        db::migrate(&pool).await?;
+        let from_backup = apply(&pool, retention, now).await?;
+        if from_backup != Expired::default() {
+            sqlx::query("VACUUM").execute(&pool).await?;
+        }
+        pool.close().await;
+        expired.subjects += from_backup.subjects;
+        expired.messages += from_backup.messages;
+    }
+    Ok(expired)
+}
+
+/// Apply `retention` at the end of a run, to the database and its backups, saying what it
+/// removed unless `quiet`.
+pub async fn enforce(
+    pool: &Pool<Sqlite>,
+    retention: &RetentionConfig,
+    now: DateTime<Utc>,
+    quiet: bool,
+) -> anyhow::Result<()> {
+    let expired = apply(pool, retention, now).await?;
+    let from_backups = expire_backups(Path::new(backup::DATABASE), retention, now).await?;
+    if quiet {
+        return Ok(());
+    }
+    if expired.subjects > 0 {
+        eprintln!(
+            "retention: forgot the subjects of {} messages",
+            format::thousands(expired.subjects as i64)
+        );
+    }
+    if expired.messages > 0 {
+        // Sender totals are kept, but anything worked out again from the message rows isn't
+        eprintln!(
+            "retention: deleted {} message rows; reports built from them, and the direct and cc \
+            counts recounted after each fetch, only cover the mail still kept",
+            format::thousands(expired.messages as i64)
+        );
+    }
+    if from_backups != Expired::default() {
+        eprintln!(
+            "retention: expired {} subjects and {} message rows from the backups too",
+            format::thousands(from_backups.subjects as i64),
+            format::thousands(from_backups.messages as i64)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::analyze::SenderCounts;
+    use crate::clock::Clock;
+    use crate::db::DEFAULT_ACCOUNT;
+    use crate::testsupport::{self, info};
+
+    const CLOCK: Clock = Clock::Fixed(DateTime::from_timestamp_millis(1_719_835_200_000).unwrap());
+
+    fn days_ago(days: i64) -> i64 {
+        (CLOCK.now() - chrono::Duration::days(days)).timestamp_millis()
+    }
+
+    fn retention(messages: &str, subjects: &str) -> RetentionConfig {
+        RetentionConfig {
+            messages: Some(messages.parse().unwrap()),
+            subjects: Some(subjects.parse().unwrap()),
+        }
+    }
+
+    // Mail from jane received 10, 60 and 200 days ago, and one with no date fetched 200 days
+    // ago, each with a subject, counted and seen
+    async fn record(pool: &Pool<Sqlite>) {
+        let mut conn = pool.acquire().await.unwrap();
+        let mut counts = SenderCounts::default();
+        let mut ids = Vec::new();
+        for (id, received, fetched) in [
+            ("m10", Some(days_ago(10)), days_ago(10)),
+            ("m60", Some(days_ago(60)), days_ago(10)),
+            ("m200", Some(days_ago(200)), days_ago(10)),
+            ("undated", None, days_ago(200)),
+        ] {
+            let mut message = info(id, "jane@example.com", 0, &["INBOX"]);
+            message.date = received;
+            message.subject = Some("Lunch?".to_string());
+            db::record_message(
+                &message,
+                DEFAULT_ACCOUNT,
+                None,
+                DateTime::from_timestamp_millis(fetched).unwrap(),
+                &mut conn,
+            )
+            .await
+            .unwrap();
+            counts.add(&message);
+            ids.push(message.id);
+        }
+        db::add_sender_counts("jane@example.com", &counts, &mut conn)
+            .await
+            .unwrap();
+        db::mark_seen(&ids, DEFAULT_ACCOUNT, &mut conn)
+            .await
+            .unwrap();
+    }
+
+    async fn subjects(pool: &Pool<Sqlite>) -> Vec<(String, Option<String>)> {
+        sqlx::query_as("SELECT mail_id, subject FROM messages ORDER BY mail_id")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn the_cutoff_is_the_period_before_now() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 31, 12, 0, 0).unwrap();
+        let millis = |y, m, d| {
+            Utc.with_ymd_and_hms(y, m, d, 12, 0, 0)
+                .unwrap()
+                .timestamp_millis()
+        };
+
+        assert_eq!(
+            cutoff("30d".parse().unwrap(), now).unwrap(),
+            millis(2024, 3, 1)
+        );
+        assert_eq!(
+            cutoff("2w".parse().unwrap(), now).unwrap(),
+            millis(2024, 3, 17)
+        );
+        assert_eq!(
+            cutoff("1m".parse().unwrap(), now).unwrap(),
+            millis(2024, 2, 29)
+        );
+        assert_eq!(
+            cutoff("1y".parse().unwrap(), now).unwrap(),
+            millis(2023, 3, 31)
+        );
+        assert!(cutoff(Period::Days(u32::MAX), now).is_err());
+    }
+
+    #[tokio::test]
+    async fn rows_and_subjects_past_their_cutoff_expire_but_counts_stay() {
+        let pool = testsupport::pool().await;
+        record(&pool).await;
+
+        let expired = apply(&pool, &retention("180d", "30d"), CLOCK.now())
+            .await
+            .unwrap();
+
+        // The undated message goes by when it was fetched
+        assert_eq!(
+            expired,
+            Expired {
+                subjects: 3,
+                messages: 2
+            }
+        );
+        assert_eq!(
+            subjects(&pool).await,
+            [
+                ("m10".to_string(), Some("Lunch?".to_string())),
+                ("m60".to_string(), None)
+            ]
+        );
+        let (labels,): (i64,) = sqlx::query_as("SELECT count(*) FROM message_labels")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(labels, 2);
+        let (mails_sent,): (i64,) = sqlx::query_as("SELECT mails_sent FROM senders")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(mails_sent, 4);
+        let unrecorded: Vec<(String, Option<String>)> =
+            sqlx::query_as("SELECT mail_id, unrecorded FROM seen_mails ORDER BY mail_id")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            unrecorded,
+            [
+                ("m10".to_string(), None),
+                ("m200".to_string(), Some("expired".to_string())),
+                ("m60".to_string(), None),
+                ("undated".to_string(), Some("expired".to_string()))
+            ]
+        );
+
+        // Applying it again at the same time finds nothing more
+        let again = apply(&pool, &retention("180d", "30d"), CLOCK.now())
+            .await
+            .unwrap();
+        assert_eq!(again, Expired::default());
+    }
+
+    #[tokio::test]
+    async fn a_day_more_and_the_next_message_expires() {
+        let pool = testsupport::pool().await;
+        record(&pool).await;
+        let later = CLOCK.now() + chrono::Duration::days(1);
+
+        // A day later, 61 days back is exactly when m60 came, and only older mail expires
+        let on_the_cutoff = apply(&pool, &retention("61d", "61d"), later).await.unwrap();
+        assert_eq!(on_the_cutoff.messages, 2);
+        let past_it = apply(
+            &pool,
+            &retention("61d", "61d"),
+            later + chrono::Duration::milliseconds(1),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(past_it.messages, 1);
+        assert_eq!(
+            subjects(&pool).await,
+            [("m10".to_string(), Some("Lunch?".to_string()))]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_quiet_run_expires_just_as_much() {
+        for quiet in [false, true] {
+            let pool = testsupport::pool().await;
+            record(&pool).await;
+
+            enforce(&pool, &retention("180d", "30d"), CLOCK.now(), quiet)
+                .await
+                .unwrap();
+
+            // Only saying so is left out
+            assert_eq!(
+                subjects(&pool).await,
+                [
+                    ("m10".to_string(), Some("Lunch?".to_string())),
+                    ("m60".to_string(), None)
+                ],
+                "quiet: {}",
+                quiet
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn backups_lose_what_expired_too() {
+        let dir =
+            std::env::temp_dir().join(format!("gmail-stats-retention-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let database = dir.join(backup::DATABASE);
+        let options = SqliteConnectOptions::new()
+            .filename(&database)
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await
+            .unwrap();
+        db::migrate(&pool).await.unwrap();
+        record(&pool).await;
+        let taken = backup::path_for(&database, CLOCK.now());
+        backup::backup(&pool, &taken).await.unwrap();
+        pool.close().await;
+
+        let expired = expire_backups(&database, &retention("180d", "30d"), CLOCK.now())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            expired,
+            Expired {
+                subjects: 3,
+                messages: 2
+            }
+        );
+        let options = SqliteConnectOptions::new().filename(&taken);
+        let backup = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await
+            .unwrap();
+        assert_eq!(subjects(&backup).await.len(), 2);
+        backup.close().await;
+        // Nothing is done to backups without a retention policy
+        let none = expire_backups(&database, &RetentionConfig::default(), CLOCK.now())
+            .await
+            .unwrap();
+        assert_eq!(none, Expired::default());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+use std::fmt::Write;
+
+use sqlx::{FromRow, Pool, Sqlite};
+
+use crate::cli::SearchArgs;
+use crate::format;
+
+/// The full-text index of subjects, an FTS5 table whose rowids are those of `messages`. It isn't
+/// a migration, since SQLite can be built without FTS5 and the rest of the database works
+/// without it. Triggers keep it in step with every insert, update and delete of a message, so
+/// fetches, imports, retention and `forget` all maintain it; the one before an insert drops
+/// the entry of a message `INSERT OR REPLACE` is about to replace.
+const SCHEMA: [&str; 5] = [
+    "CREATE VIRTUAL TABLE IF NOT EXISTS subjects_fts
+        USING fts5(subject, tokenize = 'unicode61 remove_diacritics 2')",
+    "CREATE TRIGGER IF NOT EXISTS subjects_fts_replace BEFORE INSERT ON messages BEGIN
+        DELETE FROM subjects_fts
+        WHERE rowid = (SELECT rowid FROM messages
+            WHERE account = new.account AND mail_id = new.mail_id);
+    END",
+    "CREATE TRIGGER IF NOT EXISTS subjects_fts_insert AFTER INSERT ON messages
+    WHEN new.subject IS NOT NULL BEGIN
+        INSERT INTO subjects_fts (rowid, subject) VALUES (new.rowid, new.subject);
+    END",
+    "CREATE TRIGGER IF NOT EXISTS subjects_fts_delete AFTER DELETE ON messages BEGIN
+        DELETE FROM subjects_fts WHERE rowid = old.rowid;
+    END",
+    "CREATE TRIGGER IF NOT EXISTS subjects_fts_update AFTER UPDATE OF subject ON messages BEGIN
+        DELETE FROM subjects_fts WHERE rowid = old.rowid;
+        INSERT INTO subjects_fts (rowid, subject)
+        SELECT new.rowid, new.subject WHERE new.subject IS NOT NULL;
+    END",
+];
+
+/// Said whenever something needs the index and SQLite can't build one.
+pub const UNAVAILABLE: &str =
+    "this build of SQLite has no FTS5 full-text search, so subjects can't be searched";
+
+/// Create the index and its triggers if they aren't there, returning whether SQLite has FTS5.
+/// A new index starts empty: fetches add to it from then on, and `db reindex` adds what was
+/// fetched before.
+pub async fn ensure_index(pool: &Pool<Sqlite>) -> anyhow::Result<bool> {
+    let mut tx = pool.begin().await?;
+    for statement in SCHEMA {
+        match sqlx::query(statement).execute(&mut tx).await {
+            Ok(_) => {}
+            Err(sqlx::Error::Database(err)) if err.message().contains("no such module: fts5") => {
+                return Ok(false);
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Whether the index exists, which it does wherever SQLite has FTS5.
+pub async fn is_available(pool: &Pool<Sqlite>) -> anyhow::Result<bool> {
+    let (count,): (i64,) = sqlx::query_as(
+        "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = 'subjects_fts'",
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(count > 0)
+}
+
+/// Rebuild the index from the stored subjects, returning how many it holds.
+pub async fn reindex(pool: &Pool<Sqlite>) -> anyhow::Result<u64> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM subjects_fts")
+        .execute(&mut tx)
+        .await?;
+    let indexed = sqlx::query(
+        "INSERT INTO subjects_fts (rowid, subject)
+        SELECT rowid, subject FROM messages WHERE subject IS NOT NULL",
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+    sqlx::query("INSERT INTO subjects_fts (subjects_fts) VALUES ('optimize')")
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(indexed)
+}
+
+/// Stored subjects missing from the index, such as those fetched before it existed.
+pub async fn unindexed(pool: &Pool<Sqlite>) -> anyhow::Result<i64> {
+    let (count,): (i64,) = sqlx::query_as(
+        "SELECT (SELECT count(*) FROM messages WHERE subject IS NOT NULL)
+            - (SELECT count(*) FROM subjects_fts)",
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(count.max(0))
+}
+
+/// Turn what was typed into an FTS5 query matching subjects with every word: `"double quoted"`
+/// words are a phrase, and a word ending in `*` matches as a prefix. Everything else is quoted,
+/// so punctuation such as `re:` or `50%` is searched for rather than read as query syntax.
+pub fn fts_query(terms: &str) -> String {
+    let quote = |words: &str| format!("\"{}\"", words.replace('"', "\"\""));
+    let mut parts = Vec::new();
+    for (i, chunk) in terms.split('"').enumerate() {
+        // Odd chunks were between quotes
+        if i % 2 == 1 {
+            if !chunk.trim().is_empty() {
+                parts.push(quote(chunk.trim()));
+            }
+            continue;
+        }
+        for word in chunk.split_whitespace() {
+            match word.strip_suffix('*') {
+                Some(prefix) if !prefix.is_empty() => parts.push(format!("{}*", quote(prefix))),
+                _ => parts.push(quote(word)),
+            }
+        }
+    }
+    parts.join(" ")
+}
+
+/// A message whose subject matched.
+#[derive(Clone, Debug, FromRow)]
+pub struct Hit {
+    pub mail_id: String,
+    pub sender: String,
+    pub internal_date: Option<i64>,
+    pub subject: String,
+}
+
+/// Messages whose subject matches `terms`, newest first.
+pub async fn search(pool: &Pool<Sqlite>, terms: &str, limit: usize) -> anyhow::Result<Vec<Hit>> {
+    let query = fts_query(terms);
+    if query.is_empty() {
+        anyhow::bail!("nothing to search for");
+    }
+    let rows = sqlx::query_as::<_, Hit>(
+        "SELECT m.mail_id, m.sender, m.internal_date, m.subject
+        FROM subjects_fts JOIN messages m ON m.rowid = subjects_fts.rowid
+        WHERE subjects_fts MATCH ?
+        ORDER BY m.internal_date IS NULL, m.internal_date DESC
+        LIMIT ?",
+    )
+    .bind(query)
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub fn render(hits: &[Hit]) -> String {
+    let mut out = String::new();
+    writeln!(out, "{:>10}  {:<32} subject", "date", "sender").unwrap();
+    for hit in hits {
+        writeln!(
+            out,
+            "{:>10}  {:<32} {}",
+            format::date(hit.internal_date),
+            hit.sender,
+            hit.subject
+        )
+        .unwrap();
+    }
+    out
+}
+
+/// `search`: list the messages whose subject matches.
+pub async fn run(pool: &Pool<Sqlite>, args: &SearchArgs) -> anyhow::Result<()> {
+    if !is_available(pool).await? {
+        anyhow::bail!(UNAVAILABLE);
+    }
+    let hits = search(pool, &args.terms, args.top).await?;
+    if hits.is_empty() {
+        println!("no subjects match {:?}", args.terms);
+    } else {
+        print!("{}", render(&hits));
+    }
+    let unindexed = unindexed(pool).await?;
+    if unindexed > 0 {
+        eprintln!(
+            "{} stored subjects aren't indexed yet, `db reindex` adds them",
+            format::thousands(unindexed)
+        );
+    }
+    Ok(())
+}
+
+/// `db reindex`: rebuild the subject index.
+pub async fn run_reindex(pool: &Pool<Sqlite>) -> anyhow::Result<()> {
+    if !is_available(pool).await? {
+        anyhow::bail!(UNAVAILABLE);
+    }
+    let indexed = reindex(pool).await?;
+    println!("indexed {} subjects", format::thousands(indexed as i64));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::db::{self, DEFAULT_ACCOUNT};
+    use crate::testsupport::{self, info};
+
+    #[test]
+    fn every_word_is_quoted() {
+        assert_eq!(fts_query("Re: 50% off"), r#""Re:" "50%" "off""#);
+        assert_eq!(fts_query("  weekly\tdigest \n"), r#""weekly" "digest""#);
+        assert_eq!(fts_query(""), "");
+        assert_eq!(fts_query("   "), "");
+    }
+
+    #[test]
+    fn query_syntax_is_searched_for_rather_than_obeyed() {
+        assert_eq!(fts_query("cats OR dogs"), r#""cats" "OR" "dogs""#);
+        assert_eq!(
+            fts_query("NOT spam AND eggs"),
+            r#""NOT" "spam" "AND" "eggs""#
+        );
+        assert_eq!(
+            fts_query("NEAR(a b) -x ^y subject:z"),
+            r#""NEAR(a" "b)" "-x" "^y" "subject:z""#
+        );
+        // A star alone or inside a word isn't a prefix
+        assert_eq!(fts_query("* a*b"), r#""*" "a*b""#);
+    }
+
+    #[test]
+    fn quoted_words_are_a_phrase_and_a_trailing_star_a_prefix() {
+        assert_eq!(fts_query(r#""big sale" now*"#), r#""big sale" "now"*"#);
+        // An unclosed quote runs to the end, and an empty one is dropped
+        assert_eq!(
+            fts_query(r#"invoice "march 2024"#),
+            r#""invoice" "march 2024""#
+        );
+        assert_eq!(fts_query(r#"a "" b"#), r#""a" "b""#);
+    }
+
+    #[test]
+    fn non_ascii_and_emoji_are_kept_whole() {
+        assert_eq!(fts_query("café Ünïcode*"), r#""café" "Ünïcode"*"#);
+        assert_eq!(fts_query("🎉 sale"), r#""🎉" "sale""#);
+        assert_eq!(fts_query("日本語 テスト"), r#""日本語" "テスト""#);
+    }
+
+    #[tokio::test]
+    async fn every_query_runs_against_the_index() {
+        let pool = testsupport::pool().await;
+        let mut conn = pool.acquire().await.unwrap();
+        for (id, subject) in [
+            ("m1", "Re: 50% off everything"),
+            ("m2", "Cats OR dogs, the big debate"),
+            ("m3", "Café opening 🎉"),
+            ("m4", "Big sale now on"),
+        ] {
+            let mut message = info(id, "jane@example.com", 0, &["INBOX"]);
+            message.subject = Some(subject.to_string());
+            db::record_message(
+                &message,
+                DEFAULT_ACCOUNT,
+                None,
+                DateTime::UNIX_EPOCH,
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        }
+        drop(conn);
+
+        let found = |terms: &'static str| {
+            let pool = pool.clone();
+            async move {
+                let mut ids = search(&pool, terms, 10)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|hit| hit.mail_id)
+                    .collect::<Vec<_>>();
+                ids.sort();
+                ids
+            }
+        };
+        assert_eq!(found("re: 50%").await, ["m1"]);
+        assert_eq!(found("cats OR dogs").await, ["m2"]);
+        // Diacritics are folded by the tokenizer, either way round
+        assert_eq!(found("cafe").await, ["m3"]);
+        assert_eq!(found("CAFÉ").await, ["m3"]);
+        assert_eq!(found(r#""big sale""#).await, ["m4"]);
+        assert_eq!(found("eve*").await, ["m1"]);
+        assert_eq!(found("NEAR(a b) -x ^y").await, Vec::<String>::new());
+        assert!(search(&pool, "  ", 10).await.is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use chrono::{DateTime, Utc};
+use google_gmail1::hyper::{self, header, Body, Method, Request};
+use serde_json::json;
+use sqlx::{Pool, Sqlite};
+
+use crate::auth;
+use crate::metrics;
+
+/// Read and write access to spreadsheets, asked for only the first time `--sheet-id` is used.
+pub const SCOPE: &str = "https://www.googleapis.com/auth/spreadsheets";
+
+const API: &str = "https://sheets.googleapis.com/v4/spreadsheets";
+
+/// One row of the run log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RunSummary {
+    pub finished_at: DateTime<Utc>,
+    /// Messages fetched by any run so far.
+    pub total_messages: i64,
+    /// Messages recorded by this run.
+    pub new_messages: i64,
+    pub senders: i64,
+    /// The sender with the most mail, ignored senders aside.
+    pub top_sender: Option<String>,
+}
+
+/// Summarize the latest run, after classification so ignored senders are flagged.
+pub async fn summarize(pool: &Pool<Sqlite>, now: DateTime<Utc>) -> anyhow::Result<RunSummary> {
+    let totals = metrics::totals(pool).await?;
+    let (new_messages,): (Option<i64>,) =
+        sqlx::query_as("SELECT messages FROM runs ORDER BY id DESC LIMIT 1")
+            .fetch_optional(pool)
+            .await?
+            .unwrap_or((None,));
+    let top_sender: Option<(String,)> = sqlx::query_as(
+        "SELECT sender FROM senders WHERE NOT is_ignored
+        ORDER BY mails_sent DESC, sender LIMIT 1",
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(RunSummary {
+        finished_at: now,
+        total_messages: totals.messages_seen,
+        new_messages: new_messages.unwrap_or_default(),
+        senders: totals.senders,
+        top_sender: top_sender.map(|(sender,)| sender),
+    })
+}
+
+/// A `values.append` request adding `summary` as a row after the table on the spreadsheet's first
+/// sheet. Values are entered as if typed, so the date is a real date to Sheets.
+pub fn append_request(
+    sheet_id: &str,
+    token: &str,
+    summary: &RunSummary,
+) -> anyhow::Result<Request<Body>> {
+    // Spreadsheet ids go straight into the path, so nothing else is let through
+    if sheet_id.is_empty()
+        || !sheet_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        anyhow::bail!("invalid spreadsheet id {:?}", sheet_id);
+    }
+    let uri = format!(
+        "{}/{}/values/A1:append?valueInputOption=USER_ENTERED&insertDataOption=INSERT_ROWS",
+        API, sheet_id
+    );
+    let body = json!({
+        "majorDimension": "ROWS",
+        "values": [[
+            summary.finished_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            summary.total_messages,
+            summary.new_messages,
+            summary.senders,
+            summary.top_sender.as_deref().unwrap_or(""),
+        ]],
+    });
+    Ok(Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))?)
+}
+
+/// Append the latest run's summary to the spreadsheet `sheet_id`, signed in as `profile`.
+pub async fn append_summary(
+    pool: &Pool<Sqlite>,
+    sheet_id: &str,
+    profile: Option<&str>,
+    now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let summary = summarize(pool, now).await?;
+    let token = auth::authenticator(profile).await?.token(&[SCOPE]).await?;
+    let request = append_request(sheet_id, token.as_str(), &summary)?;
+    let response = auth::client().request(request).await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        anyhow::bail!(
+            "Sheets API returned {}: {}",
+            status,
+            String::from_utf8_lossy(&body).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::analyze::SenderCounts;
+    use crate::db::{self, DEFAULT_ACCOUNT};
+    use crate::testsupport::{self, info};
+
+    fn summary(top_sender: Option<&str>) -> RunSummary {
+        RunSummary {
+            finished_at: Utc.with_ymd_and_hms(2024, 6, 10, 9, 30, 5).unwrap(),
+            total_messages: 1_200,
+            new_messages: 14,
+            senders: 87,
+            top_sender: top_sender.map(str::to_string),
+        }
+    }
+
+    async fn body(request: Request<Body>) -> Value {
+        let bytes = hyper::body::to_bytes(request.into_body()).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_run_is_appended_as_a_typed_row() {
+        let request =
+            append_request("1AbC-d_9", "token123", &summary(Some("jane@example.com"))).unwrap();
+
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(
+            request.uri(),
+            "https://sheets.googleapis.com/v4/spreadsheets/1AbC-d_9/values/A1:append\
+            ?valueInputOption=USER_ENTERED&insertDataOption=INSERT_ROWS"
+        );
+        assert_eq!(request.headers()[header::AUTHORIZATION], "Bearer token123");
+        assert_eq!(request.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(
+            body(request).await,
+            json!({
+                "majorDimension": "ROWS",
+                "values": [["2024-06-10 09:30:05", 1200, 14, 87, "jane@example.com"]],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn a_run_without_mail_leaves_the_top_sender_blank() {
+        let request = append_request("sheet", "t", &summary(None)).unwrap();
+        assert_eq!(body(request).await["values"][0][4], "");
+    }
+
+    #[test]
+    fn only_plain_spreadsheet_ids_are_put_in_the_path() {
+        for id in [
+            "",
+            "../other",
+            "id?x=1",
+            "id/values",
+            "id with space",
+            "id#frag",
+        ] {
+            let err = append_request(id, "t", &summary(None)).unwrap_err();
+            assert!(
+                err.to_string().starts_with("invalid spreadsheet id"),
+                "{}",
+                id
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn the_summary_is_of_the_latest_run() {
+        let pool = testsupport::pool().await;
+        let now = Utc.with_ymd_and_hms(2024, 6, 10, 9, 30, 5).unwrap();
+        assert_eq!(
+            summarize(&pool, now).await.unwrap(),
+            RunSummary {
+                finished_at: now,
+                total_messages: 0,
+                new_messages: 0,
+                senders: 0,
+                top_sender: None,
+            }
+        );
+
+        let mut conn = pool.acquire().await.unwrap();
+        let first = db::start_run(DEFAULT_ACCOUNT, now, &mut *conn)
+            .await
+            .unwrap();
+        let second = db::start_run(DEFAULT_ACCOUNT, now, &mut *conn)
+            .await
+            .unwrap();
+        for (id, from, run) in [
+            ("m1", "news@shop.example", first),
+            ("m2", "news@shop.example", first),
+            ("m3", "news@shop.example", first),
+            ("m4", "jane@example.com", second),
+            ("m5", "jane@example.com", second),
+        ] {
+            let message = info(id, from, 1_000, &["INBOX"]);
+            db::record_message(&message, DEFAULT_ACCOUNT, Some(run), now, &mut conn)
+                .await
+                .unwrap();
+            let mut counts = SenderCounts::default();
+            counts.add(&message);
+            db::add_sender_counts(from, &counts, &mut conn)
+                .await
+                .unwrap();
+            db::mark_seen(&[id.to_string()], DEFAULT_ACCOUNT, &mut conn)
+                .await
+                .unwrap();
+        }
+        for run in [first, second] {
+            db::finish_run(run, false, None, now, &mut *conn)
+                .await
+                .unwrap();
+        }
+        sqlx::query("UPDATE senders SET is_ignored = 1 WHERE sender = 'news@shop.example'")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        drop(conn);
+
+        assert_eq!(
+            summarize(&pool, now).await.unwrap(),
+            RunSummary {
+                finished_at: now,
+                total_messages: 5,
+                new_messages: 2,
+                senders: 2,
+                top_sender: Some("jane@example.com".to_string()),
+            }
+        );
+    }
+}
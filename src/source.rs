@@ -0,0 +1,223 @@
+use std::future::Future;
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use flate2::read::GzDecoder;
+use google_gmail1::api::{ListMessagesResponse, Message, Profile, Scope};
+use google_gmail1::hyper::{self, header, Body, Request, Response};
+use google_gmail1::Gmail;
+use serde::de::DeserializeOwned;
+
+use crate::error::{self, GmailStatsError};
+
+/// The API's path for the signed-in user, under the hub's base URL.
+const USER_PATH: &str = "gmail/v1/users/me/";
+
+/// One page of a message listing.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MessagePage {
+    /// Ids of the messages on this page.
+    pub ids: Vec<String>,
+    /// Token for the next page, `None` on the last one.
+    pub next_page_token: Option<String>,
+    /// Entries the listing had without an id, which can't be fetched.
+    pub missing_ids: u64,
+}
+
+/// Where in the listings a fetch got to: the label being listed, `None` for all mail, and the
+/// token of the page being worked through, `None` for the first.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cursor {
+    pub label: Option<String>,
+    pub page_token: Option<String>,
+}
+
+/// Where fetched mail comes from: Gmail itself, or anything else which can list and get
+/// messages the way its API does.
+pub trait MailSource {
+    /// A page of the mailbox listing, restricted to messages with `label` unless it's `None`.
+    fn list_page(
+        &self,
+        label: Option<&str>,
+        include_spam_trash: bool,
+        page_token: Option<&str>,
+    ) -> impl Future<Output = Result<MessagePage, GmailStatsError>> + Send;
+
+    /// A message's headers and labels, everything `parse` looks at.
+    fn get_message(
+        &self,
+        id: &str,
+    ) -> impl Future<Output = Result<Message, GmailStatsError>> + Send;
+
+    /// How many messages the mailbox holds by its own count, for sources which keep one.
+    fn messages_total(&self) -> impl Future<Output = Result<Option<u64>, GmailStatsError>> + Send {
+        async { Ok(None) }
+    }
+
+    /// Bytes read off the wire so far, for sources which count them.
+    fn bytes_received(&self) -> Option<u64> {
+        None
+    }
+
+    /// What the listing's page tokens belong to, for sources whose tokens still work in a later
+    /// run, so a stopped fetch can carry on from the page it was on. Others list from the top.
+    fn listing_id(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Gmail's API, called with the hub's client and sign-in but plain requests rather than its
+/// generated calls, which can't ask for compressed responses.
+pub struct GmailSource {
+    hub: Gmail,
+    api: String,
+    compression: bool,
+    received: AtomicU64,
+}
+
+impl GmailSource {
+    /// Read mail through `hub`, asking for gzipped responses when `compression` is set. Calls
+    /// go to the hub's base URL, so pointing that elsewhere points the source there too.
+    pub fn new(mut hub: Gmail, compression: bool) -> GmailSource {
+        // The hub only hands its base URL out in exchange for a new one, so it's put back
+        let base = hub.base_url(String::new());
+        hub.base_url(base.clone());
+        GmailSource {
+            hub,
+            api: format!("{}{}", base, USER_PATH),
+            compression,
+            received: AtomicU64::new(0),
+        }
+    }
+
+    // GET `path` under the API's users/me and decode the JSON it answers with. Failures are
+    // turned into the errors the generated calls give, so they're classified the same way.
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, GmailStatsError> {
+        let token = self
+            .hub
+            .auth
+            .token(&[Scope::Readonly.as_ref()])
+            .await
+            .map_err(google_gmail1::Error::MissingToken)?;
+        let mut request = Request::get(format!("{}{}", self.api, path))
+            .header(header::AUTHORIZATION, format!("Bearer {}", token.as_str()));
+        if self.compression {
+            // Google only compresses for user agents which say they take gzip too
+            request = request
+                .header(header::ACCEPT_ENCODING, "gzip")
+                .header(header::USER_AGENT, "gmail_stats (gzip)");
+        }
+        let request = request
+            .body(Body::empty())
+            .map_err(|err| GmailStatsError::Config(format!("bad request for {}: {}", path, err)))?;
+
+        let response = self
+            .hub
+            .client
+            .request(request)
+            .await
+            .map_err(google_gmail1::Error::HttpError)?;
+        let (parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body)
+            .await
+            .map_err(google_gmail1::Error::HttpError)?;
+        self.received
+            .fetch_add(body.len() as u64, Ordering::Relaxed);
+        let gzipped = parts
+            .headers
+            .get(header::CONTENT_ENCODING)
+            .is_some_and(|encoding| encoding.as_bytes().eq_ignore_ascii_case(b"gzip"));
+        let body = if gzipped {
+            let mut decoded = Vec::new();
+            GzDecoder::new(&body[..])
+                .read_to_end(&mut decoded)
+                .map_err(google_gmail1::Error::Io)?;
+            decoded
+        } else {
+            body.to_vec()
+        };
+
+        if !parts.status.is_success() {
+            // Gmail's rate limits come with a JSON body, which the error made from it loses the
+            // headers of, so Retry-After is read first
+            let retry_after = error::retry_after(&parts.headers);
+            let err = match serde_json::from_slice(&body) {
+                Ok(value) => google_gmail1::Error::BadRequest(value),
+                Err(_) => {
+                    google_gmail1::Error::Failure(Response::from_parts(parts, Body::from(body)))
+                }
+            };
+            return Err(GmailStatsError::from(err).with_retry_after(retry_after));
+        }
+        serde_json::from_slice(&body).map_err(|err| {
+            google_gmail1::Error::JsonDecodeError(String::from_utf8_lossy(&body).into_owned(), err)
+                .into()
+        })
+    }
+}
+
+// Everything in a query string except unreserved characters is percent-encoded
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+impl MailSource for GmailSource {
+    async fn list_page(
+        &self,
+        label: Option<&str>,
+        include_spam_trash: bool,
+        page_token: Option<&str>,
+    ) -> Result<MessagePage, GmailStatsError> {
+        // Fetch 500 messages at a time...
+        let mut path = format!(
+            "messages?maxResults=500&includeSpamTrash={}",
+            include_spam_trash
+        );
+        if let Some(label) = label {
+            path += &format!("&labelIds={}", encode(label));
+        }
+        if let Some(token) = page_token {
+            path += &format!("&pageToken={}", encode(token));
+        }
+
+        let response: ListMessagesResponse = self.get(&path).await?;
+        let messages = response.messages.unwrap_or_default();
+        let listed = messages.len();
+        let ids: Vec<String> = messages
+            .into_iter()
+            .filter_map(|message| message.id)
+            .collect();
+        Ok(MessagePage {
+            missing_ids: (listed - ids.len()) as u64,
+            ids,
+            next_page_token: response.next_page_token,
+        })
+    }
+
+    async fn get_message(&self, id: &str) -> Result<Message, GmailStatsError> {
+        self.get(&format!("messages/{}", encode(id))).await
+    }
+
+    async fn messages_total(&self) -> Result<Option<u64>, GmailStatsError> {
+        let profile: Profile = self.get("profile").await?;
+        Ok(profile
+            .messages_total
+            .and_then(|total| u64::try_from(total).ok()))
+    }
+
+    fn bytes_received(&self) -> Option<u64> {
+        Some(self.received.load(Ordering::Relaxed))
+    }
+
+    fn listing_id(&self) -> Option<String> {
+        Some("gmail".to_string())
+    }
+}
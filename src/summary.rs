@@ -0,0 +1,297 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::format;
+use crate::metrics::FetchStats;
+
+/// New messages which make a run notable for `--quiet-unless-changed`, unless
+/// `--notify-threshold` says otherwise.
+pub const NOTIFY_THRESHOLD: u64 = 1;
+
+/// The key numbers of a fetch or import which got to the end, printed as its last line.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Summary {
+    pub duration_secs: f64,
+    /// Listing pages asked for, retries included.
+    pub pages_listed: u64,
+    /// Messages fetched and recorded.
+    pub processed: u64,
+    /// Listed messages passed over because they'd been seen before.
+    pub already_seen: u64,
+    /// Senders counted for the first time.
+    pub new_senders: u64,
+    /// Messages skipped and recorded in the errors table.
+    pub skipped: u64,
+    /// Failed attempts by class, whether retried or not.
+    pub errors: BTreeMap<String, u64>,
+    pub quota_units: u64,
+    /// How much the database grew, or shrank if negative.
+    pub db_size_delta: i64,
+}
+
+impl Summary {
+    pub fn new(stats: &FetchStats, duration: Duration, db_size_delta: i64) -> Summary {
+        Summary {
+            duration_secs: duration.as_secs_f64(),
+            pages_listed: stats.list_calls,
+            processed: stats.processed,
+            already_seen: stats.already_seen,
+            new_senders: stats.new_senders,
+            skipped: stats.skipped,
+            errors: stats
+                .errors
+                .iter()
+                .map(|(class, count)| (class.to_string(), *count))
+                .collect(),
+            quota_units: stats.quota_units(),
+            db_size_delta,
+        }
+    }
+
+    /// Whether the run is worth telling anyone about under `--quiet-unless-changed`: it recorded
+    /// at least `threshold` new messages, found a new sender, or skipped messages or hit errors
+    /// along the way, even ones a retry got past.
+    pub fn is_notable(&self, threshold: u64) -> bool {
+        self.processed >= threshold
+            || self.new_senders > 0
+            || self.skipped > 0
+            || !self.errors.is_empty()
+    }
+
+    /// Whether the summary is printed at the end of a fetch: always, or under
+    /// `--quiet-unless-changed` only if the run is notable by `threshold`, `NOTIFY_THRESHOLD`
+    /// unless `--notify-threshold` was given.
+    pub fn is_printed(&self, quiet_unless_changed: bool, threshold: Option<u64>) -> bool {
+        !quiet_unless_changed || self.is_notable(threshold.unwrap_or(NOTIFY_THRESHOLD))
+    }
+
+    /// The summary as one line of JSON, for `--summary-json`.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("a summary always serializes")
+    }
+
+    /// The summary as one line for people.
+    pub fn describe(&self) -> String {
+        let errors = if self.errors.is_empty() {
+            "no errors".to_string()
+        } else {
+            format!(
+                "errors: {}",
+                self.errors
+                    .iter()
+                    .map(|(class, count)| format!("{} {}", class, format::thousands(*count as i64)))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        };
+        format!(
+            "done in {:.1}s: {} new messages, {} already seen, {} new senders, {} skipped, {}, \
+            {} pages listed, {} quota units, database {}{}",
+            self.duration_secs,
+            format::thousands(self.processed as i64),
+            format::thousands(self.already_seen as i64),
+            format::thousands(self.new_senders as i64),
+            format::thousands(self.skipped as i64),
+            errors,
+            format::thousands(self.pages_listed as i64),
+            format::thousands(self.quota_units as i64),
+            if self.db_size_delta < 0 { "-" } else { "+" },
+            format::bytes(self.db_size_delta.abs())
+        )
+    }
+
+    /// Print the summary at the end of a run: for people on stderr, and with `json` as the last
+    /// line of stdout too.
+    pub fn print(&self, json: bool) {
+        eprintln!("{}", self.describe());
+        if json {
+            println!("{}", self.to_json());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::GmailStatsError;
+    use crate::fetch::{self, Backoff, FetchOptions};
+    use crate::testsupport::{self, message, MockMailSource};
+
+    fn summary() -> Summary {
+        Summary {
+            duration_secs: 12.34,
+            pages_listed: 3,
+            processed: 1234,
+            already_seen: 56,
+            new_senders: 7,
+            skipped: 2,
+            errors: BTreeMap::from([("network".to_string(), 4), ("rate_limited".to_string(), 1)]),
+            quota_units: 6175,
+            db_size_delta: -2048,
+        }
+    }
+
+    #[test]
+    fn the_summary_is_built_from_the_run_counters() {
+        let mut stats = FetchStats {
+            list_calls: 2,
+            get_calls: 10,
+            processed: 8,
+            already_seen: 3,
+            new_senders: 4,
+            skipped: 1,
+            ..Default::default()
+        };
+        stats.errors.insert("network", 2);
+
+        let summary = Summary::new(&stats, Duration::from_millis(1500), 4096);
+
+        assert_eq!(
+            summary,
+            Summary {
+                duration_secs: 1.5,
+                pages_listed: 2,
+                processed: 8,
+                already_seen: 3,
+                new_senders: 4,
+                skipped: 1,
+                errors: BTreeMap::from([("network".to_string(), 2)]),
+                quota_units: stats.quota_units(),
+                db_size_delta: 4096,
+            }
+        );
+    }
+
+    #[test]
+    fn json_has_every_field() {
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&summary().to_json()).unwrap(),
+            serde_json::json!({
+                "duration_secs": 12.34,
+                "pages_listed": 3,
+                "processed": 1234,
+                "already_seen": 56,
+                "new_senders": 7,
+                "skipped": 2,
+                "errors": { "network": 4, "rate_limited": 1 },
+                "quota_units": 6175,
+                "db_size_delta": -2048,
+            })
+        );
+        assert!(!summary().to_json().contains('\n'));
+    }
+
+    #[test]
+    fn the_line_for_people() {
+        assert_eq!(
+            summary().describe(),
+            format!(
+                "done in 12.3s: 1,234 new messages, 56 already seen, 7 new senders, 2 skipped, \
+                errors: network 4, rate_limited 1, 3 pages listed, 6,175 quota units, database -{}",
+                format::bytes(2048)
+            )
+        );
+        let quiet = Summary {
+            errors: BTreeMap::new(),
+            db_size_delta: 0,
+            ..summary()
+        };
+        assert!(quiet.describe().contains(", no errors, "));
+        assert!(quiet
+            .describe()
+            .ends_with(&format!("database +{}", format::bytes(0))));
+    }
+
+    #[test]
+    fn a_run_is_notable_for_new_mail_senders_skips_or_errors() {
+        let nothing = Summary {
+            already_seen: 500,
+            pages_listed: 1,
+            quota_units: 5,
+            ..Default::default()
+        };
+        assert!(!nothing.is_notable(NOTIFY_THRESHOLD));
+
+        let new_mail = Summary {
+            processed: 3,
+            ..nothing.clone()
+        };
+        assert!(new_mail.is_notable(3));
+        assert!(!new_mail.is_notable(4));
+        // A threshold of 0 makes every run notable
+        assert!(nothing.is_notable(0));
+
+        for changed in [
+            Summary {
+                new_senders: 1,
+                ..nothing.clone()
+            },
+            Summary {
+                skipped: 1,
+                ..nothing.clone()
+            },
+            Summary {
+                errors: BTreeMap::from([("rate_limited".to_string(), 1)]),
+                ..nothing.clone()
+            },
+        ] {
+            assert!(changed.is_notable(100), "{:?}", changed);
+        }
+    }
+
+    #[test]
+    fn quiet_runs_print_only_when_notable() {
+        let nothing = Summary::default();
+        let one_new = Summary {
+            processed: 1,
+            ..Default::default()
+        };
+
+        assert!(nothing.is_printed(false, None));
+        assert!(!nothing.is_printed(true, None));
+        assert!(one_new.is_printed(true, None));
+        assert!(!one_new.is_printed(true, Some(2)));
+        assert!(nothing.is_printed(true, Some(0)));
+    }
+
+    #[tokio::test]
+    async fn a_fetch_summarizes_what_it_did() {
+        let pool = testsupport::pool().await;
+        let opts = FetchOptions {
+            max_retries: 1,
+            backoff: Backoff {
+                initial: Duration::ZERO,
+                max: Duration::ZERO,
+            },
+            quiet: true,
+            ..Default::default()
+        };
+        let first = MockMailSource::new().page([message(
+            "m1",
+            &[("From", "jane@example.com")],
+            &["INBOX"],
+        )]);
+        fetch::run(&pool, &first, &opts).await.unwrap();
+        let source = MockMailSource::new()
+            .page([
+                message("m1", &[("From", "jane@example.com")], &["INBOX"]),
+                message("m2", &[("From", "jane@example.com")], &["INBOX"]),
+            ])
+            .page([message("m3", &[("From", "bob@example.org")], &["INBOX"])])
+            .listing(&["gone"])
+            .fail_get("m2", 1, || GmailStatsError::Network("reset".to_string()));
+
+        let summary = fetch::run(&pool, &source, &opts).await.unwrap();
+
+        assert_eq!(summary.pages_listed, 3);
+        assert_eq!(summary.processed, 2);
+        assert_eq!(summary.already_seen, 1);
+        assert_eq!(summary.new_senders, 1);
+        assert_eq!(summary.skipped, 1);
+        // A message gone since it was listed is skipped rather than counted as an error
+        assert_eq!(summary.errors, BTreeMap::from([("network".to_string(), 1)]));
+        assert_eq!(summary.quota_units, 3 * 5 + 4 * 5);
+    }
+}
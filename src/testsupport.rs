@@ -0,0 +1,369 @@
+//! What the tests share: an in-memory database migrated as `stats.db` would be, messages built
+//! from headers the way the API returns them, and a mailbox to fetch them from which can be
+//! made to fail. Not part of the library's interface.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use google_gmail1::api::{Message, MessagePart, MessagePartBody, MessagePartHeader};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+
+use crate::analyze::RawHeaders;
+use crate::error::GmailStatsError;
+use crate::labels::LabelTarget;
+use crate::parse::{MessageInfo, ParseOptions};
+use crate::source::{MailSource, MessagePage};
+use crate::{db, search, Storage};
+
+/// A fresh in-memory database with every migration and the subject index. It has the one
+/// connection, since each connection to `sqlite::memory:` opens a database of its own.
+pub async fn pool() -> Storage {
+    let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with(options)
+        .await
+        .unwrap();
+    db::migrate(&pool).await.unwrap();
+    search::ensure_index(&pool).await.unwrap();
+    pool
+}
+
+/// The message the API would return for `id` with `headers` and `labels`.
+pub fn message(id: &str, headers: &[(&str, &str)], labels: &[&str]) -> Message {
+    RawHeaders {
+        labels: labels.iter().map(|label| label.to_string()).collect(),
+        ..RawHeaders::new(headers.iter().copied())
+    }
+    .to_message(id.to_string())
+}
+
+/// A MIME part of `mime_type` with `headers`, holding `body` base64url-encoded as the API sends
+/// it.
+pub fn part(mime_type: &str, headers: &[(&str, &str)], body: &str) -> MessagePart {
+    MessagePart {
+        mime_type: Some(mime_type.to_string()),
+        headers: Some(
+            headers
+                .iter()
+                .map(|(name, value)| MessagePartHeader {
+                    name: Some(name.to_string()),
+                    value: Some(value.to_string()),
+                })
+                .collect(),
+        ),
+        body: Some(MessagePartBody {
+            size: Some(body.len() as i32),
+            data: Some(URL_SAFE_NO_PAD.encode(body)),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// `message` made a `mime_type` multipart holding `parts`.
+pub fn with_parts(mut message: Message, mime_type: &str, parts: Vec<MessagePart>) -> Message {
+    let payload = message.payload.get_or_insert_with(Default::default);
+    payload.mime_type = Some(mime_type.to_string());
+    payload.parts = Some(parts);
+    message
+}
+
+/// Message `id` from `from`, received at `date` in epoch milliseconds, parsed as a fetch would.
+pub fn info(id: &str, from: &str, date: i64, labels: &[&str]) -> MessageInfo {
+    let mut message = message(id, &[("From", from)], labels);
+    message.internal_date = Some(date.to_string());
+    MessageInfo::from_message(&message, &ParseOptions::default()).unwrap()
+}
+
+// Makes the error a mocked call fails with
+type Fault = Arc<dyn Fn() -> GmailStatsError + Send + Sync>;
+
+// A call whose failures are queued up
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Call {
+    List(usize),
+    Get(String),
+    Modify,
+}
+
+/// One `LabelTarget::modify` call a `MockMailSource` took.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Modification {
+    pub ids: Vec<String>,
+    pub add: Vec<String>,
+    pub remove: Vec<String>,
+}
+
+/// A mailbox served from memory: pages of canned messages, with failures injected into given
+/// calls. Ids listed without a message answer 404, as Gmail does for mail deleted since. It
+/// takes labelling too, noting each change rather than making it.
+#[derive(Default)]
+pub struct MockMailSource {
+    pages: Vec<MessagePage>,
+    messages: HashMap<String, Message>,
+    faults: Mutex<HashMap<Call, VecDeque<Fault>>>,
+    hangs: HashSet<String>,
+    latency: Duration,
+    fetched: Mutex<Vec<String>>,
+    listed: Mutex<Vec<Option<String>>>,
+    labels: Mutex<Vec<(String, String)>>,
+    gone: HashSet<String>,
+    modified: Mutex<Vec<Modification>>,
+}
+
+impl MockMailSource {
+    pub fn new() -> MockMailSource {
+        MockMailSource::default()
+    }
+
+    /// Add a listing page of `messages`, served by `get_message`.
+    pub fn page(mut self, messages: impl IntoIterator<Item = Message>) -> MockMailSource {
+        let mut ids = Vec::new();
+        for message in messages {
+            let id = message.id.clone().expect("canned messages have ids");
+            ids.push(id.clone());
+            self.messages.insert(id, message);
+        }
+        self.push_page(ids)
+    }
+
+    /// Add a listing page of `ids` with no messages behind them.
+    pub fn listing(self, ids: &[&str]) -> MockMailSource {
+        self.push_page(ids.iter().map(|id| id.to_string()).collect())
+    }
+
+    /// Serve `message` for its id without listing it, as for `--retry-errors`.
+    pub fn unlisted_message(mut self, message: Message) -> MockMailSource {
+        let id = message.id.clone().expect("canned messages have ids");
+        self.messages.insert(id, message);
+        self
+    }
+
+    /// Answer a fetch of `id` with `message` whatever id it carries, if any, as a malformed
+    /// response would. `id` isn't listed.
+    pub fn answer(mut self, id: &str, message: Message) -> MockMailSource {
+        self.messages.insert(id.to_string(), message);
+        self
+    }
+
+    /// Have the last page list `count` entries without an id too.
+    pub fn without_ids(mut self, count: u64) -> MockMailSource {
+        self.pages.last_mut().expect("a page to add to").missing_ids += count;
+        self
+    }
+
+    /// Fail the next `times` listings of page `page`, counting from 0, with `fault`.
+    pub fn fail_list(
+        self,
+        page: usize,
+        times: usize,
+        fault: impl Fn() -> GmailStatsError + Send + Sync + 'static,
+    ) -> MockMailSource {
+        self.fail(Call::List(page), times, fault)
+    }
+
+    /// Fail the next `times` fetches of message `id` with `fault`.
+    pub fn fail_get(
+        self,
+        id: &str,
+        times: usize,
+        fault: impl Fn() -> GmailStatsError + Send + Sync + 'static,
+    ) -> MockMailSource {
+        self.fail(Call::Get(id.to_string()), times, fault)
+    }
+
+    /// Never answer a fetch of message `id`, for cancelling a fetch part way through one.
+    pub fn hang_on(mut self, id: &str) -> MockMailSource {
+        self.hangs.insert(id.to_string());
+        self
+    }
+
+    /// Take `latency` over every call, as the network would.
+    pub fn latency(mut self, latency: Duration) -> MockMailSource {
+        self.latency = latency;
+        self
+    }
+
+    /// Have the user label `name` with id `id` already.
+    pub fn label(self, name: &str, id: &str) -> MockMailSource {
+        self.labels
+            .lock()
+            .unwrap()
+            .push((name.to_string(), id.to_string()));
+        self
+    }
+
+    /// Refuse to modify message `id` with a 404, and any batch holding it with a 400, as
+    /// Gmail does for mail deleted since it was fetched.
+    pub fn gone(mut self, id: &str) -> MockMailSource {
+        self.gone.insert(id.to_string());
+        self
+    }
+
+    /// Fail the next `times` modify calls with `fault`.
+    pub fn fail_modify(
+        self,
+        times: usize,
+        fault: impl Fn() -> GmailStatsError + Send + Sync + 'static,
+    ) -> MockMailSource {
+        self.fail(Call::Modify, times, fault)
+    }
+
+    /// Every user label, `(name, id)`, the ones `label_id` created included.
+    pub fn labels(&self) -> Vec<(String, String)> {
+        self.labels.lock().unwrap().clone()
+    }
+
+    /// The modify calls which went through so far, in order.
+    pub fn modified(&self) -> Vec<Modification> {
+        self.modified.lock().unwrap().clone()
+    }
+
+    /// The ids of the messages fetched so far, in order, failed attempts included.
+    pub fn fetched(&self) -> Vec<String> {
+        self.fetched.lock().unwrap().clone()
+    }
+
+    /// The page token of every listing call so far, `None` for a first page.
+    pub fn listed(&self) -> Vec<Option<String>> {
+        self.listed.lock().unwrap().clone()
+    }
+
+    fn push_page(mut self, ids: Vec<String>) -> MockMailSource {
+        let token = format!("page{}", self.pages.len());
+        if let Some(last) = self.pages.last_mut() {
+            last.next_page_token = Some(token);
+        }
+        self.pages.push(MessagePage {
+            ids,
+            ..Default::default()
+        });
+        self
+    }
+
+    fn fail(
+        self,
+        call: Call,
+        times: usize,
+        fault: impl Fn() -> GmailStatsError + Send + Sync + 'static,
+    ) -> MockMailSource {
+        let fault: Fault = Arc::new(fault);
+        self.faults
+            .lock()
+            .unwrap()
+            .entry(call)
+            .or_default()
+            .extend((0..times).map(|_| fault.clone()));
+        self
+    }
+
+    // The injected failure of `call`, if there's one left
+    fn fault(&self, call: Call) -> Option<GmailStatsError> {
+        let fault = self.faults.lock().unwrap().get_mut(&call)?.pop_front()?;
+        Some(fault())
+    }
+}
+
+impl MailSource for MockMailSource {
+    async fn list_page(
+        &self,
+        label: Option<&str>,
+        _include_spam_trash: bool,
+        page_token: Option<&str>,
+    ) -> Result<MessagePage, GmailStatsError> {
+        tokio::time::sleep(self.latency).await;
+        self.listed
+            .lock()
+            .unwrap()
+            .push(page_token.map(str::to_string));
+        let index = page_token
+            .and_then(|token| token.strip_prefix("page"))
+            .and_then(|index| index.parse().ok())
+            .unwrap_or(0);
+        if let Some(err) = self.fault(Call::List(index)) {
+            return Err(err);
+        }
+        let mut page = self.pages.get(index).cloned().unwrap_or_default();
+        // A label's listing has just the messages carrying it
+        if let Some(label) = label {
+            page.ids.retain(|id| {
+                self.messages.get(id).is_some_and(|message| {
+                    message
+                        .label_ids
+                        .as_ref()
+                        .is_some_and(|labels| labels.iter().any(|l| l == label))
+                })
+            });
+        }
+        Ok(page)
+    }
+
+    async fn get_message(&self, id: &str) -> Result<Message, GmailStatsError> {
+        tokio::time::sleep(self.latency).await;
+        self.fetched.lock().unwrap().push(id.to_string());
+        if self.hangs.contains(id) {
+            std::future::pending::<()>().await;
+        }
+        if let Some(err) = self.fault(Call::Get(id.to_string())) {
+            return Err(err);
+        }
+        self.messages
+            .get(id)
+            .cloned()
+            .ok_or_else(|| GmailStatsError::Api {
+                status: Some(404),
+                message: "Requested entity was not found.".to_string(),
+            })
+    }
+
+    fn listing_id(&self) -> Option<String> {
+        Some("mock".to_string())
+    }
+}
+
+impl LabelTarget for MockMailSource {
+    async fn label_id(&self, name: &str) -> Result<String, GmailStatsError> {
+        let mut labels = self.labels.lock().unwrap();
+        if let Some((_, id)) = labels
+            .iter()
+            .find(|(existing, _)| existing.eq_ignore_ascii_case(name))
+        {
+            return Ok(id.clone());
+        }
+        let id = format!("Label_{}", labels.len() + 1);
+        labels.push((name.to_string(), id.clone()));
+        Ok(id)
+    }
+
+    async fn modify(
+        &self,
+        ids: &[String],
+        add: &[&str],
+        remove: &[&str],
+    ) -> Result<(), GmailStatsError> {
+        tokio::time::sleep(self.latency).await;
+        if let Some(err) = self.fault(Call::Modify) {
+            return Err(err);
+        }
+        if ids.iter().any(|id| self.gone.contains(id)) {
+            return Err(GmailStatsError::Api {
+                status: Some(if ids.len() == 1 { 404 } else { 400 }),
+                message: "Requested entity was not found.".to_string(),
+            });
+        }
+        let strings = |labels: &[&str]| labels.iter().map(|label| label.to_string()).collect();
+        self.modified.lock().unwrap().push(Modification {
+            ids: ids.to_vec(),
+            add: strings(add),
+            remove: strings(remove),
+        });
+        Ok(())
+    }
+}
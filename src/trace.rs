@@ -0,0 +1,175 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+/// The timeline a trace's spans are drawn on. Listing runs alongside the rest once pages are
+/// prefetched, so it gets its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Track {
+    Listing = 1,
+    Messages = 2,
+}
+
+impl Track {
+    fn name(self) -> &'static str {
+        match self {
+            Track::Listing => "listing",
+            Track::Messages => "messages",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Span {
+    name: &'static str,
+    track: Track,
+    start: Duration,
+    duration: Duration,
+}
+
+/// Spans recorded over a fetch, written out for `fetch --trace-out` in the Chrome trace event
+/// format that Perfetto and chrome://tracing open.
+#[derive(Clone, Debug)]
+pub struct Trace {
+    origin: Instant,
+    spans: Vec<Span>,
+}
+
+impl Trace {
+    /// A trace whose timestamps count from now.
+    pub fn new() -> Trace {
+        Trace {
+            origin: Instant::now(),
+            spans: Vec::new(),
+        }
+    }
+
+    /// Note `name` running on `track` for `duration` from `started`.
+    pub fn span(&mut self, name: &'static str, track: Track, started: Instant, duration: Duration) {
+        self.spans.push(Span {
+            name,
+            track,
+            start: started.saturating_duration_since(self.origin),
+            duration,
+        });
+    }
+
+    /// The trace as a JSON object with a complete ("X") event per span and the tracks named.
+    pub fn to_json(&self) -> Value {
+        let names = [Track::Listing, Track::Messages].map(|track| {
+            json!({
+                "name": "thread_name",
+                "ph": "M",
+                "pid": 1,
+                "tid": track as u32,
+                "args": { "name": track.name() },
+            })
+        });
+        let spans = self.spans.iter().map(|span| {
+            json!({
+                "name": span.name,
+                "cat": "fetch",
+                "ph": "X",
+                // Microseconds, which is what the format counts in
+                "ts": span.start.as_secs_f64() * 1e6,
+                "dur": span.duration.as_secs_f64() * 1e6,
+                "pid": 1,
+                "tid": span.track as u32,
+            })
+        });
+        json!({
+            "traceEvents": names.into_iter().chain(spans).collect::<Vec<_>>(),
+            "displayTimeUnit": "ms",
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_json().to_string())
+    }
+}
+
+impl Default for Trace {
+    fn default() -> Trace {
+        Trace::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::{self, FetchOptions};
+    use crate::testsupport::{self, message, MockMailSource};
+
+    #[test]
+    fn spans_are_complete_events_on_named_tracks() {
+        let mut trace = Trace::new();
+        let started = trace.origin + Duration::from_millis(2);
+        trace.span(
+            "messages.list",
+            Track::Listing,
+            started,
+            Duration::from_micros(1500),
+        );
+        trace.span("db", Track::Messages, started, Duration::from_micros(250));
+        // Before the origin counts from it rather than going negative
+        trace.span("parse", Track::Messages, trace.origin, Duration::ZERO);
+
+        assert_eq!(
+            trace.to_json(),
+            json!({
+                "traceEvents": [
+                    { "name": "thread_name", "ph": "M", "pid": 1, "tid": 1,
+                        "args": { "name": "listing" } },
+                    { "name": "thread_name", "ph": "M", "pid": 1, "tid": 2,
+                        "args": { "name": "messages" } },
+                    { "name": "messages.list", "cat": "fetch", "ph": "X", "ts": 2000.0,
+                        "dur": 1500.0, "pid": 1, "tid": 1 },
+                    { "name": "db", "cat": "fetch", "ph": "X", "ts": 2000.0, "dur": 250.0,
+                        "pid": 1, "tid": 2 },
+                    { "name": "parse", "cat": "fetch", "ph": "X", "ts": 0.0, "dur": 0.0,
+                        "pid": 1, "tid": 2 },
+                ],
+                "displayTimeUnit": "ms",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn a_fetch_writes_a_trace_that_parses_as_json() {
+        let path =
+            std::env::temp_dir().join(format!("gmail-stats-trace-{}.json", std::process::id()));
+        let pool = testsupport::pool().await;
+        let source = MockMailSource::new().page([
+            message("m1", &[("From", "jane@example.com")], &["INBOX"]),
+            message("m2", &[("From", "bob@example.org")], &["INBOX"]),
+        ]);
+        let opts = FetchOptions {
+            trace_out: Some(path.clone()),
+            quiet: true,
+            ..Default::default()
+        };
+
+        fetch::run(&pool, &source, &opts).await.unwrap();
+        let written: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(written["displayTimeUnit"], "ms");
+        let events = written["traceEvents"].as_array().unwrap();
+        let spans = events
+            .iter()
+            .filter(|event| event["ph"] == "X")
+            .collect::<Vec<_>>();
+        assert_eq!(events.len() - spans.len(), 2);
+        for span in &spans {
+            assert!(span["ts"].as_f64().unwrap() >= 0.0, "{}", span);
+            assert!(span["dur"].as_f64().unwrap() >= 0.0, "{}", span);
+            assert!(matches!(span["tid"].as_u64(), Some(1 | 2)), "{}", span);
+        }
+        let named = |name: &str| spans.iter().filter(|span| span["name"] == name).count();
+        assert!(named("messages.list") >= 1);
+        assert_eq!(named("messages.get"), 2);
+    }
+}
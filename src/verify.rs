@@ -0,0 +1,516 @@
+use sqlx::{Pool, Sqlite};
+
+use crate::cli::{RepairOrphansArgs, VerifyArgs};
+use crate::config::Config;
+use crate::format;
+
+/// Offending rows shown per failed check.
+const EXAMPLES: i64 = 5;
+
+/// Something which should always hold of the database.
+pub struct Invariant {
+    pub name: &'static str,
+    /// A query giving one line per row which breaks it.
+    pub offenders: &'static str,
+    /// Statements which put it right, for invariants `--repair` can fix without guessing.
+    pub repair: &'static [&'static str],
+}
+
+/// Everything `verify` checks, in the order it checks them.
+pub const INVARIANTS: [Invariant; 7] = [
+    Invariant {
+        // Mail fetched before message rows were kept, or whose rows retention has deleted, is
+        // only in the count, so a count can be higher than its rows but never lower. My own sent
+        // mail is recorded without being counted.
+        name: "senders' counts cover their message rows",
+        offenders: "SELECT s.sender || ': counted ' || s.mails_sent || ', recorded ' || count(*)
+            FROM messages m JOIN senders s ON s.sender = m.sender
+            WHERE NOT m.is_spam AND NOT s.is_ignored
+                AND (m.account, m.mail_id) NOT IN
+                    (SELECT account, mail_id FROM message_labels WHERE label = 'SENT')
+            GROUP BY s.sender HAVING count(*) > coalesce(s.mails_sent, 0)",
+        repair: &[
+            "UPDATE senders SET mails_sent = (SELECT count(*) FROM messages m
+                WHERE m.sender = senders.sender AND NOT m.is_spam
+                    AND (m.account, m.mail_id) NOT IN
+                        (SELECT account, mail_id FROM message_labels WHERE label = 'SENT'))
+            WHERE NOT is_ignored AND coalesce(mails_sent, 0) < (SELECT count(*) FROM messages m
+                WHERE m.sender = senders.sender AND NOT m.is_spam
+                    AND (m.account, m.mail_id) NOT IN
+                        (SELECT account, mail_id FROM message_labels WHERE label = 'SENT'))",
+        ],
+    },
+    Invariant {
+        name: "spam counts cover their message rows",
+        offenders: "SELECT s.sender || ': counted ' || s.mails_sent || ', recorded ' || count(*)
+            FROM messages m JOIN spam_senders s ON s.sender = m.sender
+            WHERE m.is_spam
+            GROUP BY s.sender HAVING count(*) > s.mails_sent",
+        repair: &[
+            "UPDATE spam_senders SET mails_sent = (SELECT count(*) FROM messages m
+                WHERE m.sender = spam_senders.sender AND m.is_spam)
+            WHERE mails_sent < (SELECT count(*) FROM messages m
+                WHERE m.sender = spam_senders.sender AND m.is_spam)",
+        ],
+    },
+    Invariant {
+        name: "each message is marked seen once",
+        offenders: "SELECT account || '/' || mail_id || ': ' || count(*) || ' times'
+            FROM seen_mails GROUP BY account, mail_id HAVING count(*) > 1",
+        repair: &["DELETE FROM seen_mails WHERE rowid NOT IN
+            (SELECT min(rowid) FROM seen_mails GROUP BY account, mail_id)"],
+    },
+    Invariant {
+        name: "message rows are marked seen",
+        offenders: "SELECT m.account || '/' || m.mail_id FROM messages m
+            WHERE NOT EXISTS (SELECT 1 FROM seen_mails s
+                WHERE s.mail_id = m.mail_id AND s.account = m.account)",
+        repair: &["INSERT INTO seen_mails (mail_id, account)
+            SELECT m.mail_id, m.account FROM messages m
+            WHERE NOT EXISTS (SELECT 1 FROM seen_mails s
+                WHERE s.mail_id = m.mail_id AND s.account = m.account)"],
+    },
+    Invariant {
+        name: "senders have an address",
+        offenders: "SELECT coalesce(quote(sender), 'NULL') || ': ' || coalesce(mails_sent, 0)
+                || ' messages'
+            FROM senders WHERE sender IS NULL OR instr(sender, '@') < 2
+                OR instr(sender, '@') = length(sender)",
+        repair: &[],
+    },
+    Invariant {
+        name: "labels, recipients and attachments belong to a message",
+        offenders: "SELECT 'label ' || label || ' of ' || account || '/' || mail_id
+                FROM message_labels
+                WHERE (account, mail_id) NOT IN (SELECT account, mail_id FROM messages)
+            UNION ALL SELECT 'recipient of ' || account || '/' || mail_id FROM message_recipients
+                WHERE (account, mail_id) NOT IN (SELECT account, mail_id FROM messages)
+            UNION ALL SELECT 'attachment of ' || account || '/' || mail_id FROM attachments
+                WHERE (account, mail_id) NOT IN (SELECT account, mail_id FROM messages)",
+        repair: &[
+            "DELETE FROM message_labels
+                WHERE (account, mail_id) NOT IN (SELECT account, mail_id FROM messages)",
+            "DELETE FROM message_recipients
+                WHERE (account, mail_id) NOT IN (SELECT account, mail_id FROM messages)",
+            "DELETE FROM attachments
+                WHERE (account, mail_id) NOT IN (SELECT account, mail_id FROM messages)",
+        ],
+    },
+    Invariant {
+        name: "messages and errors belong to a run",
+        offenders: "SELECT 'message ' || mail_id || ' of run ' || run_id FROM messages
+                WHERE run_id IS NOT NULL AND run_id NOT IN (SELECT id FROM runs)
+            UNION ALL SELECT 'error of run ' || run_id FROM errors
+                WHERE run_id IS NOT NULL AND run_id NOT IN (SELECT id FROM runs)",
+        repair: &[
+            "UPDATE messages SET run_id = NULL
+            WHERE run_id IS NOT NULL AND run_id NOT IN (SELECT id FROM runs)",
+            "UPDATE errors SET run_id = NULL
+            WHERE run_id IS NOT NULL AND run_id NOT IN (SELECT id FROM runs)",
+        ],
+    },
+];
+
+/// How one invariant held up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outcome {
+    pub name: &'static str,
+    /// Rows breaking it, none if it holds.
+    pub offenders: i64,
+    /// The first few of them.
+    pub examples: Vec<String>,
+    pub repairable: bool,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        self.offenders == 0
+    }
+}
+
+/// Check one invariant.
+pub async fn check(pool: &Pool<Sqlite>, invariant: &Invariant) -> anyhow::Result<Outcome> {
+    let (offenders,): (i64,) =
+        sqlx::query_as(&format!("SELECT count(*) FROM ({})", invariant.offenders))
+            .fetch_one(pool)
+            .await?;
+    let examples: Vec<(String,)> =
+        sqlx::query_as(&format!("{} LIMIT {}", invariant.offenders, EXAMPLES))
+            .fetch_all(pool)
+            .await?;
+    Ok(Outcome {
+        name: invariant.name,
+        offenders,
+        examples: examples.into_iter().map(|(example,)| example).collect(),
+        repairable: !invariant.repair.is_empty(),
+    })
+}
+
+/// Check every invariant.
+pub async fn check_all(pool: &Pool<Sqlite>) -> anyhow::Result<Vec<Outcome>> {
+    let mut outcomes = Vec::with_capacity(INVARIANTS.len());
+    for invariant in &INVARIANTS {
+        outcomes.push(check(pool, invariant).await?);
+    }
+    Ok(outcomes)
+}
+
+/// Put right every broken invariant which can be, in one transaction.
+pub async fn repair(pool: &Pool<Sqlite>, outcomes: &[Outcome]) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    for invariant in &INVARIANTS {
+        let broken = outcomes
+            .iter()
+            .any(|outcome| outcome.name == invariant.name && !outcome.passed());
+        if !broken {
+            continue;
+        }
+        for statement in invariant.repair {
+            sqlx::query(statement).execute(&mut tx).await?;
+        }
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+fn print(outcomes: &[Outcome]) {
+    for outcome in outcomes {
+        if outcome.passed() {
+            println!("ok    {}", outcome.name);
+            continue;
+        }
+        println!(
+            "FAIL  {}: {} rows{}",
+            outcome.name,
+            format::thousands(outcome.offenders),
+            if outcome.repairable {
+                ", --repair fixes it"
+            } else {
+                ""
+            }
+        );
+        for example in &outcome.examples {
+            println!("        {}", example);
+        }
+    }
+}
+
+/// `verify`: check the database, repairing what can be with `--repair`, and fail if anything
+/// is still broken.
+pub async fn run(pool: &Pool<Sqlite>, args: &VerifyArgs) -> anyhow::Result<()> {
+    let mut outcomes = check_all(pool).await?;
+    print(&outcomes);
+    if args.repair && outcomes.iter().any(|o| !o.passed() && o.repairable) {
+        repair(pool, &outcomes).await?;
+        println!("\nafter repairing:");
+        outcomes = check_all(pool).await?;
+        print(&outcomes);
+    }
+    let failed = outcomes.iter().filter(|outcome| !outcome.passed()).count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} checks failed", failed, outcomes.len());
+    }
+    Ok(())
+}
+
+/// Seen marks with no message row, from after message rows were first kept. A crash between
+/// marking a message seen and recording it, before the two were one transaction, left these
+/// behind, and the mail is never fetched again. Seen marks from before the first message row
+/// are mail fetched before there were message rows at all, and aren't orphans, and nor are marks
+/// noted as having no row by design, such as mail from ignored senders. The anti-join uses the
+/// messages primary key, so it's one pass over seen_mails.
+const ORPHANED_SEEN: &str = "SELECT s.rowid FROM seen_mails s
+    WHERE s.rowid > (SELECT min(first.rowid) FROM seen_mails first
+            WHERE (first.account, first.mail_id) IN (SELECT account, mail_id FROM messages))
+        AND s.unrecorded IS NULL
+        AND NOT EXISTS (SELECT 1 FROM messages m
+            WHERE m.account = s.account AND m.mail_id = s.mail_id)";
+
+/// How many seen marks are orphaned.
+pub async fn orphaned_seen(pool: &Pool<Sqlite>) -> anyhow::Result<u64> {
+    let (count,): (i64,) = sqlx::query_as(&format!("SELECT count(*) FROM ({})", ORPHANED_SEEN))
+        .fetch_one(pool)
+        .await?;
+    Ok(count as u64)
+}
+
+/// Delete the orphaned seen marks, so the next fetch fetches and counts their mail.
+pub async fn delete_orphaned_seen(pool: &Pool<Sqlite>) -> anyhow::Result<u64> {
+    let deleted = sqlx::query(&format!(
+        "DELETE FROM seen_mails WHERE rowid IN ({})",
+        ORPHANED_SEEN
+    ))
+    .execute(pool)
+    .await?
+    .rows_affected();
+    Ok(deleted)
+}
+
+/// `db repair-orphans`: unmark the orphaned seen marks, or with `--dry-run` count them.
+pub async fn repair_orphans(
+    pool: &Pool<Sqlite>,
+    args: &RepairOrphansArgs,
+    config: &Config,
+) -> anyhow::Result<()> {
+    // Expired message rows look just like orphans, and fetching their mail again would count it
+    // twice
+    if config.retention.messages.is_some() {
+        anyhow::bail!(
+            "with a [retention] period for messages, deleted rows can't be told from orphans; \
+            remove it to repair orphans"
+        );
+    }
+    if args.dry_run {
+        println!(
+            "{} seen messages have no message row",
+            format::thousands(orphaned_seen(pool).await? as i64)
+        );
+        return Ok(());
+    }
+    let deleted = delete_orphaned_seen(pool).await?;
+    println!(
+        "unmarked {} seen messages with no message row, the next fetch fetches them again",
+        format::thousands(deleted as i64)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, DEFAULT_ACCOUNT};
+    use crate::fetch::{self, FetchOptions};
+    use crate::ignore::IgnoreList;
+    use crate::own::Identity;
+    use crate::testsupport::{self, info, message, MockMailSource};
+    use sqlx::Executor;
+
+    async fn record(pool: &Pool<Sqlite>, id: &str, account: &str) {
+        let mut conn = pool.acquire().await.unwrap();
+        let message = info(id, "jane@example.com", 0, &["INBOX"]);
+        db::record_message(
+            &message,
+            account,
+            None,
+            chrono::DateTime::UNIX_EPOCH,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+    }
+
+    async fn seen(pool: &Pool<Sqlite>, ids: &[&str], account: &str) {
+        let mut conn = pool.acquire().await.unwrap();
+        let ids = ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        db::mark_seen(&ids, account, &mut conn).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_seen_mark_without_its_row_is_an_orphan() {
+        let pool = testsupport::pool().await;
+        seen(&pool, &["early"], DEFAULT_ACCOUNT).await;
+        seen(&pool, &["m1", "m2"], DEFAULT_ACCOUNT).await;
+        record(&pool, "m1", DEFAULT_ACCOUNT).await;
+
+        // Before the first message row, "early" was fetched before rows were kept
+        assert_eq!(orphaned_seen(&pool).await.unwrap(), 1);
+        assert_eq!(delete_orphaned_seen(&pool).await.unwrap(), 1);
+        assert!(!db::seen_mail("m2", DEFAULT_ACCOUNT, &pool).await.unwrap());
+        assert!(db::seen_mail("early", DEFAULT_ACCOUNT, &pool)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_row_in_another_account_doesnt_cover_a_seen_mark() {
+        let pool = testsupport::pool().await;
+        seen(&pool, &["m1"], DEFAULT_ACCOUNT).await;
+        seen(&pool, &["m1"], "work").await;
+        record(&pool, "m1", DEFAULT_ACCOUNT).await;
+
+        assert_eq!(orphaned_seen(&pool).await.unwrap(), 1);
+        delete_orphaned_seen(&pool).await.unwrap();
+        assert!(!db::seen_mail("m1", "work", &pool).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn marks_without_a_row_by_design_arent_orphans() {
+        let pool = testsupport::pool().await;
+        record(&pool, "m0", DEFAULT_ACCOUNT).await;
+        seen(&pool, &["m0"], DEFAULT_ACCOUNT).await;
+        let reasons = ["ignored", "own", "erased", "duplicate", "expired"];
+        for reason in reasons {
+            seen(&pool, &[reason], DEFAULT_ACCOUNT).await;
+            db::mark_unrecorded(reason, reason, DEFAULT_ACCOUNT, &pool)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(orphaned_seen(&pool).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn a_dry_run_counts_orphans_marked_in_bulk_and_leaves_them() {
+        let pool = testsupport::pool().await;
+        record(&pool, "m0", DEFAULT_ACCOUNT).await;
+        // More than one insert statement's worth, as a big fetch batch marks them
+        let ids = (0..1200).map(|n| format!("m{}", n)).collect::<Vec<_>>();
+        let mut conn = pool.acquire().await.unwrap();
+        db::mark_seen(&ids, DEFAULT_ACCOUNT, &mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+        let config = Config::default();
+
+        repair_orphans(&pool, &RepairOrphansArgs { dry_run: true }, &config)
+            .await
+            .unwrap();
+        assert_eq!(orphaned_seen(&pool).await.unwrap(), 1199);
+        assert_eq!(db::seen_count(DEFAULT_ACCOUNT, &pool).await.unwrap(), 1200);
+
+        repair_orphans(&pool, &RepairOrphansArgs { dry_run: false }, &config)
+            .await
+            .unwrap();
+        assert_eq!(orphaned_seen(&pool).await.unwrap(), 0);
+        assert_eq!(db::seen_count(DEFAULT_ACCOUNT, &pool).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn mail_a_fetch_leaves_out_isnt_orphaned() {
+        let pool = testsupport::pool().await;
+        let source = MockMailSource::new().page([
+            message("m1", &[("From", "jane@example.com")], &["INBOX"]),
+            message("m2", &[("From", "news@example.org")], &["INBOX"]),
+            message("m3", &[("From", "me@example.net")], &["INBOX"]),
+        ]);
+        let config = Config {
+            my_addresses: vec!["me@example.net".to_string()],
+            ..Default::default()
+        };
+        let opts = FetchOptions {
+            ignore: IgnoreList::new(&["news@example.org".to_string()]),
+            own: Identity::new(&config),
+            quiet: true,
+            ..Default::default()
+        };
+
+        fetch::run(&pool, &source, &opts).await.unwrap();
+
+        assert_eq!(db::seen_count(DEFAULT_ACCOUNT, &pool).await.unwrap(), 3);
+        assert_eq!(orphaned_seen(&pool).await.unwrap(), 0);
+    }
+
+    // One message from jane, recorded by a run, marked seen and counted: every invariant holds
+    async fn healthy() -> Pool<Sqlite> {
+        let pool = testsupport::pool().await;
+        let run = db::start_run(DEFAULT_ACCOUNT, chrono::DateTime::UNIX_EPOCH, &pool)
+            .await
+            .unwrap();
+        let message = info("m1", "jane@example.com", 0, &["INBOX"]);
+        let mut conn = pool.acquire().await.unwrap();
+        db::record_message(
+            &message,
+            DEFAULT_ACCOUNT,
+            Some(run),
+            chrono::DateTime::UNIX_EPOCH,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        let mut counts = crate::analyze::SenderCounts::default();
+        counts.add(&message);
+        db::add_sender_counts("jane@example.com", &counts, &mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+        seen(&pool, &["m1"], DEFAULT_ACCOUNT).await;
+        pool
+    }
+
+    #[tokio::test]
+    async fn a_consistent_database_passes_every_check() {
+        let pool = healthy().await;
+
+        let outcomes = check_all(&pool).await.unwrap();
+
+        assert_eq!(outcomes.len(), INVARIANTS.len());
+        assert!(outcomes.iter().all(Outcome::passed), "{:#?}", outcomes);
+    }
+
+    #[tokio::test]
+    async fn each_invariant_catches_its_own_corruption() {
+        // (invariant, statements breaking it, the offender shown)
+        let corruptions: [(&str, &str, &str); 7] = [
+            (
+                "senders' counts cover their message rows",
+                "UPDATE senders SET mails_sent = 0",
+                "jane@example.com: counted 0, recorded 1",
+            ),
+            (
+                "spam counts cover their message rows",
+                "UPDATE messages SET is_spam = 1;
+                INSERT INTO spam_senders (sender, mails_sent) VALUES ('jane@example.com', 0)",
+                "jane@example.com: counted 0, recorded 1",
+            ),
+            (
+                // Only a database from before seen marks were unique can have these
+                "each message is marked seen once",
+                "DROP INDEX seen_mails_account_mail_id;
+                INSERT INTO seen_mails (mail_id, account) VALUES ('m1', 'default')",
+                "default/m1: 2 times",
+            ),
+            (
+                "message rows are marked seen",
+                "DELETE FROM seen_mails",
+                "default/m1",
+            ),
+            (
+                "senders have an address",
+                "INSERT INTO senders (sender, mails_sent) VALUES ('jane', 2)",
+                "'jane': 2 messages",
+            ),
+            (
+                "labels, recipients and attachments belong to a message",
+                "INSERT INTO message_labels (account, mail_id, label)
+                VALUES ('default', 'gone', 'INBOX')",
+                "label INBOX of default/gone",
+            ),
+            (
+                "messages and errors belong to a run",
+                "DELETE FROM runs",
+                "message m1 of run 1",
+            ),
+        ];
+        assert_eq!(corruptions.len(), INVARIANTS.len());
+
+        for (name, corrupt, example) in corruptions {
+            let pool = healthy().await;
+            // As a database written before foreign keys were enforced could be
+            pool.execute("PRAGMA foreign_keys = OFF").await.unwrap();
+            pool.execute(corrupt).await.unwrap();
+
+            let outcomes = check_all(&pool).await.unwrap();
+            let failed = outcomes
+                .iter()
+                .filter(|outcome| !outcome.passed())
+                .collect::<Vec<_>>();
+            assert_eq!(failed.len(), 1, "{}: {:#?}", name, failed);
+            assert_eq!(failed[0].name, name);
+            assert_eq!(failed[0].examples, [example], "{}", name);
+
+            repair(&pool, &outcomes).await.unwrap();
+            let still_failing = check_all(&pool)
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|outcome| !outcome.passed())
+                .count();
+            assert_eq!(
+                still_failing,
+                usize::from(!failed[0].repairable),
+                "{}",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use chrono::{TimeZone, Utc};
+use gmail_stats::backfill::{self, BackfillOptions};
+use gmail_stats::cli::BackfillField;
+use gmail_stats::clock::Clock;
+use gmail_stats::db::{self, DEFAULT_ACCOUNT};
+use gmail_stats::testsupport::{self, info, message, MockMailSource};
+use sqlx::{Pool, Sqlite};
+
+fn options(record_missing: bool) -> BackfillOptions {
+    BackfillOptions {
+        fields: vec![BackfillField::Subject],
+        parse: Default::default(),
+        ignore: Default::default(),
+        erased: Default::default(),
+        own: Default::default(),
+        account: None,
+        record_missing,
+        cancel: Default::default(),
+        max_retries: 0,
+        clock: Clock::Fixed(Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()),
+    }
+}
+
+fn with_subject(id: &str) -> google_gmail1::api::Message {
+    message(
+        id,
+        &[("From", "jane@example.com"), ("Subject", "Lunch?")],
+        &["INBOX"],
+    )
+}
+
+// m1 is recorded without a subject, m2 with one, and m3 is seen with no row at all
+async fn mailbox() -> (Pool<Sqlite>, MockMailSource) {
+    let pool = testsupport::pool().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let mut m2 = info("m2", "jane@example.com", 0, &["INBOX"]);
+    m2.subject = Some("Lunch?".to_string());
+    for message in [info("m1", "jane@example.com", 0, &["INBOX"]), m2] {
+        db::record_message(
+            &message,
+            DEFAULT_ACCOUNT,
+            None,
+            chrono::DateTime::UNIX_EPOCH,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+    }
+    let seen = ["m1", "m2", "m3"].map(str::to_string);
+    db::mark_seen(&seen, DEFAULT_ACCOUNT, &mut conn)
+        .await
+        .unwrap();
+    drop(conn);
+    let source = MockMailSource::new()
+        .unlisted_message(with_subject("m1"))
+        .unlisted_message(with_subject("m2"))
+        .unlisted_message(with_subject("m3"));
+    (pool, source)
+}
+
+async fn subjects(pool: &Pool<Sqlite>) -> Vec<(String, Option<String>)> {
+    sqlx::query_as("SELECT mail_id, subject FROM messages ORDER BY mail_id")
+        .fetch_all(pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn only_missing_columns_and_rows_are_fetched() {
+    let (pool, source) = mailbox().await;
+
+    let done = backfill::run(&pool, &source, &options(true)).await.unwrap();
+
+    assert_eq!(source.fetched(), ["m3", "m1"]);
+    assert_eq!((done.filled, done.recorded), (1, 1));
+    let lunch = Some("Lunch?".to_string());
+    assert_eq!(
+        subjects(&pool).await,
+        [
+            ("m1".to_string(), lunch.clone()),
+            ("m2".to_string(), lunch.clone()),
+            ("m3".to_string(), lunch)
+        ]
+    );
+    // Nothing is left, so another backfill fetches nothing
+    let again = MockMailSource::new();
+    backfill::run(&pool, &again, &options(true)).await.unwrap();
+    assert!(again.fetched().is_empty());
+}
+
+#[tokio::test]
+async fn rows_which_may_have_expired_are_not_recorded_again() {
+    let (pool, source) = mailbox().await;
+
+    let done = backfill::run(&pool, &source, &options(false))
+        .await
+        .unwrap();
+
+    assert_eq!(source.fetched(), ["m1"]);
+    assert_eq!((done.filled, done.recorded), (1, 0));
+    assert_eq!(subjects(&pool).await.len(), 2);
+}
@@ -0,0 +1,301 @@
+use gmail_stats::analyze::SenderCounts;
+use gmail_stats::cli::SortBy;
+use gmail_stats::db::{self, DEFAULT_ACCOUNT};
+use gmail_stats::report::{self, SenderFilter};
+use gmail_stats::testsupport::{self, info};
+
+fn ids(ids: &[&str]) -> Vec<String> {
+    ids.iter().map(|id| id.to_string()).collect()
+}
+
+#[tokio::test]
+async fn marked_ids_are_seen_in_their_account_only() {
+    let pool = testsupport::pool().await;
+    assert!(!db::seen_mail("a", DEFAULT_ACCOUNT, &pool).await.unwrap());
+
+    let mut conn = pool.acquire().await.unwrap();
+    db::mark_seen(&ids(&["a", "b"]), DEFAULT_ACCOUNT, &mut conn)
+        .await
+        .unwrap();
+    drop(conn);
+
+    assert!(db::seen_mail("a", DEFAULT_ACCOUNT, &pool).await.unwrap());
+    assert!(db::seen_mail("b", DEFAULT_ACCOUNT, &pool).await.unwrap());
+    assert!(!db::seen_mail("c", DEFAULT_ACCOUNT, &pool).await.unwrap());
+    assert!(!db::seen_mail("a", "work", &pool).await.unwrap());
+    let rows: Vec<(String, String)> =
+        sqlx::query_as("SELECT mail_id, account FROM seen_mails ORDER BY mail_id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        rows,
+        [
+            ("a".to_string(), "default".to_string()),
+            ("b".to_string(), "default".to_string())
+        ]
+    );
+}
+
+#[tokio::test]
+async fn marking_seen_splits_at_the_bind_limit() {
+    let pool = testsupport::pool().await;
+    let many = (0..1200).map(|n| format!("id{}", n)).collect::<Vec<_>>();
+    let mut conn = pool.acquire().await.unwrap();
+    db::mark_seen(&many, DEFAULT_ACCOUNT, &mut conn)
+        .await
+        .unwrap();
+    drop(conn);
+
+    assert_eq!(db::seen_count(DEFAULT_ACCOUNT, &pool).await.unwrap(), 1200);
+    assert!(db::seen_mail("id1199", DEFAULT_ACCOUNT, &pool)
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn sender_counts_add_up_across_calls() {
+    let pool = testsupport::pool().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let mut created = 0;
+    for day in 0..100 {
+        let mut counts = SenderCounts::default();
+        counts.add(&info(
+            "x",
+            "news@example.com",
+            day * 86_400_000,
+            &["UNREAD"],
+        ));
+        if db::add_sender_counts("news@example.com", &counts, &mut conn)
+            .await
+            .unwrap()
+        {
+            created += 1;
+        }
+    }
+    drop(conn);
+
+    assert_eq!(created, 1);
+    let row: (i64, i64, Option<i64>, Option<i64>) = sqlx::query_as(
+        "SELECT mails_sent, unread_count, first_seen, last_seen FROM senders
+        WHERE sender = 'news@example.com'",
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(row, (100, 100, Some(0), Some(99 * 86_400_000)));
+}
+
+#[tokio::test]
+async fn recorded_messages_keep_their_labels() {
+    let pool = testsupport::pool().await;
+    let message = info("m1", "Jane <jane@example.com>", 1_000, &["INBOX", "UNREAD"]);
+    let mut conn = pool.acquire().await.unwrap();
+    db::record_message(
+        &message,
+        DEFAULT_ACCOUNT,
+        None,
+        chrono::DateTime::UNIX_EPOCH,
+        &mut conn,
+    )
+    .await
+    .unwrap();
+    // Recording it again replaces it rather than adding to it
+    db::record_message(
+        &message,
+        DEFAULT_ACCOUNT,
+        None,
+        chrono::DateTime::UNIX_EPOCH,
+        &mut conn,
+    )
+    .await
+    .unwrap();
+    drop(conn);
+
+    let row: (String, String, bool, Option<i64>) =
+        sqlx::query_as("SELECT mail_id, sender, is_unread, internal_date FROM messages")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        row,
+        (
+            "m1".to_string(),
+            "jane@example.com".to_string(),
+            true,
+            Some(1_000)
+        )
+    );
+    let labels: Vec<(String,)> =
+        sqlx::query_as("SELECT label FROM message_labels WHERE mail_id = 'm1' ORDER BY label")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(labels, [("INBOX".to_string(),), ("UNREAD".to_string(),)]);
+}
+
+#[tokio::test]
+async fn the_top_senders_report_ranks_by_count() {
+    let pool = testsupport::pool().await;
+    let mut conn = pool.acquire().await.unwrap();
+    for (sender, mails) in [
+        ("a@example.com", 5),
+        ("b@example.com", 9),
+        ("c@example.com", 1),
+    ] {
+        let mut counts = SenderCounts::default();
+        for n in 0..mails {
+            counts.add(&info("x", sender, n, &[]));
+        }
+        db::add_sender_counts(sender, &counts, &mut conn)
+            .await
+            .unwrap();
+    }
+    drop(conn);
+
+    let top = report::top_senders(&pool, 2, &SenderFilter::default(), SortBy::Count)
+        .await
+        .unwrap();
+    let rows = top
+        .rows
+        .iter()
+        .map(|row| (row.sender.as_str(), row.mails_sent))
+        .collect::<Vec<_>>();
+    assert_eq!(rows, [("b@example.com", 9), ("a@example.com", 5)]);
+    assert_eq!(
+        (top.total_messages, top.tail_senders, top.tail_max),
+        (15, 1, 1)
+    );
+}
+
+#[tokio::test]
+async fn a_message_recorded_without_its_count_is_undercounted() {
+    let pool = testsupport::pool().await;
+    let mut conn = pool.acquire().await.unwrap();
+    let message = info("m1", "jane@example.com", 0, &[]);
+    db::record_message(
+        &message,
+        DEFAULT_ACCOUNT,
+        None,
+        chrono::DateTime::UNIX_EPOCH,
+        &mut conn,
+    )
+    .await
+    .unwrap();
+    db::add_sender_counts("jane@example.com", &SenderCounts::default(), &mut conn)
+        .await
+        .unwrap();
+    drop(conn);
+
+    assert_eq!(
+        db::undercounted_senders(&pool).await.unwrap(),
+        [("jane@example.com".to_string(), 0, 1)]
+    );
+}
+
+#[tokio::test]
+async fn errors_keep_only_the_latest_failure() {
+    let pool = testsupport::pool().await;
+    let run = db::start_run(DEFAULT_ACCOUNT, chrono::DateTime::UNIX_EPOCH, &pool)
+        .await
+        .unwrap();
+    let mut conn = pool.acquire().await.unwrap();
+    for message in ["first", "second"] {
+        db::record_error(
+            Some("m1"),
+            Some(run),
+            "api",
+            message,
+            chrono::DateTime::UNIX_EPOCH,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+    }
+    drop(conn);
+
+    assert_eq!(
+        db::errored_ids(DEFAULT_ACCOUNT, &pool).await.unwrap(),
+        ["m1"]
+    );
+    assert!(db::errored_ids("work", &pool).await.unwrap().is_empty());
+    let (message,): (String,) = sqlx::query_as("SELECT message FROM errors")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(message, "second");
+}
+
+#[tokio::test]
+async fn the_same_id_in_two_accounts_is_two_messages() {
+    let pool = testsupport::pool().await;
+    let mut conn = pool.acquire().await.unwrap();
+    for (account, labels) in [(DEFAULT_ACCOUNT, &["INBOX"]), ("work", &["SENT"])] {
+        let message = info("m1", "jane@example.com", 0, labels);
+        db::record_message(
+            &message,
+            account,
+            None,
+            chrono::DateTime::UNIX_EPOCH,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+    }
+    drop(conn);
+
+    let labels: Vec<(String, String)> = sqlx::query_as(
+        "SELECT m.account, l.label FROM messages m
+        JOIN message_labels l ON l.account = m.account AND l.mail_id = m.mail_id
+        ORDER BY m.account",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        labels,
+        [
+            ("default".to_string(), "INBOX".to_string()),
+            ("work".to_string(), "SENT".to_string())
+        ]
+    );
+}
+
+#[tokio::test]
+async fn marking_an_id_seen_twice_keeps_one_mark() {
+    let pool = testsupport::pool().await;
+    let mut conn = pool.acquire().await.unwrap();
+    for _ in 0..2 {
+        db::mark_seen(&ids(&["a"]), DEFAULT_ACCOUNT, &mut conn)
+            .await
+            .unwrap();
+    }
+    drop(conn);
+
+    assert_eq!(db::seen_count(DEFAULT_ACCOUNT, &pool).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn marking_seen_handles_chunk_edges_and_repeats() {
+    let pool = testsupport::pool().await;
+    let mut conn = pool.acquire().await.unwrap();
+    db::mark_seen(&[], DEFAULT_ACCOUNT, &mut conn)
+        .await
+        .unwrap();
+    // A chunk's worth exactly, then one over with the first chunk's ids repeated in the second:
+    // ids already marked, by this call or an earlier one, don't fail the insert
+    let chunk = (0..499).map(|n| format!("id{}", n)).collect::<Vec<_>>();
+    db::mark_seen(&chunk, DEFAULT_ACCOUNT, &mut conn)
+        .await
+        .unwrap();
+    let mut repeated = chunk.clone();
+    repeated.push("id499".to_string());
+    repeated.extend(chunk.iter().take(10).cloned());
+    db::mark_seen(&repeated, DEFAULT_ACCOUNT, &mut conn)
+        .await
+        .unwrap();
+    drop(conn);
+
+    assert_eq!(db::seen_count(DEFAULT_ACCOUNT, &pool).await.unwrap(), 500);
+    assert_eq!(db::seen_count("work", &pool).await.unwrap(), 0);
+}
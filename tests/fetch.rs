@@ -0,0 +1,601 @@
+use std::time::Duration;
+
+use chrono::{TimeZone, Utc};
+use gmail_stats::clock::Clock;
+use gmail_stats::dates;
+use gmail_stats::db::{self, DEFAULT_ACCOUNT};
+use gmail_stats::fetch::{self, Backoff, FetchOptions};
+use gmail_stats::interrupt::{self, CancelToken};
+use gmail_stats::testsupport::{self, message, MockMailSource};
+use gmail_stats::GmailStatsError;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use sqlx::{Connection, Executor, Pool, Sqlite, SqliteConnection};
+
+fn options() -> FetchOptions {
+    FetchOptions {
+        max_retries: 3,
+        // Retries go straight ahead, so the tests don't wait on them
+        backoff: Backoff {
+            initial: Duration::ZERO,
+            max: Duration::ZERO,
+        },
+        quiet: true,
+        clock: Clock::Fixed(Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()),
+        ..Default::default()
+    }
+}
+
+fn from(id: &str, sender: &str) -> google_gmail1::api::Message {
+    message(id, &[("From", sender)], &["INBOX"])
+}
+
+// A rate limit which asks for no wait, so retries don't slow the tests down
+fn rate_limited() -> GmailStatsError {
+    GmailStatsError::RateLimited {
+        retry_after: Some(Duration::ZERO),
+    }
+}
+
+fn dropped() -> GmailStatsError {
+    GmailStatsError::Network("connection reset".to_string())
+}
+
+async fn senders(pool: &Pool<Sqlite>) -> Vec<(String, i64)> {
+    sqlx::query_as("SELECT sender, mails_sent FROM senders ORDER BY sender")
+        .fetch_all(pool)
+        .await
+        .unwrap()
+}
+
+async fn recorded(pool: &Pool<Sqlite>) -> Vec<(String,)> {
+    sqlx::query_as("SELECT mail_id FROM messages ORDER BY mail_id")
+        .fetch_all(pool)
+        .await
+        .unwrap()
+}
+
+fn ids(ids: &[&str]) -> Vec<(String,)> {
+    ids.iter().map(|id| (id.to_string(),)).collect()
+}
+
+#[tokio::test]
+async fn every_page_is_counted_and_recorded() {
+    let pool = testsupport::pool().await;
+    let source = MockMailSource::new()
+        .page([
+            from("m1", "Jane <jane@example.com>"),
+            from("m2", "news@example.org"),
+        ])
+        .page([from("m3", "jane@example.com")]);
+
+    let summary = fetch::run(&pool, &source, &options()).await.unwrap();
+
+    assert_eq!(
+        (summary.processed, summary.new_senders, summary.pages_listed),
+        (3, 2, 2)
+    );
+    assert_eq!(
+        senders(&pool).await,
+        [
+            ("jane@example.com".to_string(), 2),
+            ("news@example.org".to_string(), 1)
+        ]
+    );
+    assert_eq!(recorded(&pool).await, ids(&["m1", "m2", "m3"]));
+    assert_eq!(db::seen_count(DEFAULT_ACCOUNT, &pool).await.unwrap(), 3);
+    let run: (String, i64) = sqlx::query_as("SELECT status, messages FROM runs")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(run, ("complete".to_string(), 3));
+}
+
+#[tokio::test]
+async fn a_second_fetch_only_gets_new_mail() {
+    let pool = testsupport::pool().await;
+    let first = MockMailSource::new().page([from("m1", "jane@example.com")]);
+    fetch::run(&pool, &first, &options()).await.unwrap();
+
+    let second = MockMailSource::new().page([
+        from("m1", "jane@example.com"),
+        from("m2", "jane@example.com"),
+    ]);
+    let summary = fetch::run(&pool, &second, &options()).await.unwrap();
+
+    assert_eq!(second.fetched(), ["m2"]);
+    assert_eq!((summary.processed, summary.already_seen), (1, 1));
+    assert_eq!(senders(&pool).await, [("jane@example.com".to_string(), 2)]);
+}
+
+#[tokio::test]
+async fn a_missing_message_is_skipped_and_left_unseen() {
+    let pool = testsupport::pool().await;
+    let source = MockMailSource::new()
+        .page([from("m1", "jane@example.com")])
+        .listing(&["gone"]);
+
+    let summary = fetch::run(&pool, &source, &options()).await.unwrap();
+
+    assert_eq!((summary.processed, summary.skipped), (1, 1));
+    assert_eq!(recorded(&pool).await, ids(&["m1"]));
+    assert!(!db::seen_mail("gone", DEFAULT_ACCOUNT, &pool).await.unwrap());
+    assert_eq!(
+        db::errored_ids(DEFAULT_ACCOUNT, &pool).await.unwrap(),
+        ["gone"]
+    );
+}
+
+#[tokio::test]
+async fn rate_limits_are_retried() {
+    let pool = testsupport::pool().await;
+    let source = MockMailSource::new()
+        .page([from("m1", "jane@example.com")])
+        .page([from("m2", "bob@example.com")])
+        .fail_list(1, 1, rate_limited)
+        .fail_get("m2", 2, rate_limited);
+
+    let summary = fetch::run(&pool, &source, &options()).await.unwrap();
+
+    assert_eq!(summary.processed, 2);
+    assert_eq!(summary.errors["rate_limited"], 3);
+    assert_eq!(recorded(&pool).await, ids(&["m1", "m2"]));
+}
+
+#[tokio::test]
+async fn a_failed_message_is_retried_without_listing_its_page_again() {
+    let pool = testsupport::pool().await;
+    let source = MockMailSource::new()
+        .page([
+            from("m1", "jane@example.com"),
+            from("m2", "bob@example.com"),
+        ])
+        .fail_get("m2", 2, dropped);
+
+    let summary = fetch::run(&pool, &source, &options()).await.unwrap();
+
+    assert_eq!(source.listed().len(), 1);
+    assert_eq!(source.fetched(), ["m1", "m2", "m2", "m2"]);
+    assert_eq!(summary.processed, 2);
+}
+
+#[tokio::test]
+async fn too_many_rate_limits_in_a_row_fail_the_fetch() {
+    let pool = testsupport::pool().await;
+    let source = MockMailSource::new()
+        .page([from("m1", "jane@example.com")])
+        .fail_get("m1", 10, rate_limited);
+
+    let err = fetch::run(&pool, &source, &options()).await.unwrap_err();
+
+    assert!(matches!(err, GmailStatsError::RateLimited { .. }));
+    // The initial attempt and three retries
+    assert_eq!(source.fetched().len(), 4);
+    assert!(recorded(&pool).await.is_empty());
+}
+
+#[tokio::test]
+async fn retry_errors_fetches_only_the_failed_messages() {
+    let pool = testsupport::pool().await;
+    let first = MockMailSource::new()
+        .page([from("m1", "jane@example.com")])
+        .listing(&["m2"]);
+    fetch::run(&pool, &first, &options()).await.unwrap();
+
+    let retry = MockMailSource::new()
+        .page([from("m1", "jane@example.com")])
+        .unlisted_message(from("m2", "bob@example.com"));
+    let opts = FetchOptions {
+        retry_errors: true,
+        ..options()
+    };
+    let summary = fetch::run(&pool, &retry, &opts).await.unwrap();
+
+    assert_eq!(retry.fetched(), ["m2"]);
+    assert!(retry.listed().is_empty());
+    assert_eq!(summary.processed, 1);
+    assert!(db::errored_ids(DEFAULT_ACCOUNT, &pool)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn spam_is_recorded_but_kept_out_of_sender_counts() {
+    let pool = testsupport::pool().await;
+    let source = MockMailSource::new().page([
+        from("m1", "jane@example.com"),
+        message("m2", &[("From", "prize@example.net")], &["SPAM"]),
+    ]);
+    let opts = FetchOptions {
+        include_spam_trash: true,
+        ..options()
+    };
+
+    fetch::run(&pool, &source, &opts).await.unwrap();
+
+    assert_eq!(senders(&pool).await, [("jane@example.com".to_string(), 1)]);
+    let spam: Vec<(String, i64)> = sqlx::query_as("SELECT sender, mails_sent FROM spam_senders")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(spam, [("prize@example.net".to_string(), 1)]);
+    let flagged: Vec<(String, bool)> =
+        sqlx::query_as("SELECT mail_id, is_spam FROM messages ORDER BY mail_id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        flagged,
+        [("m1".to_string(), false), ("m2".to_string(), true)]
+    );
+}
+
+#[tokio::test]
+async fn a_run_of_transient_errors_within_the_limit_is_retried() {
+    let pool = testsupport::pool().await;
+    let source = MockMailSource::new()
+        .page([from("m1", "jane@example.com")])
+        .fail_get("m1", 2, dropped)
+        .fail_get("m1", 1, || GmailStatsError::Api {
+            status: Some(503),
+            message: "backend error".to_string(),
+        });
+
+    let summary = fetch::run(&pool, &source, &options()).await.unwrap();
+
+    assert_eq!(summary.processed, 1);
+    assert_eq!((summary.errors["network"], summary.errors["api"]), (2, 1));
+}
+
+#[tokio::test]
+async fn progress_starts_the_retry_count_over() {
+    let pool = testsupport::pool().await;
+    let source = MockMailSource::new()
+        .page([from("m1", "jane@example.com")])
+        .page([from("m2", "bob@example.com")])
+        .fail_get("m1", 3, dropped)
+        .fail_get("m2", 3, dropped);
+
+    let summary = fetch::run(&pool, &source, &options()).await.unwrap();
+
+    // Six failures in all, but never more than three in a row
+    assert_eq!(summary.errors["network"], 6);
+    assert_eq!(recorded(&pool).await, ids(&["m1", "m2"]));
+}
+
+#[tokio::test]
+async fn an_error_retrying_wont_fix_fails_the_fetch_at_once() {
+    let pool = testsupport::pool().await;
+    let source = MockMailSource::new()
+        .page([from("m1", "jane@example.com")])
+        .fail_get("m1", 1, || {
+            GmailStatsError::Auth("invalid_grant".to_string())
+        });
+
+    let err = fetch::run(&pool, &source, &options()).await.unwrap_err();
+
+    assert!(matches!(err, GmailStatsError::Auth(_)));
+    assert_eq!(source.fetched(), ["m1"]);
+}
+
+#[tokio::test]
+async fn many_retries_wait_no_longer_than_the_cap() {
+    let pool = testsupport::pool().await;
+    let source = MockMailSource::new()
+        .page([from("m1", "jane@example.com")])
+        .fail_get("m1", 40, dropped);
+    let opts = FetchOptions {
+        max_retries: 40,
+        backoff: Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(2),
+        },
+        ..options()
+    };
+
+    let started = std::time::Instant::now();
+    let summary = fetch::run(&pool, &source, &opts).await.unwrap();
+
+    assert_eq!(summary.processed, 1);
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn a_slow_writer_holds_up_fetching() {
+    let path =
+        std::env::temp_dir().join(format!("gmail-stats-slow-writer-{}.db", std::process::id()));
+    let file = SqliteConnectOptions::new()
+        .filename(&path)
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal);
+    let pool = SqlitePoolOptions::new()
+        .max_connections(4)
+        .connect_with(file.clone())
+        .await
+        .unwrap();
+    db::migrate(&pool).await.unwrap();
+    let source = MockMailSource::new()
+        .page((0..400).map(|n| from(&format!("m{:03}", n), "jane@example.com")))
+        .latency(Duration::from_millis(1));
+    let mut blocker = SqliteConnection::connect_with(&file).await.unwrap();
+
+    let opts = options();
+    let held_up = async {
+        // Once the run has started, another writer takes the lock, so the first batch waits
+        while source.fetched().is_empty() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        blocker.execute("BEGIN IMMEDIATE").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let fetched = source.fetched().len();
+        blocker.execute("COMMIT").await.unwrap();
+        fetched
+    };
+    let (summary, fetched) = tokio::join!(fetch::run(&pool, &source, &opts), held_up);
+
+    // A batch in the writer's hands, a full queue and the message waiting to join it
+    assert!(fetched >= fetch::QUEUE_CAPACITY);
+    assert!(fetched <= fetch::WRITE_BATCH + fetch::QUEUE_CAPACITY + 1);
+    assert_eq!(summary.unwrap().processed, 400);
+    pool.close().await;
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+}
+
+#[tokio::test]
+async fn listing_entries_without_an_id_are_skipped_with_their_page() {
+    let pool = testsupport::pool().await;
+    let source = MockMailSource::new()
+        .page([from("m1", "jane@example.com")])
+        .without_ids(2);
+
+    let summary = fetch::run(&pool, &source, &options()).await.unwrap();
+
+    assert_eq!((summary.processed, summary.skipped), (1, 2));
+    let (errors,): (i64,) = sqlx::query_as("SELECT count(*) FROM errors WHERE mail_id IS NULL")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(errors, 2);
+}
+
+#[tokio::test]
+async fn a_fetch_dropped_part_way_through_a_message_writes_nothing() {
+    let pool = testsupport::pool().await;
+    let source = MockMailSource::new()
+        .page([
+            from("m1", "jane@example.com"),
+            from("m2", "bob@example.com"),
+        ])
+        .without_ids(1)
+        .hang_on("m2");
+
+    let opts = options();
+    let run = fetch::run(&pool, &source, &opts);
+    let outcome = tokio::time::timeout(Duration::from_millis(200), run).await;
+
+    assert!(outcome.is_err());
+    assert_eq!(source.fetched(), ["m1", "m2"]);
+    assert!(recorded(&pool).await.is_empty());
+    assert!(senders(&pool).await.is_empty());
+    assert_eq!(db::seen_count(DEFAULT_ACCOUNT, &pool).await.unwrap(), 0);
+    let (errors,): (i64,) = sqlx::query_as("SELECT count(*) FROM errors")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(errors, 0);
+}
+
+#[tokio::test]
+async fn a_cancelled_fetch_carries_on_from_the_seen_mail() {
+    let pool = testsupport::pool().await;
+    let pages = || {
+        MockMailSource::new()
+            .page([
+                from("m1", "jane@example.com"),
+                from("m2", "bob@example.com"),
+            ])
+            .page([from("m3", "jane@example.com")])
+    };
+    let cancel = CancelToken::default();
+    // Ctrl-C comes while m2 is being fetched, which fails as the connection goes
+    let interrupt = cancel.clone();
+    let first = pages().fail_get("m2", 1, move || {
+        interrupt.cancel();
+        dropped()
+    });
+    let opts = FetchOptions {
+        cancel,
+        ..options()
+    };
+
+    let err = fetch::run(&pool, &first, &opts).await.unwrap_err();
+
+    assert!(matches!(err, GmailStatsError::Interrupted));
+    assert_eq!(recorded(&pool).await, ids(&["m1"]));
+    let (status,): (String,) = sqlx::query_as("SELECT status FROM runs")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "interrupted");
+
+    let second = pages();
+    fetch::run(&pool, &second, &options()).await.unwrap();
+
+    assert_eq!(second.listed(), [None, Some("page1".to_string())]);
+    assert_eq!(second.fetched(), ["m2", "m3"]);
+    assert_eq!(recorded(&pool).await, ids(&["m1", "m2", "m3"]));
+}
+
+// Fetch three pages, stopped by Ctrl-C while m3 on the second of them is fetched
+async fn cancel_on_the_second_page(pool: &Pool<Sqlite>) -> MockMailSource {
+    let cancel = CancelToken::default();
+    let interrupt = cancel.clone();
+    let first = pages_of_three().fail_get("m3", 1, move || {
+        interrupt.cancel();
+        dropped()
+    });
+    let opts = FetchOptions {
+        cancel,
+        ..options()
+    };
+    let err = fetch::run(pool, &first, &opts).await.unwrap_err();
+    assert!(matches!(err, GmailStatsError::Interrupted));
+    first
+}
+
+fn pages_of_three() -> MockMailSource {
+    MockMailSource::new()
+        .page([from("m1", "jane@example.com")])
+        .page([
+            from("m2", "bob@example.com"),
+            from("m3", "jane@example.com"),
+        ])
+        .page([from("m4", "news@example.org")])
+}
+
+#[tokio::test]
+async fn a_cancelled_fetch_carries_on_from_the_page_it_was_on() {
+    let pool = testsupport::pool().await;
+    cancel_on_the_second_page(&pool).await;
+    assert_eq!(recorded(&pool).await, ids(&["m1", "m2"]));
+    let cursor: (Option<String>, Option<String>) =
+        sqlx::query_as("SELECT cursor_listing, cursor_page_token FROM runs")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        cursor,
+        (Some("mock".to_string()), Some("page1".to_string()))
+    );
+
+    let second = pages_of_three();
+    fetch::run(&pool, &second, &options()).await.unwrap();
+
+    assert_eq!(
+        second.listed(),
+        [Some("page1".to_string()), Some("page2".to_string())]
+    );
+    assert_eq!(second.fetched(), ["m3", "m4"]);
+    assert_eq!(recorded(&pool).await, ids(&["m1", "m2", "m3", "m4"]));
+
+    // Finishing leaves nothing to carry on from
+    let third = pages_of_three();
+    fetch::run(&pool, &third, &options()).await.unwrap();
+    assert_eq!(third.listed()[0], None);
+}
+
+#[tokio::test]
+async fn an_expired_page_token_lists_from_the_top() {
+    let pool = testsupport::pool().await;
+    cancel_on_the_second_page(&pool).await;
+
+    let second = pages_of_three().fail_list(1, 1, || GmailStatsError::Api {
+        status: Some(400),
+        message: "Invalid pageToken".to_string(),
+    });
+    fetch::run(&pool, &second, &options()).await.unwrap();
+
+    assert_eq!(second.listed()[..2], [Some("page1".to_string()), None]);
+    assert_eq!(second.fetched(), ["m3", "m4"]);
+}
+
+#[tokio::test]
+async fn a_cursor_is_only_taken_up_by_the_same_listing() {
+    let pool = testsupport::pool().await;
+    cancel_on_the_second_page(&pool).await;
+
+    // Gmail's tokens for a listing without spam and trash don't page one with them
+    let second = pages_of_three();
+    let opts = FetchOptions {
+        include_spam_trash: true,
+        ..options()
+    };
+    fetch::run(&pool, &second, &opts).await.unwrap();
+
+    assert_eq!(second.listed()[0], None);
+    assert_eq!(second.fetched(), ["m3", "m4"]);
+}
+
+#[tokio::test]
+async fn max_duration_stops_the_fetch_and_the_next_run_carries_on() {
+    let pool = testsupport::pool().await;
+    let pages = |latency| {
+        MockMailSource::new()
+            .page([
+                from("m1", "jane@example.com"),
+                from("m2", "bob@example.com"),
+                from("m3", "jane@example.com"),
+                from("m4", "news@example.org"),
+            ])
+            .latency(latency)
+    };
+    let opts = options();
+    // With a second a call, the listing is in by 1s and m1 by 2s, and m2 is in hand when the
+    // deadline comes half a second later
+    let max = dates::parse_duration("2s").unwrap() + Duration::from_millis(500);
+    interrupt::cancel_after(opts.cancel.clone(), max);
+
+    let first = pages(Duration::from_secs(1));
+    let err = fetch::run(&pool, &first, &opts).await.unwrap_err();
+
+    assert!(matches!(err, GmailStatsError::DeadlineReached));
+    assert_eq!(recorded(&pool).await, ids(&["m1", "m2"]));
+    let (status,): (String,) = sqlx::query_as("SELECT status FROM runs")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(status, "interrupted (deadline)");
+
+    let second = pages(Duration::ZERO);
+    fetch::run(&pool, &second, &options()).await.unwrap();
+
+    assert_eq!(second.fetched(), ["m3", "m4"]);
+    assert_eq!(recorded(&pool).await, ids(&["m1", "m2", "m3", "m4"]));
+}
+
+#[tokio::test]
+async fn messages_with_parts_missing_are_recorded_without_panicking() {
+    let pool = testsupport::pool().await;
+    let mut without_id = from("m2", "jane@example.com");
+    without_id.id = None;
+    let mut without_subject = message(
+        "m3",
+        &[("From", "news@example.com"), ("Subject", "Lunch?")],
+        &["INBOX"],
+    );
+    for header in without_subject
+        .payload
+        .as_mut()
+        .and_then(|payload| payload.headers.as_mut())
+        .unwrap()
+    {
+        if header.name.as_deref() == Some("Subject") {
+            header.value = None;
+        }
+    }
+    let source = MockMailSource::new()
+        .listing(&["m1", "m2", "m3"])
+        .unlisted_message(from("m1", "jane@example.com"))
+        .answer("m2", without_id)
+        .unlisted_message(without_subject);
+
+    let summary = fetch::run(&pool, &source, &options()).await.unwrap();
+
+    // The id it was fetched by stands in for the one missing from the response
+    assert_eq!((summary.processed, summary.skipped), (3, 0));
+    let subjects: Vec<(String, Option<String>)> =
+        sqlx::query_as("SELECT mail_id, subject FROM messages ORDER BY mail_id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        subjects,
+        [
+            ("m1".to_string(), None),
+            ("m2".to_string(), None),
+            ("m3".to_string(), None)
+        ]
+    );
+}
@@ -0,0 +1,40 @@
+-- A small mailbox for the report snapshot tests: six senders, February to April 2024, the
+-- classifications already applied as `reclassify` would leave them.
+INSERT INTO messages (account, mail_id, sender, envelope_sender, internal_date, size_estimate, is_unread, is_bulk, fetched_at, subject, addressed) VALUES
+    ('default', 'm01', 'news@shop.example.com', 'news@shop.example.com', 1709542800000, 24000, 1, 1, 1714521600000, 'Message 1', 'direct'),
+    ('default', 'm02', 'news@shop.example.com', 'news@shop.example.com', 1710147600000, 24000, 1, 1, 1714521600000, 'Message 2', 'direct'),
+    ('default', 'm03', 'news@shop.example.com', 'news@shop.example.com', 1710752400000, 24000, 1, 1, 1714521600000, 'Message 3', 'direct'),
+    ('default', 'm04', 'news@shop.example.com', 'news@shop.example.com', 1711357200000, 24000, 1, 1, 1714521600000, 'Message 4', 'direct'),
+    ('default', 'm05', 'news@shop.example.com', 'news@shop.example.com', 1711962000000, 26000, 1, 1, 1714521600000, 'Message 5', 'direct'),
+    ('default', 'm06', 'news@shop.example.com', 'news@shop.example.com', 1712566800000, 26000, 1, 1, 1714521600000, 'Message 6', 'direct'),
+    ('default', 'm07', 'news@shop.example.com', 'news@shop.example.com', 1713171600000, 26000, 1, 1, 1714521600000, 'Message 7', 'direct'),
+    ('default', 'm08', 'news@shop.example.com', 'news@shop.example.com', 1713776400000, 26000, 1, 1, 1714521600000, 'Message 8', 'direct'),
+    ('default', 'm09', 'news@shop.example.com', 'news@shop.example.com', 1714381200000, 26000, 1, 1, 1714521600000, 'Message 9', 'direct'),
+    ('default', 'm10', 'jane@example.org', 'jane@example.org', 1709647200000, 5200, 0, 0, 1714521600000, 'Message 10', 'direct'),
+    ('default', 'm11', 'jane@example.org', 'jane@example.org', 1712073600000, 4100, 0, 0, 1714521600000, 'Message 11', 'direct'),
+    ('default', 'm12', 'jane@example.org', 'jane@example.org', 1713607200000, 3900, 1, 0, 1714521600000, 'Message 12', 'direct'),
+    ('default', 'm13', 'alerts@bank.example', 'alerts@bank.example', 1707548400000, 8000, 0, 0, 1714521600000, 'Message 13', 'direct'),
+    ('default', 'm14', 'alerts@bank.example', 'alerts@bank.example', 1708412400000, 8000, 0, 0, 1714521600000, 'Message 14', 'direct'),
+    ('default', 'm15', 'alerts@bank.example', 'alerts@bank.example', 1710486000000, 8000, 0, 0, 1714521600000, 'Message 15', 'direct'),
+    ('default', 'm16', 'me@mycorp.example', 'me@mycorp.example', 1712142000000, 2000, 0, 0, 1714521600000, 'Message 16', 'direct'),
+    ('default', 'm17', 'deals@promo.example.net', 'deals@promo.example.net', 1712347200000, 40000, 1, 1, 1714521600000, 'Message 17', 'direct'),
+    ('default', 'm18', 'deals@promo.example.net', 'deals@promo.example.net', 1712433600000, 40000, 1, 1, 1714521600000, 'Message 18', 'direct'),
+    ('default', 'm19', 'deals@promo.example.net', 'deals@promo.example.net', 1712952000000, 40000, 1, 1, 1714521600000, 'Message 19', 'direct'),
+    ('default', 'm20', 'deals@promo.example.net', 'deals@promo.example.net', 1713038400000, 40000, 1, 1, 1714521600000, 'Message 20', 'direct'),
+    ('default', 'm21', 'deals@promo.example.net', 'deals@promo.example.net', 1713556800000, 40000, 1, 1, 1714521600000, 'Message 21', 'direct'),
+    ('default', 'm22', 'deals@promo.example.net', 'deals@promo.example.net', 1714161600000, 40000, 1, 1, 1714521600000, 'Message 22', 'direct'),
+    ('default', 'm23', 'bob|pipes@odd.example', 'bob|pipes@odd.example', 1712649600000, 1500, 1, 0, 1714521600000, 'Message 23', 'direct');
+INSERT INTO senders (sender, mails_sent, bulk_count, bytes, first_seen, last_seen, unread_count, is_internal, direct_count) VALUES
+    ('news@shop.example.com', 9, 9, 226000, 1709542800000, 1714381200000, 9, 0, 9),
+    ('jane@example.org', 3, 0, 13200, 1709647200000, 1713607200000, 1, 0, 3),
+    ('alerts@bank.example', 3, 0, 24000, 1707548400000, 1710486000000, 0, 0, 3),
+    ('me@mycorp.example', 1, 0, 2000, 1712142000000, 1712142000000, 0, 1, 1),
+    ('deals@promo.example.net', 6, 6, 240000, 1712347200000, 1714161600000, 6, 0, 6),
+    ('bob|pipes@odd.example', 1, 0, 1500, 1712649600000, 1712649600000, 1, 0, 1);
+INSERT INTO domains (domain, class) VALUES
+    ('shop.example.com', 'commercial'),
+    ('example.org', 'personal'),
+    ('bank.example', 'commercial'),
+    ('mycorp.example', 'internal'),
+    ('promo.example.net', 'commercial'),
+    ('odd.example', 'personal');
@@ -0,0 +1,245 @@
+//! `GmailSource` against a fake Gmail on localhost, answering with the JSON the API sends.
+
+use std::io::Write;
+use std::time::Duration;
+
+use chrono::{TimeZone, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use gmail_stats::auth;
+use gmail_stats::clock::Clock;
+use gmail_stats::fetch::{self, FetchOptions};
+use gmail_stats::source::{GmailSource, MailSource};
+use gmail_stats::testsupport;
+use gmail_stats::GmailStatsError;
+use google_gmail1::oauth2::storage::{TokenInfo, TokenStorage};
+use google_gmail1::oauth2::{self, ApplicationSecret};
+use google_gmail1::Gmail;
+use serde_json::{json, Value};
+use wiremock::matchers::{header, method, path, query_param, query_param_is_missing};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const USER: &str = "/gmail/v1/users/me";
+
+// A token which never expires, so signing in never leaves the machine
+struct FixedToken;
+
+#[async_trait::async_trait]
+impl TokenStorage for FixedToken {
+    async fn set(&self, _scopes: &[&str], _token: TokenInfo) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn get(&self, _scopes: &[&str]) -> Option<TokenInfo> {
+        Some(TokenInfo {
+            access_token: "test-token".to_string(),
+            refresh_token: None,
+            expires_at: None,
+            id_token: None,
+        })
+    }
+}
+
+async fn source(server: &MockServer, compression: bool) -> GmailSource {
+    let auth = oauth2::InstalledFlowAuthenticator::builder(
+        ApplicationSecret::default(),
+        oauth2::InstalledFlowReturnMethod::Interactive,
+    )
+    .with_storage(Box::new(FixedToken))
+    .build()
+    .await
+    .unwrap();
+    let mut hub = Gmail::new(auth::client(), auth);
+    hub.base_url(format!("{}/", server.uri()));
+    GmailSource::new(hub, compression)
+}
+
+// A message in the metadata format
+fn message(id: &str, from: &str) -> Value {
+    json!({
+        "id": id,
+        "threadId": format!("t-{}", id),
+        "labelIds": ["INBOX", "UNREAD"],
+        "internalDate": "1714521600000",
+        "sizeEstimate": 2048,
+        "payload": {
+            "mimeType": "text/plain",
+            "headers": [
+                {"name": "From", "value": from},
+                {"name": "Subject", "value": format!("About {}", id)},
+            ],
+        },
+    })
+}
+
+fn listing(ids: &[&str], next: Option<&str>) -> Value {
+    let messages = ids
+        .iter()
+        .map(|id| json!({"id": id, "threadId": format!("t-{}", id)}))
+        .collect::<Vec<_>>();
+    let mut page = json!({"messages": messages, "resultSizeEstimate": ids.len()});
+    if let Some(token) = next {
+        page["nextPageToken"] = json!(token);
+    }
+    page
+}
+
+fn rate_limit(retry_after: &str) -> ResponseTemplate {
+    ResponseTemplate::new(429)
+        .insert_header("Retry-After", retry_after)
+        .set_body_json(json!({
+            "error": {
+                "code": 429,
+                "message": "Too many concurrent requests for user",
+                "errors": [{"reason": "rateLimitExceeded", "domain": "usageLimits"}],
+            }
+        }))
+}
+
+async fn mount_message(server: &MockServer, id: &str, from: &str) {
+    Mock::given(method("GET"))
+        .and(path(format!("{}/messages/{}", USER, id)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(message(id, from)))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn a_paged_listing_is_fetched_into_the_database() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/messages", USER)))
+        .and(query_param_is_missing("pageToken"))
+        .and(header("authorization", "Bearer test-token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(listing(&["m1", "m2"], Some("p2"))))
+        // The rate limit is retried on m2, not by listing the page again
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/messages", USER)))
+        .and(query_param("pageToken", "p2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(listing(&["m3"], None)))
+        .expect(1)
+        .mount(&server)
+        .await;
+    // m2 is rate limited once before it comes through
+    Mock::given(method("GET"))
+        .and(path(format!("{}/messages/m2", USER)))
+        .respond_with(rate_limit("0"))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    mount_message(&server, "m1", "Jane <jane@example.com>").await;
+    mount_message(&server, "m2", "news@example.org").await;
+    mount_message(&server, "m3", "jane@example.com").await;
+
+    let pool = testsupport::pool().await;
+    let opts = FetchOptions {
+        max_retries: 3,
+        quiet: true,
+        clock: Clock::Fixed(Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()),
+        ..Default::default()
+    };
+    let summary = fetch::run(&pool, &source(&server, false).await, &opts)
+        .await
+        .unwrap();
+
+    assert_eq!((summary.processed, summary.pages_listed), (3, 2));
+    assert_eq!(summary.errors["rate_limited"], 1);
+    let senders: Vec<(String, i64, i64)> =
+        sqlx::query_as("SELECT sender, mails_sent, unread_count FROM senders ORDER BY sender")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        senders,
+        [
+            ("jane@example.com".to_string(), 2, 2),
+            ("news@example.org".to_string(), 1, 1)
+        ]
+    );
+    let subjects: Vec<(String, Option<String>)> =
+        sqlx::query_as("SELECT mail_id, subject FROM messages ORDER BY mail_id")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        subjects[2],
+        ("m3".to_string(), Some("About m3".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn a_rate_limit_keeps_its_retry_after() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/messages/m1", USER)))
+        .respond_with(rate_limit("7"))
+        .mount(&server)
+        .await;
+
+    let err = source(&server, false)
+        .await
+        .get_message("m1")
+        .await
+        .unwrap_err();
+
+    assert!(matches!(
+        err,
+        GmailStatsError::RateLimited { retry_after: Some(wait) } if wait == Duration::from_secs(7)
+    ));
+}
+
+#[tokio::test]
+async fn a_missing_message_is_an_api_error() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/messages/gone", USER)))
+        .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+            "error": {"code": 404, "message": "Requested entity was not found."}
+        })))
+        .mount(&server)
+        .await;
+
+    let err = source(&server, false)
+        .await
+        .get_message("gone")
+        .await
+        .unwrap_err();
+
+    assert!(matches!(
+        err,
+        GmailStatsError::Api {
+            status: Some(404),
+            ..
+        }
+    ));
+}
+
+#[tokio::test]
+async fn gzipped_responses_are_decoded_and_counted_compressed() {
+    let server = MockServer::start().await;
+    let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+    gzip.write_all(message("m1", "jane@example.com").to_string().as_bytes())
+        .unwrap();
+    let body = gzip.finish().unwrap();
+    let compressed = body.len() as u64;
+    Mock::given(method("GET"))
+        .and(path(format!("{}/messages/m1", USER)))
+        .and(header("accept-encoding", "gzip"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Encoding", "gzip")
+                .set_body_raw(body, "application/json"),
+        )
+        .mount(&server)
+        .await;
+
+    let source = source(&server, true).await;
+    let message = source.get_message("m1").await.unwrap();
+
+    assert_eq!(message.id.as_deref(), Some("m1"));
+    assert_eq!(source.bytes_received(), Some(compressed));
+}
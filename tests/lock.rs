@@ -0,0 +1,23 @@
+// Releasing every lock touches the whole process, so it's tested apart from the lock unit tests
+use std::fs;
+
+use chrono::DateTime;
+use gmail_stats::lock::{self, LOCK_FILE};
+
+#[test]
+fn a_forced_exit_leaves_no_lock_behind() {
+    let dir = std::env::temp_dir().join(format!("gmail-stats-release-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(LOCK_FILE);
+    let held = lock::acquire(&path, false, DateTime::UNIX_EPOCH).unwrap();
+
+    lock::release_all();
+    assert!(!path.exists());
+    // The next run takes the lock, and dropping the released one leaves it alone
+    let next = lock::acquire(&path, false, DateTime::UNIX_EPOCH).unwrap();
+    drop(held);
+    assert!(path.exists());
+    drop(next);
+    assert!(!path.exists());
+}
@@ -0,0 +1,152 @@
+use gmail_stats::config::Config;
+use gmail_stats::own::{self, Identity};
+use gmail_stats::parse::{cleanup_sender, SenderInfo};
+use gmail_stats::testsupport::message;
+use proptest::prelude::*;
+
+// A syntactically valid address, in mixed case, with dots and plus-tags in the local part and
+// non-ASCII letters allowed in the domain
+fn address() -> impl Strategy<Value = String> {
+    (
+        "[a-zA-Z0-9][a-zA-Z0-9._-]{0,12}(\\+[a-zA-Z0-9]{1,6})?",
+        prop::collection::vec("[a-zA-Z0-9äöüéñ][a-zA-Z0-9äöüéñ-]{0,8}", 1..3),
+        "[a-zA-Z]{2,10}",
+    )
+        .prop_map(|(local, labels, tld)| format!("{}@{}.{}", local, labels.join("."), tld))
+}
+
+// `address` as a From header could carry it
+fn decorated(address: String) -> impl Strategy<Value = String> {
+    prop_oneof![
+        Just(address.clone()),
+        Just(format!("  {}  ", address)),
+        Just(format!("<{}>", address)),
+        "[A-Za-z][A-Za-z ]{0,12}".prop_map({
+            let address = address.clone();
+            move |name| format!("{} <{}>", name, address)
+        }),
+        "[A-Za-z][A-Za-z ,]{0,12}".prop_map({
+            let address = address.clone();
+            move |name| format!("\"{}\" < {} >", name, address)
+        }),
+        Just(format!("\"Old <old@example.com>\" <{}>", address)),
+    ]
+}
+
+// A plain Gmail address, and the same written another way Gmail delivers to: dots anywhere in
+// the local part, a plus-tag, googlemail.com and any case
+fn gmail_variant() -> impl Strategy<Value = (String, String)> {
+    (
+        "[a-z0-9]{2,12}",
+        prop::collection::vec(any::<bool>(), 12),
+        "(\\+[a-z0-9]{1,6})?",
+        prop_oneof![Just("gmail.com"), Just("googlemail.com")],
+        prop::collection::vec(any::<bool>(), 1..8),
+    )
+        .prop_map(|(local, dots, tag, domain, flips)| {
+            let dotted = local
+                .chars()
+                .zip(dots.iter().chain(std::iter::repeat(&false)))
+                .enumerate()
+                .map(|(at, (c, &dot))| match dot && at > 0 {
+                    true => format!(".{}", c),
+                    false => c.to_string(),
+                })
+                .collect::<String>();
+            let variant = recased(&format!("{}{}@{}", dotted, tag, domain), &flips);
+            (format!("{}@gmail.com", local), variant)
+        })
+}
+
+// `address` with the case of each ASCII letter flipped where `flips` says so
+fn recased(address: &str, flips: &[bool]) -> String {
+    address
+        .chars()
+        .zip(flips.iter().cycle())
+        .map(|(c, &flip)| match flip {
+            true if c.is_ascii_lowercase() => c.to_ascii_uppercase(),
+            true => c.to_ascii_lowercase(),
+            false => c,
+        })
+        .collect()
+}
+
+// The sender a message with `from` as its From header is counted against
+fn sender_of(from: &str) -> SenderInfo {
+    SenderInfo::from_message(&message("m", &[("From", from)], &["INBOX"]), &[])
+}
+
+// One `@`, something either side of it, a dot in the domain and nothing a header wraps it in
+fn is_well_formed(address: &str) -> bool {
+    let Some((local, domain)) = address.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && !domain.contains('@')
+        && domain.split('.').count() >= 2
+        && domain.split('.').all(|label| !label.is_empty())
+        && !address.contains(|c: char| c.is_whitespace() || "<>\"".contains(c))
+}
+
+proptest! {
+    #[test]
+    fn any_input_is_cleaned_up_without_panicking(input in any::<String>()) {
+        cleanup_sender(&input);
+        sender_of(&input);
+    }
+
+    #[test]
+    fn cleaning_up_is_idempotent(input in any::<String>()) {
+        let once = cleanup_sender(&input);
+        prop_assert_eq!(cleanup_sender(once), once);
+    }
+
+    #[test]
+    fn a_decorated_address_is_counted_against_the_address(
+        (address, from) in address().prop_flat_map(|a| (Just(a.clone()), decorated(a)))
+    ) {
+        let info = sender_of(&from);
+        prop_assert!(is_well_formed(&info.sender), "{:?} from {:?}", info.sender, from);
+        prop_assert_eq!(&info.sender, &address);
+        prop_assert_eq!(&info.envelope_sender, &address);
+        prop_assert_eq!(info.original_sender, None);
+        prop_assert_eq!(info.from, from);
+    }
+
+    #[test]
+    fn an_address_found_anywhere_comes_out_well_formed(
+        address in address(),
+        before in "[^<>@]{0,12}",
+        after in "[^<>]{0,12}",
+    ) {
+        let sender = sender_of(&format!("{}<{}>{}", before, address, after)).sender;
+        prop_assert!(is_well_formed(&sender), "{:?}", sender);
+    }
+
+    #[test]
+    fn gmail_dot_variants_fold_onto_the_plain_address(
+        ((plain, variant), from) in gmail_variant()
+            .prop_flat_map(|(plain, variant)| ((Just(plain), Just(variant.clone())), decorated(variant)))
+    ) {
+        let sender = sender_of(&from).sender;
+        prop_assert_eq!(&sender, &variant);
+        prop_assert_eq!(own::normalize(&sender), plain.clone());
+        let identity = Identity::new(&Config {
+            my_addresses: vec![plain],
+            ..Default::default()
+        });
+        prop_assert!(identity.is_mine(&sender));
+    }
+
+    #[test]
+    fn dots_only_fold_at_gmail(
+        local in "[a-z]{2,8}",
+        at in 1..8usize,
+        domain in "[a-z]{2,8}\\.(com|org)",
+    ) {
+        prop_assume!(at < local.len() && domain != "gmail.com");
+        let dotted = format!("{}.{}@{}", &local[..at], &local[at..], domain);
+        let sender = sender_of(&format!("Jane <{}>", dotted)).sender;
+        prop_assert_ne!(own::normalize(&sender), own::normalize(&format!("{}@{}", local, domain)));
+    }
+}